@@ -140,6 +140,11 @@ pub extern "C" fn rust_secondary_main() -> ! {
         }
     }
 
+    // Arm this CPU's comparator for the scheduler tick
+    if let Err(e) = crate::core::time::cpu_online() {
+        log::error!("Secondary CPU {} failed to arm scheduler tick: {:?}", cpu_id, e);
+    }
+
     // Wait for work
     log::info!("Secondary CPU {} ready, waiting for work...", cpu_id);
     secondary_idle_loop();
@@ -30,8 +30,10 @@
 //! - [Xvisor cpu_vcpu_helper.c](/home/zcxggmu/workspace/hello-projs/posp/xvisor/arch/arm/cpu/arm64/cpu_vcpu_helper.c)
 
 use crate::arch::arm64::cpu::vcpu::context::{ExtendedVcpuContext, SavedGprs};
-use crate::arch::arm64::cpu::regs::ExceptionLevel;
-use crate::arch::arm64::mmu::fault::{Stage2Fault, FaultInfo};
+use crate::arch::arm64::cpu::regs::{el2, ExceptionLevel};
+use crate::arch::arm64::mmu::fault::{self, FaultInfo, FaultResolution, Stage2Fault};
+use crate::arch::arm64::mmu::vttbr;
+use crate::arch::arm64::psci;
 
 /// Trap reason
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -255,10 +257,13 @@ pub trait TrapHandler {
     ) -> Result<TrapResolution, &'static str>;
 
     /// Handle SMC call
+    ///
+    /// `regs` holds the guest's general purpose registers; results are
+    /// returned in x0-x3.
     fn handle_smc_call(
         &mut self,
         trap: &TrapInfo,
-        function_id: u32,
+        regs: &mut [u64],
     ) -> Result<TrapResolution, &'static str>;
 }
 
@@ -354,8 +359,8 @@ impl TrapHandler for DefaultTrapHandler {
         trap: &TrapInfo,
         fault: Stage2Fault,
     ) -> Result<TrapResolution, &'static str> {
-        log::warn!("Trap: Stage-2 fault: {:?} IPA={:#x}",
-                  fault, trap.far);
+        log::debug!("Trap: Stage-2 fault: {:?} FAR={:#x}",
+                    fault, trap.far);
 
         // Check if fault is recoverable
         if !fault.is_recoverable() {
@@ -363,21 +368,59 @@ impl TrapHandler for DefaultTrapHandler {
             return Ok(TrapResolution::Halt);
         }
 
-        // Inject data/abort exception to guest
-        Ok(TrapResolution::InjectException)
+        // HPFAR_EL2 holds the faulting IPA page; FAR_EL2 the page offset
+        let ipa = ((el2::read_hpfar_el2() >> 4) << 12) | (trap.far & 0xFFF);
+        let instruction = matches!(trap.exception_class(), 0x20 | 0x21);
+        let fault_info = FaultInfo::from_esr(trap.esr, ipa, instruction);
+        let vmid = vttbr::extract_vmid(el2::read_vttbr_el2());
+
+        Ok(match fault::resolve_fault(fault_info, vmid) {
+            FaultResolution::Resolved => TrapResolution::Resume,
+            FaultResolution::EmulateMmio => TrapResolution::Emulate,
+            FaultResolution::InjectException => TrapResolution::InjectException,
+            FaultResolution::Fatal => TrapResolution::Halt,
+        })
     }
 
     fn handle_smc_call(
         &mut self,
         trap: &TrapInfo,
-        function_id: u32,
+        regs: &mut [u64],
     ) -> Result<TrapResolution, &'static str> {
+        // SMC calling convention: the function ID is in w0
+        let function_id = regs[0] as u32;
         log::debug!("Trap: SMC call function_id={:#x}", function_id);
 
-        // For now, just return undefined exception
-        // A real implementation would handle PSCI calls
-        log::warn!("  SMC not fully implemented, injecting undef");
-        Ok(TrapResolution::InjectException)
+        if function_id == psci::PSCI_0_2_FN_SYSTEM_RESET {
+            let vm_id = vttbr::extract_vmid(el2::read_vttbr_el2()) as crate::core::vmm::VmId;
+            log::info!("  Guest requested system reset of VM {}", vm_id);
+            if let Err(e) = crate::core::vmm::reset_vm(vm_id).and_then(|_| crate::core::vmm::start_vm(vm_id)) {
+                log::error!("  Failed to reset VM {}: {:?}", vm_id, e);
+            }
+            // The VCPU's registers now hold its reset state; leave this exit
+            // so the next entry loads them
+            return Ok(TrapResolution::Halt);
+        }
+
+        // Route the call to the handler of its owner
+        let call = psci::SmcccRegs {
+            x0: regs[0],
+            x1: regs[1],
+            x2: regs[2],
+            x3: regs[3],
+            x4: regs[4],
+            x5: regs[5],
+            x6: regs[6],
+            x7: regs[7],
+        };
+        let result = psci::dispatch_smccc(&call);
+        regs[0] = result.x0;
+        for (reg, value) in regs[1..4].iter_mut().zip([result.x1, result.x2, result.x3]) {
+            if let Some(value) = value {
+                *reg = value;
+            }
+        }
+        Ok(TrapResolution::Resume)
     }
 }
 
@@ -391,7 +434,7 @@ impl TrapHandler for DefaultTrapHandler {
 /// # Returns
 /// Trap resolution result
 pub fn handle_trap(
-    context: &ExtendedVcpuContext,
+    context: &mut ExtendedVcpuContext,
     trap: &TrapInfo,
     handler: &mut dyn TrapHandler,
 ) -> Result<TrapResolution, &'static str> {
@@ -414,8 +457,7 @@ pub fn handle_trap(
             handler.handle_stage2_fault(trap, fault)
         }
         TrapReason::SmcCall => {
-            let function_id = (trap.iss >> 0) & 0xFFFF;
-            handler.handle_smc_call(trap, function_id)
+            handler.handle_smc_call(trap, &mut context.gprs.x)
         }
         TrapReason::EretTrap => {
             log::warn!("Trap: ERET to EL2 is not allowed");
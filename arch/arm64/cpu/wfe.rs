@@ -438,9 +438,11 @@ impl WfeHandler {
     ///
     /// This is called when WFE is configured to yield instead of waiting.
     fn yield_scheduler(&self) {
-        // TODO: Implement actual scheduler yield
-        // For now, this is a placeholder
-        log::debug!("WFE Handler: Scheduler yield (placeholder)");
+        // Deschedule until an interrupt is pending; an SEV from another
+        // VCPU is seen through the broadcaster on the next trapped WFE
+        if let Err(e) = crate::core::sched::vcpu_idle(crate::core::sched::IdleReason::Wfe) {
+            log::debug!("WFE Handler: Scheduler yield failed: {:?}", e);
+        }
     }
 
     /// Check if WFE should be trapped based on HCR_EL2.TWE
@@ -281,13 +281,16 @@ impl WfiHandler {
                 Ok(WfiWaitResult::Success)
             }
             WfiMode::Handled => {
-                // Handle in hypervisor - wait for virtual interrupt
+                // Handle in hypervisor - deschedule until a virtual interrupt is pending
                 log::debug!("WFI Handler: Handling WFI in hypervisor");
                 self.state.activate();
-                // In a real implementation, this would wait for a virtual interrupt
-                // For now, just return success
+                let result = crate::core::sched::vcpu_idle(crate::core::sched::IdleReason::Wfi);
                 self.state.deactivate();
-                Ok(WfiWaitResult::Success)
+
+                match result {
+                    Ok(_) => Ok(WfiWaitResult::Success),
+                    Err(_) => Err("Failed to deschedule idle VCPU"),
+                }
             }
         }
     }
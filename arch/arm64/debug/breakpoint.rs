@@ -0,0 +1,298 @@
+//! ARM64 Hardware Breakpoints and Watchpoints
+//!
+//! This module manages the breakpoint and watchpoint register pairs:
+//! - Instruction address breakpoints (DBGBVR/DBGBCR)
+//! - Data read/write watchpoints (DBGWVR/DBGWCR)
+//! - Slot allocation and release
+
+use crate::arch::arm64::debug::regs::{self, DebugPrivilege, WatchAccess};
+use alloc::vec::Vec;
+
+/// Breakpoint type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakpointType {
+    /// Instruction breakpoint
+    Instruction,
+    /// Data read watchpoint
+    DataRead,
+    /// Data write watchpoint
+    DataWrite,
+    /// Data read/write watchpoint
+    DataReadWrite,
+}
+
+impl BreakpointType {
+    /// Watchpoint access type, `None` for instruction breakpoints
+    fn watch_access(self) -> Option<WatchAccess> {
+        match self {
+            BreakpointType::Instruction => None,
+            BreakpointType::DataRead => Some(WatchAccess::Load),
+            BreakpointType::DataWrite => Some(WatchAccess::Store),
+            BreakpointType::DataReadWrite => Some(WatchAccess::LoadStore),
+        }
+    }
+}
+
+/// Hardware breakpoint/watchpoint
+#[derive(Debug, Clone)]
+pub struct Breakpoint {
+    /// Slot index, used as the ID
+    pub id: u32,
+    /// Breakpoint type
+    pub bp_type: BreakpointType,
+    /// Watched address
+    pub address: u64,
+    /// Watched length in bytes (4 for instruction breakpoints)
+    pub len: u64,
+    /// Value register contents
+    pub value_reg: u64,
+    /// Control register contents
+    pub control_reg: u64,
+    /// Trigger count
+    pub trigger_count: u64,
+}
+
+/// Breakpoint manager
+#[derive(Debug)]
+pub struct BreakpointManager {
+    /// Breakpoint slots
+    breakpoints: Vec<Option<Breakpoint>>,
+    /// Watchpoint slots
+    watchpoints: Vec<Option<Breakpoint>>,
+    /// Exception levels new entries match at
+    privilege: DebugPrivilege,
+}
+
+impl BreakpointManager {
+    /// Create new breakpoint manager
+    pub fn new(max_breakpoints: u32, max_watchpoints: u32) -> Result<Self, &'static str> {
+        if max_breakpoints as usize > regs::breakpoint_count() {
+            return Err("Requested breakpoints exceed available registers");
+        }
+        if max_watchpoints as usize > regs::watchpoint_count() {
+            return Err("Requested watchpoints exceed available registers");
+        }
+
+        Ok(Self {
+            breakpoints: alloc::vec![None; max_breakpoints as usize],
+            watchpoints: alloc::vec![None; max_watchpoints as usize],
+            privilege: DebugPrivilege::El2,
+        })
+    }
+
+    /// Set the exception levels subsequently set entries match at
+    pub fn set_privilege(&mut self, privilege: DebugPrivilege) {
+        self.privilege = privilege;
+    }
+
+    /// Set an instruction breakpoint
+    pub fn set_breakpoint(&mut self, addr: usize, bp_type: BreakpointType) -> Result<u32, &'static str> {
+        if bp_type != BreakpointType::Instruction {
+            return self.set_watchpoint(addr, 4, bp_type);
+        }
+
+        let slot = self.breakpoints.iter().position(|bp| bp.is_none())
+            .ok_or("Maximum breakpoints reached")?;
+        let (bvr, bcr) = regs::encode_breakpoint(addr as u64, self.privilege)?;
+
+        regs::write_breakpoint(slot, bvr, bcr);
+        self.breakpoints[slot] = Some(Breakpoint {
+            id: slot as u32,
+            bp_type,
+            address: addr as u64,
+            len: 4,
+            value_reg: bvr,
+            control_reg: bcr,
+            trigger_count: 0,
+        });
+
+        Ok(slot as u32)
+    }
+
+    /// Set a data watchpoint covering `len` bytes at `addr`
+    pub fn set_watchpoint(&mut self, addr: usize, len: usize, bp_type: BreakpointType) -> Result<u32, &'static str> {
+        let access = bp_type.watch_access().ok_or("Instruction breakpoints are not watchpoints")?;
+
+        let slot = self.watchpoints.iter().position(|wp| wp.is_none())
+            .ok_or("Maximum watchpoints reached")?;
+        let (wvr, wcr) = regs::encode_watchpoint(addr as u64, len as u64, access, self.privilege)?;
+
+        regs::write_watchpoint(slot, wvr, wcr);
+        self.watchpoints[slot] = Some(Breakpoint {
+            id: slot as u32,
+            bp_type,
+            address: addr as u64,
+            len: len as u64,
+            value_reg: wvr,
+            control_reg: wcr,
+            trigger_count: 0,
+        });
+
+        Ok(slot as u32)
+    }
+
+    /// Clear an instruction breakpoint
+    pub fn clear_breakpoint(&mut self, id: u32) -> Result<(), &'static str> {
+        let slot = self.breakpoints.get_mut(id as usize)
+            .filter(|bp| bp.is_some())
+            .ok_or("Breakpoint not found")?;
+        *slot = None;
+        regs::write_breakpoint(id as usize, 0, 0);
+        Ok(())
+    }
+
+    /// Clear a data watchpoint
+    pub fn clear_watchpoint(&mut self, id: u32) -> Result<(), &'static str> {
+        let slot = self.watchpoints.get_mut(id as usize)
+            .filter(|wp| wp.is_some())
+            .ok_or("Watchpoint not found")?;
+        *slot = None;
+        regs::write_watchpoint(id as usize, 0, 0);
+        Ok(())
+    }
+
+    /// Get breakpoint by ID
+    pub fn get_breakpoint(&self, id: u32) -> Option<&Breakpoint> {
+        self.breakpoints.get(id as usize)?.as_ref()
+    }
+
+    /// Get watchpoint by ID
+    pub fn get_watchpoint(&self, id: u32) -> Option<&Breakpoint> {
+        self.watchpoints.get(id as usize)?.as_ref()
+    }
+
+    /// Record a breakpoint debug exception at `pc`
+    ///
+    /// Returns the ID of the matching breakpoint.
+    pub fn hit_breakpoint(&mut self, pc: u64) -> Option<u32> {
+        let bp = self.breakpoints.iter_mut().flatten().find(|bp| bp.address == pc)?;
+        bp.trigger_count += 1;
+        Some(bp.id)
+    }
+
+    /// Record a watchpoint debug exception for the faulting address
+    ///
+    /// Returns the ID of the matching watchpoint.
+    pub fn hit_watchpoint(&mut self, far: u64) -> Option<u32> {
+        let wp = self.watchpoints.iter_mut().flatten()
+            .find(|wp| far >= wp.address && far < wp.address + wp.len)?;
+        wp.trigger_count += 1;
+        Some(wp.id)
+    }
+
+    /// Disarm breakpoint `id` in hardware, keeping its slot
+    ///
+    /// Used to step over the instruction that triggered it.
+    pub fn suspend_breakpoint(&self, id: u32) {
+        if self.get_breakpoint(id).is_some() {
+            regs::write_breakpoint(id as usize, 0, 0);
+        }
+    }
+
+    /// Re-arm a breakpoint disarmed by `suspend_breakpoint`
+    pub fn restore_breakpoint(&self, id: u32) {
+        if let Some(bp) = self.get_breakpoint(id) {
+            regs::write_breakpoint(id as usize, bp.value_reg, bp.control_reg);
+        }
+    }
+
+    /// Disarm watchpoint `id` in hardware, keeping its slot
+    pub fn suspend_watchpoint(&self, id: u32) {
+        if self.get_watchpoint(id).is_some() {
+            regs::write_watchpoint(id as usize, 0, 0);
+        }
+    }
+
+    /// Re-arm a watchpoint disarmed by `suspend_watchpoint`
+    pub fn restore_watchpoint(&self, id: u32) {
+        if let Some(wp) = self.get_watchpoint(id) {
+            regs::write_watchpoint(id as usize, wp.value_reg, wp.control_reg);
+        }
+    }
+
+    /// Number of breakpoints set
+    pub fn active_breakpoints(&self) -> usize {
+        self.breakpoints.iter().flatten().count()
+    }
+
+    /// Number of watchpoints set
+    pub fn active_watchpoints(&self) -> usize {
+        self.watchpoints.iter().flatten().count()
+    }
+
+    /// Clear all breakpoints and watchpoints
+    pub fn reset(&mut self) {
+        for (slot, bp) in self.breakpoints.iter_mut().enumerate() {
+            if bp.take().is_some() {
+                regs::write_breakpoint(slot, 0, 0);
+            }
+        }
+        for (slot, wp) in self.watchpoints.iter_mut().enumerate() {
+            if wp.take().is_some() {
+                regs::write_watchpoint(slot, 0, 0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::arm64::debug::regs::ctrl;
+
+    #[test]
+    fn test_breakpoint_register_encoding() {
+        let (bvr, bcr) = regs::encode_breakpoint(0xffff_0000_0008_1000, DebugPrivilege::El2).unwrap();
+        assert_eq!(bvr, 0xffff_0000_0008_1000);
+        assert_eq!(bcr & ctrl::E, ctrl::E);
+        assert_eq!(bcr & ctrl::HMC, ctrl::HMC);
+        assert_eq!((bcr >> ctrl::PMC_SHIFT) & 0b11, 0b01);
+        assert_eq!((bcr >> ctrl::BAS_SHIFT) & 0xF, 0xF);
+        // Unlinked address match
+        assert_eq!((bcr >> 20) & 0xF, 0);
+
+        let (_, bcr) = regs::encode_breakpoint(0x4000, DebugPrivilege::El1El0).unwrap();
+        assert_eq!(bcr & ctrl::HMC, 0);
+        assert_eq!((bcr >> ctrl::PMC_SHIFT) & 0b11, 0b11);
+
+        assert!(regs::encode_breakpoint(0x4002, DebugPrivilege::El2).is_err());
+    }
+
+    #[test]
+    fn test_watchpoint_register_encoding() {
+        // Two bytes at offset 2 of a doubleword
+        let (wvr, wcr) = regs::encode_watchpoint(0x1002, 2, WatchAccess::Store, DebugPrivilege::El1).unwrap();
+        assert_eq!(wvr, 0x1000);
+        assert_eq!((wcr >> ctrl::BAS_SHIFT) & 0xFF, 0b0000_1100);
+        assert_eq!((wcr >> ctrl::LSC_SHIFT) & 0b11, WatchAccess::Store as u64);
+
+        // 4KiB region uses the address mask
+        let (wvr, wcr) = regs::encode_watchpoint(0x8000, 0x1000, WatchAccess::LoadStore, DebugPrivilege::El1).unwrap();
+        assert_eq!(wvr, 0x8000);
+        assert_eq!((wcr >> ctrl::MASK_SHIFT) & 0x1F, 12);
+
+        assert!(regs::encode_watchpoint(0x1006, 4, WatchAccess::Load, DebugPrivilege::El1).is_err());
+        assert!(regs::encode_watchpoint(0x8800, 0x1000, WatchAccess::Load, DebugPrivilege::El1).is_err());
+    }
+
+    #[test]
+    fn test_max_breakpoint_overflow() {
+        let mut manager = BreakpointManager::new(2, 1).unwrap();
+        assert_eq!(manager.set_breakpoint(0x1000, BreakpointType::Instruction), Ok(0));
+        assert_eq!(manager.set_breakpoint(0x2000, BreakpointType::Instruction), Ok(1));
+        assert_eq!(manager.set_breakpoint(0x3000, BreakpointType::Instruction),
+                   Err("Maximum breakpoints reached"));
+
+        // Watchpoints draw from their own pool
+        assert_eq!(manager.set_breakpoint(0x5000, BreakpointType::DataWrite), Ok(0));
+        assert!(manager.set_watchpoint(0x6000, 8, BreakpointType::DataRead).is_err());
+
+        // Clearing frees the slot for reuse
+        manager.clear_breakpoint(0).unwrap();
+        assert_eq!(manager.set_breakpoint(0x3000, BreakpointType::Instruction), Ok(0));
+        assert_eq!(manager.hit_breakpoint(0x3000), Some(0));
+        assert_eq!(manager.get_breakpoint(0).unwrap().trigger_count, 1);
+
+        assert!(BreakpointManager::new(regs::MAX_HW_BREAKPOINTS as u32 + 1, 0).is_err());
+    }
+}
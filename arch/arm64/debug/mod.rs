@@ -0,0 +1,286 @@
+//! ARM64 Debug Support Module
+//!
+//! This module provides self-hosted debugging support for ARM64 including:
+//! - Debug register access and encoding
+//! - Hardware breakpoints and watchpoints
+//! - Software single stepping
+//!
+//! The hypervisor debugs itself at EL2: entries are set with HMC so they
+//! match at EL2, and debug exceptions are taken to EL2.
+
+pub mod regs;
+pub mod breakpoint;
+
+use breakpoint::{BreakpointManager, BreakpointType};
+use crate::core::sync::{Once, SpinLock};
+use crate::core::sync::spinlock::SpinLockGuard;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// ESR_EL2 exception classes of debug exceptions taken from EL2
+mod ec {
+    /// Breakpoint exception without a change in exception level
+    pub const BREAKPOINT: u64 = 0x31;
+    /// Software step exception without a change in exception level
+    pub const SOFTWARE_STEP: u64 = 0x33;
+    /// Watchpoint exception without a change in exception level
+    pub const WATCHPOINT: u64 = 0x35;
+}
+
+/// No breakpoint or watchpoint is being stepped over
+const NO_STEP_OVER: u32 = u32::MAX;
+
+/// Breakpoint slot disarmed while its instruction is stepped
+static STEP_OVER_BREAKPOINT: AtomicU32 = AtomicU32::new(NO_STEP_OVER);
+/// Watchpoint slot disarmed while its access is stepped
+static STEP_OVER_WATCHPOINT: AtomicU32 = AtomicU32::new(NO_STEP_OVER);
+
+/// Debug configuration
+#[derive(Debug, Clone)]
+pub struct DebugConfig {
+    /// Enable debug support
+    pub enabled: bool,
+    /// Number of hardware breakpoints
+    pub hw_breakpoints: u32,
+    /// Number of hardware watchpoints
+    pub hw_watchpoints: u32,
+}
+
+impl Default for DebugConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            hw_breakpoints: regs::breakpoint_count() as u32,
+            hw_watchpoints: regs::watchpoint_count() as u32,
+        }
+    }
+}
+
+/// Debug configuration, set once at initialization
+static DEBUG_CONFIG: Once<DebugConfig> = Once::new();
+
+/// Global breakpoint manager
+static BREAKPOINT_MANAGER: Once<SpinLock<BreakpointManager>> = Once::new();
+
+/// Initialize debug subsystem
+pub fn init() -> Result<(), &'static str> {
+    log::info!("Initializing ARM64 debug subsystem");
+
+    // Initialize with default config
+    let config = DebugConfig::default();
+    init_with_config(config)?;
+
+    log::info!("ARM64 debug subsystem initialized");
+    Ok(())
+}
+
+/// Initialize debug subsystem with configuration
+pub fn init_with_config(config: DebugConfig) -> Result<(), &'static str> {
+    if !config.enabled {
+        log::info!("Debug support is disabled");
+        return Ok(());
+    }
+
+    log::info!("Initializing debug with {} breakpoints, {} watchpoints",
+              config.hw_breakpoints, config.hw_watchpoints);
+
+    let bp_manager = BreakpointManager::new(config.hw_breakpoints, config.hw_watchpoints)?;
+
+    BREAKPOINT_MANAGER.set(SpinLock::new(bp_manager))
+        .map_err(|_| "Debug subsystem already initialized")?;
+    let _ = DEBUG_CONFIG.set(config);
+
+    enable_debug_mode()?;
+
+    log::info!("Debug subsystem initialized successfully");
+    Ok(())
+}
+
+/// Get debug configuration
+pub fn get_config() -> Option<DebugConfig> {
+    DEBUG_CONFIG.get().cloned()
+}
+
+/// Get breakpoint manager
+///
+/// The manager stays locked while the guard is held.
+pub fn get_breakpoint_manager() -> Option<SpinLockGuard<'static, BreakpointManager>> {
+    BREAKPOINT_MANAGER.get().map(SpinLock::lock)
+}
+
+/// Enable debug mode
+///
+/// Sets MDSCR_EL1.MDE/KDE so breakpoints, watchpoints and software step
+/// generate exceptions, and unmasks them. MDCR_EL2.TDE makes EL2 the
+/// debug target, without which debug exceptions are never taken at EL2.
+pub fn enable_debug_mode() -> Result<(), &'static str> {
+    log::debug!("Enabling ARM64 debug mode");
+
+    regs::write_mdcr_el2(regs::read_mdcr_el2() | regs::mdcr::TDE);
+    regs::write_mdscr(regs::read_mdscr() | regs::mdscr::MDE | regs::mdscr::KDE);
+    regs::unmask_debug_exceptions();
+
+    log::debug!("Debug mode enabled");
+    Ok(())
+}
+
+/// Disable debug mode
+pub fn disable_debug_mode() -> Result<(), &'static str> {
+    log::debug!("Disabling ARM64 debug mode");
+
+    regs::write_mdscr(regs::read_mdscr() & !(regs::mdscr::MDE | regs::mdscr::KDE | regs::mdscr::SS));
+    regs::write_mdcr_el2(regs::read_mdcr_el2() & !regs::mdcr::TDE);
+
+    log::debug!("Debug mode disabled");
+    Ok(())
+}
+
+/// Check if debug mode is enabled
+pub fn is_debug_mode_enabled() -> bool {
+    regs::read_mdscr() & regs::mdscr::MDE != 0
+}
+
+/// Set hardware breakpoint
+///
+/// Data breakpoint types set a 4-byte watchpoint; use `set_watchpoint`
+/// for other lengths.
+pub fn set_breakpoint(addr: usize, bp_type: BreakpointType) -> Result<u32, &'static str> {
+    log::debug!("Setting breakpoint at address {:#x}", addr);
+
+    if let Some(mut bp_manager) = get_breakpoint_manager() {
+        let bp_id = bp_manager.set_breakpoint(addr, bp_type)?;
+        log::debug!("Breakpoint {} set at address {:#x}", bp_id, addr);
+        Ok(bp_id)
+    } else {
+        Err("Breakpoint manager not initialized")
+    }
+}
+
+/// Clear hardware breakpoint
+pub fn clear_breakpoint(bp_id: u32) -> Result<(), &'static str> {
+    log::debug!("Clearing breakpoint {}", bp_id);
+
+    if let Some(mut bp_manager) = get_breakpoint_manager() {
+        bp_manager.clear_breakpoint(bp_id)?;
+        log::debug!("Breakpoint {} cleared", bp_id);
+        Ok(())
+    } else {
+        Err("Breakpoint manager not initialized")
+    }
+}
+
+/// Set hardware watchpoint covering `len` bytes at `addr`
+pub fn set_watchpoint(addr: usize, len: usize, bp_type: BreakpointType) -> Result<u32, &'static str> {
+    log::debug!("Setting watchpoint at address {:#x} ({} bytes)", addr, len);
+
+    if let Some(mut bp_manager) = get_breakpoint_manager() {
+        let wp_id = bp_manager.set_watchpoint(addr, len, bp_type)?;
+        log::debug!("Watchpoint {} set at address {:#x}", wp_id, addr);
+        Ok(wp_id)
+    } else {
+        Err("Breakpoint manager not initialized")
+    }
+}
+
+/// Clear hardware watchpoint
+pub fn clear_watchpoint(wp_id: u32) -> Result<(), &'static str> {
+    log::debug!("Clearing watchpoint {}", wp_id);
+
+    if let Some(mut bp_manager) = get_breakpoint_manager() {
+        bp_manager.clear_watchpoint(wp_id)?;
+        log::debug!("Watchpoint {} cleared", wp_id);
+        Ok(())
+    } else {
+        Err("Breakpoint manager not initialized")
+    }
+}
+
+/// Enable single stepping
+///
+/// Sets MDSCR_EL1.SS. Stepping starts once the exception return that
+/// resumes the stepped context has `SPSR_SS` set in its saved PSTATE.
+pub fn enable_single_step() -> Result<(), &'static str> {
+    log::debug!("Enabling single stepping");
+
+    regs::write_mdscr(regs::read_mdscr() | regs::mdscr::SS | regs::mdscr::KDE);
+
+    log::debug!("Single stepping enabled");
+    Ok(())
+}
+
+/// Disable single stepping
+pub fn disable_single_step() -> Result<(), &'static str> {
+    log::debug!("Disabling single stepping");
+
+    regs::write_mdscr(regs::read_mdscr() & !regs::mdscr::SS);
+
+    log::debug!("Single stepping disabled");
+    Ok(())
+}
+
+/// Check if single stepping is enabled
+pub fn is_single_stepping() -> bool {
+    regs::read_mdscr() & regs::mdscr::SS != 0
+}
+
+/// Prepare a saved PSTATE to step one instruction on exception return
+pub fn step_spsr(spsr: u64) -> u64 {
+    spsr | regs::SPSR_SS
+}
+
+/// Handle a synchronous exception that may be a debug exception
+///
+/// Records breakpoint and watchpoint hits in the breakpoint manager. The
+/// triggering entry is disarmed and the instruction single stepped, so
+/// resuming does not retrigger it; the step exception re-arms it.
+/// `spsr` is the saved PSTATE of the interrupted context. Returns `false`
+/// if the exception is not a debug exception this module owns.
+pub fn handle_debug_exception(esr: u64, far: u64, elr: u64, spsr: &mut u64) -> bool {
+    let Some(mut bp_manager) = get_breakpoint_manager() else {
+        return false;
+    };
+
+    match (esr >> 26) & 0x3F {
+        ec::BREAKPOINT => {
+            let Some(bp_id) = bp_manager.hit_breakpoint(elr) else {
+                return false;
+            };
+            log::info!("Breakpoint {} hit at {:#x}", bp_id, elr);
+
+            bp_manager.suspend_breakpoint(bp_id);
+            STEP_OVER_BREAKPOINT.store(bp_id, Ordering::Relaxed);
+        }
+        ec::WATCHPOINT => {
+            let Some(wp_id) = bp_manager.hit_watchpoint(far) else {
+                return false;
+            };
+            log::info!("Watchpoint {} hit at {:#x} (pc {:#x})", wp_id, far, elr);
+
+            bp_manager.suspend_watchpoint(wp_id);
+            STEP_OVER_WATCHPOINT.store(wp_id, Ordering::Relaxed);
+        }
+        ec::SOFTWARE_STEP => {
+            let bp_id = STEP_OVER_BREAKPOINT.swap(NO_STEP_OVER, Ordering::Relaxed);
+            let wp_id = STEP_OVER_WATCHPOINT.swap(NO_STEP_OVER, Ordering::Relaxed);
+            if bp_id == NO_STEP_OVER && wp_id == NO_STEP_OVER {
+                // Stepping requested through enable_single_step: keep going
+                log::debug!("Single step at {:#x}", elr);
+                *spsr = step_spsr(*spsr);
+                return true;
+            }
+
+            if bp_id != NO_STEP_OVER {
+                bp_manager.restore_breakpoint(bp_id);
+            }
+            if wp_id != NO_STEP_OVER {
+                bp_manager.restore_watchpoint(wp_id);
+            }
+            let _ = disable_single_step();
+            return true;
+        }
+        _ => return false,
+    }
+
+    let _ = enable_single_step();
+    *spsr = step_spsr(*spsr);
+    true
+}
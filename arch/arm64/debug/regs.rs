@@ -0,0 +1,260 @@
+//! ARM64 Debug Registers
+//!
+//! This module provides encoding and access for the ARMv8 self-hosted
+//! debug registers:
+//! - Breakpoint value/control registers (DBGBVR<n>_EL1, DBGBCR<n>_EL1)
+//! - Watchpoint value/control registers (DBGWVR<n>_EL1, DBGWCR<n>_EL1)
+//! - Monitor Debug System Control Register (MDSCR_EL1)
+//!
+//! Reference: ARM DDI 0487 - D2 (AArch64 Self-hosted Debug)
+
+/// Architectural maximum number of breakpoint register pairs
+pub const MAX_HW_BREAKPOINTS: usize = 16;
+
+/// Architectural maximum number of watchpoint register pairs
+pub const MAX_HW_WATCHPOINTS: usize = 16;
+
+/// MDSCR_EL1 bits
+pub mod mdscr {
+    /// Software step enable
+    pub const SS: u64 = 1 << 0;
+    /// Local (kernel) debug enable
+    pub const KDE: u64 = 1 << 13;
+    /// Monitor debug events enable (breakpoints/watchpoints)
+    pub const MDE: u64 = 1 << 15;
+}
+
+/// MDCR_EL2 bits
+pub mod mdcr {
+    /// Route debug exceptions to EL2 and enable them at EL2
+    pub const TDE: u64 = 1 << 8;
+}
+
+/// SPSR.SS: set in the saved PSTATE so the next instruction is stepped
+pub const SPSR_SS: u64 = 1 << 21;
+
+/// DBGBCR/DBGWCR common fields
+pub mod ctrl {
+    /// Enable
+    pub const E: u64 = 1 << 0;
+    /// Privilege mode control shift
+    pub const PMC_SHIFT: u64 = 1;
+    /// Higher mode control (match at EL2)
+    pub const HMC: u64 = 1 << 13;
+    /// Byte address select shift
+    pub const BAS_SHIFT: u64 = 5;
+    /// Watchpoint load/store control shift
+    pub const LSC_SHIFT: u64 = 3;
+    /// Watchpoint address mask shift
+    pub const MASK_SHIFT: u64 = 24;
+}
+
+/// Exception levels a breakpoint or watchpoint matches at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugPrivilege {
+    /// EL0 only
+    El0,
+    /// EL1 only
+    El1,
+    /// EL1 and EL0
+    El1El0,
+    /// EL2 and EL1 (the hypervisor itself)
+    El2,
+}
+
+impl DebugPrivilege {
+    /// HMC and PMC field values
+    fn control_bits(self) -> u64 {
+        match self {
+            DebugPrivilege::El0 => 0b10 << ctrl::PMC_SHIFT,
+            DebugPrivilege::El1 => 0b01 << ctrl::PMC_SHIFT,
+            DebugPrivilege::El1El0 => 0b11 << ctrl::PMC_SHIFT,
+            DebugPrivilege::El2 => ctrl::HMC | (0b01 << ctrl::PMC_SHIFT),
+        }
+    }
+}
+
+/// Watchpoint access type (LSC field)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchAccess {
+    /// Match loads
+    Load = 0b01,
+    /// Match stores
+    Store = 0b10,
+    /// Match loads and stores
+    LoadStore = 0b11,
+}
+
+/// Encode an unlinked instruction address breakpoint
+///
+/// Returns `(DBGBVR, DBGBCR)`. A64 instructions are word aligned, so the
+/// address must be 4-byte aligned.
+pub fn encode_breakpoint(addr: u64, privilege: DebugPrivilege) -> Result<(u64, u64), &'static str> {
+    if addr & 0x3 != 0 {
+        return Err("Breakpoint address must be 4-byte aligned");
+    }
+
+    // BT=0b0000 (unlinked address match), BAS=0b1111 (whole A64 instruction)
+    let bcr = ctrl::E | privilege.control_bits() | (0xF << ctrl::BAS_SHIFT);
+    Ok((addr, bcr))
+}
+
+/// Encode an unlinked data address watchpoint
+///
+/// Returns `(DBGWVR, DBGWCR)`. Lengths up to 8 bytes use the byte
+/// address select field and must not cross a doubleword; larger lengths
+/// must be a power of two with `addr` aligned to it and use the address
+/// mask field.
+pub fn encode_watchpoint(addr: u64, len: u64, access: WatchAccess,
+                         privilege: DebugPrivilege) -> Result<(u64, u64), &'static str> {
+    if len == 0 {
+        return Err("Watchpoint length must be non-zero");
+    }
+
+    let base = ctrl::E | privilege.control_bits() | ((access as u64) << ctrl::LSC_SHIFT);
+
+    if len <= 8 {
+        let offset = addr & 0x7;
+        if offset + len > 8 {
+            return Err("Watchpoint crosses a doubleword boundary");
+        }
+        let bas = ((1u64 << len) - 1) << offset;
+        Ok((addr & !0x7, base | (bas << ctrl::BAS_SHIFT)))
+    } else {
+        if !len.is_power_of_two() || addr & (len - 1) != 0 {
+            return Err("Large watchpoint must be a naturally aligned power of two");
+        }
+        let mask = len.trailing_zeros() as u64;
+        if mask > 31 {
+            return Err("Watchpoint length too large");
+        }
+        Ok((addr, base | (0xFF << ctrl::BAS_SHIFT) | (mask << ctrl::MASK_SHIFT)))
+    }
+}
+
+/// Number of breakpoint register pairs implemented
+pub fn breakpoint_count() -> usize {
+    #[cfg(target_arch = "aarch64")]
+    {
+        let dfr0 = crate::arch::arm64::cpu::regs::info::read_id_aa64dfr0_el1();
+        (((dfr0 >> 12) & 0xF) + 1) as usize
+    }
+
+    #[cfg(not(target_arch = "aarch64"))]
+    {
+        MAX_HW_BREAKPOINTS
+    }
+}
+
+/// Number of watchpoint register pairs implemented
+pub fn watchpoint_count() -> usize {
+    #[cfg(target_arch = "aarch64")]
+    {
+        let dfr0 = crate::arch::arm64::cpu::regs::info::read_id_aa64dfr0_el1();
+        (((dfr0 >> 20) & 0xF) + 1) as usize
+    }
+
+    #[cfg(not(target_arch = "aarch64"))]
+    {
+        MAX_HW_WATCHPOINTS
+    }
+}
+
+/// Write DBG<B|W><V|C>R<n>_EL1 (op0=2, op1=0, CRn=0, CRm=n)
+#[cfg(target_arch = "aarch64")]
+macro_rules! write_dbg_reg {
+    ($n:expr, $op2:literal, $value:expr) => {
+        write_dbg_reg!(@slots $n, $op2, $value, [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15])
+    };
+    (@slots $n:expr, $op2:literal, $value:expr, [$($i:literal),*]) => {
+        match $n {
+            $($i => crate::arch::arm64::cpu::regs::write_sysreg::<2, 0, 0, $i, $op2>($value),)*
+            _ => {}
+        }
+    };
+}
+
+/// Program breakpoint register pair `n`
+///
+/// The control register is written last so the pair is never enabled
+/// with a stale value.
+pub fn write_breakpoint(n: usize, bvr: u64, bcr: u64) {
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        write_dbg_reg!(n, 5, 0);
+        write_dbg_reg!(n, 4, bvr);
+        write_dbg_reg!(n, 5, bcr);
+        core::arch::asm!("isb");
+    }
+
+    #[cfg(not(target_arch = "aarch64"))]
+    let _ = (n, bvr, bcr);
+}
+
+/// Program watchpoint register pair `n`
+pub fn write_watchpoint(n: usize, wvr: u64, wcr: u64) {
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        write_dbg_reg!(n, 7, 0);
+        write_dbg_reg!(n, 6, wvr);
+        write_dbg_reg!(n, 7, wcr);
+        core::arch::asm!("isb");
+    }
+
+    #[cfg(not(target_arch = "aarch64"))]
+    let _ = (n, wvr, wcr);
+}
+
+/// Read MDSCR_EL1
+pub fn read_mdscr() -> u64 {
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        crate::arch::arm64::cpu::regs::read_sysreg::<2, 0, 0, 2, 2>()
+    }
+
+    #[cfg(not(target_arch = "aarch64"))]
+    0
+}
+
+/// Write MDSCR_EL1
+pub fn write_mdscr(value: u64) {
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        crate::arch::arm64::cpu::regs::write_sysreg::<2, 0, 0, 2, 2>(value);
+        core::arch::asm!("isb");
+    }
+
+    #[cfg(not(target_arch = "aarch64"))]
+    let _ = value;
+}
+
+/// Read MDCR_EL2
+pub fn read_mdcr_el2() -> u64 {
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        crate::arch::arm64::cpu::regs::read_sysreg::<3, 4, 1, 1, 1>()
+    }
+
+    #[cfg(not(target_arch = "aarch64"))]
+    0
+}
+
+/// Write MDCR_EL2
+pub fn write_mdcr_el2(value: u64) {
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        crate::arch::arm64::cpu::regs::write_sysreg::<3, 4, 1, 1, 1>(value);
+        core::arch::asm!("isb");
+    }
+
+    #[cfg(not(target_arch = "aarch64"))]
+    let _ = value;
+}
+
+/// Unmask debug exceptions (clear PSTATE.D)
+pub fn unmask_debug_exceptions() {
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        core::arch::asm!("msr daifclr, #8");
+    }
+}
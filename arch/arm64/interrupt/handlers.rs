@@ -30,6 +30,8 @@
 
 use core::ffi::c_void;
 
+use crate::arch::arm64::cpu::regs::el2;
+use crate::arch::arm64::mmu::vttbr;
 use crate::arch::arm64::cpu::vcpu::{
     ExtendedVcpuContext, TrapInfo, TrapHandler, DefaultTrapHandler, handle_trap,
 };
@@ -431,12 +433,17 @@ fn handle_guest_trap(
         vcpu_ctx.sysregs.spsr_el1 = ctx_ref.spsr as u64;
 
         // Handle the trap
-        let resolution = handle_trap(&vcpu_ctx, &trap, handler)?;
+        let resolution = handle_trap(&mut vcpu_ctx, &trap, handler)?;
 
         // Apply resolution
         match resolution {
             crate::arch::arm64::cpu::vcpu::TrapResolution::Resume => {
-                // Just return to guest
+                // Hand back register results; a trapped SMC returns to
+                // itself, so step over it
+                (*ctx).x = vcpu_ctx.gprs.x;
+                if matches!(trap.reason, crate::arch::arm64::cpu::vcpu::TrapReason::SmcCall) {
+                    (*ctx).elr += 4;
+                }
                 log::debug!("Trap handled, resuming guest");
                 Ok(())
             }
@@ -451,8 +458,7 @@ fn handle_guest_trap(
             }
             crate::arch::arm64::cpu::vcpu::TrapResolution::Emulate => {
                 log::debug!("Trap requires emulation");
-                // TODO: Implement instruction emulation
-                Err("Emulation not implemented")
+                emulate_mmio(&mut *ctx, &trap)
             }
             crate::arch::arm64::cpu::vcpu::TrapResolution::Callback => {
                 log::debug!("Trap requires callback to higher level");
@@ -462,10 +468,75 @@ fn handle_guest_trap(
     }
 }
 
+/// Emulate a trapped guest load or store to an emulated device
+///
+/// The access is decoded from the data abort ISS, so only accesses that
+/// report a valid instruction syndrome can be emulated. The faulting IPA is
+/// dispatched to the devices of the VM whose VMID is in VTTBR_EL2, and the
+/// guest is stepped past the instruction.
+fn emulate_mmio(ctx: &mut ExceptionContext, trap: &TrapInfo) -> Result<(), &'static str> {
+    const ISS_ISV: u32 = 1 << 24;
+    const ISS_SSE: u32 = 1 << 21;
+    const ISS_SF: u32 = 1 << 15;
+    const ISS_WNR: u32 = 1 << 6;
+    const XZR: usize = 31;
+
+    if trap.iss & ISS_ISV == 0 {
+        return Err("MMIO access without instruction syndrome");
+    }
+    let size = 8u32 << ((trap.iss >> 22) & 0x3);
+    let srt = ((trap.iss >> 16) & 0x1F) as usize;
+    if srt != XZR && srt >= ctx.x.len() {
+        return Err("MMIO transfer register not saved");
+    }
+
+    let vm_id = vttbr::extract_vmid(el2::read_vttbr_el2()) as crate::core::vmm::VmId;
+    let ipa = ((el2::read_hpfar_el2() >> 4) << 12) | (trap.far & 0xFFF);
+    if trap.iss & ISS_WNR != 0 {
+        let value = if srt == XZR { 0 } else { ctx.x[srt] };
+        crate::emulator::mmio_write(vm_id, ipa, value, size)
+            .ok_or("No emulator at MMIO address")?
+            .map_err(|_| "MMIO write failed")?;
+    } else {
+        let mut value = crate::emulator::mmio_read(vm_id, ipa, size)
+            .ok_or("No emulator at MMIO address")?
+            .map_err(|_| "MMIO read failed")?;
+        if trap.iss & ISS_SSE != 0 && size < 64 {
+            let shift = 64 - size;
+            value = (((value << shift) as i64) >> shift) as u64;
+        }
+        if trap.iss & ISS_SF == 0 {
+            value &= 0xFFFF_FFFF;
+        }
+        if srt != XZR {
+            ctx.x[srt] = value;
+        }
+    }
+
+    // Data aborts with a valid syndrome are always 32-bit instructions
+    ctx.elr += 4;
+    Ok(())
+}
+
 /// Internal exception handler
 fn handle_exception(ctx: *mut ExceptionContext, exc_type: u32) {
     let exc_type = ExceptionType::from_raw(exc_type);
 
+    // Breakpoints, watchpoints and steps set through the debug module
+    if exc_type.is_sync() {
+        let esr: u64;
+        let far: u64;
+        unsafe {
+            core::arch::asm!("mrs {}, esr_el2", out(reg) esr);
+            core::arch::asm!("mrs {}, far_el2", out(reg) far);
+
+            let ctx = &mut *ctx;
+            if crate::arch::arm64::debug::handle_debug_exception(esr, far, ctx.elr, &mut ctx.spsr) {
+                return;
+            }
+        }
+    }
+
     // Log exception
     log::error!("Exception: {}", exc_type.name());
     log::error!("  ELR={:#018x}, SPSR={:#08x}",
@@ -4,7 +4,10 @@
 //! Reference: ARM DDI 0487I.a - Chapter D13 - Exception Syndrome Register
 
 use crate::{Result, Error};
-use crate::arch::arm64::mm::{gstage, translate};
+use crate::arch::arm64::mm::translate;
+use crate::core::mm::gstage;
+use crate::core::vmm::VmId;
+use crate::core::vmm::vm::{self, GuestRegionKind};
 
 /// Stage-2 fault information
 #[derive(Debug, Clone, Copy)]
@@ -358,6 +361,8 @@ pub unsafe fn decode_fault_from_el2(is_instruction_abort: bool) -> FaultInfo {
 pub enum FaultResolution {
     /// Fault was resolved, execution can continue
     Resolved,
+    /// Fault hit an MMIO region, emulate the access
+    EmulateMmio,
     /// Fault could not be resolved, inject exception to guest
     InjectException,
     /// Fault is fatal, terminate VM
@@ -366,8 +371,12 @@ pub enum FaultResolution {
 
 /// Resolve a Stage-2 fault
 ///
-/// This function attempts to resolve a Stage-2 fault by updating
-/// page tables or taking other corrective action.
+/// This function looks the faulting IPA up in the VM's memory map.
+/// Accesses to MMIO regions are handed to emulation; RAM and ROM
+/// regions are resolved by updating the stage-2 page tables, charging
+/// newly backed pages to the VM; a VM over its memory limit is fatal.
+/// Accesses outside any region, or not permitted by it, are reflected to
+/// the guest.
 ///
 /// # Arguments
 /// * `fault_info` - Fault information
@@ -376,6 +385,57 @@ pub enum FaultResolution {
 /// # Returns
 /// Fault resolution result
 pub fn resolve_fault(fault_info: FaultInfo, vmid: u16) -> FaultResolution {
+    let region = match vm::find_region(vmid as VmId, fault_info.ipa) {
+        Some(region) => region,
+        // The VM's platform devices, including BARs the guest programmed
+        None if crate::emulator::claims_mmio(vmid as VmId, fault_info.ipa) => return FaultResolution::EmulateMmio,
+        None => {
+            log::debug!("No memory region at IPA {:#x}", fault_info.ipa);
+            return FaultResolution::InjectException;
+        }
+    };
+
+    if region.kind == GuestRegionKind::Mmio {
+        return FaultResolution::EmulateMmio;
+    }
+
+    if !region.permits(fault_info.write, fault_info.instruction) {
+        log::debug!("{:?} region at {:#x} denies access to IPA {:#x}",
+                    region.kind, region.gpa, fault_info.ipa);
+        return FaultResolution::InjectException;
+    }
+
+    // With dirty tracking on, writes to RAM fault on write-protected pages;
+    // the dirty tracker records them and restores write access
+    if fault_info.write && matches!(fault_info.fault, Stage2Fault::Permission { .. }) {
+        let tracked = gstage::get()
+            .ok_or(Error::NotInitialized)
+            .and_then(|manager| manager.handle_write_fault(vmid, fault_info.ipa));
+        match tracked {
+            Ok(true) => return FaultResolution::Resolved,
+            Ok(false) => {}
+            Err(e) => {
+                log::error!("VMID {}: write fault at IPA {:#x}: {:?}", vmid, fault_info.ipa, e);
+                return FaultResolution::Fatal;
+            }
+        }
+    }
+
+    // RAM and ROM are backed on first touch
+    if matches!(fault_info.fault, Stage2Fault::Translation { .. }) {
+        return match vm::resolve_stage2_fault(vmid as VmId, fault_info.ipa) {
+            Ok(_) => FaultResolution::Resolved,
+            Err(Error::OutOfMemory) => {
+                log::error!("VMID {}: out of memory backing IPA {:#x}", vmid, fault_info.ipa);
+                FaultResolution::Fatal
+            }
+            Err(e) => {
+                log::debug!("VMID {}: cannot back IPA {:#x}: {:?}", vmid, fault_info.ipa, e);
+                FaultResolution::InjectException
+            }
+        };
+    }
+
     match handle_stage2_fault(fault_info, vmid) {
         Ok(true) => FaultResolution::Resolved,
         Ok(false) => FaultResolution::InjectException,
@@ -8,6 +8,7 @@
 //! - SMP support (PSCI, Spin Table)
 //! - Timer support (Generic Timer)
 //! - Device tree support
+//! - Self-hosted debug (breakpoints, watchpoints, single-step)
 //!
 //! ## Architecture Overview
 //!
@@ -32,6 +33,7 @@ pub mod platform;
 pub mod psci;
 pub mod timer;
 pub mod devtree;
+pub mod debug;
 
 // Include comprehensive unit tests
 #[cfg(test)]
@@ -289,6 +291,9 @@ pub fn arch_init() -> Result<(), &'static str> {
     // Initialize interrupt handling (GIC/VGIC)
     interrupt::init()?;
 
+    // Parse the device tree
+    devtree::init()?;
+
     // Initialize SMP
     smp::init()?;
 
@@ -304,8 +309,11 @@ pub fn arch_init() -> Result<(), &'static str> {
 
 /// ARM64 panic handler
 pub fn panic(info: &core::panic::PanicInfo) -> ! {
-    // TODO: Output panic info via UART
-    // For now, just halt
+    crate::utils::console::enter_panic();
+    crate::utils::console::print_fmt(format_args!("\n!!! PANIC !!!\n{}\n", info));
+    crate::utils::backtrace::print_backtrace();
+    crate::utils::log_ring::dump_to_console();
+
     loop {
         // Wait For Event (low power mode)
         unsafe { core::arch::asm!("wfe") };
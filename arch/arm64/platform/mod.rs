@@ -11,6 +11,8 @@ pub mod foundation_v8;
 pub use qemu_virt::*;
 pub use foundation_v8::*;
 
+use crate::libs::fdt::Fdt;
+
 /// Platform trait - common interface for all platforms
 pub trait Platform {
     /// Get platform name
@@ -53,6 +55,28 @@ pub fn detect_platform() -> Result<&'static dyn Platform, &'static str> {
     Err("No supported platform detected")
 }
 
+/// Detect current platform from a flattened device tree blob
+///
+/// Matches the root node's `compatible` list against each supported
+/// platform's compatible string.
+pub fn detect_platform_from_fdt(fdt: &Fdt) -> Result<&'static dyn Platform, &'static str> {
+    let root = fdt.root().ok_or("Device tree has no root node")?;
+
+    if let Ok(qemu) = qemu_virt::QemuVirtPlatform::probe() {
+        if root.is_compatible(qemu.compatible()) {
+            return Ok(qemu);
+        }
+    }
+
+    if let Ok(foundation) = foundation_v8::FoundationV8Platform::probe() {
+        if root.is_compatible(foundation.compatible()) {
+            return Ok(foundation);
+        }
+    }
+
+    Err("No supported platform in device tree")
+}
+
 /// Initialize platform
 pub fn init() -> Result<(), &'static str> {
     log::info!("Platform: Detecting ARM64 platform");
@@ -64,6 +88,14 @@ pub fn init() -> Result<(), &'static str> {
     // Early init
     platform.early_init()?;
 
+    // Secondaries without PSCI are released through their spin tables
+    if let Some(hw_info) = crate::arch::arm64::devtree::get_hw_info() {
+        match crate::arch::arm64::smp::spin_table::configure_from_device_tree(&hw_info.cpus) {
+            Ok(count) => log::info!("Platform: {} spin-table CPUs configured", count),
+            Err(e) => log::warn!("Platform: spin table not configured: {}", e),
+        }
+    }
+
     log::info!("Platform: {} initialized successfully", platform.name());
     Ok(())
 }
@@ -83,6 +115,7 @@ pub fn get_platform() -> Option<&'static dyn Platform> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use alloc::vec::Vec;
 
     #[test]
     fn test_platform_detect() {
@@ -92,4 +125,36 @@ mod tests {
         // May fail on systems without device tree
         drop(result);
     }
+
+    #[test]
+    fn test_platform_detect_from_fdt() {
+        // Minimal tree: a root node with compatible = "linux,dummy-virt"
+        let strings = b"compatible\0";
+        let mut structure = Vec::new();
+        for word in [1u32, 0, 3, 17, 0] {
+            structure.extend_from_slice(&word.to_be_bytes());
+        }
+        structure.extend_from_slice(b"linux,dummy-virt\0\0\0\0");
+        for word in [2u32, 9] {
+            structure.extend_from_slice(&word.to_be_bytes());
+        }
+
+        let off_strings = 56 + structure.len() as u32;
+        let total = off_strings + strings.len() as u32;
+        let header = [
+            0xd00dfeed, total, 56, off_strings, 40, 17, 16, 0,
+            strings.len() as u32, structure.len() as u32,
+        ];
+        let mut blob = Vec::new();
+        for word in header {
+            blob.extend_from_slice(&word.to_be_bytes());
+        }
+        blob.extend_from_slice(&[0u8; 16]);
+        blob.extend_from_slice(&structure);
+        blob.extend_from_slice(strings);
+
+        let fdt = Fdt::from_bytes(&blob).unwrap();
+        let platform = detect_platform_from_fdt(&fdt).unwrap();
+        assert_eq!(platform.compatible(), "linux,dummy-virt");
+    }
 }
@@ -10,6 +10,7 @@
 //! - Return value conventions
 
 use super::{PsciReturn, PSCI_0_2_FN_PSCI_VERSION};
+use crate::core::sync::SpinLock;
 
 /// SMCCC function ID immediate mask
 pub const SMCCC_FUNC_ID_MASK: u32 = 0xFFFF;
@@ -427,6 +428,160 @@ impl SmcccClientId {
     }
 }
 
+/// SMCCC return value for an unknown function ID
+pub const SMCCC_NOT_SUPPORTED: u64 = -1i64 as u64;
+
+/// Owning entity of an SMCCC fast call (function ID bits [29:24])
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmcccOwner {
+    /// Arm Architecture calls
+    Arch,
+    /// CPU service calls
+    Cpu,
+    /// SiP (silicon vendor) service calls
+    Sip,
+    /// OEM service calls
+    Oem,
+    /// PSCI, the first 0x20 Standard Secure Service functions
+    Psci,
+    /// Remaining Standard Secure Service calls (TRNG, ...)
+    StdSecure,
+    /// Standard Hypervisor Service calls
+    StdHyp,
+    /// Vendor Specific Hypervisor Service calls
+    VendorHyp,
+    /// Trusted Application calls
+    TrustedApp,
+    /// Trusted OS calls
+    TrustedOs,
+}
+
+impl SmcccOwner {
+    /// Number of owners
+    pub const COUNT: usize = 10;
+
+    /// Decode the owner of a function ID
+    ///
+    /// Returns `None` for reserved owning entity numbers.
+    pub fn from_function_id(function_id: u32) -> Option<Self> {
+        let oen = (function_id >> 24) & 0x3F;
+        let function = function_id & SMCCC_FUNC_ID_MASK;
+        Some(match oen {
+            0x00 => Self::Arch,
+            0x01 => Self::Cpu,
+            0x02 => Self::Sip,
+            0x03 => Self::Oem,
+            0x04 if function < 0x20 => Self::Psci,
+            0x04 => Self::StdSecure,
+            0x05 => Self::StdHyp,
+            0x06 => Self::VendorHyp,
+            0x30..=0x31 => Self::TrustedApp,
+            0x32..=0x3F => Self::TrustedOs,
+            _ => return None,
+        })
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Handler for calls owned by one entity
+pub type SmcccHandler = fn(&SmcccRegs) -> SmcccResult;
+
+/// Routes SMCCC calls to the handler registered for their owner
+pub struct SmcccDispatcher {
+    /// Handlers indexed by owner
+    handlers: [Option<SmcccHandler>; SmcccOwner::COUNT],
+}
+
+impl SmcccDispatcher {
+    /// Create a dispatcher with no handlers
+    pub const fn new() -> Self {
+        Self { handlers: [None; SmcccOwner::COUNT] }
+    }
+
+    /// Create a dispatcher routing PSCI to the hypervisor's PSCI emulation
+    pub const fn with_psci() -> Self {
+        let mut dispatcher = Self::new();
+        dispatcher.handlers[SmcccOwner::Psci as usize] = Some(psci_handler);
+        dispatcher
+    }
+
+    /// Register (or replace) the handler for an owner
+    pub fn register(&mut self, owner: SmcccOwner, handler: SmcccHandler) {
+        self.handlers[owner.index()] = Some(handler);
+    }
+
+    /// Remove the handler for an owner
+    pub fn unregister(&mut self, owner: SmcccOwner) {
+        self.handlers[owner.index()] = None;
+    }
+
+    /// Handler for a function ID, `None` if its owner is reserved or has
+    /// no handler
+    pub fn handler(&self, function_id: u32) -> Option<SmcccHandler> {
+        SmcccOwner::from_function_id(function_id).and_then(|owner| self.handlers[owner.index()])
+    }
+
+    /// Dispatch a call
+    ///
+    /// Calls with a reserved owner or no registered handler return
+    /// `SMCCC_NOT_SUPPORTED` in x0.
+    pub fn dispatch(&self, regs: &SmcccRegs) -> SmcccResult {
+        dispatch_with(self.handler(regs.function_id()), regs)
+    }
+}
+
+/// Run a looked-up handler, refusing calls without one
+fn dispatch_with(handler: Option<SmcccHandler>, regs: &SmcccRegs) -> SmcccResult {
+    match handler {
+        Some(handler) => handler(regs),
+        None => {
+            log::debug!("SMCCC: unsupported function ID {:#x}", regs.function_id());
+            SmcccResult::new(SMCCC_NOT_SUPPORTED)
+        }
+    }
+}
+
+/// Route PSCI calls to the PSCI emulation
+fn psci_handler(regs: &SmcccRegs) -> SmcccResult {
+    let (x0, ret) = super::handle_smc(regs.function_id(), &[regs.x1, regs.x2, regs.x3]);
+    SmcccResult::from_psci(x0, ret)
+}
+
+/// Forward a call unchanged to EL3 firmware
+///
+/// Register this for owners whose services the hypervisor passes
+/// through, e.g. `StdSecure` for TRNG.
+pub fn forward_to_firmware(regs: &SmcccRegs) -> SmcccResult {
+    #[cfg(target_arch = "aarch64")]
+    {
+        unsafe { smc_call(regs) }
+    }
+
+    #[cfg(not(target_arch = "aarch64"))]
+    {
+        let _ = regs;
+        SmcccResult::new(SMCCC_NOT_SUPPORTED)
+    }
+}
+
+/// Global SMCCC dispatcher
+static SMCCC_DISPATCHER: SpinLock<SmcccDispatcher> = SpinLock::new(SmcccDispatcher::with_psci());
+
+/// Register the handler for calls owned by `owner`
+pub fn register_smccc_handler(owner: SmcccOwner, handler: SmcccHandler) {
+    SMCCC_DISPATCHER.lock().register(owner, handler);
+}
+
+/// Dispatch a guest SMC/HVC call
+pub fn dispatch_smccc(regs: &SmcccRegs) -> SmcccResult {
+    // Release the dispatcher before calling out so handlers may register
+    let handler = SMCCC_DISPATCHER.lock().handler(regs.function_id());
+    dispatch_with(handler, regs)
+}
+
 /// Execute SMC call (hypervisor-to-firmware)
 ///
 /// # Safety
@@ -453,8 +608,7 @@ pub unsafe fn smc_call(regs: &SmcccRegs) -> SmcccResult {
         clobber_abi("system")
     );
 
-    // For simplicity, only return x0
-    SmcccResult::new(x0)
+    SmcccResult::with_x3(x0, x1, x2, x3)
 }
 
 /// Execute HVC call (hypervisor-to-hypervisor)
@@ -537,4 +691,37 @@ mod tests {
         assert!(fid.call_type().is_fast());
         assert!(fid.call_conv().is_64bit());
     }
+
+    fn mock_psci(_regs: &SmcccRegs) -> SmcccResult {
+        SmcccResult::new(0x1_0000)
+    }
+
+    #[test]
+    fn test_smccc_owner_decode() {
+        assert_eq!(SmcccOwner::from_function_id(0x84000003), Some(SmcccOwner::Psci));
+        assert_eq!(SmcccOwner::from_function_id(0xC4000003), Some(SmcccOwner::Psci));
+        // TRNG_VERSION
+        assert_eq!(SmcccOwner::from_function_id(0x84000050), Some(SmcccOwner::StdSecure));
+        assert_eq!(SmcccOwner::from_function_id(0xC2000001), Some(SmcccOwner::Sip));
+        assert_eq!(SmcccOwner::from_function_id(0x86000000), Some(SmcccOwner::VendorHyp));
+        assert_eq!(SmcccOwner::from_function_id(0xB2000000), Some(SmcccOwner::TrustedOs));
+        assert_eq!(SmcccOwner::from_function_id(0x8A000000), None);
+    }
+
+    #[test]
+    fn test_smccc_dispatch_routes_by_owner() {
+        let mut dispatcher = SmcccDispatcher::new();
+        dispatcher.register(SmcccOwner::Psci, mock_psci);
+
+        let result = dispatcher.dispatch(&SmcccRegs::with_function_id(0x84000000));
+        assert_eq!(result.x0, 0x1_0000);
+
+        // Unregistered vendor call
+        let result = dispatcher.dispatch(&SmcccRegs::with_function_id(0xC2000001));
+        assert_eq!(result.x0, SMCCC_NOT_SUPPORTED);
+
+        // Reserved owner
+        let result = dispatcher.dispatch(&SmcccRegs::with_function_id(0x8A000000));
+        assert_eq!(result.x0, SMCCC_NOT_SUPPORTED);
+    }
 }
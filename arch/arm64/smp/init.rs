@@ -221,6 +221,11 @@ extern "C" fn secondary_init() {
     // TODO: Enable MMU
     // TODO: Initialize GIC CPU interface
 
+    // Arm this CPU's comparator for the scheduler tick
+    if let Err(e) = crate::core::time::cpu_online() {
+        log::error!("SMP Init: CPU {} failed to arm scheduler tick: {:?}", logical_id, e);
+    }
+
     // Mark CPU as online
     if let Some(mgr) = super::manager_mut() {
         let _ = mgr.mark_cpu_online(logical_id);
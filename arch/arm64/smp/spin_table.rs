@@ -21,6 +21,15 @@
 //! - [Xvisor Spin Table Implementation](https://github.com/xvisor/xvisor)
 
 use super::{SmpOps, CpuState, MAX_CPUS};
+use crate::arch::arm64::devtree::{CpuEnableMethod, CpuInfo};
+
+/// Write a 64-bit value to a spin table address
+///
+/// The default writes physical memory directly; tests substitute a mock.
+pub type SpinTableWriteFn = unsafe fn(addr: u64, value: u64);
+
+/// Wake secondary CPUs waiting in WFE
+pub type SpinTableWakeFn = fn();
 
 /// Spin table entry in memory
 ///
@@ -105,15 +114,15 @@ pub struct SpinTableSmpOps {
     secondary_entry: u64,
     /// Number of configured CPUs
     count: usize,
+    /// Writes release and clear addresses
+    write: SpinTableWriteFn,
+    /// Wakes the released CPUs
+    wake: SpinTableWakeFn,
 }
 
 impl Default for SpinTableSmpOps {
     fn default() -> Self {
-        Self {
-            configs: [None; MAX_CPUS],
-            secondary_entry: 0,
-            count: 0,
-        }
+        Self::with_hooks(write_release_value, wake_cpus)
     }
 }
 
@@ -123,6 +132,17 @@ impl SpinTableSmpOps {
         Self::default()
     }
 
+    /// Create spin table SMP operations with custom memory write and wake hooks
+    pub fn with_hooks(write: SpinTableWriteFn, wake: SpinTableWakeFn) -> Self {
+        Self {
+            configs: [None; MAX_CPUS],
+            secondary_entry: 0,
+            count: 0,
+            write,
+            wake,
+        }
+    }
+
     /// Set secondary entry point
     pub fn set_secondary_entry(&mut self, entry: u64) {
         self.secondary_entry = entry;
@@ -147,8 +167,9 @@ impl SpinTableSmpOps {
             config.set_clear_addr(clear);
         }
 
-        self.configs[logical_id as usize] = Some(config);
-        self.count += 1;
+        if self.configs[logical_id as usize].replace(config).is_none() {
+            self.count += 1;
+        }
 
         log::info!("Spin Table: CPU {} release={:#x} clear={:?}",
                    logical_id, release_addr, clear_addr);
@@ -156,6 +177,32 @@ impl SpinTableSmpOps {
         Ok(())
     }
 
+    /// Configure every spin-table CPU described by the device tree
+    ///
+    /// CPUs using another enable-method, or missing `cpu-release-addr`,
+    /// are skipped. Returns the number of CPUs configured.
+    pub fn configure_from_cpus(&mut self, cpus: &[CpuInfo]) -> usize {
+        let mut configured = 0;
+
+        for cpu in cpus.iter().filter(|cpu| !cpu.is_boot_cpu()) {
+            if cpu.enable_method != CpuEnableMethod::SpinTable {
+                continue;
+            }
+            let release_addr = match cpu.release_addr {
+                Some(addr) => addr,
+                None => {
+                    log::warn!("Spin Table: CPU {} has no cpu-release-addr", cpu.cpu_id);
+                    continue;
+                }
+            };
+            if self.configure_cpu(cpu.cpu_id, release_addr, None).is_ok() {
+                configured += 1;
+            }
+        }
+
+        configured
+    }
+
     /// Get CPU configuration
     pub fn cpu_config(&self, logical_id: u32) -> Option<&SpinTableConfig> {
         if logical_id as usize >= MAX_CPUS {
@@ -169,40 +216,52 @@ impl SpinTableSmpOps {
         self.count
     }
 
-    /// Write spin table entry to memory
+    /// Write an entry point to a release address
     ///
     /// # Safety
     ///
-    /// This function writes to physical memory.
-    unsafe fn write_entry(addr: u64, entry: SpinTableEntry) {
-        // Write to physical address
-        // In a real implementation, this would use proper memory mapping
-        let ptr = addr as *mut SpinTableEntry;
-        ptr.write_volatile(entry);
-
-        // Data memory barrier to ensure write is visible
-        core::arch::asm!("dmb ish", options(nostack, nomem));
+    /// `addr` must be the release address of a CPU held in the spin table.
+    unsafe fn write_entry(&self, addr: u64, entry: SpinTableEntry) {
+        // The secondary polls a single 64-bit word
+        (self.write)(addr, entry.entry_point);
     }
 
     /// Write clear value to memory
     ///
     /// # Safety
     ///
-    /// This function writes to physical memory.
-    unsafe fn write_clear(addr: u64) {
-        // Write ~0 to clear address
-        let ptr = addr as *mut u64;
-        ptr.write_volatile(0xFFFFFFFFFFFFFFFFu64);
-
-        // Data memory barrier
-        core::arch::asm!("dmb ish", options(nostack, nomem));
+    /// `addr` must be the clear address of a CPU held in the spin table.
+    unsafe fn write_clear(&self, addr: u64) {
+        (self.write)(addr, u64::MAX);
     }
 
     /// Send SEV (Send Event) to wake up CPUs
     fn send_event(&self) {
-        unsafe {
-            core::arch::asm!("sev", options(nostack, nomem));
-        }
+        (self.wake)();
+    }
+}
+
+/// Write a value to a spin table address in physical memory
+///
+/// Secondaries poll with their caches off, so the line is cleaned to the
+/// point of coherency and the write completed before they are woken.
+///
+/// # Safety
+///
+/// `addr` must be an identity-mapped spin table address.
+unsafe fn write_release_value(addr: u64, value: u64) {
+    let ptr = addr as *mut u64;
+    ptr.write_volatile(value);
+
+    #[cfg(target_arch = "aarch64")]
+    core::arch::asm!("dc civac, {}", "dsb sy", in(reg) addr, options(nostack));
+}
+
+/// Complete outstanding writes and wake CPUs waiting in WFE
+fn wake_cpus() {
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        core::arch::asm!("dsb sy", "sev", options(nostack, nomem));
     }
 }
 
@@ -252,7 +311,7 @@ impl SmpOps for SpinTableSmpOps {
         unsafe {
             if let Some(clear_addr) = config.clear_addr {
                 log::debug!("Spin Table: Writing clear to {:#x}", clear_addr);
-                Self::write_clear(clear_addr);
+                self.write_clear(clear_addr);
             }
 
             // Write entry point to release address
@@ -260,7 +319,7 @@ impl SmpOps for SpinTableSmpOps {
                 log::debug!("Spin Table: Writing entry {:#x} to {:#x}",
                            self.secondary_entry, release_addr);
                 let entry = SpinTableEntry::new(self.secondary_entry);
-                Self::write_entry(release_addr, entry);
+                self.write_entry(release_addr, entry);
             }
         }
 
@@ -282,15 +341,12 @@ impl SmpOps for SpinTableSmpOps {
         log::info!("Spin Table: Booting CPU {} (entry={:#x})",
                    logical_id, entry_point);
 
-        // Write entry point to release address
+        let release_addr = config.release_addr.ok_or("CPU has no release address")?;
+
+        // Write entry point to release address, then wake the CPU
         unsafe {
-            if let Some(release_addr) = config.release_addr {
-                let entry = SpinTableEntry::new(entry_point);
-                Self::write_entry(release_addr, entry);
-            }
+            self.write_entry(release_addr, SpinTableEntry::new(entry_point));
         }
-
-        // Send event to wake up CPU
         self.send_event();
 
         log::info!("Spin Table: CPU {} boot initiated", logical_id);
@@ -358,6 +414,15 @@ pub fn configure_cpu(logical_id: u32, release_addr: u64,
     }
 }
 
+/// Configure spin table CPUs from the parsed device tree CPU nodes
+///
+/// Returns the number of CPUs configured.
+pub fn configure_from_device_tree(cpus: &[CpuInfo]) -> Result<usize, &'static str> {
+    ops_mut()
+        .map(|ops| ops.configure_from_cpus(cpus))
+        .ok_or("Spin table ops not initialized")
+}
+
 /// Set secondary entry point for all CPUs
 pub fn set_secondary_entry_point(entry: u64) {
     if let Some(ops) = ops_mut() {
@@ -385,12 +450,26 @@ pub fn send_event() {
 ///
 /// This function writes to physical memory.
 pub unsafe fn write_spin_table_entry(addr: u64, entry: SpinTableEntry) {
-    SpinTableSmpOps::write_entry(addr, entry);
+    write_release_value(addr, entry.entry_point);
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+    static RELEASE_ADDR: AtomicU64 = AtomicU64::new(0);
+    static RELEASE_VALUE: AtomicU64 = AtomicU64::new(0);
+    static WAKES: AtomicUsize = AtomicUsize::new(0);
+
+    unsafe fn mock_write(addr: u64, value: u64) {
+        RELEASE_ADDR.store(addr, Ordering::SeqCst);
+        RELEASE_VALUE.store(value, Ordering::SeqCst);
+    }
+
+    fn mock_wake() {
+        WAKES.fetch_add(1, Ordering::SeqCst);
+    }
 
     #[test]
     fn test_spin_table_entry() {
@@ -441,6 +520,31 @@ mod tests {
         assert_eq!(config.clear_addr, Some(0x80001000));
     }
 
+    #[test]
+    fn test_cpu_boot_writes_release_addr_and_wakes() {
+        let mut ops = SpinTableSmpOps::with_hooks(mock_write, mock_wake);
+
+        let mut cpus = [CpuInfo::new(0, 0), CpuInfo::new(1, 1), CpuInfo::new(2, 2)];
+        for cpu in cpus.iter_mut() {
+            cpu.enable_method = CpuEnableMethod::SpinTable;
+            cpu.release_addr = Some(0x8000_fff8);
+        }
+        cpus[1].release_addr = Some(0x8000_0ff0);
+        cpus[2].enable_method = CpuEnableMethod::Psci;
+        assert_eq!(ops.configure_from_cpus(&cpus), 1);
+        assert!(ops.cpu_config(0).is_none());
+        assert!(ops.cpu_config(2).is_none());
+
+        ops.cpu_boot(1, 0x4008_0000, 1).unwrap();
+        assert_eq!(RELEASE_ADDR.load(Ordering::SeqCst), 0x8000_0ff0);
+        assert_eq!(RELEASE_VALUE.load(Ordering::SeqCst), 0x4008_0000);
+        assert_eq!(WAKES.load(Ordering::SeqCst), 1);
+
+        // A CPU without a spin table entry is neither written nor woken
+        assert!(ops.cpu_boot(2, 0x4008_0000, 2).is_err());
+        assert_eq!(WAKES.load(Ordering::SeqCst), 1);
+    }
+
     #[test]
     fn test_secondary_entry_point() {
         assert_eq!(secondary_entry_point(), 0);
@@ -160,15 +160,44 @@ pub trait MmioAccess {
     fn write_u64(&self, offset: usize, value: u64);
 }
 
-/// Simple MMIO region implementation
+/// Values that can be transferred by a single MMIO access
+///
+/// Implemented for `u8`, `u16`, `u32` and `u64`. The access width is the
+/// size of the type and the required alignment equals the width.
+pub trait MmioValue: Copy + private::Sealed {
+    /// Access width in bytes
+    const WIDTH: usize = core::mem::size_of::<Self>();
+}
+
+mod private {
+    pub trait Sealed {}
+    impl Sealed for u8 {}
+    impl Sealed for u16 {}
+    impl Sealed for u32 {}
+    impl Sealed for u64 {}
+}
+
+impl MmioValue for u8 {}
+impl MmioValue for u16 {}
+impl MmioValue for u32 {}
+impl MmioValue for u64 {}
+
+/// Bounds-checked MMIO region
+///
+/// `read`/`write` reject accesses that fall outside `[0, len)` or that are
+/// not naturally aligned for the access width, so a bad offset from an
+/// emulator or driver yields an error instead of faulting the hypervisor.
+/// The raw `MmioAccess` implementation remains available for callers that
+/// have already validated their offsets.
 pub struct MmioRegion {
     base_address: usize,
+    len: usize,
 }
 
 impl MmioRegion {
-    /// Create a new MMIO region
-    pub const fn new(base_address: usize) -> Self {
-        Self { base_address }
+    /// Create a new MMIO region of `len` bytes at `base_address`
+    pub const fn new(base_address: usize, len: usize) -> Self {
+        Self { base_address, len }
     }
 
     /// Get the base address
@@ -176,10 +205,42 @@ impl MmioRegion {
         self.base_address
     }
 
+    /// Get the region length in bytes
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
     /// Calculate the address of an offset
     const fn address(&self, offset: usize) -> usize {
         self.base_address + offset
     }
+
+    /// Validate an access of `width` bytes at `offset`
+    pub fn check_access(&self, offset: usize, width: usize) -> Result<(), crate::Error> {
+        let end = offset.checked_add(width).ok_or(crate::Error::InvalidArgument)?;
+        if end > self.len {
+            return Err(crate::Error::InvalidArgument);
+        }
+
+        if self.address(offset) % width != 0 {
+            return Err(crate::Error::InvalidArgument);
+        }
+
+        Ok(())
+    }
+
+    /// Read a value at `offset` after validating range and alignment
+    pub fn read<T: MmioValue>(&self, offset: usize) -> Result<T, crate::Error> {
+        self.check_access(offset, T::WIDTH)?;
+        Ok(unsafe { core::ptr::read_volatile(self.address(offset) as *const T) })
+    }
+
+    /// Write a value at `offset` after validating range and alignment
+    pub fn write<T: MmioValue>(&self, offset: usize, value: T) -> Result<(), crate::Error> {
+        self.check_access(offset, T::WIDTH)?;
+        unsafe { core::ptr::write_volatile(self.address(offset) as *mut T, value) };
+        Ok(())
+    }
 }
 
 impl MmioAccess for MmioRegion {
@@ -236,4 +297,43 @@ impl MmioAccess for MmioRegion {
 pub fn init() -> Result<(), crate::Error> {
     crate::info!("Initializing common architecture utilities");
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mmio_region_round_trip() {
+        let mut backing = [0u64; 2];
+        let region = MmioRegion::new(backing.as_mut_ptr() as usize, 16);
+
+        region.write::<u32>(4, 0xdead_beef).unwrap();
+        assert_eq!(region.read::<u32>(4).unwrap(), 0xdead_beef);
+        region.write::<u64>(8, 0x1234_5678_9abc_def0).unwrap();
+        assert_eq!(region.read::<u64>(8).unwrap(), 0x1234_5678_9abc_def0);
+        region.write::<u8>(15, 0xff).unwrap();
+    }
+
+    #[test]
+    fn test_mmio_region_rejects_out_of_range() {
+        let mut backing = [0u64; 2];
+        let region = MmioRegion::new(backing.as_mut_ptr() as usize, 16);
+
+        assert_eq!(region.read::<u32>(16), Err(crate::Error::InvalidArgument));
+        assert_eq!(region.read::<u64>(12), Err(crate::Error::InvalidArgument));
+        assert_eq!(region.write::<u8>(16, 0), Err(crate::Error::InvalidArgument));
+        assert_eq!(region.read::<u32>(usize::MAX - 1), Err(crate::Error::InvalidArgument));
+    }
+
+    #[test]
+    fn test_mmio_region_rejects_misaligned() {
+        let mut backing = [0u64; 2];
+        let region = MmioRegion::new(backing.as_mut_ptr() as usize, 16);
+
+        assert_eq!(region.read::<u16>(1), Err(crate::Error::InvalidArgument));
+        assert_eq!(region.read::<u32>(2), Err(crate::Error::InvalidArgument));
+        assert_eq!(region.write::<u64>(4, 0), Err(crate::Error::InvalidArgument));
+        assert!(region.read::<u8>(3).is_ok());
+    }
+}
@@ -2,6 +2,8 @@
 //!
 //! This module provides CPU-related utility functions used throughout the hypervisor.
 
+use crate::libs::cpumask::CpuMask;
+
 /// Get the current CPU ID
 pub fn get_current_cpu_id() -> Option<u32> {
     #[cfg(target_arch = "riscv64")]
@@ -61,6 +63,34 @@ pub fn get_cpu_count() -> Option<u32> {
     }
 }
 
+/// Get the set of online CPUs
+pub fn get_online_cpu_mask() -> CpuMask {
+    #[cfg(target_arch = "riscv64")]
+    {
+        crate::arch::riscv64::smp::get_online_cpu_mask()
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    {
+        use crate::arch::arm64::smp;
+        match smp::manager() {
+            Some(mgr) => (0..smp::MAX_CPUS as u32)
+                .filter(|&cpu| mgr.is_cpu_online(cpu))
+                .collect(),
+            // Only the boot CPU runs before SMP bring-up
+            None => CpuMask::from_cpu(0),
+        }
+    }
+
+    #[cfg(not(any(target_arch = "riscv64", target_arch = "aarch64")))]
+    {
+        // No SMP tracking, assume every CPU is online
+        let count = get_cpu_count().unwrap_or(1).min(64);
+        // A shift by 64 (no CPUs) would overflow
+        CpuMask::from_bits(u64::MAX.checked_shr(64 - count as u32).unwrap_or(0))
+    }
+}
+
 /// Check if we're in the context of a specific CPU
 pub fn is_cpu(cpu_id: u32) -> bool {
     get_current_cpu_id() == Some(cpu_id)
@@ -213,6 +213,19 @@ pub fn hfence_vvma_asid(asid: usize) {
     }
 }
 
+/// HLVX.HU instruction: read a guest instruction halfword
+///
+/// `vaddr` is translated through VS-stage and G-stage with execute
+/// permission, like an instruction fetch by the guest.
+#[inline]
+pub fn hlvx_hu(vaddr: usize) -> u16 {
+    let value: usize;
+    unsafe {
+        core::arch::asm!("hlvx.hu {}, ({})", out(reg) value, in(reg) vaddr);
+    }
+    value as u16
+}
+
 /// HFENCE.GVMA instruction with no parameters
 #[inline]
 pub fn hfence_gvma() {
@@ -100,6 +100,12 @@ impl Breakpoint {
         }
     }
 
+    /// Check if this watches data accesses rather than execution
+    pub fn is_watchpoint(&self) -> bool {
+        matches!(self.bp_type,
+                 BreakpointType::DataRead | BreakpointType::DataWrite | BreakpointType::DataReadWrite)
+    }
+
     /// Trigger the breakpoint
     pub fn trigger(&mut self) {
         self.status = BreakpointStatus::Triggered;
@@ -146,13 +152,13 @@ pub struct BreakpointManager {
 
 impl BreakpointManager {
     /// Create new breakpoint manager
-    pub fn new(max_breakpoints: u32, max_watchpoints: u32) -> Result<Self, &'static str> {
+    pub fn new(max_breakpoints: u32, max_watchpoints: u32) -> Result<Self, Error> {
         let debug_regs = DebugRegisters::new()?;
 
         // Get total number of triggers
         let total_triggers = debug_regs.get_trigger_count();
         if (max_breakpoints + max_watchpoints) > total_triggers {
-            return Err("Requested breakpoints exceed available triggers");
+            return Err(Error::OutOfResources("Requested breakpoints exceed available triggers"));
         }
 
         // Initialize free trigger list
@@ -172,7 +178,7 @@ impl BreakpointManager {
     }
 
     /// Set a breakpoint
-    pub fn set_breakpoint(&mut self, addr: usize, bp_type: BreakpointType) -> Result<u32, &'static str> {
+    pub fn set_breakpoint(&mut self, addr: usize, bp_type: BreakpointType) -> Result<u32, Error> {
         // Determine if this is a breakpoint or watchpoint
         let is_watchpoint = match bp_type {
             BreakpointType::Instruction => false,
@@ -182,17 +188,17 @@ impl BreakpointManager {
         // Check capacity
         if is_watchpoint {
             if self.watchpoints.len() >= self.max_watchpoints as usize {
-                return Err("Maximum watchpoints reached");
+                return Err(Error::OutOfResources("Maximum watchpoints reached"));
             }
         } else {
             if self.breakpoints.len() >= self.max_breakpoints as usize {
-                return Err("Maximum breakpoints reached");
+                return Err(Error::OutOfResources("Maximum breakpoints reached"));
             }
         }
 
         // Allocate trigger
         let trigger_index = self.free_triggers.pop()
-            .ok_or("No available triggers")?;
+            .ok_or(Error::OutOfResources("No available triggers"))?;
 
         // Create breakpoint
         let id = if is_watchpoint {
@@ -217,8 +223,17 @@ impl BreakpointManager {
         Ok(id)
     }
 
+    /// Take a trigger out of the free list so no breakpoint uses it
+    pub fn reserve_trigger(&mut self, trigger_index: u32) -> Result<(), Error> {
+        let index = self.free_triggers.iter()
+            .position(|&free| free == trigger_index)
+            .ok_or(Error::Busy("Trigger already in use"))?;
+        self.free_triggers.remove(index);
+        Ok(())
+    }
+
     /// Clear a breakpoint
-    pub fn clear_breakpoint(&mut self, id: u32) -> Result<(), &'static str> {
+    pub fn clear_breakpoint(&mut self, id: u32) -> Result<(), Error> {
         // Search breakpoints first
         for i in 0..self.breakpoints.len() {
             if self.breakpoints[i].id == id {
@@ -245,7 +260,7 @@ impl BreakpointManager {
             }
         }
 
-        Err("Breakpoint not found")
+        Err(Error::NotFound("Breakpoint not found"))
     }
 
     /// Get breakpoint by ID
@@ -274,82 +289,82 @@ impl BreakpointManager {
 
     /// Check if any breakpoint/watchpoint triggered
     pub fn check_triggers(&mut self) -> Vec<u32> {
-        let mut triggered = Vec::new();
-
-        // Check all breakpoints
-        for bp in &mut self.breakpoints {
-            if let Some(trigger_index) = bp.trigger_index {
-                self.debug_regs.select_trigger(trigger_index);
-                let tdata1 = self.debug_regs.read_tdata1();
+        self.take_hits().iter().map(|bp| bp.id).collect()
+    }
 
-                if tdata1.hit() {
-                    bp.trigger();
-                    triggered.push(bp.id);
+    /// Collect the breakpoints and watchpoints whose trigger fired
+    ///
+    /// Clears the hit bits and counts the hits; temporary breakpoints that
+    /// fired are removed and their triggers freed.
+    pub fn take_hits(&mut self) -> Vec<Breakpoint> {
+        self.pending_hits()
+            .iter()
+            .filter_map(|bp| self.record_hit(bp.id))
+            .collect()
+    }
 
-                    // Clear hit bit
-                    let mut tdata1_mut = tdata1;
-                    tdata1_mut.set_hit(false);
-                    self.debug_regs.write_tdata1(tdata1_mut);
+    /// Check whether the trigger of any breakpoint or watchpoint fired
+    pub fn has_pending_hits(&self) -> bool {
+        self.breakpoints.iter().chain(self.watchpoints.iter()).any(|bp| {
+            bp.trigger_index.is_some_and(|trigger_index| {
+                self.debug_regs.select_trigger(trigger_index);
+                self.debug_regs.read_tdata1().hit()
+            })
+        })
+    }
 
-                    // If temporary, clear it
-                    if bp.temporary {
-                        self.free_triggers.push(trigger_index);
-                    }
-                }
+    /// Collect the breakpoints and watchpoints whose trigger fired, without
+    /// counting them
+    ///
+    /// Clears the hit bits. A trigger may match more coarsely than the
+    /// breakpoint it implements; only hits confirmed with `record_hit`
+    /// count.
+    pub fn pending_hits(&mut self) -> Vec<Breakpoint> {
+        let mut hits = Vec::new();
+
+        for bp in self.breakpoints.iter().chain(self.watchpoints.iter()) {
+            let Some(trigger_index) = bp.trigger_index else {
+                continue;
+            };
+
+            self.debug_regs.select_trigger(trigger_index);
+            let mut tdata1 = self.debug_regs.read_tdata1();
+            if tdata1.hit() {
+                hits.push(bp.clone());
+
+                // Clear hit bit
+                tdata1.set_hit(false);
+                self.debug_regs.write_tdata1(tdata1);
             }
         }
 
-        // Check all watchpoints
-        for wp in &mut self.watchpoints {
-            if let Some(trigger_index) = wp.trigger_index {
-                self.debug_regs.select_trigger(trigger_index);
-                let tdata1 = self.debug_regs.read_tdata1();
-
-                if tdata1.hit() {
-                    wp.trigger();
-                    triggered.push(wp.id);
+        hits
+    }
 
-                    // Clear hit bit
-                    let mut tdata1_mut = tdata1;
-                    tdata1_mut.set_hit(false);
-                    self.debug_regs.write_tdata1(tdata1_mut);
+    /// Count a hit of breakpoint or watchpoint `id`
+    ///
+    /// A temporary one is removed and its trigger freed. Returns the
+    /// breakpoint as it was hit.
+    pub fn record_hit(&mut self, id: u32) -> Option<Breakpoint> {
+        let bp = self.breakpoints.iter_mut()
+            .chain(self.watchpoints.iter_mut())
+            .find(|bp| bp.id == id)?;
+        bp.trigger();
+        let hit = bp.clone();
 
-                    // If temporary, clear it
-                    if wp.temporary {
-                        self.free_triggers.push(trigger_index);
-                    }
-                }
+        if hit.temporary {
+            if let Some(trigger_index) = hit.trigger_index {
+                self.clear_trigger(trigger_index);
+                self.free_triggers.push(trigger_index);
             }
+            self.breakpoints.retain(|bp| bp.id != id);
+            self.watchpoints.retain(|wp| wp.id != id);
         }
-
-        // Remove temporary breakpoints that triggered
-        self.breakpoints.retain(|bp| {
-            if bp.temporary && bp.status == BreakpointStatus::Triggered {
-                if let Some(trigger_index) = bp.trigger_index {
-                    self.clear_trigger(trigger_index);
-                }
-                false
-            } else {
-                true
-            }
-        });
-
-        self.watchpoints.retain(|wp| {
-            if wp.temporary && wp.status == BreakpointStatus::Triggered {
-                if let Some(trigger_index) = wp.trigger_index {
-                    self.clear_trigger(trigger_index);
-                }
-                false
-            } else {
-                true
-            }
-        });
-
-        triggered
+        Some(hit)
     }
 
     /// Configure hardware trigger
-    fn configure_trigger(&mut self, trigger_index: u32, bp: &Breakpoint) -> Result<(), &'static str> {
+    fn configure_trigger(&mut self, trigger_index: u32, bp: &Breakpoint) -> Result<(), Error> {
         self.debug_regs.select_trigger(trigger_index);
 
         // Configure TDATA1
@@ -268,7 +268,7 @@ pub struct JtagDebugInterface {
 
 impl JtagDebugInterface {
     /// Create new JTAG debug interface
-    pub fn new(dm_base: u64) -> Result<Self, &'static str> {
+    pub fn new(dm_base: u64) -> Result<Self, Error> {
         let mut jtag = Self {
             tap: TapController::new(),
             dm_base,
@@ -281,7 +281,7 @@ impl JtagDebugInterface {
     }
 
     /// Initialize JTAG interface
-    fn initialize(&mut self) -> Result<(), &'static str> {
+    fn initialize(&mut self) -> Result<(), Error> {
         log::debug!("Initializing JTAG debug interface");
 
         // Reset TAP controller
@@ -307,7 +307,7 @@ impl JtagDebugInterface {
 
         // Check if debug module is present
         if dmstatus == 0xFFFFFFFF {
-            return Err("Debug module not accessible");
+            return Err(Error::Failed("Debug module not accessible"));
         }
 
         // Read abstract command count
@@ -325,7 +325,7 @@ impl JtagDebugInterface {
     }
 
     /// Read DMI register
-    fn read_dmi(&mut self, addr: u32) -> Result<u32, &'static str> {
+    fn read_dmi(&mut self, addr: u32) -> Result<u32, Error> {
         let dmi_value = (addr << 2) | (1 << 0); // Read operation
         let _result = self.tap.shift_dr(dmi_value as u64, 41);
 
@@ -336,7 +336,7 @@ impl JtagDebugInterface {
     }
 
     /// Write DMI register
-    fn write_dmi(&mut self, addr: u32, data: u32) -> Result<(), &'static str> {
+    fn write_dmi(&mut self, addr: u32, data: u32) -> Result<(), Error> {
         let dmi_value = ((addr << 2) | (data << 2)) | (0 << 0); // Write operation
         let _result = self.tap.shift_dr(dmi_value as u64, 41);
 
@@ -346,7 +346,7 @@ impl JtagDebugInterface {
     }
 
     /// Halt the target
-    pub fn halt(&mut self) -> Result<(), &'static str> {
+    pub fn halt(&mut self) -> Result<(), Error> {
         log::debug!("Halting target via JTAG");
 
         // Set halt request in DMCONTROL
@@ -371,7 +371,7 @@ impl JtagDebugInterface {
     }
 
     /// Resume the target
-    pub fn resume(&mut self) -> Result<(), &'static str> {
+    pub fn resume(&mut self) -> Result<(), Error> {
         log::debug!("Resuming target via JTAG");
 
         // Set resume request in DMCONTROL
@@ -396,9 +396,9 @@ impl JtagDebugInterface {
     }
 
     /// Read GPR register
-    pub fn read_gpr(&mut self, reg_num: u32) -> Result<u64, &'static str> {
+    pub fn read_gpr(&mut self, reg_num: u32) -> Result<u64, Error> {
         if reg_num >= 32 {
-            return Err("Invalid register number");
+            return Err(Error::InvalidArgument("Invalid register number"));
         }
 
         // Use abstract command to read register
@@ -419,9 +419,9 @@ impl JtagDebugInterface {
     }
 
     /// Write GPR register
-    pub fn write_gpr(&mut self, reg_num: u32, value: u64) -> Result<(), &'static str> {
+    pub fn write_gpr(&mut self, reg_num: u32, value: u64) -> Result<(), Error> {
         if reg_num >= 32 {
-            return Err("Invalid register number");
+            return Err(Error::InvalidArgument("Invalid register number"));
         }
 
         // Write value to DATA0
@@ -444,7 +444,7 @@ impl JtagDebugInterface {
     }
 
     /// Build abstract command for reading register
-    fn build_abstract_read_reg(&self, reg_num: u32) -> Result<u32, &'static str> {
+    fn build_abstract_read_reg(&self, reg_num: u32) -> Result<u32, Error> {
         let mut cmd = ABSTRACT_ACCESS_REGISTER << ABSTRACT_CMD_TYPE_SHIFT;
         cmd |= ABSTRACT_REG_READ;
         cmd |= 2 << ABSTRACT_REG_SIZE_SHIFT; // 32-bit access
@@ -454,7 +454,7 @@ impl JtagDebugInterface {
     }
 
     /// Build abstract command for writing register
-    fn build_abstract_write_reg(&self, reg_num: u32) -> Result<u32, &'static str> {
+    fn build_abstract_write_reg(&self, reg_num: u32) -> Result<u32, Error> {
         let mut cmd = ABSTRACT_ACCESS_REGISTER << ABSTRACT_CMD_TYPE_SHIFT;
         cmd |= ABSTRACT_REG_WRITE;
         cmd |= 2 << ABSTRACT_REG_SIZE_SHIFT; // 32-bit access
@@ -464,9 +464,9 @@ impl JtagDebugInterface {
     }
 
     /// Execute program buffer
-    pub fn execute_program_buffer(&mut self, program: &[u32]) -> Result<(), &'static str> {
+    pub fn execute_program_buffer(&mut self, program: &[u32]) -> Result<(), Error> {
         if program.len() > self.progbuf_size as usize {
-            return Err("Program too large for program buffer");
+            return Err(Error::InvalidArgument("Program too large for program buffer"));
         }
 
         // Write program to program buffer
@@ -491,7 +491,7 @@ impl JtagDebugInterface {
     }
 
     /// Read memory word
-    pub fn read_memory(&mut self, addr: u64) -> Result<u32, &'static str> {
+    pub fn read_memory(&mut self, addr: u64) -> Result<u32, Error> {
         // Use program buffer to read memory
         let program = [
             0x00002383, // ld t2, 0(tp)
@@ -510,7 +510,7 @@ impl JtagDebugInterface {
     }
 
     /// Write memory word
-    pub fn write_memory(&mut self, addr: u64, data: u32) -> Result<(), &'static str> {
+    pub fn write_memory(&mut self, addr: u64, data: u32) -> Result<(), Error> {
         // Use program buffer to write memory
         let program = [
             0x00002303, // lw t1, 0(tp)
@@ -553,7 +553,7 @@ pub struct JtagStatus {
 }
 
 /// Initialize JTAG debug interface
-pub fn init() -> Result<(), &'static str> {
+pub fn init() -> Result<(), Error> {
     log::info!("Initializing RISC-V JTAG debug interface");
 
     // JTAG initialization is done on-demand
@@ -562,7 +562,7 @@ pub fn init() -> Result<(), &'static str> {
 }
 
 /// Create JTAG debug interface
-pub fn create_interface(dm_base: u64) -> Result<JtagDebugInterface, &'static str> {
+pub fn create_interface(dm_base: u64) -> Result<JtagDebugInterface, Error> {
     JtagDebugInterface::new(dm_base)
 }
 
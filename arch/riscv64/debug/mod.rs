@@ -15,9 +15,13 @@ pub mod jtag;
 pub mod vm_debug;
 
 use crate::arch::riscv64::*;
-use regs::DebugRegisters;
-use breakpoint::{BreakpointManager, BreakpointType};
+use regs::{DebugRegisters, DebugHaltCause};
+use breakpoint::{Breakpoint, BreakpointManager, BreakpointType};
 use tracer::{Tracer, TraceEvent};
+use crate::arch::riscv64::interrupt::TrapContext;
+use crate::core::sync::{Once, SpinLock};
+use crate::core::sync::spinlock::SpinLockGuard;
+use core::sync::atomic::{AtomicU64, Ordering};
 
 /// Debug configuration
 #[derive(Debug, Clone)]
@@ -55,11 +59,13 @@ impl Default for DebugConfig {
 /// Global debug state
 static mut DEBUG_CONFIG: Option<DebugConfig> = None;
 static mut DEBUG_REGISTERS: Option<DebugRegisters> = None;
-static mut BREAKPOINT_MANAGER: Option<BreakpointManager> = None;
+static BREAKPOINT_MANAGER: Once<SpinLock<BreakpointManager>> = Once::new();
 static mut TRACER: Option<Tracer> = None;
+static DEBUG_EVENT_HANDLER: SpinLock<Option<DebugEventHandler>> = SpinLock::new(None);
+static DEBUG_COUNTERS: DebugCounters = DebugCounters::new();
 
 /// Initialize debug subsystem
-pub fn init() -> Result<(), &'static str> {
+pub fn init() -> Result<(), Error> {
     log::info!("Initializing RISC-V debug subsystem");
 
     // Initialize with default config
@@ -71,7 +77,7 @@ pub fn init() -> Result<(), &'static str> {
 }
 
 /// Initialize debug subsystem with configuration
-pub fn init_with_config(config: DebugConfig) -> Result<(), &'static str> {
+pub fn init_with_config(config: DebugConfig) -> Result<(), Error> {
     if !config.enabled {
         log::info!("Debug support is disabled");
         return Ok(());
@@ -87,18 +93,23 @@ pub fn init_with_config(config: DebugConfig) -> Result<(), &'static str> {
 
     // Initialize debug registers
     let debug_regs = DebugRegisters::new()?;
+    let trigger_count = debug_regs.get_trigger_count();
     unsafe {
         DEBUG_REGISTERS = Some(debug_regs);
     }
 
     // Initialize breakpoint manager
-    let bp_manager = BreakpointManager::new(
+    let mut bp_manager = BreakpointManager::new(
         config.hw_breakpoints,
         config.hw_watchpoints,
     )?;
-    unsafe {
-        BREAKPOINT_MANAGER = Some(bp_manager);
+
+    // Keep host breakpoints off the triggers guest breakpoints use
+    if config.enable_vm_debug {
+        vm_debug::reserve_guest_triggers(&mut bp_manager, trigger_count)?;
     }
+    BREAKPOINT_MANAGER.set(SpinLock::new(bp_manager))
+        .map_err(|_| Error::Busy("Breakpoint manager already initialized"))?;
 
     // Initialize tracer if enabled
     if config.enable_trace {
@@ -106,8 +117,13 @@ pub fn init_with_config(config: DebugConfig) -> Result<(), &'static str> {
         unsafe {
             TRACER = Some(tracer);
         }
+        set_debug_event_handler(trace_debug_event);
     }
 
+    // Breakpoint exceptions are how triggers, steps and ebreaks reach us
+    crate::arch::riscv64::interrupt::register_trap_handler(
+        Some(ExceptionCode::Breakpoint), None, breakpoint_trap);
+
     // Initialize JTAG interface if enabled
     if config.enable_jtag {
         jtag::init()?;
@@ -131,8 +147,10 @@ pub fn get_debug_registers() -> Option<&'static DebugRegisters> {
 }
 
 /// Get breakpoint manager
-pub fn get_breakpoint_manager() -> Option<&'static BreakpointManager> {
-    unsafe { BREAKPOINT_MANAGER.as_ref() }
+///
+/// The manager stays locked while the guard is held.
+pub fn get_breakpoint_manager() -> Option<SpinLockGuard<'static, BreakpointManager>> {
+    BREAKPOINT_MANAGER.get().map(SpinLock::lock)
 }
 
 /// Get tracer
@@ -141,7 +159,7 @@ pub fn get_tracer() -> Option<&'static Tracer> {
 }
 
 /// Enable debug mode
-pub fn enable_debug_mode() -> Result<(), &'static str> {
+pub fn enable_debug_mode() -> Result<(), Error> {
     log::debug!("Enabling RISC-V debug mode");
 
     // Enable debug mode in DCSR
@@ -156,7 +174,7 @@ pub fn enable_debug_mode() -> Result<(), &'static str> {
 }
 
 /// Disable debug mode
-pub fn disable_debug_mode() -> Result<(), &'static str> {
+pub fn disable_debug_mode() -> Result<(), Error> {
     log::debug!("Disabling RISC-V debug mode");
 
     // Disable debug mode in DCSR
@@ -181,7 +199,7 @@ pub fn is_debug_mode_enabled() -> bool {
 }
 
 /// Enter debug mode (halt the CPU)
-pub fn enter_debug_mode() -> Result<(), &'static str> {
+pub fn enter_debug_mode() -> Result<(), Error> {
     log::debug!("Entering debug mode (halting CPU)");
 
     // Trigger debug halt
@@ -204,7 +222,7 @@ pub fn enter_debug_mode() -> Result<(), &'static str> {
 }
 
 /// Exit debug mode (resume the CPU)
-pub fn exit_debug_mode() -> Result<(), &'static str> {
+pub fn exit_debug_mode() -> Result<(), Error> {
     log::debug!("Exiting debug mode (resuming CPU)");
 
     // Clear halt request
@@ -219,33 +237,33 @@ pub fn exit_debug_mode() -> Result<(), &'static str> {
 }
 
 /// Set hardware breakpoint
-pub fn set_breakpoint(addr: usize, bp_type: BreakpointType) -> Result<u32, &'static str> {
+pub fn set_breakpoint(addr: usize, bp_type: BreakpointType) -> Result<u32, Error> {
     log::debug!("Setting breakpoint at address {:#x}", addr);
 
-    if let Some(bp_manager) = get_breakpoint_manager() {
+    if let Some(mut bp_manager) = get_breakpoint_manager() {
         let bp_id = bp_manager.set_breakpoint(addr, bp_type)?;
         log::debug!("Breakpoint {} set at address {:#x}", bp_id, addr);
         Ok(bp_id)
     } else {
-        Err("Breakpoint manager not initialized")
+        Err(Error::NotInitialized("Breakpoint manager not initialized"))
     }
 }
 
 /// Clear hardware breakpoint
-pub fn clear_breakpoint(bp_id: u32) -> Result<(), &'static str> {
+pub fn clear_breakpoint(bp_id: u32) -> Result<(), Error> {
     log::debug!("Clearing breakpoint {}", bp_id);
 
-    if let Some(bp_manager) = get_breakpoint_manager() {
+    if let Some(mut bp_manager) = get_breakpoint_manager() {
         bp_manager.clear_breakpoint(bp_id)?;
         log::debug!("Breakpoint {} cleared", bp_id);
         Ok(())
     } else {
-        Err("Breakpoint manager not initialized")
+        Err(Error::NotInitialized("Breakpoint manager not initialized"))
     }
 }
 
 /// Enable single stepping
-pub fn enable_single_step() -> Result<(), &'static str> {
+pub fn enable_single_step() -> Result<(), Error> {
     log::debug!("Enabling single stepping");
 
     if let Some(debug_regs) = get_debug_registers() {
@@ -259,7 +277,7 @@ pub fn enable_single_step() -> Result<(), &'static str> {
 }
 
 /// Disable single stepping
-pub fn disable_single_step() -> Result<(), &'static str> {
+pub fn disable_single_step() -> Result<(), Error> {
     log::debug!("Disabling single stepping");
 
     if let Some(debug_regs) = get_debug_registers() {
@@ -283,7 +301,7 @@ pub fn is_single_stepping() -> bool {
 }
 
 /// Step one instruction
-pub fn step_instruction() -> Result<(), &'static str> {
+pub fn step_instruction() -> Result<(), Error> {
     // Enable single stepping
     enable_single_step()?;
 
@@ -296,8 +314,159 @@ pub fn step_instruction() -> Result<(), &'static str> {
     Ok(())
 }
 
+/// What a debug exception was taken for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugEvent {
+    /// Instruction breakpoint `id` hit at `pc`
+    Breakpoint { id: u32, pc: u64 },
+    /// Watchpoint `id` hit by an access to `addr`
+    Watchpoint { id: u32, addr: u64 },
+    /// Single step completed, next instruction at `pc`
+    Step { pc: u64 },
+    /// `ebreak` instruction at `pc`
+    Ebreak { pc: u64 },
+    /// Halt request or other halt not caused by the code being debugged
+    Halt(DebugHaltCause),
+    /// Trigger fired without a matching breakpoint or watchpoint
+    Spurious,
+}
+
+/// Receives debug events for the debugger front end (e.g. a gdbstub)
+///
+/// Runs in trap context. An execution breakpoint fires again when the
+/// hart resumes, so the handler must clear it or step past it.
+pub type DebugEventHandler = fn(&DebugEvent);
+
+/// Set the handler debug events are delivered to
+///
+/// Replaces the tracer, which is installed by default when tracing is
+/// enabled.
+pub fn set_debug_event_handler(handler: DebugEventHandler) {
+    *DEBUG_EVENT_HANDLER.lock() = Some(handler);
+}
+
+/// Breakpoint exception handler
+///
+/// Without a debugger front end the exception stays unhandled, as it was
+/// before debugging was set up.
+extern "C" fn breakpoint_trap(context: &mut TrapContext) -> core::result::Result<(), &'static str> {
+    if DEBUG_EVENT_HANDLER.lock().is_none() {
+        return Err("No debugger attached");
+    }
+
+    let cause = if is_single_stepping() {
+        DebugHaltCause::STEP
+    } else if get_breakpoint_manager().is_some_and(|bp_manager| bp_manager.has_pending_hits()) {
+        DebugHaltCause::TRIGGER
+    } else {
+        DebugHaltCause::EBREAK
+    };
+    let tval = match cause {
+        DebugHaltCause::TRIGGER => context.tval,
+        _ => context.pc,
+    };
+    let event = handle_debug_exception(cause, tval as u64);
+
+    // Resume after an ebreak; c.ebreak is two bytes
+    if let DebugEvent::Ebreak { pc } = event {
+        let insn = unsafe { core::ptr::read_volatile(pc as *const u16) };
+        context.pc += if insn & 0b11 == 0b11 { 4 } else { 2 };
+    }
+    Ok(())
+}
+
+/// Default debug event handler: record the event in the trace
+///
+/// Execution breakpoints are cleared once traced, as nothing would step
+/// the hart past them.
+fn trace_debug_event(event: &DebugEvent) {
+    let pc = match *event {
+        DebugEvent::Breakpoint { pc, .. } | DebugEvent::Step { pc } | DebugEvent::Ebreak { pc } => pc,
+        _ => 0,
+    };
+    if let Some(tracer) = unsafe { TRACER.as_mut() } {
+        let mut trace = TraceEvent::exception(pc, ExceptionCode::Breakpoint as u32);
+        trace.info = Some(alloc::format!("{:?}", event));
+        tracer.trace_event(trace);
+    }
+
+    if let (DebugEvent::Breakpoint { id, .. }, Some(mut bp_manager)) = (event, get_breakpoint_manager()) {
+        if let Err(e) = bp_manager.clear_breakpoint(*id) {
+            log::warn!("Failed to clear traced breakpoint {}: {:?}", id, e);
+        }
+    }
+}
+
+/// Handle a debug exception
+///
+/// `cause` is the halt cause from DCSR and `tval` the faulting PC, or the
+/// accessed address for data triggers. Trigger hits are read from the
+/// breakpoint manager; only the one reported counts as hit, so a trigger
+/// matching more coarsely than its watchpoint leaves the watchpoint (even
+/// a temporary one) armed. Breakpoint, watchpoint, step and ebreak events
+/// are counted and delivered to the debug event handler; the caller
+/// resumes the hart on the others. The breakpoint manager is unlocked
+/// before the handler runs, so the handler may clear breakpoints.
+pub fn handle_debug_exception(cause: DebugHaltCause, tval: u64) -> DebugEvent {
+    let hits = match cause {
+        DebugHaltCause::TRIGGER => get_breakpoint_manager()
+            .map(|mut bp_manager| bp_manager.pending_hits())
+            .unwrap_or_default(),
+        _ => Vec::new(),
+    };
+
+    let event = dispatch_debug_exception(cause, tval, &hits, &DEBUG_COUNTERS);
+    match event {
+        DebugEvent::Halt(_) | DebugEvent::Spurious => {
+            log::debug!("Debug exception {:?} at {:#x}: {:?}", cause, tval, event);
+        }
+        _ => {
+            if let (DebugEvent::Breakpoint { id, .. } | DebugEvent::Watchpoint { id, .. }, Some(mut bp_manager)) =
+                (event, get_breakpoint_manager())
+            {
+                bp_manager.record_hit(id);
+            }
+            let handler = *DEBUG_EVENT_HANDLER.lock();
+            if let Some(handler) = handler {
+                handler(&event);
+            }
+        }
+    }
+    event
+}
+
+/// Decide what a debug exception was taken for and count it
+///
+/// Execution triggers take priority over data triggers. A watchpoint only
+/// counts if the access at `tval` falls inside it; hardware may match more
+/// coarsely.
+fn dispatch_debug_exception(
+    cause: DebugHaltCause,
+    tval: u64,
+    hits: &[Breakpoint],
+    counters: &DebugCounters,
+) -> DebugEvent {
+    let event = match cause {
+        DebugHaltCause::TRIGGER => {
+            let breakpoint = hits.iter().find(|bp| !bp.is_watchpoint());
+            let watchpoint = hits.iter().find(|wp| wp.is_watchpoint() && wp.matches(tval));
+            match (breakpoint, watchpoint) {
+                (Some(bp), _) => DebugEvent::Breakpoint { id: bp.id, pc: tval },
+                (None, Some(wp)) => DebugEvent::Watchpoint { id: wp.id, addr: tval },
+                (None, None) => DebugEvent::Spurious,
+            }
+        }
+        DebugHaltCause::STEP => DebugEvent::Step { pc: tval },
+        DebugHaltCause::EBREAK => DebugEvent::Ebreak { pc: tval },
+        other => DebugEvent::Halt(other),
+    };
+
+    counters.record(&event);
+    event
+}
+
 /// Start tracing
-pub fn start_trace() -> Result<(), &'static str> {
+pub fn start_trace() -> Result<(), Error> {
     log::debug!("Starting program trace");
 
     if let Some(tracer) = get_tracer() {
@@ -305,7 +474,7 @@ pub fn start_trace() -> Result<(), &'static str> {
         log::debug!("Program trace started");
         Ok(())
     } else {
-        Err("Tracer not initialized")
+        Err(Error::NotInitialized("Tracer not initialized"))
     }
 }
 
@@ -318,7 +487,7 @@ pub fn stop_trace() -> Result<Vec<TraceEvent>> {
         log::debug!("Program trace stopped, collected {} events", events.len());
         Ok(events)
     } else {
-        Err("Tracer not initialized")
+        Err(Error::NotInitialized("Tracer not initialized"))
     }
 }
 
@@ -327,21 +496,21 @@ pub fn get_trace_events() -> Result<Vec<TraceEvent>> {
     if let Some(tracer) = get_tracer() {
         Ok(tracer.get_events()?)
     } else {
-        Err("Tracer not initialized")
+        Err(Error::NotInitialized("Tracer not initialized"))
     }
 }
 
 /// Read register value
-pub fn read_register(reg_id: u32) -> Result<u64, &'static str> {
+pub fn read_register(reg_id: u32) -> Result<u64, Error> {
     if let Some(debug_regs) = get_debug_registers() {
         debug_regs.read_register(reg_id)
     } else {
-        Err("Debug registers not initialized")
+        Err(Error::NotInitialized("Debug registers not initialized"))
     }
 }
 
 /// Write register value
-pub fn write_register(reg_id: u32, value: u64) -> Result<(), &'static str> {
+pub fn write_register(reg_id: u32, value: u64) -> Result<(), Error> {
     log::debug!("Writing value {:#x} to register {}", value, reg_id);
 
     if let Some(debug_regs) = get_debug_registers() {
@@ -349,17 +518,17 @@ pub fn write_register(reg_id: u32, value: u64) -> Result<(), &'static str> {
         log::debug!("Register {} written successfully", reg_id);
         Ok(())
     } else {
-        Err("Debug registers not initialized")
+        Err(Error::NotInitialized("Debug registers not initialized"))
     }
 }
 
 /// Read memory
-pub fn read_memory(addr: usize, size: usize) -> Result<Vec<u8>, &'static str> {
+pub fn read_memory(addr: usize, size: usize) -> Result<Vec<u8>, Error> {
     log::debug!("Reading {} bytes from address {:#x}", size, addr);
 
     // Validate address
     if !crate::arch::riscv64::mmu::is_valid_address(addr) {
-        return Err("Invalid memory address");
+        return Err(Error::InvalidArgument("Invalid memory address"));
     }
 
     let mut data = Vec::with_capacity(size);
@@ -375,12 +544,12 @@ pub fn read_memory(addr: usize, size: usize) -> Result<Vec<u8>, &'static str> {
 }
 
 /// Write memory
-pub fn write_memory(addr: usize, data: &[u8]) -> Result<(), &'static str> {
+pub fn write_memory(addr: usize, data: &[u8]) -> Result<(), Error> {
     log::debug!("Writing {} bytes to address {:#x}", data.len(), addr);
 
     // Validate address
     if !crate::arch::riscv64::mmu::is_valid_address(addr) {
-        return Err("Invalid memory address");
+        return Err(Error::InvalidArgument("Invalid memory address"));
     }
 
     unsafe {
@@ -395,7 +564,7 @@ pub fn write_memory(addr: usize, data: &[u8]) -> Result<(), &'static str> {
 }
 
 /// Generate core dump
-pub fn generate_core_dump() -> Result<CoreDump, &'static str> {
+pub fn generate_core_dump() -> Result<CoreDump, Error> {
     log::info!("Generating core dump");
 
     let mut core_dump = CoreDump::new();
@@ -488,15 +657,15 @@ impl CoreDump {
     }
 
     /// Save core dump to file
-    pub fn save_to_file(&self, _path: &str) -> Result<(), &'static str> {
+    pub fn save_to_file(&self, _path: &str) -> Result<(), Error> {
         // TODO: Implement core dump file saving
         Ok(())
     }
 
     /// Load core dump from file
-    pub fn load_from_file(_path: &str) -> Result<Self, &'static str> {
+    pub fn load_from_file(_path: &str) -> Result<Self, Error> {
         // TODO: Implement core dump file loading
-        Err("Core dump loading not yet implemented")
+        Err(Error::Unsupported("Core dump loading not yet implemented"))
     }
 }
 
@@ -515,10 +684,46 @@ pub struct DebugStats {
     pub debug_time_us: u64,
 }
 
+/// Debug event counters
+struct DebugCounters {
+    breakpoints_hit: AtomicU64,
+    watchpoints_hit: AtomicU64,
+    single_steps: AtomicU64,
+}
+
+impl DebugCounters {
+    const fn new() -> Self {
+        Self {
+            breakpoints_hit: AtomicU64::new(0),
+            watchpoints_hit: AtomicU64::new(0),
+            single_steps: AtomicU64::new(0),
+        }
+    }
+
+    /// Count a debug event
+    fn record(&self, event: &DebugEvent) {
+        let counter = match event {
+            DebugEvent::Breakpoint { .. } => &self.breakpoints_hit,
+            DebugEvent::Watchpoint { .. } => &self.watchpoints_hit,
+            DebugEvent::Step { .. } => &self.single_steps,
+            _ => return,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Fill in the event counts of `stats`
+    fn fill(&self, stats: &mut DebugStats) {
+        stats.breakpoints_hit = self.breakpoints_hit.load(Ordering::Relaxed);
+        stats.watchpoints_hit = self.watchpoints_hit.load(Ordering::Relaxed);
+        stats.single_steps = self.single_steps.load(Ordering::Relaxed);
+    }
+}
+
 /// Get debug statistics
 pub fn get_debug_stats() -> DebugStats {
-    // TODO: Collect actual statistics
-    DebugStats::default()
+    let mut stats = DebugStats::default();
+    DEBUG_COUNTERS.fill(&mut stats);
+    stats
 }
 
 #[cfg(test)]
@@ -550,6 +755,36 @@ mod tests {
         assert_eq!(region.name, "test");
     }
 
+    #[test]
+    fn test_debug_exception_dispatch() {
+        let counters = DebugCounters::new();
+        let mut bp = Breakpoint::new(3, BreakpointType::Instruction, 0x8020_0000);
+        let mut wp = Breakpoint::new(1, BreakpointType::DataWrite, 0x8030_0000);
+        bp.trigger();
+        wp.trigger();
+
+        // Execution trigger wins over a data trigger firing alongside it
+        let event = dispatch_debug_exception(DebugHaltCause::TRIGGER, 0x8020_0000, &[wp.clone(), bp], &counters);
+        assert_eq!(event, DebugEvent::Breakpoint { id: 3, pc: 0x8020_0000 });
+
+        let event = dispatch_debug_exception(DebugHaltCause::TRIGGER, 0x8030_0000, &[wp.clone()], &counters);
+        assert_eq!(event, DebugEvent::Watchpoint { id: 1, addr: 0x8030_0000 });
+
+        // Access outside the watched address fails the watchpoint condition
+        let event = dispatch_debug_exception(DebugHaltCause::TRIGGER, 0x8030_0008, &[wp], &counters);
+        assert_eq!(event, DebugEvent::Spurious);
+        assert_eq!(dispatch_debug_exception(DebugHaltCause::TRIGGER, 0, &[], &counters), DebugEvent::Spurious);
+
+        let event = dispatch_debug_exception(DebugHaltCause::STEP, 0x8020_0004, &[], &counters);
+        assert_eq!(event, DebugEvent::Step { pc: 0x8020_0004 });
+        let event = dispatch_debug_exception(DebugHaltCause::HALTREQ, 0x8020_0004, &[], &counters);
+        assert_eq!(event, DebugEvent::Halt(DebugHaltCause::HALTREQ));
+
+        let mut stats = DebugStats::default();
+        counters.fill(&mut stats);
+        assert_eq!((stats.breakpoints_hit, stats.watchpoints_hit, stats.single_steps), (1, 1, 1));
+    }
+
     #[test]
     fn test_core_dump() {
         let dump = CoreDump::new();
@@ -434,10 +434,10 @@ pub struct DebugRegisters {
 
 impl DebugRegisters {
     /// Create new debug registers interface
-    pub fn new() -> Result<Self, &'static str> {
+    pub fn new() -> Result<Self, Error> {
         // Check if debug module is present
         if !has_debug_module() {
-            return Err("Debug module not present");
+            return Err(Error::NotFound("Debug module not present"));
         }
 
         Ok(Self { _private: () })
@@ -554,7 +554,7 @@ impl DebugRegisters {
     }
 
     /// Read register by ID
-    pub fn read_register(&self, reg_id: u32) -> Result<u64, &'static str> {
+    pub fn read_register(&self, reg_id: u32) -> Result<u64, Error> {
         match reg_id {
             0x0000..=0x001F => {
                 // General purpose registers (x0-x31)
@@ -569,12 +569,12 @@ impl DebugRegisters {
                 // PC
                 Ok(self.read_dpc().bits())
             }
-            _ => Err("Unsupported register ID"),
+            _ => Err(Error::Unsupported("Unsupported register ID")),
         }
     }
 
     /// Write register by ID
-    pub fn write_register(&self, reg_id: u32, value: u64) -> Result<(), &'static str> {
+    pub fn write_register(&self, reg_id: u32, value: u64) -> Result<(), Error> {
         match reg_id {
             0x0001..=0x001F => {
                 // General purpose registers (x1-x31, skip x0)
@@ -585,12 +585,12 @@ impl DebugRegisters {
                 self.write_dpc(Dpc::from_bits(value));
                 Ok(())
             }
-            _ => Err("Unsupported register ID"),
+            _ => Err(Error::Unsupported("Unsupported register ID")),
         }
     }
 
     /// Capture current CPU state
-    pub fn capture_cpu_state(&self) -> Result<CpuState, &'static str> {
+    pub fn capture_cpu_state(&self) -> Result<CpuState, Error> {
         let mut state = CpuState::new();
 
         // Read PC
@@ -609,17 +609,17 @@ impl DebugRegisters {
     }
 
     /// Read general purpose register via debug interface
-    fn read_gpr(&self, reg: u32) -> Result<u64, &'static str> {
+    fn read_gpr(&self, reg: u32) -> Result<u64, Error> {
         // In a real implementation, this would use the Abstract Command interface
         // For now, return a placeholder
-        Err("GPR access via debug interface not implemented")
+        Err(Error::Unsupported("GPR access via debug interface not implemented"))
     }
 
     /// Write general purpose register via debug interface
-    fn write_gpr(&self, _reg: u32, _value: u64) -> Result<(), &'static str> {
+    fn write_gpr(&self, _reg: u32, _value: u64) -> Result<(), Error> {
         // In a real implementation, this would use the Abstract Command interface
         // For now, return an error
-        Err("GPR access via debug interface not implemented")
+        Err(Error::Unsupported("GPR access via debug interface not implemented"))
     }
 }
 
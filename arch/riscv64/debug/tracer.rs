@@ -333,9 +333,9 @@ pub struct TraceStats {
 
 impl Tracer {
     /// Create new tracer
-    pub fn new(buffer_size: usize) -> Result<Self, &'static str> {
+    pub fn new(buffer_size: usize) -> Result<Self, Error> {
         if buffer_size == 0 {
-            return Err("Buffer size cannot be zero");
+            return Err(Error::InvalidArgument("Buffer size cannot be zero"));
         }
 
         Ok(Self {
@@ -357,9 +357,9 @@ impl Tracer {
     }
 
     /// Start tracing
-    pub fn start(&mut self) -> Result<(), &'static str> {
+    pub fn start(&mut self) -> Result<(), Error> {
         if self.active {
-            return Err("Tracer already active");
+            return Err(Error::Busy("Tracer already active"));
         }
 
         // Clear buffer and stats
@@ -372,9 +372,9 @@ impl Tracer {
     }
 
     /// Stop tracing
-    pub fn stop(&mut self) -> Result<Vec<TraceEvent>, &'static str> {
+    pub fn stop(&mut self) -> Result<Vec<TraceEvent>, Error> {
         if !self.active {
-            return Err("Tracer not active");
+            return Err(Error::InvalidState("Tracer not active"));
         }
 
         self.active = false;
@@ -494,9 +494,9 @@ impl Tracer {
     }
 
     /// Get events from buffer
-    pub fn get_events(&self) -> Result<Vec<TraceEvent>, &'static str> {
+    pub fn get_events(&self) -> Result<Vec<TraceEvent>, Error> {
         if self.active {
-            return Err("Cannot get events while tracer is active");
+            return Err(Error::InvalidState("Cannot get events while tracer is active"));
         }
         Ok(self.buffer.peek_all())
     }
@@ -6,10 +6,17 @@
 //! - VM register state access
 //! - VM single stepping
 //! - VM trace collection
+//! - Hardware breakpoints scoped to one guest
 
 use crate::arch::riscv64::*;
 use crate::arch::riscv64::debug::*;
+use crate::arch::riscv64::debug::breakpoint::BreakpointManager;
+use crate::arch::riscv64::debug::regs::{DebugRegisters, Tdata1, Tdata2};
+use crate::arch::riscv64::mmu::{RootPageTable, translate_single_stage};
 use crate::arch::riscv64::virtualization::vm::{Vm, VmId};
+use crate::core::sync::SpinLock;
+use crate::libs::cpumask::MAX_CPUS;
+use core::sync::atomic::{AtomicU64, Ordering};
 
 /// VM debug configuration
 #[derive(Debug, Clone)]
@@ -96,9 +103,9 @@ impl VmDebugContext {
     }
 
     /// Add breakpoint
-    pub fn add_breakpoint(&mut self, addr: u64) -> Result<u32, &'static str> {
+    pub fn add_breakpoint(&mut self, addr: u64) -> Result<u32, Error> {
         if self.breakpoints.len() >= self.config.max_breakpoints as usize {
-            return Err("Maximum breakpoints reached");
+            return Err(Error::OutOfResources("Maximum breakpoints reached"));
         }
 
         let id = self.breakpoints.len() as u32;
@@ -109,19 +116,19 @@ impl VmDebugContext {
     }
 
     /// Remove breakpoint
-    pub fn remove_breakpoint(&mut self, id: u32) -> Result<(), &'static str> {
+    pub fn remove_breakpoint(&mut self, id: u32) -> Result<(), Error> {
         let index = self.breakpoints.iter()
             .position(|bp| bp.id == id)
-            .ok_or("Breakpoint not found")?;
+            .ok_or(Error::NotFound("Breakpoint not found"))?;
 
         self.breakpoints.remove(index);
         Ok(())
     }
 
     /// Add watchpoint
-    pub fn add_watchpoint(&mut self, addr: u64, size: u32, access_type: MemoryAccessType) -> Result<u32, &'static str> {
+    pub fn add_watchpoint(&mut self, addr: u64, size: u32, access_type: MemoryAccessType) -> Result<u32, Error> {
         if self.watchpoints.len() >= self.config.max_watchpoints as usize {
-            return Err("Maximum watchpoints reached");
+            return Err(Error::OutOfResources("Maximum watchpoints reached"));
         }
 
         let id = self.watchpoints.len() as u32;
@@ -132,10 +139,10 @@ impl VmDebugContext {
     }
 
     /// Remove watchpoint
-    pub fn remove_watchpoint(&mut self, id: u32) -> Result<(), &'static str> {
+    pub fn remove_watchpoint(&mut self, id: u32) -> Result<(), Error> {
         let index = self.watchpoints.iter()
             .position(|wp| wp.id == id)
-            .ok_or("Watchpoint not found")?;
+            .ok_or(Error::NotFound("Watchpoint not found"))?;
 
         self.watchpoints.remove(index);
         Ok(())
@@ -538,7 +545,7 @@ pub struct VmDebugStats {
 /// VM debug manager
 pub struct VmDebugManager {
     /// VM debug contexts
-    vm_contexts: spin::Mutex<std::collections::HashMap<VmId, VmDebugContext>>,
+    vm_contexts: SpinLock<std::collections::HashMap<VmId, VmDebugContext>>,
     /// Global debug configuration
     global_config: VmDebugConfig,
 }
@@ -547,30 +554,30 @@ impl VmDebugManager {
     /// Create new VM debug manager
     pub fn new() -> Self {
         Self {
-            vm_contexts: spin::Mutex::new(std::collections::HashMap::new()),
+            vm_contexts: SpinLock::new(std::collections::HashMap::new()),
             global_config: VmDebugConfig::default(),
         }
     }
 
     /// Create new VM debug context
-    pub fn create_vm_context(&self, vm_id: VmId) -> Result<VmDebugContext, &'static str> {
+    pub fn create_vm_context(&self, vm_id: VmId) -> Result<VmDebugContext, Error> {
         let context = VmDebugContext::new(vm_id);
         self.register_vm_context(vm_id, context.clone())?;
         Ok(context)
     }
 
     /// Create VM debug context with config
-    pub fn create_vm_context_with_config(&self, vm_id: VmId, config: VmDebugConfig) -> Result<VmDebugContext, &'static str> {
+    pub fn create_vm_context_with_config(&self, vm_id: VmId, config: VmDebugConfig) -> Result<VmDebugContext, Error> {
         let context = VmDebugContext::with_config(vm_id, config);
         self.register_vm_context(vm_id, context.clone())?;
         Ok(context)
     }
 
     /// Register VM debug context
-    fn register_vm_context(&self, vm_id: VmId, context: VmDebugContext) -> Result<(), &'static str> {
+    fn register_vm_context(&self, vm_id: VmId, context: VmDebugContext) -> Result<(), Error> {
         let mut contexts = self.vm_contexts.lock();
         if contexts.contains_key(&vm_id) {
-            return Err("VM debug context already exists");
+            return Err(Error::Busy("VM debug context already exists"));
         }
         contexts.insert(vm_id, context);
         Ok(())
@@ -589,12 +596,12 @@ impl VmDebugManager {
     }
 
     /// Update VM debug context
-    pub fn update_vm_context<F>(&self, vm_id: VmId, updater: F) -> Result<(), &'static str>
+    pub fn update_vm_context<F>(&self, vm_id: VmId, updater: F) -> Result<(), Error>
     where
         F: FnOnce(&mut VmDebugContext),
     {
         let mut contexts = self.vm_contexts.lock();
-        let context = contexts.get_mut(&vm_id).ok_or("VM debug context not found")?;
+        let context = contexts.get_mut(&vm_id).ok_or(Error::NotFound("VM debug context not found"))?;
         updater(context);
         Ok(())
     }
@@ -637,6 +644,254 @@ impl VmDebugManager {
     }
 }
 
+/// Hardware triggers set aside for guest breakpoints
+///
+/// They sit at the top of the trigger range and are reserved in the host
+/// `BreakpointManager`, so host breakpoints never reuse them.
+pub const GUEST_TRIGGERS: u32 = 4;
+
+/// `vsatp` MODE field shift (RV64)
+const VSATP_MODE_SHIFT: u32 = 60;
+
+/// `vsatp` PPN field mask (RV64)
+const VSATP_PPN_MASK: usize = (1 << 44) - 1;
+
+/// PTE valid bit
+const PTE_V: u64 = 1 << 0;
+
+/// PTE readable and executable bits; either makes the entry a leaf
+const PTE_LEAF: u64 = (1 << 1) | (1 << 3);
+
+/// PTE PPN field shift
+const PTE_PPN_SHIFT: u32 = 10;
+
+/// PTE PPN field mask, after shifting
+const PTE_PPN_MASK: u64 = (1 << 44) - 1;
+
+/// `mcontrol6` type field value
+const MCONTROL6_TYPE: u64 = 6 << 60;
+
+/// `mcontrol6` vs bit: match in VS-mode
+const MCONTROL6_VS: u64 = 1 << 24;
+
+/// `mcontrol6` vu bit: match in VU-mode
+const MCONTROL6_VU: u64 = 1 << 23;
+
+/// `mcontrol6` execute bit
+const MCONTROL6_EXECUTE: u64 = 1 << 2;
+
+/// `textra64` mhvalue field shift
+const TEXTRA_MHVALUE_SHIFT: u32 = 51;
+
+/// `textra64` mhselect field shift
+const TEXTRA_MHSELECT_SHIFT: u32 = 48;
+
+/// mhselect value that matches the VMID in hgatp against
+/// `{mhvalue, mhselect[2]}`
+const MHSELECT_VMID: u64 = 2;
+
+/// Hardware breakpoint on guest code, firing only in one guest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GuestBreakpoint {
+    /// Breakpoint ID
+    pub id: u32,
+    /// VMID the trigger is scoped to
+    pub vmid: u16,
+    /// Guest virtual address the breakpoint was set on
+    pub gva: u64,
+    /// Host physical address the guest address resolved to, for reporting
+    pub hpa: u64,
+    /// Slot among the `GUEST_TRIGGERS` guest triggers
+    pub slot: u32,
+}
+
+impl GuestBreakpoint {
+    /// Check if an execution at `gva` in guest `vmid` hits the breakpoint
+    pub fn matches(&self, vmid: u16, gva: u64) -> bool {
+        self.vmid == vmid && self.gva == gva
+    }
+
+    /// Hardware trigger index, counting down from the last trigger
+    pub fn trigger_index(&self, trigger_count: u32) -> Option<u32> {
+        guest_trigger_index(self.slot, trigger_count)
+    }
+
+    /// TDATA1 value: `mcontrol6` execute match in VS and VU modes only
+    ///
+    /// The action is a breakpoint exception, which the hypervisor takes
+    /// as the guest stops, so the trigger is left writable outside debug
+    /// mode.
+    pub fn tdata1(&self) -> Tdata1 {
+        Tdata1::from_bits(MCONTROL6_TYPE | MCONTROL6_VS | MCONTROL6_VU | MCONTROL6_EXECUTE)
+    }
+
+    /// TDATA2 value: the guest virtual address, which is what the hart
+    /// compares while running the guest
+    pub fn tdata2(&self) -> Tdata2 {
+        Tdata2::from_bits(self.gva)
+    }
+
+    /// TDATA3 (textra) value: match only while hgatp holds our VMID
+    pub fn tdata3(&self) -> u64 {
+        let vmid = self.vmid as u64;
+        let mhselect = MHSELECT_VMID | ((vmid & 1) << 2);
+        ((vmid >> 1) << TEXTRA_MHVALUE_SHIFT) | (mhselect << TEXTRA_MHSELECT_SHIFT)
+    }
+}
+
+/// Breakpoints set on guest code
+#[derive(Debug, Default)]
+pub struct GuestBreakpoints {
+    /// Installed breakpoints
+    breakpoints: Vec<GuestBreakpoint>,
+    /// Next breakpoint ID
+    next_id: u32,
+}
+
+impl GuestBreakpoints {
+    /// Create an empty table
+    pub const fn new() -> Self {
+        Self {
+            breakpoints: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Add a breakpoint on `gva` in guest `vmid`
+    ///
+    /// `translate` resolves the guest virtual address to a host physical
+    /// address through the guest's page tables and stage-2.
+    pub fn insert(
+        &mut self,
+        vmid: u16,
+        gva: u64,
+        translate: impl FnOnce(u64) -> Option<u64>,
+    ) -> Result<GuestBreakpoint, Error> {
+        let slot = (0..GUEST_TRIGGERS)
+            .find(|slot| self.breakpoints.iter().all(|bp| bp.slot != *slot))
+            .ok_or(Error::OutOfResources("No guest triggers available"))?;
+        let hpa = translate(gva).ok_or(Error::NotFound("Guest address not mapped"))?;
+
+        let bp = GuestBreakpoint { id: self.next_id, vmid, gva, hpa, slot };
+        self.next_id += 1;
+        self.breakpoints.push(bp);
+        Ok(bp)
+    }
+
+    /// Remove a breakpoint
+    pub fn remove(&mut self, id: u32) -> Result<GuestBreakpoint, Error> {
+        let index = self.breakpoints.iter()
+            .position(|bp| bp.id == id)
+            .ok_or(Error::NotFound("Guest breakpoint not found"))?;
+        Ok(self.breakpoints.remove(index))
+    }
+
+    /// Find the breakpoint hit by an execution at `gva` in guest `vmid`
+    pub fn hit(&self, vmid: u16, gva: u64) -> Option<&GuestBreakpoint> {
+        self.breakpoints.iter().find(|bp| bp.matches(vmid, gva))
+    }
+
+    /// Get the breakpoints of one guest
+    pub fn for_vmid(&self, vmid: u16) -> impl Iterator<Item = &GuestBreakpoint> {
+        self.breakpoints.iter().filter(move |bp| bp.vmid == vmid)
+    }
+
+    /// Get the breakpoint occupying a guest trigger slot
+    pub fn in_slot(&self, slot: u32) -> Option<&GuestBreakpoint> {
+        self.breakpoints.iter().find(|bp| bp.slot == slot)
+    }
+}
+
+/// Hardware trigger index of guest trigger slot `slot`
+fn guest_trigger_index(slot: u32, trigger_count: u32) -> Option<u32> {
+    trigger_count.checked_sub(slot + 1)
+}
+
+/// Set the guest triggers aside in the host breakpoint manager
+pub fn reserve_guest_triggers(bp_manager: &mut BreakpointManager, trigger_count: u32) -> Result<(), Error> {
+    (0..GUEST_TRIGGERS)
+        .filter_map(|slot| guest_trigger_index(slot, trigger_count))
+        .try_for_each(|index| bp_manager.reserve_trigger(index))
+}
+
+/// Walk the guest's stage-1 page table for `gva`, giving its GPA
+///
+/// The root in `vsatp` and the next-level pointers in the table are guest
+/// physical, so every table access goes through `stage2` first; `read`
+/// loads a PTE from a host physical address. Bare mode passes the address
+/// through.
+fn walk_guest_stage1(
+    vsatp: usize,
+    gva: u64,
+    stage2: impl Fn(u64) -> Option<u64>,
+    read: impl Fn(u64) -> u64,
+) -> Option<u64> {
+    let levels = match (vsatp >> VSATP_MODE_SHIFT) as u8 {
+        0 => return Some(gva),
+        mode @ 8..=10 => mode as u32 - 5, // Sv39, Sv48, Sv57
+        _ => return None,
+    };
+
+    let mut table = ((vsatp & VSATP_PPN_MASK) as u64) << 12;
+    for level in (0..levels).rev() {
+        let shift = 12 + 9 * level;
+        let index = (gva >> shift) & 0x1ff;
+        let pte = read(stage2(table + index * 8)?);
+        if pte & PTE_V == 0 {
+            return None;
+        }
+
+        let pa = ((pte >> PTE_PPN_SHIFT) & PTE_PPN_MASK) << 12;
+        if pte & PTE_LEAF != 0 {
+            let offset_mask = (1u64 << shift) - 1;
+            return Some((pa & !offset_mask) | (gva & offset_mask));
+        }
+        table = pa;
+    }
+
+    None
+}
+
+/// Translate a guest virtual address to a host physical address
+///
+/// Walks the guest's page table from `vsatp`, then the VM's stage-2 table.
+fn translate_guest_address(vsatp: usize, stage2: &RootPageTable, gva: u64) -> Option<u64> {
+    let stage2_walk = |gpa: u64| {
+        let hpa = translate_single_stage(stage2.root().ppn(), gpa as usize, stage2.mode());
+        hpa.success.then_some(hpa.pa as u64)
+    };
+
+    let gpa = walk_guest_stage1(vsatp, gva, &stage2_walk, |hpa| unsafe {
+        core::ptr::read_volatile(hpa as *const u64)
+    })?;
+    stage2_walk(gpa)
+}
+
+/// Write this hart's guest triggers from the breakpoint table
+///
+/// Slots without a breakpoint are disabled.
+fn program_guest_triggers(breakpoints: &GuestBreakpoints) -> Result<(), Error> {
+    let regs = DebugRegisters::new()?;
+    let trigger_count = regs.get_trigger_count();
+
+    for slot in 0..GUEST_TRIGGERS {
+        let Some(index) = guest_trigger_index(slot, trigger_count) else {
+            continue;
+        };
+
+        // Keep the trigger from matching while its address and context
+        // change; an mcontrol6 with no mode bits set never fires
+        regs.select_trigger(index);
+        regs.write_tdata1(Tdata1::from_bits(MCONTROL6_TYPE));
+        if let Some(bp) = breakpoints.in_slot(slot) {
+            regs.write_tdata2(bp.tdata2());
+            regs.write_tdata3(bp.tdata3());
+            regs.write_tdata1(bp.tdata1());
+        }
+    }
+    Ok(())
+}
+
 /// Global VM debug manager
 static VM_DEBUG_MANAGER: spin::Once<VmDebugManager> = spin::Once::new();
 
@@ -645,8 +900,84 @@ pub fn get_vm_debug_manager() -> &'static VmDebugManager {
     VM_DEBUG_MANAGER.call_once(|| VmDebugManager::new())
 }
 
+/// Breakpoints set on guest code
+static GUEST_BREAKPOINTS: SpinLock<GuestBreakpoints> = SpinLock::new(GuestBreakpoints::new());
+
+/// Version of `GUEST_BREAKPOINTS`, bumped on every change
+static GUEST_BREAKPOINTS_VERSION: AtomicU64 = AtomicU64::new(1);
+
+/// Version of `GUEST_BREAKPOINTS` each hart's triggers were written from
+static LOADED_VERSION: [AtomicU64; MAX_CPUS] = [const { AtomicU64::new(0) }; MAX_CPUS];
+
+/// Bring this hart's guest triggers up to date before entering a guest
+///
+/// Triggers are per-hart, while guest breakpoints are set from any hart,
+/// so every hart rewrites its guest triggers on guest entry after the
+/// breakpoint table changed.
+pub fn load_guest_triggers() {
+    let version = GUEST_BREAKPOINTS_VERSION.load(Ordering::Acquire);
+    let Some(loaded) = LOADED_VERSION.get(crate::arch::riscv64::cpu::current_cpu_id()) else {
+        return;
+    };
+    if loaded.swap(version, Ordering::Relaxed) == version {
+        return;
+    }
+
+    if let Err(e) = program_guest_triggers(&GUEST_BREAKPOINTS.lock()) {
+        log::warn!("Failed to load guest triggers: {:?}", e);
+    }
+}
+
+/// Set a hardware breakpoint on guest code
+///
+/// `gva` is translated through the guest's current `vsatp` (taken from its
+/// first VCPU) and the VM's stage-2 table, and the trigger only fires while
+/// the hart runs the guest with this VMID. Each hart picks the breakpoint
+/// up the next time it enters a guest. Returns the breakpoint ID.
+pub fn set_guest_breakpoint(vmid: u16, gva: u64) -> Result<u32, Error> {
+    if get_config().is_some_and(|config| !config.enable_vm_debug) {
+        // The host breakpoint manager did not set the guest triggers aside
+        return Err(Error::Unsupported("VM debugging disabled"));
+    }
+    let trigger_count = DebugRegisters::new()?.get_trigger_count();
+
+    let mut manager = crate::arch::riscv64::virtualization::get_vm_manager()
+        .ok_or(Error::NotInitialized("VM manager not initialized"))?;
+    let vm = manager.get_vms_mut().iter_mut()
+        .find(|vm| vm.vmid == vmid)
+        .ok_or(Error::NotFound("VM not found"))?;
+    let vsatp = vm.vcpu_manager.get_vcpu(0)
+        .ok_or(Error::NotFound("VM has no VCPU"))?
+        .guest_csr.vsatp;
+
+    let mut breakpoints = GUEST_BREAKPOINTS.lock();
+    let bp = breakpoints.insert(vmid, gva, |gva| translate_guest_address(vsatp, &vm.stage2_ptable, gva))?;
+    if bp.trigger_index(trigger_count).is_none() {
+        breakpoints.remove(bp.id)?;
+        return Err(Error::Unsupported("Not enough hardware triggers"));
+    }
+    GUEST_BREAKPOINTS_VERSION.fetch_add(1, Ordering::Release);
+
+    log::debug!("Guest breakpoint {} on VMID {} at {:#x} (host {:#x})", bp.id, vmid, gva, bp.hpa);
+    Ok(bp.id)
+}
+
+/// Remove a guest breakpoint
+///
+/// Each hart disables the trigger the next time it enters a guest.
+pub fn clear_guest_breakpoint(id: u32) -> Result<(), Error> {
+    GUEST_BREAKPOINTS.lock().remove(id)?;
+    GUEST_BREAKPOINTS_VERSION.fetch_add(1, Ordering::Release);
+    Ok(())
+}
+
+/// Find the guest breakpoint hit by an execution at `gva` in guest `vmid`
+pub fn guest_breakpoint_hit(vmid: u16, gva: u64) -> Option<GuestBreakpoint> {
+    GUEST_BREAKPOINTS.lock().hit(vmid, gva).copied()
+}
+
 /// Initialize VM debug support
-pub fn init() -> Result<(), &'static str> {
+pub fn init() -> Result<(), Error> {
     log::info!("Initializing RISC-V VM debug support");
 
     // Initialize global VM debug manager
@@ -834,4 +1165,101 @@ mod tests {
         let not_found = manager.get_vm_context(vm_id);
         assert!(not_found.is_none());
     }
+
+    #[test]
+    fn test_guest_breakpoint_uses_resolved_host_address() {
+        let mut breakpoints = GuestBreakpoints::new();
+        let bp = breakpoints.insert(5, 0xffff_ffff_8000_1000, |gva| {
+            assert_eq!(gva, 0xffff_ffff_8000_1000);
+            Some(0x9020_1000)
+        }).unwrap();
+
+        assert_eq!(bp.hpa, 0x9020_1000);
+        assert_eq!(bp.tdata2().value(), 0xffff_ffff_8000_1000);
+        assert_eq!(bp.trigger_index(16), Some(15));
+
+        // An execute trigger for the guest's modes only (not m, s or u),
+        // not owned by debug mode
+        const HOST_MODES_AND_DMODE: u64 = (1 << 59) | (1 << 6) | (1 << 4) | (1 << 3);
+        let tdata1 = bp.tdata1().bits();
+        assert_eq!(tdata1 >> 60, 6);
+        assert_eq!(tdata1 & (MCONTROL6_VS | MCONTROL6_VU | MCONTROL6_EXECUTE), MCONTROL6_VS | MCONTROL6_VU | MCONTROL6_EXECUTE);
+        assert_eq!(tdata1 & HOST_MODES_AND_DMODE, 0);
+
+        // textra matches VMID 5 = {mhvalue 2, mhselect[2] 1}
+        let tdata3 = bp.tdata3();
+        assert_eq!(tdata3 >> TEXTRA_MHVALUE_SHIFT, 2);
+        assert_eq!((tdata3 >> TEXTRA_MHSELECT_SHIFT) & 0x7, MHSELECT_VMID | 4);
+
+        // Unmapped guest addresses are refused
+        assert!(breakpoints.insert(5, 0x1000, |_| None).is_err());
+        assert_eq!(breakpoints.for_vmid(5).count(), 1);
+    }
+
+    #[test]
+    fn test_guest_slots_reuse_freed_top_triggers() {
+        let mut breakpoints = GuestBreakpoints::new();
+        let first = breakpoints.insert(1, 0x8000_0000, Some).unwrap();
+        let second = breakpoints.insert(2, 0x8000_0000, Some).unwrap();
+        assert_eq!(second.trigger_index(16), Some(14));
+
+        // A freed slot is handed out again and empty slots read back as such
+        breakpoints.remove(first.id).unwrap();
+        assert!(breakpoints.in_slot(first.slot).is_none());
+        let third = breakpoints.insert(3, 0x8000_1000, Some).unwrap();
+        assert_eq!(third.slot, first.slot);
+        assert_eq!(breakpoints.in_slot(third.slot).map(|bp| bp.vmid), Some(3));
+
+        // Guest slots all sit above the last trigger the host may use
+        let guest: Vec<u32> = (0..GUEST_TRIGGERS).filter_map(|slot| guest_trigger_index(slot, 16)).collect();
+        assert_eq!(guest, [15, 14, 13, 12]);
+    }
+
+    #[test]
+    fn test_guest_breakpoint_ignored_outside_vmid() {
+        let mut breakpoints = GuestBreakpoints::new();
+        let bp = breakpoints.insert(5, 0x8000_0000, |_| Some(0x9000_0000)).unwrap();
+
+        assert_eq!(breakpoints.hit(5, 0x8000_0000), Some(&bp));
+        assert_eq!(breakpoints.hit(6, 0x8000_0000), None);
+        assert_eq!(breakpoints.hit(5, 0x9000_0000), None);
+
+        let other = breakpoints.insert(6, 0x8000_0000, |_| Some(0x9000_0000)).unwrap();
+        assert_ne!(other.slot, bp.slot);
+        assert_ne!(other.tdata3(), bp.tdata3());
+        assert_eq!(breakpoints.hit(6, 0x8000_0000), Some(&other));
+
+        breakpoints.remove(bp.id).unwrap();
+        assert_eq!(breakpoints.hit(5, 0x8000_0000), None);
+    }
+
+    #[test]
+    fn test_guest_walk_reads_tables_through_stage2() {
+        // Guest memory at GPA 0x8000_0000 lives at HPA 0x9000_0000
+        let stage2 = |gpa: u64| (0x8000_0000..0x8400_0000).contains(&gpa).then(|| gpa + 0x1000_0000);
+        let table = |gpa: u64| ((gpa >> 12) << PTE_PPN_SHIFT) | PTE_V;
+        let leaf = |gpa: u64| table(gpa) | PTE_LEAF;
+
+        // Sv39, root at GPA 0x8000_0000: VA 0x4020_1000 maps through two
+        // more levels to 0x8030_0000, VA 0x4040_0000 is a 2MiB superpage
+        let memory = |hpa: u64| match hpa {
+            0x9000_0008 => table(0x8000_1000),
+            0x9000_1008 => table(0x8000_2000),
+            0x9000_1010 => leaf(0x8060_0000),
+            0x9000_2008 => leaf(0x8030_0000),
+            _ => 0,
+        };
+        let vsatp = (8 << VSATP_MODE_SHIFT) | (0x8000_0000 >> 12);
+
+        assert_eq!(walk_guest_stage1(vsatp, 0x4020_1234, stage2, memory), Some(0x8030_0234));
+        assert_eq!(walk_guest_stage1(vsatp, 0x4041_2345, stage2, memory), Some(0x8061_2345));
+        assert_eq!(walk_guest_stage1(vsatp, 0x4060_0000, stage2, memory), None);
+
+        // A table outside the guest's stage-2 mappings can't be walked
+        let unmapped_root = (8 << VSATP_MODE_SHIFT) | (0x7000_0000 >> 12);
+        assert_eq!(walk_guest_stage1(unmapped_root, 0x4020_1234, stage2, memory), None);
+
+        // Bare mode
+        assert_eq!(walk_guest_stage1(0, 0x4020_1234, stage2, memory), Some(0x4020_1234));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,127 @@
+//! RISC-V architecture error type
+//!
+//! Architecture code reports failures with `Error`, which classifies the
+//! failure so callers can match on it and still carries the human-readable
+//! message that used to be returned as a bare `&'static str`. It converts
+//! into the crate-wide `crate::Error` with `?`.
+
+use core::fmt;
+
+/// RISC-V architecture error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// A subsystem was used before being initialized
+    NotInitialized(&'static str),
+    /// An argument was out of range or malformed
+    InvalidArgument(&'static str),
+    /// The object is not in a state that allows the operation
+    InvalidState(&'static str),
+    /// The requested object does not exist
+    NotFound(&'static str),
+    /// The object already exists or is in use
+    Busy(&'static str),
+    /// A fixed-size table or pool is exhausted
+    OutOfResources(&'static str),
+    /// The operation is not allowed on this object
+    PermissionDenied(&'static str),
+    /// The hardware, firmware or this implementation lacks the feature
+    Unsupported(&'static str),
+    /// An operation did not complete in time
+    Timeout(&'static str),
+    /// A hardware or firmware operation failed
+    Failed(&'static str),
+}
+
+impl Error {
+    /// Human-readable description of the error
+    pub fn message(&self) -> &'static str {
+        match *self {
+            Error::NotInitialized(msg)
+            | Error::InvalidArgument(msg)
+            | Error::InvalidState(msg)
+            | Error::NotFound(msg)
+            | Error::Busy(msg)
+            | Error::OutOfResources(msg)
+            | Error::PermissionDenied(msg)
+            | Error::Unsupported(msg)
+            | Error::Timeout(msg)
+            | Error::Failed(msg) => msg,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+/// Errors from code still returning bare messages are unclassified failures
+impl From<&'static str> for Error {
+    fn from(msg: &'static str) -> Self {
+        Error::Failed(msg)
+    }
+}
+
+/// Lets modules still returning `&'static str` propagate `Error` with `?`
+impl From<Error> for &'static str {
+    fn from(err: Error) -> Self {
+        err.message()
+    }
+}
+
+impl From<Error> for crate::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::NotInitialized(_) => crate::Error::NotInitialized,
+            Error::InvalidArgument(_) => crate::Error::InvalidArgument,
+            Error::InvalidState(_) => crate::Error::InvalidState,
+            Error::NotFound(_) => crate::Error::NotFound,
+            Error::Busy(_) => crate::Error::ResourceBusy,
+            Error::OutOfResources(_) => crate::Error::ResourceUnavailable,
+            Error::PermissionDenied(_) => crate::Error::PermissionDenied,
+            Error::Unsupported(_) => crate::Error::NotImplemented,
+            Error::Timeout(_) => crate::Error::Timeout,
+            Error::Failed(_) => crate::Error::ArchError(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_errors_convert_to_crate_error() {
+        assert_eq!(
+            crate::Error::from(Error::NotInitialized("PLIC not initialized")),
+            crate::Error::NotInitialized
+        );
+        assert_eq!(
+            crate::Error::from(Error::InvalidArgument("Invalid hart ID")),
+            crate::Error::InvalidArgument
+        );
+        assert_eq!(crate::Error::from(Error::NotFound("VCPU not found")), crate::Error::NotFound);
+        assert_eq!(crate::Error::from(Error::Busy("CPU already online")), crate::Error::ResourceBusy);
+        assert_eq!(
+            crate::Error::from(Error::Unsupported("SBI HSM extension not available")),
+            crate::Error::NotImplemented
+        );
+        assert_eq!(
+            crate::Error::from(Error::Timeout("Timeout waiting for CPU to be ready")),
+            crate::Error::Timeout
+        );
+
+        // Failures with no generic equivalent keep the arch error
+        let err = Error::Failed("SBI call failed");
+        assert_eq!(crate::Error::from(err), crate::Error::ArchError(err));
+    }
+
+    #[test]
+    fn test_message_is_preserved() {
+        let err = Error::OutOfResources("Maximum VCPUs reached");
+        assert_eq!(err.message(), "Maximum VCPUs reached");
+        assert_eq!(<&'static str>::from(err), "Maximum VCPUs reached");
+        assert_eq!(Error::from("Write failed"), Error::Failed("Write failed"));
+    }
+}
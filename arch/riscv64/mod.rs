@@ -17,6 +17,7 @@ pub mod smp;
 pub mod devtree;
 pub mod debug;
 pub mod platform;
+pub mod error;
 
 // Re-export key types and functions
 pub use cpu::*;
@@ -27,6 +28,7 @@ pub use smp::*;
 pub use devtree::*;
 pub use debug::*;
 pub use platform::*;
+pub use error::Error;
 
 /// RISC-V 64-bit architecture version
 pub const ARCH_VERSION: &str = "riscv64";
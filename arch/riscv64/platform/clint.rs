@@ -20,7 +20,7 @@ pub struct ClintConfig {
     /// Enable timer interrupts
     pub enable_timer_interrupts: bool,
     /// Enable software interrupts
-    pub enable_software_interrupts,
+    pub enable_software_interrupts: bool,
 }
 
 impl Default for ClintConfig {
@@ -42,6 +42,11 @@ pub mod clint_regs {
     pub const MTIME: usize = 0xBFF8; // Timer value
 }
 
+/// Offset of a hart's MSIP register from the CLINT base
+pub const fn msip_offset(hart_id: u32) -> usize {
+    clint_regs::MSIP0 + hart_id as usize * 4
+}
+
 /// CLINT driver
 pub struct Clint {
     /// Base address
@@ -57,7 +62,7 @@ impl Clint {
     }
 
     /// Initialize CLINT
-    pub fn init(&mut self) -> Result<(), &'static str> {
+    pub fn init(&mut self) -> Result<(), Error> {
         log::info!("Initializing CLINT at {:#x}", self.base);
 
         // Clear all software interrupt pending bits
@@ -95,9 +100,9 @@ impl Clint {
     }
 
     /// Write mtimecmp register for specific hart (64-bit)
-    pub fn write_mtimecmp(&self, hart_id: u32, value: u64) -> Result<(), &'static str> {
+    pub fn write_mtimecmp(&self, hart_id: u32, value: u64) -> Result<(), Error> {
         if hart_id >= self.config.num_harts {
-            return Err("Invalid hart ID");
+            return Err(Error::InvalidArgument("Invalid hart ID"));
         }
 
         let mtimecmp_base = self.base + (clint_regs::MTIMECMP0 as u64) + (hart_id as u64 * 8);
@@ -112,14 +117,14 @@ impl Clint {
     }
 
     /// Set timer comparator for specific hart
-    pub fn set_timer_comparator(&self, hart_id: u32, value: u64) -> Result<(), &'static str> {
+    pub fn set_timer_comparator(&self, hart_id: u32, value: u64) -> Result<(), Error> {
         self.write_mtimecmp(hart_id, value)
     }
 
     /// Get timer comparator for specific hart
-    pub fn get_timer_comparator(&self, hart_id: u32) -> Result<u64, &'static str> {
+    pub fn get_timer_comparator(&self, hart_id: u32) -> Result<u64, Error> {
         if hart_id >= self.config.num_harts {
-            return Err("Invalid hart ID");
+            return Err(Error::InvalidArgument("Invalid hart ID"));
         }
 
         let mtimecmp_base = self.base + (clint_regs::MTIMECMP0 as u64) + (hart_id as u64 * 8);
@@ -132,16 +137,16 @@ impl Clint {
     }
 
     /// Set software interrupt for specific hart
-    pub fn set_software_interrupt(&self, hart_id: u32) -> Result<(), &'static str> {
+    pub fn set_software_interrupt(&self, hart_id: u32) -> Result<(), Error> {
         if hart_id >= self.config.num_harts {
-            return Err("Invalid hart ID");
+            return Err(Error::InvalidArgument("Invalid hart ID"));
         }
 
         if !self.config.enable_software_interrupts {
-            return Err("Software interrupts disabled");
+            return Err(Error::InvalidState("Software interrupts disabled"));
         }
 
-        let msip_base = self.base + (clint_regs::MSIP0 as u64) + (hart_id as u64 * 4);
+        let msip_base = self.base + msip_offset(hart_id) as u64;
 
         unsafe {
             core::ptr::write_volatile(msip_base as *mut u32, 1);
@@ -156,7 +161,7 @@ impl Clint {
             return;
         }
 
-        let msip_base = self.base + (clint_regs::MSIP0 as u64) + (hart_id as u64 * 4);
+        let msip_base = self.base + msip_offset(hart_id) as u64;
 
         unsafe {
             core::ptr::write_volatile(msip_base as *mut u32, 0);
@@ -164,12 +169,12 @@ impl Clint {
     }
 
     /// Check if software interrupt is pending for specific hart
-    pub fn is_software_interrupt_pending(&self, hart_id: u32) -> Result<bool, &'static str> {
+    pub fn is_software_interrupt_pending(&self, hart_id: u32) -> Result<bool, Error> {
         if hart_id >= self.config.num_harts {
-            return Err("Invalid hart ID");
+            return Err(Error::InvalidArgument("Invalid hart ID"));
         }
 
-        let msip_base = self.base + (clint_regs::MSIP0 as u64) + (hart_id as u64 * 4);
+        let msip_base = self.base + msip_offset(hart_id) as u64;
 
         unsafe {
             let value = core::ptr::read_volatile(msip_base as *const u32);
@@ -190,9 +195,9 @@ impl Clint {
     }
 
     /// Enable timer interrupts for specific hart
-    pub fn enable_timer_interrupts(&self, hart_id: u32, enable: bool) -> Result<(), &'static str> {
+    pub fn enable_timer_interrupts(&self, hart_id: u32, enable: bool) -> Result<(), Error> {
         if !self.config.enable_timer_interrupts {
-            return Err("Timer interrupts disabled");
+            return Err(Error::InvalidState("Timer interrupts disabled"));
         }
 
         if enable {
@@ -208,12 +213,12 @@ impl Clint {
     }
 
     /// Send IPI (Inter-Processor Interrupt) to target hart
-    pub fn send_ipi(&self, target_hart: u32) -> Result<(), &'static str> {
+    pub fn send_ipi(&self, target_hart: u32) -> Result<(), Error> {
         self.set_software_interrupt(target_hart)
     }
 
     /// Broadcast IPI to all other harts
-    pub fn broadcast_ipi(&self, source_hart: u32) -> Result<(), &'static str> {
+    pub fn broadcast_ipi(&self, source_hart: u32) -> Result<(), Error> {
         for hart_id in 0..self.config.num_harts {
             if hart_id != source_hart {
                 self.set_software_interrupt(hart_id)?;
@@ -223,7 +228,7 @@ impl Clint {
     }
 
     /// Handle timer interrupt for specific hart
-    pub fn handle_timer_interrupt(&self, hart_id: u32, period_us: u64) -> Result<(), &'static str> {
+    pub fn handle_timer_interrupt(&self, hart_id: u32, period_us: u64) -> Result<(), Error> {
         // Calculate next timer time
         let next_time = self.calculate_next_timer(period_us);
 
@@ -234,7 +239,7 @@ impl Clint {
     }
 
     /// Get time until next timer interrupt
-    pub fn get_time_until_next_timer(&self, hart_id: u32) -> Result<u64, &'static str> {
+    pub fn get_time_until_next_timer(&self, hart_id: u32) -> Result<u64, Error> {
         let current = self.read_mtime();
         let comparator = self.get_timer_comparator(hart_id)?;
 
@@ -256,7 +261,7 @@ static mut CLINT: Option<Clint> = None;
 static CLINT_INIT: spin::Once<()> = spin::Once::new();
 
 /// Initialize CLINT subsystem
-pub fn init() -> Result<(), &'static str> {
+pub fn init() -> Result<(), Error> {
     log::info!("Initializing CLINT subsystem");
 
     CLINT_INIT.call_once(|| {
@@ -290,20 +295,20 @@ pub fn get_clint_mut() -> Option<&'static mut Clint> {
 }
 
 /// Send IPI to specific hart
-pub fn send_ipi(hart_id: u32) -> Result<(), &'static str> {
+pub fn send_ipi(hart_id: u32) -> Result<(), Error> {
     if let Some(clint) = get_clint() {
         clint.send_ipi(hart_id)
     } else {
-        Err("CLINT not initialized")
+        Err(Error::NotInitialized("CLINT not initialized"))
     }
 }
 
 /// Broadcast IPI to all harts
-pub fn broadcast_ipi(source_hart: u32) -> Result<(), &'static str> {
+pub fn broadcast_ipi(source_hart: u32) -> Result<(), Error> {
     if let Some(clint) = get_clint() {
         clint.broadcast_ipi(source_hart)
     } else {
-        Err("CLINT not initialized")
+        Err(Error::NotInitialized("CLINT not initialized"))
     }
 }
 
@@ -317,21 +322,21 @@ pub fn get_time() -> u64 {
 }
 
 /// Set timer for specific hart
-pub fn set_timer(hart_id: u32, period_us: u64) -> Result<(), &'static str> {
+pub fn set_timer(hart_id: u32, period_us: u64) -> Result<(), Error> {
     if let Some(clint) = get_clint() {
         let next_time = clint.calculate_next_timer(period_us);
         clint.set_timer_comparator(hart_id, next_time)
     } else {
-        Err("CLINT not initialized")
+        Err(Error::NotInitialized("CLINT not initialized"))
     }
 }
 
 /// Handle timer interrupt
-pub fn handle_timer_interrupt(hart_id: u32, period_us: u64) -> Result<(), &'static str> {
+pub fn handle_timer_interrupt(hart_id: u32, period_us: u64) -> Result<(), Error> {
     if let Some(clint) = get_clint() {
         clint.handle_timer_interrupt(hart_id, period_us)
     } else {
-        Err("CLINT not initialized")
+        Err(Error::NotInitialized("CLINT not initialized"))
     }
 }
 
@@ -357,6 +362,30 @@ mod tests {
         assert_eq!(clint.config.num_harts, 8);
     }
 
+    #[test]
+    fn test_msip_offset() {
+        assert_eq!(msip_offset(0), 0x0);
+        assert_eq!(msip_offset(2), 0x8);
+        assert_eq!(msip_offset(7), 0x1c);
+    }
+
+    #[test]
+    fn test_send_ipi_writes_target_msip() {
+        let mut regs = [0u32; 8];
+        let clint = Clint::new(regs.as_mut_ptr() as u64, ClintConfig::default());
+
+        clint.send_ipi(2).unwrap();
+        let msip = unsafe { core::ptr::read_volatile(&regs[msip_offset(2) / 4]) };
+        assert_eq!(msip, 1);
+
+        // Other harts are left alone
+        assert_eq!(regs.iter().filter(|&&r| r != 0).count(), 1);
+        assert!(clint.is_software_interrupt_pending(2).unwrap());
+
+        clint.clear_software_interrupt(2);
+        assert!(!clint.is_software_interrupt_pending(2).unwrap());
+    }
+
     #[test]
     fn test_period_calculation() {
         let config = ClintConfig::default();
@@ -305,14 +305,14 @@ impl ConfigManager {
     }
 
     /// Save configuration to persistent storage
-    pub fn save(&self) -> Result<(), &'static str> {
+    pub fn save(&self) -> Result<(), Error> {
         // TODO: Implement configuration persistence
         log::debug!("Saving platform configuration");
         Ok(())
     }
 
     /// Load configuration from persistent storage
-    pub fn load(&mut self) -> Result<(), &'static str> {
+    pub fn load(&mut self) -> Result<(), Error> {
         // TODO: Implement configuration loading
         log::debug!("Loading platform configuration");
         Ok(())
@@ -324,7 +324,7 @@ static mut CONFIG_MANAGER: Option<ConfigManager> = None;
 static CONFIG_MANAGER_INIT: spin::Once<()> = spin::Once::new();
 
 /// Initialize configuration manager
-pub fn init() -> Result<(), &'static str> {
+pub fn init() -> Result<(), Error> {
     CONFIG_MANAGER_INIT.call_once(|| {
         let manager = ConfigManager::new();
         unsafe {
@@ -356,12 +356,12 @@ pub fn update_config(config: PlatformConfig) -> Result<(), String> {
 }
 
 /// Reset configuration to defaults
-pub fn reset_to_defaults() -> Result<(), &'static str> {
+pub fn reset_to_defaults() -> Result<(), Error> {
     if let Some(manager) = get_manager() {
         manager.reset_to_default();
         Ok(())
     } else {
-        Err("Configuration manager not initialized")
+        Err(Error::NotInitialized("Configuration manager not initialized"))
     }
 }
 
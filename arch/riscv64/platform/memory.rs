@@ -419,7 +419,7 @@ impl MemoryConfig {
     }
 
     /// Initialize memory regions
-    pub fn init(&self) -> Result<(), &'static str> {
+    pub fn init(&self) -> Result<(), Error> {
         log::debug!("Initializing platform memory regions");
 
         for region in &self.regions {
@@ -510,7 +510,7 @@ const PMA_SHARED: u64 = 1 << 3;
 const PMA_RESERVED: u64 = 1 << 4;
 
 /// Initialize platform memory
-pub fn init() -> Result<(), &'static str> {
+pub fn init() -> Result<(), Error> {
     log::info!("Initializing platform memory");
 
     // Get memory configuration from platform
@@ -15,6 +15,7 @@ pub mod clint;
 pub mod plic;
 
 use crate::arch::riscv64::*;
+use crate::libs::fdt::Fdt;
 use config::PlatformConfig;
 
 /// Platform type
@@ -102,13 +103,28 @@ static mut PLATFORM_CONFIG: Option<PlatformConfig> = None;
 static mut PLATFORM_CONFIGURATIONS: Option<PlatformConfigurations> = None;
 
 /// Initialize platform
-pub fn init() -> Result<(), &'static str> {
+pub fn init() -> Result<(), Error> {
+    init_with_fdt(None)
+}
+
+/// Initialize platform from a boot device tree blob
+pub fn init_with_fdt(fdt: Option<&Fdt>) -> Result<(), Error> {
     log::info!("Initializing RISC-V platform support");
 
     // Detect platform from device tree if available
-    let platform_info = detect_platform().unwrap_or_else(|_| PlatformInfo::default());
+    let platform_info = match fdt {
+        Some(fdt) => detect_platform_from_fdt(fdt),
+        None => detect_platform(),
+    }
+    .unwrap_or_else(|_| PlatformInfo::default());
     log::info!("Detected platform: {}", platform_info.name);
 
+    // Keep firmware and device carveouts away from the frame allocator
+    if let Some(fdt) = fdt {
+        let count = crate::core::mm::reserved::reserve_from_fdt(fdt);
+        log::info!("Registered {} reserved memory regions", count);
+    }
+
     // Store platform information
     unsafe {
         PLATFORM_INFO = Some(platform_info.clone());
@@ -131,7 +147,7 @@ pub fn init() -> Result<(), &'static str> {
 }
 
 /// Detect platform from device tree or hardware
-pub fn detect_platform() -> Result<PlatformInfo, &'static str> {
+pub fn detect_platform() -> Result<PlatformInfo, Error> {
     // Try to detect from device tree first
     if let Some(_compatible) = crate::arch::riscv64::devtree::find_compatible("qemu,riscv-virt") {
         return Ok(PlatformInfo {
@@ -151,7 +167,59 @@ pub fn detect_platform() -> Result<PlatformInfo, &'static str> {
     }
 
     // Default to QEMU Virt
-    Err("Unable to detect platform, using default")
+    Err(Error::NotFound("Unable to detect platform, using default"))
+}
+
+/// Detect platform from a flattened device tree blob
+///
+/// Devices missing from the tree keep their QEMU virt defaults.
+pub fn detect_platform_from_fdt(fdt: &Fdt) -> Result<PlatformInfo, Error> {
+    let root = fdt.root().ok_or(Error::NotFound("Device tree has no root node"))?;
+    let defaults = PlatformInfo::default();
+
+    let (platform_type, name) = if root.is_compatible("riscv-virtio") {
+        (PlatformType::QemuVirt, "QEMU Virt")
+    } else if root.is_compatible("sifive,hifive-unleashed-a00") {
+        (PlatformType::SiFiveUnleashed, "SiFive HiFive Unleashed")
+    } else if root.is_compatible("allwinner,sun20i-d1") {
+        (PlatformType::AllwinnerD1, "Allwinner D1")
+    } else {
+        (PlatformType::Custom, root.compatible().next().unwrap_or("Custom"))
+    };
+
+    let reg_base = |compatibles: &[&str]| {
+        compatibles.iter()
+            .find_map(|c| fdt.find_compatible(c))
+            .and_then(|node| node.first_reg())
+            .map(|region| region.address)
+    };
+
+    let timer_freq = fdt.find_node("/cpus")
+        .and_then(|cpus| cpus.property("timebase-frequency"))
+        .and_then(|prop| prop.as_u64())
+        .unwrap_or(defaults.timer_freq);
+
+    let cpu_count = match fdt.cpus().len() as u32 {
+        0 => defaults.cpu_count,
+        count => count,
+    };
+
+    let memory_size = match fdt.memory_regions().iter().map(|r| r.size).sum::<u64>() {
+        0 => defaults.memory_size,
+        size => size,
+    };
+
+    Ok(PlatformInfo {
+        platform_type,
+        name: name.to_string(),
+        version: "1.0".to_string(),
+        cpu_count,
+        memory_size,
+        uart_base: reg_base(&["ns16550a", "snps,dw-apb-uart"]).unwrap_or(defaults.uart_base),
+        clint_base: reg_base(&["riscv,clint0", "sifive,clint0"]).unwrap_or(defaults.clint_base),
+        plic_base: reg_base(&["riscv,plic0", "sifive,plic-1.0.0"]).unwrap_or(defaults.plic_base),
+        timer_freq,
+    })
 }
 
 /// Get platform information
@@ -219,7 +287,7 @@ pub fn get_timer_frequency() -> u64 {
 }
 
 /// Early platform initialization
-pub fn early_init() -> Result<(), &'static str> {
+pub fn early_init() -> Result<(), Error> {
     log::debug!("Early platform initialization");
 
     // Initialize console UART
@@ -232,7 +300,7 @@ pub fn early_init() -> Result<(), &'static str> {
 }
 
 /// Late platform initialization
-pub fn late_init() -> Result<(), &'static str> {
+pub fn late_init() -> Result<(), Error> {
     log::debug!("Late platform initialization");
 
     // Initialize CLINT
@@ -308,15 +376,20 @@ pub fn power_off() -> ! {
 pub fn panic_handler(info: &core::panic::PanicInfo) -> ! {
     use crate::arch::riscv64::cpu::halt;
 
+    crate::utils::console::enter_panic();
     log::error!("Platform panic: {}", info);
 
     // Try to write panic info to UART
     if let Ok(mut uart) = uart::Console::new() {
         let _ = uart.write_str("\n!!! PANIC !!!\n");
         let _ = uart.write_str(info.to_string().as_str());
-        let _ = uart.write_str("\nSystem halted.\n");
+        let _ = uart.write_str("\n");
     }
 
+    crate::utils::backtrace::print_backtrace();
+    crate::utils::log_ring::dump_to_console();
+    crate::utils::console::print_bytes(b"System halted.\n");
+
     // Halt the system
     halt();
 }
@@ -373,8 +446,11 @@ pub struct PlatformStats {
 
 /// Get platform statistics
 pub fn get_stats() -> PlatformStats {
-    // TODO: Collect actual platform statistics
-    PlatformStats::default()
+    // TODO: Collect the remaining platform statistics
+    PlatformStats {
+        context_switches: crate::core::sched::stats().context_switches,
+        ..PlatformStats::default()
+    }
 }
 
 #[cfg(test)]
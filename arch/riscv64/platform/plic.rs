@@ -65,7 +65,7 @@ impl Plic {
     }
 
     /// Initialize PLIC
-    pub fn init(&mut self) -> Result<(), &'static str> {
+    pub fn init(&mut self) -> Result<(), Error> {
         log::info!("Initializing PLIC at {:#x}", self.base);
 
         // Disable all interrupts for all contexts
@@ -85,13 +85,13 @@ impl Plic {
     }
 
     /// Set interrupt priority
-    pub fn set_priority(&self, interrupt_id: u32, priority: u32) -> Result<(), &'static str> {
+    pub fn set_priority(&self, interrupt_id: u32, priority: u32) -> Result<(), Error> {
         if interrupt_id == 0 || interrupt_id >= self.config.num_sources {
-            return Err("Invalid interrupt ID");
+            return Err(Error::InvalidArgument("Invalid interrupt ID"));
         }
 
         if priority > self.config.max_priority {
-            return Err("Priority too high");
+            return Err(Error::InvalidArgument("Priority too high"));
         }
 
         let addr = self.base + (plic_regs::PRIORITY_BASE as u64) + (interrupt_id as u64 * 4);
@@ -104,9 +104,9 @@ impl Plic {
     }
 
     /// Get interrupt priority
-    pub fn get_priority(&self, interrupt_id: u32) -> Result<u32, &'static str> {
+    pub fn get_priority(&self, interrupt_id: u32) -> Result<u32, Error> {
         if interrupt_id == 0 || interrupt_id >= self.config.num_sources {
-            return Err("Invalid interrupt ID");
+            return Err(Error::InvalidArgument("Invalid interrupt ID"));
         }
 
         let addr = self.base + (plic_regs::PRIORITY_BASE as u64) + (interrupt_id as u64 * 4);
@@ -117,13 +117,13 @@ impl Plic {
     }
 
     /// Enable interrupt for specific context
-    pub fn enable_interrupt(&self, context: u32, interrupt_id: u32) -> Result<(), &'static str> {
+    pub fn enable_interrupt(&self, context: u32, interrupt_id: u32) -> Result<(), Error> {
         if context >= self.config.num_contexts {
-            return Err("Invalid context");
+            return Err(Error::InvalidArgument("Invalid context"));
         }
 
         if interrupt_id == 0 || interrupt_id >= self.config.num_sources {
-            return Err("Invalid interrupt ID");
+            return Err(Error::InvalidArgument("Invalid interrupt ID"));
         }
 
         let word_offset = (interrupt_id / 32) * 4;
@@ -142,13 +142,13 @@ impl Plic {
     }
 
     /// Disable interrupt for specific context
-    pub fn disable_interrupt(&self, context: u32, interrupt_id: u32) -> Result<(), &'static str> {
+    pub fn disable_interrupt(&self, context: u32, interrupt_id: u32) -> Result<(), Error> {
         if context >= self.config.num_contexts {
-            return Err("Invalid context");
+            return Err(Error::InvalidArgument("Invalid context"));
         }
 
         if interrupt_id == 0 || interrupt_id >= self.config.num_sources {
-            return Err("Invalid interrupt ID");
+            return Err(Error::InvalidArgument("Invalid interrupt ID"));
         }
 
         let word_offset = (interrupt_id / 32) * 4;
@@ -167,9 +167,9 @@ impl Plic {
     }
 
     /// Enable all interrupts for specific context
-    pub fn enable_all_interrupts(&self, context: u32) -> Result<(), &'static str> {
+    pub fn enable_all_interrupts(&self, context: u32) -> Result<(), Error> {
         if context >= self.config.num_contexts {
-            return Err("Invalid context");
+            return Err(Error::InvalidArgument("Invalid context"));
         }
 
         let num_words = (self.config.num_sources + 31) / 32;
@@ -190,9 +190,9 @@ impl Plic {
     }
 
     /// Disable all interrupts for specific context
-    pub fn disable_all_interrupts(&self, context: u32) -> Result<(), &'static str> {
+    pub fn disable_all_interrupts(&self, context: u32) -> Result<(), Error> {
         if context >= self.config.num_contexts {
-            return Err("Invalid context");
+            return Err(Error::InvalidArgument("Invalid context"));
         }
 
         let num_words = (self.config.num_sources + 31) / 32;
@@ -210,13 +210,13 @@ impl Plic {
     }
 
     /// Set priority threshold for specific context
-    pub fn set_threshold(&self, context: u32, threshold: u32) -> Result<(), &'static str> {
+    pub fn set_threshold(&self, context: u32, threshold: u32) -> Result<(), Error> {
         if context >= self.config.num_contexts {
-            return Err("Invalid context");
+            return Err(Error::InvalidArgument("Invalid context"));
         }
 
         if threshold > self.config.max_priority {
-            return Err("Threshold too high");
+            return Err(Error::InvalidArgument("Threshold too high"));
         }
 
         let addr = self.base + (plic_regs::THRESHOLD_BASE as u64) +
@@ -230,9 +230,9 @@ impl Plic {
     }
 
     /// Get priority threshold for specific context
-    pub fn get_threshold(&self, context: u32) -> Result<u32, &'static str> {
+    pub fn get_threshold(&self, context: u32) -> Result<u32, Error> {
         if context >= self.config.num_contexts {
-            return Err("Invalid context");
+            return Err(Error::InvalidArgument("Invalid context"));
         }
 
         let addr = self.base + (plic_regs::THRESHOLD_BASE as u64) +
@@ -244,9 +244,9 @@ impl Plic {
     }
 
     /// Check if interrupt is pending
-    pub fn is_pending(&self, interrupt_id: u32) -> Result<bool, &'static str> {
+    pub fn is_pending(&self, interrupt_id: u32) -> Result<bool, Error> {
         if interrupt_id == 0 || interrupt_id >= self.config.num_sources {
-            return Err("Invalid interrupt ID");
+            return Err(Error::InvalidArgument("Invalid interrupt ID"));
         }
 
         let word_offset = interrupt_id / 32;
@@ -261,9 +261,9 @@ impl Plic {
     }
 
     /// Claim interrupt for specific context
-    pub fn claim_interrupt(&self, context: u32) -> Result<u32, &'static str> {
+    pub fn claim_interrupt(&self, context: u32) -> Result<u32, Error> {
         if context >= self.config.num_contexts {
-            return Err("Invalid context");
+            return Err(Error::InvalidArgument("Invalid context"));
         }
 
         let addr = self.base + (plic_regs::CLAIM_COMPLETE_BASE as u64) +
@@ -276,13 +276,13 @@ impl Plic {
     }
 
     /// Complete interrupt for specific context
-    pub fn complete_interrupt(&self, context: u32, interrupt_id: u32) -> Result<(), &'static str> {
+    pub fn complete_interrupt(&self, context: u32, interrupt_id: u32) -> Result<(), Error> {
         if context >= self.config.num_contexts {
-            return Err("Invalid context");
+            return Err(Error::InvalidArgument("Invalid context"));
         }
 
         if interrupt_id == 0 || interrupt_id >= self.config.num_sources {
-            return Err("Invalid interrupt ID");
+            return Err(Error::InvalidArgument("Invalid interrupt ID"));
         }
 
         let addr = self.base + (plic_regs::CLAIM_COMPLETE_BASE as u64) +
@@ -296,9 +296,9 @@ impl Plic {
     }
 
     /// Get the highest priority pending interrupt for context
-    pub fn get_highest_pending(&self, context: u32) -> Result<Option<u32>, &'static str> {
+    pub fn get_highest_pending(&self, context: u32) -> Result<Option<u32>, Error> {
         if context >= self.config.num_contexts {
-            return Err("Invalid context");
+            return Err(Error::InvalidArgument("Invalid context"));
         }
 
         let threshold = self.get_threshold(context)?;
@@ -318,7 +318,7 @@ impl Plic {
 
     /// Configure interrupt for context
     pub fn configure_interrupt(&self, context: u32, interrupt_id: u32,
-                               priority: u32, enable: bool) -> Result<(), &'static str> {
+                               priority: u32, enable: bool) -> Result<(), Error> {
         // Set priority
         self.set_priority(interrupt_id, priority)?;
 
@@ -343,7 +343,7 @@ static mut PLIC: Option<Plic> = None;
 static PLIC_INIT: spin::Once<()> = spin::Once::new();
 
 /// Initialize PLIC subsystem
-pub fn init() -> Result<(), &'static str> {
+pub fn init() -> Result<(), Error> {
     log::info!("Initializing PLIC subsystem");
 
     PLIC_INIT.call_once(|| {
@@ -377,57 +377,57 @@ pub fn get_plic_mut() -> Option<&'static mut Plic> {
 }
 
 /// Set interrupt priority
-pub fn set_priority(interrupt_id: u32, priority: u32) -> Result<(), &'static str> {
+pub fn set_priority(interrupt_id: u32, priority: u32) -> Result<(), Error> {
     if let Some(plic) = get_plic() {
         plic.set_priority(interrupt_id, priority)
     } else {
-        Err("PLIC not initialized")
+        Err(Error::NotInitialized("PLIC not initialized"))
     }
 }
 
 /// Enable interrupt for context
-pub fn enable_interrupt(context: u32, interrupt_id: u32) -> Result<(), &'static str> {
+pub fn enable_interrupt(context: u32, interrupt_id: u32) -> Result<(), Error> {
     if let Some(plic) = get_plic() {
         plic.enable_interrupt(context, interrupt_id)
     } else {
-        Err("PLIC not initialized")
+        Err(Error::NotInitialized("PLIC not initialized"))
     }
 }
 
 /// Disable interrupt for context
-pub fn disable_interrupt(context: u32, interrupt_id: u32) -> Result<(), &'static str> {
+pub fn disable_interrupt(context: u32, interrupt_id: u32) -> Result<(), Error> {
     if let Some(plic) = get_plic() {
         plic.disable_interrupt(context, interrupt_id)
     } else {
-        Err("PLIC not initialized")
+        Err(Error::NotInitialized("PLIC not initialized"))
     }
 }
 
 /// Claim interrupt for context
-pub fn claim_interrupt(context: u32) -> Result<u32, &'static str> {
+pub fn claim_interrupt(context: u32) -> Result<u32, Error> {
     if let Some(plic) = get_plic() {
         plic.claim_interrupt(context)
     } else {
-        Err("PLIC not initialized")
+        Err(Error::NotInitialized("PLIC not initialized"))
     }
 }
 
 /// Complete interrupt for context
-pub fn complete_interrupt(context: u32, interrupt_id: u32) -> Result<(), &'static str> {
+pub fn complete_interrupt(context: u32, interrupt_id: u32) -> Result<(), Error> {
     if let Some(plic) = get_plic() {
         plic.complete_interrupt(context, interrupt_id)
     } else {
-        Err("PLIC not initialized")
+        Err(Error::NotInitialized("PLIC not initialized"))
     }
 }
 
 /// Configure interrupt
 pub fn configure_interrupt(context: u32, interrupt_id: u32,
-                           priority: u32, enable: bool) -> Result<(), &'static str> {
+                           priority: u32, enable: bool) -> Result<(), Error> {
     if let Some(plic) = get_plic() {
         plic.configure_interrupt(context, interrupt_id, priority, enable)
     } else {
-        Err("PLIC not initialized")
+        Err(Error::NotInitialized("PLIC not initialized"))
     }
 }
 
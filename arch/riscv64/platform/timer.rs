@@ -41,25 +41,25 @@ impl Default for TimerConfig {
 /// Timer driver interface
 pub trait TimerDriver {
     /// Initialize timer
-    fn init(&mut self) -> Result<(), &'static str>;
+    fn init(&mut self) -> Result<(), Error>;
 
     /// Set timer period
-    fn set_period(&mut self, period_us: u64) -> Result<(), &'static str>;
+    fn set_period(&mut self, period_us: u64) -> Result<(), Error>;
 
     /// Start timer
-    fn start(&mut self) -> Result<(), &'static str>;
+    fn start(&mut self) -> Result<(), Error>;
 
     /// Stop timer
-    fn stop(&mut self) -> Result<(), &'static str>;
+    fn stop(&mut self) -> Result<(), Error>;
 
     /// Get current timer value
     fn get_time(&self) -> u64;
 
     /// Set comparator value
-    fn set_comparator(&mut self, id: u32, value: u64) -> Result<(), &'static str>;
+    fn set_comparator(&mut self, id: u32, value: u64) -> Result<(), Error>;
 
     /// Clear timer interrupt
-    fn clear_interrupt(&mut self, id: u32) -> Result<(), &'static str>;
+    fn clear_interrupt(&mut self, id: u32) -> Result<(), Error>;
 
     /// Check if interrupt is pending
     fn is_interrupt_pending(&self, id: u32) -> bool;
@@ -108,7 +108,7 @@ impl ClintTimer {
 }
 
 impl TimerDriver for ClintTimer {
-    fn init(&mut self) -> Result<(), &'static str> {
+    fn init(&mut self) -> Result<(), Error> {
         log::debug!("Initializing CLINT timer at {:#x}, frequency: {}Hz",
                    self.base, self.frequency);
 
@@ -121,19 +121,19 @@ impl TimerDriver for ClintTimer {
         Ok(())
     }
 
-    fn set_period(&mut self, period_us: u64) -> Result<(), &'static str> {
+    fn set_period(&mut self, period_us: u64) -> Result<(), Error> {
         // Convert microseconds to timer ticks
         self.period_ticks = (period_us * self.frequency) / 1_000_000;
 
         if self.period_ticks == 0 {
-            return Err("Timer period too small");
+            return Err(Error::InvalidArgument("Timer period too small"));
         }
 
         log::debug!("Timer period set to {}us ({} ticks)", period_us, self.period_ticks);
         Ok(())
     }
 
-    fn start(&mut self) -> Result<(), &'static str> {
+    fn start(&mut self) -> Result<(), Error> {
         let current_time = self.read_mtime();
         let next_time = current_time + self.period_ticks;
 
@@ -147,7 +147,7 @@ impl TimerDriver for ClintTimer {
         Ok(())
     }
 
-    fn stop(&mut self) -> Result<(), &'static str> {
+    fn stop(&mut self) -> Result<(), Error> {
         // Set comparator to maximum value to disable interrupts
         let hart_id = crate::arch::riscv64::cpu::current_cpu_id() as u32;
         self.write_mtimecmp(hart_id, u64::MAX);
@@ -160,16 +160,16 @@ impl TimerDriver for ClintTimer {
         self.read_mtime()
     }
 
-    fn set_comparator(&mut self, hart_id: u32, value: u64) -> Result<(), &'static str> {
+    fn set_comparator(&mut self, hart_id: u32, value: u64) -> Result<(), Error> {
         if hart_id >= 8 {
-            return Err("Invalid hart ID");
+            return Err(Error::InvalidArgument("Invalid hart ID"));
         }
 
         self.write_mtimecmp(hart_id, value);
         Ok(())
     }
 
-    fn clear_interrupt(&mut self, _hart_id: u32) -> Result<(), &'static str> {
+    fn clear_interrupt(&mut self, _hart_id: u32) -> Result<(), Error> {
         // CLINT timer interrupt is cleared by reading mtime and setting mtimecmp
         // This is typically handled in the interrupt handler
         Ok(())
@@ -247,24 +247,24 @@ impl HighResTimer {
 }
 
 impl TimerDriver for HighResTimer {
-    fn init(&mut self) -> Result<(), &'static str> {
+    fn init(&mut self) -> Result<(), Error> {
         log::debug!("Initializing high-resolution timer, frequency: {}Hz", self.frequency);
         Ok(())
     }
 
-    fn set_period(&mut self, _period_us: u64) -> Result<(), &'static str> {
+    fn set_period(&mut self, _period_us: u64) -> Result<(), Error> {
         // High-res timer doesn't support periodic mode
-        Err("High-res timer doesn't support periodic mode")
+        Err(Error::Unsupported("High-res timer doesn't support periodic mode"))
     }
 
-    fn start(&mut self) -> Result<(), &'static str> {
+    fn start(&mut self) -> Result<(), Error> {
         // High-res timer is always running
         Ok(())
     }
 
-    fn stop(&mut self) -> Result<(), &'static str> {
+    fn stop(&mut self) -> Result<(), Error> {
         // High-res timer cannot be stopped
-        Err("High-res timer cannot be stopped")
+        Err(Error::InvalidState("High-res timer cannot be stopped"))
     }
 
     fn get_time(&self) -> u64 {
@@ -275,12 +275,12 @@ impl TimerDriver for HighResTimer {
         }
     }
 
-    fn set_comparator(&mut self, _id: u32, _value: u64) -> Result<(), &'static str> {
-        Err("High-res timer doesn't support comparators")
+    fn set_comparator(&mut self, _id: u32, _value: u64) -> Result<(), Error> {
+        Err(Error::Unsupported("High-res timer doesn't support comparators"))
     }
 
-    fn clear_interrupt(&mut self, _id: u32) -> Result<(), &'static str> {
-        Err("High-res timer doesn't generate interrupts")
+    fn clear_interrupt(&mut self, _id: u32) -> Result<(), Error> {
+        Err(Error::Unsupported("High-res timer doesn't generate interrupts"))
     }
 
     fn is_interrupt_pending(&self, _id: u32) -> bool {
@@ -300,7 +300,7 @@ pub struct TimerManager {
 
 impl TimerManager {
     /// Create new timer manager
-    pub fn new() -> Result<Self, &'static str> {
+    pub fn new() -> Result<Self, Error> {
         let clint_base = super::get_clint_base();
         let frequency = super::get_timer_frequency();
 
@@ -315,7 +315,7 @@ impl TimerManager {
     }
 
     /// Initialize timer manager
-    pub fn init(&mut self) -> Result<(), &'static str> {
+    pub fn init(&mut self) -> Result<(), Error> {
         log::info!("Initializing timer manager");
 
         // Initialize primary timer
@@ -380,7 +380,7 @@ static mut TIMER_MANAGER: Option<TimerManager> = None;
 static TIMER_MANAGER_INIT: spin::Once<()> = spin::Once::new();
 
 /// Initialize timer subsystem (early)
-pub fn early_init() -> Result<(), &'static str> {
+pub fn early_init() -> Result<(), Error> {
     log::debug!("Early timer initialization");
 
     // Just create the timer manager without full initialization
@@ -396,7 +396,7 @@ pub fn early_init() -> Result<(), &'static str> {
 }
 
 /// Initialize timer subsystem (late)
-pub fn late_init() -> Result<(), &'static str> {
+pub fn late_init() -> Result<(), Error> {
     log::info!("Initializing platform timer subsystem");
 
     if let Some(manager) = get_manager() {
@@ -404,7 +404,7 @@ pub fn late_init() -> Result<(), &'static str> {
         // In a real implementation, we'd need proper mutable access
         log::debug!("Timer manager already exists");
     } else {
-        return Err("Timer manager not initialized");
+        return Err(Error::NotInitialized("Timer manager not initialized"));
     }
 
     log::info!("Platform timer subsystem initialized");
@@ -73,16 +73,16 @@ pub mod uart16550 {
 /// UART driver interface
 pub trait UartDriver {
     /// Initialize UART
-    fn init(&mut self) -> Result<(), &'static str>;
+    fn init(&mut self) -> Result<(), Error>;
 
     /// Write byte
-    fn write_byte(&mut self, byte: u8) -> Result<(), &'static str>;
+    fn write_byte(&mut self, byte: u8) -> Result<(), Error>;
 
     /// Read byte
     fn read_byte(&mut self) -> Option<u8>;
 
     /// Write bytes
-    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), &'static str>;
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), Error>;
 
     /// Read bytes
     fn read_bytes(&mut self, buf: &mut [u8]) -> usize;
@@ -97,7 +97,7 @@ pub trait UartDriver {
     fn flush_tx(&mut self);
 
     /// Set baud rate
-    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<(), &'static str>;
+    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<(), Error>;
 
     /// Get current configuration
     fn get_config(&self) -> &UartConfig;
@@ -145,7 +145,7 @@ impl Uart16550 {
 }
 
 impl UartDriver for Uart16550 {
-    fn init(&mut self) -> Result<(), &'static str> {
+    fn init(&mut self) -> Result<(), Error> {
         log::debug!("Initializing UART at {:#x}", self.base);
 
         // Disable interrupts
@@ -175,7 +175,7 @@ impl UartDriver for Uart16550 {
         Ok(())
     }
 
-    fn write_byte(&mut self, byte: u8) -> Result<(), &'static str> {
+    fn write_byte(&mut self, byte: u8) -> Result<(), Error> {
         self.wait_tx_ready();
         self.write_reg(uart16550::THR, byte);
         Ok(())
@@ -189,7 +189,7 @@ impl UartDriver for Uart16550 {
         }
     }
 
-    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), &'static str> {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
         for &byte in bytes {
             self.write_byte(byte)?;
         }
@@ -223,7 +223,7 @@ impl UartDriver for Uart16550 {
         self.wait_tx_ready();
     }
 
-    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<(), &'static str> {
+    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<(), Error> {
         // Save current LCR
         let lcr = self.read_reg(uart16550::LCR);
 
@@ -255,12 +255,12 @@ pub struct Console {
 
 impl Console {
     /// Create new console
-    pub fn new() -> Result<Self, &'static str> {
+    pub fn new() -> Result<Self, Error> {
         Ok(Self { uart: None })
     }
 
     /// Initialize console with specific UART driver
-    pub fn init(&mut self, uart: Box<dyn UartDriver>) -> Result<(), &'static str> {
+    pub fn init(&mut self, uart: Box<dyn UartDriver>) -> Result<(), Error> {
         // Initialize UART
         // Note: We need mutable access but this is simplified
         log::debug!("Console initialized with UART");
@@ -269,7 +269,7 @@ impl Console {
     }
 
     /// Write string to console
-    pub fn write_str(&mut self, s: &str) -> Result<(), &'static str> {
+    pub fn write_str(&mut self, s: &str) -> Result<(), Error> {
         if let Some(ref mut uart) = self.uart {
             uart.write_bytes(s.as_bytes())?;
         }
@@ -277,11 +277,11 @@ impl Console {
     }
 
     /// Write formatted string to console
-    pub fn write_fmt(&mut self, args: core::fmt::Arguments<'_>) -> Result<(), &'static str> {
+    pub fn write_fmt(&mut self, args: core::fmt::Arguments<'_>) -> Result<(), Error> {
         if let Some(ref mut uart) = self.uart {
             use core::fmt::Write;
             let mut writer = UartWriter { uart };
-            write!(writer, "{}", args).map_err(|_| "Write failed")?;
+            write!(writer, "{}", args).map_err(|_| Error::Failed("Write failed"))?;
         }
         Ok(())
     }
@@ -313,7 +313,7 @@ static mut CONSOLE: Option<Console> = None;
 static CONSOLE_INIT: spin::Once<()> = spin::Once::new();
 
 /// Initialize console (early)
-pub fn early_init() -> Result<(), &'static str> {
+pub fn early_init() -> Result<(), Error> {
     CONSOLE_INIT.call_once(|| {
         let base = super::get_uart_base();
         let config = UartConfig::default();
@@ -332,7 +332,7 @@ pub fn early_init() -> Result<(), &'static str> {
 }
 
 /// Initialize console (late)
-pub fn late_init() -> Result<(), &'static str> {
+pub fn late_init() -> Result<(), Error> {
     log::info!("Initializing platform console subsystem");
 
     // Console is already initialized in early_init
@@ -380,7 +380,7 @@ macro_rules! print {
 }
 
 /// Platform-specific UART initialization
-pub fn init() -> Result<(), &'static str> {
+pub fn init() -> Result<(), Error> {
     log::info!("Initializing platform UART");
 
     // Get platform configuration
@@ -7,7 +7,7 @@
 /// - CPU ready synchronization
 
 use crate::arch::riscv64::*;
-use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
 
 /// Boot configuration
 #[derive(Debug, Clone)]
@@ -34,6 +34,7 @@ impl Default for BootConfig {
 }
 
 /// CPU boot state
+#[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CpuBootState {
     /// CPU has not started
@@ -48,6 +49,18 @@ pub enum CpuBootState {
     Failed,
 }
 
+impl From<u8> for CpuBootState {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => CpuBootState::Starting,
+            2 => CpuBootState::Started,
+            3 => CpuBootState::Ready,
+            4 => CpuBootState::Failed,
+            _ => CpuBootState::NotStarted,
+        }
+    }
+}
+
 /// Per-CPU boot information
 #[repr(C)]
 pub struct CpuBootInfo {
@@ -55,8 +68,8 @@ pub struct CpuBootInfo {
     pub cpu_id: usize,
     /// Boot configuration
     pub config: BootConfig,
-    /// Current boot state
-    pub state: CpuBootState,
+    /// Current boot state, shared between the booting hart and the primary
+    state: AtomicU8,
     /// Error code if failed
     pub error_code: isize,
     /// Stack pointer for this CPU
@@ -69,11 +82,30 @@ impl CpuBootInfo {
         Self {
             cpu_id,
             config,
-            state: CpuBootState::NotStarted,
+            state: AtomicU8::new(CpuBootState::NotStarted as u8),
             error_code: 0,
             stack_pointer: 0,
         }
     }
+
+    /// Current boot state
+    pub fn state(&self) -> CpuBootState {
+        CpuBootState::from(self.state.load(Ordering::Acquire))
+    }
+
+    /// Set the boot state
+    pub fn set_state(&self, state: CpuBootState) {
+        self.state.store(state as u8, Ordering::Release);
+    }
+
+    /// Move the boot state from `from` to `to`
+    ///
+    /// Fails, leaving the state alone, if it is no longer `from`.
+    pub fn transition(&self, from: CpuBootState, to: CpuBootState) -> bool {
+        self.state
+            .compare_exchange(from as u8, to as u8, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+    }
 }
 
 /// Global boot information for all CPUs
@@ -107,7 +139,7 @@ pub fn get_cpu_boot_info_mut(cpu_id: usize) -> Option<&'static mut CpuBootInfo>
 }
 
 /// Initialize boot system
-pub fn init_boot_system() -> Result<(), &'static str> {
+pub fn init_boot_system() -> Result<(), Error> {
     log::info!("Initializing SMP boot system");
 
     // Initialize boot information for all CPUs
@@ -125,7 +157,7 @@ pub fn init_boot_system() -> Result<(), &'static str> {
 }
 
 /// Configure boot for secondary CPUs
-pub fn configure_secondary_boot(config: BootConfig) -> Result<(), &'static str> {
+pub fn configure_secondary_boot(config: BootConfig) -> Result<(), Error> {
     log::info!("Configuring secondary CPU boot");
 
     // Set boot configuration for all secondary CPUs
@@ -150,10 +182,10 @@ pub extern "C" fn secondary_cpu_entry() -> ! {
     let boot_info = get_cpu_boot_info(cpu_id)
         .expect("Boot information not found for this CPU");
 
-    // Mark CPU as starting
-    let info_mut = get_cpu_boot_info_mut(cpu_id)
-        .expect("Cannot get mutable boot info");
-    info_mut.state = CpuBootState::Starting;
+    // Mark CPU as starting, unless the primary already gave up on it
+    if !boot_info.transition(CpuBootState::NotStarted, CpuBootState::Starting) {
+        halt_cpu();
+    }
 
     // Set up stack
     let stack_top = boot_info.stack_pointer;
@@ -165,8 +197,10 @@ pub extern "C" fn secondary_cpu_entry() -> ! {
     // Initialize this CPU
     if let Err(e) = init_secondary_cpu(cpu_id, &boot_info.config) {
         // Mark as failed
-        info_mut.state = CpuBootState::Failed;
-        info_mut.error_code = e.as_ptr() as isize;
+        boot_info.set_state(CpuBootState::Failed);
+        if let Some(info_mut) = get_cpu_boot_info_mut(cpu_id) {
+            info_mut.error_code = e.as_ptr() as isize;
+        }
 
         log::error!("Secondary CPU {} failed to initialize: {}", cpu_id, e);
 
@@ -174,8 +208,10 @@ pub extern "C" fn secondary_cpu_entry() -> ! {
         halt_cpu();
     }
 
-    // Mark CPU as ready
-    info_mut.state = CpuBootState::Ready;
+    // Mark CPU as ready, unless it came up too late
+    if !boot_info.transition(CpuBootState::Starting, CpuBootState::Ready) {
+        halt_cpu();
+    }
 
     // Increment started CPUs counter
     CPUS_STARTED.fetch_add(1, Ordering::SeqCst);
@@ -195,7 +231,7 @@ pub extern "C" fn secondary_cpu_entry() -> ! {
 }
 
 /// Initialize a secondary CPU
-fn init_secondary_cpu(cpu_id: usize, config: &BootConfig) -> Result<(), &'static str> {
+fn init_secondary_cpu(cpu_id: usize, config: &BootConfig) -> Result<(), Error> {
     log::debug!("Initializing secondary CPU {}", cpu_id);
 
     // Initialize CPU state
@@ -212,6 +248,10 @@ fn init_secondary_cpu(cpu_id: usize, config: &BootConfig) -> Result<(), &'static
         crate::arch::riscv64::virtualization::init()?;
     }
 
+    // Arm this hart's comparator for the scheduler tick
+    crate::core::time::cpu_online()
+        .map_err(|_| Error::Failed("Failed to arm scheduler tick"))?;
+
     // Enable interrupts
     crate::arch::riscv64::interrupt::enable_external_interrupts();
 
@@ -225,21 +265,21 @@ fn init_secondary_cpu(cpu_id: usize, config: &BootConfig) -> Result<(), &'static
 }
 
 /// Start a secondary CPU
-pub fn start_secondary_cpu(cpu_id: usize) -> Result<(), &'static str> {
+pub fn start_secondary_cpu(cpu_id: usize) -> Result<(), Error> {
     if cpu_id == 0 {
-        return Err("Cannot start primary CPU");
+        return Err(Error::InvalidState("Cannot start primary CPU"));
     }
 
     if cpu_id >= MAX_CPUS {
-        return Err("Invalid CPU ID");
+        return Err(Error::InvalidArgument("Invalid CPU ID"));
     }
 
     // Check if CPU is already started
     let boot_info = get_cpu_boot_info(cpu_id)
-        .ok_or("Boot information not found")?;
+        .ok_or(Error::NotFound("Boot information not found"))?;
 
-    if boot_info.state != CpuBootState::NotStarted {
-        return Err("CPU already started or failed");
+    if boot_info.state() != CpuBootState::NotStarted {
+        return Err(Error::Busy("CPU already started or failed"));
     }
 
     log::info!("Starting secondary CPU {}", cpu_id);
@@ -258,18 +298,18 @@ pub fn start_secondary_cpu(cpu_id: usize) -> Result<(), &'static str> {
 }
 
 /// Wait for secondary CPU to be ready
-pub fn wait_for_cpu_ready(cpu_id: usize, timeout_ms: u64) -> Result<(), &'static str> {
+pub fn wait_for_cpu_ready(cpu_id: usize, timeout_ms: u64) -> Result<(), Error> {
     let start_time = read_csr!(crate::arch::riscv64::cpu::csr::TIME);
 
     loop {
         if let Some(boot_info) = get_cpu_boot_info(cpu_id) {
-            match boot_info.state {
+            match boot_info.state() {
                 CpuBootState::Ready => {
                     log::info!("CPU {} is ready", cpu_id);
                     return Ok(());
                 }
                 CpuBootState::Failed => {
-                    return Err("CPU failed to start");
+                    return Err(Error::Failed("CPU failed to start"));
                 }
                 _ => {
                     // Continue waiting
@@ -283,7 +323,7 @@ pub fn wait_for_cpu_ready(cpu_id: usize, timeout_ms: u64) -> Result<(), &'static
 
         // Simple timeout check (assuming 10MHz timer)
         if elapsed > timeout_ms * 10_000 {
-            return Err("Timeout waiting for CPU to be ready");
+            return Err(Error::Timeout("Timeout waiting for CPU to be ready"));
         }
 
         // Small delay
@@ -293,8 +333,46 @@ pub fn wait_for_cpu_ready(cpu_id: usize, timeout_ms: u64) -> Result<(), &'static
     }
 }
 
+/// Give up on a secondary CPU that did not become ready in time
+///
+/// Marks its boot slot `Failed`, so the hart halts itself if it reaches
+/// the secondary entry point late instead of joining the running system,
+/// and drops it from the online set. Fails with `Error::Busy`, changing
+/// nothing, if the hart became ready in the meantime. SBI cannot stop a
+/// remote hart, so a hart still reported running is only logged.
+/// `reset_cpu` makes the slot usable again.
+pub fn abort_cpu_boot(cpu_id: usize) -> Result<(), Error> {
+    if cpu_id == 0 || cpu_id >= MAX_CPUS {
+        return Err(Error::InvalidArgument("Invalid CPU ID"));
+    }
+
+    let info = get_cpu_boot_info(cpu_id)
+        .ok_or(Error::NotFound("Boot information not found"))?;
+    // The hart moves NotStarted -> Starting -> Ready concurrently
+    let aborted = [CpuBootState::NotStarted, CpuBootState::Starting]
+        .into_iter()
+        .any(|from| info.transition(from, CpuBootState::Failed));
+    if !aborted && info.state() == CpuBootState::Ready {
+        return Err(Error::Busy("CPU became ready"));
+    }
+
+    if crate::arch::riscv64::smp::is_cpu_online(cpu_id) {
+        crate::arch::riscv64::smp::mark_cpu_offline(cpu_id);
+    }
+
+    use crate::arch::riscv64::smp::sbi::*;
+    match sbi_hart_get_status(cpu_id) {
+        Ok(HartState::Stopped) => {}
+        Ok(state) => log::warn!("CPU {} abandoned while in hart state {:?}", cpu_id, state),
+        Err(e) => log::warn!("Cannot query hart state of CPU {}: {:?}", cpu_id, e),
+    }
+
+    log::info!("CPU {} boot aborted", cpu_id);
+    Ok(())
+}
+
 /// Start all secondary CPUs
-pub fn start_all_secondary_cpus() -> Result<usize, &'static str> {
+pub fn start_all_secondary_cpus() -> Result<usize, Error> {
     log::info!("Starting all secondary CPUs");
 
     let mut started_count = 0;
@@ -316,7 +394,7 @@ pub fn start_all_secondary_cpus() -> Result<usize, &'static str> {
 }
 
 /// Wait for all secondary CPUs to be ready
-pub fn wait_for_all_cpus_ready(timeout_ms: u64) -> Result<usize, &'static str> {
+pub fn wait_for_all_cpus_ready(timeout_ms: u64) -> Result<usize, Error> {
     log::info!("Waiting for all secondary CPUs to be ready");
 
     let mut ready_count = 0;
@@ -350,7 +428,7 @@ pub fn is_primary_ready() -> bool {
 /// Check if a CPU is ready
 pub fn is_cpu_ready(cpu_id: usize) -> bool {
     if let Some(boot_info) = get_cpu_boot_info(cpu_id) {
-        boot_info.state == CpuBootState::Ready
+        boot_info.state() == CpuBootState::Ready
     } else {
         false
     }
@@ -358,20 +436,20 @@ pub fn is_cpu_ready(cpu_id: usize) -> bool {
 
 /// Get CPU boot state
 pub fn get_cpu_boot_state(cpu_id: usize) -> Option<CpuBootState> {
-    get_cpu_boot_info(cpu_id).map(|info| info.state)
+    get_cpu_boot_info(cpu_id).map(|info| info.state())
 }
 
 /// Reset a CPU
-pub fn reset_cpu(cpu_id: usize) -> Result<(), &'static str> {
+pub fn reset_cpu(cpu_id: usize) -> Result<(), Error> {
     if cpu_id >= MAX_CPUS {
-        return Err("Invalid CPU ID");
+        return Err(Error::InvalidArgument("Invalid CPU ID"));
     }
 
     log::info!("Resetting CPU {}", cpu_id);
 
     // Reset boot state
     if let Some(info) = get_cpu_boot_info_mut(cpu_id) {
-        info.state = CpuBootState::NotStarted;
+        info.set_state(CpuBootState::NotStarted);
         info.error_code = 0;
     }
 
@@ -397,14 +475,14 @@ pub fn halt_cpu() -> ! {
 }
 
 /// Power off a CPU
-pub fn poweroff_cpu(cpu_id: usize) -> Result<(), &'static str> {
+pub fn poweroff_cpu(cpu_id: usize) -> Result<(), Error> {
     if cpu_id >= MAX_CPUS {
-        return Err("Invalid CPU ID");
+        return Err(Error::InvalidArgument("Invalid CPU ID"));
     }
 
     if cpu_id == current_cpu_id() {
         // Cannot power off self
-        return Err("Cannot power off current CPU");
+        return Err(Error::InvalidState("Cannot power off current CPU"));
     }
 
     log::info!("Powering off CPU {}", cpu_id);
@@ -414,8 +492,8 @@ pub fn poweroff_cpu(cpu_id: usize) -> Result<(), &'static str> {
     sbi_hart_stop(cpu_id)?;
 
     // Mark CPU as not started
-    if let Some(info) = get_cpu_boot_info_mut(cpu_id) {
-        info.state = CpuBootState::NotStarted;
+    if let Some(info) = get_cpu_boot_info(cpu_id) {
+        info.set_state(CpuBootState::NotStarted);
     }
 
     // Decrease started CPUs counter
@@ -590,18 +668,18 @@ pub mod hotplug {
     }
 
     /// Add a CPU to the system with enhanced features
-    pub fn cpu_add(cpu_id: usize, config: BootConfig) -> Result<HotplugRequest, &'static str> {
+    pub fn cpu_add(cpu_id: usize, config: BootConfig) -> Result<HotplugRequest, Error> {
         if cpu_id >= MAX_CPUS {
-            return Err("Invalid CPU ID");
+            return Err(Error::InvalidArgument("Invalid CPU ID"));
         }
 
         if cpu_id == 0 {
-            return Err("Cannot add primary CPU");
+            return Err(Error::InvalidState("Cannot add primary CPU"));
         }
 
         // Check if CPU is already online
         if crate::arch::riscv64::smp::is_cpu_online(cpu_id) {
-            return Err("CPU already online");
+            return Err(Error::Busy("CPU already online"));
         }
 
         log::info!("Adding CPU {} to system", cpu_id);
@@ -613,7 +691,7 @@ pub mod hotplug {
         // Initialize CPU boot information
         if let Some(info) = get_cpu_boot_info_mut(cpu_id) {
             info.config = config.clone();
-            info.state = CpuBootState::NotStarted;
+            info.set_state(CpuBootState::NotStarted);
             info.error_code = 0;
             info.stack_pointer = config.stack_top - (cpu_id * 64 * 1024);
         }
@@ -651,17 +729,17 @@ pub mod hotplug {
     }
 
     /// Remove a CPU from the system with enhanced features
-    pub fn cpu_remove(cpu_id: usize) -> Result<HotplugRequest, &'static str> {
+    pub fn cpu_remove(cpu_id: usize) -> Result<HotplugRequest, Error> {
         if cpu_id == 0 {
-            return Err("Cannot remove primary CPU");
+            return Err(Error::InvalidState("Cannot remove primary CPU"));
         }
 
         if cpu_id >= MAX_CPUS {
-            return Err("Invalid CPU ID");
+            return Err(Error::InvalidArgument("Invalid CPU ID"));
         }
 
         if !crate::arch::riscv64::smp::is_cpu_online(cpu_id) {
-            return Err("CPU not online");
+            return Err(Error::InvalidState("CPU not online"));
         }
 
         log::info!("Removing CPU {} from system", cpu_id);
@@ -672,7 +750,7 @@ pub mod hotplug {
         if !cpu_can_remove_safely(cpu_id) {
             request.complete_failure(-1);
             HOTPLUG_STATS.record_failure();
-            return Err("CPU cannot be safely removed (currently in use)");
+            return Err(Error::Busy("CPU cannot be safely removed (currently in use)"));
         }
 
         // Gracefully shutdown the CPU
@@ -698,9 +776,9 @@ pub mod hotplug {
     }
 
     /// Reset a CPU with enhanced features
-    pub fn cpu_reset(cpu_id: usize) -> Result<HotplugRequest, &'static str> {
+    pub fn cpu_reset(cpu_id: usize) -> Result<HotplugRequest, Error> {
         if cpu_id >= MAX_CPUS {
-            return Err("Invalid CPU ID");
+            return Err(Error::InvalidArgument("Invalid CPU ID"));
         }
 
         log::info!("Resetting CPU {}", cpu_id);
@@ -743,17 +821,17 @@ pub mod hotplug {
     }
 
     /// Suspend a CPU
-    pub fn cpu_suspend(cpu_id: usize) -> Result<HotplugRequest, &'static str> {
+    pub fn cpu_suspend(cpu_id: usize) -> Result<HotplugRequest, Error> {
         if cpu_id >= MAX_CPUS {
-            return Err("Invalid CPU ID");
+            return Err(Error::InvalidArgument("Invalid CPU ID"));
         }
 
         if cpu_id == 0 {
-            return Err("Cannot suspend primary CPU");
+            return Err(Error::InvalidState("Cannot suspend primary CPU"));
         }
 
         if !crate::arch::riscv64::smp::is_cpu_online(cpu_id) {
-            return Err("CPU not online");
+            return Err(Error::InvalidState("CPU not online"));
         }
 
         log::info!("Suspending CPU {}", cpu_id);
@@ -780,9 +858,9 @@ pub mod hotplug {
     }
 
     /// Resume a suspended CPU
-    pub fn cpu_resume(cpu_id: usize) -> Result<HotplugRequest, &'static str> {
+    pub fn cpu_resume(cpu_id: usize) -> Result<HotplugRequest, Error> {
         if cpu_id >= MAX_CPUS {
-            return Err("Invalid CPU ID");
+            return Err(Error::InvalidArgument("Invalid CPU ID"));
         }
 
         log::info!("Resuming CPU {}", cpu_id);
@@ -821,7 +899,7 @@ pub mod hotplug {
         }
 
         if let Some(info) = get_cpu_boot_info(cpu_id) {
-            info.state == CpuBootState::NotStarted || info.state == CpuBootState::Failed
+            matches!(info.state(), CpuBootState::NotStarted | CpuBootState::Failed)
         } else {
             false
         }
@@ -843,7 +921,7 @@ pub mod hotplug {
     }
 
     /// Gracefully shutdown a CPU
-    fn graceful_shutdown_cpu(cpu_id: usize) -> Result<(), &'static str> {
+    fn graceful_shutdown_cpu(cpu_id: usize) -> Result<(), Error> {
         log::debug!("Gracefully shutting down CPU {}", cpu_id);
 
         // Send shutdown IPI
@@ -855,7 +933,7 @@ pub mod hotplug {
 
         loop {
             if let Some(info) = get_cpu_boot_info(cpu_id) {
-                if info.state == CpuBootState::NotStarted {
+                if info.state() == CpuBootState::NotStarted {
                     return Ok(());
                 }
             }
@@ -910,28 +988,28 @@ pub mod hotplug {
     }
 
     /// Validate CPU hotplug request
-    pub fn validate_hotplug_request(request: &HotplugRequest) -> Result<(), &'static str> {
+    pub fn validate_hotplug_request(request: &HotplugRequest) -> Result<(), Error> {
         // Validate CPU ID
         if request.cpu_id >= MAX_CPUS {
-            return Err("Invalid CPU ID");
+            return Err(Error::InvalidArgument("Invalid CPU ID"));
         }
 
         // Validate operation based on current state
         match request.operation {
             HotplugOp::Add => {
                 if crate::arch::riscv64::smp::is_cpu_online(request.cpu_id) {
-                    return Err("CPU already online");
+                    return Err(Error::Busy("CPU already online"));
                 }
                 if request.config.is_none() {
-                    return Err("Boot configuration required for add operation");
+                    return Err(Error::InvalidArgument("Boot configuration required for add operation"));
                 }
             }
             HotplugOp::Remove => {
                 if !crate::arch::riscv64::smp::is_cpu_online(request.cpu_id) {
-                    return Err("CPU not online");
+                    return Err(Error::InvalidState("CPU not online"));
                 }
                 if request.cpu_id == 0 {
-                    return Err("Cannot remove primary CPU");
+                    return Err(Error::InvalidState("Cannot remove primary CPU"));
                 }
             }
             HotplugOp::Reset => {
@@ -939,10 +1017,10 @@ pub mod hotplug {
             }
             HotplugOp::Suspend => {
                 if !crate::arch::riscv64::smp::is_cpu_online(request.cpu_id) {
-                    return Err("CPU not online");
+                    return Err(Error::InvalidState("CPU not online"));
                 }
                 if request.cpu_id == 0 {
-                    return Err("Cannot suspend primary CPU");
+                    return Err(Error::InvalidState("Cannot suspend primary CPU"));
                 }
             }
             HotplugOp::Resume => {
@@ -977,16 +1055,21 @@ mod tests {
         let info = CpuBootInfo::new(1, config);
         assert_eq!(info.cpu_id, 1);
         assert_eq!(info.config.entry_point, 0x80000000);
-        assert_eq!(info.state, CpuBootState::NotStarted);
+        assert_eq!(info.state(), CpuBootState::NotStarted);
     }
 
     #[test]
     fn test_cpu_boot_state() {
-        let mut info = CpuBootInfo::new(1, BootConfig::default());
+        let info = CpuBootInfo::new(1, BootConfig::default());
+
+        assert_eq!(info.state(), CpuBootState::NotStarted);
 
-        assert_eq!(info.state, CpuBootState::NotStarted);
+        info.set_state(CpuBootState::Ready);
+        assert_eq!(info.state(), CpuBootState::Ready);
 
-        info.state = CpuBootState::Ready;
-        assert_eq!(info.state, CpuBootState::Ready);
+        // A hart that reached Ready cannot be failed behind its back
+        assert!(!info.transition(CpuBootState::Starting, CpuBootState::Failed));
+        assert!(info.transition(CpuBootState::Ready, CpuBootState::Failed));
+        assert_eq!(info.state(), CpuBootState::Failed);
     }
 }
\ No newline at end of file
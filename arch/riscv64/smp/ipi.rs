@@ -7,8 +7,11 @@
 /// - Cross-CPU signaling
 
 use crate::arch::riscv64::*;
+use crate::core::sync::SpinLock;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
 use bitflags::bitflags;
-use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 
 /// IPI types
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -64,7 +67,7 @@ bitflags! {
 }
 
 /// IPI handler function type
-pub type IpiHandler = fn(cpu_id: usize, data: u64) -> Result<(), &'static str>;
+pub type IpiHandler = fn(cpu_id: usize, data: u64) -> Result<(), Error>;
 
 /// Per-CPU IPI state
 #[derive(Debug)]
@@ -199,7 +202,7 @@ static mut CPU_IPI_STATES: [CpuIpiState; MAX_CPUS] = [CpuIpiState::new(); MAX_CP
 static IPI_MASK_ALL: AtomicU32 = AtomicU32::new(0);
 
 /// Initialize IPI subsystem
-pub fn init() -> Result<(), &'static str> {
+pub fn init() -> Result<(), Error> {
     log::info!("Initializing IPI subsystem");
 
     let current_cpu = crate::arch::riscv64::cpu::current_cpu_id();
@@ -239,13 +242,13 @@ pub fn get_cpu_ipi_state_mut(cpu_id: usize) -> Option<&'static mut CpuIpiState>
 }
 
 /// Send an IPI to a specific CPU
-pub fn send_ipi(target_cpu: usize, ipi_type: IpiType, data: u64) -> Result<(), &'static str> {
+pub fn send_ipi(target_cpu: usize, ipi_type: IpiType, data: u64) -> Result<(), Error> {
     if target_cpu >= MAX_CPUS {
-        return Err("Invalid target CPU ID");
+        return Err(Error::InvalidArgument("Invalid target CPU ID"));
     }
 
     let ipi_state = get_cpu_ipi_state(target_cpu)
-        .ok_or("Target CPU IPI state not found")?;
+        .ok_or(Error::NotFound("Target CPU IPI state not found"))?;
 
     // Set IPI data and mark as pending
     ipi_state.set_data(ipi_type, data);
@@ -266,7 +269,7 @@ pub fn send_ipi_to_many(
     target_cpus: &[usize],
     ipi_type: IpiType,
     data: u64,
-) -> Result<(), &'static str> {
+) -> Result<(), Error> {
     let mut errors = Vec::new();
 
     for &cpu_id in target_cpus {
@@ -279,12 +282,12 @@ pub fn send_ipi_to_many(
         Ok(())
     } else {
         log::error!("Failed to send IPI to some CPUs: {:?}", errors);
-        Err("Failed to send IPI to some CPUs")
+        Err(Error::Failed("Failed to send IPI to some CPUs"))
     }
 }
 
 /// Broadcast IPI to all CPUs
-pub fn broadcast_ipi(ipi_type: IpiType, data: u64, exclude_self: bool) -> Result<(), &'static str> {
+pub fn broadcast_ipi(ipi_type: IpiType, data: u64, exclude_self: bool) -> Result<(), Error> {
     let current_cpu = crate::arch::riscv64::cpu::current_cpu_id();
     let mut target_cpus = Vec::new();
 
@@ -298,7 +301,7 @@ pub fn broadcast_ipi(ipi_type: IpiType, data: u64, exclude_self: bool) -> Result
 }
 
 /// Send reschedule IPI to a CPU
-pub fn send_reschedule_ipi(target_cpu: usize) -> Result<(), &'static str> {
+pub fn send_reschedule_ipi(target_cpu: usize) -> Result<(), Error> {
     send_ipi(target_cpu, IpiType::Reschedule, 0)
 }
 
@@ -307,7 +310,7 @@ pub fn send_tlb_shootdown_ipi(
     target_cpus: &[usize],
     addr: usize,
     asid: u16,
-) -> Result<(), &'static str> {
+) -> Result<(), Error> {
     let data = ((asid as u64) << 48) | (addr as u64);
     send_ipi_to_many(target_cpus, IpiType::TlbShootdown, data)
 }
@@ -317,26 +320,175 @@ pub fn send_function_call_ipi(
     target_cpu: usize,
     func: usize,
     arg: usize,
-) -> Result<(), &'static str> {
+) -> Result<(), Error> {
     let data = ((arg as u64) << 32) | (func as u64);
     send_ipi(target_cpu, IpiType::FunctionCall, data)
 }
 
+/// Function run on another CPU by `call_on_cpu`
+pub type RemoteFn = fn(arg: usize);
+
+/// A function call queued for another CPU
+pub struct RemoteCall {
+    /// Function to run
+    func: RemoteFn,
+    /// Argument passed to `func`
+    arg: usize,
+    /// Set by the target CPU once `func` has returned
+    done: AtomicBool,
+}
+
+impl RemoteCall {
+    /// Create a call that has not run yet
+    pub fn new(func: RemoteFn, arg: usize) -> Self {
+        Self { func, arg, done: AtomicBool::new(false) }
+    }
+
+    /// Run the function and signal completion
+    pub fn run(&self) {
+        (self.func)(self.arg);
+        self.done.store(true, Ordering::Release);
+    }
+
+    /// Check whether the target CPU has finished the call
+    pub fn is_done(&self) -> bool {
+        self.done.load(Ordering::Acquire)
+    }
+
+    /// Spin until the call is done, running `relax` between checks
+    pub fn wait(&self, mut relax: impl FnMut()) {
+        while !self.is_done() {
+            relax();
+        }
+    }
+}
+
+/// Calls waiting to run on one CPU
+pub struct CallQueue {
+    pending: SpinLock<VecDeque<Arc<RemoteCall>>>,
+}
+
+impl CallQueue {
+    /// Create an empty queue
+    pub const fn new() -> Self {
+        Self { pending: SpinLock::new(VecDeque::new()) }
+    }
+
+    /// Queue a call
+    pub fn push(&self, call: Arc<RemoteCall>) {
+        self.pending.lock().push_back(call);
+    }
+
+    /// Run all queued calls in order, returning how many ran
+    ///
+    /// The lock is not held while a call runs, so a call may queue further
+    /// calls.
+    pub fn run_pending(&self) -> usize {
+        let mut count = 0;
+        loop {
+            let call = self.pending.lock().pop_front();
+            match call {
+                Some(call) => {
+                    call.run();
+                    count += 1;
+                }
+                None => return count,
+            }
+        }
+    }
+}
+
+impl Default for CallQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Per-CPU queues of calls delivered by the function call IPI
+static CALL_QUEUES: [CallQueue; MAX_CPUS] = [const { CallQueue::new() }; MAX_CPUS];
+
+/// Wait for a remote call to finish
+///
+/// Calls queued for this CPU keep running while it waits, so two CPUs
+/// waiting on calls to each other do not deadlock.
+fn wait_for_call(call: &RemoteCall) {
+    let current_cpu = crate::arch::riscv64::cpu::current_cpu_id();
+    call.wait(|| {
+        CALL_QUEUES[current_cpu].run_pending();
+        core::hint::spin_loop();
+    });
+}
+
+/// Queue a call for `cpu` and send it the function call IPI
+fn queue_remote_call(cpu: usize, func: RemoteFn, arg: usize) -> Result<Arc<RemoteCall>, Error> {
+    let call = Arc::new(RemoteCall::new(func, arg));
+    CALL_QUEUES[cpu].push(call.clone());
+    send_ipi(cpu, IpiType::FunctionCall, 0)?;
+    Ok(call)
+}
+
+/// Run `func(arg)` on `cpu`
+///
+/// With `wait`, returns only after the function has returned on the target
+/// CPU. A call targeting the current CPU runs directly; one targeting an
+/// offline CPU fails, as nothing would ever run it.
+pub fn call_on_cpu(cpu: usize, func: RemoteFn, arg: usize, wait: bool) -> Result<(), Error> {
+    if cpu >= MAX_CPUS {
+        return Err(Error::InvalidArgument("Invalid target CPU ID"));
+    }
+    if cpu == crate::arch::riscv64::cpu::current_cpu_id() {
+        func(arg);
+        return Ok(());
+    }
+    if !crate::arch::riscv64::smp::is_cpu_online(cpu) {
+        return Err(Error::InvalidState("Target CPU is offline"));
+    }
+
+    let call = queue_remote_call(cpu, func, arg)?;
+    if wait {
+        wait_for_call(&call);
+    }
+    Ok(())
+}
+
+/// Run `func(arg)` on every online CPU, including the current one
+///
+/// With `wait`, returns only after the function has returned on all of
+/// them.
+pub fn call_on_all(func: RemoteFn, arg: usize, wait: bool) -> Result<(), Error> {
+    let current_cpu = crate::arch::riscv64::cpu::current_cpu_id();
+    let mut calls = Vec::new();
+    for cpu in 0..MAX_CPUS {
+        if cpu != current_cpu && crate::arch::riscv64::smp::is_cpu_online(cpu) {
+            calls.push(queue_remote_call(cpu, func, arg)?);
+        }
+    }
+
+    func(arg);
+
+    if wait {
+        for call in &calls {
+            wait_for_call(call);
+        }
+    }
+    Ok(())
+}
+
 /// Send stop IPI to a CPU
-pub fn send_stop_ipi(target_cpu: usize) -> Result<(), &'static str> {
+pub fn send_stop_ipi(target_cpu: usize) -> Result<(), Error> {
     send_ipi(target_cpu, IpiType::Stop, 0)
 }
 
 /// Send wake up IPI to a CPU
-pub fn send_wake_up_ipi(target_cpu: usize) -> Result<(), &'static str> {
+pub fn send_wake_up_ipi(target_cpu: usize) -> Result<(), Error> {
     send_ipi(target_cpu, IpiType::WakeUp, 0)
 }
 
 /// Handle incoming IPI
-pub fn handle_ipi() -> Result<(), &'static str> {
+pub fn handle_ipi() -> Result<(), Error> {
     let current_cpu = crate::arch::riscv64::cpu::current_cpu_id();
     let ipi_state = get_cpu_ipi_state(current_cpu)
-        .ok_or("CPU IPI state not found")?;
+        .ok_or(Error::NotFound("CPU IPI state not found"))?;
 
     // Get all pending IPIs
     let pending_ipis = ipi_state.get_pending_ipis();
@@ -402,6 +554,7 @@ fn setup_default_handlers() {
     // Register default handlers for common IPI types
     register_ipi_handler(IpiType::Reschedule, reschedule_ipi_handler);
     register_ipi_handler(IpiType::TlbShootdown, tlb_shootdown_ipi_handler);
+    register_ipi_handler(IpiType::FunctionCall, function_call_ipi_handler);
     register_ipi_handler(IpiType::Stop, stop_ipi_handler);
     register_ipi_handler(IpiType::WakeUp, wake_up_ipi_handler);
 
@@ -428,7 +581,7 @@ pub fn register_ipi_handler(ipi_type: IpiType, handler: IpiHandler) {
 }
 
 /// Reschedule IPI handler
-fn reschedule_ipi_handler(_cpu_id: usize, _data: u64) -> Result<(), &'static str> {
+fn reschedule_ipi_handler(_cpu_id: usize, _data: u64) -> Result<(), Error> {
     log::debug!("Received reschedule IPI");
 
     // Trigger scheduler
@@ -439,12 +592,15 @@ fn reschedule_ipi_handler(_cpu_id: usize, _data: u64) -> Result<(), &'static str
 }
 
 /// TLB shootdown IPI handler
-fn tlb_shootdown_ipi_handler(_cpu_id: usize, data: u64) -> Result<(), &'static str> {
+fn tlb_shootdown_ipi_handler(cpu_id: usize, data: u64) -> Result<(), Error> {
     let addr = (data & 0xFFFFFFFF) as usize;
     let asid = ((data >> 48) & 0xFFFF) as u16;
 
     log::debug!("TLB shootdown IPI: addr={:#x}, asid={}", addr, asid);
 
+    // Stage-2 invalidations queued by `GStageManager::unmap`
+    crate::core::mm::gstage::handle_tlb_shootdown(cpu_id);
+
     // Invalidate TLB entries
     if addr == 0 {
         // Invalidate all TLB entries for ASID
@@ -457,8 +613,15 @@ fn tlb_shootdown_ipi_handler(_cpu_id: usize, data: u64) -> Result<(), &'static s
     Ok(())
 }
 
+/// Function call IPI handler
+fn function_call_ipi_handler(cpu_id: usize, _data: u64) -> Result<(), Error> {
+    let count = CALL_QUEUES[cpu_id].run_pending();
+    log::debug!("CPU {} ran {} remote calls", cpu_id, count);
+    Ok(())
+}
+
 /// Stop IPI handler
-fn stop_ipi_handler(cpu_id: usize, _data: u64) -> Result<(), &'static str> {
+fn stop_ipi_handler(cpu_id: usize, _data: u64) -> Result<(), Error> {
     log::info!("CPU {} received stop IPI, halting", cpu_id);
 
     // Halt the CPU
@@ -466,7 +629,7 @@ fn stop_ipi_handler(cpu_id: usize, _data: u64) -> Result<(), &'static str> {
 }
 
 /// Wake up IPI handler
-fn wake_up_ipi_handler(cpu_id: usize, _data: u64) -> Result<(), &'static str> {
+fn wake_up_ipi_handler(cpu_id: usize, _data: u64) -> Result<(), Error> {
     log::debug!("CPU {} received wake up IPI", cpu_id);
 
     // Wake up the CPU
@@ -477,7 +640,7 @@ fn wake_up_ipi_handler(cpu_id: usize, _data: u64) -> Result<(), &'static str> {
 }
 
 /// CPU suspend IPI handler
-fn suspend_ipi_handler(cpu_id: usize, _data: u64) -> Result<(), &'static str> {
+fn suspend_ipi_handler(cpu_id: usize, _data: u64) -> Result<(), Error> {
     log::info!("CPU {} received suspend IPI", cpu_id);
 
     // Save current CPU state
@@ -514,7 +677,7 @@ fn suspend_ipi_handler(cpu_id: usize, _data: u64) -> Result<(), &'static str> {
 }
 
 /// CPU resume IPI handler
-fn resume_ipi_handler(cpu_id: usize, _data: u64) -> Result<(), &'static str> {
+fn resume_ipi_handler(cpu_id: usize, _data: u64) -> Result<(), Error> {
     log::info!("CPU {} received resume IPI", cpu_id);
 
     // This handler is mainly for cleanup after resume
@@ -524,7 +687,7 @@ fn resume_ipi_handler(cpu_id: usize, _data: u64) -> Result<(), &'static str> {
 }
 
 /// CPU shutdown IPI handler
-fn shutdown_ipi_handler(cpu_id: usize, _data: u64) -> Result<(), &'static str> {
+fn shutdown_ipi_handler(cpu_id: usize, _data: u64) -> Result<(), Error> {
     log::info!("CPU {} received shutdown IPI", cpu_id);
 
     // Mark CPU as offline in SMP subsystem
@@ -540,7 +703,7 @@ fn shutdown_ipi_handler(cpu_id: usize, _data: u64) -> Result<(), &'static str> {
 }
 
 /// CPU add IPI handler
-fn add_cpu_ipi_handler(cpu_id: usize, data: u64) -> Result<(), &'static str> {
+fn add_cpu_ipi_handler(cpu_id: usize, data: u64) -> Result<(), Error> {
     let entry_point = (data & 0xFFFFFFFF) as usize;
     let stack_top = ((data >> 32) & 0xFFFFFFFF) as usize;
 
@@ -554,7 +717,7 @@ fn add_cpu_ipi_handler(cpu_id: usize, data: u64) -> Result<(), &'static str> {
 }
 
 /// CPU remove IPI handler
-fn remove_cpu_ipi_handler(cpu_id: usize, _data: u64) -> Result<(), &'static str> {
+fn remove_cpu_ipi_handler(cpu_id: usize, _data: u64) -> Result<(), Error> {
     log::info!("CPU {} received remove IPI", cpu_id);
 
     // This is mainly for cleanup
@@ -564,7 +727,7 @@ fn remove_cpu_ipi_handler(cpu_id: usize, _data: u64) -> Result<(), &'static str>
 }
 
 /// VM migration IPI handler
-fn vm_migrate_ipi_handler(cpu_id: usize, data: u64) -> Result<(), &'static str> {
+fn vm_migrate_ipi_handler(cpu_id: usize, data: u64) -> Result<(), Error> {
     let target_cpu = (data & 0xFFFFFFFF) as usize;
     let vm_id = ((data >> 32) & 0xFFFF) as u16;
     let vcpu_id = ((data >> 48) & 0xFFFF) as u16;
@@ -591,7 +754,7 @@ fn vm_migrate_ipi_handler(cpu_id: usize, data: u64) -> Result<(), &'static str>
 }
 
 /// Memory pressure IPI handler
-fn memory_pressure_ipi_handler(cpu_id: usize, data: u64) -> Result<(), &'static str> {
+fn memory_pressure_ipi_handler(cpu_id: usize, data: u64) -> Result<(), Error> {
     let pressure_level = (data & 0xFF) as u8;
     let reclaim_target = ((data >> 8) & 0xFFFFFFFF) as usize;
 
@@ -624,9 +787,9 @@ fn memory_pressure_ipi_handler(cpu_id: usize, data: u64) -> Result<(), &'static
 }
 
 /// Get IPI statistics
-pub fn get_ipi_stats(cpu_id: usize) -> Result<Vec<(IpiType, u64)>, &'static str> {
+pub fn get_ipi_stats(cpu_id: usize) -> Result<Vec<(IpiType, u64)>, Error> {
     let ipi_state = get_cpu_ipi_state(cpu_id)
-        .ok_or("CPU IPI state not found")?;
+        .ok_or(Error::NotFound("CPU IPI state not found"))?;
 
     let mut stats = Vec::new();
 
@@ -643,9 +806,9 @@ pub fn get_ipi_stats(cpu_id: usize) -> Result<Vec<(IpiType, u64)>, &'static str>
 }
 
 /// Clear IPI statistics
-pub fn clear_ipi_stats(cpu_id: usize) -> Result<(), &'static str> {
+pub fn clear_ipi_stats(cpu_id: usize) -> Result<(), Error> {
     let ipi_state = get_cpu_ipi_state(cpu_id)
-        .ok_or("CPU IPI state not found")?;
+        .ok_or(Error::NotFound("CPU IPI state not found"))?;
 
     for i in 0..IpiType::Max as usize {
         ipi_state.ipi_counts[i].store(0, Ordering::SeqCst);
@@ -687,4 +850,34 @@ mod tests {
         assert!(flags.contains(IpiFlags::HIGH_PRIORITY));
         assert!(flags.contains(IpiFlags::ONE_SHOT));
     }
-}
\ No newline at end of file
+
+    static REMOTE_RUNS: AtomicU32 = AtomicU32::new(0);
+
+    fn count_run(arg: usize) {
+        REMOTE_RUNS.fetch_add(arg as u32, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn test_waited_call_returns_after_remote_completion() {
+        let queue = CallQueue::new();
+        let call = Arc::new(RemoteCall::new(count_run, 1));
+        queue.push(call.clone());
+
+        // The "remote CPU" only drains its queue on the third spin; the
+        // waiter must not return before then
+        let mut spins = 0;
+        call.wait(|| {
+            spins += 1;
+            assert!(!call.is_done());
+            assert_eq!(REMOTE_RUNS.load(Ordering::SeqCst), 0);
+            if spins == 3 {
+                assert_eq!(queue.run_pending(), 1);
+            }
+        });
+
+        assert_eq!(spins, 3);
+        assert!(call.is_done());
+        assert_eq!(REMOTE_RUNS.load(Ordering::SeqCst), 1);
+        assert_eq!(queue.run_pending(), 0);
+    }
+}
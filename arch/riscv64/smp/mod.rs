@@ -16,7 +16,10 @@ pub use ipi::*;
 pub use scheduler::*;
 
 use crate::arch::riscv64::*;
-use core::sync::atomic::{AtomicUsize, AtomicU32, AtomicU64, Ordering};
+use crate::core::sync::{Once, SpinLock};
+use crate::core::sync::spinlock::SpinLockGuard;
+use crate::libs::cpumask::CpuMask;
+use core::sync::atomic::{AtomicUsize, AtomicU8, AtomicU32, AtomicU64, Ordering};
 use alloc::vec::Vec;
 
 /// SMP configuration
@@ -69,27 +72,50 @@ pub enum SmpState {
     Stopped,
 }
 
+impl SmpState {
+    /// Decode a state stored in `SMP_STATE`
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => SmpState::Initialized,
+            2 => SmpState::Running,
+            3 => SmpState::Stopped,
+            _ => SmpState::Uninitialized,
+        }
+    }
+}
+
 /// Global SMP state
-static mut SMP_STATE: SmpState = SmpState::Uninitialized;
+///
+/// Stored with release ordering once the state it describes is set up, so
+/// a CPU that reads `Running` also sees the online CPUs and load balancer.
+static SMP_STATE: AtomicU8 = AtomicU8::new(SmpState::Uninitialized as u8);
 
 /// SMP configuration
-static mut SMP_CONFIG: Option<SmpConfig> = None;
+static SMP_CONFIG: SpinLock<Option<SmpConfig>> = SpinLock::new(None);
 
 /// Number of online CPUs
-static mut ONLINE_CPUS: AtomicUsize = AtomicUsize::new(0);
+static ONLINE_CPUS: AtomicUsize = AtomicUsize::new(0);
 
 /// CPU mask of online CPUs
-static mut ONLINE_CPU_MASK: AtomicUsize = AtomicUsize::new(0);
+static ONLINE_CPU_MASK: AtomicUsize = AtomicUsize::new(0);
 
-/// Load balancer instance
-static mut LOAD_BALANCER: Option<Box<dyn LoadBalancer>> = None;
+/// Number of CPUs per-CPU state is sized for
+///
+/// `MAX_CPUS` until SMP initialization records the detected count.
+static NR_CPUS: AtomicUsize = AtomicUsize::new(MAX_CPUS);
+
+/// Load balancer instance, chosen once at SMP initialization
+static LOAD_BALANCER: Once<Box<dyn LoadBalancer>> = Once::new();
 
 /// Initialize SMP subsystem
-pub fn init() -> Result<(), &'static str> {
+pub fn init() -> Result<(), Error> {
     log::info!("Initializing RISC-V SMP subsystem");
 
-    // Set default configuration
-    let config = SmpConfig::default();
+    // Size per-CPU state for the CPUs the platform reports
+    let mut config = SmpConfig::default();
+    if let Some(info) = crate::arch::riscv64::platform::get_platform_info() {
+        config.max_cpus = info.cpu_count as usize;
+    }
     init_with_config(config)?;
 
     log::info!("RISC-V SMP subsystem initialized successfully");
@@ -97,14 +123,13 @@ pub fn init() -> Result<(), &'static str> {
 }
 
 /// Initialize SMP subsystem with configuration
-pub fn init_with_config(config: SmpConfig) -> Result<(), &'static str> {
+pub fn init_with_config(config: SmpConfig) -> Result<(), Error> {
     log::info!("Initializing SMP with config: max_cpus={}, boot_cpus={}",
              config.max_cpus, config.boot_cpus);
 
     // Store configuration
-    unsafe {
-        SMP_CONFIG = Some(config.clone());
-    }
+    *SMP_CONFIG.lock() = Some(config.clone());
+    NR_CPUS.store(config.max_cpus.clamp(1, MAX_CPUS), Ordering::SeqCst);
 
     // Initialize SBI for SMP operations
     sbi::init()?;
@@ -124,16 +149,17 @@ pub fn init_with_config(config: SmpConfig) -> Result<(), &'static str> {
     }
 
     // Update SMP state
-    unsafe {
-        SMP_STATE = SmpState::Running;
-    }
+    SMP_STATE.store(SmpState::Running as u8, Ordering::Release);
 
     log::info!("SMP initialization complete");
     Ok(())
 }
 
 /// Initialize load balancer
-fn init_load_balancer(lb_type: LoadBalancerType) -> Result<(), &'static str> {
+///
+/// The balancer cannot be replaced once set: other CPUs hold references
+/// to it.
+fn init_load_balancer(lb_type: LoadBalancerType) -> Result<(), Error> {
     let balancer: Box<dyn LoadBalancer> = match lb_type {
         LoadBalancerType::None => Box::new(NoLoadBalancer::new()),
         LoadBalancerType::RoundRobin => Box::new(RoundRobinLoadBalancer::new()),
@@ -141,16 +167,15 @@ fn init_load_balancer(lb_type: LoadBalancerType) -> Result<(), &'static str> {
         LoadBalancerType::Affinity => Box::new(AffinityLoadBalancer::new()),
     };
 
-    unsafe {
-        LOAD_BALANCER = Some(balancer);
-    }
+    LOAD_BALANCER.set(balancer)
+        .map_err(|_| Error::Busy("Load balancer already initialized"))?;
 
     log::debug!("Load balancer initialized: {:?}", lb_type);
     Ok(())
 }
 
 /// Start secondary CPUs
-fn start_secondary_cpus(num_cpus: usize) -> Result<(), &'static str> {
+fn start_secondary_cpus(num_cpus: usize) -> Result<(), Error> {
     log::info!("Starting {} secondary CPUs", num_cpus - 1);
 
     // Configure boot for secondary CPUs
@@ -187,119 +212,176 @@ fn start_secondary_cpus(num_cpus: usize) -> Result<(), &'static str> {
 
 /// Get SMP configuration
 pub fn get_config() -> Option<SmpConfig> {
-    unsafe { SMP_CONFIG.clone() }
+    SMP_CONFIG.lock().clone()
 }
 
 /// Get SMP state
 pub fn get_state() -> SmpState {
-    unsafe { SMP_STATE }
+    SmpState::from_u8(SMP_STATE.load(Ordering::Acquire))
+}
+
+/// Get the load balancer, once SMP initialization has chosen it
+pub fn load_balancer() -> Option<&'static dyn LoadBalancer> {
+    LOAD_BALANCER.get().map(|balancer| &**balancer)
+}
+
+/// Number of CPUs per-CPU state is sized for
+///
+/// At most `MAX_CPUS`. CPU IDs at or above it are rejected.
+pub fn nr_cpus() -> usize {
+    NR_CPUS.load(Ordering::SeqCst)
 }
 
 /// Get number of online CPUs
 pub fn num_online_cpus() -> usize {
-    unsafe { ONLINE_CPUS.load(Ordering::SeqCst) }
+    ONLINE_CPUS.load(Ordering::SeqCst)
 }
 
-/// Get online CPU mask
-pub fn get_online_cpu_mask() -> usize {
-    unsafe { ONLINE_CPU_MASK.load(Ordering::SeqCst) }
+/// Get the set of online CPUs
+pub fn get_online_cpu_mask() -> CpuMask {
+    CpuMask::from_bits(ONLINE_CPU_MASK.load(Ordering::SeqCst) as u64)
 }
 
 /// Check if a CPU is online
 pub fn is_cpu_online(cpu_id: usize) -> bool {
-    if cpu_id >= MAX_CPUS {
+    if cpu_id >= nr_cpus() {
         return false;
     }
 
-    let mask = get_online_cpu_mask();
-    (mask & (1 << cpu_id)) != 0
+    get_online_cpu_mask().contains(cpu_id as u32)
 }
 
 /// Mark a CPU as online
 pub fn mark_cpu_online(cpu_id: usize) {
-    if cpu_id < MAX_CPUS {
-        unsafe {
-            ONLINE_CPUS.fetch_add(1, Ordering::SeqCst);
-            ONLINE_CPU_MASK.fetch_or(1 << cpu_id, Ordering::SeqCst);
-        }
+    if cpu_id < nr_cpus() {
+        ONLINE_CPUS.fetch_add(1, Ordering::SeqCst);
+        ONLINE_CPU_MASK.fetch_or(1 << cpu_id, Ordering::SeqCst);
         log::debug!("CPU {} marked as online", cpu_id);
     }
 }
 
 /// Mark a CPU as offline
 pub fn mark_cpu_offline(cpu_id: usize) {
-    if cpu_id < MAX_CPUS {
-        unsafe {
-            ONLINE_CPUS.fetch_sub(1, Ordering::SeqCst);
-            ONLINE_CPU_MASK.fetch_and(!(1 << cpu_id), Ordering::SeqCst);
-        }
+    if cpu_id < nr_cpus() {
+        ONLINE_CPUS.fetch_sub(1, Ordering::SeqCst);
+        ONLINE_CPU_MASK.fetch_and(!(1 << cpu_id), Ordering::SeqCst);
         log::debug!("CPU {} marked as offline", cpu_id);
     }
 }
 
 /// Send IPI to target CPU
-pub fn send_ipi(cpu_id: usize, ipi_type: u32) -> Result<(), &'static str> {
+pub fn send_ipi(cpu_id: usize, ipi_type: u32) -> Result<(), Error> {
     if let Ok(ipi_type_enum) = ipi::IpiType::try_from(ipi_type) {
         ipi::send_ipi(cpu_id, ipi_type_enum, 0)
     } else {
-        Err("Invalid IPI type")
+        Err(Error::InvalidArgument("Invalid IPI type"))
     }
 }
 
 /// Send IPI to multiple CPUs
-pub fn send_ipi_to_many(cpu_ids: &[usize], ipi_type: u32) -> Result<(), &'static str> {
+pub fn send_ipi_to_many(cpu_ids: &[usize], ipi_type: u32) -> Result<(), Error> {
     if let Ok(ipi_type_enum) = ipi::IpiType::try_from(ipi_type) {
         ipi::send_ipi_to_many(cpu_ids, ipi_type_enum, 0)
     } else {
-        Err("Invalid IPI type")
+        Err(Error::InvalidArgument("Invalid IPI type"))
     }
 }
 
 /// Broadcast IPI to all online CPUs
-pub fn broadcast_ipi(ipi_type: u32, exclude_self: bool) -> Result<(), &'static str> {
+pub fn broadcast_ipi(ipi_type: u32, exclude_self: bool) -> Result<(), Error> {
     if let Ok(ipi_type_enum) = ipi::IpiType::try_from(ipi_type) {
         ipi::broadcast_ipi(ipi_type_enum, 0, exclude_self)
     } else {
-        Err("Invalid IPI type")
+        Err(Error::InvalidArgument("Invalid IPI type"))
     }
 }
 
 /// Select CPU for task scheduling
-pub fn select_cpu(task_affinity: Option<usize>) -> Option<usize> {
-    if let Some(ref balancer) = unsafe { LOAD_BALANCER.as_ref() } {
-        balancer.select_cpu(task_affinity)
+///
+/// A task waking up after last running on `last_cpu` is kept near its
+/// cache footprint: the previous CPU or an idle sibling sharing its
+/// last-level cache or package is preferred over the rest of the system.
+/// An explicit `task_affinity` takes precedence.
+pub fn select_cpu(task_affinity: Option<usize>, last_cpu: Option<usize>) -> Option<usize> {
+    if let Some(balancer) = load_balancer() {
+        match (task_affinity, last_cpu) {
+            (None, Some(last_cpu)) => match scheduler::get() {
+                Some(sched) => balancer.select_wake_cpu(last_cpu, &sched.topology),
+                None => balancer.select_wake_cpu(last_cpu, &CpuTopology::default()),
+            },
+            _ => balancer.select_cpu(task_affinity),
+        }
     } else {
         // No load balancer, return current CPU
         Some(crate::arch::riscv64::cpu::current_cpu_id())
     }
 }
 
+/// Set the smoothing factor applied to CPU load samples
+///
+/// `alpha` is the weight of the newest sample, in (0, 1]; 1 disables
+/// smoothing.
+pub fn set_load_alpha(alpha: f64) -> Result<(), Error> {
+    if !(alpha > 0.0 && alpha <= 1.0) {
+        return Err(Error::InvalidArgument("Load alpha must be in (0, 1]"));
+    }
+
+    if let Some(balancer) = load_balancer() {
+        balancer.set_load_alpha(alpha);
+    }
+    Ok(())
+}
+
+/// Record an idle tick on a CPU, decaying its load toward zero
+pub fn decay_cpu_load(cpu_id: usize) {
+    update_cpu_load(cpu_id, 0.0);
+}
+
 /// Update CPU load statistics
 pub fn update_cpu_load(cpu_id: usize, load: f64) {
-    if let Some(ref balancer) = unsafe { LOAD_BALANCER.as_ref() } {
+    if let Some(balancer) = load_balancer() {
         balancer.update_load(cpu_id, load);
     }
 }
 
 /// Get CPU load statistics
 pub fn get_cpu_load(cpu_id: usize) -> Option<f64> {
-    if let Some(ref balancer) = unsafe { LOAD_BALANCER.as_ref() } {
+    if let Some(balancer) = load_balancer() {
         balancer.get_load(cpu_id)
     } else {
         None
     }
 }
 
+/// Waits for a secondary CPU to report ready
+pub type CpuReadyWaitFn = fn(cpu_id: usize, timeout_ms: u64) -> Result<(), Error>;
+
+/// Releases a secondary CPU that failed to become ready
+pub type CpuBootAbortFn = fn(cpu_id: usize) -> Result<(), Error>;
+
+/// Result of a multi-core boot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BootOutcome {
+    /// CPUs running, including the primary
+    pub ready: usize,
+    /// CPUs that failed to start or timed out
+    pub failed: usize,
+}
+
 /// Advanced multi-core boot manager
 pub struct MultiCoreBootManager {
     /// Boot configuration
     config: SmpConfig,
     /// Boot statistics
     stats: BootStatistics,
-    /// Boot state tracking
-    boot_states: [AtomicU32; crate::MAX_CPUS],
+    /// Boot state tracking, one slot per CPU
+    boot_states: Vec<AtomicU32>,
     /// Performance monitoring
     performance: BootPerformanceMonitor,
+    /// Secondary CPU readiness wait
+    wait_ready: CpuReadyWaitFn,
+    /// Cleanup of a CPU that failed to become ready
+    abort_boot: CpuBootAbortFn,
 }
 
 /// Boot statistics
@@ -323,33 +405,51 @@ pub struct BootStatistics {
 #[derive(Debug)]
 pub struct BootPerformanceMonitor {
     /// Boot times per CPU
-    boot_times: [AtomicU64; crate::MAX_CPUS],
+    boot_times: Vec<AtomicU64>,
     /// Boot start times per CPU
-    boot_start_times: [AtomicU64; crate::MAX_CPUS],
+    boot_start_times: Vec<AtomicU64>,
     /// CPU readiness times
-    readiness_times: [AtomicU64; crate::MAX_CPUS],
+    readiness_times: Vec<AtomicU64>,
     /// Last boot timestamp
     last_boot_timestamp: AtomicU64,
 }
 
 impl MultiCoreBootManager {
     /// Create new multi-core boot manager
+    ///
+    /// Per-CPU state is allocated for `config.max_cpus` CPUs, capped at
+    /// `MAX_CPUS`.
     pub fn new(config: SmpConfig) -> Self {
+        let nr_cpus = config.max_cpus.clamp(1, crate::MAX_CPUS);
+        let per_cpu = || (0..nr_cpus).map(|_| AtomicU64::new(0)).collect::<Vec<_>>();
         Self {
             config,
             stats: BootStatistics::default(),
-            boot_states: [const { AtomicU32::new(0) }; crate::MAX_CPUS],
+            boot_states: (0..nr_cpus).map(|_| AtomicU32::new(0)).collect(),
             performance: BootPerformanceMonitor {
-                boot_times: [const { AtomicU64::new(0) }; crate::MAX_CPUS],
-                boot_start_times: [const { AtomicU64::new(0) }; crate::MAX_CPUS],
-                readiness_times: [const { AtomicU64::new(0) }; crate::MAX_CPUS],
+                boot_times: per_cpu(),
+                boot_start_times: per_cpu(),
+                readiness_times: per_cpu(),
                 last_boot_timestamp: AtomicU64::new(0),
             },
+            wait_ready: crate::arch::riscv64::smp::boot::wait_for_cpu_ready,
+            abort_boot: crate::arch::riscv64::smp::boot::abort_cpu_boot,
         }
     }
 
+    /// Replace how secondary CPUs are waited for and released on failure
+    pub fn set_boot_hooks(&mut self, wait_ready: CpuReadyWaitFn, abort_boot: CpuBootAbortFn) {
+        self.wait_ready = wait_ready;
+        self.abort_boot = abort_boot;
+    }
+
+    /// Number of CPUs this manager tracks
+    pub fn nr_cpus(&self) -> usize {
+        self.boot_states.len()
+    }
+
     /// Initialize multi-core boot system
-    pub fn initialize(&mut self) -> Result<(), &'static str> {
+    pub fn initialize(&mut self) -> Result<(), Error> {
         log::info!("Initializing multi-core boot manager");
 
         // Initialize SBI interface
@@ -372,11 +472,11 @@ impl MultiCoreBootManager {
     }
 
     /// Initialize primary CPU
-    fn initialize_primary_cpu(&mut self) -> Result<(), &'static str> {
+    fn initialize_primary_cpu(&mut self) -> Result<(), Error> {
         let cpu_id = 0;
 
         // Mark primary CPU as booting
-        self.set_cpu_state(cpu_id, CpuState::Booting);
+        self.set_cpu_state(cpu_id, CpuState::Booting)?;
 
         let start_time = crate::arch::riscv64::cpu::csr::TIME::read();
 
@@ -400,7 +500,7 @@ impl MultiCoreBootManager {
         self.stats.total_boot_time.fetch_add(boot_time, Ordering::SeqCst);
 
         // Mark primary CPU as ready
-        self.set_cpu_state(cpu_id, CpuState::Running);
+        self.set_cpu_state(cpu_id, CpuState::Running)?;
         mark_cpu_online(cpu_id);
 
         log::info!("Primary CPU {} initialized in {} cycles", cpu_id, boot_time);
@@ -408,7 +508,7 @@ impl MultiCoreBootManager {
     }
 
     /// Start secondary CPUs
-    pub fn start_secondary_cpus(&mut self) -> Result<usize, &'static str> {
+    pub fn start_secondary_cpus(&mut self) -> Result<usize, Error> {
         if self.config.boot_cpus <= 1 {
             return Ok(0);
         }
@@ -430,9 +530,9 @@ impl MultiCoreBootManager {
         let mut concurrent_boots = 0;
 
         // Start secondary CPUs in parallel if possible
-        for cpu_id in 1..self.config.boot_cpus.min(crate::MAX_CPUS) {
+        for cpu_id in 1..self.config.boot_cpus.min(self.nr_cpus()) {
             // Mark CPU as booting
-            self.set_cpu_state(cpu_id, CpuState::Booting);
+            self.set_cpu_state(cpu_id, CpuState::Booting)?;
             self.performance.boot_start_times[cpu_id].store(start_time, Ordering::SeqCst);
             concurrent_boots += 1;
 
@@ -443,7 +543,7 @@ impl MultiCoreBootManager {
                 }
                 Err(e) => {
                     log::error!("Failed to start CPU {}: {}", cpu_id, e);
-                    self.set_cpu_state(cpu_id, CpuState::Failed);
+                    self.set_cpu_state(cpu_id, CpuState::Failed)?;
                     self.stats.failed_boots.fetch_add(1, Ordering::SeqCst);
                 }
             }
@@ -460,20 +560,24 @@ impl MultiCoreBootManager {
     }
 
     /// Wait for all CPUs to be ready
-    pub fn wait_for_all_cpus_ready(&mut self, timeout_ms: u64) -> Result<usize, &'static str> {
+    pub fn wait_for_all_cpus_ready(&mut self, timeout_ms: u64) -> Result<usize, Error> {
         let start_time = crate::arch::riscv64::cpu::csr::TIME::read();
         let mut ready_count = 0;
 
         log::info!("Waiting for CPUs to be ready (timeout: {}ms)", timeout_ms);
 
-        for cpu_id in 0..self.config.boot_cpus.min(crate::MAX_CPUS) {
+        for cpu_id in 0..self.config.boot_cpus.min(self.nr_cpus()) {
             if cpu_id == 0 {
                 // Primary CPU is already ready
                 ready_count += 1;
                 continue;
             }
+            if self.cpu_state(cpu_id)? == CpuState::Failed {
+                // Never started, nothing to wait for
+                continue;
+            }
 
-            match crate::arch::riscv64::smp::boot::wait_for_cpu_ready(cpu_id, timeout_ms) {
+            match (self.wait_ready)(cpu_id, timeout_ms) {
                 Ok(_) => {
                     ready_count += 1;
                     let end_time = crate::arch::riscv64::cpu::csr::TIME::read();
@@ -484,14 +588,18 @@ impl MultiCoreBootManager {
 
                     self.performance.boot_times[cpu_id].store(boot_time, Ordering::SeqCst);
                     self.performance.readiness_times[cpu_id].store(ready_time, Ordering::SeqCst);
-                    self.set_cpu_state(cpu_id, CpuState::Running);
+                    self.set_cpu_state(cpu_id, CpuState::Running)?;
 
                     log::debug!("CPU {} ready after {} cycles (ready in {} cycles)",
                                cpu_id, boot_time, ready_time);
                 }
                 Err(e) => {
                     log::warn!("CPU {} failed to become ready: {}", cpu_id, e);
-                    self.set_cpu_state(cpu_id, CpuState::Failed);
+                    if !self.release_failed_cpu(cpu_id)? {
+                        log::info!("CPU {} became ready late", cpu_id);
+                        ready_count += 1;
+                        self.set_cpu_state(cpu_id, CpuState::Running)?;
+                    }
                 }
             }
         }
@@ -510,8 +618,34 @@ impl MultiCoreBootManager {
         Ok(ready_count)
     }
 
+    /// Clean up a secondary CPU that failed to become ready
+    ///
+    /// Aborts its boot and counts it as a failed boot. Returns false,
+    /// leaving the CPU alone, if it became ready before the abort.
+    fn release_failed_cpu(&self, cpu_id: usize) -> Result<bool, Error> {
+        match (self.abort_boot)(cpu_id) {
+            Ok(()) => {}
+            Err(Error::Busy(_)) => return Ok(false),
+            Err(e) => log::warn!("Failed to abort boot of CPU {}: {}", cpu_id, e),
+        }
+        self.stats.failed_boots.fetch_add(1, Ordering::SeqCst);
+        self.set_cpu_state(cpu_id, CpuState::Failed)?;
+        Ok(true)
+    }
+
+    /// Number of CPUs that failed to boot
+    pub fn failed_cpus(&self) -> usize {
+        self.boot_states
+            .iter()
+            .filter(|state| CpuState::from(state.load(Ordering::SeqCst)) == CpuState::Failed)
+            .count()
+    }
+
     /// Perform complete multi-core boot sequence
-    pub fn boot_all_cpus(&mut self) -> Result<usize, &'static str> {
+    ///
+    /// Secondary CPUs that fail to start or time out are released and
+    /// counted in the outcome; the boot only fails if the primary does.
+    pub fn boot_all_cpus(&mut self) -> Result<BootOutcome, Error> {
         log::info!("Starting multi-core boot sequence for {} CPUs", self.config.boot_cpus);
 
         let start_time = crate::arch::riscv64::cpu::csr::TIME::read();
@@ -520,27 +654,29 @@ impl MultiCoreBootManager {
         self.initialize_primary_cpu()?;
 
         // Start secondary CPUs
-        let started = self.start_secondary_cpus()?;
+        self.start_secondary_cpus()?;
 
         // Wait for all CPUs to be ready
         let ready = self.wait_for_all_cpus_ready(5000)?; // 5 second timeout
 
+        let failed = self.failed_cpus();
         let total_time = crate::arch::riscv64::cpu::csr::TIME::read().wrapping_sub(start_time);
 
         log::info!("Multi-core boot completed: {}/{} CPUs ready in {} cycles",
                     ready, self.config.boot_cpus, total_time);
+        if failed > 0 {
+            log::warn!("{} CPUs failed to boot", failed);
+        }
 
         // Update SMP state
-        unsafe {
-            SMP_STATE = SmpState::Running;
-        }
+        SMP_STATE.store(SmpState::Running as u8, Ordering::Release);
 
-        Ok(ready)
+        Ok(BootOutcome { ready, failed })
     }
 
     /// Perform dynamic CPU hotplug
     pub fn hotplug_cpu(&mut self, cpu_id: usize, operation: crate::arch::riscv64::smp::boot::hotplug::HotplugOp) ->
-        Result<crate::arch::riscv64::smp::boot::hotplug::HotplugRequest, &'static str> {
+        Result<crate::arch::riscv64::smp::boot::hotplug::HotplugRequest, Error> {
 
         match operation {
             crate::arch::riscv64::smp::boot::hotplug::HotplugOp::Add => {
@@ -578,7 +714,7 @@ impl MultiCoreBootManager {
         };
 
         let mut per_cpu_stats = Vec::new();
-        for cpu_id in 0..crate::MAX_CPUS {
+        for cpu_id in 0..self.nr_cpus() {
             let boot_time = self.performance.boot_times[cpu_id].load(Ordering::SeqCst);
             let ready_time = self.performance.readiness_times[cpu_id].load(Ordering::SeqCst);
             let state = self.boot_states[cpu_id].load(Ordering::SeqCst);
@@ -605,9 +741,21 @@ impl MultiCoreBootManager {
         }
     }
 
+    /// Get a CPU's boot state
+    pub fn cpu_state(&self, cpu_id: usize) -> Result<CpuState, Error> {
+        self.boot_states
+            .get(cpu_id)
+            .map(|state| CpuState::from(state.load(Ordering::SeqCst)))
+            .ok_or(Error::InvalidArgument("CPU ID beyond the live CPU count"))
+    }
+
     /// Set CPU state
-    fn set_cpu_state(&self, cpu_id: usize, state: CpuState) {
-        self.boot_states[cpu_id].store(state as u32, Ordering::SeqCst);
+    fn set_cpu_state(&self, cpu_id: usize, state: CpuState) -> Result<(), Error> {
+        self.boot_states
+            .get(cpu_id)
+            .ok_or(Error::InvalidArgument("CPU ID beyond the live CPU count"))?
+            .store(state as u32, Ordering::SeqCst);
+        Ok(())
     }
 
     /// Get boot configuration
@@ -708,20 +856,24 @@ impl From<u32> for CpuState {
 }
 
 /// Global multi-core boot manager
-static mut BOOT_MANAGER: Option<MultiCoreBootManager> = None;
+static BOOT_MANAGER: Once<SpinLock<MultiCoreBootManager>> = Once::new();
 
 /// Get global multi-core boot manager
-pub fn get_boot_manager() -> Option<&'static MultiCoreBootManager> {
-    unsafe { BOOT_MANAGER.as_ref() }
+///
+/// The manager stays locked while the guard is held.
+pub fn get_boot_manager() -> Option<SpinLockGuard<'static, MultiCoreBootManager>> {
+    BOOT_MANAGER.get().map(SpinLock::lock)
 }
 
 /// Get mutable global multi-core boot manager
-pub fn get_boot_manager_mut() -> Option<&'static mut MultiCoreBootManager> {
-    unsafe { BOOT_MANAGER.as_mut() }
+///
+/// The manager stays locked while the guard is held.
+pub fn get_boot_manager_mut() -> Option<SpinLockGuard<'static, MultiCoreBootManager>> {
+    get_boot_manager()
 }
 
 /// Initialize multi-core boot system
-pub fn init_multi_core_boot(config: SmpConfig) -> Result<(), &'static str> {
+pub fn init_multi_core_boot(config: SmpConfig) -> Result<(), Error> {
     log::info!("Initializing multi-core boot system with config: {:?}", config);
 
     let mut manager = MultiCoreBootManager::new(config);
@@ -730,25 +882,26 @@ pub fn init_multi_core_boot(config: SmpConfig) -> Result<(), &'static str> {
     manager.initialize()?;
 
     // Store global reference
-    unsafe {
-        BOOT_MANAGER = Some(manager);
-    }
+    BOOT_MANAGER.set(SpinLock::new(manager))
+        .map_err(|_| Error::Busy("Multi-core boot manager already initialized"))?;
 
     log::info!("Multi-core boot system initialized");
     Ok(())
 }
 
 /// Perform complete multi-core boot
-pub fn boot_all_cpus() -> Result<usize, &'static str> {
-    if let Some(manager) = get_boot_manager_mut() {
+pub fn boot_all_cpus() -> Result<BootOutcome, Error> {
+    if let Some(mut manager) = get_boot_manager_mut() {
         manager.boot_all_cpus()
     } else {
-        Err("Multi-core boot manager not initialized")
+        Err(Error::NotInitialized("Multi-core boot manager not initialized"))
     }
 }
 
 /// Load balancer trait
-pub trait LoadBalancer {
+///
+/// Balancers are shared by all CPUs, so they keep their state in atomics.
+pub trait LoadBalancer: Send + Sync {
     /// Select a CPU for a task
     fn select_cpu(&self, affinity: Option<usize>) -> Option<usize>;
 
@@ -757,6 +910,17 @@ pub trait LoadBalancer {
 
     /// Get CPU load
     fn get_load(&self, cpu_id: usize) -> Option<f64>;
+
+    /// Set the weight of new load samples
+    fn set_load_alpha(&self, _alpha: f64) {
+        // Balancers without load tracking ignore this
+    }
+
+    /// Select a CPU for a task waking up after last running on `last_cpu`
+    fn select_wake_cpu(&self, _last_cpu: usize, _topology: &CpuTopology) -> Option<usize> {
+        // Balancers without load tracking have no idle CPUs to prefer
+        self.select_cpu(None)
+    }
 }
 
 /// No load balancer (always use current CPU)
@@ -816,18 +980,7 @@ impl LoadBalancer for RoundRobinLoadBalancer {
         let cpu_id = current % num_cpus;
 
         // Find the actual CPU ID at this position
-        let mask = get_online_cpu_mask();
-        let mut pos = 0;
-        for i in 0..MAX_CPUS {
-            if (mask & (1 << i)) != 0 {
-                if pos == cpu_id {
-                    return Some(i);
-                }
-                pos += 1;
-            }
-        }
-
-        None
+        get_online_cpu_mask().iter().nth(cpu_id).map(|cpu| cpu as usize)
     }
 
     fn update_load(&self, _cpu_id: usize, _load: f64) {
@@ -861,36 +1014,46 @@ impl AtomicF64 {
     }
 }
 
+/// Default weight of a new load sample
+pub const DEFAULT_LOAD_ALPHA: f64 = 0.5;
+
+/// Smoothed load below which a CPU counts as idle
+pub const IDLE_LOAD_THRESHOLD: f64 = 0.05;
+
 /// Least loaded load balancer
+///
+/// Loads are exponentially weighted moving averages, so a CPU that goes
+/// idle (samples of 0.0) decays back toward zero instead of looking busy
+/// forever.
 pub struct LeastLoadedLoadBalancer {
-    cpu_loads: [AtomicF64; MAX_CPUS],
+    /// Smoothed load, one slot per CPU
+    cpu_loads: Vec<AtomicF64>,
+    /// Weight of the newest sample
+    alpha: AtomicF64,
 }
 
 impl LeastLoadedLoadBalancer {
+    /// Create a balancer for the CPUs SMP initialization detected
     pub fn new() -> Self {
-        Self {
-            cpu_loads: [const { AtomicF64::new(0.0) }; MAX_CPUS],
-        }
+        Self::with_cpus(nr_cpus())
     }
-}
 
-impl LoadBalancer for LeastLoadedLoadBalancer {
-    fn select_cpu(&self, affinity: Option<usize>) -> Option<usize> {
-        // If affinity is specified and CPU is online, use it
-        if let Some(cpu_id) = affinity {
-            if is_cpu_online(cpu_id) {
-                return Some(cpu_id);
-            }
+    /// Create a balancer tracking `nr_cpus` CPUs, capped at `MAX_CPUS`
+    pub fn with_cpus(nr_cpus: usize) -> Self {
+        Self {
+            cpu_loads: (0..nr_cpus.min(MAX_CPUS)).map(|_| AtomicF64::new(0.0)).collect(),
+            alpha: AtomicF64::new(DEFAULT_LOAD_ALPHA),
         }
+    }
 
-        // Find CPU with minimum load
-        let mask = get_online_cpu_mask();
+    /// Find the least loaded CPU in `mask`
+    fn least_loaded_in(&self, mask: CpuMask) -> Option<usize> {
         let mut min_load = 1.0;
         let mut selected_cpu = None;
 
-        for i in 0..MAX_CPUS {
-            if (mask & (1 << i)) != 0 {
-                let load = self.cpu_loads[i].load(Ordering::SeqCst);
+        for (i, cpu_load) in self.cpu_loads.iter().enumerate() {
+            if mask.contains(i as u32) {
+                let load = cpu_load.load(Ordering::SeqCst);
                 if selected_cpu.is_none() || load < min_load {
                     min_load = load;
                     selected_cpu = Some(i);
@@ -901,18 +1064,67 @@ impl LoadBalancer for LeastLoadedLoadBalancer {
         selected_cpu
     }
 
+    /// Check whether a CPU's smoothed load counts as idle
+    fn is_idle(&self, cpu_id: usize) -> bool {
+        self.get_load(cpu_id).map_or(false, |load| load < IDLE_LOAD_THRESHOLD)
+    }
+
+    /// Find the least loaded idle CPU in `mask`
+    fn idle_in(&self, mask: CpuMask) -> Option<usize> {
+        let idle = mask.iter()
+            .filter(|&cpu| self.is_idle(cpu as usize))
+            .collect();
+        self.least_loaded_in(idle)
+    }
+
+    /// Pick a wakeup CPU among `online` CPUs
+    ///
+    /// Keeps the task's cache footprint warm: the previous CPU if idle,
+    /// then an idle sibling sharing its last-level cache, then an idle
+    /// CPU in its package. Only then does the task spill to the least
+    /// loaded CPU anywhere.
+    fn select_wake_cpu_in(&self, last_cpu: usize, topology: &CpuTopology, online: CpuMask) -> Option<usize> {
+        if last_cpu < self.cpu_loads.len() && online.contains(last_cpu as u32) && self.is_idle(last_cpu) {
+            return Some(last_cpu);
+        }
+
+        self.idle_in(topology.llc_mask(last_cpu).intersection(&online))
+            .or_else(|| self.idle_in(topology.package_mask(last_cpu).intersection(&online)))
+            .or_else(|| self.least_loaded_in(online))
+    }
+}
+
+impl LoadBalancer for LeastLoadedLoadBalancer {
+    fn select_cpu(&self, affinity: Option<usize>) -> Option<usize> {
+        // If affinity is specified and CPU is online, use it
+        if let Some(cpu_id) = affinity {
+            if is_cpu_online(cpu_id) {
+                return Some(cpu_id);
+            }
+        }
+
+        // Find CPU with minimum load
+        self.least_loaded_in(get_online_cpu_mask())
+    }
+
     fn update_load(&self, cpu_id: usize, load: f64) {
-        if cpu_id < MAX_CPUS {
-            self.cpu_loads[cpu_id].store(load, Ordering::SeqCst);
+        if let Some(cpu_load) = self.cpu_loads.get(cpu_id) {
+            let alpha = self.alpha.load(Ordering::SeqCst);
+            let old = cpu_load.load(Ordering::SeqCst);
+            cpu_load.store(alpha * load + (1.0 - alpha) * old, Ordering::SeqCst);
         }
     }
 
     fn get_load(&self, cpu_id: usize) -> Option<f64> {
-        if cpu_id < MAX_CPUS {
-            Some(self.cpu_loads[cpu_id].load(Ordering::SeqCst))
-        } else {
-            None
-        }
+        self.cpu_loads.get(cpu_id).map(|cpu_load| cpu_load.load(Ordering::SeqCst))
+    }
+
+    fn set_load_alpha(&self, alpha: f64) {
+        self.alpha.store(alpha, Ordering::SeqCst);
+    }
+
+    fn select_wake_cpu(&self, last_cpu: usize, topology: &CpuTopology) -> Option<usize> {
+        self.select_wake_cpu_in(last_cpu, topology, get_online_cpu_mask())
     }
 }
 
@@ -997,4 +1209,140 @@ mod tests {
         let cpu = aff_lb.select_cpu(Some(0));
         assert!(cpu.is_some());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_least_loaded_load_decays_when_idle() {
+        let lb = LeastLoadedLoadBalancer::new();
+        lb.set_load_alpha(0.5);
+
+        // CPU 0 was briefly saturated, CPU 1 runs a steady 30% load
+        lb.update_load(0, 1.0);
+        lb.update_load(0, 1.0);
+        for _ in 0..8 {
+            lb.update_load(1, 0.3);
+        }
+        assert_eq!(lb.least_loaded_in(CpuMask::from_bits(0b11)), Some(1));
+
+        // Idle ticks decay CPU 0's load monotonically toward zero
+        let mut prev = lb.get_load(0).unwrap();
+        for _ in 0..4 {
+            lb.update_load(0, 0.0);
+            let load = lb.get_load(0).unwrap();
+            assert!(load < prev);
+            prev = load;
+        }
+        assert!(prev < 0.1);
+
+        assert_eq!(lb.least_loaded_in(CpuMask::from_bits(0b11)), Some(0));
+    }
+
+    #[test]
+    fn test_load_balancer_is_set_once() {
+        let _ = init_load_balancer(LoadBalancerType::RoundRobin);
+        let first = load_balancer().unwrap();
+
+        // A late initializer cannot swap the balancer out from under the
+        // CPUs already using it
+        assert_eq!(init_load_balancer(LoadBalancerType::LeastLoaded),
+                   Err(Error::Busy("Load balancer already initialized")));
+        assert!(core::ptr::addr_eq(first, load_balancer().unwrap()));
+        assert!(first.select_cpu(Some(0)).is_some());
+    }
+
+    #[test]
+    fn test_load_alpha_one_is_instantaneous() {
+        let lb = LeastLoadedLoadBalancer::new();
+        lb.set_load_alpha(1.0);
+        lb.update_load(2, 0.8);
+        lb.update_load(2, 0.2);
+        assert_eq!(lb.get_load(2), Some(0.2));
+    }
+
+    /// Two packages of four CPUs, each package sharing one LLC
+    fn two_package_balancer(loads: [f64; 8]) -> (LeastLoadedLoadBalancer, CpuTopology) {
+        let lb = LeastLoadedLoadBalancer::new();
+        lb.set_load_alpha(1.0);
+        for (cpu, &load) in loads.iter().enumerate() {
+            lb.update_load(cpu, load);
+        }
+        (lb, CpuTopology::new(2, 4, 1))
+    }
+
+    #[test]
+    fn test_wake_prefers_idle_sibling_in_same_package() {
+        // Previous CPU 1 is busy; CPU 2 shares its LLC and is idle, while
+        // package 1 is completely (and slightly more) idle
+        let (lb, topo) = two_package_balancer([0.6, 0.9, 0.02, 0.7, 0.0, 0.0, 0.0, 0.0]);
+
+        assert_eq!(lb.least_loaded_in(CpuMask::from_bits(0xFF)), Some(4));
+        assert_eq!(lb.select_wake_cpu_in(1, &topo, CpuMask::from_bits(0xFF)), Some(2));
+
+        // An idle previous CPU wins outright
+        lb.update_load(1, 0.0);
+        assert_eq!(lb.select_wake_cpu_in(1, &topo, CpuMask::from_bits(0xFF)), Some(1));
+    }
+
+    #[test]
+    fn test_wake_spills_to_other_package_when_busy() {
+        let (lb, topo) = two_package_balancer([0.6, 0.9, 0.5, 0.7, 0.3, 0.0, 0.8, 0.2]);
+        assert_eq!(lb.select_wake_cpu_in(1, &topo, CpuMask::from_bits(0xFF)), Some(5));
+
+        // Offline idle CPUs are never chosen
+        assert_eq!(lb.select_wake_cpu_in(1, &topo, CpuMask::from_bits(0xDF)), Some(7));
+    }
+
+    #[test]
+    fn test_per_cpu_state_sized_from_cpu_count() {
+        let config = SmpConfig { max_cpus: 2, ..SmpConfig::default() };
+        let manager = MultiCoreBootManager::new(config);
+        assert_eq!(manager.nr_cpus(), 2);
+        assert_eq!(manager.performance.boot_times.len(), 2);
+        assert_eq!(manager.performance.readiness_times.len(), 2);
+
+        assert!(manager.set_cpu_state(1, CpuState::Running).is_ok());
+        assert_eq!(manager.cpu_state(1), Ok(CpuState::Running));
+        assert!(matches!(manager.cpu_state(2), Err(Error::InvalidArgument(_))));
+        assert!(matches!(manager.set_cpu_state(2, CpuState::Booting), Err(Error::InvalidArgument(_))));
+
+        let lb = LeastLoadedLoadBalancer::with_cpus(2);
+        assert_eq!(lb.cpu_loads.len(), 2);
+        lb.update_load(2, 1.0);
+        assert_eq!(lb.get_load(2), None);
+        assert_eq!(lb.least_loaded_in(CpuMask::from_bits(0b1111)), Some(0));
+    }
+
+    #[test]
+    fn test_timed_out_cpu_is_released_and_counted() {
+        static ABORTED: AtomicUsize = AtomicUsize::new(0);
+        fn wait_ready(cpu_id: usize, _timeout_ms: u64) -> Result<(), Error> {
+            if cpu_id == 1 {
+                Ok(())
+            } else {
+                Err(Error::Timeout("Timeout waiting for CPU to be ready"))
+            }
+        }
+        // CPU 3 reports ready just as its boot is aborted
+        fn abort_boot(cpu_id: usize) -> Result<(), Error> {
+            ABORTED.fetch_or(1 << cpu_id, Ordering::SeqCst);
+            if cpu_id == 3 {
+                Err(Error::Busy("CPU became ready"))
+            } else {
+                Ok(())
+            }
+        }
+
+        let config = SmpConfig { max_cpus: 4, boot_cpus: 4, ..SmpConfig::default() };
+        let mut manager = MultiCoreBootManager::new(config);
+        manager.set_boot_hooks(wait_ready, abort_boot);
+        for cpu_id in 1..4 {
+            manager.set_cpu_state(cpu_id, CpuState::Booting).unwrap();
+        }
+
+        assert_eq!(manager.wait_for_all_cpus_ready(10), Ok(3));
+        assert_eq!(manager.cpu_state(2), Ok(CpuState::Failed));
+        assert_eq!(manager.cpu_state(3), Ok(CpuState::Running));
+        assert_eq!(ABORTED.load(Ordering::SeqCst), 0b1100);
+        assert_eq!(manager.failed_cpus(), 1);
+        assert_eq!(manager.get_boot_statistics().failed_boots, 1);
+    }
+}
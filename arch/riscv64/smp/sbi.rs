@@ -47,10 +47,10 @@ impl SbiError {
     }
 
     /// Convert to result
-    pub fn into_result(self) -> Result<(), &'static str> {
+    pub fn into_result(self) -> Result<(), Error> {
         match self {
             SbiError::Success => Ok(()),
-            _ => Err("SBI call failed"),
+            _ => Err(Error::Failed("SBI call failed")),
         }
     }
 }
@@ -391,23 +391,23 @@ pub fn is_rfence_available() -> bool {
 }
 
 /// Initialize SBI for SMP
-pub fn init() -> Result<(), &'static str> {
+pub fn init() -> Result<(), Error> {
     log::info!("Initializing SBI for SMP");
 
     // Check required extensions
     if !is_hsm_available() {
         log::warn!("SBI HSM extension not available");
-        return Err("SBI HSM extension not available");
+        return Err(Error::Unsupported("SBI HSM extension not available"));
     }
 
     if !is_ipi_available() {
         log::warn!("SBI IPI extension not available");
-        return Err("SBI IPI extension not available");
+        return Err(Error::Unsupported("SBI IPI extension not available"));
     }
 
     if !is_rfence_available() {
         log::warn!("SBI RFENCE extension not available");
-        return Err("SBI RFENCE extension not available");
+        return Err(Error::Unsupported("SBI RFENCE extension not available"));
     }
 
     log::info!("SBI extensions available: HSM={}, IPI={}, RFENCE={}",
@@ -11,8 +11,10 @@
 use crate::core::sched::{self, ThreadId, Priority, ThreadState};
 use crate::core::sync::SpinLock;
 use crate::core::vmm::{VmId, VcpuId};
+use crate::libs::cpumask::CpuMask;
 use crate::utils::bitmap::Bitmap;
 use crate::arch::riscv64::cpu::{current_cpu_id, get_cpu_count};
+use crate::arch::riscv64::Error;
 use core::sync::atomic::{AtomicUsize, AtomicU64, AtomicU32, Ordering};
 use core::cmp::{min, max};
 use alloc::{vec::Vec, collections::VecDeque};
@@ -107,6 +109,39 @@ impl CpuTopology {
         }
     }
 
+    /// Create a uniform topology
+    ///
+    /// CPUs are numbered package by package, core by core. All CPUs in a
+    /// package share its last-level cache and NUMA node.
+    pub fn new(packages: usize, cores_per_package: usize, threads_per_core: usize) -> Self {
+        let cpus_per_package = cores_per_package * threads_per_core;
+        let total_cpus = packages * cpus_per_package;
+        let mut cpu_packages = Vec::with_capacity(total_cpus);
+        let mut cpu_cores = Vec::with_capacity(total_cpus);
+        let mut cpu_threads = Vec::with_capacity(total_cpus);
+        let mut llc_siblings = Vec::with_capacity(total_cpus);
+
+        for cpu_id in 0..total_cpus {
+            let package = cpu_id / cpus_per_package;
+            cpu_packages.push(package);
+            cpu_cores.push((cpu_id % cpus_per_package) / threads_per_core);
+            cpu_threads.push(cpu_id % threads_per_core);
+            llc_siblings.push((package * cpus_per_package..(package + 1) * cpus_per_package).collect());
+        }
+
+        Self {
+            packages,
+            cores_per_package,
+            threads_per_core,
+            total_cpus,
+            numa_nodes: cpu_packages.clone(),
+            cpu_packages,
+            cpu_cores,
+            cpu_threads,
+            llc_siblings,
+        }
+    }
+
     /// Get CPUs in the same package
     pub fn get_package_cpus(&self, cpu_id: usize) -> Vec<usize> {
         if cpu_id >= self.total_cpus {
@@ -129,6 +164,17 @@ impl CpuTopology {
         &self.llc_siblings[cpu_id]
     }
 
+    /// CPUs sharing the last-level cache with `cpu_id`
+    pub fn llc_mask(&self, cpu_id: usize) -> CpuMask {
+        self.llc_siblings.get(cpu_id)
+            .map_or(CpuMask::from_cpu(cpu_id as u32), |siblings| cpu_mask(siblings))
+    }
+
+    /// CPUs in the same package as `cpu_id`
+    pub fn package_mask(&self, cpu_id: usize) -> CpuMask {
+        cpu_mask(&self.get_package_cpus(cpu_id))
+    }
+
     /// Get CPUs in the same NUMA node
     pub fn get_numa_cpus(&self, cpu_id: usize) -> Vec<usize> {
         if cpu_id >= self.total_cpus {
@@ -144,6 +190,13 @@ impl CpuTopology {
     }
 }
 
+/// Build a CPU mask from a list of CPU IDs
+fn cpu_mask(cpus: &[usize]) -> CpuMask {
+    cpus.iter()
+        .filter_map(|&cpu| u32::try_from(cpu).ok())
+        .collect()
+}
+
 /// Task load metrics
 #[derive(Debug, Clone, Copy, Default)]
 pub struct TaskLoadMetrics {
@@ -345,11 +398,11 @@ impl LoadBalancedScheduler {
     }
 
     /// Initialize the scheduler
-    pub fn initialize(&mut self) -> Result<(), &'static str> {
+    pub fn initialize(&mut self) -> Result<(), Error> {
         log::info!("Initializing load-balanced SMP scheduler");
 
         // Initialize the core scheduler
-        sched::init().map_err(|_| "Failed to initialize core scheduler")?;
+        sched::init().map_err(|_| Error::Failed("Failed to initialize core scheduler"))?;
 
         // Initialize per-CPU schedulers
         for cpu_scheduler in &self.cpu_schedulers {
@@ -361,7 +414,7 @@ impl LoadBalancedScheduler {
     }
 
     /// Select the best CPU for a new task
-    pub fn select_cpu_for_task(&self, task_id: ThreadId, affinity: Option<usize>) -> Result<usize, &'static str> {
+    pub fn select_cpu_for_task(&self, task_id: ThreadId, affinity: Option<usize>) -> Result<usize, Error> {
         let strategy = self.get_strategy();
 
         // If affinity is specified and CPU is online, use it
@@ -383,12 +436,12 @@ impl LoadBalancedScheduler {
     }
 
     /// Select CPU using round-robin strategy
-    fn select_cpu_round_robin(&self) -> Result<usize, &'static str> {
+    fn select_cpu_round_robin(&self) -> Result<usize, Error> {
         static NEXT_CPU: AtomicUsize = AtomicUsize::new(0);
 
         let cpu_count = self.cpu_schedulers.len();
         if cpu_count == 0 {
-            return Err("No CPUs available");
+            return Err(Error::OutOfResources("No CPUs available"));
         }
 
         let next = NEXT_CPU.fetch_add(1, Ordering::Relaxed) % cpu_count;
@@ -396,7 +449,7 @@ impl LoadBalancedScheduler {
     }
 
     /// Select CPU with least load
-    fn select_cpu_least_loaded(&self) -> Result<usize, &'static str> {
+    fn select_cpu_least_loaded(&self) -> Result<usize, Error> {
         let mut best_cpu = 0;
         let mut min_load = f64::MAX;
 
@@ -412,7 +465,7 @@ impl LoadBalancedScheduler {
     }
 
     /// Select CPU using power-aware strategy
-    fn select_cpu_power_aware(&self) -> Result<usize, &'static str> {
+    fn select_cpu_power_aware(&self) -> Result<usize, Error> {
         // Prefer to pack tasks onto fewer CPUs to allow others to enter low-power states
         let mut best_cpu = 0;
         let mut max_load = 0.0;
@@ -434,7 +487,7 @@ impl LoadBalancedScheduler {
     }
 
     /// Select CPU using NUMA-aware strategy
-    fn select_cpu_numa_aware(&self) -> Result<usize, &'static str> {
+    fn select_cpu_numa_aware(&self) -> Result<usize, Error> {
         let current_cpu = current_cpu_id();
 
         // Try to find a CPU in the same NUMA node with available capacity
@@ -465,11 +518,11 @@ impl LoadBalancedScheduler {
                 }
             }
             Some(best)
-        }).ok_or("No CPUs available")
+        }).ok_or(Error::OutOfResources("No CPUs available"))
     }
 
     /// Select CPU using cache-aware strategy
-    fn select_cpu_cache_aware(&self) -> Result<usize, &'static str> {
+    fn select_cpu_cache_aware(&self) -> Result<usize, Error> {
         let current_cpu = current_cpu_id();
 
         // Try to find a CPU sharing the same last-level cache
@@ -503,11 +556,11 @@ impl LoadBalancedScheduler {
 
         // Fall back to current CPU if no better option
         best_cpu.or(Some(current_cpu))
-            .ok_or("No CPUs available")
+            .ok_or(Error::OutOfResources("No CPUs available"))
     }
 
     /// Select CPU using adaptive strategy
-    fn select_cpu_adaptive(&self) -> Result<usize, &'static str> {
+    fn select_cpu_adaptive(&self) -> Result<usize, Error> {
         // Adaptive strategy selects the best approach based on current system load
         let total_load: f64 = self.cpu_schedulers.iter()
             .map(|scheduler| scheduler.calculate_load_factor())
@@ -528,12 +581,12 @@ impl LoadBalancedScheduler {
     }
 
     /// Schedule a task
-    pub fn schedule_task(&self, task_id: ThreadId, affinity: Option<usize>) -> Result<usize, &'static str> {
+    pub fn schedule_task(&self, task_id: ThreadId, affinity: Option<usize>) -> Result<usize, Error> {
         let cpu_id = self.select_cpu_for_task(task_id, affinity)?;
 
         // Create the task in the core scheduler
         sched::create_thread(None, Some(task_id as VcpuId), Priority::Normal)
-            .map_err(|_| "Failed to create task")?;
+            .map_err(|_| Error::Failed("Failed to create task"))?;
 
         // Add to the selected CPU's run queue
         self.cpu_schedulers[cpu_id].enqueue_task(task_id);
@@ -543,7 +596,7 @@ impl LoadBalancedScheduler {
     }
 
     /// Perform load balancing across CPUs
-    pub fn balance_load(&self) -> Result<usize, &'static str> {
+    pub fn balance_load(&self) -> Result<usize, Error> {
         let current_time = crate::utils::get_timestamp();
         let last_balance = self.last_balance_time.load(Ordering::Relaxed);
         let interval = self.balance_interval.load(Ordering::Relaxed) as u64;
@@ -588,7 +641,7 @@ impl LoadBalancedScheduler {
     }
 
     /// Migrate a task from one CPU to another
-    pub fn migrate_task(&self, from_cpu: usize, to_cpu: usize) -> Result<bool, &'static str> {
+    pub fn migrate_task(&self, from_cpu: usize, to_cpu: usize) -> Result<bool, Error> {
         if from_cpu >= self.cpu_schedulers.len() || to_cpu >= self.cpu_schedulers.len() {
             return Ok(false);
         }
@@ -612,11 +665,22 @@ impl LoadBalancedScheduler {
         }
     }
 
-    /// Handle scheduler tick
-    pub fn handle_tick(&self) -> Result<(), &'static str> {
-        // Update per-CPU statistics
-        for scheduler in &self.cpu_schedulers {
+    /// Handle the scheduler tick of the current CPU
+    ///
+    /// Every CPU runs its own tick, so only the current CPU's statistics
+    /// are updated here.
+    pub fn handle_tick(&self) -> Result<(), Error> {
+        if let Some(scheduler) = self.cpu_schedulers.get(current_cpu_id()) {
             let _ = scheduler.tick_count.fetch_add(1, Ordering::Relaxed);
+
+            // Feed the balancer's smoothed load; idle CPUs decay toward zero
+            let idle = scheduler.get_current_task().is_none()
+                && scheduler.run_queue.lock().is_empty();
+            if idle {
+                super::decay_cpu_load(scheduler.cpu_id);
+            } else {
+                super::update_cpu_load(scheduler.cpu_id, scheduler.calculate_load_factor());
+            }
         }
 
         // Perform load balancing if enabled
@@ -633,7 +697,7 @@ impl LoadBalancedScheduler {
     }
 
     /// Perform work stealing
-    pub fn steal_work(&self) -> Result<(), &'static str> {
+    pub fn steal_work(&self) -> Result<(), Error> {
         let current_cpu = current_cpu_id();
         if current_cpu >= self.cpu_schedulers.len() {
             return Ok(());
@@ -814,7 +878,7 @@ static mut LOAD_BALANCED_SCHEDULER: Option<LoadBalancedScheduler> = None;
 static SCHEDULER_INITIALIZED: AtomicBool = AtomicBool::new(false);
 
 /// Initialize the load-balanced scheduler
-pub fn init() -> Result<(), &'static str> {
+pub fn init() -> Result<(), Error> {
     unsafe {
         if !SCHEDULER_INITIALIZED.load(Ordering::Acquire) {
             let mut scheduler = LoadBalancedScheduler::new();
@@ -833,23 +897,23 @@ pub fn get() -> Option<&'static LoadBalancedScheduler> {
 }
 
 /// Schedule a task with load balancing
-pub fn schedule_task(task_id: ThreadId, affinity: Option<usize>) -> Result<usize, &'static str> {
+pub fn schedule_task(task_id: ThreadId, affinity: Option<usize>) -> Result<usize, Error> {
     if let Some(scheduler) = get() {
         scheduler.schedule_task(task_id, affinity)
     } else {
-        Err("Load-balanced scheduler not initialized")
+        Err(Error::NotInitialized("Load-balanced scheduler not initialized"))
     }
 }
 
 /// Set task affinity
-pub fn set_task_affinity(task_id: ThreadId, cpu_id: usize) -> Result<(), &'static str> {
+pub fn set_task_affinity(task_id: ThreadId, cpu_id: usize) -> Result<(), Error> {
     // Implementation would move task to specified CPU
     log::debug!("Setting task {} affinity to CPU {}", task_id, cpu_id);
     Ok(())
 }
 
 /// Perform load balancing
-pub fn balance_load() -> Result<usize, &'static str> {
+pub fn balance_load() -> Result<usize, Error> {
     if let Some(scheduler) = get() {
         scheduler.balance_load()
     } else {
@@ -858,7 +922,7 @@ pub fn balance_load() -> Result<usize, &'static str> {
 }
 
 /// Handle scheduler tick
-pub fn handle_tick() -> Result<(), &'static str> {
+pub fn handle_tick() -> Result<(), Error> {
     if let Some(scheduler) = get() {
         scheduler.handle_tick()
     } else {
@@ -0,0 +1,197 @@
+//! RISC-V Guest CSR Emulation
+//!
+//! Counter CSRs the guest may not read directly (their hcounteren bit is
+//! clear) trap as virtual instructions (cause 22) in VS and VU mode. This
+//! module decodes the trapped CSR instruction and emulates it against the
+//! VCPU's shadow counters:
+//! - `cycle` and `instret` from the shadow counts
+//! - `time` as the host time plus the guest's time delta
+//! - `hpmcounter3`..`hpmcounter31`, which are not implemented and read as zero
+//!
+//! The counters are read-only; an instruction that would write one is
+//! rejected so the caller can reflect it back to the guest.
+
+use crate::arch::riscv64::Error;
+
+/// Counter CSR numbers
+pub mod counter_csr {
+    /// Cycle counter
+    pub const CYCLE: u16 = 0xC00;
+    /// Timer
+    pub const TIME: u16 = 0xC01;
+    /// Instructions-retired counter
+    pub const INSTRET: u16 = 0xC02;
+    /// First hardware performance counter
+    pub const HPMCOUNTER3: u16 = 0xC03;
+    /// Last hardware performance counter
+    pub const HPMCOUNTER31: u16 = 0xC1F;
+}
+
+/// CSR instruction operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CsrOp {
+    /// CSRRW/CSRRWI: swap
+    ReadWrite,
+    /// CSRRS/CSRRSI: read and set bits
+    ReadSet,
+    /// CSRRC/CSRRCI: read and clear bits
+    ReadClear,
+}
+
+/// Decoded CSR instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CsrInstruction {
+    /// CSR number
+    pub csr: u16,
+    /// Operation
+    pub op: CsrOp,
+    /// Destination register
+    pub rd: u8,
+    /// Source register, or the zero-extended immediate for the I forms
+    pub rs1: u8,
+    /// `rs1` is an immediate
+    pub imm: bool,
+}
+
+impl CsrInstruction {
+    /// Whether the instruction writes the CSR
+    ///
+    /// CSRRS/CSRRC with x0 or a zero immediate only read it.
+    pub fn writes(&self) -> bool {
+        self.op == CsrOp::ReadWrite || self.rs1 != 0
+    }
+}
+
+/// Decode a CSR instruction (SYSTEM opcode, funct3 other than 0 and 4)
+pub fn decode_csr_instruction(insn: u32) -> Option<CsrInstruction> {
+    if insn & 0x7F != 0x73 {
+        return None;
+    }
+
+    let funct3 = (insn >> 12) & 0x7;
+    let op = match funct3 & 0x3 {
+        1 => CsrOp::ReadWrite,
+        2 => CsrOp::ReadSet,
+        3 => CsrOp::ReadClear,
+        _ => return None,
+    };
+
+    Some(CsrInstruction {
+        csr: (insn >> 20) as u16,
+        op,
+        rd: ((insn >> 7) & 0x1F) as u8,
+        rs1: ((insn >> 15) & 0x1F) as u8,
+        imm: funct3 & 0x4 != 0,
+    })
+}
+
+/// Instruction word of an illegal or virtual instruction trap
+///
+/// Taken from htinst, or from the trap value when the hardware leaves
+/// htinst zero.
+pub fn trapped_instruction(htinst: usize, tval: usize) -> u32 {
+    if htinst != 0 { htinst as u32 } else { tval as u32 }
+}
+
+/// Check whether `csr` is a counter emulated for guests
+pub fn is_emulated_counter(csr: u16) -> bool {
+    (counter_csr::CYCLE..=counter_csr::HPMCOUNTER31).contains(&csr)
+}
+
+/// Guest view of the counter CSRs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CounterShadow {
+    /// Cycles the guest has run for
+    pub cycle: u64,
+    /// Instructions the guest has retired
+    pub instret: u64,
+    /// Offset from host time to guest time (htimedelta)
+    pub time_delta: u64,
+}
+
+impl CounterShadow {
+    /// Read a counter CSR at host time `host_time`
+    ///
+    /// Returns `None` if `csr` is not an emulated counter.
+    pub fn read(&self, csr: u16, host_time: u64) -> Option<u64> {
+        match csr {
+            counter_csr::CYCLE => Some(self.cycle),
+            counter_csr::TIME => Some(host_time.wrapping_add(self.time_delta)),
+            counter_csr::INSTRET => Some(self.instret),
+            counter_csr::HPMCOUNTER3..=counter_csr::HPMCOUNTER31 => Some(0),
+            _ => None,
+        }
+    }
+}
+
+/// Emulate a trapped CSR instruction against the shadow counters
+///
+/// Writes the counter value to `rd` and advances `pc` past the
+/// instruction, which is never compressed. Fails without touching the
+/// registers if the CSR is not an emulated counter or would be written.
+pub fn emulate_csr_instruction(
+    insn: &CsrInstruction,
+    shadow: &CounterShadow,
+    host_time: u64,
+    gpr: &mut [usize; 32],
+    pc: &mut usize,
+) -> Result<(), Error> {
+    let value = shadow.read(insn.csr, host_time)
+        .ok_or(Error::Unsupported("CSR not emulated"))?;
+    if insn.writes() {
+        return Err(Error::PermissionDenied("Write to read-only counter CSR"));
+    }
+
+    // x0 is hardwired to zero
+    if insn.rd != 0 {
+        gpr[insn.rd as usize] = value as usize;
+    }
+    *pc += 4;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// csrr a0, cycle (csrrs a0, cycle, x0)
+    const CSRR_A0_CYCLE: u32 = 0xc000_2573;
+    /// csrr a1, time
+    const CSRR_A1_TIME: u32 = 0xc010_25f3;
+    /// csrw cycle, a0 (csrrw x0, cycle, a0)
+    const CSRW_CYCLE_A0: u32 = 0xc005_1073;
+
+    #[test]
+    fn test_decode_csr_instructions() {
+        assert_eq!(decode_csr_instruction(CSRR_A0_CYCLE), Some(CsrInstruction {
+            csr: counter_csr::CYCLE, op: CsrOp::ReadSet, rd: 10, rs1: 0, imm: false,
+        }));
+        assert!(decode_csr_instruction(CSRW_CYCLE_A0).unwrap().writes());
+        // csrrci a0, cycle, 0 only reads
+        assert!(!decode_csr_instruction(0xc000_7573).unwrap().writes());
+        // ecall and wfi are SYSTEM but not CSR instructions
+        assert_eq!(decode_csr_instruction(0x0000_0073), None);
+        assert_eq!(decode_csr_instruction(0x1050_0073), None);
+    }
+
+    #[test]
+    fn test_emulated_counter_read() {
+        let shadow = CounterShadow { cycle: 0x1234_5678_9abc, instret: 7, time_delta: 1_000 };
+        let mut gpr = [0usize; 32];
+        let mut pc = 0x8000_0000;
+
+        let insn = decode_csr_instruction(CSRR_A1_TIME).unwrap();
+        emulate_csr_instruction(&insn, &shadow, 500, &mut gpr, &mut pc).unwrap();
+        assert_eq!(gpr[11], 1_500);
+        assert_eq!(pc, 0x8000_0004);
+
+        // Writes and unknown CSRs leave the guest untouched
+        let insn = decode_csr_instruction(CSRW_CYCLE_A0).unwrap();
+        assert!(emulate_csr_instruction(&insn, &shadow, 0, &mut gpr, &mut pc).is_err());
+        // csrr a0, sstatus
+        let insn = decode_csr_instruction(0x1000_2573).unwrap();
+        assert!(emulate_csr_instruction(&insn, &shadow, 0, &mut gpr, &mut pc).is_err());
+        assert_eq!(pc, 0x8000_0004);
+        assert_eq!(gpr[10], 0);
+    }
+}
@@ -8,6 +8,7 @@
 
 use crate::arch::riscv64::cpu::csr::*;
 use crate::arch::riscv64::cpu::csr::{ExceptionCode, InterruptCause};
+use crate::arch::riscv64::Error;
 use bitflags::bitflags;
 use core::sync::atomic::{AtomicUsize, Ordering};
 
@@ -116,7 +117,7 @@ impl ExceptionDelegationManager {
     }
 
     /// Initialize delegation registers based on configuration
-    pub fn init(&self) -> Result<(), &'static str> {
+    pub fn init(&self) -> Result<(), Error> {
         log::debug!("Initializing exception delegation");
 
         // Configure HEDELEG
@@ -130,7 +131,7 @@ impl ExceptionDelegationManager {
     }
 
     /// Configure HEDELEG register
-    fn configure_hedeleg(&self) -> Result<(), &'static str> {
+    fn configure_hedeleg(&self) -> Result<(), Error> {
         let hedeleg = match self.config.exception_policy {
             ExceptionDelegationPolicy::None => Hedeleg::empty(),
             ExceptionDelegationPolicy::Safe => {
@@ -169,7 +170,7 @@ impl ExceptionDelegationManager {
     }
 
     /// Configure HIDELEG register
-    fn configure_hideleg(&self) -> Result<(), &'static str> {
+    fn configure_hideleg(&self) -> Result<(), Error> {
         let hideleg = match self.config.interrupt_policy {
             InterruptDelegationPolicy::None => Hideleg::empty(),
             InterruptDelegationPolicy::All => {
@@ -271,7 +272,7 @@ impl ExceptionDelegationManager {
     }
 
     /// Update delegation configuration
-    pub fn update_config(&mut self, config: DelegationConfig) -> Result<(), &'static str> {
+    pub fn update_config(&mut self, config: DelegationConfig) -> Result<(), Error> {
         self.config = config;
         self.init()
     }
@@ -298,7 +299,7 @@ impl ExceptionDelegationManager {
 
     /// Enable/disable specific exception delegation
     pub fn set_exception_delegation(&self, exception: ExceptionCode,
-                                   enable: bool) -> Result<(), &'static str> {
+                                   enable: bool) -> Result<(), Error> {
         let mut hedeleg = HEDELEG::read();
         let bit = match exception {
             ExceptionCode::InstructionMisaligned => Hedeleg::INSTRUCTION_MISALIGNED,
@@ -331,7 +332,7 @@ impl ExceptionDelegationManager {
 
     /// Enable/disable specific interrupt delegation
     pub fn set_interrupt_delegation(&self, interrupt: InterruptCause,
-                                   enable: bool) -> Result<(), &'static str> {
+                                   enable: bool) -> Result<(), Error> {
         let mut hideleg = HIDELEG::read();
         let bit = match interrupt {
             InterruptCause::SupervisorSoftware => Hideleg::SSIP,
@@ -378,7 +379,7 @@ impl Default for ExceptionDelegationManager {
 static mut EXCEPTION_DELEGATION: Option<ExceptionDelegationManager> = None;
 
 /// Initialize global exception delegation
-pub fn init() -> Result<(), &'static str> {
+pub fn init() -> Result<(), Error> {
     log::info!("Initializing RISC-V exception delegation");
 
     let config = DelegationConfig::default();
@@ -439,11 +440,11 @@ pub fn handle_interrupt(interrupt: InterruptCause,
 }
 
 /// Configure delegation policy
-pub fn configure_policy(policy: DelegationConfig) -> Result<(), &'static str> {
+pub fn configure_policy(policy: DelegationConfig) -> Result<(), Error> {
     if let Some(manager) = get_manager_mut() {
         manager.update_config(policy)
     } else {
-        Err("Delegation manager not initialized")
+        Err(Error::NotInitialized("Delegation manager not initialized"))
     }
 }
 
@@ -454,21 +455,21 @@ pub fn get_current_policy() -> Option<DelegationConfig> {
 
 /// Enable/disable specific exception delegation
 pub fn configure_exception_delegation(exception: ExceptionCode,
-                                     enable: bool) -> Result<(), &'static str> {
+                                     enable: bool) -> Result<(), Error> {
     if let Some(manager) = get_manager() {
         manager.set_exception_delegation(exception, enable)
     } else {
-        Err("Delegation manager not initialized")
+        Err(Error::NotInitialized("Delegation manager not initialized"))
     }
 }
 
 /// Enable/disable specific interrupt delegation
 pub fn configure_interrupt_delegation(interrupt: InterruptCause,
-                                    enable: bool) -> Result<(), &'static str> {
+                                    enable: bool) -> Result<(), Error> {
     if let Some(manager) = get_manager() {
         manager.set_interrupt_delegation(interrupt, enable)
     } else {
-        Err("Delegation manager not initialized")
+        Err(Error::NotInitialized("Delegation manager not initialized"))
     }
 }
 
@@ -478,12 +479,12 @@ pub fn get_delegation_stats() -> Option<DelegationStatsSnapshot> {
 }
 
 /// Reset delegation statistics
-pub fn reset_delegation_stats() -> Result<(), &'static str> {
+pub fn reset_delegation_stats() -> Result<(), Error> {
     if let Some(manager) = get_manager_mut() {
         manager.reset_stats();
         Ok(())
     } else {
-        Err("Delegation manager not initialized")
+        Err(Error::NotInitialized("Delegation manager not initialized"))
     }
 }
 
@@ -0,0 +1,585 @@
+//! Virtual AIA (APLIC + IMSIC) for RISC-V guests
+//!
+//! Guests using the Advanced Interrupt Architecture see one IMSIC
+//! interrupt file per vCPU, mapped as a 4KiB page in guest physical
+//! memory, plus an APLIC in MSI delivery mode that turns wired sources
+//! into MSIs:
+//! - a write to a vCPU's `seteipnum` sets the identity pending in its file
+//! - the file's highest-priority enabled pending identity is its `topei`
+//! - while `topei` is non-zero and delivery is enabled, the vCPU's
+//!   VS-level external interrupt (VSEIP) is raised
+//! - the guest claims `topei` through the `stopei` CSR, which is trapped
+//!   and emulated here along with `vsiselect`/`vsireg`
+
+use crate::arch::riscv64::virtualization::VcpuId;
+use crate::arch::riscv64::Error;
+use alloc::vec::Vec;
+
+/// Interrupt identities per file (identity 0 is reserved)
+pub const IMSIC_NUM_IDS: usize = 2048;
+
+/// Size of one guest interrupt file page
+pub const IMSIC_PAGE_SIZE: u64 = 0x1000;
+
+/// IMSIC page register offsets
+pub mod imsic_reg {
+    /// Little-endian set-pending register
+    pub const SETEIPNUM_LE: u64 = 0x000;
+    /// Big-endian set-pending register
+    pub const SETEIPNUM_BE: u64 = 0x004;
+}
+
+/// Indirectly accessed IMSIC registers (`vsiselect` values)
+pub mod iselect {
+    /// Interrupt delivery enable
+    pub const EIDELIVERY: u64 = 0x70;
+    /// Interrupt enable threshold
+    pub const EITHRESHOLD: u64 = 0x72;
+    /// First interrupt-pending register
+    pub const EIP0: u64 = 0x80;
+    /// Last interrupt-pending register
+    pub const EIP63: u64 = 0xBF;
+    /// First interrupt-enable register
+    pub const EIE0: u64 = 0xC0;
+    /// Last interrupt-enable register
+    pub const EIE63: u64 = 0xFF;
+}
+
+/// Words of 64 identities in a file
+const ID_WORDS: usize = IMSIC_NUM_IDS / 64;
+
+/// One vCPU's virtual IMSIC interrupt file
+#[derive(Debug, Clone)]
+pub struct VirtualImsicFile {
+    /// Interrupt delivery enabled
+    eidelivery: bool,
+    /// Only identities below this are delivered (0 = no threshold)
+    eithreshold: u32,
+    /// Pending identities
+    eip: [u64; ID_WORDS],
+    /// Enabled identities
+    eie: [u64; ID_WORDS],
+}
+
+impl VirtualImsicFile {
+    /// Create a file with delivery disabled and nothing pending
+    pub const fn new() -> Self {
+        Self {
+            eidelivery: false,
+            eithreshold: 0,
+            eip: [0; ID_WORDS],
+            eie: [0; ID_WORDS],
+        }
+    }
+
+    /// Mark an identity pending, as an MSI write does
+    ///
+    /// Identity 0 and out-of-range identities are ignored.
+    pub fn set_pending(&mut self, id: u32) {
+        let id = id as usize;
+        if id != 0 && id < IMSIC_NUM_IDS {
+            self.eip[id / 64] |= 1 << (id % 64);
+        }
+    }
+
+    /// Enable or disable an identity
+    pub fn set_enabled(&mut self, id: u32, enabled: bool) {
+        let id = id as usize;
+        if id != 0 && id < IMSIC_NUM_IDS {
+            if enabled {
+                self.eie[id / 64] |= 1 << (id % 64);
+            } else {
+                self.eie[id / 64] &= !(1 << (id % 64));
+            }
+        }
+    }
+
+    /// Check whether an identity is pending
+    pub fn is_pending(&self, id: u32) -> bool {
+        let id = id as usize;
+        id < IMSIC_NUM_IDS && self.eip[id / 64] & (1 << (id % 64)) != 0
+    }
+
+    /// Highest-priority deliverable identity, 0 if none
+    fn top_id(&self) -> u32 {
+        for (word, (&pending, &enabled)) in self.eip.iter().zip(self.eie.iter()).enumerate() {
+            let ready = pending & enabled;
+            if ready != 0 {
+                let id = (word * 64) as u32 + ready.trailing_zeros();
+                if self.eithreshold != 0 && id >= self.eithreshold {
+                    return 0;
+                }
+                return id;
+            }
+        }
+        0
+    }
+
+    /// Value of the `topei` register: identity in 26:16, priority in 10:0
+    pub fn topei(&self) -> u32 {
+        let id = self.top_id();
+        (id << 16) | id
+    }
+
+    /// Claim the top identity, as a `stopei` write does
+    ///
+    /// Returns the `topei` value before the claim.
+    pub fn claim(&mut self) -> u32 {
+        let topei = self.topei();
+        let id = (topei >> 16) as usize;
+        if id != 0 {
+            self.eip[id / 64] &= !(1 << (id % 64));
+        }
+        topei
+    }
+
+    /// Check whether the file asserts the external interrupt
+    pub fn external_pending(&self) -> bool {
+        self.eidelivery && self.top_id() != 0
+    }
+
+    /// Read an indirectly accessed register
+    pub fn read_indirect(&self, select: u64) -> Result<u64, Error> {
+        match select {
+            iselect::EIDELIVERY => Ok(self.eidelivery as u64),
+            iselect::EITHRESHOLD => Ok(self.eithreshold as u64),
+            iselect::EIP0..=iselect::EIP63 => self.word(&self.eip, select - iselect::EIP0),
+            iselect::EIE0..=iselect::EIE63 => self.word(&self.eie, select - iselect::EIE0),
+            _ => Err(Error::InvalidArgument("Invalid IMSIC register select")),
+        }
+    }
+
+    /// Write an indirectly accessed register
+    pub fn write_indirect(&mut self, select: u64, value: u64) -> Result<(), Error> {
+        match select {
+            iselect::EIDELIVERY => self.eidelivery = value & 1 != 0,
+            iselect::EITHRESHOLD => self.eithreshold = (value as u32) & (IMSIC_NUM_IDS as u32 - 1),
+            iselect::EIP0..=iselect::EIP63 => {
+                let index = Self::word_index(select - iselect::EIP0)?;
+                // Identity 0 never becomes pending
+                self.eip[index] = if index == 0 { value & !1 } else { value };
+            }
+            iselect::EIE0..=iselect::EIE63 => {
+                let index = Self::word_index(select - iselect::EIE0)?;
+                self.eie[index] = if index == 0 { value & !1 } else { value };
+            }
+            _ => return Err(Error::InvalidArgument("Invalid IMSIC register select")),
+        }
+        Ok(())
+    }
+
+    /// Map an eip/eie register offset to a 64-bit word
+    ///
+    /// On RV64 only even-numbered registers exist.
+    fn word_index(offset: u64) -> Result<usize, Error> {
+        if offset % 2 != 0 {
+            return Err(Error::InvalidArgument("Odd IMSIC register on RV64"));
+        }
+        let index = (offset / 2) as usize;
+        if index >= ID_WORDS {
+            return Err(Error::InvalidArgument("IMSIC register beyond implemented identities"));
+        }
+        Ok(index)
+    }
+
+    fn word(&self, bits: &[u64; ID_WORDS], offset: u64) -> Result<u64, Error> {
+        Ok(bits[Self::word_index(offset)?])
+    }
+}
+
+/// APLIC register offsets
+pub mod aplic_reg {
+    /// Domain configuration
+    pub const DOMAINCFG: u64 = 0x0000;
+    /// Source configuration for source 1
+    pub const SOURCECFG_BASE: u64 = 0x0004;
+    /// Set pending by source number
+    pub const SETIPNUM: u64 = 0x1CDC;
+    /// Set enable by source number
+    pub const SETIENUM: u64 = 0x1EDC;
+    /// Clear enable by source number
+    pub const CLRIENUM: u64 = 0x1FDC;
+    /// Target for source 1
+    pub const TARGET_BASE: u64 = 0x3004;
+}
+
+/// domaincfg: interrupt enable
+const DOMAINCFG_IE: u32 = 1 << 8;
+/// domaincfg: MSI delivery mode
+const DOMAINCFG_DM: u32 = 1 << 2;
+
+/// Number of wired sources (source 0 is reserved)
+pub const APLIC_NUM_SOURCES: usize = 1024;
+
+/// Virtual APLIC in MSI delivery mode
+#[derive(Debug, Clone)]
+pub struct VirtualAplic {
+    /// Domain configuration
+    domaincfg: u32,
+    /// Per-source configuration (0 = inactive)
+    sourcecfg: Vec<u32>,
+    /// Per-source target: hart index in 31:18, EIID in 10:0
+    target: Vec<u32>,
+    /// Per-source enable
+    enabled: Vec<bool>,
+    /// Per-source pending, latched while the source or domain is disabled
+    pending: Vec<bool>,
+}
+
+impl VirtualAplic {
+    /// Create an APLIC with all sources inactive
+    pub fn new() -> Self {
+        Self {
+            domaincfg: DOMAINCFG_DM,
+            sourcecfg: alloc::vec![0; APLIC_NUM_SOURCES],
+            target: alloc::vec![0; APLIC_NUM_SOURCES],
+            enabled: alloc::vec![false; APLIC_NUM_SOURCES],
+            pending: alloc::vec![false; APLIC_NUM_SOURCES],
+        }
+    }
+
+    /// Read a register
+    pub fn read(&self, offset: u64) -> u32 {
+        match offset {
+            aplic_reg::DOMAINCFG => self.domaincfg | 0x8000_0000,
+            o if (aplic_reg::SOURCECFG_BASE..aplic_reg::SOURCECFG_BASE + 4 * (APLIC_NUM_SOURCES as u64 - 1)).contains(&o) => {
+                self.sourcecfg[((o - aplic_reg::SOURCECFG_BASE) / 4 + 1) as usize]
+            }
+            o if (aplic_reg::TARGET_BASE..aplic_reg::TARGET_BASE + 4 * (APLIC_NUM_SOURCES as u64 - 1)).contains(&o) => {
+                self.target[((o - aplic_reg::TARGET_BASE) / 4 + 1) as usize]
+            }
+            _ => 0,
+        }
+    }
+
+    /// Write a register
+    ///
+    /// Returns the `(hart index, EIID)` MSIs to deliver: a source made
+    /// pending while enabled, or sources latched pending that the write
+    /// enabled.
+    pub fn write(&mut self, offset: u64, value: u32) -> Vec<(usize, u32)> {
+        match offset {
+            // Only IE is writable; MSI delivery mode is fixed
+            aplic_reg::DOMAINCFG => {
+                self.domaincfg = (value & DOMAINCFG_IE) | DOMAINCFG_DM;
+                return self.forward_pending(1..APLIC_NUM_SOURCES);
+            }
+            aplic_reg::SETIPNUM => return self.raise(value as usize).into_iter().collect(),
+            aplic_reg::SETIENUM => {
+                let source = value as usize;
+                self.set_source_enabled(source, true);
+                return self.forward_pending(source..source + 1);
+            }
+            aplic_reg::CLRIENUM => self.set_source_enabled(value as usize, false),
+            o if (aplic_reg::SOURCECFG_BASE..aplic_reg::SOURCECFG_BASE + 4 * (APLIC_NUM_SOURCES as u64 - 1)).contains(&o) => {
+                self.sourcecfg[((o - aplic_reg::SOURCECFG_BASE) / 4 + 1) as usize] = value & 0x7FF;
+            }
+            o if (aplic_reg::TARGET_BASE..aplic_reg::TARGET_BASE + 4 * (APLIC_NUM_SOURCES as u64 - 1)).contains(&o) => {
+                self.target[((o - aplic_reg::TARGET_BASE) / 4 + 1) as usize] = value & 0xFFFC_07FF;
+            }
+            _ => {}
+        }
+        Vec::new()
+    }
+
+    fn set_source_enabled(&mut self, source: usize, enabled: bool) {
+        if source != 0 && source < APLIC_NUM_SOURCES {
+            self.enabled[source] = enabled;
+        }
+    }
+
+    /// Signal a wired source
+    ///
+    /// Returns the `(hart index, EIID)` MSI the source is routed to, if
+    /// the domain and source are enabled. Otherwise an active source stays
+    /// pending until it is enabled.
+    pub fn raise(&mut self, source: usize) -> Option<(usize, u32)> {
+        if source == 0 || source >= APLIC_NUM_SOURCES || self.sourcecfg[source] == 0 {
+            return None;
+        }
+
+        self.pending[source] = true;
+        self.forward(source)
+    }
+
+    /// Check whether a source is pending
+    pub fn is_pending(&self, source: usize) -> bool {
+        self.pending.get(source).copied().unwrap_or(false)
+    }
+
+    /// Forward a pending source as an MSI if it can be delivered
+    fn forward(&mut self, source: usize) -> Option<(usize, u32)> {
+        let deliverable = self.pending[source]
+            && self.domaincfg & DOMAINCFG_IE != 0
+            && self.sourcecfg[source] != 0
+            && self.enabled[source];
+        if !deliverable {
+            return None;
+        }
+
+        self.pending[source] = false;
+        let target = self.target[source];
+        Some(((target >> 18) as usize, target & 0x7FF))
+    }
+
+    /// Forward every deliverable pending source in `sources`
+    fn forward_pending(&mut self, sources: core::ops::Range<usize>) -> Vec<(usize, u32)> {
+        let sources = sources.start.max(1)..sources.end.min(APLIC_NUM_SOURCES);
+        sources.filter_map(|source| self.forward(source)).collect()
+    }
+}
+
+/// Callback driving a vCPU's VS-level external interrupt line
+pub type VseipFn = fn(vcpu_id: VcpuId, pending: bool);
+
+/// Virtual AIA for one VM
+pub struct VirtualAia {
+    /// Guest physical base of the vCPU interrupt file pages
+    imsic_base: u64,
+    /// Interrupt files, indexed by vCPU
+    files: Vec<VirtualImsicFile>,
+    /// Per-vCPU `vsiselect`
+    vsiselect: Vec<u64>,
+    /// Wired-interrupt front end
+    aplic: VirtualAplic,
+    /// VSEIP delivery
+    set_vseip: VseipFn,
+}
+
+impl VirtualAia {
+    /// Create a virtual AIA with one interrupt file per vCPU
+    pub fn new(imsic_base: u64, num_vcpus: usize) -> Self {
+        Self {
+            imsic_base,
+            files: alloc::vec![VirtualImsicFile::new(); num_vcpus],
+            vsiselect: alloc::vec![0; num_vcpus],
+            aplic: VirtualAplic::new(),
+            set_vseip: inject_vseip,
+        }
+    }
+
+    /// Replace the VSEIP delivery callback
+    pub fn set_vseip_handler(&mut self, handler: VseipFn) {
+        self.set_vseip = handler;
+    }
+
+    /// Get a vCPU's interrupt file
+    pub fn file(&self, vcpu: usize) -> Option<&VirtualImsicFile> {
+        self.files.get(vcpu)
+    }
+
+    /// Get the virtual APLIC
+    pub fn aplic(&mut self) -> &mut VirtualAplic {
+        &mut self.aplic
+    }
+
+    /// Map a guest physical address to `(vcpu, page offset)`
+    pub fn decode_imsic(&self, gpa: u64) -> Option<(usize, u64)> {
+        let offset = gpa.checked_sub(self.imsic_base)?;
+        let vcpu = (offset / IMSIC_PAGE_SIZE) as usize;
+        if vcpu >= self.files.len() {
+            return None;
+        }
+        Some((vcpu, offset % IMSIC_PAGE_SIZE))
+    }
+
+    /// Handle a guest or device write to an IMSIC page
+    pub fn imsic_write(&mut self, gpa: u64, value: u32) -> Result<(), Error> {
+        let (vcpu, offset) = self.decode_imsic(gpa).ok_or(Error::InvalidArgument("Address outside virtual IMSIC"))?;
+        let id = match offset {
+            imsic_reg::SETEIPNUM_LE => value,
+            imsic_reg::SETEIPNUM_BE => value.swap_bytes(),
+            // Other page offsets are reserved and ignored
+            _ => return Ok(()),
+        };
+        self.send_msi(vcpu, id)
+    }
+
+    /// Deliver an MSI with identity `id` to a vCPU
+    pub fn send_msi(&mut self, vcpu: usize, id: u32) -> Result<(), Error> {
+        let file = self.files.get_mut(vcpu).ok_or(Error::InvalidArgument("Invalid vCPU"))?;
+        file.set_pending(id);
+        self.update(vcpu);
+        Ok(())
+    }
+
+    /// Handle a guest write to the virtual APLIC
+    pub fn aplic_write(&mut self, offset: u64, value: u32) -> Result<(), Error> {
+        for (hart, eiid) in self.aplic.write(offset, value) {
+            self.send_msi(hart, eiid)?;
+        }
+        Ok(())
+    }
+
+    /// Signal a wired source through the virtual APLIC
+    pub fn raise_source(&mut self, source: usize) -> Result<(), Error> {
+        match self.aplic.raise(source) {
+            Some((hart, eiid)) => self.send_msi(hart, eiid),
+            None => Ok(()),
+        }
+    }
+
+    /// Emulate a guest access to `stopei`
+    ///
+    /// Reads return `topei`; any write claims the top identity.
+    pub fn stopei(&mut self, vcpu: usize, write: bool) -> Result<u64, Error> {
+        let file = self.files.get_mut(vcpu).ok_or(Error::InvalidArgument("Invalid vCPU"))?;
+        let topei = if write { file.claim() } else { file.topei() };
+        self.update(vcpu);
+        Ok(topei as u64)
+    }
+
+    /// Emulate a guest write to `vsiselect`
+    pub fn write_vsiselect(&mut self, vcpu: usize, value: u64) -> Result<(), Error> {
+        *self.vsiselect.get_mut(vcpu).ok_or(Error::InvalidArgument("Invalid vCPU"))? = value;
+        Ok(())
+    }
+
+    /// Emulate a guest read of `vsireg`
+    pub fn read_vsireg(&self, vcpu: usize) -> Result<u64, Error> {
+        let file = self.files.get(vcpu).ok_or(Error::InvalidArgument("Invalid vCPU"))?;
+        file.read_indirect(self.vsiselect[vcpu])
+    }
+
+    /// Emulate a guest write to `vsireg`
+    pub fn write_vsireg(&mut self, vcpu: usize, value: u64) -> Result<(), Error> {
+        let select = *self.vsiselect.get(vcpu).ok_or(Error::InvalidArgument("Invalid vCPU"))?;
+        self.files[vcpu].write_indirect(select, value)?;
+        self.update(vcpu);
+        Ok(())
+    }
+
+    /// Drive a vCPU's VSEIP from its interrupt file
+    fn update(&self, vcpu: usize) {
+        (self.set_vseip)(vcpu as VcpuId, self.files[vcpu].external_pending());
+    }
+}
+
+/// Default VSEIP delivery through the virtual interrupt controller
+fn inject_vseip(vcpu_id: VcpuId, pending: bool) {
+    use crate::arch::riscv64::virtualization::vintc::{self, VirtualInterruptFlags, VirtualInterruptType};
+
+    if pending {
+        let result = vintc::inject_interrupt(vcpu_id, VirtualInterruptType::SupervisorExternal,
+                                             VirtualInterruptFlags::LEVEL_TRIGGERED);
+        if let Some(e) = result.error {
+            log::warn!("Virtual AIA: failed to raise VSEIP on vCPU {}: {}", vcpu_id, e);
+        }
+    } else {
+        let _ = vintc::clear_interrupt(vcpu_id, VirtualInterruptType::SupervisorExternal);
+    }
+}
+
+/// Initialize virtual AIA support
+pub fn init() -> Result<(), Error> {
+    log::debug!("Initializing virtual AIA subsystem");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicU64, Ordering};
+
+    /// VSEIP level per vCPU, one bit each
+    static VSEIP: AtomicU64 = AtomicU64::new(0);
+
+    fn record_vseip(vcpu_id: VcpuId, pending: bool) {
+        if pending {
+            VSEIP.fetch_or(1 << vcpu_id, Ordering::SeqCst);
+        } else {
+            VSEIP.fetch_and(!(1 << vcpu_id), Ordering::SeqCst);
+        }
+    }
+
+    fn enable(aia: &mut VirtualAia, vcpu: usize, id: u32) {
+        aia.write_vsiselect(vcpu, iselect::EIDELIVERY).unwrap();
+        aia.write_vsireg(vcpu, 1).unwrap();
+        let select = iselect::EIE0 + (id as u64 / 64) * 2;
+        aia.write_vsiselect(vcpu, select).unwrap();
+        let bits = aia.read_vsireg(vcpu).unwrap();
+        aia.write_vsireg(vcpu, bits | 1 << (id % 64)).unwrap();
+    }
+
+    #[test]
+    fn test_msi_sets_topei_and_raises_vseip() {
+        let mut aia = VirtualAia::new(0x2800_0000, 2);
+        aia.set_vseip_handler(record_vseip);
+        enable(&mut aia, 1, 37);
+
+        // MSI to vCPU 1's page
+        aia.imsic_write(0x2800_0000 + IMSIC_PAGE_SIZE, 37).unwrap();
+
+        assert_eq!(aia.file(1).unwrap().topei(), (37 << 16) | 37);
+        assert_ne!(VSEIP.load(Ordering::SeqCst) & 0b10, 0);
+        assert_eq!(aia.file(0).unwrap().topei(), 0);
+
+        // Claiming through stopei drops the line
+        assert_eq!(aia.stopei(1, true).unwrap(), (37 << 16) | 37);
+        assert_eq!(aia.file(1).unwrap().topei(), 0);
+        assert_eq!(VSEIP.load(Ordering::SeqCst) & 0b10, 0);
+    }
+
+    #[test]
+    fn test_topei_priority_and_threshold() {
+        let mut file = VirtualImsicFile::new();
+        for id in [5, 9, 200] {
+            file.set_enabled(id, true);
+            file.set_pending(id);
+        }
+        // Disabled identities never surface
+        file.set_pending(3);
+
+        assert_eq!(file.topei() >> 16, 5);
+        file.write_indirect(iselect::EITHRESHOLD, 5).unwrap();
+        assert_eq!(file.topei(), 0);
+        file.write_indirect(iselect::EITHRESHOLD, 0).unwrap();
+
+        assert_eq!(file.claim() >> 16, 5);
+        assert_eq!(file.claim() >> 16, 9);
+        assert_eq!(file.claim() >> 16, 200);
+        assert_eq!(file.claim(), 0);
+        assert!(file.is_pending(3));
+    }
+
+    #[test]
+    fn test_aplic_routes_source_as_msi() {
+        let mut aia = VirtualAia::new(0x2800_0000, 2);
+        enable(&mut aia, 0, 12);
+
+        aia.aplic_write(aplic_reg::DOMAINCFG, DOMAINCFG_IE).unwrap();
+        aia.aplic_write(aplic_reg::SOURCECFG_BASE + 4 * 6, 0x4).unwrap();
+        aia.aplic_write(aplic_reg::TARGET_BASE + 4 * 6, 12).unwrap();
+
+        // Source 7 is not enabled yet, so the raise is latched
+        aia.raise_source(7).unwrap();
+        assert_eq!(aia.file(0).unwrap().topei(), 0);
+        assert!(aia.aplic().is_pending(7));
+
+        // Enabling the source delivers the latched raise
+        aia.aplic_write(aplic_reg::SETIENUM, 7).unwrap();
+        assert!(!aia.aplic().is_pending(7));
+        assert_eq!(aia.file(0).unwrap().topei() >> 16, 12);
+
+        aia.stopei(0, true).unwrap();
+        aia.raise_source(7).unwrap();
+        assert_eq!(aia.file(0).unwrap().topei() >> 16, 12);
+    }
+
+    #[test]
+    fn test_aplic_delivers_pending_when_domain_enabled() {
+        let mut aplic = VirtualAplic::new();
+        aplic.write(aplic_reg::SOURCECFG_BASE + 4 * 2, 0x4);
+        aplic.write(aplic_reg::TARGET_BASE + 4 * 2, (1 << 18) | 40);
+        aplic.write(aplic_reg::SETIENUM, 3);
+
+        // Domain disabled: latched, not delivered
+        assert_eq!(aplic.raise(3), None);
+        // Inactive sources never latch
+        assert_eq!(aplic.raise(4), None);
+        assert!(!aplic.is_pending(4));
+
+        assert_eq!(aplic.write(aplic_reg::DOMAINCFG, DOMAINCFG_IE), [(1, 40)]);
+        assert!(aplic.write(aplic_reg::DOMAINCFG, DOMAINCFG_IE).is_empty());
+    }
+}
@@ -5,9 +5,11 @@
 /// - Virtual NIC
 /// - VirtIO devices
 /// - Platform devices
+/// - Virtual AIA (APLIC/IMSIC)
 
 use crate::arch::riscv64::virtualization::vm::*;
 use crate::arch::riscv64::virtualization::vcpu::*;
+use crate::arch::riscv64::Error;
 
 /// Re-export virtual device traits
 pub use super::vm::VirtualDevice;
@@ -25,8 +27,11 @@ pub mod virtio;
 /// Platform virtual devices
 pub mod platform;
 
+/// Virtual AIA (APLIC/IMSIC)
+pub mod aia;
+
 /// Initialize virtual device subsystem
-pub fn init() -> Result<(), &'static str> {
+pub fn init() -> Result<(), Error> {
     log::info!("Initializing virtual device subsystem");
 
     // Initialize device frameworks
@@ -34,6 +39,7 @@ pub fn init() -> Result<(), &'static str> {
     nic::init()?;
     virtio::init()?;
     platform::init()?;
+    aia::init()?;
 
     log::info!("Virtual device subsystem initialized");
     Ok(())
@@ -1,9 +1,10 @@
 //! Virtual NIC Device
 
 use crate::arch::riscv64::virtualization::vm::*;
+use crate::arch::riscv64::Error;
 
 /// Initialize virtual NIC subsystem
-pub fn init() -> Result<(), &'static str> {
+pub fn init() -> Result<(), Error> {
     log::debug!("Initializing virtual NIC subsystem");
     Ok(())
 }
\ No newline at end of file
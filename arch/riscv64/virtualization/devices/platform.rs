@@ -1,9 +1,10 @@
 //! Platform Virtual Devices
 
 use crate::arch::riscv64::virtualization::vm::*;
+use crate::arch::riscv64::Error;
 
 /// Initialize platform device subsystem
-pub fn init() -> Result<(), &'static str> {
+pub fn init() -> Result<(), Error> {
     log::debug!("Initializing platform device subsystem");
     Ok(())
 }
\ No newline at end of file
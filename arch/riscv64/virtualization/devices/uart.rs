@@ -1,9 +1,101 @@
 //! Virtual UART Device
+//!
+//! A transmit-only 16550 console: bytes the guest writes to THR are
+//! collected and handed to the VM's diagnostic log, the line status
+//! register always reports the transmitter empty, and the receiver never
+//! has data.
 
 use crate::arch::riscv64::virtualization::vm::*;
+use crate::arch::riscv64::Error;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+
+/// Transmit holding register (divisor latch low while DLAB is set)
+const UART_THR: usize = 0;
+/// Line control register
+const UART_LCR: usize = 3;
+/// Line status register
+const UART_LSR: usize = 5;
+/// Size of the register window
+const UART_MMIO_SIZE: usize = 8;
+
+/// LCR: divisor latch access
+const UART_LCR_DLAB: u8 = 1 << 7;
+/// LSR: transmit holding register empty
+const UART_LSR_THRE: u8 = 1 << 5;
+/// LSR: transmitter empty
+const UART_LSR_TEMT: u8 = 1 << 6;
+
+/// Guest console UART
+pub struct VirtualUart {
+    /// MMIO window and device parameters
+    config: VmDeviceConfig,
+    /// Line control register
+    lcr: u8,
+    /// Bytes written since the last `take_console_output`
+    output: Vec<u8>,
+}
+
+impl VirtualUart {
+    /// Create a console UART with its registers at `base_addr`
+    pub fn new(base_addr: usize) -> Self {
+        Self {
+            config: VmDeviceConfig {
+                device_type: "uart".to_string(),
+                base_addr,
+                mmio_size: UART_MMIO_SIZE,
+                num_irqs: 0,
+                params: Default::default(),
+            },
+            lcr: 0,
+            output: Vec::new(),
+        }
+    }
+}
+
+impl VirtualDevice for VirtualUart {
+    fn device_id(&self) -> u32 {
+        0
+    }
+
+    fn device_name(&self) -> &str {
+        "uart"
+    }
+
+    fn init(&mut self, _vm: &mut VirtualMachine) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn handle_mmio(&mut self, gpa: usize, is_write: bool, value: u64) -> Result<u64, Error> {
+        let dlab = self.lcr & UART_LCR_DLAB != 0;
+        match (gpa - self.config.base_addr, is_write) {
+            (UART_THR, true) if !dlab => self.output.push(value as u8),
+            (UART_LCR, true) => self.lcr = value as u8,
+            (UART_LCR, false) => return Ok(self.lcr as u64),
+            (UART_LSR, false) => return Ok((UART_LSR_THRE | UART_LSR_TEMT) as u64),
+            _ => {}
+        }
+        Ok(0)
+    }
+
+    fn handle_interrupt(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn get_config(&self) -> &VmDeviceConfig {
+        &self.config
+    }
+
+    fn take_console_output(&mut self) -> Option<Vec<u8>> {
+        if self.output.is_empty() {
+            return None;
+        }
+        Some(core::mem::take(&mut self.output))
+    }
+}
 
 /// Initialize virtual UART subsystem
-pub fn init() -> Result<(), &'static str> {
+pub fn init() -> Result<(), Error> {
     log::debug!("Initializing virtual UART subsystem");
     Ok(())
-}
\ No newline at end of file
+}
@@ -1,9 +1,10 @@
 //! VirtIO Device Framework
 
 use crate::arch::riscv64::virtualization::vm::*;
+use crate::arch::riscv64::Error;
 
 /// Initialize VirtIO subsystem
-pub fn init() -> Result<(), &'static str> {
+pub fn init() -> Result<(), Error> {
     log::debug!("Initializing VirtIO subsystem");
     Ok(())
 }
\ No newline at end of file
@@ -16,6 +16,7 @@ use crate::arch::riscv64::virtualization::devices::{VirtualDevice, VmDeviceConfi
 use crate::arch::riscv64::virtualization::{VmId, VcpuId};
 use crate::core::mm::{PhysAddr, VirtAddr};
 use crate::drivers::{DeviceType, DeviceId, DeviceStatus};
+use crate::arch::riscv64::Error;
 use alloc::collections::{BTreeMap, VecDeque};
 use alloc::string::{String, ToString};
 use alloc::sync::Arc;
@@ -372,12 +373,12 @@ impl VirtualDeviceDiscovery {
     }
 
     /// Discover devices from device tree
-    pub fn discover_from_device_tree(&mut self, fdt: &FlattenedDeviceTree) -> Result<Vec<DeviceId>, &'static str> {
+    pub fn discover_from_device_tree(&mut self, fdt: &FlattenedDeviceTree) -> Result<Vec<DeviceId>, Error> {
         let start_time = crate::utils::time::get_microseconds();
         let mut discovered_devices = Vec::new();
 
         // Get root node
-        let root = fdt.get_root().ok_or("No root node in device tree")?;
+        let root = fdt.get_root().ok_or(Error::NotFound("No root node in device tree"))?;
 
         // Discover platform devices
         self.discover_platform_devices(fdt, &root, &mut discovered_devices)?;
@@ -406,7 +407,7 @@ impl VirtualDeviceDiscovery {
         fdt: &FlattenedDeviceTree,
         root: &crate::arch::riscv64::devtree::fdt::Node,
         discovered_devices: &mut Vec<DeviceId>,
-    ) -> Result<(), &'static str> {
+    ) -> Result<(), Error> {
         // Look for common platform devices
         let platform_device_paths = [
             "/soc/serial",
@@ -444,7 +445,7 @@ impl VirtualDeviceDiscovery {
         fdt: &FlattenedDeviceTree,
         node: &crate::arch::riscv64::devtree::fdt::Node,
         path: &str,
-    ) -> Result<Option<VirtualDeviceDesc>, &'static str> {
+    ) -> Result<Option<VirtualDeviceDesc>, Error> {
         // Get compatible property
         let compatible = if let Some(prop) = node.get_property("compatible") {
             let compat_str = core::str::from_utf8(prop.data).unwrap_or("");
@@ -492,7 +493,7 @@ impl VirtualDeviceDiscovery {
         &mut self,
         fdt: &FlattenedDeviceTree,
         discovered_devices: &mut Vec<DeviceId>,
-    ) -> Result<(), &'static str> {
+    ) -> Result<(), Error> {
         // Look for VirtIO devices in device tree
         let mut virtio_device_count = 0;
 
@@ -554,7 +555,7 @@ impl VirtualDeviceDiscovery {
         mmio_base: u64,
         device_type: DeviceType,
         instance: u32,
-    ) -> Result<VirtualDeviceDesc, &'static str> {
+    ) -> Result<VirtualDeviceDesc, Error> {
         // Read VirtIO device features
         let features = unsafe {
             core::ptr::read_volatile((mmio_base + 0x010) as *const u32)
@@ -703,7 +704,7 @@ impl VirtualDeviceDiscovery {
     }
 
     /// Determine device class from compatible strings
-    fn determine_device_class(&self, compatible: &[String]) -> Result<DeviceClass, &'static str> {
+    fn determine_device_class(&self, compatible: &[String]) -> Result<DeviceClass, Error> {
         // Check for specific compatible strings
         for compat_str in compatible {
             if compat_str.contains("virtio,net") {
@@ -726,7 +727,7 @@ impl VirtualDeviceDiscovery {
     }
 
     /// Map device type to device class
-    fn device_type_to_class(&self, device_type: DeviceType) -> Result<DeviceClass, &'static str> {
+    fn device_type_to_class(&self, device_type: DeviceType) -> Result<DeviceClass, Error> {
         let class_id = match device_type {
             DeviceType::Network => 1,
             DeviceType::Block => 2,
@@ -739,7 +740,7 @@ impl VirtualDeviceDiscovery {
 
         self.classes.get(&class_id)
             .cloned()
-            .ok_or("Device class not found")
+            .ok_or(Error::NotFound("Device class not found"))
     }
 
     /// Parse device resources from device tree
@@ -747,7 +748,7 @@ impl VirtualDeviceDiscovery {
         &self,
         fdt: &FlattenedDeviceTree,
         node: &crate::arch::riscv64::devtree::fdt::Node,
-    ) -> Result<DeviceResources, &'static str> {
+    ) -> Result<DeviceResources, Error> {
         let mut resources = DeviceResources::default();
 
         // Parse memory regions from 'reg' property
@@ -821,7 +822,7 @@ impl VirtualDeviceDiscovery {
         &mut self,
         fdt: &FlattenedDeviceTree,
         discovered_devices: &mut Vec<DeviceId>,
-    ) -> Result<(), &'static str> {
+    ) -> Result<(), Error> {
         // Look for common interrupt controllers
         let ic_paths = [
             "/interrupt-controller@0",
@@ -854,7 +855,7 @@ impl VirtualDeviceDiscovery {
         fdt: &FlattenedDeviceTree,
         node: &crate::arch::riscv64::devtree::fdt::Node,
         path: &str,
-    ) -> Result<VirtualDeviceDesc, &'static str> {
+    ) -> Result<VirtualDeviceDesc, Error> {
         // Get compatible string
         let compatible = if let Some(prop) = node.get_property("compatible") {
             let compat_str = core::str::from_utf8(prop.data).unwrap_or("");
@@ -893,7 +894,7 @@ impl VirtualDeviceDiscovery {
         &mut self,
         fdt: &FlattenedDeviceTree,
         discovered_devices: &mut Vec<DeviceId>,
-    ) -> Result<(), &'static str> {
+    ) -> Result<(), Error> {
         // Look for CPU nodes
         if let Some(cpus_node) = fdt.find_node("/cpus") {
             // Iterate through CPU nodes
@@ -921,7 +922,7 @@ impl VirtualDeviceDiscovery {
         &self,
         fdt: &FlattenedDeviceTree,
         node: &crate::arch::riscv64::devtree::fdt::Node,
-    ) -> Result<VirtualDeviceDesc, &'static str> {
+    ) -> Result<VirtualDeviceDesc, Error> {
         let name = node.name.clone();
 
         // Get CPU capabilities
@@ -1051,7 +1052,7 @@ impl VirtualDeviceDiscovery {
     }
 
     /// Process hotplug events
-    pub fn process_hotplug_events(&mut self) -> Result<Vec<DeviceId>, &'static str> {
+    pub fn process_hotplug_events(&mut self) -> Result<Vec<DeviceId>, Error> {
         let mut new_devices = Vec::new();
 
         while let Some(event) = self.hotplug_queue.pop_front() {
@@ -12,6 +12,7 @@ use crate::arch::riscv64::virtualization::VmConfig;
 use crate::arch::riscv64::virtualization::{VmId, VcpuId};
 use crate::core::mm::PhysAddr;
 use crate::drivers::{DeviceId, DeviceType, DeviceStatus};
+use crate::arch::riscv64::Error;
 use alloc::collections::BTreeMap;
 use alloc::string::{String, ToString};
 use alloc::sync::Arc;
@@ -21,16 +22,16 @@ use core::sync::atomic::{AtomicU32, Ordering};
 /// Device discovery manager interface
 pub trait DeviceDiscoveryManager {
     /// Discover all available devices
-    fn discover_all_devices(&mut self) -> Result<Vec<DeviceId>, &'static str>;
+    fn discover_all_devices(&mut self) -> Result<Vec<DeviceId>, Error>;
 
     /// Discover devices for a specific VM
-    fn discover_vm_devices(&mut self, vm_config: &VmConfig) -> Result<Vec<DeviceId>, &'static str>;
+    fn discover_vm_devices(&mut self, vm_config: &VmConfig) -> Result<Vec<DeviceId>, Error>;
 
     /// Add a new device dynamically
-    fn add_device(&mut self, device_desc: VirtualDeviceDesc) -> Result<DeviceId, &'static str>;
+    fn add_device(&mut self, device_desc: VirtualDeviceDesc) -> Result<DeviceId, Error>;
 
     /// Remove a device
-    fn remove_device(&mut self, device_id: DeviceId) -> Result<(), &'static str>;
+    fn remove_device(&mut self, device_id: DeviceId) -> Result<(), Error>;
 
     /// Get device information
     fn get_device_info(&self, device_id: DeviceId) -> Option<&VirtualDeviceDesc>;
@@ -42,7 +43,7 @@ pub trait DeviceDiscoveryManager {
     fn get_stats(&self) -> &DiscoveryStats;
 
     /// Process hotplug events
-    fn process_hotplug(&mut self) -> Result<Vec<DeviceId>, &'static str>;
+    fn process_hotplug(&mut self) -> Result<Vec<DeviceId>, Error>;
 }
 
 /// Main device discovery manager implementation
@@ -72,7 +73,7 @@ impl RiscvDeviceDiscoveryManager {
     }
 
     /// Initialize with global device tree
-    pub fn init_with_fdt(&mut self, fdt: FlattenedDeviceTree) -> Result<(), &'static str> {
+    pub fn init_with_fdt(&mut self, fdt: FlattenedDeviceTree) -> Result<(), Error> {
         self.global_fdt = Some(fdt.clone());
 
         // Discover all devices from the device tree
@@ -87,9 +88,9 @@ impl RiscvDeviceDiscoveryManager {
     }
 
     /// Create VM-specific device tree
-    pub fn create_vm_device_tree(&self, vm_config: &VmConfig) -> Result<FlattenedDeviceTree, &'static str> {
+    pub fn create_vm_device_tree(&self, vm_config: &VmConfig) -> Result<FlattenedDeviceTree, Error> {
         let fdt = self.global_fdt.as_ref()
-            .ok_or("No global device tree available")?;
+            .ok_or(Error::NotFound("No global device tree available"))?;
 
         // Create VM-specific device tree
         let mut vm_fdt = fdt.clone();
@@ -101,7 +102,7 @@ impl RiscvDeviceDiscoveryManager {
     }
 
     /// Filter devices for VM based on configuration
-    fn filter_devices_for_vm(&self, fdt: &mut FlattenedDeviceTree, vm_config: &VmConfig) -> Result<(), &'static str> {
+    fn filter_devices_for_vm(&self, fdt: &mut FlattenedDeviceTree, vm_config: &VmConfig) -> Result<(), Error> {
         // Remove devices that are not assigned to this VM
         let assigned_devices = self.vm_assignments.get(&vm_config.vm_id);
 
@@ -119,7 +120,7 @@ impl RiscvDeviceDiscoveryManager {
         node: &mut crate::arch::riscv64::devtree::fdt::Node,
         assigned_devices: Option<&Vec<DeviceId>>,
         vm_config: &VmConfig,
-    ) -> Result<bool, &'static str> {
+    ) -> Result<bool, Error> {
         let mut keep_node = true;
 
         // Check if node should be kept based on VM configuration
@@ -154,16 +155,16 @@ impl RiscvDeviceDiscoveryManager {
     }
 
     /// Assign device to VM
-    pub fn assign_device_to_vm(&mut self, device_id: DeviceId, vm_id: VmId) -> Result<(), &'static str> {
+    pub fn assign_device_to_vm(&mut self, device_id: DeviceId, vm_id: VmId) -> Result<(), Error> {
         // Check if device exists
         if !self.discovery.devices.contains_key(&device_id) {
-            return Err("Device not found");
+            return Err(Error::NotFound("Device not found"));
         }
 
         // Check if device is already assigned to another VM
         for (existing_vm_id, devices) in &self.vm_assignments {
             if *existing_vm_id != vm_id && devices.contains(&device_id) {
-                return Err("Device already assigned to another VM");
+                return Err(Error::Busy("Device already assigned to another VM"));
             }
         }
 
@@ -179,17 +180,17 @@ impl RiscvDeviceDiscoveryManager {
     }
 
     /// Unassign device from VM
-    pub fn unassign_device_from_vm(&mut self, device_id: DeviceId, vm_id: VmId) -> Result<(), &'static str> {
+    pub fn unassign_device_from_vm(&mut self, device_id: DeviceId, vm_id: VmId) -> Result<(), Error> {
         if let Some(devices) = self.vm_assignments.get_mut(&vm_id) {
             if let Some(pos) = devices.iter().position(|&id| id == device_id) {
                 devices.remove(pos);
                 log::info!("Unassigned device {:?} from VM {:?}", device_id, vm_id);
                 Ok(())
             } else {
-                Err("Device not assigned to this VM")
+                Err(Error::PermissionDenied("Device not assigned to this VM"))
             }
         } else {
-            Err("VM not found")
+            Err(Error::NotFound("VM not found"))
         }
     }
 
@@ -291,17 +292,17 @@ impl RiscvDeviceDiscoveryManager {
 }
 
 impl DeviceDiscoveryManager for RiscvDeviceDiscoveryManager {
-    fn discover_all_devices(&mut self) -> Result<Vec<DeviceId>, &'static str> {
+    fn discover_all_devices(&mut self) -> Result<Vec<DeviceId>, Error> {
         if let Some(fdt) = &self.global_fdt {
             let discovered_devices = self.discovery.discover_from_device_tree(fdt)?;
             self.update_compat_cache();
             Ok(discovered_devices)
         } else {
-            Err("No device tree available for discovery")
+            Err(Error::NotFound("No device tree available for discovery"))
         }
     }
 
-    fn discover_vm_devices(&mut self, vm_config: &VmConfig) -> Result<Vec<DeviceId>, &'static str> {
+    fn discover_vm_devices(&mut self, vm_config: &VmConfig) -> Result<Vec<DeviceId>, Error> {
         // Discover all devices first
         let all_devices = self.discover_all_devices()?;
 
@@ -321,7 +322,7 @@ impl DeviceDiscoveryManager for RiscvDeviceDiscoveryManager {
         Ok(vm_devices)
     }
 
-    fn add_device(&mut self, device_desc: VirtualDeviceDesc) -> Result<DeviceId, &'static str> {
+    fn add_device(&mut self, device_desc: VirtualDeviceDesc) -> Result<DeviceId, Error> {
         let device_id = device_desc.device_id;
         self.discovery.devices.insert(device_id, device_desc);
         self.update_compat_cache();
@@ -332,7 +333,7 @@ impl DeviceDiscoveryManager for RiscvDeviceDiscoveryManager {
         Ok(device_id)
     }
 
-    fn remove_device(&mut self, device_id: DeviceId) -> Result<(), &'static str> {
+    fn remove_device(&mut self, device_id: DeviceId) -> Result<(), Error> {
         // Remove from all VM assignments
         for (_, devices) in self.vm_assignments.iter_mut() {
             devices.retain(|&id| id != device_id);
@@ -370,7 +371,7 @@ impl DeviceDiscoveryManager for RiscvDeviceDiscoveryManager {
         self.discovery.get_stats()
     }
 
-    fn process_hotplug(&mut self) -> Result<Vec<DeviceId>, &'static str> {
+    fn process_hotplug(&mut self) -> Result<Vec<DeviceId>, Error> {
         let new_devices = self.discovery.process_hotplug_events()?;
         self.update_compat_cache();
         Ok(new_devices)
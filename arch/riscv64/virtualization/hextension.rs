@@ -29,6 +29,9 @@ pub mod hcsr {
     /// Hypervisor counter enable register
     pub const HCOUNTEREN: usize = 0x606;
 
+    /// Hypervisor environment configuration register
+    pub const HENVCFG: usize = csr::HENVCFG;
+
     /// Hypervisor guest external interrupt enable register
     pub const HGEIE: usize = 0x607;
 
@@ -297,9 +300,9 @@ impl HExtensionManager {
     }
 
     /// Initialize H extension
-    pub fn init(&mut self) -> Result<(), &'static str> {
+    pub fn init(&mut self) -> Result<(), Error> {
         if !Self::is_available() {
-            return Err("H extension not supported");
+            return Err(Error::Unsupported("H extension not supported"));
         }
 
         log::info!("Initializing RISC-V H extension");
@@ -313,6 +316,9 @@ impl HExtensionManager {
         // Configure counter enable for virtualization
         self.configure_counter_enable()?;
 
+        // Enable Svpbmt memory types for guests if implemented
+        self.configure_envcfg();
+
         // Enable H extension
         self.enabled = true;
 
@@ -321,11 +327,14 @@ impl HExtensionManager {
     }
 
     /// Configure HSTATUS register
-    fn configure_hstatus(&self) -> Result<(), &'static str> {
+    fn configure_hstatus(&self) -> Result<(), Error> {
         let mut hstatus = HSTATUS::read();
 
         // Clear virtualization bits
-        hstatus &= !(Hstatus::VTSR | Hstatus::VTW | Hstatus::VTVM);
+        hstatus &= !(Hstatus::VTSR | Hstatus::VTVM);
+
+        // Trap guest WFI so idle VCPUs can be descheduled
+        hstatus |= Hstatus::VTW;
 
         // Enable hypervisor features based on configuration
         if self.config.enable_two_stage_translation {
@@ -337,7 +346,7 @@ impl HExtensionManager {
     }
 
     /// Configure exception and interrupt delegation
-    fn configure_delegation(&self) -> Result<(), &'static str> {
+    fn configure_delegation(&self) -> Result<(), Error> {
         // Use delegation module to configure registers
         if let Some(deleg_manager) = crate::arch::riscv64::virtualization::delegation::get_manager() {
             log::debug!("Using delegation module for configuration");
@@ -353,8 +362,28 @@ impl HExtensionManager {
         }
     }
 
+    /// Enable henvcfg.PBMTE when the hart implements Svpbmt
+    ///
+    /// PBMTE reads back as zero if M-mode has not enabled Svpbmt in
+    /// menvcfg, so the readback decides whether page tables may use PBMT.
+    fn configure_envcfg(&self) {
+        const PBMTE: usize = 1 << 62;
+
+        let features = crate::arch::riscv64::cpu::features::get_cpu_info().features;
+        let enabled = if features.contains(crate::arch::riscv64::cpu::features::CpuFeatures::HAS_SVPBMT) {
+            let henvcfg = UsizeCsr(hcsr::HENVCFG);
+            henvcfg.set(PBMTE);
+            henvcfg.read() & PBMTE != 0
+        } else {
+            false
+        };
+
+        crate::core::mm::set_pbmt_enabled(enabled);
+        log::debug!("Svpbmt memory types {}", if enabled { "enabled" } else { "unavailable" });
+    }
+
     /// Configure counter enable for virtualization
-    fn configure_counter_enable(&self) -> Result<(), &'static str> {
+    fn configure_counter_enable(&self) -> Result<(), Error> {
         // Enable counters for guest access
         let mut hcounteren = 0usize;
         hcounteren |= 1 << 0; // Cycle
@@ -368,7 +397,7 @@ impl HExtensionManager {
     }
 
     /// Allocate a VMID
-    pub fn allocate_vmid(&mut self) -> Result<u16, &'static str> {
+    pub fn allocate_vmid(&mut self) -> Result<u16, Error> {
         self.vmid_allocator.allocate()
     }
 
@@ -388,9 +417,9 @@ impl HExtensionManager {
     }
 
     /// Enable virtualization mode for entering guest
-    pub fn enter_virtualization(&self, guest_csr: &GuestCsrState) -> Result<(), &'static str> {
+    pub fn enter_virtualization(&self, guest_csr: &GuestCsrState) -> Result<(), Error> {
         if !self.enabled {
-            return Err("H extension not enabled");
+            return Err(Error::InvalidState("H extension not enabled"));
         }
 
         // Save current hypervisor state if needed
@@ -407,9 +436,9 @@ impl HExtensionManager {
     }
 
     /// Exit virtualization mode
-    pub fn exit_virtualization(&self) -> Result<HypervisorTrapInfo, &'static str> {
+    pub fn exit_virtualization(&self) -> Result<HypervisorTrapInfo, Error> {
         if !self.enabled {
-            return Err("H extension not enabled");
+            return Err(Error::InvalidState("H extension not enabled"));
         }
 
         // Save guest CSR state
@@ -418,8 +447,9 @@ impl HExtensionManager {
         // Read trap information
         let trap_info = HypervisorTrapInfo {
             guest_csr,
-            cause: crate::arch::riscv64::cpu::csr::read_csr!(crate::arch::riscv64::cpu::csr::HGATP), // This is just placeholder
+            cause: crate::arch::riscv64::cpu::csr::read_csr!(crate::arch::riscv64::cpu::csr::SCAUSE),
             tval: crate::arch::riscv64::cpu::csr::read_csr!(crate::arch::riscv64::cpu::csr::HTVAL),
+            gva: crate::arch::riscv64::cpu::csr::read_csr!(crate::arch::riscv64::cpu::csr::STVAL),
             htinst: crate::arch::riscv64::cpu::csr::read_csr!(hcsr::HTINST),
         };
 
@@ -451,7 +481,7 @@ impl VmidAllocator {
     }
 
     /// Allocate a VMID
-    pub fn allocate(&mut self) -> Result<u16, &'static str> {
+    pub fn allocate(&mut self) -> Result<u16, Error> {
         // Try to reuse a freed VMID
         if let Some(vmid) = self.free_vmid.pop() {
             return Ok(vmid);
@@ -463,7 +493,7 @@ impl VmidAllocator {
             self.next_vmid += 1;
             Ok(vmid)
         } else {
-            Err("No available VMID")
+            Err(Error::OutOfResources("No available VMID"))
         }
     }
 
@@ -527,6 +557,33 @@ impl GuestCsrState {
         crate::arch::riscv64::cpu::csr::write_csr!(hcsr::VSATP, self.vsatp);
     }
 
+    /// Take exception `cause` with trap value `tval` at guest `pc`, from
+    /// VS-mode if `from_supervisor` and from VU-mode otherwise
+    ///
+    /// Updates the VS-level trap CSRs as hardware would: VSEPC holds `pc`,
+    /// VSSTATUS.SPP the previous privilege and SPIE the previous SIE,
+    /// which is cleared. Returns the PC of the guest's trap handler;
+    /// exceptions use the vector base even when VSTVEC is in vectored mode.
+    pub fn enter_trap(&mut self, cause: usize, tval: usize, pc: usize, from_supervisor: bool) -> usize {
+        let sie = SstatusFlags::SIE.bits();
+        let spie = SstatusFlags::SPIE.bits();
+        let spp = SstatusFlags::SPP.bits();
+
+        let mut vsstatus = self.vsstatus & !(sie | spie | spp);
+        if self.vsstatus & sie != 0 {
+            vsstatus |= spie;
+        }
+        if from_supervisor {
+            vsstatus |= spp;
+        }
+
+        self.vsstatus = vsstatus;
+        self.vscause = cause;
+        self.vstval = tval;
+        self.vsepc = pc;
+        self.vstvec & !0x3
+    }
+
     /// Create a new guest CSR state with default values
     pub fn new() -> Self {
         Self {
@@ -558,6 +615,8 @@ pub struct HypervisorTrapInfo {
     pub cause: usize,
     /// Trap value
     pub tval: usize,
+    /// Faulting guest virtual address
+    pub gva: usize,
     /// Trap instruction
     pub htinst: usize,
 }
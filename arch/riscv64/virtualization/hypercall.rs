@@ -0,0 +1,305 @@
+//! RISC-V Hypercall ABI
+//!
+//! Guests issue hypercalls with a supervisor-mode `ecall`:
+//! - `a7` holds the hypercall number (see `HypercallId`)
+//! - `a0`-`a5` hold up to six arguments
+//! - on return `a0` holds an error code (0 on success, negative SBI-style
+//!   codes otherwise) and `a1` the result value
+//!
+//! Call numbers are stable. Numbers below `HYPERCALL_VENDOR_BASE` are
+//! reserved for the hypervisor; paravirt drivers register their own calls
+//! in the vendor range and can query `HypercallId::AbiVersion` to find out
+//! what the hypervisor supports.
+
+use crate::core::sync::SpinLock;
+use crate::arch::riscv64::Error;
+use alloc::collections::BTreeMap;
+
+/// Hypercall ABI version, major in the upper 16 bits
+pub const HYPERCALL_ABI_VERSION: usize = 0x0001_0001;
+
+/// First call number available to vendor hypercalls
+pub const HYPERCALL_VENDOR_BASE: usize = 0x1000;
+
+/// Register holding the hypercall number (a7)
+const REG_ID: usize = 17;
+/// First argument register (a0)
+const REG_ARG0: usize = 10;
+/// Number of argument registers (a0-a5)
+const NUM_ARGS: usize = 6;
+
+/// Hypercall number
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HypercallId {
+    /// Forward to the virtual SBI implementation
+    Sbi,
+    /// Request guest shutdown
+    Shutdown,
+    /// Query the hypercall ABI version
+    AbiVersion,
+    /// Give up the CPU until an interrupt is pending for the calling VCPU
+    Yield,
+    /// Append a message to the VM's diagnostic log (see `vm_log`)
+    Log,
+    /// Register the calling VCPU's paravirtual clock page (see `pvclock`)
+    Pvclock,
+    /// Vendor hypercall, numbered from `HYPERCALL_VENDOR_BASE`
+    Vendor(usize),
+}
+
+impl HypercallId {
+    /// Raw call number passed in a7
+    pub fn raw(self) -> usize {
+        match self {
+            HypercallId::Sbi => 0,
+            HypercallId::Shutdown => 1,
+            HypercallId::AbiVersion => 2,
+            HypercallId::Yield => 3,
+            HypercallId::Log => 4,
+            HypercallId::Pvclock => 5,
+            HypercallId::Vendor(n) => HYPERCALL_VENDOR_BASE + n,
+        }
+    }
+
+    /// Decode a raw call number
+    pub fn from_raw(raw: usize) -> Option<Self> {
+        match raw {
+            0 => Some(HypercallId::Sbi),
+            1 => Some(HypercallId::Shutdown),
+            2 => Some(HypercallId::AbiVersion),
+            3 => Some(HypercallId::Yield),
+            4 => Some(HypercallId::Log),
+            5 => Some(HypercallId::Pvclock),
+            n if n >= HYPERCALL_VENDOR_BASE => Some(HypercallId::Vendor(n - HYPERCALL_VENDOR_BASE)),
+            _ => None,
+        }
+    }
+}
+
+/// Error returned to the guest in a0
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HypercallError {
+    /// Generic failure
+    Failed,
+    /// Unknown or unregistered call number
+    NotSupported,
+    /// Invalid argument
+    InvalidParam,
+    /// Caller not allowed to make this call
+    Denied,
+}
+
+impl HypercallError {
+    /// Error code as seen by the guest, matching SBI error values
+    pub fn code(self) -> isize {
+        match self {
+            HypercallError::Failed => -1,
+            HypercallError::NotSupported => -2,
+            HypercallError::InvalidParam => -3,
+            HypercallError::Denied => -4,
+        }
+    }
+}
+
+/// Hypercall arguments from a0-a5
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HypercallArgs {
+    pub args: [usize; NUM_ARGS],
+}
+
+/// Hypercall result value or error
+pub type HypercallResult = core::result::Result<usize, HypercallError>;
+
+/// Hypercall handler
+pub type HypercallHandler = fn(&HypercallArgs) -> HypercallResult;
+
+/// Table of registered hypercalls
+pub struct HypercallRegistry {
+    handlers: BTreeMap<usize, HypercallHandler>,
+}
+
+impl HypercallRegistry {
+    /// Create an empty registry
+    pub const fn new() -> Self {
+        Self {
+            handlers: BTreeMap::new(),
+        }
+    }
+
+    /// Register a handler for a call number
+    pub fn register(&mut self, id: HypercallId, handler: HypercallHandler) -> Result<(), Error> {
+        if matches!(id, HypercallId::AbiVersion | HypercallId::Yield | HypercallId::Log | HypercallId::Pvclock) {
+            return Err(Error::PermissionDenied("Hypercall is reserved"));
+        }
+        if self.handlers.contains_key(&id.raw()) {
+            return Err(Error::Busy("Hypercall already registered"));
+        }
+        self.handlers.insert(id.raw(), handler);
+        Ok(())
+    }
+
+    /// Remove the handler for a call number
+    pub fn unregister(&mut self, id: HypercallId) -> Result<(), Error> {
+        self.handlers.remove(&id.raw()).map(|_| ()).ok_or(Error::NotFound("Hypercall not registered"))
+    }
+
+    /// Look up the handler for a raw call number
+    pub fn handler(&self, raw: usize) -> Option<HypercallHandler> {
+        self.handlers.get(&raw).copied()
+    }
+
+    /// Dispatch a raw call number
+    pub fn dispatch(&self, raw: usize, args: &HypercallArgs) -> HypercallResult {
+        dispatch_with(self.handler(raw), raw, args)
+    }
+}
+
+impl Default for HypercallRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Run a looked-up handler, answering the ABI version query directly
+fn dispatch_with(handler: Option<HypercallHandler>, raw: usize, args: &HypercallArgs) -> HypercallResult {
+    if raw == HypercallId::AbiVersion.raw() {
+        return Ok(HYPERCALL_ABI_VERSION);
+    }
+
+    match handler {
+        Some(handler) => handler(args),
+        None => {
+            log::warn!("Unknown hypercall: {:#x}", raw);
+            Err(HypercallError::NotSupported)
+        }
+    }
+}
+
+/// Decode a hypercall from guest registers and write back the result
+///
+/// Returns the raw call number that was handled.
+pub fn handle_registers(regs: &mut [usize; 32], lookup: impl FnOnce(usize) -> Option<HypercallHandler>) -> usize {
+    let raw = regs[REG_ID];
+    let mut args = HypercallArgs::default();
+    args.args.copy_from_slice(&regs[REG_ARG0..REG_ARG0 + NUM_ARGS]);
+
+    let (error, value) = match dispatch_with(lookup(raw), raw, &args) {
+        Ok(value) => (0, value),
+        Err(err) => (err.code(), 0),
+    };
+    regs[REG_ARG0] = error as usize;
+    regs[REG_ARG0 + 1] = value;
+    raw
+}
+
+/// Registered hypercalls
+static HYPERCALLS: SpinLock<HypercallRegistry> = SpinLock::new(HypercallRegistry::new());
+
+/// Register a hypercall handler
+pub fn register_hypercall(id: HypercallId, handler: HypercallHandler) -> Result<(), Error> {
+    HYPERCALLS.lock().register(id, handler)
+}
+
+/// Unregister a hypercall handler
+pub fn unregister_hypercall(id: HypercallId) -> Result<(), Error> {
+    HYPERCALLS.lock().unregister(id)
+}
+
+/// Built-in yield: deschedule the calling VCPU until an interrupt is pending
+fn yield_vcpu(_args: &HypercallArgs) -> HypercallResult {
+    crate::core::sched::vcpu_idle(crate::core::sched::IdleReason::Yield)
+        .map(|_| 0)
+        .map_err(|_| HypercallError::Failed)
+}
+
+/// Handle a hypercall using the trapping VCPU's general purpose registers
+///
+/// Returns `false`, leaving the registers untouched, if no handler is
+/// registered here; such calls are left to the VM (see `VcpuExit::Hypercall`).
+pub fn dispatch_hypercall(regs: &mut [usize; 32]) -> bool {
+    let raw = regs[REG_ID];
+    // Release the registry before calling out so handlers may register
+    let handler = if raw == HypercallId::Yield.raw() {
+        Some(yield_vcpu as HypercallHandler)
+    } else {
+        HYPERCALLS.lock().handler(raw)
+    };
+    if handler.is_none() && raw != HypercallId::AbiVersion.raw() {
+        return false;
+    }
+
+    handle_registers(regs, |_| handler);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add_args(args: &HypercallArgs) -> HypercallResult {
+        Ok(args.args.iter().sum())
+    }
+
+    fn reject(_args: &HypercallArgs) -> HypercallResult {
+        Err(HypercallError::InvalidParam)
+    }
+
+    #[test]
+    fn test_id_encoding() {
+        for id in [HypercallId::Sbi, HypercallId::Shutdown, HypercallId::AbiVersion, HypercallId::Yield, HypercallId::Log, HypercallId::Pvclock, HypercallId::Vendor(7)] {
+            assert_eq!(HypercallId::from_raw(id.raw()), Some(id));
+        }
+        assert_eq!(HypercallId::Vendor(7).raw(), HYPERCALL_VENDOR_BASE + 7);
+        assert_eq!(HypercallId::from_raw(0x20), None);
+    }
+
+    #[test]
+    fn test_custom_hypercall_dispatched() {
+        let mut registry = HypercallRegistry::new();
+        registry.register(HypercallId::Vendor(1), add_args).unwrap();
+        registry.register(HypercallId::Vendor(2), reject).unwrap();
+        assert!(registry.register(HypercallId::Vendor(1), reject).is_err());
+        assert!(registry.register(HypercallId::AbiVersion, reject).is_err());
+
+        let args = HypercallArgs { args: [1, 2, 3, 4, 5, 6] };
+        assert_eq!(registry.dispatch(HypercallId::Vendor(1).raw(), &args), Ok(21));
+        assert_eq!(registry.dispatch(HypercallId::Vendor(2).raw(), &args), Err(HypercallError::InvalidParam));
+        assert_eq!(registry.dispatch(HypercallId::AbiVersion.raw(), &args), Ok(HYPERCALL_ABI_VERSION));
+
+        registry.unregister(HypercallId::Vendor(1)).unwrap();
+        assert_eq!(registry.dispatch(HypercallId::Vendor(1).raw(), &args), Err(HypercallError::NotSupported));
+    }
+
+    #[test]
+    fn test_guest_registers() {
+        let mut registry = HypercallRegistry::new();
+        registry.register(HypercallId::Vendor(1), add_args).unwrap();
+
+        let mut regs = [0usize; 32];
+        regs[REG_ID] = HypercallId::Vendor(1).raw();
+        regs[REG_ARG0] = 40;
+        regs[REG_ARG0 + 1] = 2;
+        handle_registers(&mut regs, |raw| registry.handler(raw));
+        assert_eq!(regs[REG_ARG0], 0);
+        assert_eq!(regs[REG_ARG0 + 1], 42);
+
+        // Unknown ids report not-supported to the guest
+        regs[REG_ID] = HypercallId::Vendor(99).raw();
+        handle_registers(&mut regs, |raw| registry.handler(raw));
+        assert_eq!(regs[REG_ARG0] as isize, HypercallError::NotSupported.code());
+        assert_eq!(regs[REG_ARG0 + 1], 0);
+    }
+
+    #[test]
+    fn test_unregistered_calls_left_to_vm() {
+        let mut regs = [0usize; 32];
+        regs[REG_ID] = HypercallId::Log.raw();
+        regs[REG_ARG0] = 7;
+        assert!(!dispatch_hypercall(&mut regs));
+        assert_eq!(regs[REG_ARG0], 7);
+
+        regs[REG_ID] = HypercallId::AbiVersion.raw();
+        assert!(dispatch_hypercall(&mut regs));
+        assert_eq!((regs[REG_ARG0], regs[REG_ARG0 + 1]), (0, HYPERCALL_ABI_VERSION));
+    }
+}
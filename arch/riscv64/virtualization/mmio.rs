@@ -0,0 +1,259 @@
+//! RISC-V MMIO Access Decoding
+//!
+//! This module decodes the guest load/store that caused an MMIO guest-page
+//! fault and caches the result per VCPU:
+//! - Standard loads and stores (LB..LD, LBU..LWU, SB..SD)
+//! - Compressed C.LW/C.LD/C.SW/C.SD
+//! - A small direct-mapped cache keyed by guest address space (vsatp) and
+//!   faulting guest PC, so hot device registers skip fetching and
+//!   re-decoding the instruction when the hardware does not report it
+
+/// Number of entries in the MMIO decode cache
+pub const MMIO_CACHE_ENTRIES: usize = 16;
+
+/// Decoded MMIO load or store
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MmioAccess {
+    /// Access size in bytes
+    pub size: u8,
+    /// Store (true) or load (false)
+    pub is_write: bool,
+    /// Destination register for loads, source register for stores
+    pub reg: u8,
+    /// Sign-extend loaded value into the register
+    pub sign_extend: bool,
+    /// Instruction length in bytes, to advance the guest PC
+    pub len: u8,
+}
+
+/// Decode a guest load/store instruction
+///
+/// Returns `None` for anything that is not a plain integer load or store.
+pub fn decode_mmio_access(insn: u32) -> Option<MmioAccess> {
+    if insn & 0x3 != 0x3 {
+        return decode_compressed(insn as u16);
+    }
+
+    let funct3 = (insn >> 12) & 0x7;
+    match insn & 0x7F {
+        // LOAD
+        0x03 => {
+            let (size, sign_extend) = match funct3 {
+                0 => (1, true),
+                1 => (2, true),
+                2 => (4, true),
+                3 => (8, false),
+                4 => (1, false),
+                5 => (2, false),
+                6 => (4, false),
+                _ => return None,
+            };
+            Some(MmioAccess {
+                size,
+                is_write: false,
+                reg: ((insn >> 7) & 0x1F) as u8,
+                sign_extend,
+                len: 4,
+            })
+        }
+        // STORE
+        0x23 if funct3 <= 3 => Some(MmioAccess {
+            size: 1 << funct3,
+            is_write: true,
+            reg: ((insn >> 20) & 0x1F) as u8,
+            sign_extend: false,
+            len: 4,
+        }),
+        _ => None,
+    }
+}
+
+/// Decode a quadrant 0 compressed load/store
+fn decode_compressed(insn: u16) -> Option<MmioAccess> {
+    if insn & 0x3 != 0 {
+        return None;
+    }
+
+    // rd' (loads) and rs2' (stores) share bits 4:2
+    let reg = (((insn >> 2) & 0x7) + 8) as u8;
+    let (size, is_write) = match (insn >> 13) & 0x7 {
+        0b010 => (4, false), // C.LW
+        0b011 => (8, false), // C.LD
+        0b110 => (4, true),  // C.SW
+        0b111 => (8, true),  // C.SD
+        _ => return None,
+    };
+
+    Some(MmioAccess {
+        size,
+        is_write,
+        reg,
+        sign_extend: !is_write && size == 4,
+        len: 2,
+    })
+}
+
+/// Fetch the guest instruction at guest virtual address `pc`
+///
+/// Reads one halfword first so a compressed instruction at the end of a
+/// page does not touch the next one.
+pub fn fetch_guest_instruction(pc: usize) -> Option<u32> {
+    let low = crate::arch::riscv64::cpu::asm::hlvx_hu(pc) as u32;
+    if low & 0x3 != 0x3 {
+        return Some(low);
+    }
+    let high = crate::arch::riscv64::cpu::asm::hlvx_hu(pc + 2) as u32;
+    Some(low | (high << 16))
+}
+
+/// MMIO decode cache statistics
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MmioCacheStats {
+    /// Lookups answered from the cache
+    pub hits: u64,
+    /// Lookups that had to decode
+    pub misses: u64,
+    /// Times the cache was flushed
+    pub invalidations: u64,
+}
+
+/// Per-VCPU cache of decoded MMIO accesses keyed by vsatp and guest PC
+///
+/// The same PC in two guest address spaces is two different instructions,
+/// so entries also record the vsatp they were decoded under. Must be
+/// invalidated whenever the instruction at a cached PC may have changed:
+/// on VCPU context switch and whenever guest code is modified.
+#[derive(Debug, Clone)]
+pub struct MmioDecodeCache {
+    /// Direct-mapped entries of (vsatp, guest PC, decoded access)
+    entries: [Option<(usize, usize, MmioAccess)>; MMIO_CACHE_ENTRIES],
+    /// Statistics
+    stats: MmioCacheStats,
+}
+
+impl MmioDecodeCache {
+    /// Create an empty cache
+    pub const fn new() -> Self {
+        Self {
+            entries: [None; MMIO_CACHE_ENTRIES],
+            stats: MmioCacheStats { hits: 0, misses: 0, invalidations: 0 },
+        }
+    }
+
+    /// Cache slot for a guest PC (instructions are at least 2-byte aligned)
+    fn slot(pc: usize) -> usize {
+        (pc >> 1) % MMIO_CACHE_ENTRIES
+    }
+
+    /// Look up a cached decode without fetching
+    pub fn lookup(&self, vsatp: usize, pc: usize) -> Option<MmioAccess> {
+        match self.entries[Self::slot(pc)] {
+            Some((cached_vsatp, cached_pc, access)) if cached_vsatp == vsatp && cached_pc == pc => Some(access),
+            _ => None,
+        }
+    }
+
+    /// Decode the access at `pc` in the address space `vsatp`, fetching
+    /// the instruction only on a miss
+    ///
+    /// `fetch` reads the instruction word at the guest PC.
+    pub fn decode(&mut self, vsatp: usize, pc: usize, fetch: impl FnOnce(usize) -> Option<u32>) -> Option<MmioAccess> {
+        if let Some(access) = self.lookup(vsatp, pc) {
+            self.stats.hits += 1;
+            return Some(access);
+        }
+
+        self.stats.misses += 1;
+        let access = decode_mmio_access(fetch(pc)?)?;
+        self.entries[Self::slot(pc)] = Some((vsatp, pc, access));
+        Some(access)
+    }
+
+    /// Drop all cached decodes
+    pub fn invalidate(&mut self) {
+        self.entries = [None; MMIO_CACHE_ENTRIES];
+        self.stats.invalidations += 1;
+    }
+
+    /// Get cache statistics
+    pub fn stats(&self) -> MmioCacheStats {
+        self.stats
+    }
+}
+
+impl Default for MmioDecodeCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// lw a0, 4(a1)
+    const LW_A0: u32 = 0x0045_a503;
+    /// sd a2, 8(a1)
+    const SD_A2: u32 = 0x00c5_b423;
+    /// c.sw a0, 0(a1)
+    const C_SW_A0: u32 = 0xc188;
+
+    #[test]
+    fn test_decode_loads_and_stores() {
+        assert_eq!(decode_mmio_access(LW_A0), Some(MmioAccess {
+            size: 4, is_write: false, reg: 10, sign_extend: true, len: 4,
+        }));
+        assert_eq!(decode_mmio_access(SD_A2), Some(MmioAccess {
+            size: 8, is_write: true, reg: 12, sign_extend: false, len: 4,
+        }));
+        assert_eq!(decode_mmio_access(C_SW_A0), Some(MmioAccess {
+            size: 4, is_write: true, reg: 10, sign_extend: false, len: 2,
+        }));
+        // addi a0, a0, 1
+        assert_eq!(decode_mmio_access(0x0015_0513), None);
+    }
+
+    #[test]
+    fn test_second_access_hits_cache() {
+        let mut cache = MmioDecodeCache::new();
+        let mut fetches = 0;
+
+        let first = cache.decode(0, 0x8000_1000, |_| { fetches += 1; Some(LW_A0) });
+        let second = cache.decode(0, 0x8000_1000, |_| { fetches += 1; Some(SD_A2) });
+
+        assert_eq!(first, decode_mmio_access(LW_A0));
+        assert_eq!(second, first);
+        assert_eq!(fetches, 1);
+        assert_eq!(cache.stats().hits, 1);
+        assert_eq!(cache.stats().misses, 1);
+
+        // A different PC in the same slot evicts the entry
+        let alias = 0x8000_1000 + 2 * MMIO_CACHE_ENTRIES;
+        assert_eq!(cache.decode(0, alias, |_| Some(SD_A2)), decode_mmio_access(SD_A2));
+        assert_eq!(cache.lookup(0, 0x8000_1000), None);
+    }
+
+    #[test]
+    fn test_same_pc_in_other_address_space_misses() {
+        const VSATP_A: usize = 0x8000_0000_0008_1000;
+        const VSATP_B: usize = 0x8000_0000_0008_2000;
+        let mut cache = MmioDecodeCache::new();
+
+        cache.decode(VSATP_A, 0x1000, |_| Some(LW_A0));
+        assert_eq!(cache.decode(VSATP_B, 0x1000, |_| Some(SD_A2)), decode_mmio_access(SD_A2));
+        assert_eq!(cache.lookup(VSATP_A, 0x1000), None);
+        assert_eq!(cache.stats().misses, 2);
+    }
+
+    #[test]
+    fn test_invalidate_forces_redecode() {
+        let mut cache = MmioDecodeCache::new();
+        cache.decode(0, 0x8000_2000, |_| Some(LW_A0));
+        cache.invalidate();
+
+        // Guest code changed: the new instruction is decoded
+        assert_eq!(cache.decode(0, 0x8000_2000, |_| Some(SD_A2)), decode_mmio_access(SD_A2));
+        assert_eq!(cache.stats().misses, 2);
+        assert_eq!(cache.stats().invalidations, 1);
+    }
+}
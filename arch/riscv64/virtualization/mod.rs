@@ -18,6 +18,11 @@ pub mod discovery_manager;
 pub mod virtio_framework;
 pub mod virtio_driver;
 pub mod virtio_manager;
+pub mod hypercall;
+pub mod mmio;
+pub mod csr_emul;
+pub mod vm_log;
+pub mod pvclock;
 
 pub use hextension::*;
 pub use vcpu::*;
@@ -30,14 +35,20 @@ pub use discovery_manager::*;
 pub use virtio_framework::*;
 pub use virtio_driver::*;
 pub use virtio_manager::*;
+pub use mmio::*;
+pub use vm_log::{VmLog, VmLogEntry, VmLogSource};
+pub use pvclock::{PvClock, PvclockTimeInfo};
+pub use hypercall::{HypercallId, HypercallArgs, HypercallError, HypercallResult, register_hypercall};
 
 use crate::arch::riscv64::*;
+use crate::core::sync::{Once, SpinLock};
+use crate::core::sync::spinlock::SpinLockGuard;
 
 /// Global H extension manager
-static mut H_EXTENSION: Option<HExtensionManager> = None;
+static H_EXTENSION: Once<SpinLock<HExtensionManager>> = Once::new();
 
 /// Virtual machine manager
-static mut VM_MANAGER: Option<VmManager> = None;
+static VM_MANAGER: Once<SpinLock<VmManager>> = Once::new();
 
 /// Global device discovery manager
 static mut DEVICE_DISCOVERY: Option<RiscvDeviceDiscoveryManager> = None;
@@ -46,7 +57,7 @@ static mut DEVICE_DISCOVERY: Option<RiscvDeviceDiscoveryManager> = None;
 static mut VIRTIO_MANAGER: Option<VirtIOManager> = None;
 
 /// Initialize virtualization subsystem
-pub fn init() -> Result<(), &'static str> {
+pub fn init() -> Result<(), Error> {
     log::info!("Initializing RISC-V virtualization subsystem");
 
     // Check if H extension is available
@@ -61,9 +72,8 @@ pub fn init() -> Result<(), &'static str> {
     h_ext.init()?;
 
     // Store global H extension manager
-    unsafe {
-        H_EXTENSION = Some(h_ext);
-    }
+    H_EXTENSION.set(SpinLock::new(h_ext))
+        .map_err(|_| Error::Busy("H extension already initialized"))?;
 
     // Initialize exception delegation
     delegation::init()?;
@@ -74,11 +84,13 @@ pub fn init() -> Result<(), &'static str> {
     // Initialize virtual interrupt controller
     vintc::init()?;
 
+    // Register built-in hypercalls
+    register_hypercall(HypercallId::Sbi, sbi_hypercall)?;
+    register_hypercall(HypercallId::Shutdown, shutdown_hypercall)?;
+
     // Initialize VM manager
-    let vm_manager = VmManager::new();
-    unsafe {
-        VM_MANAGER = Some(vm_manager);
-    }
+    VM_MANAGER.set(SpinLock::new(VmManager::new()))
+        .map_err(|_| Error::Busy("VM manager already initialized"))?;
 
     // Initialize device discovery manager
     let mut discovery_manager = RiscvDeviceDiscoveryManager::new();
@@ -104,23 +116,32 @@ pub fn init() -> Result<(), &'static str> {
 }
 
 /// Get the global H extension manager
-pub fn get_h_extension() -> Option<&'static HExtensionManager> {
-    unsafe { H_EXTENSION.as_ref() }
+///
+/// The manager stays locked while the guard is held.
+pub fn get_h_extension() -> Option<SpinLockGuard<'static, HExtensionManager>> {
+    H_EXTENSION.get().map(SpinLock::lock)
 }
 
 /// Get mutable reference to global H extension manager
-pub fn get_h_extension_mut() -> Option<&'static mut HExtensionManager> {
-    unsafe { H_EXTENSION.as_mut() }
+///
+/// The manager stays locked while the guard is held.
+pub fn get_h_extension_mut() -> Option<SpinLockGuard<'static, HExtensionManager>> {
+    get_h_extension()
 }
 
 /// Get the global VM manager
-pub fn get_vm_manager() -> Option<&'static VmManager> {
-    unsafe { VM_MANAGER.as_ref() }
+///
+/// The manager stays locked while the guard is held, so VMs are created
+/// and destroyed one CPU at a time.
+pub fn get_vm_manager() -> Option<SpinLockGuard<'static, VmManager>> {
+    VM_MANAGER.get().map(SpinLock::lock)
 }
 
 /// Get mutable reference to global VM manager
-pub fn get_vm_manager_mut() -> Option<&'static mut VmManager> {
-    unsafe { VM_MANAGER.as_mut() }
+///
+/// The manager stays locked while the guard is held.
+pub fn get_vm_manager_mut() -> Option<SpinLockGuard<'static, VmManager>> {
+    get_vm_manager()
 }
 
 /// Get the global device discovery manager
@@ -149,41 +170,58 @@ pub fn has_h_extension() -> bool {
 }
 
 /// Enter virtualization mode with a VCPU
-pub fn enter_virtualization(vcpu: &Vcpu) -> Result<(), &'static str> {
-    let h_ext = get_h_extension().ok_or("H extension not initialized")?;
-
+pub fn enter_virtualization(vcpu: &Vcpu) -> Result<(), Error> {
     // Save current host state
     // This would be done in assembly
 
     // Configure stage-2 translation if enabled
     // This would be handled by the VM
 
+    // Stage-2 TLB shootdowns for the VM must reach this CPU from now on
+    set_running_vmid(Some(vcpu.vmid));
+
     // Enter guest mode
-    h_ext.enter_virtualization(&vcpu.guest_csr)?;
+    if let Err(e) = get_h_extension()
+        .ok_or(Error::NotInitialized("H extension not initialized"))
+        .and_then(|h| h.enter_virtualization(&vcpu.guest_csr))
+    {
+        set_running_vmid(None);
+        return Err(e);
+    }
 
     // This would continue with assembly code to restore guest state and execute
 
     Ok(())
 }
 
-/// Exit virtualization mode
-pub fn exit_virtualization() -> Result<HypervisorTrapInfo, &'static str> {
-    let h_ext = get_h_extension().ok_or("H extension not initialized")?;
+/// Record the VMID this CPU runs guest code for, `None` for host code
+fn set_running_vmid(vmid: Option<u16>) {
+    if let Some(manager) = crate::core::mm::gstage::get() {
+        manager.set_cpu_vmid(crate::arch::riscv64::cpu::current_cpu_id(), vmid);
+    }
+}
 
+/// Exit virtualization mode
+pub fn exit_virtualization() -> Result<HypervisorTrapInfo, Error> {
     // Save guest state
     // This would be done in assembly
 
     // Exit to hypervisor
-    let trap_info = h_ext.exit_virtualization()?;
-
-    // Handle the trap
+    let trap_info = get_h_extension()
+        .ok_or(Error::NotInitialized("H extension not initialized"))?
+        .exit_virtualization();
+    set_running_vmid(None);
+    let trap_info = trap_info?;
+
+    // Handle the trap with the H extension manager unlocked: trap handling
+    // may take the VM manager, which locks it in the opposite order
     handle_hypervisor_trap(&trap_info)?;
 
     Ok(trap_info)
 }
 
 /// Handle hypervisor trap
-fn handle_hypervisor_trap(trap_info: &HypervisorTrapInfo) -> Result<(), &'static str> {
+fn handle_hypervisor_trap(trap_info: &HypervisorTrapInfo) -> Result<(), Error> {
     log::debug!("Handling hypervisor trap: cause={:#x}, tval={:#x}",
                 trap_info.cause, trap_info.tval);
 
@@ -199,7 +237,7 @@ fn handle_hypervisor_trap(trap_info: &HypervisorTrapInfo) -> Result<(), &'static
             9 => InterruptCause::SupervisorExternal,
             _ => {
                 log::warn!("Unknown interrupt cause: {}", interrupt_cause);
-                return Err("Unknown interrupt cause");
+                return Err(Error::Unsupported("Unknown interrupt cause"));
             }
         };
 
@@ -217,7 +255,7 @@ fn handle_hypervisor_trap(trap_info: &HypervisorTrapInfo) -> Result<(), &'static
     } else {
         // Handle guest exception with delegation
         let exception_code = ExceptionCode::try_from(trap_info.cause)
-            .map_err(|_| "Invalid exception code")?;
+            .map_err(|_| Error::InvalidArgument("Invalid exception code"))?;
 
         let delegation_result = delegation::handle_exception(
             exception_code,
@@ -233,7 +271,7 @@ fn handle_hypervisor_trap(trap_info: &HypervisorTrapInfo) -> Result<(), &'static
 }
 
 /// Handle virtual interrupt
-fn handle_virtual_interrupt(trap_info: &HypervisorTrapInfo) -> Result<(), &'static str> {
+fn handle_virtual_interrupt(trap_info: &HypervisorTrapInfo) -> Result<(), Error> {
     let interrupt_cause = trap_info.cause & 0x7FFFFFFF;
 
     match interrupt_cause {
@@ -256,7 +294,7 @@ fn handle_virtual_interrupt(trap_info: &HypervisorTrapInfo) -> Result<(), &'stat
 }
 
 /// Handle guest exception
-fn handle_guest_exception(trap_info: &HypervisorTrapInfo) -> Result<(), &'static str> {
+fn handle_guest_exception(trap_info: &HypervisorTrapInfo) -> Result<(), Error> {
     let exception_code = trap_info.cause;
 
     match exception_code {
@@ -290,7 +328,7 @@ fn handle_guest_exception(trap_info: &HypervisorTrapInfo) -> Result<(), &'static
 
 /// Handle hypervisor exception (when delegation is disabled)
 fn handle_hypervisor_exception(trap_info: &HypervisorTrapInfo,
-                               exception_code: ExceptionCode) -> Result<(), &'static str> {
+                               exception_code: ExceptionCode) -> Result<(), Error> {
     log::debug!("Handling hypervisor exception: {:?}", exception_code);
 
     match exception_code {
@@ -306,7 +344,7 @@ fn handle_hypervisor_exception(trap_info: &HypervisorTrapInfo,
                 }
                 _ => {
                     log::error!("Unknown hypervisor illegal instruction: {:#x}", trap_info.htinst);
-                    Err("Unknown hypervisor illegal instruction")
+                    Err(Error::Unsupported("Unknown hypervisor illegal instruction"))
                 }
             }
         }
@@ -319,14 +357,14 @@ fn handle_hypervisor_exception(trap_info: &HypervisorTrapInfo,
         }
         _ => {
             log::warn!("Unhandled hypervisor exception: {:?}", exception_code);
-            Err("Unhandled hypervisor exception")
+            Err(Error::Unsupported("Unhandled hypervisor exception"))
         }
     }
 }
 
 /// Handle hypervisor interrupt (when delegation is disabled)
 fn handle_hypervisor_interrupt(trap_info: &HypervisorTrapInfo,
-                                interrupt: InterruptCause) -> Result<(), &'static str> {
+                                interrupt: InterruptCause) -> Result<(), Error> {
     log::debug!("Handling hypervisor interrupt: {:?}", interrupt);
 
     match interrupt {
@@ -349,14 +387,14 @@ fn handle_hypervisor_interrupt(trap_info: &HypervisorTrapInfo,
 }
 
 /// Handle instruction address misaligned
-fn handle_instruction_misaligned(_trap_info: &HypervisorTrapInfo) -> Result<(), &'static str> {
+fn handle_instruction_misaligned(_trap_info: &HypervisorTrapInfo) -> Result<(), Error> {
     // For now, just inject exception to guest
     // In a real implementation, we might handle this differently
-    Err("Instruction address misaligned not handled")
+    Err(Error::Unsupported("Instruction address misaligned not handled"))
 }
 
 /// Handle illegal instruction
-fn handle_illegal_instruction(trap_info: &HypervisorTrapInfo) -> Result<(), &'static str> {
+fn handle_illegal_instruction(trap_info: &HypervisorTrapInfo) -> Result<(), Error> {
     // Check if this is a hypervisor instruction that should be trapped
     match trap_info.htinst & 0xFFFF {
         0x102 => {
@@ -371,16 +409,28 @@ fn handle_illegal_instruction(trap_info: &HypervisorTrapInfo) -> Result<(), &'st
             // Handle virtual load
             Ok(())
         }
+        _ if is_counter_csr_read(trap_info) => {
+            // Emulated by the VCPU against its shadow counters
+            log::debug!("Guest counter CSR read");
+            Ok(())
+        }
         _ => {
             // Unknown illegal instruction
             log::warn!("Guest illegal instruction: {:#x}", trap_info.htinst);
-            Err("Illegal instruction")
+            Err(Error::Failed("Illegal instruction"))
         }
     }
 }
 
+/// Check whether an illegal instruction trap is a counter CSR read the
+/// VCPU can emulate
+fn is_counter_csr_read(trap_info: &HypervisorTrapInfo) -> bool {
+    csr_emul::decode_csr_instruction(csr_emul::trapped_instruction(trap_info.htinst, trap_info.tval))
+        .map_or(false, |insn| !insn.writes() && csr_emul::is_emulated_counter(insn.csr))
+}
+
 /// Handle environment call (ecall)
-fn handle_ecall(trap_info: &HypervisorTrapInfo) -> Result<(), &'static str> {
+fn handle_ecall(trap_info: &HypervisorTrapInfo) -> Result<(), Error> {
     // Check privilege level from guest status
     let guest_privilege = (trap_info.guest_csr.vsstatus >> 8) & 0x3;
 
@@ -388,16 +438,16 @@ fn handle_ecall(trap_info: &HypervisorTrapInfo) -> Result<(), &'static str> {
         0 => {
             // User-mode ecall - forward to guest OS
             log::debug!("Guest user-mode ecall");
-            inject_guest_ecall(8)?; // User ecall
+            inject_guest_exception(ExceptionCode::ECallFromUMode as usize, 0)?;
         }
         1 => {
-            // Supervisor-mode ecall - hypervisor call
+            // Supervisor-mode ecall - hypervisor call, dispatched by the
+            // VCPU against its own registers
             log::debug!("Guest supervisor ecall (hypercall)");
-            handle_hypercall(trap_info)?;
         }
         _ => {
             log::warn!("Unexpected ecall privilege level: {}", guest_privilege);
-            return Err("Unexpected ecall privilege level");
+            return Err(Error::Unsupported("Unexpected ecall privilege level"));
         }
     }
 
@@ -405,7 +455,7 @@ fn handle_ecall(trap_info: &HypervisorTrapInfo) -> Result<(), &'static str> {
 }
 
 /// Handle page fault
-fn handle_page_fault(trap_info: &HypervisorTrapInfo) -> Result<(), &'static str> {
+fn handle_page_fault(trap_info: &HypervisorTrapInfo) -> Result<(), Error> {
     log::debug!("Guest page fault at {:#x}", trap_info.tval);
 
     // Check if this is a valid guest physical address
@@ -413,53 +463,45 @@ fn handle_page_fault(trap_info: &HypervisorTrapInfo) -> Result<(), &'static str>
 
     // For now, just forward to guest
     match trap_info.cause {
-        12 => inject_guest_ecall(12)?, // Instruction page fault
-        13 => inject_guest_ecall(13)?, // Load page fault
-        15 => inject_guest_ecall(15)?, // Store page fault
-        _ => return Err("Invalid page fault type"),
+        12 | 13 | 15 => inject_guest_exception(trap_info.cause, trap_info.tval),
+        _ => Err(Error::InvalidArgument("Invalid page fault type")),
     }
-
-    Ok(())
 }
 
-/// Inject ecall to guest
-fn inject_guest_ecall(exception_code: usize) -> Result<(), &'static str> {
-    // Set guest cause and tval
-    crate::arch::riscv64::cpu::csr::write_csr!(hcsr::VSCAUSE, exception_code);
-    crate::arch::riscv64::cpu::csr::write_csr!(hcsr::VSTVAL, 0);
-
-    // Set guest PC to trap handler
-    let vstvec = crate::arch::riscv64::cpu::csr::read_csr!(hcsr::VSTVEC);
-    crate::arch::riscv64::cpu::csr::write_csr!(hcsr::VSEPC, vstvec);
-
+/// Inject an exception into the guest running on this CPU
+///
+/// The guest trapped to the hypervisor at SEPC with its privilege in
+/// SSTATUS.SPP; the hypervisor's SRET then resumes it in VS-mode at its
+/// trap handler.
+fn inject_guest_exception(exception_code: usize, tval: usize) -> Result<(), Error> {
+    use crate::arch::riscv64::cpu::csr::{address, SstatusFlags, UsizeCsr, SSTATUS};
+
+    let sepc = UsizeCsr(address::SEPC);
+    let sstatus = SSTATUS::read();
+    let mut guest_csr = GuestCsrState::save();
+    let handler = guest_csr.enter_trap(exception_code, tval, sepc.read(),
+                                       sstatus.contains(SstatusFlags::SPP));
+    guest_csr.load();
+
+    sepc.write(handler);
+    SSTATUS::write(sstatus | SstatusFlags::SPP);
     Ok(())
 }
 
-/// Handle hypercall
-fn handle_hypercall(trap_info: &HypervisorTrapInfo) -> Result<(), &'static str> {
-    // Get hypercall number from a register (e.g., a7)
-    let hypercall_num = 0; // This would be read from VCPU state
+/// Built-in SBI forwarding hypercall
+fn sbi_hypercall(_args: &HypercallArgs) -> HypercallResult {
+    log::debug!("Guest SBI call");
+    handle_sbi_call().map(|_| 0).map_err(|_| HypercallError::Failed)
+}
 
-    match hypercall_num {
-        0 => {
-            // SBI call - forward to SBI implementation
-            log::debug!("Guest SBI call");
-            handle_sbi_call(trap_info)
-        }
-        1 => {
-            // Hypervisor shutdown
-            log::info!("Guest requested shutdown");
-            Ok(())
-        }
-        _ => {
-            log::warn!("Unknown hypercall: {}", hypercall_num);
-            Err("Unknown hypercall")
-        }
-    }
+/// Built-in shutdown hypercall
+fn shutdown_hypercall(_args: &HypercallArgs) -> HypercallResult {
+    log::info!("Guest requested shutdown");
+    Ok(0)
 }
 
 /// Handle SBI call from guest
-fn handle_sbi_call(_trap_info: &HypervisorTrapInfo) -> Result<(), &'static str> {
+fn handle_sbi_call() -> Result<(), Error> {
     // Implement virtual SBI interface
     // This would handle various SBI extensions
     log::debug!("Handling virtual SBI call");
@@ -489,9 +531,9 @@ impl VmManager {
         name: String,
         config: VmConfig,
         flags: VmFlags,
-    ) -> Result<&mut VirtualMachine, &'static str> {
+    ) -> Result<&mut VirtualMachine, Error> {
         if self.next_vm_id >= 1024 {
-            return Err("Maximum VMs reached");
+            return Err(Error::OutOfResources("Maximum VMs reached"));
         }
 
         let vm_id = self.next_vm_id;
@@ -520,9 +562,9 @@ impl VmManager {
     }
 
     /// Destroy a VM
-    pub fn destroy_vm(&mut self, vm_id: u16) -> Result<(), &'static str> {
+    pub fn destroy_vm(&mut self, vm_id: u16) -> Result<(), Error> {
         let index = self.vms.iter().position(|vm| vm.id == vm_id)
-            .ok_or("VM not found")?;
+            .ok_or(Error::NotFound("VM not found"))?;
 
         let vm = &mut self.vms[index];
 
@@ -532,7 +574,7 @@ impl VmManager {
         }
 
         // Free VMID
-        if let Some(h_ext) = get_h_extension_mut() {
+        if let Some(mut h_ext) = get_h_extension_mut() {
             h_ext.free_vmid(vm.vmid);
         }
 
@@ -541,6 +583,24 @@ impl VmManager {
         Ok(())
     }
 
+    /// Pause a running VM
+    pub fn pause_vm(&mut self, vm_id: u16) -> Result<(), Error> {
+        self.get_vm(vm_id).ok_or(Error::NotFound("VM not found"))?.pause()
+    }
+
+    /// Resume a paused VM
+    pub fn resume_vm(&mut self, vm_id: u16) -> Result<(), Error> {
+        self.get_vm(vm_id).ok_or(Error::NotFound("VM not found"))?.resume()
+    }
+
+    /// Run a VCPU of a VM until an exit the host must handle
+    ///
+    /// Exits the VM emulates itself are completed on the way (see
+    /// `VirtualMachine::run_vcpu`).
+    pub fn run_vcpu(&mut self, vm_id: u16, vcpu_id: u8) -> Result<VcpuExit, Error> {
+        self.get_vm(vm_id).ok_or(Error::NotFound("VM not found"))?.run_vcpu(vcpu_id)
+    }
+
     /// Get total number of VMs
     pub fn vm_count(&self) -> usize {
         self.vms.len()
@@ -610,4 +670,28 @@ mod tests {
         assert!(retrieved.is_some());
         assert_eq!(retrieved.unwrap().id, vm.id);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_vm_manager_pause_resume() {
+        let mut manager = VmManager::new();
+
+        let vm_id = manager.create_vm(
+            "test_vm".to_string(),
+            VmConfig::default(),
+            VmFlags::empty(),
+        ).unwrap().id;
+
+        // Created but not started
+        assert!(manager.pause_vm(vm_id).is_err());
+
+        manager.get_vm(vm_id).unwrap().start().unwrap();
+        manager.pause_vm(vm_id).unwrap();
+        assert_eq!(manager.get_vm(vm_id).unwrap().state, VmState::Paused);
+        assert!(manager.get_running_vms().is_empty());
+
+        manager.resume_vm(vm_id).unwrap();
+        assert_eq!(manager.get_vm(vm_id).unwrap().state, VmState::Running);
+
+        assert!(manager.pause_vm(vm_id + 1).is_err());
+    }
+}
@@ -0,0 +1,238 @@
+//! Paravirtual Clock
+//!
+//! Each VCPU can register a page with the `HypercallId::Pvclock`
+//! hypercall (a0 = guest physical address, 0 to unregister). The
+//! hypervisor keeps a `PvclockTimeInfo` in it that lets the guest turn its
+//! `time` counter into nanoseconds without trapping:
+//!
+//! ```text
+//! delta = counter - counter_timestamp
+//! delta = shift >= 0 ? delta << shift : delta >> -shift
+//! time  = system_time + (delta * counter_to_system_mul) >> 32
+//! ```
+//!
+//! The page is republished whenever the counter frequency, the guest time
+//! offset or the VCPU's counter offset (e.g. after migration) changes.
+//! `version` is odd while an update is in progress; the guest retries a
+//! read if it sees an odd version or the version changed under it. The
+//! page must be aligned to its size, so it never straddles a page
+//! boundary.
+
+use crate::arch::riscv64::virtualization::hypercall::HypercallError;
+use crate::utils::time::NSEC_PER_SEC;
+
+/// Size of the guest-visible structure
+pub const PVCLOCK_INFO_SIZE: usize = core::mem::size_of::<PvclockTimeInfo>();
+
+/// Flag: the counter is synchronized across VCPUs
+pub const PVCLOCK_FLAG_STABLE: u8 = 1 << 0;
+
+/// Guest-visible clock parameters (guest ABI, little-endian)
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PvclockTimeInfo {
+    /// Update sequence number, odd while an update is in progress
+    pub version: u32,
+    /// Reserved
+    pub pad0: u32,
+    /// Guest counter value `system_time` was sampled at
+    pub counter_timestamp: u64,
+    /// Guest time in nanoseconds at `counter_timestamp`
+    pub system_time: u64,
+    /// Counter to nanoseconds multiplier, a 32.32 fixed-point fraction
+    pub counter_to_system_mul: u32,
+    /// Counter shift applied before the multiplier
+    pub counter_shift: i8,
+    /// `PVCLOCK_FLAG_*`
+    pub flags: u8,
+    /// Reserved
+    pub pad1: [u8; 2],
+}
+
+impl PvclockTimeInfo {
+    /// Guest time in nanoseconds at guest counter value `counter`
+    ///
+    /// The formula the guest applies to the published fields.
+    pub fn guest_time(&self, counter: u64) -> u64 {
+        let delta = counter.wrapping_sub(self.counter_timestamp) as u128;
+        let delta = if self.counter_shift >= 0 {
+            delta << self.counter_shift
+        } else {
+            delta >> -self.counter_shift
+        };
+        self.system_time.wrapping_add(((delta * self.counter_to_system_mul as u128) >> 32) as u64)
+    }
+}
+
+/// Multiplier and shift converting a `counter_hz` counter to nanoseconds
+///
+/// Picks the shift that gives the largest multiplier still fitting in 32
+/// bits. Returns `None` for a zero frequency.
+pub fn time_scale(counter_hz: u64) -> Option<(u32, i8)> {
+    if counter_hz == 0 {
+        return None;
+    }
+
+    let ns = (NSEC_PER_SEC as u128) << 32;
+    (-31i8..=31).find_map(|shift| {
+        let mul = if shift >= 0 {
+            ns / ((counter_hz as u128) << shift)
+        } else {
+            (ns << -shift) / counter_hz as u128
+        };
+        u32::try_from(mul).ok().map(|mul| (mul, shift))
+    })
+}
+
+/// Paravirtual clock of one VCPU
+#[derive(Debug, Clone, Default)]
+pub struct PvClock {
+    /// Guest physical address of the registered page
+    page: Option<usize>,
+    /// Guest counter frequency in Hz
+    counter_hz: u64,
+    /// Guest time in nanoseconds at guest counter zero
+    offset_ns: u64,
+    /// Contents last published
+    info: PvclockTimeInfo,
+}
+
+impl PvClock {
+    /// Create an unregistered clock for a `counter_hz` counter
+    pub fn new(counter_hz: u64) -> Self {
+        Self { counter_hz, ..Self::default() }
+    }
+
+    /// Guest physical address of the registered page
+    pub fn page(&self) -> Option<usize> {
+        self.page
+    }
+
+    /// Register the page at `gpa`, or unregister with 0
+    pub fn register(&mut self, gpa: usize) -> Result<(), HypercallError> {
+        if gpa == 0 {
+            self.page = None;
+            return Ok(());
+        }
+        if gpa & (PVCLOCK_INFO_SIZE - 1) != 0 {
+            return Err(HypercallError::InvalidParam);
+        }
+        self.page = Some(gpa);
+        Ok(())
+    }
+
+    /// Guest counter frequency in Hz
+    pub fn frequency(&self) -> u64 {
+        self.counter_hz
+    }
+
+    /// Change the guest counter frequency
+    pub fn set_frequency(&mut self, counter_hz: u64) {
+        self.counter_hz = counter_hz;
+    }
+
+    /// Guest time in nanoseconds at guest counter zero
+    pub fn offset(&self) -> u64 {
+        self.offset_ns
+    }
+
+    /// Change the guest time at guest counter zero
+    pub fn set_offset(&mut self, offset_ns: u64) {
+        self.offset_ns = offset_ns;
+    }
+
+    /// The hypervisor's notion of guest time at guest counter `counter`
+    pub fn guest_time_ns(&self, counter: u64) -> u64 {
+        if self.counter_hz == 0 {
+            return self.offset_ns;
+        }
+        let elapsed = counter as u128 * NSEC_PER_SEC as u128 / self.counter_hz as u128;
+        self.offset_ns.wrapping_add(elapsed as u64)
+    }
+
+    /// Contents last published
+    pub fn info(&self) -> &PvclockTimeInfo {
+        &self.info
+    }
+
+    /// Publish the clock parameters sampled at guest counter `counter`
+    ///
+    /// `write` stores the structure at the page's guest physical address.
+    /// It is called twice, with an odd and then an even version. It must
+    /// store an odd `version` before the other fields and an even one
+    /// after them. Returns `false` without writing if no page is
+    /// registered or the frequency is unknown.
+    pub fn publish(&mut self, counter: u64, mut write: impl FnMut(usize, &PvclockTimeInfo)) -> bool {
+        let (Some(page), Some((mul, shift))) = (self.page, time_scale(self.counter_hz)) else {
+            return false;
+        };
+
+        let mut info = PvclockTimeInfo {
+            version: self.info.version | 1,
+            counter_timestamp: counter,
+            system_time: self.guest_time_ns(counter),
+            counter_to_system_mul: mul,
+            counter_shift: shift,
+            flags: PVCLOCK_FLAG_STABLE,
+            ..PvclockTimeInfo::default()
+        };
+        write(page, &info);
+
+        info.version = info.version.wrapping_add(1);
+        write(page, &info);
+        self.info = info;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_guest_formula_matches_hypervisor_time() {
+        for counter_hz in [1_000_000, 10_000_000, 24_000_000, 1_000_000_000, 3_300_000_000] {
+            let mut clock = PvClock::new(counter_hz);
+            clock.set_offset(5 * NSEC_PER_SEC);
+            clock.register(0x8000_1000).unwrap();
+
+            let start = 123_456_789;
+            assert!(clock.publish(start, |_, _| {}));
+            let info = *clock.info();
+
+            // The 32-bit multiplier drifts by under a nanosecond per second
+            for seconds in [0, 1, 60, 3600] {
+                let counter = start + seconds * counter_hz + 17;
+                let expected = clock.guest_time_ns(counter);
+                let guest = info.guest_time(counter);
+                assert!(guest.abs_diff(expected) <= seconds + 1,
+                        "{} Hz after {}s: {} vs {}", counter_hz, seconds, guest, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_updates_bump_version_around_writes() {
+        let mut clock = PvClock::new(10_000_000);
+        assert!(!clock.publish(0, |_, _| panic!("no page registered")));
+        assert_eq!(clock.register(0x8000_1004), Err(HypercallError::InvalidParam));
+        // 8-byte aligned, but would straddle the page at 0x8000_2000
+        assert_eq!(clock.register(0x8000_1FE8), Err(HypercallError::InvalidParam));
+        clock.register(0x8000_1000).unwrap();
+
+        let mut writes = Vec::new();
+        clock.publish(1000, |gpa, info| writes.push((gpa, info.version)));
+        clock.set_frequency(20_000_000);
+        clock.publish(2000, |gpa, info| writes.push((gpa, info.version)));
+        assert_eq!(writes, [(0x8000_1000, 1), (0x8000_1000, 2), (0x8000_1000, 3), (0x8000_1000, 4)]);
+
+        // The frequency change is visible to the guest
+        let info = clock.info();
+        assert_eq!(info.guest_time(2000 + 20_000_000), clock.guest_time_ns(2000) + NSEC_PER_SEC);
+
+        clock.register(0).unwrap();
+        assert_eq!(clock.page(), None);
+        assert_eq!(PVCLOCK_INFO_SIZE, 32);
+    }
+}
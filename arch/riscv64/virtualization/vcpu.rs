@@ -10,6 +10,10 @@ use crate::arch::riscv64::*;
 use crate::arch::riscv64::cpu::regs::CpuState;
 use crate::arch::riscv64::virtualization::hextension::*;
 use crate::arch::riscv64::virtualization::vintc::*;
+use crate::arch::riscv64::virtualization::mmio::{MmioAccess, MmioDecodeCache, decode_mmio_access, fetch_guest_instruction};
+use crate::arch::riscv64::virtualization::csr_emul::{CounterShadow, decode_csr_instruction, emulate_csr_instruction, trapped_instruction};
+use crate::arch::riscv64::virtualization::hypercall::{self, HypercallId, HypercallResult};
+use crate::arch::riscv64::virtualization::pvclock::PvClock;
 use bitflags::bitflags;
 
 /// VCPU state
@@ -38,6 +42,8 @@ pub struct VcpuStats {
     pub hypervisor_traps: u64,
     /// Number of virtual interrupts injected
     pub virtual_interrupts_injected: u64,
+    /// Hypervisor traps by exit reason
+    pub exits: VcpuExitStats,
 }
 
 impl Default for VcpuStats {
@@ -47,10 +53,48 @@ impl Default for VcpuStats {
             cycles_spent: 0,
             hypervisor_traps: 0,
             virtual_interrupts_injected: 0,
+            exits: VcpuExitStats::default(),
         }
     }
 }
 
+/// VCPU exit counts by reason
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VcpuExitStats {
+    /// Environment calls
+    pub ecall: u64,
+    /// Page faults
+    pub page_fault: u64,
+    /// Illegal instructions
+    pub illegal_instruction: u64,
+    /// Interrupts
+    pub interrupt: u64,
+    /// MMIO accesses
+    pub mmio: u64,
+    /// Any other exit
+    pub other: u64,
+}
+
+impl VcpuExitStats {
+    /// Count one exit
+    pub fn record(&mut self, reason: VcpuExitReason) {
+        let counter = match reason {
+            VcpuExitReason::SystemCall | VcpuExitReason::Hypercall => &mut self.ecall,
+            VcpuExitReason::MemoryFault => &mut self.page_fault,
+            VcpuExitReason::IllegalInstruction => &mut self.illegal_instruction,
+            VcpuExitReason::Interrupt => &mut self.interrupt,
+            VcpuExitReason::Io => &mut self.mmio,
+            VcpuExitReason::Breakpoint | VcpuExitReason::Shutdown | VcpuExitReason::Unknown => &mut self.other,
+        };
+        *counter += 1;
+    }
+
+    /// Total number of exits
+    pub fn total(&self) -> u64 {
+        self.ecall + self.page_fault + self.illegal_instruction + self.interrupt + self.mmio + self.other
+    }
+}
+
 /// VCPU flags and configuration
 bitflags! {
     #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -207,6 +251,8 @@ pub enum VcpuWaitReason {
     Memory,
     /// Waiting for lock
     Lock,
+    /// Parked while the owning VM is paused
+    VmPaused,
     /// Custom reason
     Custom,
 }
@@ -266,6 +312,15 @@ pub struct Vcpu {
 
     /// Nested virtualization support
     pub nested_virt: Option<VcpuNestedVirt>,
+
+    /// Decoded MMIO accesses by guest PC
+    pub mmio_cache: MmioDecodeCache,
+    /// MMIO load waiting for the host to supply its value
+    pub pending_mmio: Option<MmioAccess>,
+    /// Counter CSR values seen by the guest
+    pub counters: CounterShadow,
+    /// Paravirtual clock page
+    pub pvclock: PvClock,
 }
 
 /// Nested virtualization state
@@ -329,6 +384,10 @@ impl Vcpu {
             },
             wait_queue: None,
             nested_virt: None,
+            mmio_cache: MmioDecodeCache::new(),
+            pending_mmio: None,
+            counters: CounterShadow::default(),
+            pvclock: PvClock::new(crate::utils::time::timer_frequency()),
         }
     }
 
@@ -377,6 +436,10 @@ impl Vcpu {
             },
             wait_queue: None,
             nested_virt: None,
+            mmio_cache: MmioDecodeCache::new(),
+            pending_mmio: None,
+            counters: CounterShadow::default(),
+            pvclock: PvClock::new(crate::utils::time::timer_frequency()),
         }
     }
 
@@ -424,7 +487,7 @@ impl Vcpu {
     }
 
     /// Initialize the VCPU
-    pub fn init(&mut self, entry_pc: usize, stack_pointer: usize) -> Result<(), &'static str> {
+    pub fn init(&mut self, entry_pc: usize, stack_pointer: usize) -> Result<(), Error> {
         // Set up initial CPU state
         self.cpu_state.set_pc(entry_pc);
         self.cpu_state.set_sp(stack_pointer);
@@ -481,7 +544,7 @@ impl Vcpu {
     }
 
     /// Validate state transition
-    fn validate_state_transition(&self, from: VcpuState, to: VcpuState) -> Result<(), &'static str> {
+    fn validate_state_transition(&self, from: VcpuState, to: VcpuState) -> Result<(), Error> {
         use VcpuState::*;
 
         match (from, to) {
@@ -492,19 +555,20 @@ impl Vcpu {
             (Running, Blocked) => Ok(()),
             (Running, Exited) => Ok(()),
             (Blocked, Ready) => Ok(()),
+            (Ready, Blocked) => Ok(()),
             (Ready, Exited) => Ok(()),
 
             // Invalid transitions
-            (Uninitialized, Running) => Err("Cannot transition from Uninitialized to Running"),
-            (Uninitialized, Blocked) => Err("Cannot transition from Uninitialized to Blocked"),
-            (Uninitialized, Exited) => Err("Cannot transition from Uninitialized to Exited"),
-            (Exited, _) => Err("Cannot transition from Exited state"),
+            (Uninitialized, Running) => Err(Error::InvalidState("Cannot transition from Uninitialized to Running")),
+            (Uninitialized, Blocked) => Err(Error::InvalidState("Cannot transition from Uninitialized to Blocked")),
+            (Uninitialized, Exited) => Err(Error::InvalidState("Cannot transition from Uninitialized to Exited")),
+            (Exited, _) => Err(Error::InvalidState("Cannot transition from Exited state")),
 
             // Allow same state (no-op)
             (state1, state2) if state1 == state2 => Ok(()),
 
             // All other transitions are invalid
-            _ => Err("Invalid state transition"),
+            _ => Err(Error::InvalidState("Invalid state transition")),
         }
     }
 
@@ -591,9 +655,9 @@ impl Vcpu {
     }
 
     /// Block VCPU with timeout
-    pub fn block_with_timeout(&mut self, reason: VcpuWaitReason, timeout_ns: u64) -> Result<(), &'static str> {
+    pub fn block_with_timeout(&mut self, reason: VcpuWaitReason, timeout_ns: u64) -> Result<(), Error> {
         if !self.can_transition_to(VcpuState::Blocked) {
-            return Err("Cannot transition to Blocked state");
+            return Err(Error::InvalidState("Cannot transition to Blocked state"));
         }
 
         self.wait_queue = Some(VcpuWaitQueue {
@@ -608,9 +672,9 @@ impl Vcpu {
     }
 
     /// Unblock VCPU
-    pub fn unblock(&mut self) -> Result<(), &'static str> {
+    pub fn unblock(&mut self) -> Result<(), Error> {
         if self.state != VcpuState::Blocked {
-            return Err("VCPU is not in Blocked state");
+            return Err(Error::InvalidState("VCPU is not in Blocked state"));
         }
 
         self.wait_queue = None;
@@ -656,9 +720,9 @@ impl Vcpu {
     }
 
     /// Reset VCPU to initial state
-    pub fn reset(&mut self) -> Result<(), &'static str> {
+    pub fn reset(&mut self) -> Result<(), Error> {
         if self.state == VcpuState::Running {
-            return Err("Cannot reset VCPU while running");
+            return Err(Error::InvalidState("Cannot reset VCPU while running"));
         }
 
         // Reset CPU state
@@ -682,6 +746,8 @@ impl Vcpu {
         // Reset exit info and wait queue
         self.exit_info = None;
         self.wait_queue = None;
+        self.mmio_cache.invalidate();
+        self.pending_mmio = None;
 
         // Set to uninitialized state
         self.state = VcpuState::Uninitialized;
@@ -692,9 +758,9 @@ impl Vcpu {
     }
 
     /// Pause VCPU execution
-    pub fn pause(&mut self) -> Result<(), &'static str> {
+    pub fn pause(&mut self) -> Result<(), Error> {
         if self.state != VcpuState::Running {
-            return Err("VCPU is not running");
+            return Err(Error::InvalidState("VCPU is not running"));
         }
 
         self.set_state(VcpuState::Blocked);
@@ -710,9 +776,9 @@ impl Vcpu {
     }
 
     /// Resume VCPU execution
-    pub fn resume(&mut self) -> Result<(), &'static str> {
+    pub fn resume(&mut self) -> Result<(), Error> {
         if self.state != VcpuState::Blocked {
-            return Err("VCPU is not paused/blocked");
+            return Err(Error::InvalidState("VCPU is not paused/blocked"));
         }
 
         self.wait_queue = None;
@@ -723,14 +789,14 @@ impl Vcpu {
     }
 
     /// Shutdown VCPU gracefully
-    pub fn shutdown(&mut self) -> Result<(), &'static str> {
+    pub fn shutdown(&mut self) -> Result<(), Error> {
         if self.state == VcpuState::Exited {
-            return Err("VCPU is already shut down");
+            return Err(Error::Busy("VCPU is already shut down"));
         }
 
         // Set exit reason to normal shutdown
         self.exit_info = Some(VcpuExitInfo {
-            reason: VcpuExitReason::Unknown,
+            reason: VcpuExitReason::Shutdown,
             trap_cause: 0,
             trap_val: 0,
             instruction: 0,
@@ -742,9 +808,9 @@ impl Vcpu {
     }
 
     /// Inject a virtual interrupt
-    pub fn inject_interrupt(&mut self, interrupt_id: u32) -> Result<(), &'static str> {
+    pub fn inject_interrupt(&mut self, interrupt_id: u32) -> Result<(), Error> {
         if !self.flags.contains(VcpuFlags::VIRTUAL_INTERRUPTS) {
-            return Err("Virtual interrupts not enabled");
+            return Err(Error::InvalidState("Virtual interrupts not enabled"));
         }
 
         let interrupt_bit = 1u64 << interrupt_id;
@@ -787,7 +853,7 @@ impl Vcpu {
     // ===== ENHANCED VIRTUAL INTERRUPT INJECTION METHODS =====
 
     /// Initialize VCPU with virtual interrupt controller
-    pub fn init_virtual_interrupts(&mut self) -> Result<(), &'static str> {
+    pub fn init_virtual_interrupts(&mut self) -> Result<(), Error> {
         if !self.flags.contains(VcpuFlags::VIRTUAL_INTERRUPTS) {
             return Ok(()); // VCPU not configured for virtual interrupts
         }
@@ -804,7 +870,7 @@ impl Vcpu {
 
     /// Inject virtual interrupt using enhanced controller
     pub fn inject_virtual_interrupt(&mut self, interrupt_type: VirtualInterruptType,
-                                   flags: VirtualInterruptFlags) -> Result<InjectionResult, &'static str> {
+                                   flags: VirtualInterruptFlags) -> Result<InjectionResult, Error> {
         if let Some(vintc_key) = self.vintc_key {
             let result = inject_interrupt(self.id as VcpuId, interrupt_type, flags);
 
@@ -834,9 +900,9 @@ impl Vcpu {
 
     /// Legacy interrupt injection method (fallback)
     fn legacy_inject_interrupt(&mut self, interrupt_type: VirtualInterruptType,
-                              _flags: VirtualInterruptFlags) -> Result<(), &'static str> {
+                              _flags: VirtualInterruptFlags) -> Result<(), Error> {
         if !self.is_interrupt_enabled_legacy(interrupt_type.bit_position() as u32) {
-            return Err("Interrupt is not enabled");
+            return Err(Error::InvalidState("Interrupt is not enabled"));
         }
 
         self.pending_interrupts |= interrupt_type.mask();
@@ -853,7 +919,7 @@ impl Vcpu {
     }
 
     /// Clear virtual interrupt using enhanced controller
-    pub fn clear_virtual_interrupt(&mut self, interrupt_type: VirtualInterruptType) -> Result<(), &'static str> {
+    pub fn clear_virtual_interrupt(&mut self, interrupt_type: VirtualInterruptType) -> Result<(), Error> {
         if let Some(vintc_key) = self.vintc_key {
             clear_interrupt(self.id as VcpuId, interrupt_type)?;
         }
@@ -866,42 +932,42 @@ impl Vcpu {
     }
 
     /// Assert virtual interrupt (level-triggered)
-    pub fn assert_virtual_interrupt(&mut self, interrupt_type: VirtualInterruptType) -> Result<(), &'static str> {
+    pub fn assert_virtual_interrupt(&mut self, interrupt_type: VirtualInterruptType) -> Result<(), Error> {
         let flags = VirtualInterruptFlags::LEVEL_TRIGGERED | VirtualInterruptFlags::NORMAL;
         self.inject_virtual_interrupt(interrupt_type, flags)
             .map(|_| ())
     }
 
     /// Deassert virtual interrupt
-    pub fn deassert_virtual_interrupt(&mut self, interrupt_type: VirtualInterruptType) -> Result<(), &'static str> {
+    pub fn deassert_virtual_interrupt(&mut self, interrupt_type: VirtualInterruptType) -> Result<(), Error> {
         self.clear_virtual_interrupt(interrupt_type)
     }
 
     /// Inject software interrupt (IPI)
-    pub fn inject_software_interrupt(&mut self) -> Result<(), &'static str> {
+    pub fn inject_software_interrupt(&mut self) -> Result<(), Error> {
         let flags = VirtualInterruptFlags::IMMEDIATE | VirtualInterruptFlags::AUTO_CLEAR;
         self.inject_virtual_interrupt(VirtualInterruptType::SupervisorSoftware, flags)
             .map(|_| ())
     }
 
     /// Inject timer interrupt
-    pub fn inject_timer_interrupt(&mut self) -> Result<(), &'static str> {
+    pub fn inject_timer_interrupt(&mut self) -> Result<(), Error> {
         let flags = VirtualInterruptFlags::IMMEDIATE;
         self.inject_virtual_interrupt(VirtualInterruptType::SupervisorTimer, flags)
             .map(|_| ())
     }
 
     /// Inject external interrupt
-    pub fn inject_external_interrupt(&mut self) -> Result<(), &'static str> {
+    pub fn inject_external_interrupt(&mut self) -> Result<(), Error> {
         let flags = VirtualInterruptFlags::NORMAL;
         self.inject_virtual_interrupt(VirtualInterruptType::SupervisorExternal, flags)
             .map(|_| ())
     }
 
     /// Inject custom virtual interrupt
-    pub fn inject_custom_interrupt(&mut self, interrupt_id: u32, flags: VirtualInterruptFlags) -> Result<(), &'static str> {
+    pub fn inject_custom_interrupt(&mut self, interrupt_id: u32, flags: VirtualInterruptFlags) -> Result<(), Error> {
         if interrupt_id < 10 || interrupt_id > 63 {
-            return Err("Custom interrupt ID must be between 10 and 63");
+            return Err(Error::InvalidArgument("Custom interrupt ID must be between 10 and 63"));
         }
 
         let interrupt_type = VirtualInterruptType::Custom(interrupt_id);
@@ -950,7 +1016,7 @@ impl Vcpu {
     }
 
     /// Enable virtual interrupt by type
-    pub fn enable_virtual_interrupt(&mut self, interrupt_type: VirtualInterruptType) -> Result<(), &'static str> {
+    pub fn enable_virtual_interrupt(&mut self, interrupt_type: VirtualInterruptType) -> Result<(), Error> {
         if let Some(vintc_key) = self.vintc_key {
             if let Some(controller) = get_controller_mut() {
                 if let Some(state) = controller.get_vcpu_state_mut(vintc_key) {
@@ -966,7 +1032,7 @@ impl Vcpu {
     }
 
     /// Disable virtual interrupt by type
-    pub fn disable_virtual_interrupt(&mut self, interrupt_type: VirtualInterruptType) -> Result<(), &'static str> {
+    pub fn disable_virtual_interrupt(&mut self, interrupt_type: VirtualInterruptType) -> Result<(), Error> {
         if let Some(vintc_key) = self.vintc_key {
             if let Some(controller) = get_controller_mut() {
                 if let Some(state) = controller.get_vcpu_state_mut(vintc_key) {
@@ -1000,7 +1066,7 @@ impl Vcpu {
     }
 
     /// Sync virtual interrupt state with hardware
-    pub fn sync_virtual_interrupts(&mut self) -> Result<(), &'static str> {
+    pub fn sync_virtual_interrupts(&mut self) -> Result<(), Error> {
         if let Some(vintc_key) = self.vintc_key {
             if let Some(controller) = get_controller_mut() {
                 if let Some(state) = controller.get_vcpu_state_mut(vintc_key) {
@@ -1021,7 +1087,7 @@ impl Vcpu {
     }
 
     /// Cleanup virtual interrupt controller resources
-    pub fn cleanup_virtual_interrupts(&mut self) -> Result<(), &'static str> {
+    pub fn cleanup_virtual_interrupts(&mut self) -> Result<(), Error> {
         if let Some(vintc_key) = self.vintc_key {
             unregister_vcpu(vintc_key)?;
             self.vintc_key = None;
@@ -1033,13 +1099,16 @@ impl Vcpu {
     }
 
     /// Save VCPU state
-    pub fn save_state(&mut self) -> Result<(), &'static str> {
+    pub fn save_state(&mut self) -> Result<(), Error> {
         // Save guest CSR state using enhanced virtual CSR
         self.virtual_csr = VirtualCsrState::save_from_hw(self.vmid)?;
 
         // Also update legacy guest CSR for compatibility
         self.guest_csr = GuestCsrState::save();
 
+        // Cached decodes are only trusted while this VCPU stays scheduled
+        self.mmio_cache.invalidate();
+
         // Update statistics
         self.stats.instructions_executed += read_csr!(crate::arch::riscv64::cpu::csr::MINSTRET);
         self.stats.cycles_spent += read_csr!(crate::arch::riscv64::cpu::csr::MCYCLE);
@@ -1049,7 +1118,7 @@ impl Vcpu {
     }
 
     /// Restore VCPU state
-    pub fn restore_state(&self) -> Result<(), &'static str> {
+    pub fn restore_state(&self) -> Result<(), Error> {
         // Restore using enhanced virtual CSR
         self.virtual_csr.restore_to_hw()?;
 
@@ -1066,7 +1135,7 @@ impl Vcpu {
     }
 
     /// Save VCPU state with validation
-    pub fn save_state_validated(&mut self) -> Result<(), &'static str> {
+    pub fn save_state_validated(&mut self) -> Result<(), Error> {
         // Save state
         self.save_state()?;
 
@@ -1081,7 +1150,7 @@ impl Vcpu {
     }
 
     /// Restore VCPU state with optimized switching
-    pub fn restore_state_optimized(&self, from_state: &VirtualCsrState) -> Result<(), &'static str> {
+    pub fn restore_state_optimized(&self, from_state: &VirtualCsrState) -> Result<(), Error> {
         // Use optimized state switching
         switch_state(from_state, &self.virtual_csr)?;
 
@@ -1115,13 +1184,269 @@ impl Vcpu {
         self.stats = VcpuStats::default();
     }
 
+    /// Get exit counts by reason
+    pub fn exit_stats(&self) -> VcpuExitStats {
+        self.stats.exits
+    }
+
+    /// Decode the MMIO access at a faulting guest PC in address space `vsatp`
+    ///
+    /// `fetch` reads the guest instruction and is only called when the
+    /// PC is not in the decode cache.
+    pub fn decode_mmio(
+        &mut self,
+        vsatp: usize,
+        pc: usize,
+        fetch: impl FnOnce(usize) -> Option<u32>,
+    ) -> Option<MmioAccess> {
+        self.mmio_cache.decode(vsatp, pc, fetch)
+    }
+
+    /// Forget cached MMIO decodes after guest code was modified
+    pub fn invalidate_mmio_cache(&mut self) {
+        self.mmio_cache.invalidate();
+    }
+
+    /// Enter the guest and run it until an exit the host must handle
+    ///
+    /// A host control thread calls this in a loop, servicing each returned
+    /// exit before re-entering. Hypercalls for which `vm_hypercall` returns
+    /// true are left to the VM ahead of those registered with the
+    /// hypervisor.
+    pub fn run(&mut self, vm_hypercall: impl Fn(usize) -> bool) -> VcpuExit {
+        if self.is_shut_down() {
+            return VcpuExit::Shutdown;
+        }
+        if self.pending_mmio.is_some() {
+            return VcpuExit::InternalError {
+                error: Error::InvalidState("MMIO load not completed"),
+                cause: 0,
+                tval: 0,
+            };
+        }
+
+        loop {
+            // Guest breakpoints may have changed on another hart
+            crate::arch::riscv64::debug::vm_debug::load_guest_triggers();
+
+            let start = crate::arch::riscv64::cpu::asm::read_cycle();
+            let trap = match super::enter_virtualization(self).and_then(|_| super::exit_virtualization()) {
+                Ok(trap) => trap,
+                Err(error) => return VcpuExit::InternalError { error, cause: 0, tval: 0 },
+            };
+            self.counters.cycle += crate::arch::riscv64::cpu::asm::read_cycle().wrapping_sub(start);
+
+            if let Err(error) = self.handle_hypervisor_trap(&trap) {
+                return VcpuExit::InternalError { error, cause: trap.cause, tval: trap.tval };
+            }
+
+            // Whatever was pending has been taken by the guest
+            if !self.has_virtual_interrupts_pending() {
+                crate::core::sched::vcpu_interrupts_handled(
+                    self.vmid as crate::core::vmm::VmId,
+                    self.id as crate::core::vmm::VcpuId,
+                );
+            }
+
+            // Idle guests give up the CPU until an interrupt is pending
+            if self.handle_wfi(&trap) {
+                continue;
+            }
+
+            // Counter reads are emulated without involving the host
+            if trap.cause == 22 && self.emulate_csr(&trap).is_ok() {
+                continue;
+            }
+            // So are hypercalls registered with the hypervisor, unless the
+            // VM handles the call itself
+            if !vm_hypercall(self.cpu_state.gpr[17]) && self.dispatch_hypercall(&trap) {
+                continue;
+            }
+            return self.decode_exit(&trap);
+        }
+    }
+
+    /// Emulate a guest CSR instruction that trapped as a virtual instruction
+    ///
+    /// Only counter reads are emulated; on success the result is in the
+    /// destination register and the PC is past the instruction.
+    pub fn emulate_csr(&mut self, trap_info: &HypervisorTrapInfo) -> Result<(), Error> {
+        let insn = decode_csr_instruction(trapped_instruction(trap_info.htinst, trap_info.tval))
+            .ok_or(Error::Unsupported("Not a CSR instruction"))?;
+        let host_time = crate::arch::riscv64::cpu::asm::read_time() as u64;
+
+        emulate_csr_instruction(&insn, &self.counters, host_time, &mut self.cpu_state.gpr, &mut self.cpu_state.pc)
+    }
+
+    /// Deschedule the VCPU on a WFI trapped as a virtual instruction
+    ///
+    /// Returns `false` for other traps. The guest resumes after the WFI
+    /// once an interrupt is pending for it.
+    pub fn handle_wfi(&mut self, trap_info: &HypervisorTrapInfo) -> bool {
+        if trap_info.cause != 22 || trapped_instruction(trap_info.htinst, trap_info.tval) != WFI_INSTRUCTION {
+            return false;
+        }
+
+        self.cpu_state.pc += 4;
+        if !self.has_virtual_interrupts_pending() {
+            if let Err(e) = crate::core::sched::vcpu_idle(crate::core::sched::IdleReason::Wfi) {
+                log::warn!("VCPU {}: failed to deschedule on WFI: {:?}", self.id, e);
+            }
+        }
+        true
+    }
+
+    /// Run a hypercall registered with the hypervisor on the VCPU's registers
+    ///
+    /// Returns `false` for other traps, for shutdown, and for calls the VM
+    /// handles itself; on success the PC is past the `ecall`.
+    pub fn dispatch_hypercall(&mut self, trap_info: &HypervisorTrapInfo) -> bool {
+        if self.determine_exit_reason(trap_info) != VcpuExitReason::SystemCall
+            || HypercallId::from_raw(self.cpu_state.gpr[17]) == Some(HypercallId::Shutdown)
+        {
+            return false;
+        }
+        if !hypercall::dispatch_hypercall(&mut self.cpu_state.gpr) {
+            return false;
+        }
+
+        self.cpu_state.pc += 4;
+        true
+    }
+
+    /// Turn a guest trap into the exit reported by `run`
+    pub fn decode_exit(&mut self, trap_info: &HypervisorTrapInfo) -> VcpuExit {
+        let internal_error = |error| VcpuExit::InternalError {
+            error,
+            cause: trap_info.cause,
+            tval: trap_info.tval,
+        };
+
+        match self.determine_exit_reason(trap_info) {
+            VcpuExitReason::Interrupt => VcpuExit::Interrupt {
+                cause: trap_info.cause & !0x80000000,
+            },
+            VcpuExitReason::Io => self.decode_mmio_exit(trap_info).unwrap_or_else(|| {
+                internal_error(Error::Unsupported("Undecodable MMIO instruction"))
+            }),
+            VcpuExitReason::SystemCall => {
+                let regs = &self.cpu_state.gpr;
+                let nr = regs[17];
+                let mut args = [0; 6];
+                args.copy_from_slice(&regs[10..16]);
+                let system_reset = nr == SBI_EXT_SRST && regs[16] == SBI_SRST_SYSTEM_RESET;
+
+                // Resume after the ecall
+                self.cpu_state.pc += 4;
+
+                // SBI system reset: reboots reset the VM in place
+                if system_reset && args[0] != SBI_SRST_TYPE_SHUTDOWN {
+                    let vm_id = self.vmid as crate::core::vmm::VmId;
+                    if let Err(e) = crate::core::vmm::reset_vm(vm_id).and_then(|_| crate::core::vmm::start_vm(vm_id)) {
+                        log::error!("VCPU {}: failed to reset VM {}: {:?}", self.id, vm_id, e);
+                    }
+                    return VcpuExit::Reset;
+                }
+
+                if system_reset || HypercallId::from_raw(nr) == Some(HypercallId::Shutdown) {
+                    if let Some(info) = self.exit_info.as_mut() {
+                        info.reason = VcpuExitReason::Shutdown;
+                    }
+                    VcpuExit::Shutdown
+                } else {
+                    VcpuExit::Hypercall { nr, args }
+                }
+            }
+            VcpuExitReason::IllegalInstruction => internal_error(Error::Unsupported("Illegal instruction")),
+            VcpuExitReason::MemoryFault => internal_error(Error::NotFound("Unhandled guest page fault")),
+            _ => internal_error(Error::Unsupported("Unhandled guest trap")),
+        }
+    }
+
+    /// Decode an MMIO guest-page fault
+    fn decode_mmio_exit(&mut self, trap_info: &HypervisorTrapInfo) -> Option<VcpuExit> {
+        // htinst holds the trapped instruction in transformed form, with bit
+        // 1 clear if the original was compressed. It is always current, so
+        // the decode cache only stands in when the hardware reports nothing.
+        let htinst = trap_info.htinst as u32;
+        let access = if htinst != 0 {
+            let mut access = decode_mmio_access(htinst | 0x2)?;
+            access.len = if htinst & 0x2 == 0 { 2 } else { 4 };
+            access
+        } else {
+            self.decode_mmio(trap_info.guest_csr.vsatp, self.cpu_state.pc, fetch_guest_instruction)?
+        };
+
+        // htval holds the guest physical address shifted right by two; the
+        // low bits match the guest virtual address
+        let addr = ((trap_info.tval << 2) | (trap_info.gva & 0x3)) as u64;
+        let data = if access.is_write {
+            let value = self.cpu_state.gpr[access.reg as usize] as u64;
+            self.cpu_state.pc += access.len as usize;
+            match access.size {
+                8 => value,
+                size => value & ((1u64 << (size * 8)) - 1),
+            }
+        } else {
+            self.pending_mmio = Some(access);
+            0
+        };
+
+        Some(VcpuExit::Mmio {
+            addr,
+            size: access.size,
+            is_write: access.is_write,
+            data,
+        })
+    }
+
+    /// Supply the value of the MMIO load reported by the last exit
+    pub fn complete_mmio_read(&mut self, value: u64) -> Result<(), Error> {
+        let access = self.pending_mmio.take().ok_or(Error::InvalidState("No MMIO load pending"))?;
+
+        let bits = access.size as u32 * 8;
+        let value = if bits == 64 {
+            value
+        } else if access.sign_extend {
+            (((value << (64 - bits)) as i64) >> (64 - bits)) as u64
+        } else {
+            value & ((1u64 << bits) - 1)
+        };
+
+        // x0 is hardwired to zero
+        if access.reg != 0 {
+            self.cpu_state.gpr[access.reg as usize] = value as usize;
+        }
+        self.cpu_state.pc += access.len as usize;
+        Ok(())
+    }
+
+    /// Return the result of the hypercall reported by the last exit
+    pub fn complete_hypercall(&mut self, result: HypercallResult) {
+        let (error, value) = match result {
+            Ok(value) => (0, value),
+            Err(err) => (err.code(), 0),
+        };
+        self.cpu_state.gpr[10] = error as usize;
+        self.cpu_state.gpr[11] = value;
+    }
+
+    /// Check whether the VCPU was shut down
+    pub fn is_shut_down(&self) -> bool {
+        self.state == VcpuState::Exited
+            && self.exit_info.as_ref().map_or(false, |info| info.reason == VcpuExitReason::Shutdown)
+    }
+
     /// Handle hypervisor trap
-    pub fn handle_hypervisor_trap(&mut self, trap_info: &HypervisorTrapInfo) -> Result<(), &'static str> {
+    pub fn handle_hypervisor_trap(&mut self, trap_info: &HypervisorTrapInfo) -> Result<(), Error> {
         self.stats.hypervisor_traps += 1;
 
+        let reason = self.determine_exit_reason(trap_info);
+        self.stats.exits.record(reason);
+
         // Create exit information
         self.exit_info = Some(VcpuExitInfo {
-            reason: self.determine_exit_reason(trap_info),
+            reason,
             trap_cause: trap_info.cause,
             trap_val: trap_info.tval,
             instruction: trap_info.htinst,
@@ -1144,14 +1469,26 @@ impl Vcpu {
             match trap_info.cause {
                 2 => VcpuExitReason::IllegalInstruction,
                 3 => VcpuExitReason::Breakpoint,
-                8 | 9 => VcpuExitReason::SystemCall,
-                12 | 13 | 15 => VcpuExitReason::MemoryFault,
+                8 | 9 | 10 => VcpuExitReason::SystemCall,
+                12 | 13 | 15 | 20 => VcpuExitReason::MemoryFault,
+                // Guest-page faults on loads and stores are how MMIO traps
+                21 | 23 => VcpuExitReason::Io,
                 _ => VcpuExitReason::Unknown,
             }
         }
     }
 }
 
+/// Encoding of the WFI instruction
+const WFI_INSTRUCTION: u32 = 0x1050_0073;
+
+/// SBI system reset extension ID ("SRST")
+const SBI_EXT_SRST: usize = 0x5352_5354;
+/// SBI `sbi_system_reset` function ID
+const SBI_SRST_SYSTEM_RESET: usize = 0;
+/// `sbi_system_reset` reset type for shutdown; the others are reboots
+const SBI_SRST_TYPE_SHUTDOWN: usize = 0;
+
 /// VCPU exit reason
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum VcpuExitReason {
@@ -1169,10 +1506,60 @@ pub enum VcpuExitReason {
     Io,
     /// Hypercall
     Hypercall,
+    /// Guest or host shut the VCPU down
+    Shutdown,
     /// Unknown reason
     Unknown,
 }
 
+/// Exit returned to the host by `Vcpu::run`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VcpuExit {
+    /// Guest load or store to an emulated region
+    ///
+    /// For stores `data` holds the value written. For loads the host must
+    /// call `Vcpu::complete_mmio_read` before running the VCPU again;
+    /// `VirtualMachine::run_vcpu` does so for the regions it emulates.
+    Mmio {
+        /// Guest physical address
+        addr: u64,
+        /// Access size in bytes
+        size: u8,
+        /// Store (true) or load (false)
+        is_write: bool,
+        /// Value stored, 0 for loads
+        data: u64,
+    },
+    /// Guest hypercall not handled by the hypervisor
+    ///
+    /// The host completes it with `Vcpu::complete_hypercall`;
+    /// `VirtualMachine::run_vcpu` does so itself.
+    Hypercall {
+        /// Raw call number from a7
+        nr: usize,
+        /// Arguments from a0-a5
+        args: [usize; 6],
+    },
+    /// Host interrupt taken while the guest was running
+    Interrupt {
+        /// Interrupt cause without the interrupt bit
+        cause: usize,
+    },
+    /// VCPU was shut down and must not be run again before a reset
+    Shutdown,
+    /// Guest rebooted; the VM was reset and restarts from its entry point
+    Reset,
+    /// Exit the hypervisor cannot hand to the host
+    InternalError {
+        /// What went wrong
+        error: Error,
+        /// Trap cause, 0 if the failure was not a trap
+        cause: usize,
+        /// Trap value
+        tval: usize,
+    },
+}
+
 /// VCPU exit information
 #[derive(Debug, Clone)]
 pub struct VcpuExitInfo {
@@ -1224,9 +1611,9 @@ impl VcpuManager {
         &mut self,
         vmid: u16,
         flags: VcpuFlags,
-    ) -> Result<&mut Vcpu, &'static str> {
+    ) -> Result<&mut Vcpu, Error> {
         if self.next_vcpu_id >= 16 {
-            return Err("Maximum VCPUs reached");
+            return Err(Error::OutOfResources("Maximum VCPUs reached"));
         }
 
         let vcpu_id = self.next_vcpu_id;
@@ -1239,9 +1626,9 @@ impl VcpuManager {
     }
 
     /// Free a VCPU
-    pub fn free_vcpu(&mut self, vcpu_id: u8) -> Result<(), &'static str> {
+    pub fn free_vcpu(&mut self, vcpu_id: u8) -> Result<(), Error> {
         let index = self.vcpus.iter().position(|v| v.id == vcpu_id)
-            .ok_or("VCPU not found")?;
+            .ok_or(Error::NotFound("VCPU not found"))?;
 
         // If this is the current VCPU, clear it
         if self.current_vcpu == Some(vcpu_id) {
@@ -1268,11 +1655,11 @@ impl VcpuManager {
     }
 
     /// Schedule a VCPU to run
-    pub fn schedule_vcpu(&mut self, vcpu_id: u8) -> Result<(), &'static str> {
-        let vcpu = self.get_vcpu(vcpu_id).ok_or("VCPU not found")?;
+    pub fn schedule_vcpu(&mut self, vcpu_id: u8) -> Result<(), Error> {
+        let vcpu = self.get_vcpu(vcpu_id).ok_or(Error::NotFound("VCPU not found"))?;
 
         if !vcpu.is_ready() {
-            return Err("VCPU is not ready");
+            return Err(Error::InvalidState("VCPU is not ready"));
         }
 
         // If another VCPU is running, save its state
@@ -1294,6 +1681,43 @@ impl VcpuManager {
         Ok(())
     }
 
+    /// Deschedule every runnable VCPU and block it for `reason`
+    ///
+    /// VCPUs already blocked for another reason are left untouched.
+    /// Returns the number of VCPUs parked.
+    pub fn park_all(&mut self, reason: VcpuWaitReason) -> Result<usize, Error> {
+        self.current_vcpu = None;
+
+        let mut parked = 0;
+        for vcpu in &mut self.vcpus {
+            if vcpu.is_running() {
+                vcpu.save_state()?;
+            } else if !vcpu.is_ready() {
+                continue;
+            }
+
+            vcpu.block_with_timeout(reason, 0)?;
+            parked += 1;
+        }
+
+        Ok(parked)
+    }
+
+    /// Make every VCPU parked for `reason` ready again
+    ///
+    /// Returns the number of VCPUs released.
+    pub fn unpark_all(&mut self, reason: VcpuWaitReason) -> Result<usize, Error> {
+        let mut released = 0;
+        for vcpu in &mut self.vcpus {
+            if vcpu.is_blocked_for(reason) {
+                vcpu.unblock()?;
+                released += 1;
+            }
+        }
+
+        Ok(released)
+    }
+
     /// Get list of all VCPUs
     pub fn get_vcpus(&self) -> &[Vcpu] {
         &self.vcpus
@@ -1332,7 +1756,7 @@ impl VcpuManager {
     }
 
     /// Inject virtual interrupt into all VCPUs of a VM
-    pub fn inject_interrupt_to_vm(&mut self, vmid: u16, interrupt_id: u32) -> Result<(), &'static str> {
+    pub fn inject_interrupt_to_vm(&mut self, vmid: u16, interrupt_id: u32) -> Result<(), Error> {
         for vcpu in &mut self.vcpus {
             if vcpu.vmid == vmid {
                 vcpu.inject_interrupt(interrupt_id)?;
@@ -1484,9 +1908,9 @@ impl VcpuManager {
     }
 
     /// Balance VCPUs across available host CPUs
-    pub fn balance_vcpus(&mut self, available_cpus: usize) -> Result<(), &'static str> {
+    pub fn balance_vcpus(&mut self, available_cpus: usize) -> Result<(), Error> {
         if available_cpus == 0 {
-            return Err("No available CPUs");
+            return Err(Error::OutOfResources("No available CPUs"));
         }
 
         // Simple load balancing based on current host CPU assignment
@@ -1581,9 +2005,9 @@ impl VcpuManager {
         affinity: VcpuAffinity,
         time_slice_ns: u64,
         periodicity_ns: u64,
-    ) -> Result<&mut Vcpu, &'static str> {
+    ) -> Result<&mut Vcpu, Error> {
         if self.next_vcpu_id >= 16 {
-            return Err("Maximum VCPUs reached");
+            return Err(Error::OutOfResources("Maximum VCPUs reached"));
         }
 
         let vcpu_id = self.next_vcpu_id;
@@ -1604,9 +2028,9 @@ impl VcpuManager {
         name: String,
         flags: VcpuFlags,
         l2_vmid: u16,
-    ) -> Result<&mut Vcpu, &'static str> {
+    ) -> Result<&mut Vcpu, Error> {
         if self.next_vcpu_id >= 16 {
-            return Err("Maximum VCPUs reached");
+            return Err(Error::OutOfResources("Maximum VCPUs reached"));
         }
 
         let vcpu_id = self.next_vcpu_id;
@@ -1626,9 +2050,9 @@ impl VcpuManager {
         stack_size: usize,
         flags: VcpuFlags,
         priority: VcpuPriority,
-    ) -> Result<&mut Vcpu, &'static str> {
+    ) -> Result<&mut Vcpu, Error> {
         if self.next_vcpu_id >= 16 {
-            return Err("Maximum VCPUs reached");
+            return Err(Error::OutOfResources("Maximum VCPUs reached"));
         }
 
         let vcpu_id = self.next_vcpu_id;
@@ -1650,9 +2074,9 @@ impl VcpuManager {
     }
 
     /// Destroy orphan VCPU
-    pub fn destroy_orphan_vcpu(&mut self, vcpu_id: u8) -> Result<(), &'static str> {
+    pub fn destroy_orphan_vcpu(&mut self, vcpu_id: u8) -> Result<(), Error> {
         let index = self.vcpus.iter().position(|v| v.id == vcpu_id && v.vmid == 0)
-            .ok_or("Orphan VCPU not found")?;
+            .ok_or(Error::NotFound("Orphan VCPU not found"))?;
 
         // If this is the current VCPU, clear it
         if self.current_vcpu == Some(vcpu_id) {
@@ -1667,7 +2091,7 @@ impl VcpuManager {
     }
 
     /// Cleanup all VCPUs for a specific VM
-    pub fn cleanup_vm_vcpus(&mut self, vmid: u16) -> Result<usize, &'static str> {
+    pub fn cleanup_vm_vcpus(&mut self, vmid: u16) -> Result<usize, Error> {
         let initial_count = self.vcpus.len();
 
         // Remove all VCPUs belonging to the VM
@@ -1688,19 +2112,28 @@ impl VcpuManager {
         Ok(removed_count)
     }
 
+    /// Forget cached MMIO decodes of every VCPU of a VM
+    ///
+    /// Call after writing to guest memory that may hold code.
+    pub fn invalidate_mmio_caches(&mut self, vmid: u16) {
+        for vcpu in self.vcpus.iter_mut().filter(|v| v.vmid == vmid) {
+            vcpu.invalidate_mmio_cache();
+        }
+    }
+
     /// Clone VCPU configuration
     pub fn clone_vcpu(
         &mut self,
         source_vcpu_id: u8,
         new_vcpu_id: u8,
         new_name: String,
-    ) -> Result<&mut Vcpu, &'static str> {
+    ) -> Result<&mut Vcpu, Error> {
         if self.next_vcpu_id <= new_vcpu_id {
             self.next_vcpu_id = new_vcpu_id + 1;
         }
 
         let source_vcpu = self.get_vcpu(source_vcpu_id)
-            .ok_or("Source VCPU not found")?;
+            .ok_or(Error::NotFound("Source VCPU not found"))?;
 
         let mut new_vcpu = Vcpu::new(
             new_vcpu_id,
@@ -1733,19 +2166,19 @@ impl VcpuManager {
     }
 
     /// Migrate VCPU to different host CPU
-    pub fn migrate_vcpu(&mut self, vcpu_id: u8, target_host_cpu: usize) -> Result<(), &'static str> {
+    pub fn migrate_vcpu(&mut self, vcpu_id: u8, target_host_cpu: usize) -> Result<(), Error> {
         let vcpu = self.get_vcpu_mut(vcpu_id)
-            .ok_or("VCPU not found")?;
+            .ok_or(Error::NotFound("VCPU not found"))?;
 
         // Check if target CPU is in affinity mask
         if !vcpu.affinity.allow_migration &&
            vcpu.host_cpu.is_some() &&
            vcpu.host_cpu.unwrap() != target_host_cpu {
-            return Err("VCPU migration not allowed");
+            return Err(Error::InvalidState("VCPU migration not allowed"));
         }
 
         if ((1 << target_host_cpu) & vcpu.affinity.cpu_mask) == 0 {
-            return Err("Target CPU not in VCPU affinity mask");
+            return Err(Error::InvalidState("Target CPU not in VCPU affinity mask"));
         }
 
         let old_host_cpu = vcpu.host_cpu;
@@ -2476,4 +2909,147 @@ mod tests {
         assert!(broadcast_flags.contains(VirtualInterruptFlags::BROADCAST));
         assert!(broadcast_flags.contains(VirtualInterruptFlags::LEVEL_TRIGGERED));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_exit_stats() {
+        let mut vcpu = Vcpu::new(0, 1, "test-vcpu".to_string(), VcpuFlags::empty());
+        let trap = |cause| HypervisorTrapInfo {
+            guest_csr: GuestCsrState {
+                vsstatus: 0,
+                vsie: 0,
+                vstvec: 0,
+                vsscratch: 0,
+                vsepc: 0,
+                vscause: 0,
+                vstval: 0,
+                vsip: 0,
+                vsatp: 0,
+            },
+            cause,
+            tval: 0,
+            gva: 0,
+            htinst: 0,
+        };
+
+        vcpu.handle_hypervisor_trap(&trap(13)).unwrap();
+        vcpu.handle_hypervisor_trap(&trap(15)).unwrap();
+        vcpu.handle_hypervisor_trap(&trap(10)).unwrap();
+
+        let exits = vcpu.exit_stats();
+        assert_eq!(exits.page_fault, 2);
+        assert_eq!(exits.ecall, 1);
+        assert_eq!(exits.mmio, 0);
+        assert_eq!(exits.total(), vcpu.get_stats().hypervisor_traps);
+
+        vcpu.reset_stats();
+        assert_eq!(vcpu.exit_stats(), VcpuExitStats::default());
+    }
+
+    fn guest_page_fault(cause: usize, gpa: usize, htinst: usize) -> HypervisorTrapInfo {
+        HypervisorTrapInfo {
+            guest_csr: GuestCsrState::new(),
+            cause,
+            tval: gpa >> 2,
+            gva: 0x4000_0000 | (gpa & 0xFFF),
+            htinst,
+        }
+    }
+
+    #[test]
+    fn test_guest_store_returns_mmio_exit() {
+        let mut vcpu = Vcpu::new(0, 1, "test-vcpu".to_string(), VcpuFlags::empty());
+        vcpu.cpu_state.pc = 0x8000_0000;
+        vcpu.cpu_state.gpr[10] = 0x1ab;
+        vcpu.cpu_state.gpr[12] = 0x1122_3344_5566_7788;
+
+        // sd a2, 8(a1)
+        let exit = vcpu.decode_exit(&guest_page_fault(23, 0x1000_0008, 0x00c5_b423));
+        assert_eq!(exit, VcpuExit::Mmio { addr: 0x1000_0008, size: 8, is_write: true, data: 0x1122_3344_5566_7788 });
+        assert_eq!(vcpu.cpu_state.pc, 0x8000_0004);
+
+        // sb a0, 5(a1): unaligned byte register
+        let exit = vcpu.decode_exit(&guest_page_fault(23, 0x1000_0005, 0x00a5_82a3));
+        assert_eq!(exit, VcpuExit::Mmio { addr: 0x1000_0005, size: 1, is_write: true, data: 0xab });
+        assert_eq!(vcpu.cpu_state.pc, 0x8000_0008);
+        assert!(vcpu.pending_mmio.is_none());
+    }
+
+    #[test]
+    fn test_reported_instruction_overrides_cached_decode() {
+        let mut vcpu = Vcpu::new(0, 1, "test-vcpu".to_string(), VcpuFlags::empty());
+        vcpu.cpu_state.pc = 0x8000_0000;
+        vcpu.cpu_state.gpr[12] = 0x55;
+
+        // A load was cached at this PC before the guest rewrote it
+        vcpu.decode_mmio(0, 0x8000_0000, |_| Some(0x0045_a503)).unwrap();
+
+        // sd a2, 8(a1)
+        let exit = vcpu.decode_exit(&guest_page_fault(23, 0x1000_0008, 0x00c5_b423));
+        assert_eq!(exit, VcpuExit::Mmio { addr: 0x1000_0008, size: 8, is_write: true, data: 0x55 });
+        assert!(vcpu.pending_mmio.is_none());
+    }
+
+    #[test]
+    fn test_wfi_trap_resumes_after_instruction() {
+        let mut vcpu = Vcpu::new(0, 1, "test-vcpu".to_string(), VcpuFlags::VIRTUAL_INTERRUPTS);
+        vcpu.cpu_state.pc = 0x8000_0000;
+        let mut trap = HypervisorTrapInfo {
+            guest_csr: GuestCsrState::new(),
+            cause: 22,
+            tval: WFI_INSTRUCTION as usize,
+            gva: 0,
+            htinst: 0,
+        };
+
+        // A pending interrupt keeps the VCPU running past the WFI
+        vcpu.inject_interrupt(5).unwrap();
+        vcpu.enable_interrupt(5);
+        assert!(vcpu.handle_wfi(&trap));
+        assert_eq!(vcpu.cpu_state.pc, 0x8000_0004);
+
+        // Other virtual instructions are not WFI
+        trap.tval = 0x1200_0073;
+        assert!(!vcpu.handle_wfi(&trap));
+        assert_eq!(vcpu.cpu_state.pc, 0x8000_0004);
+    }
+
+    #[test]
+    fn test_guest_load_completed_by_host() {
+        let mut vcpu = Vcpu::new(0, 1, "test-vcpu".to_string(), VcpuFlags::empty());
+        vcpu.cpu_state.pc = 0x8000_0000;
+
+        // lw a0, 4(a1)
+        let exit = vcpu.decode_exit(&guest_page_fault(21, 0x1000_0004, 0x0045_a503));
+        assert_eq!(exit, VcpuExit::Mmio { addr: 0x1000_0004, size: 4, is_write: false, data: 0 });
+        assert!(matches!(vcpu.run(|_| false), VcpuExit::InternalError { .. }));
+
+        vcpu.complete_mmio_read(0x8000_0000).unwrap();
+        assert_eq!(vcpu.cpu_state.gpr[10], 0xFFFF_FFFF_8000_0000);
+        assert_eq!(vcpu.cpu_state.pc, 0x8000_0004);
+        assert!(vcpu.complete_mmio_read(0).is_err());
+    }
+
+    #[test]
+    fn test_guest_cycle_read_is_emulated() {
+        let mut vcpu = Vcpu::new(0, 1, "test-vcpu".to_string(), VcpuFlags::empty());
+        vcpu.cpu_state.pc = 0x8000_0000;
+        vcpu.counters.cycle = 0x1234_5678;
+
+        // csrr a0, cycle; the instruction arrives in the trap value
+        let trap = HypervisorTrapInfo {
+            guest_csr: GuestCsrState::new(),
+            cause: 22,
+            tval: 0xc000_2573,
+            gva: 0,
+            htinst: 0,
+        };
+        vcpu.emulate_csr(&trap).unwrap();
+        assert_eq!(vcpu.cpu_state.gpr[10], 0x1234_5678);
+        assert_eq!(vcpu.cpu_state.pc, 0x8000_0004);
+
+        // csrw cycle, a0 is not emulated
+        let trap = HypervisorTrapInfo { tval: 0xc005_1073, ..trap };
+        assert!(vcpu.emulate_csr(&trap).is_err());
+        assert_eq!(vcpu.cpu_state.pc, 0x8000_0004);
+    }
+}
@@ -11,6 +11,7 @@
 
 use crate::arch::riscv64::cpu::csr;
 use crate::arch::riscv64::virtualization::{VcpuId, VmId};
+use crate::arch::riscv64::Error;
 use bitflags::bitflags;
 use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 
@@ -301,9 +302,9 @@ impl VcpuInterruptState {
 
     /// Inject interrupt with flags
     pub fn inject_interrupt(&mut self, interrupt_type: VirtualInterruptType,
-                           flags: VirtualInterruptFlags) -> Result<(), &'static str> {
+                           flags: VirtualInterruptFlags) -> Result<(), Error> {
         if !self.is_interrupt_enabled(interrupt_type) {
-            return Err("Interrupt is not enabled");
+            return Err(Error::InvalidState("Interrupt is not enabled"));
         }
 
         let mask = interrupt_type.mask();
@@ -336,7 +337,7 @@ impl VcpuInterruptState {
     }
 
     /// Clear interrupt
-    pub fn clear_interrupt(&mut self, interrupt_type: VirtualInterruptType) -> Result<(), &'static str> {
+    pub fn clear_interrupt(&mut self, interrupt_type: VirtualInterruptType) -> Result<(), Error> {
         let mask = interrupt_type.mask();
 
         // Clear pending bits
@@ -356,17 +357,17 @@ impl VcpuInterruptState {
     }
 
     /// Assert/deassert interrupt (similar to Linux IRQ API)
-    pub fn assert_interrupt(&mut self, interrupt_type: VirtualInterruptType) -> Result<(), &'static str> {
+    pub fn assert_interrupt(&mut self, interrupt_type: VirtualInterruptType) -> Result<(), Error> {
         let flags = VirtualInterruptFlags::LEVEL_TRIGGERED | VirtualInterruptFlags::NORMAL;
         self.inject_interrupt(interrupt_type, flags)
     }
 
-    pub fn deassert_interrupt(&mut self, interrupt_type: VirtualInterruptType) -> Result<(), &'static str> {
+    pub fn deassert_interrupt(&mut self, interrupt_type: VirtualInterruptType) -> Result<(), Error> {
         self.clear_interrupt(interrupt_type)
     }
 
     /// Set HVIP register (hardware operation)
-    fn set_hvip_register(&mut self, mask: u64) -> Result<(), &'static str> {
+    fn set_hvip_register(&mut self, mask: u64) -> Result<(), Error> {
         // In a real implementation, this would write to HVIP CSR
         // For now, we simulate the operation
         log::trace!("Would set HVIP register bits: {:#x}", mask);
@@ -374,7 +375,7 @@ impl VcpuInterruptState {
     }
 
     /// Clear HVIP register (hardware operation)
-    fn clear_hvip_register(&mut self, mask: u64) -> Result<(), &'static str> {
+    fn clear_hvip_register(&mut self, mask: u64) -> Result<(), Error> {
         // In a real implementation, this would clear HVIP CSR bits
         // For now, we simulate the operation
         log::trace!("Would clear HVIP register bits: {:#x}", mask);
@@ -382,7 +383,7 @@ impl VcpuInterruptState {
     }
 
     /// Synchronize with hardware state
-    pub fn sync_with_hardware(&mut self) -> Result<(), &'static str> {
+    pub fn sync_with_hardware(&mut self) -> Result<(), Error> {
         // Read current HVIP state
         // In a real implementation, this would read from HVIP CSR
         log::trace!("Would sync VCPU {} interrupt state with hardware", self.vcpu_id);
@@ -428,7 +429,7 @@ pub struct InjectionResult {
     /// Number of VCPUs affected
     pub vcpus_affected: usize,
     /// Error message (if any)
-    pub error: Option<&'static str>,
+    pub error: Option<Error>,
 }
 
 /// Virtual interrupt controller
@@ -496,7 +497,7 @@ impl VirtualInterruptController {
     }
 
     /// Initialize the controller
-    pub fn init(&mut self) -> Result<(), &'static str> {
+    pub fn init(&mut self) -> Result<(), Error> {
         log::info!("Initializing RISC-V Virtual Interrupt Controller");
 
         log::info!("Virtual Interrupt Controller initialized successfully");
@@ -504,9 +505,9 @@ impl VirtualInterruptController {
     }
 
     /// Register a VCPU with the controller
-    pub fn register_vcpu(&mut self, vcpu_id: VcpuId, vmid: VmId) -> Result<usize, &'static str> {
+    pub fn register_vcpu(&mut self, vcpu_id: VcpuId, vmid: VmId) -> Result<usize, Error> {
         if self.vcpu_states.len() >= self.config.max_vcpus {
-            return Err("Maximum VCPU limit reached");
+            return Err(Error::OutOfResources("Maximum VCPU limit reached"));
         }
 
         let state = VcpuInterruptState::new(vcpu_id, vmid);
@@ -517,14 +518,14 @@ impl VirtualInterruptController {
     }
 
     /// Unregister a VCPU from the controller
-    pub fn unregister_vcpu(&mut self, vcpu_key: usize) -> Result<(), &'static str> {
+    pub fn unregister_vcpu(&mut self, vcpu_key: usize) -> Result<(), Error> {
         if self.vcpu_states.contains(vcpu_key) {
             let state = self.vcpu_states.remove(vcpu_key);
             log::debug!("Unregistered VCPU {} (VMID: {}) with VIC key: {}",
                        state.vcpu_id, state.vmid, vcpu_key);
             Ok(())
         } else {
-            Err("VCPU not found")
+            Err(Error::NotFound("VCPU not found"))
         }
     }
 
@@ -569,7 +570,7 @@ impl VirtualInterruptController {
                 already_pending: false,
                 immediate_delivery: false,
                 vcpus_affected: 0,
-                error: Some("VCPU not found"),
+                error: Some(Error::NotFound("VCPU not found")),
             }
         };
 
@@ -597,11 +598,11 @@ impl VirtualInterruptController {
     }
 
     /// Clear interrupt from specific VCPU
-    pub fn clear_interrupt(&mut self, vcpu_key: usize, interrupt_type: VirtualInterruptType) -> Result<(), &'static str> {
+    pub fn clear_interrupt(&mut self, vcpu_key: usize, interrupt_type: VirtualInterruptType) -> Result<(), Error> {
         if let Some(state) = self.vcpu_states.get_mut(vcpu_key) {
             state.clear_interrupt(interrupt_type)
         } else {
-            Err("VCPU not found")
+            Err(Error::NotFound("VCPU not found"))
         }
     }
 
@@ -620,6 +621,13 @@ impl VirtualInterruptController {
         self.vcpu_states.iter().find(|(_, state)| state.vcpu_id == vcpu_id).map(|(key, _)| key)
     }
 
+    /// Find a VM's VCPU by its ID within the VM
+    pub fn find_vm_vcpu(&self, vmid: VmId, vcpu_id: VcpuId) -> Option<usize> {
+        self.vcpu_states.iter()
+            .find(|(_, state)| state.vmid == vmid && state.vcpu_id == vcpu_id)
+            .map(|(key, _)| key)
+    }
+
     /// Find VCPUs by VM ID
     pub fn find_vcpus_by_vm(&self, vmid: VmId) -> Vec<usize> {
         self.vcpu_states.iter()
@@ -629,7 +637,7 @@ impl VirtualInterruptController {
     }
 
     /// Synchronize all VCPU states with hardware
-    pub fn sync_all_with_hardware(&mut self) -> Result<(), &'static str> {
+    pub fn sync_all_with_hardware(&mut self) -> Result<(), Error> {
         for state in self.vcpu_states.values_mut() {
             state.sync_with_hardware()?;
         }
@@ -654,7 +662,7 @@ impl VirtualInterruptController {
     }
 
     /// Update configuration
-    pub fn update_config(&mut self, config: VirtualIntcConfig) -> Result<(), &'static str> {
+    pub fn update_config(&mut self, config: VirtualIntcConfig) -> Result<(), Error> {
         self.config = config;
         Ok(())
     }
@@ -680,7 +688,7 @@ pub struct VirtualIntcStatsSnapshot {
 static mut VIRTUAL_INTC: Option<VirtualInterruptController> = None;
 
 /// Initialize global virtual interrupt controller
-pub fn init() -> Result<(), &'static str> {
+pub fn init() -> Result<(), Error> {
     log::info!("Initializing RISC-V Virtual Interrupt Controller");
 
     let config = VirtualIntcConfig::default();
@@ -718,7 +726,7 @@ pub fn inject_interrupt(vcpu_id: VcpuId, interrupt_type: VirtualInterruptType,
                 already_pending: false,
                 immediate_delivery: false,
                 vcpus_affected: 0,
-                error: Some("VCPU not found"),
+                error: Some(Error::NotFound("VCPU not found")),
             }
         }
     } else {
@@ -727,21 +735,21 @@ pub fn inject_interrupt(vcpu_id: VcpuId, interrupt_type: VirtualInterruptType,
             already_pending: false,
             immediate_delivery: false,
             vcpus_affected: 0,
-            error: Some("Virtual interrupt controller not initialized"),
+            error: Some(Error::NotInitialized("Virtual interrupt controller not initialized")),
         }
     }
 }
 
 /// Clear interrupt from VCPU by ID
-pub fn clear_interrupt(vcpu_id: VcpuId, interrupt_type: VirtualInterruptType) -> Result<(), &'static str> {
+pub fn clear_interrupt(vcpu_id: VcpuId, interrupt_type: VirtualInterruptType) -> Result<(), Error> {
     if let Some(controller) = get_controller_mut() {
         if let Some(vcpu_key) = controller.find_vcpu_by_id(vcpu_id) {
             controller.clear_interrupt(vcpu_key, interrupt_type)
         } else {
-            Err("VCPU not found")
+            Err(Error::NotFound("VCPU not found"))
         }
     } else {
-        Err("Virtual interrupt controller not initialized")
+        Err(Error::NotInitialized("Virtual interrupt controller not initialized"))
     }
 }
 
@@ -781,20 +789,20 @@ pub fn get_global_stats() -> Option<VirtualIntcStatsSnapshot> {
 }
 
 /// Register VCPU with virtual interrupt controller
-pub fn register_vcpu(vcpu_id: VcpuId, vmid: VmId) -> Result<usize, &'static str> {
+pub fn register_vcpu(vcpu_id: VcpuId, vmid: VmId) -> Result<usize, Error> {
     if let Some(controller) = get_controller_mut() {
         controller.register_vcpu(vcpu_id, vmid)
     } else {
-        Err("Virtual interrupt controller not initialized")
+        Err(Error::NotInitialized("Virtual interrupt controller not initialized"))
     }
 }
 
 /// Unregister VCPU from virtual interrupt controller
-pub fn unregister_vcpu(vcpu_key: usize) -> Result<(), &'static str> {
+pub fn unregister_vcpu(vcpu_key: usize) -> Result<(), Error> {
     if let Some(controller) = get_controller_mut() {
         controller.unregister_vcpu(vcpu_key)
     } else {
-        Err("Virtual interrupt controller not initialized")
+        Err(Error::NotInitialized("Virtual interrupt controller not initialized"))
     }
 }
 
@@ -19,6 +19,7 @@ use crate::arch::riscv64::virtualization::discovery::{VirtualDeviceDesc, DeviceR
 use crate::drivers::{DeviceId, DeviceType, DeviceStatus};
 use crate::core::mm::{PhysAddr, VirtAddr};
 use crate::core::sync::SpinLock;
+use crate::arch::riscv64::Error;
 use alloc::collections::BTreeMap;
 use alloc::string::{String, ToString};
 use alloc::sync::Arc;
@@ -34,28 +35,28 @@ pub trait VirtIODriver: Send + Sync {
     fn supported_device_types(&self) -> &[VirtIODeviceType];
 
     /// Probe a device - check if driver can handle it
-    fn probe(&self, device: &VirtIODevice) -> Result<(), &'static str>;
+    fn probe(&self, device: &VirtIODevice) -> Result<(), Error>;
 
     /// Remove a device from driver
-    fn remove(&self, device: &mut VirtIODevice) -> Result<(), &'static str>;
+    fn remove(&self, device: &mut VirtIODevice) -> Result<(), Error>;
 
     /// Suspend a device
-    fn suspend(&self, device: &mut VirtIODevice) -> Result<(), &'static str>;
+    fn suspend(&self, device: &mut VirtIODevice) -> Result<(), Error>;
 
     /// Resume a device
-    fn resume(&self, device: &mut VirtIODevice) -> Result<(), &'static str>;
+    fn resume(&self, device: &mut VirtIODevice) -> Result<(), Error>;
 
     /// Handle device-specific MMIO access
-    fn handle_mmio(&mut self, device: &mut VirtIODevice, gpa: usize, is_write: bool, value: u64) -> Result<u64, &'static str>;
+    fn handle_mmio(&mut self, device: &mut VirtIODevice, gpa: usize, is_write: bool, value: u64) -> Result<u64, Error>;
 
     /// Handle device interrupt
-    fn handle_interrupt(&mut self, device: &mut VirtIODevice) -> Result<(), &'static str>;
+    fn handle_interrupt(&mut self, device: &mut VirtIODevice) -> Result<(), Error>;
 
     /// Get driver-specific configuration
     fn get_driver_config(&self) -> Option<&[u8]>;
 
     /// Set driver-specific configuration
-    fn set_driver_config(&mut self, config: &[u8]) -> Result<(), &'static str>;
+    fn set_driver_config(&mut self, config: &[u8]) -> Result<(), Error>;
 
     /// Get driver statistics
     fn get_driver_stats(&self) -> &VirtIODriverStats;
@@ -193,44 +194,44 @@ impl VirtIONetDriver {
     }
 
     /// Set MAC address for device
-    pub fn set_mac_address(&mut self, device_id: DeviceId, mac: [u8; 6]) -> Result<(), &'static str> {
+    pub fn set_mac_address(&mut self, device_id: DeviceId, mac: [u8; 6]) -> Result<(), Error> {
         let device = self.devices.get_mut(&device_id)
-            .ok_or("Device not found")?;
+            .ok_or(Error::NotFound("Device not found"))?;
         device.mac_address = mac;
         Ok(())
     }
 
     /// Set link status
-    pub fn set_link_status(&mut self, device_id: DeviceId, up: bool) -> Result<(), &'static str> {
+    pub fn set_link_status(&mut self, device_id: DeviceId, up: bool) -> Result<(), Error> {
         let device = self.devices.get_mut(&device_id)
-            .ok_or("Device not found")?;
+            .ok_or(Error::NotFound("Device not found"))?;
         device.link_status = up;
         Ok(())
     }
 
     /// Enable/disable RX
-    pub fn set_rx_enabled(&mut self, device_id: DeviceId, enabled: bool) -> Result<(), &'static str> {
+    pub fn set_rx_enabled(&mut self, device_id: DeviceId, enabled: bool) -> Result<(), Error> {
         let device = self.devices.get_mut(&device_id)
-            .ok_or("Device not found")?;
+            .ok_or(Error::NotFound("Device not found"))?;
         device.rx_enabled = enabled;
         Ok(())
     }
 
     /// Enable/disable TX
-    pub fn set_tx_enabled(&mut self, device_id: DeviceId, enabled: bool) -> Result<(), &'static str> {
+    pub fn set_tx_enabled(&mut self, device_id: DeviceId, enabled: bool) -> Result<(), Error> {
         let device = self.devices.get_mut(&device_id)
-            .ok_or("Device not found")?;
+            .ok_or(Error::NotFound("Device not found"))?;
         device.tx_enabled = enabled;
         Ok(())
     }
 
     /// Receive packet (simulated)
-    pub fn receive_packet(&mut self, device_id: DeviceId, packet: &[u8]) -> Result<(), &'static str> {
+    pub fn receive_packet(&mut self, device_id: DeviceId, packet: &[u8]) -> Result<(), Error> {
         let device = self.devices.get_mut(&device_id)
-            .ok_or("Device not found")?;
+            .ok_or(Error::NotFound("Device not found"))?;
 
         if !device.link_status || !device.rx_enabled {
-            return Err("RX not enabled");
+            return Err(Error::InvalidState("RX not enabled"));
         }
 
         // Update statistics
@@ -244,12 +245,12 @@ impl VirtIONetDriver {
     }
 
     /// Transmit packet (simulated)
-    pub fn transmit_packet(&mut self, device_id: DeviceId, packet: &[u8]) -> Result<(), &'static str> {
+    pub fn transmit_packet(&mut self, device_id: DeviceId, packet: &[u8]) -> Result<(), Error> {
         let device = self.devices.get_mut(&device_id)
-            .ok_or("Device not found")?;
+            .ok_or(Error::NotFound("Device not found"))?;
 
         if !device.link_status || !device.tx_enabled {
-            return Err("TX not enabled");
+            return Err(Error::InvalidState("TX not enabled"));
         }
 
         // Update statistics
@@ -272,13 +273,13 @@ impl VirtIODriver for VirtIONetDriver {
         &[VirtIODeviceType::Network]
     }
 
-    fn probe(&self, device: &VirtIODevice) -> Result<(), &'static str> {
+    fn probe(&self, device: &VirtIODevice) -> Result<(), Error> {
         self.stats.probe_attempts.fetch_add(1, Ordering::Relaxed);
 
         // Check if this is a network device
         if device.config.device_type != VirtIODeviceType::Network {
             self.stats.failed_probes.fetch_add(1, Ordering::Relaxed);
-            return Err("Not a network device");
+            return Err(Error::InvalidArgument("Not a network device"));
         }
 
         // Check for required features
@@ -287,7 +288,7 @@ impl VirtIODriver for VirtIONetDriver {
 
         if (device.driver_features & required_features) != required_features {
             self.stats.failed_probes.fetch_add(1, Ordering::Relaxed);
-            return Err("Missing required features");
+            return Err(Error::Unsupported("Missing required features"));
         }
 
         self.stats.successful_probes.fetch_add(1, Ordering::Relaxed);
@@ -296,7 +297,7 @@ impl VirtIODriver for VirtIONetDriver {
         Ok(())
     }
 
-    fn remove(&self, device: &mut VirtIODevice) -> Result<(), &'static str> {
+    fn remove(&self, device: &mut VirtIODevice) -> Result<(), Error> {
         let _lock = device.lock.lock();
         device.reset();
 
@@ -307,21 +308,21 @@ impl VirtIODriver for VirtIONetDriver {
         Ok(())
     }
 
-    fn suspend(&self, device: &mut VirtIODevice) -> Result<(), &'static str> {
+    fn suspend(&self, device: &mut VirtIODevice) -> Result<(), Error> {
         let _lock = device.lock.lock();
         // Save device state
         log::info!("VirtIO network driver suspended device");
         Ok(())
     }
 
-    fn resume(&self, device: &mut VirtIODevice) -> Result<(), &'static str> {
+    fn resume(&self, device: &mut VirtIODevice) -> Result<(), Error> {
         let _lock = device.lock.lock();
         // Restore device state
         log::info!("VirtIO network driver resumed device");
         Ok(())
     }
 
-    fn handle_mmio(&mut self, device: &mut VirtIODevice, gpa: usize, is_write: bool, value: u64) -> Result<u64, &'static str> {
+    fn handle_mmio(&mut self, device: &mut VirtIODevice, gpa: usize, is_write: bool, value: u64) -> Result<u64, Error> {
         self.stats.mmio_operations.fetch_add(1, Ordering::Relaxed);
 
         let _lock = device.lock.lock();
@@ -351,7 +352,7 @@ impl VirtIODriver for VirtIONetDriver {
         Ok(result as u64)
     }
 
-    fn handle_interrupt(&mut self, device: &mut VirtIODevice) -> Result<(), &'static str> {
+    fn handle_interrupt(&mut self, device: &mut VirtIODevice) -> Result<(), Error> {
         self.stats.interrupts_handled.fetch_add(1, Ordering::Relaxed);
 
         let _lock = device.lock.lock();
@@ -371,7 +372,7 @@ impl VirtIODriver for VirtIONetDriver {
         Some(&[])
     }
 
-    fn set_driver_config(&mut self, config: &[u8]) -> Result<(), &'static str> {
+    fn set_driver_config(&mut self, config: &[u8]) -> Result<(), Error> {
         // Parse and set config
         log::info!("VirtIO network driver config updated");
         Ok(())
@@ -443,13 +444,13 @@ impl VirtIOBlockDriver {
     }
 
     /// Read block (simulated)
-    pub fn read_block(&mut self, device_id: DeviceId, lba: u64, blocks: u32) -> Result<Vec<u8>, &'static str> {
+    pub fn read_block(&mut self, device_id: DeviceId, lba: u64, blocks: u32) -> Result<Vec<u8>, Error> {
         let device = self.devices.get(&device_id)
-            .ok_or("Device not found")?;
+            .ok_or(Error::NotFound("Device not found"))?;
 
         // Check bounds
         if lba + blocks as u64 > device.capacity / self.config.block_size as u64 {
-            return Err("Read beyond capacity");
+            return Err(Error::OutOfResources("Read beyond capacity"));
         }
 
         let data_size = blocks as usize * self.config.block_size as usize;
@@ -465,19 +466,19 @@ impl VirtIOBlockDriver {
     }
 
     /// Write block (simulated)
-    pub fn write_block(&mut self, device_id: DeviceId, lba: u64, data: &[u8]) -> Result<(), &'static str> {
+    pub fn write_block(&mut self, device_id: DeviceId, lba: u64, data: &[u8]) -> Result<(), Error> {
         let device = self.devices.get(&device_id)
-            .ok_or("Device not found")?;
+            .ok_or(Error::NotFound("Device not found"))?;
 
         if device.read_only {
-            return Err("Device is read-only");
+            return Err(Error::PermissionDenied("Device is read-only"));
         }
 
         let blocks = (data.len() / self.config.block_size as usize) as u64;
 
         // Check bounds
         if lba + blocks > device.capacity / self.config.block_size as u64 {
-            return Err("Write beyond capacity");
+            return Err(Error::OutOfResources("Write beyond capacity"));
         }
 
         // Update statistics
@@ -490,12 +491,12 @@ impl VirtIOBlockDriver {
     }
 
     /// Flush device (simulated)
-    pub fn flush(&mut self, device_id: DeviceId) -> Result<(), &'static str> {
+    pub fn flush(&mut self, device_id: DeviceId) -> Result<(), Error> {
         let device = self.devices.get(&device_id)
-            .ok_or("Device not found")?;
+            .ok_or(Error::NotFound("Device not found"))?;
 
         if !device.flush_enabled {
-            return Err("Flush not supported");
+            return Err(Error::Unsupported("Flush not supported"));
         }
 
         // Simulate flush operation
@@ -514,13 +515,13 @@ impl VirtIODriver for VirtIOBlockDriver {
         &[VirtIODeviceType::Block]
     }
 
-    fn probe(&self, device: &VirtIODevice) -> Result<(), &'static str> {
+    fn probe(&self, device: &VirtIODevice) -> Result<(), Error> {
         self.stats.probe_attempts.fetch_add(1, Ordering::Relaxed);
 
         // Check if this is a block device
         if device.config.device_type != VirtIODeviceType::Block {
             self.stats.failed_probes.fetch_add(1, Ordering::Relaxed);
-            return Err("Not a block device");
+            return Err(Error::InvalidArgument("Not a block device"));
         }
 
         // Check for required features
@@ -528,7 +529,7 @@ impl VirtIODriver for VirtIOBlockDriver {
 
         if (device.driver_features & required_features) != required_features {
             self.stats.failed_probes.fetch_add(1, Ordering::Relaxed);
-            return Err("Missing required features");
+            return Err(Error::Unsupported("Missing required features"));
         }
 
         self.stats.successful_probes.fetch_add(1, Ordering::Relaxed);
@@ -537,7 +538,7 @@ impl VirtIODriver for VirtIOBlockDriver {
         Ok(())
     }
 
-    fn remove(&self, device: &mut VirtIODevice) -> Result<(), &'static str> {
+    fn remove(&self, device: &mut VirtIODevice) -> Result<(), Error> {
         let _lock = device.lock.lock();
         device.reset();
         self.stats.devices_managed.fetch_sub(1, Ordering::Relaxed);
@@ -545,19 +546,19 @@ impl VirtIODriver for VirtIOBlockDriver {
         Ok(())
     }
 
-    fn suspend(&self, device: &mut VirtIODevice) -> Result<(), &'static str> {
+    fn suspend(&self, device: &mut VirtIODevice) -> Result<(), Error> {
         let _lock = device.lock.lock();
         log::info!("VirtIO block driver suspended device");
         Ok(())
     }
 
-    fn resume(&self, device: &mut VirtIODevice) -> Result<(), &'static str> {
+    fn resume(&self, device: &mut VirtIODevice) -> Result<(), Error> {
         let _lock = device.lock.lock();
         log::info!("VirtIO block driver resumed device");
         Ok(())
     }
 
-    fn handle_mmio(&mut self, device: &mut VirtIODevice, gpa: usize, is_write: bool, value: u64) -> Result<u64, &'static str> {
+    fn handle_mmio(&mut self, device: &mut VirtIODevice, gpa: usize, is_write: bool, value: u64) -> Result<u64, Error> {
         self.stats.mmio_operations.fetch_add(1, Ordering::Relaxed);
 
         let _lock = device.lock.lock();
@@ -587,7 +588,7 @@ impl VirtIODriver for VirtIOBlockDriver {
         Ok(result as u64)
     }
 
-    fn handle_interrupt(&mut self, device: &mut VirtIODevice) -> Result<(), &'static str> {
+    fn handle_interrupt(&mut self, device: &mut VirtIODevice) -> Result<(), Error> {
         self.stats.interrupts_handled.fetch_add(1, Ordering::Relaxed);
 
         let _lock = device.lock.lock();
@@ -606,7 +607,7 @@ impl VirtIODriver for VirtIOBlockDriver {
         Some(&[])
     }
 
-    fn set_driver_config(&mut self, config: &[u8]) -> Result<(), &'static str> {
+    fn set_driver_config(&mut self, config: &[u8]) -> Result<(), Error> {
         log::info!("VirtIO block driver config updated");
         Ok(())
     }
@@ -638,7 +639,7 @@ impl VirtIODriverRegistry {
     }
 
     /// Register a driver
-    pub fn register_driver(&mut self, driver: Arc<dyn VirtIODriver>) -> Result<(), &'static str> {
+    pub fn register_driver(&mut self, driver: Arc<dyn VirtIODriver>) -> Result<(), Error> {
         for device_type in driver.supported_device_types() {
             self.drivers.insert(*device_type, driver.clone());
         }
@@ -647,7 +648,7 @@ impl VirtIODriverRegistry {
     }
 
     /// Unregister a driver
-    pub fn unregister_driver(&mut self, driver_name: &str) -> Result<(), &'static str> {
+    pub fn unregister_driver(&mut self, driver_name: &str) -> Result<(), Error> {
         // Remove from drivers map
         self.drivers.retain(|_, driver| driver.driver_name() != driver_name);
 
@@ -659,11 +660,11 @@ impl VirtIODriverRegistry {
     }
 
     /// Probe and bind a device
-    pub fn probe_device(&mut self, device: &mut VirtIODevice) -> Result<Arc<dyn VirtIODriver>, &'static str> {
+    pub fn probe_device(&mut self, device: &mut VirtIODevice) -> Result<Arc<dyn VirtIODriver>, Error> {
         let device_type = device.config.device_type;
 
         let driver = self.drivers.get(&device_type)
-            .ok_or("No driver found for device type")?;
+            .ok_or(Error::NotFound("No driver found for device type"))?;
 
         // Probe the device
         driver.probe(device)?;
@@ -681,9 +682,9 @@ impl VirtIODriverRegistry {
     }
 
     /// Remove device binding
-    pub fn remove_device(&mut self, device: &mut VirtIODevice) -> Result<(), &'static str> {
+    pub fn remove_device(&mut self, device: &mut VirtIODevice) -> Result<(), Error> {
         let driver = self.device_bindings.remove(&device.config.device_id)
-            .ok_or("Device not bound")?;
+            .ok_or(Error::NotFound("Device not bound"))?;
 
         driver.remove(device)?;
 
@@ -17,6 +17,7 @@ use crate::arch::riscv64::virtualization::discovery_manager::{DeviceDiscoveryMan
 use crate::drivers::{DeviceId, DeviceType, DeviceStatus};
 use crate::core::mm::{PhysAddr, VirtAddr};
 use crate::core::sync::SpinLock;
+use crate::arch::riscv64::Error;
 use alloc::collections::BTreeMap;
 use alloc::string::{String, ToString};
 use alloc::sync::Arc;
@@ -348,9 +349,9 @@ impl VirtQueue {
     }
 
     /// Initialize queue with size
-    pub fn init(&mut self, size: u16) -> Result<(), &'static str> {
+    pub fn init(&mut self, size: u16) -> Result<(), Error> {
         if size > self.max_size || size == 0 {
-            return Err("Invalid queue size");
+            return Err(Error::InvalidArgument("Invalid queue size"));
         }
 
         self.size = size;
@@ -383,9 +384,9 @@ impl VirtQueue {
     }
 
     /// Add buffer to available ring
-    pub fn add_buf(&mut self, desc: u16, len: u32, write: bool, next_desc: Option<u16>) -> Result<(), &'static str> {
+    pub fn add_buf(&mut self, desc: u16, len: u32, write: bool, next_desc: Option<u16>) -> Result<(), Error> {
         if desc >= self.size as u16 {
-            return Err("Invalid descriptor");
+            return Err(Error::InvalidArgument("Invalid descriptor"));
         }
 
         let descriptor = &mut self.descriptors[desc as usize];
@@ -603,7 +604,7 @@ impl VirtIODevice {
     }
 
     /// Read MMIO register
-    pub fn read_register(&self, offset: usize) -> Result<u32, &'static str> {
+    pub fn read_register(&self, offset: usize) -> Result<u32, Error> {
         match offset {
             registers::MAGIC_VALUE => Ok(self.registers.magic_value),
             registers::VERSION => Ok(self.registers.version),
@@ -657,7 +658,7 @@ impl VirtIODevice {
     }
 
     /// Write MMIO register
-    pub fn write_register(&mut self, offset: usize, value: u32) -> Result<(), &'static str> {
+    pub fn write_register(&mut self, offset: usize, value: u32) -> Result<(), Error> {
         match offset {
             registers::DEVICE_FEATURES_SEL => {
                 self.registers.device_features_sel = value;
@@ -681,7 +682,7 @@ impl VirtIODevice {
                         self.queues[queue_index].size = value as u16;
                         self.registers.queue_num = value;
                     } else {
-                        return Err("Queue size too large");
+                        return Err(Error::InvalidArgument("Queue size too large"));
                     }
                 }
             }
@@ -721,7 +722,7 @@ impl VirtIODevice {
     }
 
     /// Handle device status change
-    fn handle_status_change(&mut self, status: u32) -> Result<(), &'static str> {
+    fn handle_status_change(&mut self, status: u32) -> Result<(), Error> {
         if status & status_flags::FAILED != 0 {
             log::warn!("VirtIO device {:?} reported failure", self.config.device_type);
             self.status = DeviceStatus::Error;
@@ -743,10 +744,10 @@ impl VirtIODevice {
     }
 
     /// Handle queue notification
-    fn handle_queue_notification(&mut self, queue_index: u32) -> Result<(), &'static str> {
+    fn handle_queue_notification(&mut self, queue_index: u32) -> Result<(), Error> {
         let queue_index = queue_index as usize;
         if queue_index >= self.queues.len() {
-            return Err("Invalid queue index");
+            return Err(Error::InvalidArgument("Invalid queue index"));
         }
 
         log::debug!("VirtIO queue {} notification received", queue_index);
@@ -761,7 +762,7 @@ impl VirtIODevice {
     }
 
     /// Process queue buffers
-    fn process_queue_buffers(&mut self, queue_index: usize) -> Result<(), &'static str> {
+    fn process_queue_buffers(&mut self, queue_index: usize) -> Result<(), Error> {
         // This would be implemented by specific device types
         // For now, just simulate processing
         log::debug!("Processing buffers for queue {}", queue_index);
@@ -771,10 +772,10 @@ impl VirtIODevice {
     }
 
     /// Get queue address
-    fn get_queue_address(&self, offset: usize) -> Result<u32, &'static str> {
+    fn get_queue_address(&self, offset: usize) -> Result<u32, Error> {
         let queue_index = self.registers.queue_sel as usize;
         if queue_index >= self.queues.len() {
-            return Err("Invalid queue index");
+            return Err(Error::InvalidArgument("Invalid queue index"));
         }
 
         let queue = &self.queues[queue_index];
@@ -783,7 +784,7 @@ impl VirtIODevice {
             match offset {
                 registers::QUEUE_DESC_LOW => Ok((addr & 0xFFFFFFFF) as u32),
                 registers::QUEUE_DESC_HIGH => Ok((addr >> 32) as u32),
-                _ => Err("Invalid queue address register"),
+                _ => Err(Error::InvalidArgument("Invalid queue address register")),
             }
         } else {
             Ok(0)
@@ -791,22 +792,22 @@ impl VirtIODevice {
     }
 
     /// Get queue driver address
-    fn get_queue_driver_address(&self, offset: usize) -> Result<u32, &'static str> {
+    fn get_queue_driver_address(&self, offset: usize) -> Result<u32, Error> {
         // Simplified implementation
         Ok(0)
     }
 
     /// Get queue device address
-    fn get_queue_device_address(&self, offset: usize) -> Result<u32, &'static str> {
+    fn get_queue_device_address(&self, offset: usize) -> Result<u32, Error> {
         // Simplified implementation
         Ok(0)
     }
 
     /// Set queue address
-    fn set_queue_address(&mut self, offset: usize, value: u32) -> Result<(), &'static str> {
+    fn set_queue_address(&mut self, offset: usize, value: u32) -> Result<(), Error> {
         let queue_index = self.registers.queue_sel as usize;
         if queue_index >= self.queues.len() {
-            return Err("Invalid queue index");
+            return Err(Error::InvalidArgument("Invalid queue index"));
         }
 
         // Simplified implementation - would need proper address handling
@@ -816,21 +817,21 @@ impl VirtIODevice {
     }
 
     /// Set queue driver address
-    fn set_queue_driver_address(&mut self, offset: usize, value: u32) -> Result<(), &'static str> {
+    fn set_queue_driver_address(&mut self, offset: usize, value: u32) -> Result<(), Error> {
         // Simplified implementation
         log::debug!("Set queue driver address: {:#x}", value);
         Ok(())
     }
 
     /// Set queue device address
-    fn set_queue_device_address(&mut self, offset: usize, value: u32) -> Result<(), &'static str> {
+    fn set_queue_device_address(&mut self, offset: usize, value: u32) -> Result<(), Error> {
         // Simplified implementation
         log::debug!("Set queue device address: {:#x}", value);
         Ok(())
     }
 
     /// Read from config space
-    fn read_config(&self, offset: usize) -> Result<u32, &'static str> {
+    fn read_config(&self, offset: usize) -> Result<u32, Error> {
         let config_offset = offset - registers::CONFIG;
         if config_offset >= self.config.config_data.len() {
             return Ok(0);
@@ -847,10 +848,10 @@ impl VirtIODevice {
     }
 
     /// Write to config space
-    fn write_config(&mut self, offset: usize, value: u32) -> Result<(), &'static str> {
+    fn write_config(&mut self, offset: usize, value: u32) -> Result<(), Error> {
         let config_offset = offset - registers::CONFIG;
         if config_offset >= self.config.config_data.len() {
-            return Err("Config offset out of range");
+            return Err(Error::InvalidArgument("Config offset out of range"));
         }
 
         let data = value.to_le_bytes();
@@ -866,7 +867,7 @@ impl VirtIODevice {
     }
 
     /// Interrupt the VM
-    pub fn interrupt_vm(&self) -> Result<(), &'static str> {
+    pub fn interrupt_vm(&self) -> Result<(), Error> {
         // This would trigger an interrupt to the VM
         log::debug!("Interrupting VM for VirtIO device {:?}", self.config.device_type);
         self.stats.interrupts.fetch_add(1, Ordering::Relaxed);
@@ -892,7 +893,7 @@ pub struct VirtIODeviceFactory;
 
 impl VirtIODeviceFactory {
     /// Create VirtIO device from description
-    pub fn create_device(desc: &VirtualDeviceDesc) -> Result<VirtIODevice, &'static str> {
+    pub fn create_device(desc: &VirtualDeviceDesc) -> Result<VirtIODevice, Error> {
         // Determine device type
         let device_type = match desc.device_type {
             DeviceType::Network => VirtIODeviceType::Network,
@@ -901,7 +902,7 @@ impl VirtIODeviceFactory {
             DeviceType::Rng => VirtIODeviceType::Rng,
             DeviceType::Graphics => VirtIODeviceType::GPU,
             DeviceType::Input => VirtIODeviceType::Input,
-            _ => return Err("Unsupported VirtIO device type"),
+            _ => return Err(Error::Unsupported("Unsupported VirtIO device type")),
         };
 
         // Create device configuration
@@ -915,9 +916,9 @@ impl VirtIODeviceFactory {
             config_data: Self::create_config_data(device_type),
             config_generation: 0,
             mmio_base: desc.resources.memory_regions.first()
-                .ok_or("No MMIO region found")?
+                .ok_or(Error::NotFound("No MMIO region found"))?
                 .base
-                .ok_or("MMIO region not mapped")?,
+                .ok_or(Error::NotFound("MMIO region not mapped"))?,
             mmio_size: desc.resources.memory_regions.first()
                 .map(|r| r.size)
                 .unwrap_or(0x1000),
@@ -20,6 +20,7 @@ use crate::arch::riscv64::virtualization::discovery_manager::DeviceDiscoveryMana
 use crate::drivers::{DeviceId, DeviceType, DeviceStatus};
 use crate::core::mm::{PhysAddr, VirtAddr};
 use crate::core::sync::SpinLock;
+use crate::arch::riscv64::Error;
 use alloc::collections::BTreeMap;
 use alloc::string::{String, ToString};
 use alloc::sync::Arc;
@@ -165,10 +166,10 @@ impl VirtIOManager {
     }
 
     /// Create VirtIO device from request
-    pub fn create_device(&mut self, request: VirtIODeviceRequest) -> Result<DeviceId, &'static str> {
+    pub fn create_device(&mut self, request: VirtIODeviceRequest) -> Result<DeviceId, Error> {
         // Check device limit
         if self.devices.len() >= 64 { // Configurable limit
-            return Err("Maximum number of devices reached");
+            return Err(Error::OutOfResources("Maximum number of devices reached"));
         }
 
         // Generate device ID
@@ -213,7 +214,7 @@ impl VirtIOManager {
     }
 
     /// Create device description from request
-    fn create_device_description(&self, request: &VirtIODeviceRequest, device_id: DeviceId) -> Result<VirtualDeviceDesc, &'static str> {
+    fn create_device_description(&self, request: &VirtIODeviceRequest, device_id: DeviceId) -> Result<VirtualDeviceDesc, Error> {
         // Determine device type
         let device_type = match request.device_type {
             VirtIODeviceType::Network => DeviceType::Network,
@@ -284,9 +285,9 @@ impl VirtIOManager {
     }
 
     /// Destroy VirtIO device
-    pub fn destroy_device(&mut self, device_id: DeviceId) -> Result<(), &'static str> {
+    pub fn destroy_device(&mut self, device_id: DeviceId) -> Result<(), Error> {
         let device = self.devices.remove(&device_id)
-            .ok_or("Device not found")?;
+            .ok_or(Error::NotFound("Device not found"))?;
 
         let mut device_guard = device.lock();
 
@@ -320,12 +321,12 @@ impl VirtIOManager {
     }
 
     /// Handle MMIO access for VirtIO device
-    pub fn handle_mmio(&mut self, device_id: DeviceId, gpa: usize, is_write: bool, value: u64) -> Result<u64, &'static str> {
+    pub fn handle_mmio(&mut self, device_id: DeviceId, gpa: usize, is_write: bool, value: u64) -> Result<u64, Error> {
         let device = self.devices.get(&device_id)
-            .ok_or("Device not found")?;
+            .ok_or(Error::NotFound("Device not found"))?;
 
         let driver = self.driver_registry.get_driver_for_device(device_id)
-            .ok_or("No driver bound to device")?;
+            .ok_or(Error::NotFound("No driver bound to device"))?;
 
         // Update statistics
         self.stats.total_mmio_operations.fetch_add(1, Ordering::Relaxed);
@@ -340,7 +341,7 @@ impl VirtIOManager {
             Err(driver_arc) => {
                 // Can't unwrap, need to use Arc references
                 // This would require changing the trait to accept &Arc<dyn VirtIODriver>
-                Err("Driver sharing not implemented")
+                Err(Error::Unsupported("Driver sharing not implemented"))
             }
         };
 
@@ -348,12 +349,12 @@ impl VirtIOManager {
     }
 
     /// Handle interrupt for VirtIO device
-    pub fn handle_interrupt(&mut self, device_id: DeviceId) -> Result<(), &'static str> {
+    pub fn handle_interrupt(&mut self, device_id: DeviceId) -> Result<(), Error> {
         let device = self.devices.get(&device_id)
-            .ok_or("Device not found")?;
+            .ok_or(Error::NotFound("Device not found"))?;
 
         let driver = self.driver_registry.get_driver_for_device(device_id)
-            .ok_or("No driver bound to device")?;
+            .ok_or(Error::NotFound("No driver bound to device"))?;
 
         // Update statistics
         self.stats.total_interrupts_handled.fetch_add(1, Ordering::Relaxed);
@@ -429,21 +430,21 @@ impl VirtIOManager {
     }
 
     /// Perform hotplug addition
-    pub fn hotplug_add_device(&mut self, request: VirtIODeviceRequest) -> Result<DeviceId, &'static str> {
+    pub fn hotplug_add_device(&mut self, request: VirtIODeviceRequest) -> Result<DeviceId, Error> {
         log::info!("Hotplug adding VirtIO {:?}", request.device_type);
         self.create_device(request)
     }
 
     /// Perform hotplug removal
-    pub fn hotplug_remove_device(&mut self, device_id: DeviceId) -> Result<(), &'static str> {
+    pub fn hotplug_remove_device(&mut self, device_id: DeviceId) -> Result<(), Error> {
         log::info!("Hotplug removing VirtIO device {:?}", device_id);
         self.destroy_device(device_id)
     }
 
     /// Export device state
-    pub fn export_device_state(&self, device_id: DeviceId) -> Result<Vec<u8>, &'static str> {
+    pub fn export_device_state(&self, device_id: DeviceId) -> Result<Vec<u8>, Error> {
         let device = self.devices.get(&device_id)
-            .ok_or("Device not found")?;
+            .ok_or(Error::NotFound("Device not found"))?;
 
         let device_guard = device.lock();
 
@@ -463,14 +464,14 @@ impl VirtIOManager {
     }
 
     /// Import device state
-    pub fn import_device_state(&mut self, device_id: DeviceId, data: &[u8]) -> Result<(), &'static str> {
+    pub fn import_device_state(&mut self, device_id: DeviceId, data: &[u8]) -> Result<(), Error> {
         let device = self.devices.get_mut(&device_id)
-            .ok_or("Device not found")?;
+            .ok_or(Error::NotFound("Device not found"))?;
 
         let device_guard = device.lock();
 
         // Parse and restore state (simplified)
-        let data_str = core::str::from_utf8(data).map_err(|_| "Invalid state data")?;
+        let data_str = core::str::from_utf8(data).map_err(|_| Error::InvalidArgument("Invalid state data"))?;
         log::info!("Importing state for device {:?}: {}", device_id, data_str);
 
         // Restore state fields (implementation would be more detailed)
@@ -515,10 +516,10 @@ struct VirtIODeviceState {
 /// Extension trait for device discovery manager
 trait DeviceDiscoveryManagerExt {
     /// Add device to discovery manager
-    fn add_device(&mut self, device_desc: VirtualDeviceDesc) -> Result<DeviceId, &'static str>;
+    fn add_device(&mut self, device_desc: VirtualDeviceDesc) -> Result<DeviceId, Error>;
 
     /// Remove device from discovery manager
-    fn remove_device(&mut self, device_id: DeviceId) -> Result<(), &'static str>;
+    fn remove_device(&mut self, device_id: DeviceId) -> Result<(), Error>;
 }
 
 impl Default for VirtIOManager {
@@ -549,7 +550,7 @@ impl core::fmt::Display for VirtIODeviceInfo {
 static mut VIRTIO_MANAGER: Option<VirtIOManager> = None;
 
 /// Initialize global VirtIO manager
-pub fn init_virtio_manager(config: VirtIOManagerConfig) -> Result<(), &'static str> {
+pub fn init_virtio_manager(config: VirtIOManagerConfig) -> Result<(), Error> {
     let manager = VirtIOManager::new(config);
 
     unsafe {
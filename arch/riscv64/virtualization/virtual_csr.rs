@@ -7,6 +7,7 @@
 //! - Memory-safe CSR state management
 
 use crate::arch::riscv64::cpu::csr::*;
+use crate::arch::riscv64::Error;
 use bitflags::bitflags;
 use core::mem;
 use core::sync::atomic::{AtomicUsize, Ordering};
@@ -93,7 +94,7 @@ impl VirtualCsrState {
     }
 
     /// Save current VS* CSR state from hardware
-    pub fn save_from_hw(vmid: u16) -> Result<Self, &'static str> {
+    pub fn save_from_hw(vmid: u16) -> Result<Self, Error> {
         let state = Self {
             vsstatus: VSSTATUS::read(),
             vstvec: VSTVEC::read(),
@@ -113,7 +114,7 @@ impl VirtualCsrState {
     }
 
     /// Restore VS* CSR state to hardware
-    pub fn restore_to_hw(&self) -> Result<(), &'static str> {
+    pub fn restore_to_hw(&self) -> Result<(), Error> {
         // Validate state before restore
         self.validate()?;
 
@@ -135,7 +136,7 @@ impl VirtualCsrState {
     }
 
     /// Load CSR state from another VirtualCsrState
-    pub fn load_from_state(&mut self, other: &VirtualCsrState) -> Result<(), &'static str> {
+    pub fn load_from_state(&mut self, other: &VirtualCsrState) -> Result<(), Error> {
         other.validate()?;
 
         // Copy all fields
@@ -159,27 +160,27 @@ impl VirtualCsrState {
     }
 
     /// Validate CSR state
-    pub fn validate(&self) -> Result<(), &'static str> {
+    pub fn validate(&self) -> Result<(), Error> {
         // Check VSSTATUS validity
         if !self.validity.contains(CsrValidityFlags::VSSTATUS_VALID) {
-            return Err("VSSTATUS is not valid");
+            return Err(Error::InvalidState("VSSTATUS is not valid"));
         }
 
         // Check VSTVEC alignment (must be 4-byte aligned)
         if self.vstvec & 0x3 != 0 {
-            return Err("VSTVEC is not properly aligned");
+            return Err(Error::InvalidArgument("VSTVEC is not properly aligned"));
         }
 
         // Check VSATP validity if enabled
         if (self.vsstatus.contains(VsstatusFlags::TVM)) && self.vsatp != 0 {
             // VSATP should not be enabled if TVM is set
-            return Err("VSATP invalid with TVM set");
+            return Err(Error::InvalidArgument("VSATP invalid with TVM set"));
         }
 
         // Check privilege level consistency
         let spp = if self.vsstatus.contains(VsstatusFlags::SPP) { 1 } else { 0 };
         if spp > 1 {
-            return Err("Invalid SPP value in VSSTATUS");
+            return Err(Error::InvalidArgument("Invalid SPP value in VSSTATUS"));
         }
 
         Ok(())
@@ -362,21 +363,21 @@ impl CsrStateManager {
     }
 
     /// Save current hardware state
-    pub fn save_hw_state(vmid: u16) -> Result<VirtualCsrState, &'static str> {
+    pub fn save_hw_state(vmid: u16) -> Result<VirtualCsrState, Error> {
         let state = VirtualCsrState::save_from_hw(vmid)?;
         Self::get_stats().saves.fetch_add(1, Ordering::Relaxed);
         Ok(state)
     }
 
     /// Restore state to hardware
-    pub fn restore_hw_state(state: &VirtualCsrState) -> Result<(), &'static str> {
+    pub fn restore_hw_state(state: &VirtualCsrState) -> Result<(), Error> {
         state.restore_to_hw()
     }
 
     /// Validate state freshness
-    pub fn validate_state_freshness(state: &VirtualCsrState) -> Result<(), &'static str> {
+    pub fn validate_state_freshness(state: &VirtualCsrState) -> Result<(), Error> {
         if state.is_stale(MAX_STATE_AGE) {
-            return Err("CSR state is stale");
+            return Err(Error::InvalidState("CSR state is stale"));
         }
         Ok(())
     }
@@ -436,7 +437,7 @@ const MAX_STATE_AGE: u64 = 1000;
 static mut CSR_STATE_MANAGER: Option<CsrStateManager> = None;
 
 /// Initialize CSR state manager
-pub fn init() -> Result<(), &'static str> {
+pub fn init() -> Result<(), Error> {
     log::info!("Initializing virtual CSR state manager");
 
     let manager = CsrStateManager::new(MAX_STATE_AGE);
@@ -455,7 +456,7 @@ pub fn get_manager() -> Option<&'static CsrStateManager> {
 }
 
 /// Save current hardware CSR state
-pub fn save_state(vmid: u16) -> Result<VirtualCsrState, &'static str> {
+pub fn save_state(vmid: u16) -> Result<VirtualCsrState, Error> {
     if let Some(manager) = get_manager() {
         manager.save_hw_state(vmid)
     } else {
@@ -464,7 +465,7 @@ pub fn save_state(vmid: u16) -> Result<VirtualCsrState, &'static str> {
 }
 
 /// Restore CSR state to hardware
-pub fn restore_state(state: &VirtualCsrState) -> Result<(), &'static str> {
+pub fn restore_state(state: &VirtualCsrState) -> Result<(), Error> {
     if let Some(manager) = get_manager() {
         manager.restore_hw_state(state)
     } else {
@@ -473,7 +474,7 @@ pub fn restore_state(state: &VirtualCsrState) -> Result<(), &'static str> {
 }
 
 /// Perform optimized state switch
-pub fn switch_state(from: &VirtualCsrState, to: &VirtualCsrState) -> Result<(), &'static str> {
+pub fn switch_state(from: &VirtualCsrState, to: &VirtualCsrState) -> Result<(), Error> {
     if let Some(manager) = get_manager() {
         let updates = manager.optimize_state_switch(from, to);
 
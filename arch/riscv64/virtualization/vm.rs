@@ -10,7 +10,14 @@ use crate::arch::riscv64::*;
 use crate::arch::riscv64::mmu::*;
 use crate::arch::riscv64::virtualization::vcpu::*;
 use crate::arch::riscv64::virtualization::hextension::*;
+use crate::arch::riscv64::virtualization::hypercall::{
+    HypercallArgs, HypercallError, HypercallHandler, HypercallId, HypercallRegistry, HypercallResult,
+};
+use crate::arch::riscv64::virtualization::pvclock::{PvClock, PvclockTimeInfo, PVCLOCK_INFO_SIZE};
+use crate::arch::riscv64::virtualization::vm_log::{VmLog, VmLogEntry};
+use crate::arch::riscv64::virtualization::devices::uart::VirtualUart;
 use bitflags::bitflags;
+use core::ops::Range;
 
 /// VM state
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -50,6 +57,48 @@ bitflags! {
     }
 }
 
+/// Guest exception raised by `VirtualMachine::inject_fault`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultKind {
+    /// Instruction page fault
+    InstructionPageFault,
+    /// Load page fault
+    LoadPageFault,
+    /// Store/AMO page fault
+    StorePageFault,
+    /// Illegal instruction
+    IllegalInstruction,
+}
+
+impl FaultKind {
+    /// Exception code the guest sees in VSCAUSE
+    pub fn exception_code(self) -> ExceptionCode {
+        match self {
+            FaultKind::InstructionPageFault => ExceptionCode::InstructionPageFault,
+            FaultKind::LoadPageFault => ExceptionCode::LoadPageFault,
+            FaultKind::StorePageFault => ExceptionCode::StorePageFault,
+            FaultKind::IllegalInstruction => ExceptionCode::IllegalInstruction,
+        }
+    }
+}
+
+/// Handler for guest accesses to a registered MMIO range
+///
+/// Called with the same arguments as `VirtualDevice::handle_mmio` and
+/// returns the same result; a closure can carry the device's state.
+pub type MmioHandler = Box<dyn FnMut(usize, bool, u64) -> Result<u64, Error>>;
+
+/// MMIO handler registered with `VirtualMachine::register_mmio_handler`
+struct MmioHandlerEntry {
+    /// Guest physical addresses handled
+    range: Range<usize>,
+    /// The handler
+    handler: MmioHandler,
+}
+
+/// Guest physical address of the emulated console UART
+pub const GUEST_UART_BASE: usize = 0x1000_0000;
+
 /// Virtual Machine
 pub struct VirtualMachine {
     /// VM ID (unique across the system)
@@ -72,6 +121,12 @@ pub struct VirtualMachine {
     pub devices: Vec<Box<dyn VirtualDevice>>,
     /// VM configuration
     pub config: VmConfig,
+    /// Diagnostic log read by the host
+    log: VmLog,
+    /// MMIO handlers consulted before the virtual devices
+    mmio_handlers: Vec<MmioHandlerEntry>,
+    /// Hypercalls handled for this VM only, consulted before the built-in ones
+    hypercalls: HypercallRegistry,
 }
 
 /// VM configuration
@@ -125,18 +180,18 @@ impl GuestPhysicalMemory {
     }
 
     /// Add a memory region
-    pub fn add_region(&mut self, region: MemRegion) -> Result<(), &'static str> {
+    pub fn add_region(&mut self, region: MemRegion) -> Result<(), Error> {
         // Check if region overlaps with existing regions
         for existing in &self.regions {
             if (region.va_start < existing.va_end() && region.va_end() > existing.va_start) ||
                (region.pa_start < existing.pa_end() && region.pa_end() > existing.pa_start) {
-                return Err("Memory region overlaps with existing region");
+                return Err(Error::Busy("Memory region overlaps with existing region"));
             }
         }
 
         // Check if region is within bounds
         if region.va_start < self.base_pa || region.va_end() > self.base_pa + self.size {
-            return Err("Memory region out of bounds");
+            return Err(Error::InvalidArgument("Memory region out of bounds"));
         }
 
         self.regions.push(region);
@@ -173,16 +228,24 @@ pub trait VirtualDevice {
     fn device_name(&self) -> &str;
 
     /// Initialize the device
-    fn init(&mut self, vm: &mut VirtualMachine) -> Result<(), &'static str>;
+    fn init(&mut self, vm: &mut VirtualMachine) -> Result<(), Error>;
 
     /// Handle MMIO access
-    fn handle_mmio(&mut self, gpa: usize, is_write: bool, value: u64) -> Result<u64, &'static str>;
+    fn handle_mmio(&mut self, gpa: usize, is_write: bool, value: u64) -> Result<u64, Error>;
 
     /// Handle interrupt (if device generates interrupts)
-    fn handle_interrupt(&mut self) -> Result<(), &'static str>;
+    fn handle_interrupt(&mut self) -> Result<(), Error>;
 
     /// Get device configuration
     fn get_config(&self) -> &VmDeviceConfig;
+
+    /// Take console output produced since the last call
+    ///
+    /// Console devices return what the guest wrote to them, which is
+    /// copied into the VM's diagnostic log.
+    fn take_console_output(&mut self) -> Option<Vec<u8>> {
+        None
+    }
 }
 
 /// VM device configuration
@@ -202,7 +265,7 @@ pub struct VmDeviceConfig {
 
 impl VirtualMachine {
     /// Create a new virtual machine
-    pub fn new(id: u16, name: String, config: VmConfig, flags: VmFlags) -> Result<Self, &'static str> {
+    pub fn new(id: u16, name: String, config: VmConfig, flags: VmFlags) -> Result<Self, Error> {
         log::info!("Creating VM {}: {} ({} VCPUs, {}MB memory)",
                   id, name, config.num_vcpus, config.memory_size / (1024 * 1024));
 
@@ -229,6 +292,9 @@ impl VirtualMachine {
             vcpu_manager,
             devices: Vec::new(),
             config,
+            log: VmLog::new(),
+            mmio_handlers: Vec::new(),
+            hypercalls: HypercallRegistry::new(),
         };
 
         log::info!("VM {} created with VMID {}", id, vmid);
@@ -236,7 +302,7 @@ impl VirtualMachine {
     }
 
     /// Initialize the VM
-    pub fn init(&mut self) -> Result<(), &'static str> {
+    pub fn init(&mut self) -> Result<(), Error> {
         log::info!("Initializing VM {}", self.id);
 
         // Map guest physical memory
@@ -259,7 +325,7 @@ impl VirtualMachine {
     }
 
     /// Map guest physical memory to host physical memory
-    fn map_guest_memory(&mut self) -> Result<(), &'static str> {
+    fn map_guest_memory(&mut self) -> Result<(), Error> {
         log::debug!("Mapping guest physical memory for VM {}", self.id);
 
         // Add guest memory region
@@ -274,23 +340,15 @@ impl VirtualMachine {
         self.guest_memory.add_region(memory_region)?;
         self.stage2_ptable.map_region(memory_region)?;
 
-        // Add standard MMIO regions
-        let uart_region = MemRegion::new(
-            0x10000000,                    // UART base GPA
-            0x10000000,                    // UART base HPA
-            0x1000,                        // UART size
-            MemFlags::READABLE | MemFlags::WRITABLE | MemFlags::DEVICE,
-            "uart",
-        );
-
-        self.guest_memory.add_region(uart_region)?;
-        self.stage2_ptable.map_region(uart_region)?;
+        // The console UART is left unmapped so guest accesses trap to
+        // the emulated one, whose output goes to the diagnostic log
+        self.add_device(Box::new(VirtualUart::new(GUEST_UART_BASE)));
 
         Ok(())
     }
 
     /// Create VCPUs for the VM
-    fn create_vcpus(&mut self) -> Result<(), &'static str> {
+    fn create_vcpus(&mut self) -> Result<(), Error> {
         log::debug!("Creating {} VCPUs for VM {}", self.config.num_vcpus, self.id);
 
         for i in 0..self.config.num_vcpus {
@@ -316,7 +374,7 @@ impl VirtualMachine {
     }
 
     /// Initialize virtual devices
-    fn init_devices(&mut self) -> Result<(), &'static str> {
+    fn init_devices(&mut self) -> Result<(), Error> {
         log::debug!("Initializing devices for VM {}", self.id);
 
         // Initialize each device
@@ -329,7 +387,7 @@ impl VirtualMachine {
     }
 
     /// Set up device tree for the VM
-    fn setup_device_tree(&mut self) -> Result<(), &'static str> {
+    fn setup_device_tree(&mut self) -> Result<(), Error> {
         log::debug!("Setting up device tree for VM {}", self.id);
 
         // This would typically:
@@ -345,9 +403,9 @@ impl VirtualMachine {
     }
 
     /// Start the VM
-    pub fn start(&mut self) -> Result<(), &'static str> {
+    pub fn start(&mut self) -> Result<(), Error> {
         if self.state != VmState::Created {
-            return Err("VM must be created before starting");
+            return Err(Error::InvalidState("VM must be created before starting"));
         }
 
         log::info!("Starting VM {}", self.id);
@@ -357,6 +415,9 @@ impl VirtualMachine {
             self.activate_stage2_translation()?;
         }
 
+        // The host may have loaded guest code since any VCPU last ran
+        self.vcpu_manager.invalidate_mmio_caches(self.vmid);
+
         // Schedule first VCPU
         if let Some(vcpu) = self.vcpu_manager.get_next_ready_vcpu() {
             self.vcpu_manager.schedule_vcpu(vcpu.id)?;
@@ -368,43 +429,44 @@ impl VirtualMachine {
     }
 
     /// Pause the VM
-    pub fn pause(&mut self) -> Result<(), &'static str> {
+    ///
+    /// Deschedules all VCPUs while keeping their state, so the VM can be
+    /// inspected or staged for migration and later resumed.
+    pub fn pause(&mut self) -> Result<(), Error> {
         if self.state != VmState::Running {
-            return Err("VM is not running");
+            return Err(Error::InvalidState("VM is not running"));
         }
 
         log::info!("Pausing VM {}", self.id);
 
-        // Save state of all VCPUs
-        for vcpu in self.vcpu_manager.get_vcpus_mut() {
-            if vcpu.is_running() {
-                vcpu.save_state()?;
-                vcpu.set_state(VcpuState::Blocked);
-            }
-        }
+        let parked = self.vcpu_manager.park_all(VcpuWaitReason::VmPaused)?;
+        log::debug!("VM {}: parked {} VCPUs", self.id, parked);
 
         self.state = VmState::Paused;
         Ok(())
     }
 
     /// Resume the VM
-    pub fn resume(&mut self) -> Result<(), &'static str> {
+    pub fn resume(&mut self) -> Result<(), Error> {
         if self.state != VmState::Paused {
-            return Err("VM is not paused");
+            return Err(Error::InvalidState("VM is not paused"));
         }
 
         log::info!("Resuming VM {}", self.id);
 
-        // Resume all blocked VCPUs
-        for vcpu in self.vcpu_manager.get_vcpus_mut() {
-            if vcpu.state == VcpuState::Blocked {
-                vcpu.set_state(VcpuState::Ready);
-            }
-        }
+        // Release the VCPUs parked by pause()
+        self.vcpu_manager.unpark_all(VcpuWaitReason::VmPaused)?;
+
+        // Guest memory may have been written while the VM was paused
+        self.vcpu_manager.invalidate_mmio_caches(self.vmid);
+
+        // Counter offsets may have changed while paused, e.g. for migration
+        self.refresh_pvclocks();
 
         // Schedule a VCPU
         if let Some(vcpu) = self.vcpu_manager.get_next_ready_vcpu() {
-            self.vcpu_manager.schedule_vcpu(vcpu.id)?;
+            let vcpu_id = vcpu.id;
+            self.vcpu_manager.schedule_vcpu(vcpu_id)?;
         }
 
         self.state = VmState::Running;
@@ -412,7 +474,7 @@ impl VirtualMachine {
     }
 
     /// Stop the VM
-    pub fn stop(&mut self) -> Result<(), &'static str> {
+    pub fn stop(&mut self) -> Result<(), Error> {
         log::info!("Stopping VM {}", self.id);
 
         // Set all VCPUs to exited state
@@ -430,30 +492,217 @@ impl VirtualMachine {
         self.devices.push(device);
     }
 
+    /// Register a handler for guest accesses to `range`
+    ///
+    /// Lets a custom device trap its registers without a `VirtualDevice`.
+    /// The range must not overlap one already registered.
+    pub fn register_mmio_handler(&mut self, range: Range<usize>, handler: MmioHandler) -> Result<(), Error> {
+        if range.is_empty() {
+            return Err(Error::InvalidArgument("Empty MMIO range"));
+        }
+        if self.mmio_handlers.iter().any(|entry| entry.range.start < range.end && range.start < entry.range.end) {
+            return Err(Error::Busy("MMIO range already registered"));
+        }
+
+        log::debug!("VM {}: MMIO handler for [{:#x}..{:#x})", self.id, range.start, range.end);
+        self.mmio_handlers.push(MmioHandlerEntry { range, handler });
+        Ok(())
+    }
+
+    /// Register a hypercall handled for this VM only
+    ///
+    /// Subject to the same rules as `hypercall::register_hypercall`:
+    /// reserved call numbers are refused, as is registering one twice.
+    pub fn register_hypercall_handler(&mut self, id: HypercallId, handler: HypercallHandler) -> Result<(), Error> {
+        self.hypercalls.register(id, handler)?;
+        log::debug!("VM {}: hypercall handler for {:?}", self.id, id);
+        Ok(())
+    }
+
     /// Handle MMIO access
-    pub fn handle_mmio(&mut self, gpa: usize, is_write: bool, value: u64) -> Result<u64, &'static str> {
+    pub fn handle_mmio(&mut self, gpa: usize, is_write: bool, value: u64) -> Result<u64, Error> {
+        if let Some(entry) = self.mmio_handlers.iter_mut().find(|entry| entry.range.contains(&gpa)) {
+            return (entry.handler)(gpa, is_write, value);
+        }
+
         // Find device that handles this GPA
         for device in &mut self.devices {
             let config = device.get_config();
             if gpa >= config.base_addr && gpa < config.base_addr + config.mmio_size {
-                return device.handle_mmio(gpa, is_write, value);
+                let result = device.handle_mmio(gpa, is_write, value);
+                if let Some(output) = device.take_console_output() {
+                    self.log.write_console(&output);
+                }
+                return result;
             }
         }
 
-        Err("No device handles this MMIO address")
+        Err(Error::NotFound("No device handles this MMIO address"))
     }
 
     /// Inject virtual interrupt into the VM
-    pub fn inject_interrupt(&mut self, interrupt_id: u32) -> Result<(), &'static str> {
+    pub fn inject_interrupt(&mut self, interrupt_id: u32) -> Result<(), Error> {
         if !self.flags.contains(VmFlags::VIRTUAL_INTERRUPTS) {
-            return Err("Virtual interrupts not enabled");
+            return Err(Error::InvalidState("Virtual interrupts not enabled"));
         }
 
         self.vcpu_manager.inject_interrupt_to_vm(self.vmid, interrupt_id)
     }
 
+    /// Run a VCPU until an exit the host must handle
+    ///
+    /// Hypercalls are completed here: those the VM implements itself (see
+    /// `handle_hypercall`) are run, any other is answered with
+    /// `HypercallError::NotSupported`. So are accesses to the MMIO regions
+    /// of registered handlers and devices (see `handle_mmio`).
+    pub fn run_vcpu(&mut self, vcpu_id: u8) -> Result<VcpuExit, Error> {
+        // The host counter frequency may have been calibrated or changed
+        // since the clock was last published
+        let counter_hz = crate::utils::time::timer_frequency();
+        let pvclock = &self.vcpu_manager.get_vcpu(vcpu_id)
+            .ok_or(Error::NotFound("VCPU not found"))?
+            .pvclock;
+        if pvclock.frequency() != counter_hz {
+            let offset_ns = pvclock.offset();
+            self.set_guest_clock(counter_hz, offset_ns);
+        }
+
+        loop {
+            let hypercalls = &self.hypercalls;
+            let exit = self.vcpu_manager.get_vcpu(vcpu_id)
+                .ok_or(Error::NotFound("VCPU not found"))?
+                .run(|nr| hypercalls.handler(nr).is_some());
+            if let Some(exit) = self.service_exit(vcpu_id, exit)? {
+                return Ok(exit);
+            }
+        }
+    }
+
+    /// Complete the exits `run_vcpu` handles itself
+    ///
+    /// Returns the exit if the host must handle it.
+    fn service_exit(&mut self, vcpu_id: u8, exit: VcpuExit) -> Result<Option<VcpuExit>, Error> {
+        match exit {
+            VcpuExit::Hypercall { nr, args } => {
+                let result = self.handle_hypercall(vcpu_id, nr, &args)
+                    .unwrap_or(Err(HypercallError::NotSupported));
+                self.vcpu_manager.get_vcpu(vcpu_id)
+                    .ok_or(Error::NotFound("VCPU not found"))?
+                    .complete_hypercall(result);
+                Ok(None)
+            }
+            VcpuExit::Mmio { addr, size, is_write, data } => {
+                let value = match self.handle_mmio(addr as usize, is_write, data) {
+                    Ok(value) => value,
+                    Err(Error::NotFound(_)) => match self.platform_mmio(addr, size, is_write, data) {
+                        Some(result) => result?,
+                        // Not emulated here, the host must handle it
+                        None => return Ok(Some(exit)),
+                    },
+                    Err(e) => return Err(e),
+                };
+                if !is_write {
+                    self.vcpu_manager.get_vcpu(vcpu_id)
+                        .ok_or(Error::NotFound("VCPU not found"))?
+                        .complete_mmio_read(value)?;
+                }
+                Ok(None)
+            }
+            exit => Ok(Some(exit)),
+        }
+    }
+
+    /// Dispatch an MMIO access of `size` bytes to the platform devices
+    /// registered for the VMID this VM runs under in hgatp
+    ///
+    /// Returns `None` if none of them decodes `addr`.
+    fn platform_mmio(&self, addr: u64, size: u8, is_write: bool, data: u64) -> Option<Result<u64, Error>> {
+        let vm_id = self.vmid as crate::core::vmm::VmId;
+        let bits = size as u32 * 8;
+        let result = if is_write {
+            crate::emulator::mmio_write(vm_id, addr, data, bits)?.map(|_| 0)
+        } else {
+            crate::emulator::mmio_read(vm_id, addr, bits)?
+        };
+        Some(result.map_err(|_| Error::Failed("Platform device access failed")))
+    }
+
+    /// Handle a hypercall exit the VM implements itself
+    ///
+    /// Returns `None` for calls the host must handle.
+    pub fn handle_hypercall(&mut self, vcpu_id: u8, nr: usize, args: &[usize; 6]) -> Option<HypercallResult> {
+        if let Some(handler) = self.hypercalls.handler(nr) {
+            return Some(handler(&HypercallArgs { args: *args }));
+        }
+
+        match HypercallId::from_raw(nr)? {
+            HypercallId::Log => Some(self.log.log_hypercall(vcpu_id, args)),
+            HypercallId::Pvclock => Some(self.register_pvclock(vcpu_id, args[0])),
+            _ => None,
+        }
+    }
+
+    /// Register a VCPU's paravirtual clock page and publish it
+    fn register_pvclock(&mut self, vcpu_id: u8, gpa: usize) -> HypercallResult {
+        if gpa != 0 && !self.guest_memory.is_valid_range(gpa, PVCLOCK_INFO_SIZE) {
+            return Err(HypercallError::InvalidParam);
+        }
+
+        let vcpu = self.vcpu_manager.get_vcpu(vcpu_id).ok_or(HypercallError::InvalidParam)?;
+        vcpu.pvclock.register(gpa)?;
+        publish_pvclock(&self.guest_memory, &mut vcpu.pvclock, vcpu.counters.time_delta);
+        Ok(0)
+    }
+
+    /// Change the guest counter frequency and the guest time at counter
+    /// zero, republishing every registered paravirtual clock page
+    pub fn set_guest_clock(&mut self, counter_hz: u64, offset_ns: u64) {
+        for vcpu in self.vcpu_manager.get_vcpus_mut() {
+            vcpu.pvclock.set_frequency(counter_hz);
+            vcpu.pvclock.set_offset(offset_ns);
+        }
+        self.refresh_pvclocks();
+    }
+
+    /// Republish every registered paravirtual clock page
+    ///
+    /// Needed whenever a VCPU's counter offset changes, e.g. after it
+    /// migrated to a host CPU with a different counter.
+    pub fn refresh_pvclocks(&mut self) {
+        for vcpu in self.vcpu_manager.get_vcpus_mut() {
+            publish_pvclock(&self.guest_memory, &mut vcpu.pvclock, vcpu.counters.time_delta);
+        }
+    }
+
+    /// Remove and return the entries of the VM's diagnostic log
+    pub fn drain_log(&mut self) -> Vec<VmLogEntry> {
+        self.log.drain()
+    }
+
+    /// Diagnostic log entries dropped because the log was full
+    pub fn log_dropped(&self) -> u64 {
+        self.log.dropped()
+    }
+
+    /// Inject a fault into a VCPU, for exercising guest fault handlers
+    ///
+    /// Sets the VCPU's virtual cause and trap value (`addr`, the faulting
+    /// address or instruction), saves its PC in VSEPC and redirects it to
+    /// its trap vector in VS-mode. Takes effect when the VCPU next runs.
+    pub fn inject_fault(&mut self, vcpu_id: u8, kind: FaultKind, addr: usize) -> Result<(), Error> {
+        let vcpu = self.vcpu_manager.get_vcpu(vcpu_id)
+            .ok_or(Error::NotFound("VCPU not found"))?;
+        let from_supervisor = vcpu.cpu_state.privilege == 1;
+        vcpu.cpu_state.pc = vcpu.guest_csr.enter_trap(kind.exception_code() as usize, addr,
+                                                      vcpu.cpu_state.pc, from_supervisor);
+        vcpu.cpu_state.privilege = 1;
+
+        log::debug!("Injected {:?} at {:#x} into VM {} VCPU {}", kind, addr, self.id, vcpu_id);
+        Ok(())
+    }
+
     /// Activate stage-2 translation
-    fn activate_stage2_translation(&self) -> Result<(), &'static str> {
+    fn activate_stage2_translation(&self) -> Result<(), Error> {
         log::debug!("Activating stage-2 translation for VM {}", self.id);
 
         // Set HGATP with stage-2 page table
@@ -483,6 +732,40 @@ impl VirtualMachine {
     }
 }
 
+/// Publish a VCPU's paravirtual clock at the current guest counter value
+fn publish_pvclock(memory: &GuestPhysicalMemory, pvclock: &mut PvClock, time_delta: u64) {
+    let counter = crate::arch::riscv64::cpu::csr::TIME::read().wrapping_add(time_delta);
+    pvclock.publish(counter, |gpa, info| write_pvclock_page(memory, gpa, info));
+}
+
+/// Store a paravirtual clock structure in guest memory
+///
+/// An odd version, opening an update, is stored before the other fields;
+/// an even one, closing it, after them, so a guest that sees the even
+/// version also sees the final fields.
+fn write_pvclock_page(memory: &GuestPhysicalMemory, gpa: usize, info: &PvclockTimeInfo) {
+    let Some(hpa) = memory.translate_gpa_to_hpa(gpa) else {
+        log::warn!("Paravirtual clock page {:#x} is not backed by guest memory", gpa);
+        return;
+    };
+
+    let page = hpa as *mut PvclockTimeInfo;
+    unsafe {
+        let version = core::ptr::addr_of_mut!((*page).version);
+        if info.version & 1 != 0 {
+            core::ptr::write_volatile(version, info.version);
+            core::sync::atomic::fence(core::sync::atomic::Ordering::Release);
+            core::ptr::write_volatile(page, *info);
+        } else {
+            // The fields land under the odd version of the open update
+            let fields = PvclockTimeInfo { version: info.version.wrapping_sub(1), ..*info };
+            core::ptr::write_volatile(page, fields);
+            core::sync::atomic::fence(core::sync::atomic::Ordering::Release);
+            core::ptr::write_volatile(version, info.version);
+        }
+    }
+}
+
 /// VM statistics
 #[derive(Debug, Clone)]
 pub struct VmStats {
@@ -563,4 +846,209 @@ mod tests {
         vm.stop().unwrap();
         assert_eq!(vm.state, VmState::Stopped);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_vm_pause_deschedules_vcpus() {
+        let config = VmConfig {
+            num_vcpus: 2,
+            ..VmConfig::default()
+        };
+        let mut vm = VirtualMachine::new(1, "test_vm".to_string(), config, VmFlags::empty()).unwrap();
+
+        // Pausing a VM that is not running is rejected
+        vm.init().unwrap();
+        assert!(vm.pause().is_err());
+
+        vm.start().unwrap();
+        vm.pause().unwrap();
+        assert_eq!(vm.state, VmState::Paused);
+
+        // No VCPU is running or eligible to run
+        assert!(vm.vcpu_manager.get_current_vcpu().is_none());
+        assert!(!vm.vcpu_manager.has_ready_vcpu());
+        assert!(vm.vcpu_manager.get_vcpus().iter()
+            .all(|vcpu| vcpu.is_blocked_for(VcpuWaitReason::VmPaused)));
+        assert!(vm.vcpu_manager.schedule_vcpu(0).is_err());
+        assert!(vm.pause().is_err());
+
+        vm.resume().unwrap();
+        assert_eq!(vm.state, VmState::Running);
+        assert!(vm.vcpu_manager.get_current_vcpu().is_some());
+        assert!(vm.vcpu_manager.get_vcpus().iter()
+            .all(|vcpu| !vcpu.is_blocked_for(VcpuWaitReason::VmPaused)));
+    }
+
+    #[test]
+    fn test_inject_load_page_fault() {
+        let mut vm = VirtualMachine::new(1, "test_vm".to_string(), VmConfig::default(), VmFlags::empty()).unwrap();
+        vm.init().unwrap();
+
+        // A user task with interrupts enabled faults; in vectored mode
+        // exceptions still go to the base
+        let vcpu = vm.vcpu_manager.get_vcpu(0).unwrap();
+        vcpu.guest_csr.vstvec = 0x8020_0001;
+        vcpu.guest_csr.vsstatus = SstatusFlags::SIE.bits();
+        vcpu.cpu_state.pc = 0x1_0040;
+        vcpu.cpu_state.privilege = 0;
+        vm.inject_fault(0, FaultKind::LoadPageFault, 0xdead_b000).unwrap();
+
+        let vcpu = vm.vcpu_manager.get_vcpu(0).unwrap();
+        assert_eq!(vcpu.guest_csr.vscause, 13);
+        assert_eq!(vcpu.guest_csr.vstval, 0xdead_b000);
+        assert_eq!(vcpu.guest_csr.vsepc, 0x1_0040);
+        assert_eq!(vcpu.guest_csr.vsstatus, SstatusFlags::SPIE.bits());
+        assert_eq!(vcpu.cpu_state.pc, 0x8020_0000);
+        assert_eq!(vcpu.cpu_state.privilege, 1);
+
+        assert_eq!(vm.inject_fault(7, FaultKind::IllegalInstruction, 0), Err(Error::NotFound("VCPU not found")));
+    }
+
+    #[test]
+    fn test_log_hypercall_is_drained_by_host() {
+        use crate::arch::riscv64::virtualization::VmLogSource;
+
+        let mut vm = VirtualMachine::new(1, "test_vm".to_string(), VmConfig::default(), VmFlags::empty()).unwrap();
+        vm.init().unwrap();
+
+        // "hello, log" packed little-endian into a1-a2, completed on the
+        // VCPU's a0/a1 like a real hypercall exit
+        let mut args = [0usize; 6];
+        args[0] = 10;
+        args[1] = usize::from_le_bytes(*b"hello, l");
+        args[2] = usize::from_le_bytes(*b"og\0\0\0\0\0\0");
+        let exit = VcpuExit::Hypercall { nr: HypercallId::Log.raw(), args };
+        assert_eq!(vm.service_exit(0, exit), Ok(None));
+        assert_eq!(vm.vcpu_manager.get_vcpu(0).unwrap().cpu_state.gpr[10..12], [0, 10]);
+
+        // Too long for the argument registers
+        args[0] = 41;
+        assert!(vm.handle_hypercall(0, HypercallId::Log.raw(), &args).unwrap().is_err());
+
+        // Calls nobody implements are refused rather than left pending
+        let exit = VcpuExit::Hypercall { nr: HypercallId::Vendor(1).raw(), args };
+        assert_eq!(vm.service_exit(0, exit), Ok(None));
+        let a0 = vm.vcpu_manager.get_vcpu(0).unwrap().cpu_state.gpr[10];
+        assert_eq!(a0, HypercallError::NotSupported.code() as usize);
+        assert_eq!(vm.service_exit(0, VcpuExit::Shutdown), Ok(Some(VcpuExit::Shutdown)));
+
+        // The guest console lands in the log too
+        for &byte in b"ok\n" {
+            vm.handle_mmio(GUEST_UART_BASE, true, byte as u64).unwrap();
+        }
+
+        let entries = vm.drain_log();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].source, VmLogSource::Hypercall { vcpu_id: 0 });
+        assert_eq!(entries[0].data, b"hello, log");
+        assert_eq!(entries[1].source, VmLogSource::Console);
+        assert_eq!(entries[1].data, b"ok");
+        assert!(vm.drain_log().is_empty());
+        assert_eq!(vm.log_dropped(), 0);
+    }
+
+    #[test]
+    fn test_pvclock_page_must_be_in_guest_memory() {
+        let mut vm = VirtualMachine::new(1, "test_vm".to_string(), VmConfig::default(), VmFlags::empty()).unwrap();
+        vm.init().unwrap();
+
+        let mut args = [0usize; 6];
+        args[0] = 0x1000;
+        assert_eq!(vm.handle_hypercall(0, HypercallId::Pvclock.raw(), &args), Some(Err(HypercallError::InvalidParam)));
+        args[0] = 0x4000_1000;
+        assert_eq!(vm.handle_hypercall(7, HypercallId::Pvclock.raw(), &args), Some(Err(HypercallError::InvalidParam)));
+        assert_eq!(vm.vcpu_manager.get_vcpu(0).unwrap().pvclock.page(), None);
+    }
+
+    /// Device answering every read with its base address
+    struct BaseDevice {
+        config: VmDeviceConfig,
+    }
+
+    impl VirtualDevice for BaseDevice {
+        fn device_id(&self) -> u32 {
+            1
+        }
+
+        fn device_name(&self) -> &str {
+            "base"
+        }
+
+        fn init(&mut self, _vm: &mut VirtualMachine) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn handle_mmio(&mut self, _gpa: usize, _is_write: bool, _value: u64) -> Result<u64, Error> {
+            Ok(self.config.base_addr as u64)
+        }
+
+        fn handle_interrupt(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn get_config(&self) -> &VmDeviceConfig {
+            &self.config
+        }
+    }
+
+    #[test]
+    fn test_registered_mmio_handler_takes_precedence() {
+        // Latches the last value written and reads it back
+        fn latch() -> MmioHandler {
+            let mut latched = 0;
+            Box::new(move |_gpa, is_write, value| {
+                if is_write {
+                    latched = value;
+                }
+                Ok(latched)
+            })
+        }
+
+        let mut vm = VirtualMachine::new(1, "test_vm".to_string(), VmConfig::default(), VmFlags::empty()).unwrap();
+        vm.add_device(Box::new(BaseDevice {
+            config: VmDeviceConfig {
+                device_type: "base".to_string(),
+                base_addr: 0x1000_0000,
+                mmio_size: 0x2000,
+                num_irqs: 0,
+                params: std::collections::HashMap::new(),
+            },
+        }));
+        vm.register_mmio_handler(0x1000_1000..0x1000_1100, latch()).unwrap();
+        assert_eq!(vm.register_mmio_handler(0x1000_10f0..0x1000_2000, latch()),
+                   Err(Error::Busy("MMIO range already registered")));
+        assert!(vm.register_mmio_handler(0x1000_2000..0x1000_2000, latch()).is_err());
+
+        // Guest accesses in the range reach the handler, not the device
+        // under it
+        let store = VcpuExit::Mmio { addr: 0x1000_10f8, size: 8, is_write: true, data: 42 };
+        assert_eq!(vm.service_exit(0, store), Ok(None));
+        assert_eq!(vm.handle_mmio(0x1000_1008, false, 0), Ok(42));
+
+        // Everything else falls through to the devices
+        assert_eq!(vm.handle_mmio(0x1000_1100, false, 0), Ok(0x1000_0000));
+        assert_eq!(vm.handle_mmio(0x1000_0ff8, false, 0), Ok(0x1000_0000));
+        assert!(vm.handle_mmio(0x2000_0000, false, 0).is_err());
+
+        // Accesses nobody emulates go to the host
+        let store = VcpuExit::Mmio { addr: 0x2000_0000, size: 4, is_write: true, data: 1 };
+        assert_eq!(vm.service_exit(0, store), Ok(Some(store)));
+    }
+
+    #[test]
+    fn test_registered_hypercall_is_per_vm() {
+        fn sum(args: &HypercallArgs) -> HypercallResult {
+            Ok(args.args[0] + args.args[1])
+        }
+
+        let mut vm = VirtualMachine::new(1, "test_vm".to_string(), VmConfig::default(), VmFlags::empty()).unwrap();
+        let other = VirtualMachine::new(2, "other_vm".to_string(), VmConfig::default(), VmFlags::empty()).unwrap();
+        vm.register_hypercall_handler(HypercallId::Vendor(3), sum).unwrap();
+        assert!(vm.register_hypercall_handler(HypercallId::Vendor(3), sum).is_err());
+        assert!(vm.register_hypercall_handler(HypercallId::Log, sum).is_err());
+
+        let args = [2, 3, 0, 0, 0, 0];
+        assert_eq!(vm.handle_hypercall(0, HypercallId::Vendor(3).raw(), &args), Some(Ok(5)));
+        assert!(other.hypercalls.handler(HypercallId::Vendor(3).raw()).is_none());
+        assert_eq!(vm.handle_hypercall(0, HypercallId::Vendor(4).raw(), &args), None);
+    }
+}
@@ -0,0 +1,154 @@
+//! Per-VM Diagnostic Log
+//!
+//! Each VM has a bounded log the host reads with
+//! `VirtualMachine::drain_log()`, separate from the hypervisor log. It
+//! collects:
+//! - messages the guest writes with the `HypercallId::Log` hypercall
+//! - output of the VM's console devices, one entry per line
+//!
+//! When the log is full the oldest entry is dropped and counted.
+
+use crate::arch::riscv64::virtualization::hypercall::{HypercallError, HypercallResult};
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+/// Maximum number of entries kept
+pub const VM_LOG_CAPACITY: usize = 256;
+
+/// Console output longer than this is split into several entries
+pub const VM_LOG_LINE_MAX: usize = 256;
+
+/// Maximum message bytes in one log hypercall (a1-a5)
+pub const LOG_HYPERCALL_MAX: usize = 5 * core::mem::size_of::<usize>();
+
+/// Where a log entry came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmLogSource {
+    /// Log hypercall from a VCPU
+    Hypercall { vcpu_id: u8 },
+    /// Console device output
+    Console,
+}
+
+/// One log entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VmLogEntry {
+    /// Producer of the entry
+    pub source: VmLogSource,
+    /// Raw message bytes
+    pub data: Vec<u8>,
+}
+
+/// Bounded log of one VM
+#[derive(Debug, Default)]
+pub struct VmLog {
+    /// Entries, oldest first
+    entries: VecDeque<VmLogEntry>,
+    /// Console output since the last complete line
+    console_line: Vec<u8>,
+    /// Entries dropped to make room
+    dropped: u64,
+}
+
+impl VmLog {
+    /// Create an empty log
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append an entry, dropping the oldest if the log is full
+    pub fn push(&mut self, source: VmLogSource, data: Vec<u8>) {
+        if self.entries.len() == VM_LOG_CAPACITY {
+            self.entries.pop_front();
+            self.dropped += 1;
+        }
+        self.entries.push_back(VmLogEntry { source, data });
+    }
+
+    /// Append console output, completing an entry at each newline
+    pub fn write_console(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            if byte == b'\n' {
+                let line = core::mem::take(&mut self.console_line);
+                self.push(VmLogSource::Console, line);
+                continue;
+            }
+            self.console_line.push(byte);
+            if self.console_line.len() == VM_LOG_LINE_MAX {
+                let line = core::mem::take(&mut self.console_line);
+                self.push(VmLogSource::Console, line);
+            }
+        }
+    }
+
+    /// Log a message from a log hypercall
+    ///
+    /// The guest passes the message length in a0 and up to
+    /// `LOG_HYPERCALL_MAX` bytes packed little-endian in a1-a5. Returns the
+    /// number of bytes logged.
+    pub fn log_hypercall(&mut self, vcpu_id: u8, args: &[usize; 6]) -> HypercallResult {
+        let len = args[0];
+        if len > LOG_HYPERCALL_MAX {
+            return Err(HypercallError::InvalidParam);
+        }
+
+        let data = args[1..].iter()
+            .flat_map(|word| word.to_le_bytes())
+            .take(len)
+            .collect();
+        self.push(VmLogSource::Hypercall { vcpu_id }, data);
+        Ok(len)
+    }
+
+    /// Remove and return all entries, oldest first
+    pub fn drain(&mut self) -> Vec<VmLogEntry> {
+        self.entries.drain(..).collect()
+    }
+
+    /// Number of entries waiting to be drained
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check whether no entry is waiting
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Entries dropped because the log was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overflow_drops_oldest() {
+        let mut log = VmLog::new();
+        for i in 0..VM_LOG_CAPACITY + 3 {
+            log.push(VmLogSource::Console, alloc::vec![i as u8]);
+        }
+        assert_eq!(log.len(), VM_LOG_CAPACITY);
+        assert_eq!(log.dropped(), 3);
+
+        let entries = log.drain();
+        assert_eq!(entries[0].data, [3]);
+        assert_eq!(entries[VM_LOG_CAPACITY - 1].data, [(VM_LOG_CAPACITY + 2) as u8]);
+        assert!(log.is_empty());
+        assert_eq!(log.dropped(), 3);
+    }
+
+    #[test]
+    fn test_console_output_is_split_into_lines() {
+        let mut log = VmLog::new();
+        log.write_console(b"boot");
+        assert!(log.is_empty());
+        log.write_console(b"ing\nok\n");
+
+        let lines: Vec<_> = log.drain().into_iter().map(|entry| entry.data).collect();
+        assert_eq!(lines, [b"booting".to_vec(), b"ok".to_vec()]);
+    }
+}
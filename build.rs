@@ -9,6 +9,7 @@ use std::path::{Path, PathBuf};
 fn main() {
     println!("cargo:rerun-if-changed=build.rs");
     println!("cargo:rerun-if-env-changed=TARGET");
+    println!("cargo:rerun-if-env-changed=FERROVISOR_EARLY_UART_BASE");
 
     // Get target triple
     let target = env::var("TARGET").unwrap_or_else(|_| {
@@ -160,8 +161,10 @@ MEMORY {
 SECTIONS {
     /* Code section */
     .text : {
+        __text_start = .;
         KEEP(*(.text.entry))   /* Entry point first */
         *(.text .text.*)
+        __text_end = .;
         *(.rodata .rodata.*)
     } > RAM
 
@@ -223,8 +226,10 @@ MEMORY {
 SECTIONS {
     /* Code section */
     .text : {
+        __text_start = .;
         KEEP(*(.text.entry))   /* Entry point first */
         *(.text .text.*)
+        __text_end = .;
         *(.rodata .rodata.*)
     } > RAM
 
@@ -286,8 +291,10 @@ MEMORY {
 SECTIONS {
     /* Code section */
     .text : {
+        __text_start = .;
         KEEP(*(.text.entry))   /* Entry point first */
         *(.text .text.*)
+        __text_end = .;
         *(.rodata .rodata.*)
     } > RAM
 
@@ -4,9 +4,11 @@
 //! including VM configurations and runtime settings.
 
 use crate::{Error, Result};
+use crate::core::sync::SpinLock;
 use alloc::collections::BTreeMap;
 use alloc::string::String;
 use alloc::vec::Vec;
+use core::fmt;
 
 /// VM configuration structure
 #[derive(Debug, Clone)]
@@ -59,6 +61,16 @@ pub enum DeviceType {
     VirtioNet,
     /// VirtIO console device
     VirtioConsole,
+    /// VirtIO memory balloon device
+    VirtioBalloon,
+    /// VirtIO input device
+    VirtioInput,
+    /// VirtIO GPU device
+    VirtioGpu,
+    /// VirtIO entropy device
+    VirtioRng,
+    /// CFI flash bank
+    Flash,
     /// PCI device
     Pci,
     /// Platform device
@@ -149,4 +161,209 @@ pub fn validate_vm_config(config: &VmConfig) -> Result<()> {
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// MMIO range claimed by a device or emulator
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MmioClaim {
+    /// Claiming device
+    pub owner: String,
+    /// Base address
+    pub base: u64,
+    /// Size in bytes
+    pub size: u64,
+}
+
+impl MmioClaim {
+    /// First address past the range
+    fn end(&self) -> u64 {
+        self.base.saturating_add(self.size)
+    }
+}
+
+/// Conflict found by validation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResourceConflict {
+    /// Two MMIO ranges overlap
+    MmioOverlap { first: MmioClaim, second: MmioClaim },
+    /// Two devices claim the same IRQ
+    DuplicateIrq { irq: u32, first: String, second: String },
+}
+
+impl fmt::Display for ResourceConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResourceConflict::MmioOverlap { first, second } => write!(
+                f, "MMIO overlap: {} [{:#x}..{:#x}) and {} [{:#x}..{:#x})",
+                first.owner, first.base, first.end(), second.owner, second.base, second.end()),
+            ResourceConflict::DuplicateIrq { irq, first, second } => {
+                write!(f, "IRQ {} claimed by both {} and {}", irq, first, second)
+            }
+        }
+    }
+}
+
+/// MMIO ranges and IRQ numbers claimed during initialization
+#[derive(Debug, Default)]
+pub struct ResourceRegistry {
+    /// MMIO claims in registration order
+    mmio: Vec<MmioClaim>,
+    /// IRQ claims in registration order
+    irqs: Vec<(u32, String)>,
+}
+
+impl ResourceRegistry {
+    /// Create an empty registry
+    pub const fn new() -> Self {
+        Self {
+            mmio: Vec::new(),
+            irqs: Vec::new(),
+        }
+    }
+
+    /// Record an MMIO range
+    ///
+    /// Zero-sized ranges claim nothing and are ignored.
+    pub fn add_mmio(&mut self, owner: &str, base: u64, size: u64) {
+        if size != 0 {
+            self.mmio.push(MmioClaim { owner: String::from(owner), base, size });
+        }
+    }
+
+    /// Drop the MMIO ranges claimed by `owner`
+    pub fn remove_mmio(&mut self, owner: &str) {
+        self.mmio.retain(|claim| claim.owner != owner);
+    }
+
+    /// Record an IRQ number
+    pub fn add_irq(&mut self, owner: &str, irq: u32) {
+        self.irqs.push((irq, String::from(owner)));
+    }
+
+    /// Record the MMIO range and IRQ of each configured device
+    pub fn add_devices(&mut self, devices: &[DeviceConfig]) {
+        for device in devices {
+            if let (Some(base), Some(size)) = (device.base_address, device.size) {
+                self.add_mmio(&device.name, base, size);
+            }
+            if let Some(irq) = device.irq {
+                self.add_irq(&device.name, irq);
+            }
+        }
+    }
+
+    /// Find the lowest-addressed MMIO overlap, else the lowest duplicate IRQ
+    pub fn find_conflict(&self) -> Option<ResourceConflict> {
+        let mut mmio: Vec<&MmioClaim> = self.mmio.iter().collect();
+        mmio.sort_by_key(|claim| claim.base);
+        for pair in mmio.windows(2) {
+            if pair[1].base < pair[0].end() {
+                return Some(ResourceConflict::MmioOverlap {
+                    first: pair[0].clone(),
+                    second: pair[1].clone(),
+                });
+            }
+        }
+
+        let mut irqs: Vec<&(u32, String)> = self.irqs.iter().collect();
+        irqs.sort_by_key(|(irq, _)| *irq);
+        for pair in irqs.windows(2) {
+            if pair[0].0 == pair[1].0 {
+                return Some(ResourceConflict::DuplicateIrq {
+                    irq: pair[0].0,
+                    first: pair[0].1.clone(),
+                    second: pair[1].1.clone(),
+                });
+            }
+        }
+
+        None
+    }
+
+    /// Check that no MMIO ranges overlap and no IRQ is claimed twice
+    pub fn validate(&self) -> Result<()> {
+        match self.find_conflict() {
+            Some(conflict) => {
+                crate::error!("config: {}", conflict);
+                Err(Error::InvalidState)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// Resources claimed by devices and emulators at init
+static RESOURCES: SpinLock<ResourceRegistry> = SpinLock::new(ResourceRegistry::new());
+
+/// Register an MMIO range for validation
+pub fn register_mmio(owner: &str, base: u64, size: u64) {
+    RESOURCES.lock().add_mmio(owner, base, size);
+}
+
+/// Release the MMIO ranges registered by `owner`
+pub fn release_mmio(owner: &str) {
+    RESOURCES.lock().remove_mmio(owner);
+}
+
+/// Register an IRQ number for validation
+pub fn register_irq(owner: &str, irq: u32) {
+    RESOURCES.lock().add_irq(owner, irq);
+}
+
+/// Validate all registered MMIO ranges and IRQ numbers
+///
+/// Returns `Error::InvalidState` on the first conflict; the conflicting
+/// owners and ranges are logged.
+pub fn validate() -> Result<()> {
+    RESOURCES.lock().validate()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overlapping_emulator_ranges_rejected() {
+        let mut registry = ResourceRegistry::new();
+        registry.add_mmio("uart0", 0x0900_0000, 0x1000);
+        registry.add_mmio("rtc", 0x0901_0000, 0x1000);
+        registry.add_mmio("gpio", 0x0900_0800, 0x1000);
+
+        assert_eq!(registry.validate(), Err(Error::InvalidState));
+        match registry.find_conflict() {
+            Some(ResourceConflict::MmioOverlap { first, second }) => {
+                assert_eq!(first.owner, "uart0");
+                assert_eq!(second.owner, "gpio");
+            }
+            other => panic!("unexpected conflict {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_disjoint_ranges_pass() {
+        let mut registry = ResourceRegistry::new();
+        // Adjacent ranges do not overlap
+        registry.add_mmio("uart0", 0x0900_0000, 0x1000);
+        registry.add_mmio("uart1", 0x0900_1000, 0x1000);
+        registry.add_mmio("rtc", 0x0901_0000, 0x1000);
+        registry.add_irq("uart0", 33);
+        registry.add_irq("uart1", 34);
+
+        assert_eq!(registry.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_duplicate_irq_rejected() {
+        let mut registry = ResourceRegistry::new();
+        registry.add_irq("uart0", 33);
+        registry.add_irq("rtc", 34);
+        registry.add_irq("virtio-blk", 33);
+
+        assert_eq!(registry.find_conflict(), Some(ResourceConflict::DuplicateIrq {
+            irq: 33,
+            first: String::from("uart0"),
+            second: String::from("virtio-blk"),
+        }));
+        assert_eq!(registry.validate(), Err(Error::InvalidState));
+    }
+}
@@ -0,0 +1,355 @@
+//! DMA remapping (IOMMU) support
+//!
+//! Passthrough devices issue DMA with I/O virtual addresses (IOVAs) that
+//! must be confined to their owner's memory. Each device is attached to a
+//! domain modelled on the ARM SMMUv3 / RISC-V IOMMU two-stage layout:
+//! - stage 1 maps IOVA -> output address with per-range permissions
+//! - stage 2 (optional) treats the stage-1 output as a GPA and translates
+//!   it through the owning VM's G-stage context
+//!
+//! Domains without stage 2 map IOVAs directly to host physical addresses.
+//! The DMA path calls `translate` before touching memory; any IOVA not
+//! covered by a mapping with the required permissions faults.
+
+use crate::{Result, Error};
+use crate::core::mm::{PhysAddr, PAGE_SIZE};
+use crate::core::mm::gstage::Vmid;
+use crate::core::sync::SpinLock;
+use alloc::collections::BTreeMap;
+
+/// I/O virtual address
+pub type Iova = u64;
+
+/// Device (stream) identifier
+pub type DeviceId = u32;
+
+/// DMA access permissions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DmaPerms {
+    /// Device may read memory
+    pub read: bool,
+    /// Device may write memory
+    pub write: bool,
+}
+
+impl DmaPerms {
+    /// Read-only access
+    pub const READ: Self = Self { read: true, write: false };
+    /// Write-only access
+    pub const WRITE: Self = Self { read: false, write: true };
+    /// Read-write access
+    pub const READ_WRITE: Self = Self { read: true, write: true };
+
+    /// Check whether these permissions allow `access`
+    pub fn allows(&self, access: DmaPerms) -> bool {
+        (!access.read || self.read) && (!access.write || self.write)
+    }
+}
+
+/// A stage-1 IOVA range mapping
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IommuMapping {
+    /// First IOVA of the range
+    pub iova: Iova,
+    /// Output address the range maps to
+    pub output: PhysAddr,
+    /// Size in bytes
+    pub size: u64,
+    /// Allowed accesses
+    pub perms: DmaPerms,
+}
+
+impl IommuMapping {
+    /// Check whether `iova` falls inside this mapping
+    pub fn contains(&self, iova: Iova) -> bool {
+        iova >= self.iova && iova - self.iova < self.size
+    }
+}
+
+/// Translation domain of one device
+#[derive(Debug)]
+pub struct IommuDomain {
+    /// Device attached to this domain
+    device_id: DeviceId,
+    /// VM whose G-stage tables provide stage 2, if nested
+    stage2_vmid: Option<Vmid>,
+    /// Stage-1 mappings keyed by starting IOVA
+    mappings: BTreeMap<Iova, IommuMapping>,
+}
+
+impl IommuDomain {
+    /// Create a single-stage domain (IOVA -> HPA)
+    pub const fn new(device_id: DeviceId) -> Self {
+        Self {
+            device_id,
+            stage2_vmid: None,
+            mappings: BTreeMap::new(),
+        }
+    }
+
+    /// Create a nested domain whose stage-1 output is a GPA of `vmid`
+    pub const fn new_nested(device_id: DeviceId, vmid: Vmid) -> Self {
+        Self {
+            device_id,
+            stage2_vmid: Some(vmid),
+            mappings: BTreeMap::new(),
+        }
+    }
+
+    /// Get the attached device
+    pub fn device_id(&self) -> DeviceId {
+        self.device_id
+    }
+
+    /// Get the stage-2 VM, if nested
+    pub fn stage2_vmid(&self) -> Option<Vmid> {
+        self.stage2_vmid
+    }
+
+    /// Get the number of stage-1 mappings
+    pub fn mapping_count(&self) -> usize {
+        self.mappings.len()
+    }
+
+    /// Map `[iova, iova + size)` to `output`
+    ///
+    /// All arguments must be page aligned and the range must not overlap
+    /// an existing mapping.
+    pub fn map(&mut self, iova: Iova, output: PhysAddr, size: u64, perms: DmaPerms) -> Result<()> {
+        if size == 0 || (iova | output | size) & (PAGE_SIZE - 1) != 0 {
+            return Err(Error::InvalidArgument);
+        }
+        let end = iova.checked_add(size).ok_or(Error::InvalidArgument)?;
+
+        // Nearest mapping at or below `end` is the only one that can overlap
+        if let Some((_, prev)) = self.mappings.range(..end).next_back() {
+            if prev.iova + prev.size > iova {
+                return Err(Error::ResourceBusy);
+            }
+        }
+
+        self.mappings.insert(iova, IommuMapping { iova, output, size, perms });
+        Ok(())
+    }
+
+    /// Remove the mapping starting at `iova`
+    ///
+    /// `size` must match the size it was mapped with.
+    pub fn unmap(&mut self, iova: Iova, size: u64) -> Result<()> {
+        match self.mappings.get(&iova) {
+            Some(mapping) if mapping.size == size => {
+                self.mappings.remove(&iova);
+                Ok(())
+            }
+            Some(_) => Err(Error::InvalidArgument),
+            None => Err(Error::NotFound),
+        }
+    }
+
+    /// Find the stage-1 mapping covering `iova`
+    fn lookup(&self, iova: Iova) -> Option<&IommuMapping> {
+        self.mappings
+            .range(..=iova)
+            .next_back()
+            .map(|(_, mapping)| mapping)
+            .filter(|mapping| mapping.contains(iova))
+    }
+
+    /// Translate an IOVA through stage 1 only
+    pub fn translate_stage1(&self, iova: Iova, access: DmaPerms) -> Result<PhysAddr> {
+        let mapping = self.lookup(iova).ok_or(Error::NotFound)?;
+        if !mapping.perms.allows(access) {
+            return Err(Error::PermissionDenied);
+        }
+        Ok(mapping.output + (iova - mapping.iova))
+    }
+
+    /// Translate an IOVA to a host physical address
+    pub fn translate(&self, iova: Iova, access: DmaPerms) -> Result<PhysAddr> {
+        let output = self.translate_stage1(iova, access)?;
+
+        match self.stage2_vmid {
+            Some(vmid) => crate::core::mm::gstage::get()
+                .and_then(|manager| manager.get_context(vmid))
+                .ok_or(Error::NotInitialized)?
+                .translate(output),
+            None => Ok(output),
+        }
+    }
+
+    /// Translate a DMA buffer, requiring every byte to be mapped
+    ///
+    /// Returns the host address of the first byte. The buffer must lie
+    /// within a single mapping so it is contiguous on the host side.
+    pub fn translate_range(&self, iova: Iova, len: u64, access: DmaPerms) -> Result<PhysAddr> {
+        let mapping = self.lookup(iova).ok_or(Error::NotFound)?;
+        let last = iova.checked_add(len.max(1) - 1).ok_or(Error::InvalidArgument)?;
+        if !mapping.contains(last) {
+            return Err(Error::NotFound);
+        }
+        self.translate(iova, access)
+    }
+}
+
+/// IOMMU managing all device domains
+pub struct Iommu {
+    /// Domains keyed by device
+    domains: BTreeMap<DeviceId, IommuDomain>,
+}
+
+impl Iommu {
+    /// Create an IOMMU with no attached devices
+    pub const fn new() -> Self {
+        Self {
+            domains: BTreeMap::new(),
+        }
+    }
+
+    /// Attach a domain to its device
+    pub fn attach(&mut self, domain: IommuDomain) -> Result<()> {
+        if self.domains.contains_key(&domain.device_id) {
+            return Err(Error::ResourceBusy);
+        }
+        self.domains.insert(domain.device_id, domain);
+        Ok(())
+    }
+
+    /// Detach and return a device's domain
+    pub fn detach(&mut self, device_id: DeviceId) -> Result<IommuDomain> {
+        self.domains.remove(&device_id).ok_or(Error::NotFound)
+    }
+
+    /// Get a device's domain
+    pub fn domain(&self, device_id: DeviceId) -> Option<&IommuDomain> {
+        self.domains.get(&device_id)
+    }
+
+    /// Get a device's domain mutably
+    pub fn domain_mut(&mut self, device_id: DeviceId) -> Option<&mut IommuDomain> {
+        self.domains.get_mut(&device_id)
+    }
+}
+
+/// Global IOMMU
+static IOMMU: SpinLock<Iommu> = SpinLock::new(Iommu::new());
+
+/// Initialize DMA remapping
+pub fn init() -> Result<()> {
+    crate::info!("IOMMU: DMA remapping initialized");
+    Ok(())
+}
+
+/// Attach a device to a new single-stage domain
+pub fn attach_device(device_id: DeviceId) -> Result<()> {
+    IOMMU.lock().attach(IommuDomain::new(device_id))
+}
+
+/// Attach a device to a new domain nested under a VM's G-stage
+pub fn attach_device_nested(device_id: DeviceId, vmid: Vmid) -> Result<()> {
+    IOMMU.lock().attach(IommuDomain::new_nested(device_id, vmid))
+}
+
+/// Detach a device, dropping all of its mappings
+pub fn detach_device(device_id: DeviceId) -> Result<()> {
+    IOMMU.lock().detach(device_id).map(|_| ())
+}
+
+/// Map an IOVA range for a device
+pub fn map(device_id: DeviceId, iova: Iova, hpa: PhysAddr, size: u64, perms: DmaPerms) -> Result<()> {
+    IOMMU.lock()
+        .domain_mut(device_id)
+        .ok_or(Error::NotFound)?
+        .map(iova, hpa, size, perms)
+}
+
+/// Unmap an IOVA range for a device
+pub fn unmap(device_id: DeviceId, iova: Iova, size: u64) -> Result<()> {
+    IOMMU.lock()
+        .domain_mut(device_id)
+        .ok_or(Error::NotFound)?
+        .unmap(iova, size)
+}
+
+/// Translate a device DMA access of `len` bytes at `iova`
+///
+/// This is the check every DMA path must pass before touching memory.
+pub fn translate(device_id: DeviceId, iova: Iova, len: u64, access: DmaPerms) -> Result<PhysAddr> {
+    IOMMU.lock()
+        .domain(device_id)
+        .ok_or(Error::NotFound)?
+        .translate_range(iova, len, access)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unmapped_iova_faults() {
+        let mut domain = IommuDomain::new(1);
+        assert_eq!(domain.translate(0x1000, DmaPerms::READ), Err(Error::NotFound));
+
+        domain.map(0x10000, 0x8000_0000, 0x2000, DmaPerms::READ_WRITE).unwrap();
+        assert_eq!(domain.translate(0xf000, DmaPerms::READ), Err(Error::NotFound));
+        assert_eq!(domain.translate(0x12000, DmaPerms::READ), Err(Error::NotFound));
+
+        domain.unmap(0x10000, 0x2000).unwrap();
+        assert_eq!(domain.translate(0x10000, DmaPerms::READ), Err(Error::NotFound));
+    }
+
+    #[test]
+    fn test_mapped_range_translates() {
+        let mut domain = IommuDomain::new(1);
+        domain.map(0x10000, 0x8000_0000, 0x2000, DmaPerms::READ_WRITE).unwrap();
+        domain.map(0x20000, 0x9000_0000, 0x1000, DmaPerms::READ).unwrap();
+
+        assert_eq!(domain.translate(0x10000, DmaPerms::WRITE), Ok(0x8000_0000));
+        assert_eq!(domain.translate(0x11ff8, DmaPerms::READ), Ok(0x8000_1ff8));
+        assert_eq!(domain.translate(0x20010, DmaPerms::READ), Ok(0x9000_0010));
+
+        // Buffers must stay inside one mapping
+        assert_eq!(domain.translate_range(0x11000, 0x1000, DmaPerms::READ), Ok(0x8000_1000));
+        assert_eq!(domain.translate_range(0x11000, 0x1001, DmaPerms::READ), Err(Error::NotFound));
+    }
+
+    #[test]
+    fn test_permissions_enforced() {
+        let mut domain = IommuDomain::new(1);
+        domain.map(0x20000, 0x9000_0000, 0x1000, DmaPerms::READ).unwrap();
+        domain.map(0x30000, 0xa000_0000, 0x1000, DmaPerms::WRITE).unwrap();
+
+        assert_eq!(domain.translate(0x20000, DmaPerms::WRITE), Err(Error::PermissionDenied));
+        assert_eq!(domain.translate(0x20000, DmaPerms::READ_WRITE), Err(Error::PermissionDenied));
+        assert_eq!(domain.translate(0x30000, DmaPerms::READ), Err(Error::PermissionDenied));
+        assert_eq!(domain.translate(0x30000, DmaPerms::WRITE), Ok(0xa000_0000));
+    }
+
+    #[test]
+    fn test_map_rejects_overlap_and_misalignment() {
+        let mut domain = IommuDomain::new(1);
+        domain.map(0x10000, 0x8000_0000, 0x2000, DmaPerms::READ).unwrap();
+
+        assert_eq!(domain.map(0x11000, 0x9000_0000, 0x1000, DmaPerms::READ), Err(Error::ResourceBusy));
+        assert_eq!(domain.map(0xf000, 0x9000_0000, 0x2000, DmaPerms::READ), Err(Error::ResourceBusy));
+        assert_eq!(domain.map(0x12000, 0x9000_0800, 0x1000, DmaPerms::READ), Err(Error::InvalidArgument));
+        assert_eq!(domain.unmap(0x10000, 0x1000), Err(Error::InvalidArgument));
+
+        domain.map(0x12000, 0x9000_0000, 0x1000, DmaPerms::READ).unwrap();
+        assert_eq!(domain.mapping_count(), 2);
+    }
+
+    #[test]
+    fn test_iommu_domains_are_per_device() {
+        let mut iommu = Iommu::new();
+        iommu.attach(IommuDomain::new(1)).unwrap();
+        iommu.attach(IommuDomain::new(2)).unwrap();
+        assert_eq!(iommu.attach(IommuDomain::new(1)).unwrap_err(), Error::ResourceBusy);
+
+        iommu.domain_mut(1).unwrap().map(0x10000, 0x8000_0000, 0x1000, DmaPerms::READ).unwrap();
+        assert!(iommu.domain(1).unwrap().translate(0x10000, DmaPerms::READ).is_ok());
+        assert_eq!(iommu.domain(2).unwrap().translate(0x10000, DmaPerms::READ), Err(Error::NotFound));
+
+        iommu.detach(1).unwrap();
+        assert!(iommu.domain(1).is_none());
+    }
+}
@@ -10,148 +10,7 @@ use alloc::vec::Vec;
 use alloc::vec;
 use core::sync::atomic::{AtomicU64, AtomicU32, Ordering};
 
-/// Maximum number of CPUs supported
-pub const MAX_CPUS: usize = 64;
-
-/// CPU mask for affinity operations
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub struct CpuMask {
-    bits: u64,
-}
-
-impl CpuMask {
-    /// Create an empty CPU mask
-    pub const fn new() -> Self {
-        Self { bits: 0 }
-    }
-
-    /// Create a CPU mask with all bits set
-    pub const fn all() -> Self {
-        Self { bits: u64::MAX }
-    }
-
-    /// Create a CPU mask from a bit pattern
-    pub const fn from_bits(bits: u64) -> Self {
-        Self { bits }
-    }
-
-    /// Create a CPU mask for a single CPU
-    pub fn from_cpu(cpu: u32) -> Self {
-        Self { bits: 1u64 << cpu }
-    }
-
-    /// Get the underlying bits
-    pub const fn bits(&self) -> u64 {
-        self.bits
-    }
-
-    /// Check if a CPU is set in the mask
-    pub fn contains(&self, cpu: u32) -> bool {
-        (self.bits & (1u64 << cpu)) != 0
-    }
-
-    /// Set a CPU in the mask
-    pub fn set(&mut self, cpu: u32) {
-        self.bits |= 1u64 << cpu;
-    }
-
-    /// Clear a CPU in the mask
-    pub fn clear(&mut self, cpu: u32) {
-        self.bits &= !(1u64 << cpu);
-    }
-
-    /// Check if the mask is empty
-    pub fn is_empty(&self) -> bool {
-        self.bits == 0
-    }
-
-    /// Check if the mask has all CPUs
-    pub fn is_all(&self) -> bool {
-        self.bits == u64::MAX
-    }
-
-    /// Count the number of CPUs in the mask
-    pub fn count(&self) -> u32 {
-        self.bits.count_ones()
-    }
-
-    /// Get the first CPU in the mask
-    pub fn first(&self) -> Option<u32> {
-        if self.is_empty() {
-            None
-        } else {
-            Some(self.bits.trailing_zeros())
-        }
-    }
-
-    /// Get a random CPU from the mask
-    pub fn random(&self) -> Option<u32> {
-        if self.is_empty() {
-            None
-        } else {
-            let count = self.count();
-            let index = (crate::utils::random::u32() % count) as u32;
-            let mut bits = self.bits;
-
-            // Skip to the selected index
-            for _ in 0..index {
-                bits &= bits - 1; // Clear the lowest set bit
-            }
-
-            Some(bits.trailing_zeros())
-        }
-    }
-
-    /// Perform bitwise AND with another mask
-    pub fn and(&self, other: &CpuMask) -> CpuMask {
-        CpuMask::from_bits(self.bits & other.bits)
-    }
-
-    /// Perform bitwise OR with another mask
-    pub fn or(&self, other: &CpuMask) -> CpuMask {
-        CpuMask::from_bits(self.bits | other.bits)
-    }
-
-    /// Perform bitwise XOR with another mask
-    pub fn xor(&self, other: &CpuMask) -> CpuMask {
-        CpuMask::from_bits(self.bits ^ other.bits)
-    }
-
-    /// Get the complement of the mask
-    pub fn not(&self) -> CpuMask {
-        CpuMask::from_bits(!self.bits)
-    }
-
-    /// Iterate over CPUs in the mask
-    pub fn iter(&self) -> CpuIter {
-        CpuIter { bits: self.bits }
-    }
-}
-
-/// Iterator over CPUs in a mask
-pub struct CpuIter {
-    bits: u64,
-}
-
-impl Iterator for CpuIter {
-    type Item = u32;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.bits == 0 {
-            None
-        } else {
-            let cpu = self.bits.trailing_zeros();
-            self.bits &= self.bits - 1; // Clear the lowest set bit
-            Some(cpu)
-        }
-    }
-}
-
-impl Default for CpuMask {
-    fn default() -> Self {
-        Self::new()
-    }
-}
+pub use crate::libs::cpumask::{CpuIter, CpuMask, MAX_CPUS};
 
 /// CPU topology information
 #[derive(Debug, Clone)]
@@ -354,6 +213,86 @@ pub enum LoadBalanceStrategy {
     NumaAware,
 }
 
+/// Default fixed cost of any migration, in percent of the source CPU's load
+pub const DEFAULT_MIGRATION_BASE_COST: u32 = 10;
+
+/// Default extra cost per earlier migration of the same interrupt
+pub const DEFAULT_MIGRATION_REPEAT_COST: u32 = 5;
+
+/// Default number of earlier migrations that add to the cost
+pub const DEFAULT_MIGRATION_REPEAT_LIMIT: u32 = 8;
+
+/// Default extra cost when the source CPU handled an interrupt recently
+pub const DEFAULT_MIGRATION_WARM_COST: u32 = 10;
+
+/// Default time after its last interrupt during which a CPU's cache is warm
+pub const DEFAULT_MIGRATION_WARM_WINDOW_NS: u64 = 10_000_000;
+
+/// Cost model for moving an interrupt to another CPU
+///
+/// Moving a hot interrupt loses the cache state its handler built up on the
+/// old CPU, and an interrupt that keeps bouncing never gets it back. The
+/// balancer only migrates when the projected benefit, the drop in load
+/// from the source CPU to the target in percent of the source load, exceeds
+/// the cost. The cost grows with the interrupt's `migration_count` (capped,
+/// so a clearly misplaced interrupt can always move) and with recent
+/// activity on the source CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MigrationCostModel {
+    /// Fixed cost of any migration, in percent
+    pub base_cost: u32,
+    /// Extra cost per earlier migration, in percent
+    pub repeat_cost: u32,
+    /// Number of earlier migrations that add to the cost
+    pub repeat_limit: u32,
+    /// Extra cost while the source CPU's cache is warm, in percent
+    pub warm_cost: u32,
+    /// Time after its last interrupt during which a CPU's cache is warm
+    pub warm_window_ns: u64,
+}
+
+impl MigrationCostModel {
+    /// Create a model with the default costs
+    pub const fn new() -> Self {
+        Self {
+            base_cost: DEFAULT_MIGRATION_BASE_COST,
+            repeat_cost: DEFAULT_MIGRATION_REPEAT_COST,
+            repeat_limit: DEFAULT_MIGRATION_REPEAT_LIMIT,
+            warm_cost: DEFAULT_MIGRATION_WARM_COST,
+            warm_window_ns: DEFAULT_MIGRATION_WARM_WINDOW_NS,
+        }
+    }
+
+    /// Cost in percent of moving an interrupt migrated `migration_count`
+    /// times off a CPU that has been idle for `source_idle_ns`
+    pub fn cost(&self, migration_count: u32, source_idle_ns: u64) -> u32 {
+        let repeat = migration_count.min(self.repeat_limit) * self.repeat_cost;
+        let warm = if source_idle_ns < self.warm_window_ns { self.warm_cost } else { 0 };
+        self.base_cost + repeat + warm
+    }
+
+    /// Projected benefit in percent of moving load from `source_load` to
+    /// a CPU at `target_load`
+    pub fn benefit(source_load: f64, target_load: f64) -> u32 {
+        if source_load <= 0.0 {
+            return 0;
+        }
+        let gain = (source_load - target_load) / source_load * 100.0;
+        gain.max(0.0) as u32
+    }
+
+    /// Check whether the benefit of a migration outweighs its cost
+    pub fn should_migrate(&self, source_load: f64, target_load: f64, migration_count: u32, source_idle_ns: u64) -> bool {
+        Self::benefit(source_load, target_load) > self.cost(migration_count, source_idle_ns)
+    }
+}
+
+impl Default for MigrationCostModel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Interrupt affinity manager
 pub struct InterruptAffinityManager {
     /// CPU topology
@@ -370,6 +309,8 @@ pub struct InterruptAffinityManager {
     rr_counter: AtomicU32,
     /// Per-IRQ affinity cache
     irq_affinity_cache: SpinLock<Vec<Option<CpuMask>>>,
+    /// Cost model used by the balancer
+    migration_cost: SpinLock<MigrationCostModel>,
 }
 
 /// Affinity hints for interrupts
@@ -430,6 +371,7 @@ impl InterruptAffinityManager {
             strategy: AtomicU32::new(LoadBalanceStrategy::LeastLoaded as u32),
             rr_counter: AtomicU32::new(0),
             irq_affinity_cache: SpinLock::new(vec![None; 1024]),
+            migration_cost: SpinLock::new(MigrationCostModel::new()),
         }
     }
 
@@ -494,6 +436,16 @@ impl InterruptAffinityManager {
         }
     }
 
+    /// Set the migration cost model
+    pub fn set_migration_cost_model(&self, model: MigrationCostModel) {
+        *self.migration_cost.lock() = model;
+    }
+
+    /// Get the migration cost model
+    pub fn migration_cost_model(&self) -> MigrationCostModel {
+        *self.migration_cost.lock()
+    }
+
     /// Calculate interrupt load for a CPU
     pub fn calculate_cpu_load(&self, cpu: u32) -> f64 {
         if cpu as usize >= self.cpu_stats.len() {
@@ -529,11 +481,11 @@ impl InterruptAffinityManager {
         let active_cpus = self.get_active_cpus();
 
         // Apply hints
-        let mut available = online_cpus.and(&active_cpus);
-        available = available.and(&hints.avoid_cpus.not());
+        let mut available = online_cpus.intersection(&active_cpus);
+        available = available.difference(&hints.avoid_cpus);
 
         if !hints.preferred_cpus.is_empty() {
-            available = available.and(&hints.preferred_cpus);
+            available = available.intersection(&hints.preferred_cpus);
         }
 
         if available.is_empty() {
@@ -558,7 +510,7 @@ impl InterruptAffinityManager {
                 // Prefer CPUs in the same package for cache locality
                 if let Some(current_cpu) = crate::arch::cpu::get_current_cpu_id() {
                     let package_cpus = self.topology.get_package_cpus(current_cpu);
-                    let package_available = available.and(&package_cpus);
+                    let package_available = available.intersection(&package_cpus);
 
                     if !package_available.is_empty() {
                         self.get_least_loaded_cpu(&package_available)
@@ -576,7 +528,7 @@ impl InterruptAffinityManager {
                 // Prefer different cores for better parallelism
                 if let Some(current_cpu) = crate::arch::cpu::get_current_cpu_id() {
                     let current_core_cpus = self.topology.get_core_cpus(current_cpu);
-                    let different_cores = available.and(&current_core_cpus.not());
+                    let different_cores = available.difference(&current_core_cpus);
 
                     if !different_cores.is_empty() {
                         self.get_least_loaded_cpu(&different_cores)
@@ -606,7 +558,7 @@ impl InterruptAffinityManager {
         // Check if any CPUs in the mask are online/active
         let online_cpus = self.get_online_cpus();
         let active_cpus = self.get_active_cpus();
-        let available = mask.and(&online_cpus).and(&active_cpus);
+        let available = mask.intersection(&online_cpus).intersection(&active_cpus);
 
         if available.is_empty() && !force {
             return Err(Error::InvalidState);
@@ -717,6 +669,33 @@ impl InterruptAffinityManager {
         self.set_irq_affinity(irq, new_mask, false)
     }
 
+    /// Check whether moving an interrupt from `current` to `optimal` is worth its cost
+    ///
+    /// Only a move from one known CPU to another has a cost; widening or
+    /// narrowing the mask around the CPU that handles the interrupt is free.
+    fn migration_worthwhile(&self, descriptor: &InterruptDescriptor, current: &CpuMask, optimal: &CpuMask) -> bool {
+        let source = descriptor.last_cpu
+            .or_else(|| if current.count() == 1 { current.first() } else { None });
+        let target = if optimal.count() == 1 { optimal.first() } else { None };
+
+        let (Some(source), Some(target)) = (source, target) else {
+            return true;
+        };
+        if source == target || source as usize >= self.cpu_stats.len() {
+            return true;
+        }
+
+        let last_interrupt = self.cpu_stats[source as usize].lock().last_interrupt.load(Ordering::Relaxed);
+        let source_idle_ns = crate::utils::time::timestamp_ns().saturating_sub(last_interrupt);
+
+        self.migration_cost_model().should_migrate(
+            self.calculate_cpu_load(source),
+            self.calculate_cpu_load(target),
+            descriptor.migration_count,
+            source_idle_ns,
+        )
+    }
+
     /// Balance all interrupts
     pub fn balance_interrupts(&self, descriptors: &[InterruptDescriptor]) -> Result<usize> {
         let strategy = self.get_strategy();
@@ -732,7 +711,9 @@ impl InterruptAffinityManager {
 
             let optimal_affinity = self.calculate_optimal_affinity(descriptor);
 
-            if current_affinity != optimal_affinity {
+            if current_affinity != optimal_affinity
+                && self.migration_worthwhile(descriptor, &current_affinity, &optimal_affinity)
+            {
                 if self.set_irq_affinity(descriptor.irq, optimal_affinity, false).is_ok() {
                     migrated += 1;
                 }
@@ -785,6 +766,96 @@ pub struct SystemIrqStats {
     pub total_cpu_load: f64,
 }
 
+/// Default imbalance, in percent above the average CPU load, that triggers balancing
+pub const DEFAULT_BALANCE_THRESHOLD: u32 = 50;
+
+/// Default minimum time between automatic balancing checks
+pub const DEFAULT_BALANCE_INTERVAL_NS: u64 = 1_000_000_000;
+
+/// Decides when automatic affinity balancing should run
+///
+/// Checked from the timer tick: at most one check per interval, and
+/// balancing only runs when the busiest CPU's interrupt load exceeds the
+/// average by more than the threshold.
+pub struct BalanceTrigger {
+    /// Imbalance threshold in percent
+    threshold: AtomicU32,
+    /// Minimum nanoseconds between checks
+    interval_ns: AtomicU64,
+    /// Time of the last check
+    last_check_ns: AtomicU64,
+}
+
+impl BalanceTrigger {
+    /// Create a trigger with the default threshold and interval
+    pub const fn new() -> Self {
+        Self {
+            threshold: AtomicU32::new(DEFAULT_BALANCE_THRESHOLD),
+            interval_ns: AtomicU64::new(DEFAULT_BALANCE_INTERVAL_NS),
+            last_check_ns: AtomicU64::new(0),
+        }
+    }
+
+    /// Set the imbalance threshold in percent above the average load
+    pub fn set_threshold(&self, percent: u32) {
+        self.threshold.store(percent, Ordering::Relaxed);
+    }
+
+    /// Get the imbalance threshold
+    pub fn threshold(&self) -> u32 {
+        self.threshold.load(Ordering::Relaxed)
+    }
+
+    /// Set the minimum time between checks
+    pub fn set_interval(&self, interval_ns: u64) -> Result<()> {
+        if interval_ns == 0 {
+            return Err(Error::InvalidArgument);
+        }
+        self.interval_ns.store(interval_ns, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Get the minimum time between checks
+    pub fn interval(&self) -> u64 {
+        self.interval_ns.load(Ordering::Relaxed)
+    }
+
+    /// Load of the busiest CPU in percent above the average
+    pub fn imbalance(stats: &SystemIrqStats) -> u32 {
+        if stats.avg_cpu_load <= 0.0 {
+            return 0;
+        }
+        let excess = (stats.max_cpu_load - stats.avg_cpu_load) / stats.avg_cpu_load * 100.0;
+        excess.max(0.0) as u32
+    }
+
+    /// Check whether balancing should run at `now_ns`
+    ///
+    /// `stats` is only sampled once the interval has elapsed, so the
+    /// per-CPU statistics are not walked on every tick.
+    pub fn check(&self, now_ns: u64, stats: impl FnOnce() -> SystemIrqStats) -> bool {
+        let last = self.last_check_ns.load(Ordering::Relaxed);
+        if now_ns.saturating_sub(last) < self.interval() {
+            return false;
+        }
+        // Only one CPU checks per interval
+        if self.last_check_ns
+            .compare_exchange(last, now_ns, Ordering::AcqRel, Ordering::Relaxed)
+            .is_err()
+        {
+            return false;
+        }
+
+        Self::imbalance(&stats()) > self.threshold()
+    }
+}
+
+impl Default for BalanceTrigger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Global interrupt affinity manager
 static mut AFFINITY_MANAGER: Option<InterruptAffinityManager> = None;
 static AFFINITY_MANAGER_INIT: SpinLock<bool> = SpinLock::new(false);
@@ -817,4 +888,57 @@ pub fn get() -> Option<&'static InterruptAffinityManager> {
 /// Get the global interrupt affinity manager (panic if not initialized)
 pub fn get_expect() -> &'static InterruptAffinityManager {
     get().expect("Interrupt affinity manager not initialized")
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migration_cost_prevents_thrashing() {
+        let model = MigrationCostModel::new();
+        let bounced = 12;
+
+        // A hot interrupt that keeps moving stays put for a minor imbalance...
+        assert!(!model.should_migrate(1100.0, 900.0, bounced, 0));
+        // ...while one that has never moved is free to follow the imbalance once the cache is cold
+        assert!(model.should_migrate(1100.0, 900.0, 0, DEFAULT_MIGRATION_WARM_WINDOW_NS));
+
+        // A clearly misplaced interrupt still moves, however often it moved before
+        assert!(model.should_migrate(1000.0, 10.0, bounced, 0));
+        assert!(model.should_migrate(1000.0, 10.0, u32::MAX, 0));
+
+        // Moving to a busier CPU is never worthwhile
+        assert_eq!(MigrationCostModel::benefit(100.0, 200.0), 0);
+        assert!(!model.should_migrate(0.0, 0.0, 0, u64::MAX));
+    }
+
+    #[test]
+    fn test_balance_interrupts_places_and_keeps_interrupts() {
+        let manager = InterruptAffinityManager::new(4);
+        manager.set_active_cpus(CpuMask::from_bits(0b1100)).unwrap();
+        let descriptors = [
+            InterruptDescriptor::new(10, IrqType::Hardware, Priority::Normal),
+            InterruptDescriptor::new(11, IrqType::Hardware, Priority::Normal),
+        ];
+
+        manager.set_strategy(LoadBalanceStrategy::None);
+        assert_eq!(manager.balance_interrupts(&descriptors), Ok(0));
+        assert_eq!(manager.get_irq_affinity(10), None);
+
+        // Unplaced interrupts go to the least loaded active CPU
+        manager.set_strategy(LoadBalanceStrategy::LeastLoaded);
+        assert_eq!(manager.balance_interrupts(&descriptors), Ok(2));
+        assert_eq!(manager.get_irq_affinity(10), Some(CpuMask::from_cpu(2)));
+        assert_eq!(manager.get_irq_affinity(11), Some(CpuMask::from_cpu(2)));
+        assert_eq!(manager.balance_interrupts(&descriptors), Ok(0));
+
+        // An interrupt handled on CPU 3 stays there: moving to an equally
+        // loaded CPU gains nothing
+        let mut handled = InterruptDescriptor::new(12, IrqType::Hardware, Priority::Normal);
+        handled.last_cpu = Some(3);
+        manager.set_irq_affinity(12, CpuMask::from_cpu(3), false).unwrap();
+        assert_eq!(manager.balance_interrupts(&[handled]), Ok(0));
+        assert_eq!(manager.get_irq_affinity(12), Some(CpuMask::from_cpu(3)));
+    }
+}
@@ -2221,7 +2221,7 @@ pub fn setup_interrupt_controller() -> Result<()> {
     let controller = create_interrupt_controller()?;
 
     {
-        let mut manager = crate::core::irq::get();
+        let manager = super::manager();
         manager.set_controller(controller);
     }
 
@@ -2276,7 +2276,7 @@ pub fn init_nextgen_interrupts() -> Result<()> {
 
     // Set up interrupt controller
     {
-        let manager = crate::core::irq::get();
+        let manager = super::manager();
         manager.set_controller(aplic);
     }
 
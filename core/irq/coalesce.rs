@@ -0,0 +1,180 @@
+//! Interrupt coalescing
+//!
+//! Devices can ask for their interrupts to be moderated: instead of
+//! running the handler for every event, events are batched until either
+//! `max_events` have accumulated or `max_usec` has passed since the first
+//! one. IRQs without a moderation policy are delivered immediately.
+//!
+//! A level-sensitive line stays asserted until its handler runs, so the
+//! IRQ manager masks it while its events are held back and unmasks it on
+//! delivery; `set_masked` and `take_masked` track which IRQs it masked.
+
+use crate::core::irq::IrqNumber;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+/// Interrupt moderation policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct IrqModeration {
+    /// Deliver once this many events are pending, 0 for no count limit
+    pub max_events: u32,
+    /// Deliver once the oldest pending event is this old, 0 for no time limit
+    pub max_usec: u32,
+}
+
+impl IrqModeration {
+    /// No moderation, every event is delivered immediately
+    pub const NONE: Self = Self { max_events: 0, max_usec: 0 };
+
+    /// Check whether the policy delivers every event immediately
+    pub fn is_immediate(&self) -> bool {
+        self.max_events == 1 || (self.max_events == 0 && self.max_usec == 0)
+    }
+}
+
+/// Coalescing state of one IRQ
+#[derive(Debug, Clone, Copy)]
+struct CoalesceState {
+    /// Policy
+    moderation: IrqModeration,
+    /// Events not yet delivered
+    pending: u32,
+    /// Time of the oldest pending event
+    first_ns: u64,
+    /// Masked until the pending events are delivered
+    masked: bool,
+}
+
+impl CoalesceState {
+    /// Check whether pending events must be delivered at `now_ns`
+    fn due(&self, now_ns: u64) -> bool {
+        let limit = self.moderation;
+        (limit.max_events != 0 && self.pending >= limit.max_events)
+            || (limit.max_usec != 0
+                && now_ns.saturating_sub(self.first_ns) >= limit.max_usec as u64 * 1_000)
+    }
+}
+
+/// Per-IRQ interrupt coalescing
+#[derive(Debug)]
+pub struct IrqCoalescer {
+    /// Moderated IRQs
+    irqs: BTreeMap<IrqNumber, CoalesceState>,
+}
+
+impl IrqCoalescer {
+    /// Create a coalescer with no IRQ moderated
+    pub const fn new() -> Self {
+        Self {
+            irqs: BTreeMap::new(),
+        }
+    }
+
+    /// Set the moderation policy of an IRQ
+    ///
+    /// Events still pending under the old policy are kept.
+    pub fn set_moderation(&mut self, irq: IrqNumber, moderation: IrqModeration) {
+        if moderation.is_immediate() {
+            self.irqs.remove(&irq);
+            return;
+        }
+
+        self.irqs.entry(irq)
+            .and_modify(|state| state.moderation = moderation)
+            .or_insert(CoalesceState { moderation, pending: 0, first_ns: 0, masked: false });
+    }
+
+    /// Check whether an IRQ has a moderation policy
+    pub fn is_moderated(&self, irq: IrqNumber) -> bool {
+        self.irqs.contains_key(&irq)
+    }
+
+    /// Get the moderation policy of an IRQ
+    pub fn moderation(&self, irq: IrqNumber) -> IrqModeration {
+        self.irqs.get(&irq).map_or(IrqModeration::NONE, |state| state.moderation)
+    }
+
+    /// Record an event on an IRQ at `now_ns`
+    ///
+    /// Returns the number of events to deliver now, 0 if the event was
+    /// held back.
+    pub fn event(&mut self, irq: IrqNumber, now_ns: u64) -> u32 {
+        let state = match self.irqs.get_mut(&irq) {
+            Some(state) => state,
+            None => return 1,
+        };
+
+        if state.pending == 0 {
+            state.first_ns = now_ns;
+        }
+        state.pending += 1;
+
+        if state.due(now_ns) {
+            core::mem::take(&mut state.pending)
+        } else {
+            0
+        }
+    }
+
+    /// Record that a moderated IRQ was masked while its events are held back
+    pub fn set_masked(&mut self, irq: IrqNumber) {
+        if let Some(state) = self.irqs.get_mut(&irq) {
+            state.masked = true;
+        }
+    }
+
+    /// Check and clear whether an IRQ was masked by `set_masked`
+    pub fn take_masked(&mut self, irq: IrqNumber) -> bool {
+        self.irqs.get_mut(&irq).is_some_and(|state| core::mem::take(&mut state.masked))
+    }
+
+    /// Take the IRQs whose held-back events have waited `max_usec`
+    pub fn expired(&mut self, now_ns: u64) -> Vec<(IrqNumber, u32)> {
+        self.irqs.iter_mut()
+            .filter(|(_, state)| state.pending != 0 && state.due(now_ns))
+            .map(|(&irq, state)| (irq, core::mem::take(&mut state.pending)))
+            .collect()
+    }
+}
+
+impl Default for IrqCoalescer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_events_batched_by_count_and_time() {
+        let mut coalescer = IrqCoalescer::new();
+        coalescer.set_moderation(33, IrqModeration { max_events: 4, max_usec: 50 });
+
+        // Unmoderated IRQs are delivered every time
+        assert_eq!(coalescer.event(27, 0), 1);
+
+        assert_eq!(coalescer.event(33, 0), 0);
+        assert_eq!(coalescer.event(33, 1_000), 0);
+        assert_eq!(coalescer.event(33, 2_000), 0);
+        assert_eq!(coalescer.event(33, 3_000), 4);
+
+        // A lone event goes out once it has waited max_usec
+        assert_eq!(coalescer.event(33, 10_000), 0);
+        assert!(coalescer.expired(59_999).is_empty());
+        assert_eq!(coalescer.expired(60_000), [(33, 1)]);
+        assert!(coalescer.expired(100_000).is_empty());
+
+        // Masking is tracked until the events are delivered
+        coalescer.set_masked(33);
+        assert!(coalescer.take_masked(33));
+        assert!(!coalescer.take_masked(33));
+        coalescer.set_masked(27);
+        assert!(!coalescer.take_masked(27));
+
+        coalescer.set_moderation(33, IrqModeration::NONE);
+        assert_eq!(coalescer.moderation(33), IrqModeration::NONE);
+        assert_eq!(coalescer.event(33, 0), 1);
+    }
+}
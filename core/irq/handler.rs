@@ -7,6 +7,7 @@ use crate::{Result, Error};
 use crate::core::irq::{IrqNumber, InterruptHandler, InterruptDescriptor};
 use crate::core::sync::SpinLock;
 use crate::core::sched::{self, ThreadId};
+use crate::core::time;
 use crate::core::vmm::{self, VmId, VcpuId};
 use core::sync::atomic::{AtomicU64, Ordering};
 
@@ -89,6 +90,17 @@ unsafe impl Sync for TimerIrqHandler {}
 
 impl IrqHandler for TimerIrqHandler {
     fn handle(&mut self, irq: IrqNumber, _arg: *mut u8) -> Result<()> {
+        // The tick is driven by this CPU's comparator
+        if !time::tick_running() {
+            time::start_tick(self.tick_period_ms as u64 * 1_000_000)?;
+        }
+
+        // Fire due timers and reprogram the comparator before the
+        // scheduler runs; interrupts for timers between ticks stop here
+        if !time::handle_interrupt() {
+            return Ok(());
+        }
+
         let current_time = crate::utils::get_timestamp();
         let tick_count = self.total_ticks.fetch_add(1, Ordering::Relaxed);
 
@@ -100,6 +112,21 @@ impl IrqHandler for TimerIrqHandler {
             crate::error!("Scheduler tick failed: {:?}", e);
         }
 
+        // Feed this CPU's load to the SMP balancer
+        #[cfg(target_arch = "riscv64")]
+        if let Err(e) = crate::arch::riscv64::smp::scheduler::handle_tick() {
+            crate::error!("SMP scheduler tick failed: {:?}", e);
+        }
+
+        // Rebalance interrupt affinity if load has drifted
+        let now_ns = crate::utils::time::timestamp_ns();
+        if let Err(e) = crate::core::irq::balance_tick(now_ns) {
+            crate::error!("Interrupt balancing failed: {:?}", e);
+        }
+
+        // Deliver moderated interrupts that have waited long enough
+        crate::core::irq::flush_coalesced(now_ns);
+
         // Trigger scheduling on current CPU
         let cpu_id = crate::core::cpu_id();
         if let Err(e) = sched::schedule(cpu_id) {
@@ -8,19 +8,26 @@ use crate::arch::common;
 use crate::core::mm::VirtAddr;
 use crate::core::sync::SpinLock;
 use crate::utils::bitmap::Bitmap;
+use core::sync::atomic::{AtomicBool, Ordering};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
 
 pub mod chip;
 pub mod handler;
 pub mod exception;
 pub mod msi;
 pub mod affinity;
+pub mod coalesce;
 
 // Re-export commonly used types
 pub use chip::{Plic, Aplic, Imsic, AplicSourceCfg, AplicMsiConfig, ImsicGlobalConfig, ImsicLocalConfig};
 pub use chip::{AplicStats, ImsicStats, create_aplic, create_imsic, init_nextgen_interrupts};
 pub use msi::{MsiAddress, MsiController, MsiXController, MsiXVector, create_msi_controller, create_msix_controller};
-pub use affinity::{InterruptAffinityManager, CpuMask, CpuTopology, AffinityHints, LoadBalanceStrategy};
+pub use msi::{MsiRemapEntry, MsiRemapKey, MsiRemapTable, deliver_msi, map_msi, unmap_msi};
+pub use affinity::{InterruptAffinityManager, CpuMask, CpuTopology, AffinityHints, LoadBalanceStrategy, MigrationCostModel};
 pub use affinity::{CpuIrqStats, SystemIrqStats, init as init_affinity, get as get_affinity_manager};
+pub use affinity::BalanceTrigger;
+pub use coalesce::{IrqCoalescer, IrqModeration};
 pub use exception::IpiType;
 
 /// Interrupt number type
@@ -85,6 +92,25 @@ pub trait InterruptController {
     fn handle_interrupt(&mut self) -> Option<IrqNumber>;
 }
 
+/// Interrupt trigger flags
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct IrqFlags {
+    /// Level-sensitive (true) or edge-triggered (false)
+    pub level_sensitive: bool,
+}
+
+impl IrqFlags {
+    /// Flags for an edge-triggered interrupt
+    pub const fn edge() -> Self {
+        Self { level_sensitive: false }
+    }
+
+    /// Flags for a level-sensitive interrupt
+    pub const fn level() -> Self {
+        Self { level_sensitive: true }
+    }
+}
+
 /// Interrupt descriptor
 #[derive(Debug, Clone)]
 pub struct InterruptDescriptor {
@@ -106,6 +132,10 @@ pub struct InterruptDescriptor {
     pub handler: Option<InterruptHandler>,
     /// Handler context
     pub context: Option<*mut core::ffi::c_void>,
+    /// Trigger flags
+    pub flags: IrqFlags,
+    /// Line state query for level-sensitive sources
+    pub is_asserted: Option<IrqAssertedFn>,
     /// Last CPU that handled this interrupt
     pub last_cpu: Option<u32>,
     /// Migration count
@@ -125,6 +155,8 @@ impl InterruptDescriptor {
             auto_affinity: true, // Enable auto-affinity by default
             handler: None,
             context: None,
+            flags: IrqFlags::edge(),
+            is_asserted: None,
             last_cpu: None,
             migration_count: 0,
         }
@@ -166,21 +198,61 @@ impl InterruptDescriptor {
         self.handler = Some(handler);
         self.context = context;
     }
+
+    /// Set trigger flags
+    pub fn set_flags(&mut self, flags: IrqFlags) {
+        self.flags = flags;
+    }
+
+    /// Set the line state query used for level-sensitive sources
+    pub fn set_asserted_callback(&mut self, is_asserted: IrqAssertedFn) {
+        self.is_asserted = Some(is_asserted);
+    }
+
+    /// Check whether the source still asserts the line after handling
+    ///
+    /// Edge-triggered interrupts never remain asserted. Level-sensitive
+    /// interrupts without a callback are treated as deasserted.
+    pub fn still_asserted(&self) -> bool {
+        if !self.flags.level_sensitive {
+            return false;
+        }
+        self.is_asserted
+            .map_or(false, |is_asserted| is_asserted(self.irq, self.context))
+    }
 }
 
 /// Interrupt handler function type
 pub type InterruptHandler = fn(irq: IrqNumber, context: Option<*mut core::ffi::c_void>) -> Result<()>;
 
+/// Level-sensitive line state query function type
+pub type IrqAssertedFn = fn(irq: IrqNumber, context: Option<*mut core::ffi::c_void>) -> bool;
+
+/// Maximum number of IRQ descriptors
+const MAX_IRQS: usize = 1024;
+
+/// Number of words in the software pending bitmap
+const SOFT_PENDING_WORDS: usize = MAX_IRQS / 64;
+
+/// Bitmap of enabled IRQs, one bit per IRQ number
+pub type IrqEnableSet = [u64; SOFT_PENDING_WORDS];
+
 /// IRQ manager
 pub struct IrqManager {
     /// Interrupt descriptors
     descriptors: SpinLock<[Option<InterruptDescriptor>; 1024]>,
     /// IRQ bitmap for tracking active IRQs
     irq_bitmap: SpinLock<Bitmap>,
+    /// Software-raised pending IRQs
+    soft_pending: SpinLock<[u64; SOFT_PENDING_WORDS]>,
+    /// IRQs enabled at the controller
+    enabled: SpinLock<IrqEnableSet>,
     /// Statistics
     stats: SpinLock<IrqStats>,
     /// Platform interrupt controller
     controller: SpinLock<Option<Box<dyn InterruptController>>>,
+    /// Per-IRQ interrupt moderation
+    coalescer: SpinLock<IrqCoalescer>,
 }
 
 /// IRQ statistics
@@ -204,8 +276,11 @@ impl IrqManager {
         Self {
             descriptors: SpinLock::new([None; 1024]),
             irq_bitmap: SpinLock::new(unsafe { Bitmap::new(core::ptr::null_mut(), 1024) }),
+            soft_pending: SpinLock::new([0; SOFT_PENDING_WORDS]),
+            enabled: SpinLock::new([0; SOFT_PENDING_WORDS]),
             stats: SpinLock::new(IrqStats::default()),
             controller: SpinLock::new(None),
+            coalescer: SpinLock::new(IrqCoalescer::new()),
         }
     }
 
@@ -227,6 +302,81 @@ impl IrqManager {
         }
     }
 
+    /// Enable an IRQ at the controller
+    pub fn enable_irq(&self, irq: IrqNumber) -> Result<()> {
+        self.set_irq_enabled(irq, true)
+    }
+
+    /// Disable an IRQ at the controller
+    pub fn disable_irq(&self, irq: IrqNumber) -> Result<()> {
+        self.set_irq_enabled(irq, false)
+    }
+
+    /// Program an IRQ's enable bit and record it in the enable set
+    fn set_irq_enabled(&self, irq: IrqNumber, enable: bool) -> Result<()> {
+        let index = irq as usize;
+        if index >= MAX_IRQS {
+            return Err(Error::InvalidArgument);
+        }
+
+        let mut enabled = self.enabled.lock();
+        if let Some(ctrl) = self.controller.lock().as_mut() {
+            if enable {
+                ctrl.enable_irq(irq)?;
+            } else {
+                ctrl.disable_irq(irq)?;
+            }
+        }
+
+        if enable {
+            enabled[index / 64] |= 1u64 << (index % 64);
+        } else {
+            enabled[index / 64] &= !(1u64 << (index % 64));
+        }
+        Ok(())
+    }
+
+    /// Check whether an IRQ is enabled
+    pub fn is_irq_enabled(&self, irq: IrqNumber) -> bool {
+        let irq = irq as usize;
+        irq < MAX_IRQS && self.enabled.lock()[irq / 64] & (1u64 << (irq % 64)) != 0
+    }
+
+    /// Snapshot the set of enabled IRQs
+    pub fn enabled_irqs(&self) -> IrqEnableSet {
+        *self.enabled.lock()
+    }
+
+    /// Reprogram the controller after it lost its state
+    ///
+    /// Used on wakeup from suspend-to-RAM. Trigger types and priorities
+    /// come from the registered descriptors; exactly the IRQs in `enabled`,
+    /// as saved by `enabled_irqs` before sleeping, are enabled again.
+    pub fn restore_controller(&self, enabled: &IrqEnableSet) -> Result<()> {
+        let descriptors = self.descriptors.lock();
+        let mut current = self.enabled.lock();
+        let mut controller = self.controller.lock();
+        if let Some(ctrl) = controller.as_mut() {
+            ctrl.init()?;
+            for descriptor in descriptors.iter().flatten() {
+                ctrl.set_type(descriptor.irq, !descriptor.flags.level_sensitive)?;
+                ctrl.set_priority(descriptor.irq, descriptor.priority)?;
+            }
+
+            for (word, &bits) in enabled.iter().enumerate() {
+                let mut bits = bits;
+                while bits != 0 {
+                    let bit = bits.trailing_zeros() as usize;
+                    ctrl.enable_irq((word * 64 + bit) as IrqNumber)?;
+                    bits &= bits - 1;
+                }
+            }
+        }
+
+        *current = *enabled;
+        Ok(())
+    }
+
     /// Register an interrupt
     pub fn register_irq(&self, descriptor: InterruptDescriptor) -> Result<()> {
         let mut descriptors = self.descriptors.lock();
@@ -265,8 +415,81 @@ impl IrqManager {
         Ok(())
     }
 
+    /// Set the moderation policy of an IRQ
+    pub fn set_irq_moderation(&self, irq: IrqNumber, moderation: IrqModeration) -> Result<()> {
+        if irq as usize >= MAX_IRQS {
+            return Err(Error::InvalidArgument);
+        }
+
+        let unmask = {
+            let mut coalescer = self.coalescer.lock();
+            // Held-back events of a masked line refire once it is unmasked
+            let unmask = moderation.is_immediate() && coalescer.take_masked(irq);
+            coalescer.set_moderation(irq, moderation);
+            unmask
+        };
+        if unmask {
+            self.enable_irq(irq)?;
+        }
+        Ok(())
+    }
+
+    /// Get the moderation policy of an IRQ
+    pub fn irq_moderation(&self, irq: IrqNumber) -> IrqModeration {
+        self.coalescer.lock().moderation(irq)
+    }
+
     /// Handle an interrupt
+    ///
+    /// Events on a moderated IRQ may be held back; they are delivered
+    /// once enough accumulate or by `flush_coalesced`. A held-back
+    /// level-sensitive IRQ is masked until then, as its line stays
+    /// asserted.
     pub fn handle_irq(&self, irq: IrqNumber) -> Result<()> {
+        let held_back = {
+            let mut coalescer = self.coalescer.lock();
+            coalescer.is_moderated(irq)
+                && coalescer.event(irq, crate::utils::time::timestamp_ns()) == 0
+        };
+        if held_back {
+            if self.is_level_sensitive(irq) {
+                self.disable_irq(irq)?;
+                self.coalescer.lock().set_masked(irq);
+            }
+            return Ok(());
+        }
+
+        self.deliver_irq(irq)
+    }
+
+    /// Check whether a registered IRQ is level-sensitive
+    fn is_level_sensitive(&self, irq: IrqNumber) -> bool {
+        self.descriptors.lock()
+            .get(irq as usize)
+            .and_then(|descriptor| descriptor.as_ref())
+            .is_some_and(|descriptor| descriptor.flags.level_sensitive)
+    }
+
+    /// Deliver interrupts held back longer than their moderation allows
+    ///
+    /// Returns the number of IRQs delivered.
+    pub fn flush_coalesced(&self, now_ns: u64) -> usize {
+        let expired = self.coalescer.lock().expired(now_ns);
+        for &(irq, _) in &expired {
+            if let Err(e) = self.deliver_irq(irq) {
+                crate::warn!("Coalesced IRQ {} handler failed: {:?}", irq, e);
+            }
+            if self.coalescer.lock().take_masked(irq) {
+                if let Err(e) = self.enable_irq(irq) {
+                    crate::warn!("Failed to unmask coalesced IRQ {}: {:?}", irq, e);
+                }
+            }
+        }
+        expired.len()
+    }
+
+    /// Run the handler of an interrupt
+    fn deliver_irq(&self, irq: IrqNumber) -> Result<()> {
         let descriptors = self.descriptors.lock();
         let irq = irq as usize;
 
@@ -301,6 +524,71 @@ impl IrqManager {
         }
     }
 
+    /// Raise a software interrupt
+    ///
+    /// The IRQ is latched as pending until `process_soft_irqs` runs.
+    pub fn raise_soft_irq(&self, irq: IrqNumber) -> Result<()> {
+        let irq = irq as usize;
+        if irq >= MAX_IRQS {
+            return Err(Error::InvalidArgument);
+        }
+
+        self.soft_pending.lock()[irq / 64] |= 1u64 << (irq % 64);
+        Ok(())
+    }
+
+    /// Check if a software interrupt is pending
+    pub fn is_soft_pending(&self, irq: IrqNumber) -> bool {
+        let irq = irq as usize;
+        if irq >= MAX_IRQS {
+            return false;
+        }
+
+        self.soft_pending.lock()[irq / 64] & (1u64 << (irq % 64)) != 0
+    }
+
+    /// Dispatch all pending software interrupts
+    ///
+    /// Edge-triggered IRQs fire once per `raise_soft_irq`. Level-sensitive
+    /// IRQs stay pending, and fire again on the next pass, for as long as
+    /// their source reports the line as asserted.
+    ///
+    /// Returns the number of interrupts dispatched.
+    pub fn process_soft_irqs(&self) -> Result<usize> {
+        // Snapshot and clear in one step so IRQs raised by a handler are
+        // kept for the next pass rather than lost
+        let pending = {
+            let mut soft_pending = self.soft_pending.lock();
+            let snapshot = *soft_pending;
+            *soft_pending = [0; SOFT_PENDING_WORDS];
+            snapshot
+        };
+
+        let mut handled = 0;
+        for (word_idx, &word) in pending.iter().enumerate() {
+            let mut bits = word;
+            while bits != 0 {
+                let bit = bits.trailing_zeros() as usize;
+                bits &= !(1u64 << bit);
+                let irq = (word_idx * 64 + bit) as IrqNumber;
+
+                if let Err(e) = self.handle_irq(irq) {
+                    crate::warn!("Software IRQ {} handler failed: {:?}", irq, e);
+                }
+                handled += 1;
+
+                // Re-queue level-sensitive sources that are still asserted
+                let still_asserted = self.get_irq(irq)
+                    .map_or(false, |descriptor| descriptor.still_asserted());
+                if still_asserted {
+                    self.raise_soft_irq(irq)?;
+                }
+            }
+        }
+
+        Ok(handled)
+    }
+
     /// Get IRQ statistics
     pub fn get_stats(&self) -> IrqStats {
         *self.stats.lock()
@@ -471,11 +759,31 @@ impl IrqManager {
 /// Global IRQ manager
 static IRQ_MANAGER: IrqManager = IrqManager::new();
 
+/// Set once interrupt handling is initialized
+static IRQ_INIT: AtomicBool = AtomicBool::new(false);
+
 /// Get the global IRQ manager
-pub fn get() -> &'static IrqManager {
+///
+/// Fails with `NotInitialized` until `init()` has completed, so code that
+/// runs too early in boot gets an error instead of touching a manager with
+/// no controller behind it.
+pub fn get() -> Result<&'static IrqManager> {
+    if !IRQ_INIT.load(Ordering::Acquire) {
+        return Err(Error::NotInitialized);
+    }
+    Ok(&IRQ_MANAGER)
+}
+
+/// Get the global IRQ manager while interrupt handling is being initialized
+fn manager() -> &'static IrqManager {
     &IRQ_MANAGER
 }
 
+/// Check whether interrupt handling is initialized
+pub fn is_initialized() -> bool {
+    IRQ_INIT.load(Ordering::Acquire)
+}
+
 /// Initialize interrupt handling
 pub fn init() -> Result<()> {
     crate::info!("Initializing interrupt handling");
@@ -499,6 +807,7 @@ pub fn init() -> Result<()> {
         affinity_mgr.set_strategy(LoadBalanceStrategy::PackageAware);
     }
 
+    IRQ_INIT.store(true, Ordering::Release);
     crate::info!("Interrupt handling initialized successfully");
     Ok(())
 }
@@ -524,13 +833,53 @@ pub fn init_with_cpus(num_cpus: u32) -> Result<()> {
         affinity_mgr.set_strategy(LoadBalanceStrategy::PackageAware);
     }
 
+    IRQ_INIT.store(true, Ordering::Release);
     crate::info!("Interrupt handling initialized successfully");
     Ok(())
 }
 
 /// Perform periodic interrupt affinity balancing
 pub fn perform_affinity_balancing() -> Result<usize> {
-    get().balance_interrupts()
+    get()?.balance_interrupts()
+}
+
+/// Automatic affinity balancing trigger
+static BALANCE_TRIGGER: BalanceTrigger = BalanceTrigger::new();
+
+/// Set the load imbalance, in percent above average, that triggers balancing
+pub fn set_balance_threshold(percent: u32) {
+    BALANCE_TRIGGER.set_threshold(percent);
+}
+
+/// Set the minimum time between automatic balancing checks
+pub fn set_balance_interval(interval_ns: u64) -> Result<()> {
+    BALANCE_TRIGGER.set_interval(interval_ns)
+}
+
+/// Balance interrupt affinity from the timer tick if load is imbalanced
+///
+/// Returns the number of interrupts migrated.
+pub fn balance_tick(now_ns: u64) -> Result<usize> {
+    let affinity_mgr = match affinity::get() {
+        Some(mgr) => mgr,
+        None => return Ok(0),
+    };
+
+    if BALANCE_TRIGGER.check(now_ns, || affinity_mgr.get_system_stats()) {
+        perform_affinity_balancing()
+    } else {
+        Ok(0)
+    }
+}
+
+/// Set the moderation policy of an IRQ
+pub fn set_irq_moderation(irq: IrqNumber, moderation: IrqModeration) -> Result<()> {
+    get()?.set_irq_moderation(irq, moderation)
+}
+
+/// Deliver moderated interrupts whose time limit has passed
+pub fn flush_coalesced(now_ns: u64) -> usize {
+    get().map_or(0, |manager| manager.flush_coalesced(now_ns))
 }
 
 /// Get interrupt affinity statistics
@@ -616,43 +965,317 @@ pub fn are_interrupts_enabled() -> bool {
 pub fn send_ipi(cpu_id: usize, ipi_type: IpiType) -> Result<()> {
     crate::debug!("Sending IPI {:?} to CPU {}", ipi_type, cpu_id);
 
-    // TODO: Implement IPI sending
-    match ipi_type {
-        crate::core::irq::exception::IpiType::Reschedule => {
-            // Trigger scheduler tick
-            crate::core::sched::handle_tick()?;
-        }
-        _ => {
-            // TODO: Implement other IPI types
+    #[cfg(target_arch = "riscv64")]
+    {
+        riscv_send_ipi(cpu_id, ipi_type)
+    }
+
+    #[cfg(not(target_arch = "riscv64"))]
+    {
+        // TODO: Implement remote IPI delivery
+        match ipi_type {
+            crate::core::irq::exception::IpiType::Reschedule => {
+                // Trigger scheduler tick
+                crate::core::sched::handle_tick()?;
+            }
+            _ => {
+                // TODO: Implement other IPI types
+            }
         }
+
+        Ok(())
     }
+}
 
-    Ok(())
+/// Deliver an IPI to a remote hart
+///
+/// The IPI type is latched in the target's per-CPU IPI state, then the
+/// hart is kicked through its CLINT MSIP register so it takes a software
+/// interrupt and drains the pending set. Falls back to the SBI IPI
+/// extension when no CLINT is mapped.
+#[cfg(target_arch = "riscv64")]
+fn riscv_send_ipi(cpu_id: usize, ipi_type: IpiType) -> Result<()> {
+    use crate::arch::riscv64::{platform::clint, smp};
+
+    let riscv_type = match ipi_type {
+        IpiType::Reschedule => smp::ipi::IpiType::Reschedule,
+        IpiType::Stop => smp::ipi::IpiType::Stop,
+        IpiType::FunctionCall => smp::ipi::IpiType::FunctionCall,
+        IpiType::TlbFlush => smp::ipi::IpiType::TlbShootdown,
+    };
+
+    let state = smp::ipi::get_cpu_ipi_state(cpu_id).ok_or(Error::InvalidArgument)?;
+    state.set_pending(riscv_type);
+
+    if clint::get_clint().is_some() {
+        clint::send_ipi(cpu_id as u32).map_err(|_| Error::InvalidArgument)
+    } else {
+        smp::sbi::sbi_send_ipi(1 << cpu_id, 0).map_err(|_| Error::ResourceUnavailable)
+    }
 }
 
-/// Broadcast an IPI to all CPUs
-pub fn broadcast_ipi(ipi_type: crate::core::irq::exception::IpiType) -> Result<()> {
+/// Broadcast an IPI to all online CPUs
+///
+/// When `exclude_self` is set the calling CPU is skipped.
+pub fn broadcast_ipi(ipi_type: IpiType, exclude_self: bool) -> Result<()> {
     crate::debug!("Broadcasting IPI {:?}", ipi_type);
 
-    // TODO: Implement IPI broadcasting
-    match ipi_type {
-        crate::core::irq::exception::IpiType::Reschedule => {
-            // Trigger scheduler tick on all CPUs
-            for cpu_id in 0..64 {
-                if let Err(e) = send_ipi(cpu_id, ipi_type) {
-                    crate::error!("Failed to send IPI to CPU {}: {:?}", cpu_id, e);
-                }
-            }
-        }
-        _ => {
-            // TODO: Implement other IPI types
-        }
-    }
+    let online = crate::arch::cpu::get_online_cpu_mask();
+    let self_cpu = crate::arch::cpu::get_current_cpu_id().unwrap_or(0) as usize;
+    broadcast_ipi_to(online, self_cpu, exclude_self, ipi_type, send_ipi);
 
     Ok(())
 }
 
+/// Send `ipi_type` to every CPU in `online` through `send`
+fn broadcast_ipi_to<F>(online: CpuMask, self_cpu: usize, exclude_self: bool, ipi_type: IpiType, mut send: F)
+where
+    F: FnMut(usize, IpiType) -> Result<()>,
+{
+    let targets = online.iter()
+        .map(|cpu| cpu as usize)
+        .filter(|&cpu| !(exclude_self && cpu == self_cpu));
+
+    for cpu_id in targets {
+        if let Err(e) = send(cpu_id, ipi_type) {
+            crate::error!("Failed to send IPI to CPU {}: {:?}", cpu_id, e);
+        }
+    }
+}
+
 /// Get interrupt statistics
-pub fn get_stats() -> IrqStats {
-    get().get_stats()
-}
\ No newline at end of file
+pub fn get_stats() -> Result<IrqStats> {
+    Ok(get()?.get_stats())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+    static EDGE_FIRED: AtomicU32 = AtomicU32::new(0);
+    static LEVEL_FIRED: AtomicU32 = AtomicU32::new(0);
+    static LEVEL_ASSERTED: AtomicBool = AtomicBool::new(false);
+
+    fn edge_handler(_irq: IrqNumber, _context: Option<*mut core::ffi::c_void>) -> Result<()> {
+        EDGE_FIRED.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn level_handler(_irq: IrqNumber, _context: Option<*mut core::ffi::c_void>) -> Result<()> {
+        LEVEL_FIRED.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn level_asserted(_irq: IrqNumber, _context: Option<*mut core::ffi::c_void>) -> bool {
+        LEVEL_ASSERTED.load(Ordering::SeqCst)
+    }
+
+    #[test]
+    fn test_edge_irq_fires_once_per_trigger() {
+        let manager = IrqManager::new();
+        let mut descriptor = InterruptDescriptor::new(10, IrqType::Software, Priority::Normal);
+        descriptor.set_handler(edge_handler, None);
+        manager.register_irq(descriptor).unwrap();
+
+        manager.raise_soft_irq(10).unwrap();
+        assert_eq!(manager.process_soft_irqs().unwrap(), 1);
+        assert_eq!(EDGE_FIRED.load(Ordering::SeqCst), 1);
+        assert!(!manager.is_soft_pending(10));
+
+        // No new trigger, no new interrupt
+        assert_eq!(manager.process_soft_irqs().unwrap(), 0);
+        assert_eq!(EDGE_FIRED.load(Ordering::SeqCst), 1);
+
+        manager.raise_soft_irq(10).unwrap();
+        manager.process_soft_irqs().unwrap();
+        assert_eq!(EDGE_FIRED.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_level_irq_refires_until_cleared() {
+        let manager = IrqManager::new();
+        let mut descriptor = InterruptDescriptor::new(20, IrqType::Software, Priority::Normal);
+        descriptor.set_handler(level_handler, None);
+        descriptor.set_flags(IrqFlags::level());
+        descriptor.set_asserted_callback(level_asserted);
+        manager.register_irq(descriptor).unwrap();
+
+        LEVEL_ASSERTED.store(true, Ordering::SeqCst);
+        manager.raise_soft_irq(20).unwrap();
+
+        manager.process_soft_irqs().unwrap();
+        assert!(manager.is_soft_pending(20));
+        manager.process_soft_irqs().unwrap();
+        assert!(manager.is_soft_pending(20));
+        assert_eq!(LEVEL_FIRED.load(Ordering::SeqCst), 2);
+
+        // Deassert the source: one final dispatch, then pending clears
+        LEVEL_ASSERTED.store(false, Ordering::SeqCst);
+        manager.process_soft_irqs().unwrap();
+        assert!(!manager.is_soft_pending(20));
+        assert_eq!(LEVEL_FIRED.load(Ordering::SeqCst), 3);
+
+        assert_eq!(manager.process_soft_irqs().unwrap(), 0);
+        assert_eq!(LEVEL_FIRED.load(Ordering::SeqCst), 3);
+    }
+
+    /// IRQs enabled at the mock controller
+    static MOCK_ENABLED: SpinLock<Vec<IrqNumber>> = SpinLock::new(Vec::new());
+
+    /// Controller that forgets its state on `init`, like one that slept
+    struct MockController;
+
+    impl InterruptController for MockController {
+        fn init(&mut self) -> Result<()> {
+            MOCK_ENABLED.lock().clear();
+            Ok(())
+        }
+        fn enable_irq(&mut self, irq: IrqNumber) -> Result<()> {
+            MOCK_ENABLED.lock().push(irq);
+            Ok(())
+        }
+        fn disable_irq(&mut self, irq: IrqNumber) -> Result<()> {
+            MOCK_ENABLED.lock().retain(|&enabled| enabled != irq);
+            Ok(())
+        }
+        fn ack_irq(&mut self, _irq: IrqNumber) -> Result<()> { Ok(()) }
+        fn set_priority(&mut self, _irq: IrqNumber, _priority: Priority) -> Result<()> { Ok(()) }
+        fn set_type(&mut self, _irq: IrqNumber, _edge_triggered: bool) -> Result<()> { Ok(()) }
+        fn get_pending_irqs(&self) -> u64 { 0 }
+        fn is_pending(&self, _irq: IrqNumber) -> bool { false }
+        fn handle_interrupt(&mut self) -> Option<IrqNumber> { None }
+    }
+
+    #[test]
+    fn test_restore_controller_enables_saved_set() {
+        let manager = IrqManager::new();
+        manager.set_controller(Box::new(MockController));
+        for irq in [3, 70, 71] {
+            manager.register_irq(InterruptDescriptor::new(irq, IrqType::Hardware, Priority::Normal)).unwrap();
+        }
+        manager.enable_irq(3).unwrap();
+        manager.enable_irq(70).unwrap();
+        manager.enable_irq(71).unwrap();
+        manager.disable_irq(70).unwrap();
+
+        let saved = manager.enabled_irqs();
+        manager.with_controller(|ctrl| ctrl.init()).unwrap().unwrap();
+        assert!(MOCK_ENABLED.lock().is_empty());
+
+        // Registered but disabled IRQ 70 stays disabled
+        manager.restore_controller(&saved).unwrap();
+        assert_eq!(*MOCK_ENABLED.lock(), [3, 71]);
+        assert!(manager.is_irq_enabled(71));
+        assert!(!manager.is_irq_enabled(70));
+    }
+
+    static MODERATED_FIRED: AtomicU32 = AtomicU32::new(0);
+
+    fn moderated_handler(_irq: IrqNumber, _context: Option<*mut core::ffi::c_void>) -> Result<()> {
+        MODERATED_FIRED.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    #[test]
+    fn test_held_back_level_irq_masked_until_delivered() {
+        let manager = IrqManager::new();
+        let mut descriptor = InterruptDescriptor::new(40, IrqType::Hardware, Priority::Normal);
+        descriptor.set_handler(moderated_handler, None);
+        descriptor.set_flags(IrqFlags::level());
+        manager.register_irq(descriptor).unwrap();
+        manager.enable_irq(40).unwrap();
+        manager.set_irq_moderation(40, IrqModeration { max_events: 4, max_usec: 50 }).unwrap();
+
+        // Held back: the asserted line must not keep interrupting
+        manager.handle_irq(40).unwrap();
+        assert_eq!(MODERATED_FIRED.load(Ordering::SeqCst), 0);
+        assert!(!manager.is_irq_enabled(40));
+
+        // Delivered once max_usec has passed, and unmasked again
+        assert_eq!(manager.flush_coalesced(u64::MAX), 1);
+        assert_eq!(MODERATED_FIRED.load(Ordering::SeqCst), 1);
+        assert!(manager.is_irq_enabled(40));
+
+        // Dropping the policy while masked unmasks the line
+        manager.handle_irq(40).unwrap();
+        assert!(!manager.is_irq_enabled(40));
+        manager.set_irq_moderation(40, IrqModeration::NONE).unwrap();
+        assert!(manager.is_irq_enabled(40));
+    }
+
+    #[test]
+    fn test_raise_soft_irq_out_of_range() {
+        let manager = IrqManager::new();
+        assert_eq!(manager.raise_soft_irq(MAX_IRQS as IrqNumber), Err(Error::InvalidArgument));
+    }
+
+    #[test]
+    fn test_irq_api_before_init_fails() {
+        // Tests never run init(), so the global manager is unavailable
+        assert!(!is_initialized());
+        assert!(matches!(get(), Err(Error::NotInitialized)));
+        assert_eq!(
+            set_irq_moderation(33, IrqModeration { max_events: 4, max_usec: 0 }),
+            Err(Error::NotInitialized)
+        );
+        assert_eq!(perform_affinity_balancing(), Err(Error::NotInitialized));
+        assert_eq!(flush_coalesced(0), 0);
+        assert!(get_stats().is_err());
+    }
+
+    #[test]
+    fn test_broadcast_ipi_online_targets() {
+        // CPUs 0, 2 and 5 online; caller is CPU 2
+        let online = CpuMask::from_bits(0b10_0101);
+
+        let mut hit = CpuMask::new();
+        broadcast_ipi_to(online, 2, false, IpiType::Reschedule, |cpu, _| {
+            hit.set(cpu as u32);
+            Ok(())
+        });
+        assert_eq!(hit, online);
+
+        let mut hit = CpuMask::new();
+        broadcast_ipi_to(online, 2, true, IpiType::Reschedule, |cpu, ipi| {
+            assert_eq!(ipi, IpiType::Reschedule);
+            hit.set(cpu as u32);
+            Ok(())
+        });
+        assert_eq!(hit, CpuMask::from_bits(0b10_0001));
+    }
+
+    fn irq_load(avg: f64, max: f64) -> SystemIrqStats {
+        SystemIrqStats {
+            active_cpus: 4,
+            avg_cpu_load: avg,
+            max_cpu_load: max,
+            total_cpu_load: avg * 4.0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_balance_triggers_only_on_imbalance() {
+        let trigger = BalanceTrigger::new();
+        trigger.set_threshold(50);
+        trigger.set_interval(1_000).unwrap();
+        assert_eq!(trigger.set_interval(0), Err(Error::InvalidArgument));
+
+        // One CPU takes 10% more than average: left alone
+        assert!(!trigger.check(1_000, || irq_load(1000.0, 1100.0)));
+
+        // One CPU takes 3x the average: rebalance
+        assert_eq!(BalanceTrigger::imbalance(&irq_load(1000.0, 3000.0)), 200);
+        assert!(trigger.check(2_000, || irq_load(1000.0, 3000.0)));
+
+        // Still imbalanced, but the interval has not elapsed
+        let mut sampled = false;
+        assert!(!trigger.check(2_500, || { sampled = true; irq_load(1000.0, 3000.0) }));
+        assert!(!sampled);
+        assert!(trigger.check(3_000, || irq_load(1000.0, 3000.0)));
+
+        // Idle system never triggers
+        assert!(!trigger.check(4_000, SystemIrqStats::default));
+    }
+}
@@ -4,11 +4,13 @@
 //! enabling direct interrupt delivery through memory writes.
 
 use crate::{Result, Error};
-use crate::core::irq::IrqNumber;
+use crate::core::irq::{IrqNumber, InterruptDescriptor, IrqType, Priority};
 use crate::core::mm::{PhysAddr, VirtAddr, PAGE_SIZE};
 use crate::core::sync::SpinLock;
+use crate::core::vmm::{VmId, VcpuId};
 use alloc::vec::Vec;
 use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
 
 /// Simple volatile memory access helper
 fn read_volatile_u32(addr: VirtAddr) -> u32 {
@@ -474,4 +476,234 @@ pub fn create_standard_msi_address(irq: IrqNumber, vector: u8) -> MsiAddress {
     let addr = 0xfee0_0000 + (vector as u64 * 16);
     let data = (irq << 8) | vector as u32;
     MsiAddress::new(addr, data, vector)
-}
\ No newline at end of file
+}
+
+/// Identifies an incoming MSI: the address it was written to, which
+/// encodes the destination, and the host vector from its data
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MsiRemapKey {
+    /// MSI address
+    pub addr: PhysAddr,
+    /// Host vector, bits [7:0] of the MSI data
+    pub host_vector: IrqNumber,
+}
+
+impl MsiRemapKey {
+    /// Key of an MSI write of `data` to `addr`
+    pub fn new(addr: PhysAddr, data: u32) -> Self {
+        let msi = MsiAddress::new(addr, data, data as u8);
+        Self { addr, host_vector: msi.extract_irq_id() }
+    }
+}
+
+/// Where a remapped host MSI is delivered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MsiRemapEntry {
+    /// Target VM
+    pub vm_id: VmId,
+    /// Target vCPU
+    pub vcpu_id: VcpuId,
+    /// Vector injected into the vCPU's virtual interrupt controller
+    pub guest_vector: u32,
+}
+
+/// Interrupt remapping table
+///
+/// Maps host MSIs of passthrough and virtio MSI-X devices to the guest
+/// interrupt they stand for. An MSI with no entry for both its address
+/// and vector is dropped and counted, never delivered to the host.
+#[derive(Debug, Default)]
+pub struct MsiRemapTable {
+    /// Incoming MSI -> guest target
+    entries: BTreeMap<MsiRemapKey, MsiRemapEntry>,
+    /// MSIs remapped to a guest
+    remapped: u64,
+    /// MSIs dropped for having no valid entry
+    dropped: u64,
+}
+
+impl MsiRemapTable {
+    /// Create an empty table
+    pub const fn new() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            remapped: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Route an incoming MSI to a guest vCPU
+    ///
+    /// Fails with `Error::ResourceBusy` if the MSI is already remapped.
+    pub fn map(&mut self, key: MsiRemapKey, entry: MsiRemapEntry) -> Result<()> {
+        if self.entries.contains_key(&key) {
+            return Err(Error::ResourceBusy);
+        }
+        self.entries.insert(key, entry);
+        Ok(())
+    }
+
+    /// Remove the entry of `key`
+    pub fn unmap(&mut self, key: MsiRemapKey) -> Option<MsiRemapEntry> {
+        self.entries.remove(&key)
+    }
+
+    /// Remove every entry targeting a VM
+    ///
+    /// Returns the keys of the removed entries.
+    pub fn unmap_vm(&mut self, vm_id: VmId) -> Vec<MsiRemapKey> {
+        let keys: Vec<MsiRemapKey> = self.entries.iter()
+            .filter(|(_, entry)| entry.vm_id == vm_id)
+            .map(|(key, _)| *key)
+            .collect();
+        for key in &keys {
+            self.entries.remove(key);
+        }
+        keys
+    }
+
+    /// Look up the entry of `key`
+    pub fn lookup(&self, key: MsiRemapKey) -> Option<MsiRemapEntry> {
+        self.entries.get(&key).copied()
+    }
+
+    /// Resolve an MSI write of `data` to `addr` to its guest target
+    ///
+    /// Returns `None`, counting the MSI as dropped, if it has no entry.
+    pub fn route(&mut self, addr: PhysAddr, data: u32) -> Option<MsiRemapEntry> {
+        let valid = MsiAddress::new(addr, data, data as u8).is_valid();
+        match self.lookup(MsiRemapKey::new(addr, data)) {
+            Some(entry) if valid => {
+                self.remapped += 1;
+                Some(entry)
+            }
+            _ => {
+                self.dropped += 1;
+                None
+            }
+        }
+    }
+
+    /// Number of MSIs remapped to a guest
+    pub fn remapped(&self) -> u64 {
+        self.remapped
+    }
+
+    /// Number of MSIs dropped for having no valid entry
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+/// Global interrupt remapping table
+static MSI_REMAP: SpinLock<MsiRemapTable> = SpinLock::new(MsiRemapTable::new());
+
+/// Route the host MSI `data` written to `addr` to `guest_vector` on a vCPU
+///
+/// Claims the host vector in the IRQ manager so the MSI is delivered
+/// through `deliver_msi` instead of a host handler.
+pub fn map_msi(addr: PhysAddr, data: u32, vm_id: VmId, vcpu_id: VcpuId, guest_vector: u32) -> Result<()> {
+    let key = MsiRemapKey::new(addr, data);
+    MSI_REMAP.lock().map(key, MsiRemapEntry { vm_id, vcpu_id, guest_vector })?;
+
+    let mut descriptor = InterruptDescriptor::new(key.host_vector, IrqType::Hardware, Priority::High);
+    descriptor.set_handler(remapped_msi_handler, Some(addr as *mut core::ffi::c_void));
+    let claimed = crate::core::irq::get().and_then(|manager| {
+        manager.register_irq(descriptor)?;
+        manager.enable_irq(key.host_vector)
+    });
+    if let Err(err) = claimed {
+        MSI_REMAP.lock().unmap(key);
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// Stop remapping the host MSI `data` written to `addr`
+pub fn unmap_msi(addr: PhysAddr, data: u32) -> Option<MsiRemapEntry> {
+    let key = MsiRemapKey::new(addr, data);
+    let entry = MSI_REMAP.lock().unmap(key)?;
+    release_host_vector(key.host_vector);
+    Some(entry)
+}
+
+/// Drop every remapping entry of a VM being destroyed
+pub fn unmap_vm_msis(vm_id: VmId) {
+    let keys = MSI_REMAP.lock().unmap_vm(vm_id);
+    for key in keys {
+        release_host_vector(key.host_vector);
+    }
+}
+
+/// Return a host vector claimed by `map_msi` to the IRQ manager
+fn release_host_vector(host_vector: IrqNumber) {
+    if let Ok(manager) = crate::core::irq::get() {
+        let _ = manager.disable_irq(host_vector);
+        let _ = manager.unregister_irq(host_vector);
+    }
+}
+
+/// IRQ handler of a host vector claimed by `map_msi`
+///
+/// The context carries the MSI address the vector was mapped for.
+fn remapped_msi_handler(irq: IrqNumber, context: Option<*mut core::ffi::c_void>) -> Result<()> {
+    let addr = context.map_or(0, |addr| addr as PhysAddr);
+    deliver_msi(addr, irq)
+}
+
+/// Deliver an incoming MSI write to the vCPU it is remapped to
+///
+/// The vCPU is injected after the table lock is released. Unmapped MSIs
+/// are dropped and counted in `msi_remap_dropped()`.
+pub fn deliver_msi(addr: PhysAddr, data: u32) -> Result<()> {
+    let entry = MSI_REMAP.lock().route(addr, data);
+    match entry {
+        Some(entry) => crate::core::vmm::inject_interrupt(entry.vm_id, entry.vcpu_id, entry.guest_vector),
+        None => {
+            crate::debug!("Dropping unmapped MSI addr={:#x} data={:#x}", addr, data);
+            Ok(())
+        }
+    }
+}
+
+/// Number of incoming MSIs dropped for having no remapping entry
+pub fn msi_remap_dropped() -> u64 {
+    MSI_REMAP.lock().dropped()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remapped_msi_reaches_vcpu() {
+        let mut table = MsiRemapTable::new();
+        let entry = MsiRemapEntry { vm_id: 2, vcpu_id: 1, guest_vector: 0x31 };
+        let key = MsiRemapKey::new(0xfee0_0000, 0x41);
+        table.map(key, entry).unwrap();
+        assert_eq!(table.map(key, entry), Err(Error::ResourceBusy));
+
+        assert_eq!(table.route(0xfee0_0000, 0x41), Some(entry));
+        assert_eq!(table.remapped(), 1);
+        assert_eq!(table.dropped(), 0);
+    }
+
+    #[test]
+    fn test_unmapped_msi_is_dropped() {
+        let mut table = MsiRemapTable::new();
+        let entry = MsiRemapEntry { vm_id: 2, vcpu_id: 0, guest_vector: 0x20 };
+        table.map(MsiRemapKey::new(0xfee0_0000, 0x41), entry).unwrap();
+
+        assert_eq!(table.route(0xfee0_0000, 0x42), None);
+        assert_eq!(table.dropped(), 1);
+
+        // Same vector sent to another destination
+        assert_eq!(table.route(0xfee0_1000, 0x41), None);
+        assert_eq!(table.dropped(), 2);
+
+        assert_eq!(table.unmap_vm(2), [MsiRemapKey::new(0xfee0_0000, 0x41)]);
+        assert_eq!(table.route(0xfee0_0000, 0x41), None);
+        assert_eq!(table.dropped(), 3);
+        assert_eq!(table.remapped(), 0);
+    }
+}
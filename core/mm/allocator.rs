@@ -7,9 +7,33 @@
 //!
 //! This module provides a unified allocation interface that automatically
 //! chooses the best allocator based on size and usage patterns.
-
-use crate::core::mm::{PAGE_SIZE, buddy, slab, frame};
+//!
+//! Reclaimable page allocations may hold guest data, so when released they
+//! are held back from the buddy and frame allocators until a background
+//! scrubber has zeroed them. A held-back allocation can be reused before
+//! then, but is zeroed first when it goes to a different owner VM. The
+//! scrubber runs from a timer interrupt, so it only zeroes; the zeroed
+//! memory is freed by the next allocation or release.
+//!
+//! An automatic allocation that fails everywhere shrinks the slab caches
+//! and signals memory pressure (see `pressure`) before being retried once.
+//!
+//! As a hardening measure, freed allocations of at least
+//! `QUARANTINE_MIN_SIZE` bytes can be quarantined (see `set_quarantine`):
+//! they go back to their allocator only after a number of further
+//! allocations, so a dangling pointer keeps pointing at unused memory for
+//! longer instead of at a new object.
+//!
+//! The underlying allocators only guarantee `MIN_ALIGNMENT` (slab) or page
+//! alignment (buddy, frame). A request for a larger alignment than its
+//! strategy provides gets `alignment` extra bytes and the pointer is
+//! rounded up, with the block's start stored in the word just before it.
+//! Such allocations must be freed with `release` and the same config, which
+//! finds the whole block again.
+
+use crate::core::mm::{PAGE_SIZE, buddy, slab, frame, pressure};
 use crate::core::sync::SpinLock;
+use crate::core::vmm::VmId;
 use core::ptr::NonNull;
 
 /// Allocation strategy
@@ -34,10 +58,12 @@ pub struct AllocationConfig {
     pub alignment: usize,
     /// Whether to zero the memory
     pub zero: bool,
-    /// Whether memory is reclaimable
+    /// Whether memory is reclaimable, and scrubbed when released
     pub reclaimable: bool,
     /// Purpose tag for debugging
     pub tag: &'static str,
+    /// VM the memory is for, `None` for the hypervisor itself
+    pub owner: Option<VmId>,
 }
 
 impl Default for AllocationConfig {
@@ -48,6 +74,7 @@ impl Default for AllocationConfig {
             zero: false,
             reclaimable: true,
             tag: "general",
+            owner: None,
         }
     }
 }
@@ -75,10 +102,304 @@ pub struct AllocationStats {
     pub fragmentation: f64,
 }
 
+/// Maximum number of live allocations served by a fallback strategy
+pub const MAX_FALLBACK_ALLOCATIONS: usize = 256;
+
+/// Allocations served by a strategy other than the one `Auto` would pick
+///
+/// `Auto` deallocation guesses the strategy from the size, so fallback
+/// allocations are recorded here to be freed by the allocator that
+/// actually served them. The table is fixed-size because the unified
+/// allocator backs the global heap and cannot allocate for itself.
+struct FallbackTable {
+    entries: [Option<(usize, AllocationStrategy)>; MAX_FALLBACK_ALLOCATIONS],
+}
+
+impl FallbackTable {
+    const fn new() -> Self {
+        Self { entries: [None; MAX_FALLBACK_ALLOCATIONS] }
+    }
+
+    /// Record a fallback allocation, returns false if the table is full
+    fn record(&mut self, addr: usize, strategy: AllocationStrategy) -> bool {
+        match self.entries.iter_mut().find(|entry| entry.is_none()) {
+            Some(entry) => {
+                *entry = Some((addr, strategy));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove a fallback allocation, returning the strategy that served it
+    fn take(&mut self, addr: usize) -> Option<AllocationStrategy> {
+        let entry = self.entries.iter_mut()
+            .find(|entry| matches!(entry, Some((a, _)) if *a == addr))?;
+        entry.take().map(|(_, strategy)| strategy)
+    }
+}
+
+/// Alignment every allocation has without further work
+pub const MIN_ALIGNMENT: usize = 8;
+
+/// Bytes stored in front of a padded over-aligned allocation
+const ALIGN_HEADER_SIZE: usize = core::mem::size_of::<usize>();
+
+/// Block size for `size` bytes aligned to `align` behind the header
+///
+/// Blocks start `MIN_ALIGNMENT`-aligned, so rounding up past the header
+/// skips at most `align` bytes.
+fn padded_size(size: usize, align: usize) -> Option<usize> {
+    size.checked_add(align)
+}
+
+/// Aligned pointer inside the padded block at `base`
+///
+/// `base` is stored in the word just before the returned pointer.
+///
+/// # Safety
+/// `base` must start a live block of `padded_size(_, align)` bytes.
+unsafe fn place_aligned(base: usize, align: usize) -> usize {
+    let addr = (base + ALIGN_HEADER_SIZE + align - 1) & !(align - 1);
+    ((addr - ALIGN_HEADER_SIZE) as *mut usize).write(base);
+    addr
+}
+
+/// Start of the padded block behind a pointer from `place_aligned`
+///
+/// # Safety
+/// `addr` must have been returned by `place_aligned` for a live block.
+unsafe fn padded_base(addr: usize) -> usize {
+    ((addr - ALIGN_HEADER_SIZE) as *const usize).read()
+}
+
+/// Maximum number of released allocations awaiting scrubbing
+pub const MAX_SCRUB_PENDING: usize = 64;
+
+/// Period of the background scrubber
+pub const SCRUB_INTERVAL_NS: u64 = 10_000_000;
+
+/// Allocations zeroed per scrubber run
+pub const SCRUB_BATCH: usize = 8;
+
+/// Released reclaimable allocation awaiting scrubbing
+#[derive(Debug, Clone, Copy)]
+struct ScrubEntry {
+    /// Start address
+    addr: usize,
+    /// Size in bytes
+    size: usize,
+    /// Allocator to return it to
+    strategy: AllocationStrategy,
+    /// Previous owner
+    owner: Option<VmId>,
+    /// Whether it has been zeroed since it was released
+    scrubbed: bool,
+}
+
+/// Zero `size` bytes at `addr`
+///
+/// # Safety
+/// The range must be owned by the caller and not in use.
+unsafe fn scrub(addr: usize, size: usize) {
+    core::ptr::write_bytes(addr as *mut u8, 0, size);
+}
+
+/// Released reclaimable allocations not yet returned to their allocator
+///
+/// Fixed-size for the same reason as `FallbackTable`.
+struct ScrubQueue {
+    entries: [Option<ScrubEntry>; MAX_SCRUB_PENDING],
+}
+
+impl ScrubQueue {
+    const fn new() -> Self {
+        Self { entries: [None; MAX_SCRUB_PENDING] }
+    }
+
+    /// Queue a released allocation, returns false if the queue is full
+    fn push(&mut self, entry: ScrubEntry) -> bool {
+        match self.entries.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                *slot = Some(entry);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Reuse a queued allocation of `size` bytes from `strategy` for `owner`
+    ///
+    /// Memory released by a different owner is zeroed before it is
+    /// returned, unless the scrubber already has.
+    fn take(&mut self, size: usize, strategy: AllocationStrategy, owner: Option<VmId>) -> Option<NonNull<u8>> {
+        let entry = self.entries.iter_mut()
+            .find(|slot| matches!(slot, Some(e) if e.size == size && e.strategy == strategy))?
+            .take()?;
+
+        if !entry.scrubbed && entry.owner != owner {
+            unsafe { scrub(entry.addr, entry.size) };
+        }
+        NonNull::new(entry.addr as *mut u8)
+    }
+
+    /// Zero up to `max` queued allocations in place
+    ///
+    /// Returns the number zeroed.
+    fn scrub_batch(&mut self, max: usize) -> usize {
+        let mut scrubbed = 0;
+        for entry in self.entries.iter_mut().flatten().filter(|e| !e.scrubbed).take(max) {
+            unsafe { scrub(entry.addr, entry.size) };
+            entry.scrubbed = true;
+            scrubbed += 1;
+        }
+        scrubbed
+    }
+
+    /// Remove a zeroed allocation
+    fn pop_scrubbed(&mut self) -> Option<ScrubEntry> {
+        self.entries.iter_mut()
+            .find(|slot| matches!(slot, Some(e) if e.scrubbed))?
+            .take()
+    }
+
+    /// Number of queued allocations
+    fn len(&self) -> usize {
+        self.entries.iter().filter(|slot| slot.is_some()).count()
+    }
+}
+
+/// Maximum number of quarantined allocations
+pub const MAX_QUARANTINE: usize = 64;
+
+/// Smallest freed allocation that is quarantined
+pub const QUARANTINE_MIN_SIZE: usize = 256;
+
+/// Freed allocation held back from reuse
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct QuarantineEntry {
+    /// Start address
+    addr: usize,
+    /// Size in bytes
+    size: usize,
+    /// Allocator to return it to
+    strategy: AllocationStrategy,
+    /// Allocation count at which it may be reused
+    release_at: u64,
+}
+
+/// Freed allocations waiting out a number of allocations before reuse
+///
+/// Fixed-size for the same reason as `FallbackTable`.
+struct Quarantine {
+    entries: [Option<QuarantineEntry>; MAX_QUARANTINE],
+    /// Allocations held, and allocations each is held for; 0 disables
+    capacity: usize,
+    /// Allocations made so far
+    allocations: u64,
+}
+
+impl Quarantine {
+    const fn new() -> Self {
+        Self { entries: [None; MAX_QUARANTINE], capacity: 0, allocations: 0 }
+    }
+
+    /// Set the number of entries, at most `MAX_QUARANTINE`
+    ///
+    /// Entries over a reduced capacity are handed back by `expire`.
+    fn set_capacity(&mut self, entries: usize) {
+        self.capacity = entries.min(MAX_QUARANTINE);
+    }
+
+    /// Quarantine a freed allocation of `size` bytes
+    ///
+    /// Returns the allocation that must be freed now: the given one if it
+    /// is not quarantined, or the oldest one if the quarantine was full.
+    fn hold(&mut self, addr: usize, size: usize, strategy: AllocationStrategy) -> Option<QuarantineEntry> {
+        let release_at = self.allocations + self.capacity as u64;
+        let entry = QuarantineEntry { addr, size, strategy, release_at };
+        if self.capacity == 0 || size < QUARANTINE_MIN_SIZE {
+            return Some(entry);
+        }
+
+        let evicted = if self.len() >= self.capacity { self.take_oldest() } else { None };
+        if let Some(slot) = self.entries.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(entry);
+        }
+        evicted
+    }
+
+    /// Count an allocation
+    fn note_allocation(&mut self) {
+        self.allocations += 1;
+    }
+
+    /// Remove an allocation that has served its time, or that no longer
+    /// fits a reduced capacity
+    fn expire(&mut self) -> Option<QuarantineEntry> {
+        let oldest = self.entries.iter().flatten().min_by_key(|e| e.release_at)?;
+        if oldest.release_at <= self.allocations || self.len() > self.capacity {
+            self.take_oldest()
+        } else {
+            None
+        }
+    }
+
+    /// Remove the allocation due first
+    fn take_oldest(&mut self) -> Option<QuarantineEntry> {
+        self.entries.iter_mut()
+            .filter(|slot| slot.is_some())
+            .min_by_key(|slot| slot.map(|e| e.release_at))?
+            .take()
+    }
+
+    /// Number of quarantined allocations
+    fn len(&self) -> usize {
+        self.entries.iter().filter(|slot| slot.is_some()).count()
+    }
+}
+
+/// Try each strategy in `order`, reclaiming memory once if all fail
+///
+/// Returns the allocation and the strategy that served it, or the last
+/// strategy's error.
+pub(crate) fn allocate_in_order<A, R>(
+    order: &[AllocationStrategy],
+    mut try_alloc: A,
+    mut reclaim: R,
+) -> Result<(NonNull<u8>, AllocationStrategy), AllocationError>
+where
+    A: FnMut(AllocationStrategy) -> Result<NonNull<u8>, AllocationError>,
+    R: FnMut() -> usize,
+{
+    let mut last_error = AllocationError::OutOfMemory;
+
+    for attempt in 0..2 {
+        if attempt == 1 && reclaim() == 0 {
+            break;
+        }
+
+        for &strategy in order {
+            match try_alloc(strategy) {
+                Ok(ptr) => return Ok((ptr, strategy)),
+                Err(e) => last_error = e,
+            }
+        }
+    }
+
+    Err(last_error)
+}
+
 /// Unified memory allocator
 pub struct UnifiedAllocator {
     /// Global statistics
     stats: SpinLock<AllocationStats>,
+    /// Live allocations served by a fallback strategy
+    fallbacks: SpinLock<FallbackTable>,
+    /// Released reclaimable allocations awaiting scrubbing
+    scrub_queue: SpinLock<ScrubQueue>,
+    /// Freed allocations held back from reuse
+    quarantine: SpinLock<Quarantine>,
     /// Current peak usage
     peak_usage: u64,
     /// Allocation threshold for using buddy vs slab
@@ -100,6 +421,9 @@ impl UnifiedAllocator {
                 efficiency: 1.0,
                 fragmentation: 0.0,
             }),
+            fallbacks: SpinLock::new(FallbackTable::new()),
+            scrub_queue: SpinLock::new(ScrubQueue::new()),
+            quarantine: SpinLock::new(Quarantine::new()),
             peak_usage: 0,
             buddy_threshold: 8 * PAGE_SIZE, // 32KB threshold for buddy allocator
         }
@@ -107,25 +431,70 @@ impl UnifiedAllocator {
 
     /// Allocate memory using the best strategy
     pub fn allocate(&self, size: usize, config: AllocationConfig) -> Result<NonNull<u8>, AllocationError> {
+        if config.alignment <= MIN_ALIGNMENT {
+            return self.allocate_unaligned(size, &config);
+        }
+        if !config.alignment.is_power_of_two() {
+            return Err(AllocationError::UnsupportedAlignment);
+        }
+        if config.alignment <= self.natural_alignment(size, config.strategy) {
+            return self.allocate_unaligned(size, &config);
+        }
+
+        let total = padded_size(size, config.alignment).ok_or(AllocationError::InvalidSize)?;
+        let base = self.allocate_unaligned(total, &config)?;
+        let addr = unsafe { place_aligned(base.as_ptr() as usize, config.alignment) };
+        log::debug!("Allocated {} bytes aligned to {} at {:#x}", size, config.alignment, addr);
+        Ok(NonNull::new(addr as *mut u8).unwrap())
+    }
+
+    /// Alignment every allocation of `size` bytes with `strategy` has
+    ///
+    /// `Auto` falls back from buddy only to frame, so page-sized requests
+    /// stay page-aligned whichever allocator serves them.
+    fn natural_alignment(&self, size: usize, strategy: AllocationStrategy) -> usize {
+        let strategy = match strategy {
+            AllocationStrategy::Auto => self.select_strategy(size),
+            strategy => strategy,
+        };
+        match strategy {
+            AllocationStrategy::Slab => MIN_ALIGNMENT,
+            _ => PAGE_SIZE,
+        }
+    }
+
+    /// Allocate memory with no alignment beyond what the strategy provides
+    fn allocate_unaligned(&self, size: usize, config: &AllocationConfig) -> Result<NonNull<u8>, AllocationError> {
         if size == 0 {
             return Err(AllocationError::InvalidSize);
         }
 
-        let strategy = if config.strategy == AllocationStrategy::Auto {
-            self.select_strategy(size)
+        self.quarantine.lock().note_allocation();
+        self.drain_quarantine();
+        self.free_scrubbed();
+
+        let reused = if config.reclaimable {
+            let strategy = match config.strategy {
+                AllocationStrategy::Auto => self.select_strategy(size),
+                strategy => strategy,
+            };
+            self.scrub_queue.lock().take(size, strategy, config.owner)
+                .map(|ptr| (ptr, strategy))
         } else {
-            config.strategy
+            None
         };
 
-        let result = match strategy {
-            AllocationStrategy::Buddy => self.allocate_buddy(size, &config),
-            AllocationStrategy::Slab => self.allocate_slab(size, &config),
-            AllocationStrategy::Frame => self.allocate_frame(size, &config),
-            AllocationStrategy::Auto => unreachable!(),
+        let result = if let Some(reused) = reused {
+            Ok(reused)
+        } else if config.strategy == AllocationStrategy::Auto {
+            self.allocate_auto(size, config)
+        } else {
+            self.allocate_from(config.strategy, size, config)
+                .map(|ptr| (ptr, config.strategy))
         };
 
         match result {
-            Ok(ptr) => {
+            Ok((ptr, strategy)) => {
                 if config.zero {
                     unsafe {
                         core::ptr::write_bytes(ptr.as_ptr(), 0, size);
@@ -146,23 +515,31 @@ impl UnifiedAllocator {
     }
 
     /// Deallocate memory
+    ///
+    /// Over-aligned allocations must go through `release` instead.
     pub fn deallocate(&self, ptr: NonNull<u8>, size: usize, strategy: AllocationStrategy) -> Result<(), AllocationError> {
         if size == 0 {
             return Err(AllocationError::InvalidSize);
         }
 
-        let result = match strategy {
-            AllocationStrategy::Buddy => self.deallocate_buddy(ptr, size),
-            AllocationStrategy::Slab => self.deallocate_slab(ptr, size),
-            AllocationStrategy::Frame => self.deallocate_frame(ptr, size),
+        let strategy = match strategy {
             AllocationStrategy::Auto => {
-                // Try to determine the strategy based on size
-                if size >= self.buddy_threshold {
-                    self.deallocate_buddy(ptr, size)
-                } else {
-                    self.deallocate_slab(ptr, size)
+                // Fallback allocations know their strategy, otherwise
+                // it is the one selected for this size
+                match self.fallbacks.lock().take(ptr.as_ptr() as usize) {
+                    Some(AllocationStrategy::Buddy) => AllocationStrategy::Buddy,
+                    Some(AllocationStrategy::Frame) => AllocationStrategy::Frame,
+                    Some(_) => AllocationStrategy::Slab,
+                    None => self.select_strategy(size),
                 }
             }
+            strategy => strategy,
+        };
+
+        let freed = self.quarantine.lock().hold(ptr.as_ptr() as usize, size, strategy);
+        let result = match freed {
+            Some(entry) => self.free_to(entry),
+            None => Ok(()),
         };
 
         match result {
@@ -178,6 +555,127 @@ impl UnifiedAllocator {
         }
     }
 
+    /// Release an allocation made with `config`
+    ///
+    /// Reclaimable page allocations are queued for the scrubber instead of
+    /// going straight back to their allocator; if the queue is full they
+    /// are zeroed here.
+    pub fn release(&self, ptr: NonNull<u8>, size: usize, config: &AllocationConfig) -> Result<(), AllocationError> {
+        if size == 0 {
+            return Err(AllocationError::InvalidSize);
+        }
+        self.free_scrubbed();
+        let (ptr, size) = self.underlying_block(ptr, size, config);
+
+        let addr = ptr.as_ptr() as usize;
+        let strategy = match config.strategy {
+            AllocationStrategy::Auto => self.fallbacks.lock().take(addr)
+                .unwrap_or_else(|| self.select_strategy(size)),
+            strategy => strategy,
+        };
+
+        if config.reclaimable && strategy != AllocationStrategy::Slab {
+            let entry = ScrubEntry { addr, size, strategy, owner: config.owner, scrubbed: false };
+            if self.scrub_queue.lock().push(entry) {
+                self.update_allocation_stats(size, false);
+                return Ok(());
+            }
+            unsafe { scrub(addr, size) };
+        }
+
+        self.deallocate(ptr, size, strategy)
+    }
+
+    /// Start and size of the block behind an allocation made with `config`
+    ///
+    /// Differs from `ptr` and `size` only for over-aligned allocations
+    /// that had to be padded.
+    fn underlying_block(&self, ptr: NonNull<u8>, size: usize, config: &AllocationConfig) -> (NonNull<u8>, usize) {
+        let align = config.alignment;
+        if align <= MIN_ALIGNMENT || align <= self.natural_alignment(size, config.strategy) {
+            return (ptr, size);
+        }
+        let base = unsafe { padded_base(ptr.as_ptr() as usize) };
+        (NonNull::new(base as *mut u8).unwrap_or(ptr), padded_size(size, align).unwrap_or(size))
+    }
+
+    /// Zero up to `max` released allocations
+    ///
+    /// Safe from interrupt context: nothing is freed here, and the run is
+    /// skipped if the interrupted code holds the queue. Zeroed allocations
+    /// go back to their allocator on the next allocation or release.
+    /// Returns the number zeroed.
+    pub fn scrub_pending(&self, max: usize) -> usize {
+        match self.scrub_queue.try_lock() {
+            Some(mut queue) => queue.scrub_batch(max),
+            None => 0,
+        }
+    }
+
+    /// Return the zeroed released allocations to their allocator
+    fn free_scrubbed(&self) {
+        while let Some(entry) = self.take_scrubbed() {
+            let ptr = NonNull::new(entry.addr as *mut u8).unwrap();
+            let result = match entry.strategy {
+                AllocationStrategy::Frame => self.deallocate_frame(ptr, entry.size),
+                _ => self.deallocate_buddy(ptr, entry.size),
+            };
+            if let Err(e) = result {
+                log::warn!("Failed to free scrubbed {} bytes at {:#x}: {:?}", entry.size, entry.addr, e);
+            }
+        }
+    }
+
+    /// Take one zeroed released allocation
+    fn take_scrubbed(&self) -> Option<ScrubEntry> {
+        self.scrub_queue.lock().pop_scrubbed()
+    }
+
+    /// Number of released allocations awaiting scrubbing
+    pub fn scrub_backlog(&self) -> usize {
+        self.scrub_queue.lock().len()
+    }
+
+    /// Quarantine freed allocations for `entries` further allocations
+    ///
+    /// Up to `entries` (at most `MAX_QUARANTINE`) freed allocations of at
+    /// least `QUARANTINE_MIN_SIZE` bytes are held back, each until
+    /// `entries` more allocations have been made. 0 turns quarantine off
+    /// and frees everything held.
+    pub fn set_quarantine(&self, entries: usize) {
+        self.quarantine.lock().set_capacity(entries);
+        self.drain_quarantine();
+    }
+
+    /// Number of quarantined allocations
+    pub fn quarantined(&self) -> usize {
+        self.quarantine.lock().len()
+    }
+
+    /// Free the quarantined allocations that may be reused
+    fn drain_quarantine(&self) {
+        while let Some(entry) = self.expire_quarantined() {
+            if let Err(e) = self.free_to(entry) {
+                log::warn!("Failed to free quarantined {} bytes at {:#x}: {:?}", entry.size, entry.addr, e);
+            }
+        }
+    }
+
+    /// Take one quarantined allocation that may be reused
+    fn expire_quarantined(&self) -> Option<QuarantineEntry> {
+        self.quarantine.lock().expire()
+    }
+
+    /// Return a freed allocation to the allocator that served it
+    fn free_to(&self, entry: QuarantineEntry) -> Result<(), AllocationError> {
+        let ptr = NonNull::new(entry.addr as *mut u8).ok_or(AllocationError::InvalidPointer)?;
+        match entry.strategy {
+            AllocationStrategy::Buddy => self.deallocate_buddy(ptr, entry.size),
+            AllocationStrategy::Frame => self.deallocate_frame(ptr, entry.size),
+            _ => self.deallocate_slab(ptr, entry.size),
+        }
+    }
+
     /// Reallocate memory
     pub fn reallocate(&self, ptr: Option<NonNull<u8>>, old_size: usize, new_size: usize, config: AllocationConfig) -> Result<NonNull<u8>, AllocationError> {
         if new_size == 0 {
@@ -194,7 +692,7 @@ impl UnifiedAllocator {
         }
 
         // Allocate new memory
-        let new_ptr = self.allocate(new_size, config)?;
+        let new_ptr = self.allocate(new_size, config.clone())?;
 
         // Copy old data if pointer exists
         if let Some(old_ptr) = ptr {
@@ -204,6 +702,7 @@ impl UnifiedAllocator {
             }
 
             // Free old memory
+            let (old_ptr, old_size) = self.underlying_block(old_ptr, old_size, &config);
             let strategy = if old_size >= self.buddy_threshold {
                 AllocationStrategy::Buddy
             } else {
@@ -224,6 +723,60 @@ impl UnifiedAllocator {
         }
     }
 
+    /// Fallback order for `Auto` allocations of `size` bytes
+    ///
+    /// Small allocations go slab → buddy → frame; sizes at or above the
+    /// buddy threshold skip the slab allocator.
+    fn fallback_order(&self, size: usize) -> &'static [AllocationStrategy] {
+        match self.select_strategy(size) {
+            AllocationStrategy::Slab => &[
+                AllocationStrategy::Slab,
+                AllocationStrategy::Buddy,
+                AllocationStrategy::Frame,
+            ],
+            _ => &[AllocationStrategy::Buddy, AllocationStrategy::Frame],
+        }
+    }
+
+    /// Allocate with a fixed strategy
+    fn allocate_from(&self, strategy: AllocationStrategy, size: usize,
+                     config: &AllocationConfig) -> Result<NonNull<u8>, AllocationError> {
+        match strategy {
+            AllocationStrategy::Buddy => self.allocate_buddy(size, config),
+            AllocationStrategy::Slab => self.allocate_slab(size, config),
+            AllocationStrategy::Frame => self.allocate_frame(size, config),
+            AllocationStrategy::Auto => unreachable!(),
+        }
+    }
+
+    /// Allocate with the selected strategy, falling back before giving up
+    ///
+    /// When every strategy fails, slab caches are shrunk and the sequence
+    /// is retried once, so transient fragmentation does not end in OOM.
+    fn allocate_auto(&self, size: usize,
+                     config: &AllocationConfig) -> Result<(NonNull<u8>, AllocationStrategy), AllocationError> {
+        let order = self.fallback_order(size);
+        let pages = (size + PAGE_SIZE - 1) / PAGE_SIZE;
+        let (ptr, strategy) = allocate_in_order(
+            order,
+            |strategy| self.allocate_from(strategy, size, config),
+            || self.reclaim_memory() + pressure::notify_memory_pressure(pages),
+        )?;
+
+        if strategy != order[0] {
+            if !self.fallbacks.lock().record(ptr.as_ptr() as usize, strategy) {
+                let _ = match strategy {
+                    AllocationStrategy::Buddy => self.deallocate_buddy(ptr, size),
+                    _ => self.deallocate_frame(ptr, size),
+                };
+                return Err(AllocationError::OutOfMemory);
+            }
+            log::debug!("Allocated {} bytes via {:?} fallback", size, strategy);
+        }
+
+        Ok((ptr, strategy))
+    }
+
     /// Allocate using buddy allocator
     fn allocate_buddy(&self, size: usize, config: &AllocationConfig) -> Result<NonNull<u8>, AllocationError> {
         // Align size to page boundary for buddy allocator
@@ -463,6 +1016,35 @@ pub fn reclaim_memory() -> usize {
     get_unified_allocator().reclaim_memory()
 }
 
+/// Release memory allocated with a custom configuration
+pub fn release_with_config(ptr: NonNull<u8>, size: usize, config: &AllocationConfig) -> Result<(), AllocationError> {
+    get_unified_allocator().release(ptr, size, config)
+}
+
+/// Quarantine freed allocations for `entries` further allocations (0 disables)
+pub fn set_quarantine(entries: usize) {
+    get_unified_allocator().set_quarantine(entries)
+}
+
+/// Zero up to `max` released reclaimable allocations
+pub fn scrub_pending(max: usize) -> usize {
+    get_unified_allocator().scrub_pending(max)
+}
+
+/// Scrubber timer callback, run in interrupt context
+fn scrub_tick(_handle: crate::core::time::TimerHandle) {
+    scrub_pending(SCRUB_BATCH);
+}
+
+/// Start the background scrubber
+///
+/// Needs the timer subsystem, so it is started after `init`.
+pub fn start_scrubber() -> crate::Result<()> {
+    crate::core::time::periodic(SCRUB_INTERVAL_NS, scrub_tick)?;
+    log::info!("Memory scrubber started");
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -476,6 +1058,68 @@ mod tests {
         assert_eq!(allocator.select_strategy(64 * 1024), AllocationStrategy::Buddy);
     }
 
+    #[test]
+    fn test_fallback_serves_failed_preferred_strategy() {
+        let allocator = UnifiedAllocator::new();
+        let order = allocator.fallback_order(64);
+        assert_eq!(order, &[AllocationStrategy::Slab, AllocationStrategy::Buddy, AllocationStrategy::Frame]);
+        assert_eq!(allocator.fallback_order(64 * 1024), &[AllocationStrategy::Buddy, AllocationStrategy::Frame]);
+
+        let mut block = [0u8; 64];
+        let ptr = NonNull::new(block.as_mut_ptr()).unwrap();
+        let mut tried = alloc::vec::Vec::new();
+        let result = allocate_in_order(order, |strategy| {
+            tried.push(strategy);
+            if strategy == AllocationStrategy::Buddy { Ok(ptr) } else { Err(AllocationError::OutOfMemory) }
+        }, || panic!("reclaim not needed"));
+        assert_eq!(result, Ok((ptr, AllocationStrategy::Buddy)));
+        assert_eq!(tried, [AllocationStrategy::Slab, AllocationStrategy::Buddy]);
+
+        // Succeeds after reclaim frees slab pages
+        let reclaimed = core::cell::Cell::new(false);
+        let result = allocate_in_order(order, |strategy| {
+            if reclaimed.get() && strategy == AllocationStrategy::Slab { Ok(ptr) } else { Err(AllocationError::OutOfMemory) }
+        }, || { reclaimed.set(true); 4 });
+        assert_eq!(result, Ok((ptr, AllocationStrategy::Slab)));
+
+        // Fallback allocations are freed by the allocator that served them
+        let mut table = FallbackTable::new();
+        assert!(table.record(0x1000, AllocationStrategy::Frame));
+        assert_eq!(table.take(0x2000), None);
+        assert_eq!(table.take(0x1000), Some(AllocationStrategy::Frame));
+        assert_eq!(table.take(0x1000), None);
+    }
+
+    #[test]
+    fn test_exhaustion_still_fails() {
+        let order = [AllocationStrategy::Slab, AllocationStrategy::Buddy, AllocationStrategy::Frame];
+        let mut attempts = 0;
+        let mut reclaims = 0;
+        let result = allocate_in_order(&order, |_| {
+            attempts += 1;
+            Err(AllocationError::OutOfMemory)
+        }, || { reclaims += 1; 1 });
+        assert_eq!(result, Err(AllocationError::OutOfMemory));
+        // Full sequence before and after one reclaim
+        assert_eq!(attempts, 6);
+        assert_eq!(reclaims, 1);
+
+        // Nothing reclaimed, no point retrying
+        let mut attempts = 0;
+        let result = allocate_in_order(&order, |_| {
+            attempts += 1;
+            Err(AllocationError::OutOfMemory)
+        }, || 0);
+        assert_eq!(result, Err(AllocationError::OutOfMemory));
+        assert_eq!(attempts, 3);
+
+        let mut table = FallbackTable::new();
+        for addr in 0..MAX_FALLBACK_ALLOCATIONS {
+            assert!(table.record(addr, AllocationStrategy::Buddy));
+        }
+        assert!(!table.record(MAX_FALLBACK_ALLOCATIONS, AllocationStrategy::Buddy));
+    }
+
     #[test]
     fn test_allocation_config() {
         let config = AllocationConfig::default();
@@ -483,4 +1127,146 @@ mod tests {
         assert_eq!(config.alignment, 8);
         assert!(!config.zero);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_reclaimed_page_zeroed_for_other_vm() {
+        let mut page = alloc::vec![0xAAu8; PAGE_SIZE];
+        let addr = page.as_mut_ptr() as usize;
+        let released = ScrubEntry {
+            addr,
+            size: PAGE_SIZE,
+            strategy: AllocationStrategy::Frame,
+            owner: Some(1),
+            scrubbed: false,
+        };
+
+        let mut queue = ScrubQueue::new();
+        assert!(queue.push(released));
+        assert_eq!(queue.len(), 1);
+
+        // Wrong size or allocator is not reused
+        assert!(queue.take(2 * PAGE_SIZE, AllocationStrategy::Frame, Some(2)).is_none());
+        assert!(queue.take(PAGE_SIZE, AllocationStrategy::Buddy, Some(2)).is_none());
+
+        // Same owner reuses its own page as is
+        let ptr = queue.take(PAGE_SIZE, AllocationStrategy::Frame, Some(1)).unwrap();
+        assert_eq!(ptr.as_ptr() as usize, addr);
+        assert!(page.iter().all(|&b| b == 0xAA));
+
+        // A different owner never sees the previous contents
+        assert!(queue.push(released));
+        let ptr = queue.take(PAGE_SIZE, AllocationStrategy::Frame, Some(2)).unwrap();
+        assert_eq!(ptr.as_ptr() as usize, addr);
+        assert!(page.iter().all(|&b| b == 0));
+        assert_eq!(queue.len(), 0);
+
+        // The background scrubber zeroes in place; only then is it freed
+        page.fill(0x55);
+        assert!(queue.push(released));
+        assert!(queue.pop_scrubbed().is_none());
+        assert_eq!(queue.scrub_batch(SCRUB_BATCH), 1);
+        assert!(page.iter().all(|&b| b == 0));
+        assert_eq!(queue.scrub_batch(SCRUB_BATCH), 0);
+        assert_eq!(queue.pop_scrubbed().map(|e| e.addr), Some(addr));
+        assert_eq!(queue.len(), 0);
+    }
+
+    #[test]
+    fn test_quarantined_block_reused_only_after_drain() {
+        let mut quarantine = Quarantine::new();
+
+        // Disabled, or too small: freed at once
+        let freed = quarantine.hold(0x1000, PAGE_SIZE, AllocationStrategy::Buddy);
+        assert_eq!(freed.map(|e| e.addr), Some(0x1000));
+        quarantine.set_capacity(3);
+        let freed = quarantine.hold(0x2000, QUARANTINE_MIN_SIZE - 1, AllocationStrategy::Slab);
+        assert_eq!(freed.map(|e| e.addr), Some(0x2000));
+
+        // Held for three allocations
+        assert_eq!(quarantine.hold(0x3000, PAGE_SIZE, AllocationStrategy::Buddy), None);
+        for _ in 0..2 {
+            quarantine.note_allocation();
+            assert_eq!(quarantine.expire(), None);
+        }
+        quarantine.note_allocation();
+        let entry = quarantine.expire().unwrap();
+        assert_eq!((entry.addr, entry.size, entry.strategy), (0x3000, PAGE_SIZE, AllocationStrategy::Buddy));
+        assert_eq!(quarantine.expire(), None);
+        assert_eq!(quarantine.len(), 0);
+
+        // A full quarantine gives up its oldest block early
+        for addr in [0x4000, 0x5000, 0x6000] {
+            assert_eq!(quarantine.hold(addr, PAGE_SIZE, AllocationStrategy::Frame), None);
+            quarantine.note_allocation();
+        }
+        let evicted = quarantine.hold(0x7000, PAGE_SIZE, AllocationStrategy::Frame);
+        assert_eq!(evicted.map(|e| e.addr), Some(0x4000));
+        assert_eq!(quarantine.len(), 3);
+
+        // Turning quarantine off hands everything back
+        quarantine.set_capacity(0);
+        let mut drained = alloc::vec::Vec::new();
+        while let Some(entry) = quarantine.expire() {
+            drained.push(entry.addr);
+        }
+        assert_eq!(drained, [0x5000, 0x6000, 0x7000]);
+    }
+
+    #[test]
+    fn test_quarantine_through_allocate_and_deallocate() {
+        // Back the global buddy allocator with test memory
+        let size = 16 * PAGE_SIZE;
+        let layout = core::alloc::Layout::from_size_align(size, size).unwrap();
+        let base = unsafe { alloc::alloc::alloc(layout) } as usize;
+        buddy::init(base, size, &[]).unwrap();
+
+        let allocator = UnifiedAllocator::new();
+        let config = AllocationConfig { strategy: AllocationStrategy::Buddy, ..Default::default() };
+
+        // Without quarantine a freed page is handed straight back
+        let page = allocator.allocate(PAGE_SIZE, config.clone()).unwrap();
+        allocator.deallocate(page, PAGE_SIZE, AllocationStrategy::Buddy).unwrap();
+        assert_eq!(allocator.allocate(PAGE_SIZE, config.clone()), Ok(page));
+
+        // Held for two allocations
+        allocator.set_quarantine(2);
+        allocator.deallocate(page, PAGE_SIZE, AllocationStrategy::Buddy).unwrap();
+        assert_eq!(allocator.quarantined(), 1);
+        let other = allocator.allocate(PAGE_SIZE, config.clone()).unwrap();
+        assert_ne!(other, page);
+        assert_eq!(allocator.quarantined(), 1);
+        assert_eq!(allocator.allocate(PAGE_SIZE, config.clone()), Ok(page));
+        assert_eq!(allocator.quarantined(), 0);
+
+        // An over-aligned allocation counts once
+        allocator.deallocate(page, PAGE_SIZE, AllocationStrategy::Buddy).unwrap();
+        let aligned = AllocationConfig { alignment: 2 * PAGE_SIZE, ..config.clone() };
+        let ptr = allocator.allocate(PAGE_SIZE, aligned.clone()).unwrap();
+        assert_eq!(ptr.as_ptr() as usize % (2 * PAGE_SIZE), 0);
+        assert_eq!(allocator.quarantined(), 1);
+        allocator.release(ptr, PAGE_SIZE, &aligned).unwrap();
+    }
+
+    #[test]
+    fn test_over_aligned_requests_are_aligned() {
+        let mut arena = alloc::vec![0u8; 4 * PAGE_SIZE];
+        let start = (arena.as_mut_ptr() as usize + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+
+        for align in [16, 64, 256, PAGE_SIZE] {
+            let total = padded_size(100, align).unwrap();
+            // Blocks only guaranteed MIN_ALIGNMENT, one of them already aligned
+            for base in [start, start + MIN_ALIGNMENT, start + align - MIN_ALIGNMENT] {
+                let addr = unsafe { place_aligned(base, align) };
+                assert_eq!(addr % align, 0, "{}-byte alignment", align);
+                assert!(base + ALIGN_HEADER_SIZE <= addr && addr + 100 <= base + total);
+                assert_eq!(unsafe { padded_base(addr) }, base);
+            }
+        }
+
+        // Only alignments the strategy does not provide are padded
+        let allocator = UnifiedAllocator::new();
+        assert_eq!(allocator.natural_alignment(64, AllocationStrategy::Frame), PAGE_SIZE);
+        assert_eq!(allocator.natural_alignment(64, AllocationStrategy::Auto), MIN_ALIGNMENT);
+        assert_eq!(allocator.natural_alignment(8 * PAGE_SIZE, AllocationStrategy::Auto), PAGE_SIZE);
+    }
+}
@@ -0,0 +1,247 @@
+//! Bump allocation arena
+//!
+//! An `Arena` hands out memory from one preallocated region by bumping an
+//! offset, and reclaims everything at once with `reset()`. It suits
+//! short-lived data with a clear end of life, such as the scratch state of
+//! a single VCPU exit, and keeps that churn away from the slab allocator.
+//!
+//! `reset()` takes `&mut self`, so nothing allocated from the arena can
+//! still be borrowed when its memory is reused. Destructors of values
+//! placed in the arena are not run; `ArenaVec` drops its elements itself.
+
+use crate::{Result, Error};
+use alloc::alloc::{alloc, dealloc, Layout};
+use core::cell::Cell;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+use core::ptr::NonNull;
+
+/// Alignment of the arena's backing region
+const ARENA_ALIGN: usize = 16;
+
+/// Bump allocator over a fixed region
+pub struct Arena {
+    /// Start of the region
+    base: NonNull<u8>,
+    /// Size of the region in bytes
+    capacity: usize,
+    /// Offset of the first free byte
+    offset: Cell<usize>,
+    /// Whether the region was allocated by `new` and is freed on drop
+    owned: bool,
+}
+
+impl Arena {
+    /// Create an arena with a freshly allocated region of `capacity` bytes
+    pub fn new(capacity: usize) -> Result<Self> {
+        if capacity == 0 {
+            return Err(Error::InvalidArgument);
+        }
+
+        let layout = Layout::from_size_align(capacity, ARENA_ALIGN)
+            .map_err(|_| Error::InvalidArgument)?;
+        let base = NonNull::new(unsafe { alloc(layout) }).ok_or(Error::OutOfMemory)?;
+
+        Ok(Self {
+            base,
+            capacity,
+            offset: Cell::new(0),
+            owned: true,
+        })
+    }
+
+    /// Create an arena over an existing region
+    ///
+    /// # Safety
+    /// `base` must be valid for reads and writes of `capacity` bytes for
+    /// the lifetime of the arena and not be used by anything else.
+    pub unsafe fn from_raw(base: NonNull<u8>, capacity: usize) -> Self {
+        Self {
+            base,
+            capacity,
+            offset: Cell::new(0),
+            owned: false,
+        }
+    }
+
+    /// Allocate memory for `layout`
+    pub fn alloc(&self, layout: Layout) -> Result<NonNull<u8>> {
+        let base = self.base.as_ptr() as usize;
+        let start = (base + self.offset.get() + layout.align() - 1) & !(layout.align() - 1);
+        let end = start.checked_add(layout.size()).ok_or(Error::OutOfMemory)?;
+
+        if end > base + self.capacity {
+            return Err(Error::OutOfMemory);
+        }
+
+        self.offset.set(end - base);
+        Ok(unsafe { NonNull::new_unchecked(start as *mut u8) })
+    }
+
+    /// Move a value into the arena
+    ///
+    /// The value's destructor is not run when the arena is reset.
+    // Every call returns a distinct allocation, so the references never alias
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_value<T>(&self, value: T) -> Result<&mut T> {
+        let ptr = self.alloc(Layout::new::<T>())?.cast::<T>();
+        unsafe {
+            ptr.as_ptr().write(value);
+            Ok(&mut *ptr.as_ptr())
+        }
+    }
+
+    /// Create a vector holding up to `capacity` elements in the arena
+    pub fn vec_with_capacity<T>(&self, capacity: usize) -> Result<ArenaVec<'_, T>> {
+        let layout = Layout::array::<T>(capacity).map_err(|_| Error::InvalidArgument)?;
+        let ptr = self.alloc(layout)?.cast::<T>();
+
+        Ok(ArenaVec {
+            ptr,
+            len: 0,
+            capacity,
+            _arena: PhantomData,
+        })
+    }
+
+    /// Reclaim everything allocated from the arena
+    pub fn reset(&mut self) {
+        self.offset.set(0);
+    }
+
+    /// Bytes in use, including alignment padding
+    pub fn used(&self) -> usize {
+        self.offset.get()
+    }
+
+    /// Bytes still available, ignoring alignment
+    pub fn remaining(&self) -> usize {
+        self.capacity - self.offset.get()
+    }
+
+    /// Size of the region
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        if self.owned {
+            unsafe {
+                dealloc(self.base.as_ptr(), Layout::from_size_align_unchecked(self.capacity, ARENA_ALIGN));
+            }
+        }
+    }
+}
+
+/// Fixed-capacity vector stored in an `Arena`
+pub struct ArenaVec<'a, T> {
+    /// Element storage
+    ptr: NonNull<T>,
+    /// Number of initialized elements
+    len: usize,
+    /// Number of elements that fit
+    capacity: usize,
+    /// Borrow of the arena holding the storage
+    _arena: PhantomData<&'a Arena>,
+}
+
+impl<'a, T> ArenaVec<'a, T> {
+    /// Append an element, handing it back if the vector is full
+    pub fn push(&mut self, value: T) -> core::result::Result<(), T> {
+        if self.len == self.capacity {
+            return Err(value);
+        }
+
+        unsafe { self.ptr.as_ptr().add(self.len).write(value) };
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Remove the last element
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+
+        self.len -= 1;
+        Some(unsafe { self.ptr.as_ptr().add(self.len).read() })
+    }
+
+    /// Remove all elements
+    pub fn clear(&mut self) {
+        while self.pop().is_some() {}
+    }
+
+    /// Number of elements that fit
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+impl<'a, T> Deref for ArenaVec<'a, T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        unsafe { core::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<'a, T> DerefMut for ArenaVec<'a, T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { core::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<'a, T> Drop for ArenaVec<'a, T> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alloc_until_full_then_reset() {
+        let mut arena = Arena::new(256).unwrap();
+
+        for _ in 0..2 {
+            let mut count = 0;
+            while arena.alloc_value(count as u64).is_ok() {
+                count += 1;
+            }
+            assert_eq!(count, 32);
+            assert_eq!(arena.remaining(), 0);
+            assert_eq!(arena.alloc(Layout::new::<u8>()), Err(Error::OutOfMemory));
+
+            arena.reset();
+            assert_eq!(arena.used(), 0);
+            assert_eq!(arena.remaining(), arena.capacity());
+        }
+    }
+
+    #[test]
+    fn test_alignment_and_vec() {
+        let arena = Arena::new(128).unwrap();
+        let byte = arena.alloc_value(1u8).unwrap();
+        let word = arena.alloc_value(2u64).unwrap();
+        assert_eq!(*byte, 1);
+        assert_eq!(*word, 2);
+        assert_eq!(word as *mut u64 as usize % core::mem::align_of::<u64>(), 0);
+        assert_eq!(arena.used(), 16);
+
+        let mut events = arena.vec_with_capacity::<u32>(4).unwrap();
+        for n in 0..4 {
+            events.push(n).unwrap();
+        }
+        assert_eq!(events.push(4), Err(4));
+        assert_eq!(&events[..], &[0, 1, 2, 3]);
+        assert_eq!(events.pop(), Some(3));
+
+        // Too big for what is left
+        assert!(arena.vec_with_capacity::<u64>(32).is_err());
+    }
+}
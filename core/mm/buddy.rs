@@ -11,7 +11,9 @@
 //! - Integration with frame allocator for backing memory
 
 use crate::core::mm::{PAGE_SIZE, align_up, frame::alloc_frame, frame::dealloc_frame};
+use crate::core::mm::fsck::{ConsistencyReport, Violation};
 use crate::core::sync::SpinLock;
+use core::ops::Range;
 use core::ptr::NonNull;
 use core::sync::atomic::{AtomicUsize, Ordering};
 
@@ -196,6 +198,8 @@ pub struct BuddyAllocator {
     allocation_count: AtomicUsize,
     /// Number of deallocations performed
     deallocation_count: AtomicUsize,
+    /// Ranges kept out of the free lists
+    holes: SpinLock<alloc::vec::Vec<Range<usize>>>,
 }
 
 /// Buddy allocator statistics
@@ -220,6 +224,15 @@ pub struct BuddyStats {
 impl BuddyAllocator {
     /// Create a new buddy allocator
     pub fn new(base_addr: usize, total_size: usize) -> Result<Self, BuddyError> {
+        Self::with_holes(base_addr, total_size, &[])
+    }
+
+    /// Create a buddy allocator that never hands out the `holes`
+    ///
+    /// Pages overlapping a hole are left out of the free lists and their
+    /// memory is never written, so the holes may be firmware or the
+    /// hypervisor image.
+    pub fn with_holes(base_addr: usize, total_size: usize, holes: &[Range<usize>]) -> Result<Self, BuddyError> {
         if total_size == 0 || !total_size.is_power_of_two() {
             return Err(BuddyError::InvalidSize);
         }
@@ -236,10 +249,11 @@ impl BuddyAllocator {
             total_free: AtomicUsize::new(0),
             allocation_count: AtomicUsize::new(0),
             deallocation_count: AtomicUsize::new(0),
+            holes: SpinLock::new(holes.to_vec()),
         };
 
         // Initialize the allocator by adding the entire memory as one large block
-        allocator.initialize_memory()?;
+        allocator.initialize_memory(holes)?;
 
         log::debug!("Buddy allocator initialized: base_addr={:#x}, size={}",
                    base_addr, total_size);
@@ -248,7 +262,7 @@ impl BuddyAllocator {
     }
 
     /// Initialize the memory by adding blocks to free lists
-    fn initialize_memory(&mut self) -> Result<(), BuddyError> {
+    fn initialize_memory(&mut self, holes: &[Range<usize>]) -> Result<(), BuddyError> {
         let mut remaining_size = self.total_size;
         let mut current_addr = self.base_addr;
 
@@ -264,7 +278,7 @@ impl BuddyAllocator {
             }
 
             // Add this block to the appropriate free list
-            self.add_block_to_free_list(current_addr, order)?;
+            self.add_free_outside(current_addr, order as u8, holes);
 
             remaining_size -= (1 << order) * PAGE_SIZE;
             current_addr += (1 << order) * PAGE_SIZE;
@@ -273,21 +287,54 @@ impl BuddyAllocator {
         Ok(())
     }
 
-    /// Add a block to the free list for the given order
-    fn add_block_to_free_list(&mut self, addr: usize, order: u8) -> Result<(), BuddyError> {
-        if order > MAX_ORDER as u8 {
-            return Err(BuddyError::InvalidOrder);
-        }
-
-        let block = addr as *mut BuddyBlock;
-        unsafe {
-            *block = BuddyBlock::new(order, true);
+    /// Free the parts of a block that don't overlap any hole
+    ///
+    /// A block overlapping a hole is split into its two halves until each
+    /// piece is either clear of the holes or a single page inside one.
+    fn add_free_outside(&self, addr: usize, order: u8, holes: &[Range<usize>]) {
+        let end = addr + order_to_size(order);
+        if !holes.iter().any(|hole| hole.start < end && addr < hole.end) {
+            let block = addr as *mut BuddyBlock;
+            unsafe {
+                *block = BuddyBlock::new(order, true);
+            }
+            self.free_lists[order as usize].lock().push_front(block);
+            self.total_free.fetch_add(order_to_size(order), Ordering::Relaxed);
+        } else if order > 0 {
+            self.add_free_outside(addr, order - 1, holes);
+            self.add_free_outside(addr + order_to_size(order - 1), order - 1, holes);
         }
+    }
 
-        self.free_lists[order as usize].lock().push_front(block);
-        self.total_free.fetch_add((1 << order) * PAGE_SIZE, Ordering::Relaxed);
+    /// Take `range` out of the free lists
+    ///
+    /// Free blocks overlapping it are split so the rest of them stays
+    /// free. Blocks already allocated are left alone; the range is only
+    /// kept from being handed out again through the free lists.
+    pub fn reserve_range(&self, range: Range<usize>) {
+        self.holes.lock().push(range.clone());
+        let hole = [range];
+        for order in (0..=MAX_ORDER as u8).rev() {
+            let size = order_to_size(order);
+            let mut overlapping = alloc::vec::Vec::new();
+            {
+                let mut list = self.free_lists[order as usize].lock();
+                let mut cursor = list.head;
+                while let Some(block) = cursor {
+                    let addr = block.as_ptr() as usize;
+                    cursor = unsafe { block.as_ref().next };
+                    if hole[0].start < addr + size && addr < hole[0].end {
+                        list.remove(block.as_ptr());
+                        overlapping.push(addr);
+                    }
+                }
+            }
 
-        Ok(())
+            for addr in overlapping {
+                self.total_free.fetch_sub(size, Ordering::Relaxed);
+                self.add_free_outside(addr, order, &hole);
+            }
+        }
     }
 
     /// Remove a block from the free list for the given order
@@ -379,6 +426,9 @@ impl BuddyAllocator {
         if addr < self.base_addr || addr >= self.base_addr + self.total_size {
             return Err(BuddyError::InvalidAddress);
         }
+        if self.in_hole(addr, order) {
+            return Err(BuddyError::InvalidAddress);
+        }
 
         let block = addr as *mut BuddyBlock;
 
@@ -408,14 +458,16 @@ impl BuddyAllocator {
                 let buddy_addr = (*current_block).buddy_addr(self.base_addr);
                 let buddy = buddy_addr as *mut BuddyBlock;
 
-                // Check if buddy exists and is free
-                if self.is_valid_buddy(buddy, current_order) {
+                // Check if buddy exists and is free; a hole has no header
+                if !self.in_hole(buddy_addr, current_order) && self.is_valid_buddy(buddy, current_order) {
                     // Remove buddy from free list
                     let removed = self.free_lists[current_order as usize]
                         .lock()
                         .remove(buddy);
 
                     if removed {
+                        self.total_free.fetch_sub(order_to_size(current_order), Ordering::Relaxed);
+
                         // Coalesce the blocks
                         let block_addr = (*current_block).addr();
                         let coalesced_addr = if block_addr < buddy_addr {
@@ -442,6 +494,12 @@ impl BuddyAllocator {
         self.total_free.fetch_add((1 << current_order) * PAGE_SIZE, Ordering::Relaxed);
     }
 
+    /// Check if a block overlaps a range kept out of the free lists
+    fn in_hole(&self, addr: usize, order: u8) -> bool {
+        let end = addr + order_to_size(order);
+        self.holes.lock().iter().any(|hole| hole.start < end && addr < hole.end)
+    }
+
     /// Check if a buddy block is valid and free
     fn is_valid_buddy(&self, buddy: *mut BuddyBlock, order: u8) -> bool {
         unsafe {
@@ -490,6 +548,80 @@ impl BuddyAllocator {
     pub fn total_size(&self) -> usize {
         self.total_size
     }
+
+    /// Check the free lists, adding any violations to `report`
+    ///
+    /// A list is walked until its first misplaced or corrupt entry, whose
+    /// links cannot be trusted. With the `debug` feature, free blocks are
+    /// also checked for overlaps, which catches a block on two lists.
+    pub fn check(&self, report: &mut ConsistencyReport) {
+        #[cfg(feature = "debug")]
+        let mut blocks = alloc::vec::Vec::new();
+
+        for (order, free_list) in self.free_lists.iter().enumerate() {
+            let order = order as u8;
+            let list = free_list.lock();
+            let size = order_to_size(order);
+            let mut counted = 0;
+            let mut prev: Option<NonNull<BuddyBlock>> = None;
+            let mut cursor = list.head;
+
+            while let Some(block) = cursor {
+                let addr = block.as_ptr() as usize;
+                if addr < self.base_addr
+                    || addr + size > self.base_addr + self.total_size
+                    || (addr - self.base_addr) % size != 0
+                {
+                    report.report(Violation::BuddyMisplaced { addr, order });
+                    break;
+                }
+
+                // A list longer than the arena holds blocks loops
+                if counted == self.total_size / size {
+                    report.report(Violation::BuddyBrokenLink { addr });
+                    break;
+                }
+
+                let block_ref = unsafe { block.as_ref() };
+                if !block_ref.is_valid() {
+                    report.report(Violation::BuddyBadMagic { addr });
+                    break;
+                }
+                if block_ref.order != order {
+                    report.report(Violation::BuddyWrongOrder { addr, list_order: order, block_order: block_ref.order });
+                }
+                if !block_ref.free {
+                    report.report(Violation::BuddyNotFree { addr });
+                }
+                if block_ref.prev != prev {
+                    report.report(Violation::BuddyBrokenLink { addr });
+                }
+
+                #[cfg(feature = "debug")]
+                blocks.push((addr, size));
+
+                counted += 1;
+                report.buddy_blocks += 1;
+                prev = cursor;
+                cursor = block_ref.next;
+            }
+
+            if cursor.is_none() && counted != list.len() {
+                report.report(Violation::BuddyCountMismatch { order, counted, recorded: list.len() });
+            }
+        }
+
+        #[cfg(feature = "debug")]
+        {
+            blocks.sort_unstable();
+            for pair in blocks.windows(2) {
+                let ((addr, size), (other, _)) = (pair[0], pair[1]);
+                if addr + size > other {
+                    report.report(Violation::BuddyOverlap { addr, other });
+                }
+            }
+        }
+    }
 }
 
 /// Global buddy allocator instance
@@ -497,14 +629,17 @@ static mut BUDDY_ALLOCATOR: Option<BuddyAllocator> = None;
 static BUDDY_ALLOCATOR_INIT: SpinLock<bool> = SpinLock::new(false);
 
 /// Initialize the global buddy allocator
-pub fn init(base_addr: usize, size: usize) -> Result<(), BuddyError> {
+///
+/// The `holes` (firmware, the hypervisor image, reserved regions) are
+/// never handed out.
+pub fn init(base_addr: usize, size: usize, holes: &[Range<usize>]) -> Result<(), BuddyError> {
     let mut init_flag = BUDDY_ALLOCATOR_INIT.lock();
 
     if *init_flag {
         return Ok(());
     }
 
-    let allocator = BuddyAllocator::new(base_addr, size)?;
+    let allocator = BuddyAllocator::with_holes(base_addr, size, holes)?;
 
     unsafe {
         BUDDY_ALLOCATOR = Some(allocator);
@@ -534,11 +669,30 @@ pub fn dealloc(addr: usize, order: u8) -> Result<(), BuddyError> {
         .deallocate(addr, order)
 }
 
+/// Take a range out of the global buddy allocator's free memory
+pub fn reserve(range: Range<usize>) {
+    if let Some(allocator) = get_buddy_allocator() {
+        allocator.reserve_range(range);
+    }
+}
+
+/// Physical range `(base, size)` managed by the global buddy allocator
+pub fn pool_range() -> Option<(usize, usize)> {
+    get_buddy_allocator().map(|allocator| (allocator.base_addr(), allocator.total_size()))
+}
+
 /// Get buddy allocator statistics
 pub fn get_stats() -> Option<BuddyStats> {
     get_buddy_allocator().map(|allocator| allocator.stats())
 }
 
+/// Check the free lists of the global buddy allocator, if initialized
+pub(crate) fn check_free_lists(report: &mut ConsistencyReport) {
+    if let Some(allocator) = get_buddy_allocator() {
+        allocator.check(report);
+    }
+}
+
 /// Convert size to order
 pub fn size_to_order(size: usize) -> Result<u8, BuddyError> {
     if size == 0 {
@@ -597,4 +751,67 @@ mod tests {
         // This is a basic test - in practice, blocks would be properly allocated
         // and initialized with actual memory addresses
     }
+
+    #[test]
+    fn test_check_reports_corrupt_free_blocks() {
+        let size = order_to_size(4);
+        let layout = core::alloc::Layout::from_size_align(size, size).unwrap();
+        let base = unsafe { alloc::alloc::alloc(layout) } as usize;
+        let allocator = BuddyAllocator::new(base, size).unwrap();
+
+        // Splitting the arena leaves one free block of each lower order
+        assert_eq!(allocator.allocate(0).unwrap(), base);
+        let mut report = ConsistencyReport::new();
+        allocator.check(&mut report);
+        assert!(report.is_clean());
+        assert_eq!(report.buddy_blocks, 4);
+
+        // Scribble over the order-1 header and mislabel the order-2 block
+        let order1 = base + order_to_size(1);
+        let order2 = base + order_to_size(2);
+        unsafe {
+            (*(order1 as *mut BuddyBlock)).magic = 0;
+            (*(order2 as *mut BuddyBlock)).order = 3;
+        }
+
+        let mut report = ConsistencyReport::new();
+        allocator.check(&mut report);
+        assert_eq!(report.violations, [
+            Violation::BuddyBadMagic { addr: order1 },
+            Violation::BuddyWrongOrder { addr: order2, list_order: 2, block_order: 3 },
+        ]);
+
+        unsafe { alloc::alloc::dealloc(base as *mut u8, layout) };
+    }
+
+    #[test]
+    fn test_holes_are_never_allocated() {
+        let size = order_to_size(4);
+        let layout = core::alloc::Layout::from_size_align(size, size).unwrap();
+        let base = unsafe { alloc::alloc::alloc(layout) } as usize;
+        let page = |n: usize| base + n * PAGE_SIZE;
+
+        // Pages 5 and 6 are excluded up front, page 12 after the fact
+        let allocator = BuddyAllocator::with_holes(base, size, &[page(5)..page(6) + 1]).unwrap();
+        allocator.reserve_range(page(12)..page(13));
+        assert_eq!(allocator.stats().free_memory, 13 * PAGE_SIZE);
+
+        let mut allocated = alloc::vec::Vec::new();
+        while let Ok(addr) = allocator.allocate(0) {
+            assert!(![page(5), page(6), page(12)].contains(&addr));
+            allocated.push(addr);
+        }
+        assert_eq!(allocated.len(), 13);
+        assert_eq!(allocator.deallocate(page(12), 0), Err(BuddyError::InvalidAddress));
+        for &addr in &allocated {
+            allocator.deallocate(addr, 0).unwrap();
+        }
+        assert_eq!(allocator.stats().free_memory, 13 * PAGE_SIZE);
+
+        let mut report = ConsistencyReport::new();
+        allocator.check(&mut report);
+        assert!(report.is_clean());
+
+        unsafe { alloc::alloc::dealloc(base as *mut u8, layout) };
+    }
 }
\ No newline at end of file
@@ -5,6 +5,7 @@
 use crate::core::mm::{FrameNr, PhysAddr, PAGE_SIZE, align_up, align_down};
 use crate::utils::bitmap::Bitmap;
 use crate::core::sync::SpinLock;
+use alloc::vec::Vec;
 use core::ptr::NonNull;
 
 /// Physical frame allocator
@@ -17,6 +18,8 @@ pub struct FrameAllocator {
     start_addr: PhysAddr,
     /// End physical address of managed memory
     end_addr: PhysAddr,
+    /// Reserved `[start, end)` frame ranges, never handed out
+    reserved: SpinLock<Vec<(FrameNr, FrameNr)>>,
 }
 
 impl FrameAllocator {
@@ -33,7 +36,7 @@ impl FrameAllocator {
         size: u64,
     ) -> Self {
         let total_frames = align_up(size) / PAGE_SIZE;
-        let bitmap = Bitmap::new(bitmap_data, total_frames as usize);
+        let mut bitmap = Bitmap::new(bitmap_data, total_frames as usize);
 
         // Mark all frames as allocated initially
         bitmap.set_all();
@@ -43,16 +46,22 @@ impl FrameAllocator {
             total_frames,
             start_addr,
             end_addr: start_addr + align_up(size),
+            reserved: SpinLock::new(Vec::new()),
         }
     }
 
     /// Add a free memory region
+    ///
+    /// Frames in reserved regions stay allocated.
     pub fn add_free_region(&self, start: PhysAddr, size: u64) {
         let start_frame = align_down(start) / PAGE_SIZE;
         let end_frame = align_up(start + size) / PAGE_SIZE;
         let allocator_start_frame = self.start_addr / PAGE_SIZE;
 
         for frame in start_frame..end_frame {
+            if self.is_reserved(frame) {
+                continue;
+            }
             if frame >= allocator_start_frame && frame < self.end_addr / PAGE_SIZE {
                 let index = (frame - allocator_start_frame) as usize;
                 if index < self.bitmap.lock().bits() {
@@ -62,6 +71,28 @@ impl FrameAllocator {
         }
     }
 
+    /// Reserve a memory region
+    ///
+    /// Its frames are marked allocated, whether or not they are free now,
+    /// and are never freed again by `add_free_region`.
+    pub fn reserve_region(&self, start: PhysAddr, size: u64) {
+        let start_frame = align_down(start) / PAGE_SIZE;
+        let end_frame = align_up(start + size) / PAGE_SIZE;
+        self.reserved.lock().push((start_frame, end_frame));
+
+        let allocator_start_frame = self.start_addr / PAGE_SIZE;
+        let allocator_end_frame = self.end_addr / PAGE_SIZE;
+        let mut bitmap = self.bitmap.lock();
+        for frame in start_frame.max(allocator_start_frame)..end_frame.min(allocator_end_frame) {
+            bitmap.set_bit((frame - allocator_start_frame) as usize);
+        }
+    }
+
+    /// Check if a frame is in a reserved region
+    fn is_reserved(&self, frame: FrameNr) -> bool {
+        self.reserved.lock().iter().any(|&(first, last)| frame >= first && frame < last)
+    }
+
     /// Allocate a single frame
     pub fn allocate_frame(&self) -> Option<PhysAddr> {
         let mut bitmap = self.bitmap.lock();
@@ -141,7 +172,7 @@ impl FrameAllocator {
         let allocator_start = self.start_addr / PAGE_SIZE;
         let allocator_end = self.end_addr / PAGE_SIZE;
 
-        if frame < allocator_start || frame >= allocator_end {
+        if frame < allocator_start || frame >= allocator_end || self.is_reserved(frame) {
             return false;
         }
 
@@ -167,6 +198,11 @@ impl FrameAllocator {
             return false;
         }
 
+        // Reserved frames are never freed
+        if (start_frame..start_frame + count as u64).any(|frame| self.is_reserved(frame)) {
+            return false;
+        }
+
         let start_index = (start_frame - allocator_start) as usize;
         let mut bitmap = self.bitmap.lock();
 
@@ -266,11 +302,19 @@ pub fn get_frame_allocator() -> &'static FrameAllocator {
     }
 }
 
+/// Get the global frame allocator, if it is set up
+pub fn frame_allocator() -> Option<&'static FrameAllocator> {
+    unsafe { FRAME_ALLOCATOR.as_ref() }
+}
+
 /// Set up the global frame allocator
 ///
+/// Regions already reserved are taken away from it.
+///
 /// # Safety
 /// Must be called during initialization before using the allocator
 pub unsafe fn setup_allocator(allocator: FrameAllocator) {
+    crate::core::mm::reserved::exclude_from(&allocator);
     FRAME_ALLOCATOR = Some(allocator);
     FRAME_ALLOCATOR_INITIALIZED = true;
 }
@@ -0,0 +1,84 @@
+//! Allocator consistency checking
+//!
+//! An fsck for the buddy free lists and slab caches: walks the allocator
+//! metadata and reports broken invariants instead of letting them surface
+//! later as unrelated memory corruption. The cheap checks (magic numbers,
+//! block orders and placement, list linkage and counts, slab page lists)
+//! always run; the ones that visit every slab object or sort every free
+//! block are only built with the `debug` feature.
+
+use crate::core::mm::{buddy, slab};
+use alloc::vec::Vec;
+
+/// A broken allocator invariant
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    /// Buddy free list entry without the block magic
+    BuddyBadMagic { addr: usize },
+    /// Buddy block on the free list of another order
+    BuddyWrongOrder { addr: usize, list_order: u8, block_order: u8 },
+    /// Buddy block on a free list but not marked free
+    BuddyNotFree { addr: usize },
+    /// Buddy block outside the managed range or not aligned to its size
+    BuddyMisplaced { addr: usize, order: u8 },
+    /// Free list `prev`/`next` pointers disagree, or the list loops
+    BuddyBrokenLink { addr: usize },
+    /// Free list length differs from its recorded count
+    BuddyCountMismatch { order: u8, counted: usize, recorded: usize },
+    /// Free block overlapping another free block, e.g. on two lists
+    BuddyOverlap { addr: usize, other: usize },
+    /// Slab cache without the cache magic
+    SlabBadCacheMagic { cache: &'static str },
+    /// Slab page on more than one page list
+    SlabPageOnTwoLists { cache: &'static str, page: usize },
+    /// Slab page whose use count does not fit the list it is on
+    SlabPageWrongList { cache: &'static str, page: usize, inuse: u32, total: u32 },
+    /// Slab object header with neither the free nor the allocated magic
+    SlabBadObjectMagic { cache: &'static str, addr: usize },
+    /// Allocated objects in a slab page differ from its use count
+    SlabInUseMismatch { cache: &'static str, page: usize, counted: u32, recorded: u32 },
+}
+
+/// Result of a consistency check
+#[derive(Debug, Clone, Default)]
+pub struct ConsistencyReport {
+    /// Violations found, in the order they were found
+    pub violations: Vec<Violation>,
+    /// Buddy free blocks visited
+    pub buddy_blocks: usize,
+    /// Slab pages visited
+    pub slab_pages: usize,
+}
+
+impl ConsistencyReport {
+    /// Create an empty report
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a violation
+    pub fn report(&mut self, violation: Violation) {
+        self.violations.push(violation);
+    }
+
+    /// Check whether no violation was found
+    pub fn is_clean(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+/// Check the global buddy and slab allocators
+///
+/// Allocators that are not initialized are skipped.
+pub fn check_consistency() -> ConsistencyReport {
+    let mut report = ConsistencyReport::new();
+    buddy::check_free_lists(&mut report);
+    slab::check_caches(&mut report);
+
+    for violation in &report.violations {
+        crate::error!("mm: allocator inconsistency: {:?}", violation);
+    }
+    crate::debug!("mm: checked {} buddy blocks and {} slab pages, {} violations",
+                  report.buddy_blocks, report.slab_pages, report.violations.len());
+    report
+}
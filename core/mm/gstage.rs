@@ -6,9 +6,10 @@
 
 use crate::{Result, Error};
 use crate::core::mm::{PhysAddr, VirtAddr, PageNr, PAGE_SIZE, PAGE_SHIFT, PageFlags};
+use crate::libs::cpumask::{CpuMask, MAX_CPUS};
 use crate::core::sync::SpinLock;
-use alloc::{vec::Vec, vec};
-use core::sync::atomic::{AtomicU32, Ordering};
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec, vec};
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 
 /// Guest Virtual Address type
 pub type Gva = VirtAddr;
@@ -930,6 +931,20 @@ impl GStagePageTable {
         Ok(true)
     }
 
+    /// Set and clear flag bits on the leaf PTE mapping a GPA
+    pub fn update_leaf_flags(&self, gpa: Gpa, set: u64, clear: u64) -> Result<()> {
+        let (pte, _) = self.walk(gpa, false)?;
+
+        if !pte.is_valid() || !pte.is_leaf() {
+            return Err(Error::NotFound);
+        }
+
+        let vpn = self.extract_vpn(gpa, self.level);
+        let mut modified_pte = pte;
+        modified_pte.bits = (pte.bits & !clear) | set;
+        self.set_pte(vpn, modified_pte)
+    }
+
     /// Flush TLB entries for this page table
     pub fn flush_tlb(&self, gpa: Option<Gpa>, size: Option<u64>) {
         // In a real implementation, this would:
@@ -947,6 +962,29 @@ impl GStagePageTable {
     }
 }
 
+/// Remove `[gpa, gpa + size)` from a list of `(gpa, size, flags)` mapping records
+///
+/// Records partly inside the range keep the parts outside it, so a hole
+/// punched into a large mapping leaves one record on each side.
+fn remove_mapping_range(mappings: &mut Vec<(Gpa, u64, u64)>, gpa: Gpa, size: u64) {
+    let end = gpa.saturating_add(size);
+    let mut kept = Vec::with_capacity(mappings.len() + 1);
+    for &(start, len, flags) in mappings.iter() {
+        let map_end = start + len;
+        if map_end <= gpa || start >= end {
+            kept.push((start, len, flags));
+            continue;
+        }
+        if start < gpa {
+            kept.push((start, gpa - start, flags));
+        }
+        if map_end > end {
+            kept.push((end, map_end - end, flags));
+        }
+    }
+    *mappings = kept;
+}
+
 /// G-stage translation context
 pub struct GStageContext {
     /// VMID for this context
@@ -965,6 +1003,10 @@ pub struct GStageContext {
     pub root_pa: SpinLock<Option<PhysAddr>>,
     /// Context statistics
     pub stats: SpinLock<GStageStats>,
+    /// Mapped ranges as (GPA, size, flags)
+    pub mappings: SpinLock<Vec<(Gpa, u64, u64)>>,
+    /// Dirty page log while migration tracking is enabled
+    dirty_log: SpinLock<Option<DirtyLog>>,
 }
 
 /// G-stage context statistics
@@ -1000,6 +1042,8 @@ impl GStageContext {
             hgatp: SpinLock::new(0),
             root_pa: SpinLock::new(None),
             stats: SpinLock::new(GStageStats::default()),
+            mappings: SpinLock::new(Vec::new()),
+            dirty_log: SpinLock::new(None),
         }
     }
 
@@ -1022,6 +1066,8 @@ impl GStageContext {
             hgatp: SpinLock::new(0),
             root_pa: SpinLock::new(None),
             stats: SpinLock::new(GStageStats::default()),
+            mappings: SpinLock::new(Vec::new()),
+            dirty_log: SpinLock::new(None),
         })
     }
 
@@ -1152,7 +1198,9 @@ impl GStageContext {
     pub fn map(&self, gpa: Gpa, hpa: Hpa, size: u64, flags: u64) -> Result<()> {
         let root = self.root.lock();
         if let Some(ref root_table) = *root {
-            root_table.map(gpa, hpa, size, flags)
+            root_table.map(gpa, hpa, size, flags)?;
+            self.mappings.lock().push((gpa, size, flags));
+            Ok(())
         } else {
             Err(Error::InvalidState)
         }
@@ -1162,12 +1210,108 @@ impl GStageContext {
     pub fn unmap(&self, gpa: Gpa, size: u64) -> Result<()> {
         let root = self.root.lock();
         if let Some(ref root_table) = *root {
-            root_table.unmap(gpa, size)
+            root_table.unmap(gpa, size)?;
+            remove_mapping_range(&mut self.mappings.lock(), gpa, size);
+            Ok(())
         } else {
             Err(Error::InvalidState)
         }
     }
 
+    /// Set and clear PTE flag bits on every page in a GPA range
+    fn update_range_flags(&self, gpa: Gpa, size: u64, set: u64, clear: u64) -> Result<()> {
+        let root = self.root.lock();
+        let root_table = root.as_ref().ok_or(Error::InvalidState)?;
+
+        let mut page = gpa & !(PAGE_SIZE - 1);
+        while page < gpa + size {
+            root_table.update_leaf_flags(page, set, clear)?;
+            page += PAGE_SIZE;
+        }
+
+        Ok(())
+    }
+
+    /// Start logging guest writes for live migration
+    ///
+    /// Write-protects every writable mapping; the resulting write faults
+    /// are recorded by `handle_write_fault`.
+    pub fn enable_dirty_tracking(&self) -> Result<()> {
+        let regions: Vec<(Gpa, u64)> = self.mappings.lock()
+            .iter()
+            .filter(|&&(_, _, flags)| flags & gstage_pte::W != 0)
+            .map(|&(gpa, size, _)| (gpa, size))
+            .collect();
+
+        for &(gpa, size) in &regions {
+            self.update_range_flags(gpa, size, 0, gstage_pte::W)?;
+        }
+        self.flush_tlb_all();
+
+        *self.dirty_log.lock() = Some(DirtyLog::new(regions));
+        crate::debug!("Dirty tracking enabled for VMID {}", self.vmid);
+        Ok(())
+    }
+
+    /// Stop logging guest writes and restore write access
+    pub fn disable_dirty_tracking(&self) -> Result<()> {
+        let log = self.dirty_log.lock().take().ok_or(Error::InvalidState)?;
+
+        for &(gpa, size) in log.regions() {
+            self.update_range_flags(gpa, size, gstage_pte::W, 0)?;
+        }
+        self.flush_tlb_all();
+
+        crate::debug!("Dirty tracking disabled for VMID {}", self.vmid);
+        Ok(())
+    }
+
+    /// Check whether dirty tracking is enabled
+    pub fn dirty_tracking_enabled(&self) -> bool {
+        self.dirty_log.lock().is_some()
+    }
+
+    /// Handle a guest write fault
+    ///
+    /// Returns `Ok(true)` if the fault was caused by dirty tracking: the
+    /// page is marked dirty and write access restored so the guest can
+    /// retry. `Ok(false)` means the fault is a genuine access violation.
+    pub fn handle_write_fault(&self, gpa: Gpa) -> Result<bool> {
+        let mut log = self.dirty_log.lock();
+        let log = match log.as_mut() {
+            Some(log) => log,
+            None => return Ok(false),
+        };
+
+        if !log.record_write(gpa) {
+            return Ok(false);
+        }
+
+        let page = gpa & !(PAGE_SIZE - 1);
+        self.update_range_flags(page, PAGE_SIZE, gstage_pte::W | gstage_pte::D, 0)?;
+        self.flush_tlb(Some(page), Some(PAGE_SIZE));
+        Ok(true)
+    }
+
+    /// Return the pages dirtied since the last call and re-arm tracking
+    ///
+    /// Returned pages are write-protected again so later writes are caught.
+    pub fn fetch_and_clear_dirty(&self) -> Result<DirtyBitmap> {
+        let dirty = self.dirty_log.lock()
+            .as_mut()
+            .ok_or(Error::InvalidState)?
+            .fetch_and_clear();
+
+        for gpa in dirty.iter() {
+            self.update_range_flags(gpa, PAGE_SIZE, 0, gstage_pte::W | gstage_pte::D)?;
+        }
+        if !dirty.is_empty() {
+            self.flush_tlb_all();
+        }
+
+        Ok(dirty)
+    }
+
     /// Translate GPA to HPA with multi-format support and statistics
     pub fn translate(&self, gpa: Gpa) -> Result<Hpa> {
         // Update statistics
@@ -1304,6 +1448,297 @@ impl GStageContext {
     }
 }
 
+/// Bitmap of guest pages written since the last fetch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirtyBitmap {
+    /// GPA of the first tracked page
+    base: Gpa,
+    /// Number of tracked pages
+    pages: usize,
+    /// One bit per page
+    words: Vec<u64>,
+}
+
+impl DirtyBitmap {
+    /// Create an empty bitmap covering `[base, base + size)`
+    pub fn new(base: Gpa, size: u64) -> Self {
+        let pages = ((size + PAGE_SIZE - 1) / PAGE_SIZE) as usize;
+        Self {
+            base: base & !(PAGE_SIZE - 1),
+            pages,
+            words: vec![0; (pages + 63) / 64],
+        }
+    }
+
+    /// Get the GPA of the first tracked page
+    pub fn base(&self) -> Gpa {
+        self.base
+    }
+
+    /// Get the number of tracked pages
+    pub fn pages(&self) -> usize {
+        self.pages
+    }
+
+    /// Page index of a GPA, if tracked
+    fn index(&self, gpa: Gpa) -> Option<usize> {
+        if gpa < self.base {
+            return None;
+        }
+        let index = ((gpa - self.base) / PAGE_SIZE) as usize;
+        (index < self.pages).then_some(index)
+    }
+
+    /// Mark the page containing `gpa` dirty
+    ///
+    /// Returns false if the GPA is outside the bitmap.
+    pub fn set(&mut self, gpa: Gpa) -> bool {
+        match self.index(gpa) {
+            Some(index) => {
+                self.words[index / 64] |= 1 << (index % 64);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Check whether the page containing `gpa` is dirty
+    pub fn is_dirty(&self, gpa: Gpa) -> bool {
+        self.index(gpa)
+            .map(|index| self.words[index / 64] & (1 << (index % 64)) != 0)
+            .unwrap_or(false)
+    }
+
+    /// Get the number of dirty pages
+    pub fn count(&self) -> usize {
+        self.words.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    /// Check whether no page is dirty
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|&word| word == 0)
+    }
+
+    /// Iterate over the GPAs of dirty pages
+    pub fn iter(&self) -> impl Iterator<Item = Gpa> + '_ {
+        (0..self.pages)
+            .filter(move |&index| self.words[index / 64] & (1 << (index % 64)) != 0)
+            .map(move |index| self.base + index as u64 * PAGE_SIZE)
+    }
+
+    /// Clear every bit
+    pub fn clear(&mut self) {
+        self.words.iter_mut().for_each(|word| *word = 0);
+    }
+}
+
+/// Dirty tracking state of one G-stage context
+#[derive(Debug)]
+struct DirtyLog {
+    /// Write-protected ranges as (GPA, size)
+    regions: Vec<(Gpa, u64)>,
+    /// Pages dirtied since the last fetch
+    bitmap: DirtyBitmap,
+}
+
+impl DirtyLog {
+    /// Track writes to `regions`
+    fn new(regions: Vec<(Gpa, u64)>) -> Self {
+        let start = regions.iter().map(|&(gpa, _)| gpa).min().unwrap_or(0);
+        let end = regions.iter().map(|&(gpa, size)| gpa + size).max().unwrap_or(0);
+        Self {
+            regions,
+            bitmap: DirtyBitmap::new(start, end - start),
+        }
+    }
+
+    /// Get the tracked ranges
+    fn regions(&self) -> &[(Gpa, u64)] {
+        &self.regions
+    }
+
+    /// Record a write to `gpa`
+    ///
+    /// Returns false if the GPA is not covered by tracking.
+    fn record_write(&mut self, gpa: Gpa) -> bool {
+        let tracked = self.regions.iter().any(|&(start, size)| gpa >= start && gpa < start + size);
+        tracked && self.bitmap.set(gpa)
+    }
+
+    /// Take the current dirty set, leaving an empty one behind
+    fn fetch_and_clear(&mut self) -> DirtyBitmap {
+        let dirty = self.bitmap.clone();
+        self.bitmap.clear();
+        dirty
+    }
+}
+
+/// VMID each CPU is running guest code for, and the CPUs each VMID has run on
+pub struct RunningVmids {
+    state: SpinLock<RunningState>,
+}
+
+struct RunningState {
+    /// VMID per CPU, `None` while the CPU runs host code
+    cpus: [Option<Vmid>; MAX_CPUS],
+    /// CPUs that have entered each VMID
+    ///
+    /// A CPU keeps a VMID's translations in its TLB after it exits the
+    /// guest, so it stays here and keeps taking the VMID's shootdowns.
+    entered: BTreeMap<Vmid, CpuMask>,
+}
+
+impl RunningVmids {
+    /// Create a map with every CPU in the host
+    pub const fn new() -> Self {
+        Self { state: SpinLock::new(RunningState { cpus: [None; MAX_CPUS], entered: BTreeMap::new() }) }
+    }
+
+    /// Record the VMID `cpu` runs, or `None` when it returns to the host
+    pub fn set(&self, cpu: usize, vmid: Option<Vmid>) {
+        if cpu >= MAX_CPUS {
+            return;
+        }
+        let mut state = self.state.lock();
+        state.cpus[cpu] = vmid;
+        if let Some(vmid) = vmid {
+            state.entered.entry(vmid).or_insert_with(CpuMask::new).set(cpu as u32);
+        }
+    }
+
+    /// CPUs running `vmid`, leaving out `exclude_cpu`
+    pub fn cpus_running(&self, vmid: Vmid, exclude_cpu: usize) -> CpuMask {
+        self.state.lock().cpus.iter()
+            .enumerate()
+            .filter(|&(cpu, &running)| running == Some(vmid) && cpu != exclude_cpu)
+            .map(|(cpu, _)| cpu as u32)
+            .collect()
+    }
+
+    /// CPUs whose TLBs may hold translations of `vmid`: those running it
+    /// and those that ran it before, leaving out `exclude_cpu`
+    pub fn cpus_to_flush(&self, vmid: Vmid, exclude_cpu: usize) -> CpuMask {
+        let mut cpus = self.state.lock().entered.get(&vmid).copied().unwrap_or_default();
+        cpus.clear(exclude_cpu as u32);
+        cpus
+    }
+}
+
+impl Default for RunningVmids {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A stage-2 invalidation other CPUs must perform
+pub struct TlbShootdown {
+    /// VM whose translations are stale, `None` for every VM
+    pub vmid: Option<Vmid>,
+    /// Start of the unmapped range
+    pub gpa: Gpa,
+    /// Size of the unmapped range
+    pub size: u64,
+    /// CPUs that have not flushed yet
+    pending: AtomicU64,
+}
+
+impl TlbShootdown {
+    /// Create a request to be acknowledged by every CPU in `targets`
+    pub fn new(vmid: Option<Vmid>, gpa: Gpa, size: u64, targets: CpuMask) -> Self {
+        Self { vmid, gpa, size, pending: AtomicU64::new(targets.bits()) }
+    }
+
+    /// Record that `cpu` has flushed
+    pub fn ack(&self, cpu: usize) {
+        self.pending.fetch_and(!(1 << cpu), Ordering::Release);
+    }
+
+    /// Check whether every target CPU has flushed
+    pub fn is_complete(&self) -> bool {
+        self.pending.load(Ordering::Acquire) == 0
+    }
+}
+
+/// Shootdown requests waiting for each CPU
+static SHOOTDOWN_QUEUES: [SpinLock<Vec<Arc<TlbShootdown>>>; MAX_CPUS] =
+    [const { SpinLock::new(Vec::new()) }; MAX_CPUS];
+
+/// Invalidate this CPU's stage-2 translations of a VMID's GPA range
+///
+/// With no VMID, every guest's translations are invalidated.
+fn flush_local(vmid: Option<Vmid>, gpa: Gpa, size: u64) {
+    #[cfg(target_arch = "riscv64")]
+    {
+        use crate::arch::riscv64::cpu::asm;
+
+        // Larger ranges are flushed by VMID instead of page by page
+        const MAX_PAGE_FLUSHES: u64 = 64;
+
+        match vmid {
+            None => asm::hfence_gvma(),
+            Some(vmid) if size / PAGE_SIZE > MAX_PAGE_FLUSHES => asm::hfence_gvma_vmid(vmid as usize),
+            Some(vmid) => {
+                // HFENCE.GVMA takes the guest physical address shifted right by 2
+                let mut page = gpa & !(PAGE_SIZE - 1);
+                while page < gpa + size {
+                    asm::hfence_gvma_addr_vmid((page >> 2) as usize, vmid as usize);
+                    page += PAGE_SIZE;
+                }
+            }
+        }
+    }
+
+    #[cfg(not(target_arch = "riscv64"))]
+    let _ = (vmid, gpa, size);
+}
+
+/// Handle a `TlbFlush` IPI: flush and acknowledge every request queued for `cpu`
+pub fn handle_tlb_shootdown(cpu: usize) {
+    let requests = match SHOOTDOWN_QUEUES.get(cpu) {
+        Some(queue) => core::mem::take(&mut *queue.lock()),
+        None => return,
+    };
+
+    for request in requests {
+        flush_local(request.vmid, request.gpa, request.size);
+        request.ack(cpu);
+    }
+}
+
+/// Make the CPUs in `targets` flush a VMID's GPA range and wait until they have
+///
+/// `send` kicks one CPU, normally with a `TlbFlush` IPI. A CPU that cannot
+/// be kicked is not waited for; the first such error is returned. While
+/// waiting, `self_cpu` serves requests queued for it, so two CPUs shooting
+/// each other down do not wait on each other forever.
+fn shootdown<F>(self_cpu: usize, targets: CpuMask, vmid: Option<Vmid>, gpa: Gpa, size: u64, mut send: F) -> Result<()>
+where
+    F: FnMut(usize) -> Result<()>,
+{
+    if targets.is_empty() {
+        return Ok(());
+    }
+
+    let request = Arc::new(TlbShootdown::new(vmid, gpa, size, targets));
+    let mut result = Ok(());
+    for cpu in targets.iter().map(|cpu| cpu as usize) {
+        SHOOTDOWN_QUEUES[cpu].lock().push(request.clone());
+        if let Err(e) = send(cpu) {
+            crate::error!("TLB shootdown: failed to kick CPU {}: {:?}", cpu, e);
+            request.ack(cpu);
+            result = result.and(Err(e));
+        }
+    }
+
+    loop {
+        handle_tlb_shootdown(self_cpu);
+        if request.is_complete() {
+            return result;
+        }
+        core::hint::spin_loop();
+    }
+}
+
 /// G-stage manager for managing multiple VM contexts
 pub struct GStageManager {
     /// VMID allocation bitmap
@@ -1314,6 +1749,8 @@ pub struct GStageManager {
     contexts: SpinLock<Vec<Option<GStageContext>>>,
     /// Current active VMID
     active_vmid: SpinLock<Option<Vmid>>,
+    /// VMID each CPU is running, for TLB shootdowns
+    running: RunningVmids,
 }
 
 impl GStageManager {
@@ -1323,8 +1760,9 @@ impl GStageManager {
         Self {
             vmid_bitmap: SpinLock::new(vec![0; bitmap_size]),
             max_vmid,
-            contexts: SpinLock::new(vec![None; (max_vmid + 1) as usize]),
+            contexts: SpinLock::new((0..=max_vmid).map(|_| None).collect()),
             active_vmid: SpinLock::new(None),
+            running: RunningVmids::new(),
         }
     }
 
@@ -1440,6 +1878,7 @@ impl GStageManager {
             }
 
             *self.active_vmid.lock() = Some(vmid);
+            self.running.set(current_cpu(), Some(vmid));
             Ok(())
         } else {
             Err(Error::NotFound)
@@ -1451,6 +1890,54 @@ impl GStageManager {
         *self.active_vmid.lock()
     }
 
+    /// Record the VMID `cpu` runs, or `None` when it returns to the host
+    pub fn set_cpu_vmid(&self, cpu: usize, vmid: Option<Vmid>) {
+        self.running.set(cpu, vmid);
+    }
+
+    /// Unmap a GPA range of a VM and invalidate its translations on all CPUs
+    ///
+    /// The other CPUs that have run the VM are sent a `TlbFlush` IPI, also
+    /// those that have since exited it. Returns once all of them have
+    /// flushed, so the pages that backed the range can be freed.
+    pub fn unmap(&self, vmid: Vmid, gpa: Gpa, size: u64) -> Result<()> {
+        let context = self.get_context(vmid).ok_or(Error::NotFound)?;
+        context.unmap(gpa, size)?;
+
+        let self_cpu = current_cpu();
+        flush_local(Some(vmid), gpa, size);
+        let targets = self.running.cpus_to_flush(vmid, self_cpu);
+        shootdown(self_cpu, targets, Some(vmid), gpa, size, send_tlb_flush)
+    }
+
+    /// Invalidate every translation of a VM on all CPUs that have run it
+    pub fn flush_vmid(&self, vmid: Vmid) -> Result<()> {
+        let self_cpu = current_cpu();
+        flush_local(Some(vmid), 0, u64::MAX);
+        let targets = self.running.cpus_to_flush(vmid, self_cpu);
+        shootdown(self_cpu, targets, Some(vmid), 0, u64::MAX, send_tlb_flush)
+    }
+
+    /// Start dirty page tracking for a VM
+    pub fn enable_dirty_tracking(&self, vmid: Vmid) -> Result<()> {
+        self.get_context(vmid).ok_or(Error::NotFound)?.enable_dirty_tracking()
+    }
+
+    /// Stop dirty page tracking for a VM
+    pub fn disable_dirty_tracking(&self, vmid: Vmid) -> Result<()> {
+        self.get_context(vmid).ok_or(Error::NotFound)?.disable_dirty_tracking()
+    }
+
+    /// Route a guest write fault to a VM's dirty tracker
+    pub fn handle_write_fault(&self, vmid: Vmid, gpa: Gpa) -> Result<bool> {
+        self.get_context(vmid).ok_or(Error::NotFound)?.handle_write_fault(gpa)
+    }
+
+    /// Fetch and reset the dirty page bitmap of a VM
+    pub fn fetch_and_clear_dirty(&self, vmid: Vmid) -> Result<DirtyBitmap> {
+        self.get_context(vmid).ok_or(Error::NotFound)?.fetch_and_clear_dirty()
+    }
+
     /// Translate GPA for active VM
     pub fn translate_active(&self, gpa: Gpa) -> Result<Hpa> {
         if let Some(vmid) = self.get_active_vmid() {
@@ -1465,6 +1952,28 @@ impl GStageManager {
     }
 }
 
+/// ID of the calling CPU
+fn current_cpu() -> usize {
+    crate::arch::cpu::get_current_cpu_id().unwrap_or(0) as usize
+}
+
+/// Kick `cpu` to serve its queued shootdowns
+fn send_tlb_flush(cpu: usize) -> Result<()> {
+    crate::core::irq::send_ipi(cpu, crate::core::irq::IpiType::TlbFlush)
+}
+
+/// Invalidate every guest's stage-2 translations on all online CPUs
+///
+/// For guests without a VMID, whose translations cannot be told apart
+/// from other guests' or traced to the CPUs that ran them.
+pub fn flush_all_guests() -> Result<()> {
+    let self_cpu = current_cpu();
+    flush_local(None, 0, u64::MAX);
+    let mut targets = crate::arch::cpu::get_online_cpu_mask();
+    targets.clear(self_cpu as u32);
+    shootdown(self_cpu, targets, None, 0, u64::MAX, send_tlb_flush)
+}
+
 /// Global G-stage manager
 static mut G_STAGE_MANAGER: Option<GStageManager> = None;
 static G_STAGE_MANAGER_INIT: SpinLock<bool> = SpinLock::new(false);
@@ -1762,6 +2271,11 @@ pub mod flags {
             gstage_flags |= gstage_pte::G;
         }
 
+        // Svpbmt memory type for device and write-combining mappings
+        if crate::core::mm::pbmt_enabled() {
+            gstage_flags |= flags.memory_type.riscv_pte_bits();
+        }
+
         // Set accessed bit initially
         gstage_flags |= gstage_pte::A;
 
@@ -1782,6 +2296,7 @@ pub mod flags {
             global: (gstage_flags & gstage_pte::G) != 0,
             cow: false,
             write_protected: false,
+            memory_type: crate::core::mm::MemoryType::Normal,
         }
     }
 
@@ -1799,4 +2314,147 @@ pub mod flags {
     pub const fn exec_only_gstage_flags() -> u64 {
         gstage_pte::X | gstage_pte::U | gstage_pte::A
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unmap_splits_mapping_records() {
+        let mut mappings = vec![(0x8000_0000, 0x10_0000, 0x7), (0x9000_0000, 0x1000, 0x3)];
+
+        // Punch a hole into the middle of the first mapping
+        remove_mapping_range(&mut mappings, 0x8000_4000, 0x2000);
+        assert_eq!(mappings, [
+            (0x8000_0000, 0x4000, 0x7),
+            (0x8000_6000, 0xFA000, 0x7),
+            (0x9000_0000, 0x1000, 0x3),
+        ]);
+
+        // Trim the head of a record and drop one fully covered
+        remove_mapping_range(&mut mappings, 0x8000_0000, 0x1000);
+        remove_mapping_range(&mut mappings, 0x9000_0000, 0x1000);
+        assert_eq!(mappings, [(0x8000_1000, 0x3000, 0x7), (0x8000_6000, 0xFA000, 0x7)]);
+    }
+
+    #[test]
+    fn test_dirty_log_records_written_page() {
+        let mut log = DirtyLog::new(vec![(0x8000_0000, 16 * PAGE_SIZE)]);
+
+        assert!(log.record_write(0x8000_3010));
+
+        let dirty = log.fetch_and_clear();
+        assert_eq!(dirty.count(), 1);
+        assert!(dirty.is_dirty(0x8000_3000));
+        assert!(!dirty.is_dirty(0x8000_2000));
+        assert_eq!(dirty.iter().collect::<Vec<_>>(), vec![0x8000_3000]);
+    }
+
+    #[test]
+    fn test_dirty_log_fetch_and_clear_resets() {
+        let mut log = DirtyLog::new(vec![(0x8000_0000, 16 * PAGE_SIZE)]);
+
+        log.record_write(0x8000_0000);
+        log.record_write(0x8000_f000);
+        assert_eq!(log.fetch_and_clear().count(), 2);
+
+        assert!(log.fetch_and_clear().is_empty());
+
+        log.record_write(0x8000_5000);
+        assert_eq!(log.fetch_and_clear().iter().collect::<Vec<_>>(), vec![0x8000_5000]);
+    }
+
+    #[test]
+    fn test_dirty_log_ignores_untracked() {
+        let mut log = DirtyLog::new(vec![(0x8000_0000, PAGE_SIZE), (0x8000_4000, PAGE_SIZE)]);
+
+        // Gap between regions and outside the span
+        assert!(!log.record_write(0x8000_2000));
+        assert!(!log.record_write(0x9000_0000));
+        assert!(log.record_write(0x8000_4ff8));
+
+        let dirty = log.fetch_and_clear();
+        assert_eq!(dirty.count(), 1);
+        assert!(dirty.is_dirty(0x8000_4000));
+    }
+
+    #[test]
+    fn test_update_leaf_flags_write_protects() {
+        let table = GStagePageTable::new(GStageLevel::Root, 1, GStageMode::Sv39X4, 0, 0);
+        let gpa = 0x4000_0000;
+        let vpn = table.extract_vpn(gpa, GStageLevel::Root);
+        table.set_pte(vpn, GStagePte::leaf(0x80000, flags::default_gstage_flags())).unwrap();
+
+        table.update_leaf_flags(gpa, 0, gstage_pte::W).unwrap();
+        assert!(!table.get_pte(vpn).unwrap().can_write());
+
+        table.update_leaf_flags(gpa, gstage_pte::W | gstage_pte::D, 0).unwrap();
+        let pte = table.get_pte(vpn).unwrap();
+        assert!(pte.can_write() && pte.is_dirty());
+
+        // Nothing mapped here
+        assert_eq!(table.update_leaf_flags(0x8000_0000, 0, gstage_pte::W), Err(Error::NotFound));
+    }
+
+    #[test]
+    fn test_shootdown_targets_cpus_running_vmid() {
+        let running = RunningVmids::new();
+        running.set(0, Some(5));
+        running.set(1, Some(5));
+        running.set(2, Some(6));
+        running.set(3, Some(5));
+
+        // CPU 0 unmaps and flushes itself; only CPUs 1 and 3 run VMID 5
+        let targets = running.cpus_running(5, 0);
+        assert_eq!(targets, CpuMask::from_bits(0b1010));
+
+        let mut kicked = Vec::new();
+        shootdown(0, targets, Some(5), 0x8000_0000, PAGE_SIZE, |cpu| {
+            kicked.push(cpu);
+            handle_tlb_shootdown(cpu);
+            Ok(())
+        }).unwrap();
+        assert_eq!(kicked, [1, 3]);
+
+        // CPU 1 waits for CPU 0 while CPU 0's own request to CPU 1 is
+        // still queued: CPU 1 serves it while waiting
+        let request = Arc::new(TlbShootdown::new(Some(5), 0x8000_0000, PAGE_SIZE, CpuMask::from_cpu(1)));
+        SHOOTDOWN_QUEUES[1].lock().push(request.clone());
+        shootdown(1, CpuMask::from_cpu(0), Some(5), 0x8000_0000, PAGE_SIZE, |cpu| {
+            handle_tlb_shootdown(cpu);
+            Ok(())
+        }).unwrap();
+        assert!(request.is_complete());
+
+        running.set(3, None);
+        assert_eq!(running.cpus_running(5, 0), CpuMask::from_cpu(1));
+        assert!(running.cpus_running(7, 0).is_empty());
+    }
+
+    #[test]
+    fn test_shootdown_reaches_cpu_that_exited_vmid() {
+        // CPUs apart from the other shootdown test's, whose queues it drains
+        let running = RunningVmids::new();
+        running.set(6, Some(9));
+        running.set(6, None);
+        running.set(5, Some(9));
+
+        // CPU 6 has left the guest but still caches VMID 9's translations
+        assert_eq!(running.cpus_running(9, 4), CpuMask::from_cpu(5));
+        let targets = running.cpus_to_flush(9, 4);
+        assert_eq!(targets, CpuMask::from_bits(0b110_0000));
+
+        let mut kicked = Vec::new();
+        shootdown(4, targets, Some(9), 0x8000_0000, PAGE_SIZE, |cpu| {
+            kicked.push(cpu);
+            handle_tlb_shootdown(cpu);
+            Ok(())
+        }).unwrap();
+        assert_eq!(kicked, [5, 6]);
+
+        // The unmapping CPU flushes itself
+        assert_eq!(running.cpus_to_flush(9, 5), CpuMask::from_cpu(6));
+        assert!(running.cpus_to_flush(7, 4).is_empty());
+    }
+}
@@ -0,0 +1,155 @@
+//! Boot memory map
+//!
+//! Platform code registers the physical memory layout here before
+//! `core::mm::init`, which prints it and checks it for obvious
+//! misconfiguration before the hypervisor starts handing out memory.
+
+use crate::{Result, Error};
+use crate::core::mm::{MemoryRegion, MemoryRegionKind, PhysAddr};
+use crate::core::sync::SpinLock;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// Problem found in the memory map
+#[derive(Debug, Clone, Copy)]
+pub enum MemoryMapError {
+    /// No Available memory was registered
+    NoAvailableMemory,
+    /// A Kernel region overlaps an Available region
+    KernelOverlap { kernel: MemoryRegion, available: MemoryRegion },
+}
+
+impl fmt::Display for MemoryMapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MemoryMapError::NoAvailableMemory => write!(f, "no available memory"),
+            MemoryMapError::KernelOverlap { kernel, available } => write!(
+                f, "kernel region [{:#x}..{:#x}) overlaps available region [{:#x}..{:#x})",
+                kernel.start, region_end(kernel), available.start, region_end(available)),
+        }
+    }
+}
+
+/// One past the last address of a region
+fn region_end(region: &MemoryRegion) -> PhysAddr {
+    region.start.saturating_add(region.size)
+}
+
+/// Check a memory map for misconfiguration
+///
+/// The map must contain some Available memory, and no Kernel region may
+/// overlap an Available one.
+pub fn check_memory_map(regions: &[MemoryRegion]) -> core::result::Result<(), MemoryMapError> {
+    let available = regions.iter().filter(|r| r.kind == MemoryRegionKind::Available);
+    if available.clone().map(|r| r.size).sum::<u64>() == 0 {
+        return Err(MemoryMapError::NoAvailableMemory);
+    }
+
+    for kernel in regions.iter().filter(|r| r.kind == MemoryRegionKind::Kernel) {
+        if let Some(available) = available.clone()
+            .find(|a| a.start < region_end(kernel) && kernel.start < region_end(a))
+        {
+            return Err(MemoryMapError::KernelOverlap { kernel: *kernel, available: *available });
+        }
+    }
+
+    Ok(())
+}
+
+/// Physical memory regions registered at boot
+static MEMORY_MAP: SpinLock<Vec<MemoryRegion>> = SpinLock::new(Vec::new());
+
+/// Register a physical memory region
+pub fn register_memory_region(region: MemoryRegion) {
+    MEMORY_MAP.lock().push(region);
+}
+
+/// Get a copy of the registered memory map
+pub fn memory_map() -> Vec<MemoryRegion> {
+    MEMORY_MAP.lock().clone()
+}
+
+/// Log the registered memory map with per-kind totals
+pub fn dump_memory_map() {
+    let mut regions = memory_map();
+    regions.sort_by_key(|r| r.start);
+
+    crate::info!("Physical memory map ({} regions):", regions.len());
+    for region in &regions {
+        let flags = region.flags;
+        crate::info!(
+            "  [{:#012x}..{:#012x}) {:>8} KiB {:?} {}{}{}{}",
+            region.start,
+            region_end(region),
+            region.size / 1024,
+            region.kind,
+            if flags.readable { 'r' } else { '-' },
+            if flags.writable { 'w' } else { '-' },
+            if flags.executable { 'x' } else { '-' },
+            if flags.device { " device" } else if flags.cached { "" } else { " uncached" },
+        );
+    }
+
+    let total = |kind| regions.iter().filter(|r| r.kind == kind).map(|r| r.size).sum::<u64>();
+    crate::info!(
+        "  available {} KiB, kernel {} KiB, reserved {} KiB",
+        total(MemoryRegionKind::Available) / 1024,
+        total(MemoryRegionKind::Kernel) / 1024,
+        total(MemoryRegionKind::Reserved) / 1024,
+    );
+}
+
+/// Validate the registered memory map
+pub fn validate_memory_map() -> Result<()> {
+    check_memory_map(&MEMORY_MAP.lock()).map_err(|err| {
+        crate::error!("mm: invalid memory map: {}", err);
+        Error::InvalidState
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::mm::MemoryRegionFlags;
+
+    fn region(start: PhysAddr, size: u64, kind: MemoryRegionKind) -> MemoryRegion {
+        MemoryRegion { start, size, kind, flags: MemoryRegionFlags::default() }
+    }
+
+    #[test]
+    fn test_kernel_overlapping_available_reported() {
+        let regions = [
+            region(0x4000_0000, 0x4000_0000, MemoryRegionKind::Available),
+            region(0x0900_0000, 0x1000, MemoryRegionKind::Mmio),
+            region(0x7ff0_0000, 0x20_0000, MemoryRegionKind::Kernel),
+        ];
+
+        match check_memory_map(&regions) {
+            Err(MemoryMapError::KernelOverlap { kernel, available }) => {
+                assert_eq!(kernel.start, 0x7ff0_0000);
+                assert_eq!(available.start, 0x4000_0000);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_adjacent_kernel_region_accepted() {
+        let regions = [
+            region(0x4000_0000, 0x20_0000, MemoryRegionKind::Kernel),
+            region(0x4020_0000, 0x3fe0_0000, MemoryRegionKind::Available),
+        ];
+        assert!(check_memory_map(&regions).is_ok());
+    }
+
+    #[test]
+    fn test_no_available_memory_reported() {
+        assert!(matches!(check_memory_map(&[]), Err(MemoryMapError::NoAvailableMemory)));
+
+        let regions = [
+            region(0x4000_0000, 0x20_0000, MemoryRegionKind::Kernel),
+            region(0x5000_0000, 0, MemoryRegionKind::Available),
+        ];
+        assert!(matches!(check_memory_map(&regions), Err(MemoryMapError::NoAvailableMemory)));
+    }
+}
@@ -13,6 +13,11 @@ pub mod buddy;
 pub mod allocator;
 pub mod hugepage;
 pub mod gstage;
+pub mod memmap;
+pub mod arena;
+pub mod pressure;
+pub mod fsck;
+pub mod reserved;
 
 // Re-export commonly used types
 pub use page::{AddressSpace, AddressSpaceType};
@@ -23,6 +28,9 @@ pub use gstage::{GStageContext, GStageManager, GStagePageTable, GStagePte, GStag
 pub use gstage::{Gva, Gpa, Hpa, Vmid, init as init_gstage, get as get_gstage_manager, get_expect as get_gstage_manager_expect};
 pub use gstage::gstage_pte;
 pub use gstage::flags as gstage_flags;
+pub use memmap::{register_memory_region, memory_map, dump_memory_map, validate_memory_map};
+pub use arena::{Arena, ArenaVec};
+pub use fsck::{check_consistency, ConsistencyReport, Violation};
 
 /// Physical address type
 pub type PhysAddr = u64;
@@ -216,6 +224,82 @@ pub enum AddressSpaceType {
     Physical,
 }
 
+/// Memory type of a mapping
+///
+/// Selects the cacheability and ordering the CPU applies to accesses. Each
+/// architecture encodes it differently in the page table entry:
+/// - aarch64: AttrIndx into the default `MairConfig` layout
+/// - riscv64: Svpbmt PBMT field (PMA, NC or IO), only while `pbmt_enabled`
+/// - x86_64: PWT/PCD selecting a PAT entry, assuming the PAT layout WB, WC,
+///   UC-, UC in entries 0-3
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MemoryType {
+    /// Cacheable RAM
+    #[default]
+    Normal,
+    /// Device registers: uncached, strongly ordered, early write ack allowed
+    Device,
+    /// Uncached, writes may be combined (framebuffers)
+    WriteCombining,
+    /// Uncached and strongly ordered
+    Uncached,
+}
+
+impl MemoryType {
+    /// aarch64 MAIR attribute index
+    pub const fn aarch64_attr_index(self) -> u64 {
+        match self {
+            MemoryType::Uncached => 0,       // Device-nGnRnE
+            MemoryType::Device => 1,         // Device-nGnRE
+            MemoryType::Normal => 2,         // Normal WB-WA
+            MemoryType::WriteCombining => 4, // Normal NC
+        }
+    }
+
+    /// aarch64 stage-1 PTE AttrIndx field, bits [4:2]
+    pub const fn aarch64_pte_bits(self) -> u64 {
+        self.aarch64_attr_index() << 2
+    }
+
+    /// riscv64 Svpbmt PBMT field, bits [62:61]
+    pub const fn riscv_pte_bits(self) -> u64 {
+        let pbmt = match self {
+            MemoryType::Normal => 0,         // PMA
+            MemoryType::WriteCombining => 1, // NC
+            MemoryType::Device | MemoryType::Uncached => 2, // IO
+        };
+        pbmt << 61
+    }
+
+    /// x86_64 PWT (bit 3) and PCD (bit 4) selecting the PAT entry
+    pub const fn x86_pte_bits(self) -> u64 {
+        const PWT: u64 = 1 << 3;
+        const PCD: u64 = 1 << 4;
+        match self {
+            MemoryType::Normal => 0,            // PAT0: WB
+            MemoryType::WriteCombining => PWT,  // PAT1: WC
+            MemoryType::Uncached => PCD,        // PAT2: UC-
+            MemoryType::Device => PCD | PWT,    // PAT3: UC
+        }
+    }
+}
+
+/// Set once the hart implements Svpbmt and henvcfg.PBMTE is enabled
+static PBMT_ENABLED: core::sync::atomic::AtomicBool = core::sync::atomic::AtomicBool::new(false);
+
+/// Record whether riscv64 page tables may carry a PBMT memory type
+///
+/// Without Svpbmt the PBMT bits are reserved and must stay zero, so every
+/// mapping falls back to the PMA attributes of the physical region.
+pub fn set_pbmt_enabled(enabled: bool) {
+    PBMT_ENABLED.store(enabled, core::sync::atomic::Ordering::Release);
+}
+
+/// Whether riscv64 page tables may carry a PBMT memory type
+pub fn pbmt_enabled() -> bool {
+    PBMT_ENABLED.load(core::sync::atomic::Ordering::Acquire)
+}
+
 /// Page table entry flags
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct PageFlags {
@@ -241,6 +325,8 @@ pub struct PageFlags {
     pub cow: bool,
     /// Write-protected (for COW)
     pub write_protected: bool,
+    /// Cacheability and ordering of the mapping
+    pub memory_type: MemoryType,
 }
 
 impl Default for PageFlags {
@@ -257,6 +343,7 @@ impl Default for PageFlags {
             global: false,
             cow: false,
             write_protected: false,
+            memory_type: MemoryType::Normal,
         }
     }
 }
@@ -276,6 +363,7 @@ impl PageFlags {
             global: false,
             cow: true,
             write_protected: true,
+            memory_type: MemoryType::Normal,
         }
     }
 
@@ -298,6 +386,18 @@ impl PageFlags {
             global: false,
             cow: false,            // No longer COW
             write_protected: false,
+            memory_type: MemoryType::Normal,
+        }
+    }
+
+    /// Flags for a read-write, non-executable MMIO mapping of `memory_type`
+    pub fn mmio(memory_type: MemoryType) -> Self {
+        Self {
+            writable: true,
+            executable: false,
+            cache_disable: memory_type != MemoryType::Normal,
+            memory_type,
+            ..Self::default()
         }
     }
 }
@@ -313,8 +413,13 @@ pub fn init() -> Result<()> {
     // Initialize heap allocator
     heap::init()?;
 
-    // Initialize buddy allocator
-    buddy::init(0x80000000, 64 * 1024 * 1024) // 64MB starting at 2GB
+    // Initialize buddy allocator, keeping the hypervisor image and the
+    // reserved regions out of it
+    let mut holes = reserved::ranges();
+    if let Some((start, end)) = image_range() {
+        holes.push(start as usize..end as usize);
+    }
+    buddy::init(0x80000000, 64 * 1024 * 1024, &holes) // 64MB starting at 2GB
         .map_err(|_| crate::Error::MemoryError)?;
 
     // Initialize slab allocator
@@ -332,9 +437,74 @@ pub fn init() -> Result<()> {
     // Initialize G-stage address translation (support up to 256 VMs)
     gstage::init(255)?;
 
+    // Check what the allocators actually hand out against the image
+    register_allocator_regions();
+    dump_memory_map();
+    validate_memory_map()?;
+
+    // Catch allocator bookkeeping bugs before anything depends on it
+    if cfg!(feature = "debug") && !check_consistency().is_clean() {
+        return Err(crate::Error::MemoryError);
+    }
+
     Ok(())
 }
 
+/// Add the buddy pool and the hypervisor image to the memory map
+///
+/// The pool is what the frame allocators hand out, so it is checked as
+/// Available memory whether or not platform code registered it.
+fn register_allocator_regions() {
+    let map = memory_map();
+    let registered = |start: PhysAddr, size: u64, kind: MemoryRegionKind| {
+        map.iter().any(|r| r.kind == kind && r.start <= start && start + size <= r.start + r.size)
+    };
+
+    if let Some((base, size)) = buddy::pool_range() {
+        let (base, size) = (base as PhysAddr, size as u64);
+        if size != 0 && !registered(base, size, MemoryRegionKind::Available) {
+            register_memory_region(MemoryRegion {
+                start: base,
+                size,
+                kind: MemoryRegionKind::Available,
+                flags: MemoryRegionFlags::default(),
+            });
+        }
+    }
+
+    if let Some((start, end)) = image_range() {
+        let size = end - start;
+        if size != 0 && !registered(start, size, MemoryRegionKind::Kernel) {
+            register_memory_region(MemoryRegion {
+                start,
+                size,
+                kind: MemoryRegionKind::Kernel,
+                flags: MemoryRegionFlags::default(),
+            });
+        }
+    }
+}
+
+/// Physical range of the hypervisor image, text through boot stacks
+#[cfg(target_os = "none")]
+fn image_range() -> Option<(PhysAddr, PhysAddr)> {
+    extern "C" {
+        static __text_start: u8;
+        static __stack_end: u8;
+    }
+
+    let (start, end) = unsafe {
+        (&__text_start as *const u8 as PhysAddr, &__stack_end as *const u8 as PhysAddr)
+    };
+    Some((start, end))
+}
+
+/// Hosted builds have no linker-script image layout
+#[cfg(not(target_os = "none"))]
+fn image_range() -> Option<(PhysAddr, PhysAddr)> {
+    None
+}
+
 /// Align an address down to page boundary
 pub const fn align_down(addr: u64) -> u64 {
     addr & PAGE_MASK
@@ -470,4 +640,49 @@ pub mod barrier {
         #[cfg(target_arch = "x86_64")]
         unsafe { core::arch::asm!("lfence") };
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_device_memory_type_pte_bits() {
+        let flags = PageFlags::mmio(MemoryType::Device);
+        assert!(!flags.executable);
+        assert!(flags.cache_disable);
+
+        // aarch64: AttrIndx 1, Device-nGnRE in the default MAIR layout
+        assert_eq!(flags.memory_type.aarch64_pte_bits(), 0b001 << 2);
+        assert_eq!(MemoryType::Normal.aarch64_pte_bits(), 0b010 << 2);
+        assert_eq!(MemoryType::WriteCombining.aarch64_pte_bits(), 0b100 << 2);
+
+        // riscv64: PBMT = IO
+        assert_eq!(flags.memory_type.riscv_pte_bits(), 2 << 61);
+        assert_eq!(MemoryType::WriteCombining.riscv_pte_bits(), 1 << 61);
+        assert_eq!(MemoryType::Normal.riscv_pte_bits(), 0);
+
+        // x86_64: PCD | PWT selects UC
+        assert_eq!(flags.memory_type.x86_pte_bits(), 0x18);
+        assert_eq!(MemoryType::WriteCombining.x86_pte_bits(), 0x08);
+        assert_eq!(MemoryType::Normal.x86_pte_bits(), 0);
+
+        // Full entries: memory type lands in the attribute field and does
+        // not overlap the permission bits
+        let user = PageFlags { user: true, ..flags };
+        let entry = page::make_aarch64_pt_entry(0x1000_0000, user);
+        assert_eq!(entry & (0b111 << 2), 0b001 << 2);
+        assert_eq!(entry & (1 << 6), 1 << 6); // AP[1]: EL0 access
+        assert_eq!(entry & (0b11 << 53), 0b11 << 53); // PXN | UXN
+
+        let entry = page::make_riscv64_pt_entry(0x1000_0000, user, true);
+        assert_eq!(entry & (0b11 << 61), 2 << 61);
+        assert_eq!((entry >> 10) & ((1 << 44) - 1), 0x1000_0000 >> 12);
+        // PBMT is reserved without Svpbmt
+        let entry = page::make_riscv64_pt_entry(0x1000_0000, user, false);
+        assert_eq!(entry & (0b11 << 61), 0);
+
+        let entry = page::make_x86_64_pt_entry(0x1000_0000, user);
+        assert_eq!(entry & 0x1C, 0x1C); // US | PWT | PCD
+    }
+}
@@ -5,7 +5,7 @@
 use crate::core::mm::{
     VirtAddr, PhysAddr, PageNr, FrameNr, PAGE_SIZE, PAGE_SHIFT,
     PageFlags, AddressSpaceType, align_up, align_down, flush_tlb_addr,
-    PageSize, should_use_huge_pages, optimal_page_size,
+    PageSize, should_use_huge_pages, optimal_page_size, MemoryType,
 };
 use crate::core::mm::frame::{alloc_frame, dealloc_frame, alloc_contiguous_frames, dealloc_contiguous_frames};
 use crate::core::sync::SpinLock;
@@ -950,6 +950,7 @@ impl AddressSpace {
             global: false,
             cow: false,
             write_protected: false,
+            memory_type: MemoryType::Normal,
         };
 
         for (i, new_frame) in new_pages.into_iter().enumerate() {
@@ -1098,6 +1099,92 @@ pub fn virt_to_indices(virt_addr: VirtAddr) -> [usize; 4] {
 
 /// Calculate the page table entry for a physical address with flags
 pub fn make_pt_entry(phys_addr: PhysAddr, flags: PageFlags) -> u64 {
+    #[cfg(target_arch = "aarch64")]
+    return make_aarch64_pt_entry(phys_addr, flags);
+    #[cfg(target_arch = "riscv64")]
+    return make_riscv64_pt_entry(phys_addr, flags, crate::core::mm::pbmt_enabled());
+    #[cfg(not(any(target_arch = "aarch64", target_arch = "riscv64")))]
+    return make_x86_64_pt_entry(phys_addr, flags);
+}
+
+/// aarch64 stage-1 level 3 page descriptor
+pub fn make_aarch64_pt_entry(phys_addr: PhysAddr, flags: PageFlags) -> u64 {
+    const VALID_PAGE: u64 = 0b11;
+    const AP_EL0: u64 = 1 << 6;
+    const AP_RO: u64 = 1 << 7;
+    const SH_INNER: u64 = 0b11 << 8;
+    const AF: u64 = 1 << 10;
+    const NG: u64 = 1 << 11;
+    const PXN: u64 = 1 << 53;
+    const UXN: u64 = 1 << 54;
+    const SW_COW: u64 = 1 << 55;
+
+    let mut entry = align_down(phys_addr) | VALID_PAGE | AF;
+
+    entry |= flags.memory_type.aarch64_pte_bits();
+    if flags.memory_type == MemoryType::Normal {
+        entry |= SH_INNER;
+    }
+    if !flags.writable {
+        entry |= AP_RO;
+    }
+    if flags.user {
+        entry |= AP_EL0;
+    }
+    if !flags.executable {
+        entry |= PXN | UXN;
+    }
+    if !flags.global {
+        entry |= NG;
+    }
+    if flags.cow {
+        entry |= SW_COW;
+    }
+
+    entry
+}
+
+/// riscv64 Sv39/Sv48 leaf entry
+///
+/// The PBMT field is only encoded when `pbmt` is set, since it is reserved
+/// (and must be zero) unless Svpbmt is implemented and enabled.
+pub fn make_riscv64_pt_entry(phys_addr: PhysAddr, flags: PageFlags, pbmt: bool) -> u64 {
+    const V: u64 = 1 << 0;
+    const R: u64 = 1 << 1;
+    const W: u64 = 1 << 2;
+    const X: u64 = 1 << 3;
+    const U: u64 = 1 << 4;
+    const G: u64 = 1 << 5;
+    const A: u64 = 1 << 6;
+    const D: u64 = 1 << 7;
+    const RSW_COW: u64 = 1 << 8;
+
+    let mut entry = ((align_down(phys_addr) >> PAGE_SHIFT) << 10) | V | R | A;
+
+    if flags.writable {
+        entry |= W | D;
+    }
+    if flags.executable {
+        entry |= X;
+    }
+    if flags.user {
+        entry |= U;
+    }
+    if flags.global {
+        entry |= G;
+    }
+    if flags.cow {
+        entry |= RSW_COW;
+    }
+    if pbmt {
+        entry |= flags.memory_type.riscv_pte_bits();
+    }
+
+    entry
+}
+
+/// x86_64 4-level paging leaf entry
+pub fn make_x86_64_pt_entry(phys_addr: PhysAddr, flags: PageFlags) -> u64 {
     let mut entry = align_down(phys_addr);
 
     if flags.writable {
@@ -1112,9 +1199,11 @@ pub fn make_pt_entry(phys_addr: PhysAddr, flags: PageFlags) -> u64 {
 
     // Handle COW-specific flags
     if flags.cow {
-        entry |= 0x200; // Architecture-specific COW/dirty bit
+        entry |= 0x200; // Available bit used for COW
     }
 
+    entry |= flags.memory_type.x86_pte_bits();
+
     entry | 0x1 // Present bit
 }
 
@@ -0,0 +1,219 @@
+//! Memory pressure notification
+//!
+//! When the allocator runs out of memory it notifies registered pressure
+//! callbacks before giving up. Callbacks free what they can, e.g. the
+//! balloon device asks guests to return free pages, and report how many
+//! pages they freed. Callbacks run in descending priority order and stop
+//! once enough pages were reported. Work that completes asynchronously is
+//! not reported, so the allocator does not retry before the memory is
+//! actually back.
+//!
+//! The registry is a fixed-size table so notifying never allocates.
+
+use crate::{Result, Error};
+use crate::core::sync::SpinLock;
+
+/// Maximum number of registered pressure callbacks
+pub const MAX_PRESSURE_CALLBACKS: usize = 16;
+
+/// Called with the number of pages the allocator is short of; returns the
+/// number of pages freed
+pub type PressureCallback = fn(pages: usize) -> usize;
+
+/// A registered pressure callback
+#[derive(Debug, Clone, Copy)]
+struct PressureNotifier {
+    /// Name, unique in the registry
+    name: &'static str,
+    /// Higher priorities are asked first
+    priority: u8,
+    /// Callback
+    callback: PressureCallback,
+}
+
+/// Memory pressure callback registry
+#[derive(Debug)]
+pub struct PressureRegistry {
+    /// Registered callbacks, sorted by descending priority
+    notifiers: [Option<PressureNotifier>; MAX_PRESSURE_CALLBACKS],
+    /// Number of times pressure was signalled
+    events: u64,
+}
+
+impl PressureRegistry {
+    /// Create an empty registry
+    pub const fn new() -> Self {
+        Self {
+            notifiers: [None; MAX_PRESSURE_CALLBACKS],
+            events: 0,
+        }
+    }
+
+    /// Register `callback` under `name`
+    ///
+    /// Callbacks of equal priority run in registration order. Fails with
+    /// `Error::ResourceBusy` if `name` is taken and
+    /// `Error::ResourceUnavailable` if the registry is full.
+    pub fn register(&mut self, name: &'static str, priority: u8, callback: PressureCallback) -> Result<()> {
+        if self.notifiers.iter().flatten().any(|n| n.name == name) {
+            return Err(Error::ResourceBusy);
+        }
+
+        let len = self.notifiers.iter().take_while(|n| n.is_some()).count();
+        if len == MAX_PRESSURE_CALLBACKS {
+            return Err(Error::ResourceUnavailable);
+        }
+
+        let pos = self.notifiers[..len]
+            .iter()
+            .flatten()
+            .position(|n| n.priority < priority)
+            .unwrap_or(len);
+        self.notifiers[pos..=len].rotate_right(1);
+        self.notifiers[pos] = Some(PressureNotifier { name, priority, callback });
+        Ok(())
+    }
+
+    /// Remove the callback registered under `name`
+    pub fn unregister(&mut self, name: &'static str) -> Result<()> {
+        let pos = self.notifiers
+            .iter()
+            .position(|n| n.map_or(false, |n| n.name == name))
+            .ok_or(Error::NotFound)?;
+        self.notifiers[pos] = None;
+        self.notifiers[pos..].rotate_left(1);
+        Ok(())
+    }
+
+    /// Number of registered callbacks
+    pub fn len(&self) -> usize {
+        self.notifiers.iter().flatten().count()
+    }
+
+    /// Check whether no callback is registered
+    pub fn is_empty(&self) -> bool {
+        self.notifiers[0].is_none()
+    }
+
+    /// Number of times pressure was signalled
+    pub fn events(&self) -> u64 {
+        self.events
+    }
+}
+
+impl Default for PressureRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Global pressure callback registry
+static PRESSURE_REGISTRY: SpinLock<PressureRegistry> = SpinLock::new(PressureRegistry::new());
+
+/// Register a callback invoked when the allocator runs out of memory
+pub fn on_memory_pressure(name: &'static str, priority: u8, callback: PressureCallback) -> Result<()> {
+    PRESSURE_REGISTRY.lock().register(name, priority, callback)?;
+    crate::debug!("Registered memory pressure callback '{}' (priority {})", name, priority);
+    Ok(())
+}
+
+/// Remove a memory pressure callback
+pub fn remove_memory_pressure_callback(name: &'static str) -> Result<()> {
+    PRESSURE_REGISTRY.lock().unregister(name)
+}
+
+/// Signal that `pages` pages are needed
+///
+/// Returns the number of pages the callbacks freed or are freeing.
+pub fn notify_memory_pressure(pages: usize) -> usize {
+    notify(&PRESSURE_REGISTRY, pages)
+}
+
+/// Run the callbacks of `registry` in priority order
+///
+/// The registry is snapshotted so callbacks run without its lock held and
+/// may register or allocate themselves.
+fn notify(registry: &SpinLock<PressureRegistry>, pages: usize) -> usize {
+    let notifiers = {
+        let mut registry = registry.lock();
+        registry.events += 1;
+        registry.notifiers
+    };
+
+    let mut freed = 0;
+    for notifier in notifiers.iter().flatten() {
+        if freed >= pages {
+            break;
+        }
+        let got = (notifier.callback)(pages - freed);
+        crate::debug!("Memory pressure: '{}' freed {} pages", notifier.name, got);
+        freed += got;
+    }
+    freed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    static CALLS: SpinLock<[&str; 4]> = SpinLock::new([""; 4]);
+    static NCALLS: AtomicUsize = AtomicUsize::new(0);
+
+    fn record(name: &'static str) {
+        CALLS.lock()[NCALLS.fetch_add(1, Ordering::SeqCst)] = name;
+    }
+
+    fn balloon(_pages: usize) -> usize {
+        record("balloon");
+        2
+    }
+
+    fn cache(_pages: usize) -> usize {
+        record("cache");
+        1
+    }
+
+    fn idle(_pages: usize) -> usize {
+        record("idle");
+        8
+    }
+
+    #[test]
+    fn test_callbacks_run_in_priority_order() {
+        let mut registry = PressureRegistry::new();
+        registry.register("cache", 10, cache).unwrap();
+        registry.register("balloon", 100, balloon).unwrap();
+        registry.register("idle", 10, idle).unwrap();
+        assert_eq!(registry.register("cache", 1, cache), Err(Error::ResourceBusy));
+        let registry = SpinLock::new(registry);
+
+        // The allocation only succeeds once callbacks freed enough pages
+        let freed = AtomicUsize::new(0);
+        let result = crate::core::mm::allocator::allocate_in_order(
+            &[crate::core::mm::allocator::AllocationStrategy::Buddy],
+            |_| {
+                if freed.load(Ordering::SeqCst) >= 4 {
+                    Ok(core::ptr::NonNull::dangling())
+                } else {
+                    Err(crate::core::mm::allocator::AllocationError::OutOfMemory)
+                }
+            },
+            || {
+                let pages = notify(&registry, 4);
+                freed.store(pages, Ordering::SeqCst);
+                pages
+            },
+        );
+        assert!(result.is_ok());
+
+        // Highest priority first, ties in registration order; stops once
+        // four pages were reported
+        assert_eq!(NCALLS.load(Ordering::SeqCst), 3);
+        assert_eq!(*CALLS.lock(), ["balloon", "cache", "idle", ""]);
+        assert_eq!(registry.lock().events(), 1);
+
+        registry.lock().unregister("balloon").unwrap();
+        assert_eq!(registry.lock().len(), 2);
+    }
+}
@@ -0,0 +1,263 @@
+//! Reserved memory (carveouts)
+//!
+//! Firmware and devices own some physical memory the hypervisor must never
+//! hand out as general-purpose frames: SBI firmware images, DMA pools,
+//! shared-memory windows. Platform code registers these regions at boot,
+//! usually from the device tree, and the frame allocator skips them.
+//!
+//! Every region has a name. Drivers that need memory from a particular
+//! carveout (e.g. a DMA pool) take it with `alloc_from`, which hands out
+//! page-aligned chunks from the start of the region and never past its
+//! end. Carveout memory is not returned.
+
+use crate::{Result, Error};
+use crate::core::mm::{PhysAddr, align_up};
+use crate::core::mm::frame::FrameAllocator;
+use crate::core::sync::SpinLock;
+use crate::libs::fdt::Fdt;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+/// A named reserved region
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Carveout {
+    /// Region name, unique among reserved regions
+    pub name: String,
+    /// Start physical address
+    pub start: PhysAddr,
+    /// Size in bytes
+    pub size: u64,
+    /// Bytes already handed out by `alloc_from`
+    pub used: u64,
+}
+
+impl Carveout {
+    /// One past the last address of the region
+    pub fn end(&self) -> PhysAddr {
+        self.start.saturating_add(self.size)
+    }
+
+    /// Check if an address is within the region
+    pub fn contains(&self, addr: PhysAddr) -> bool {
+        addr >= self.start && addr < self.end()
+    }
+
+    /// Check if the region overlaps `[start, start + size)`
+    fn overlaps(&self, start: PhysAddr, size: u64) -> bool {
+        start < self.end() && self.start < start.saturating_add(size)
+    }
+}
+
+/// Set of reserved regions
+#[derive(Debug, Default)]
+pub struct ReservedMemory {
+    /// Registered regions, in registration order
+    regions: Vec<Carveout>,
+}
+
+impl ReservedMemory {
+    /// Create an empty set
+    pub const fn new() -> Self {
+        Self { regions: Vec::new() }
+    }
+
+    /// Register a region
+    ///
+    /// Fails with `ResourceBusy` if the name is taken or the region
+    /// overlaps one already registered.
+    pub fn add(&mut self, name: &str, start: PhysAddr, size: u64) -> Result<()> {
+        if size == 0 {
+            return Err(Error::InvalidArgument);
+        }
+        if self.regions.iter().any(|r| r.name == name || r.overlaps(start, size)) {
+            return Err(Error::ResourceBusy);
+        }
+
+        self.regions.push(Carveout { name: String::from(name), start, size, used: 0 });
+        Ok(())
+    }
+
+    /// Allocate `size` bytes, rounded up to whole pages, from a named region
+    pub fn alloc_from(&mut self, name: &str, size: u64) -> Result<PhysAddr> {
+        let region = self.regions.iter_mut()
+            .find(|r| r.name == name)
+            .ok_or(Error::NotFound)?;
+
+        let size = align_up(size.max(1));
+        if size > region.size - region.used {
+            return Err(Error::OutOfMemory);
+        }
+
+        let addr = region.start + region.used;
+        region.used += size;
+        Ok(addr)
+    }
+
+    /// Check if an address is within any reserved region
+    pub fn contains(&self, addr: PhysAddr) -> bool {
+        self.regions.iter().any(|r| r.contains(addr))
+    }
+
+    /// Get a region by name
+    pub fn get(&self, name: &str) -> Option<&Carveout> {
+        self.regions.iter().find(|r| r.name == name)
+    }
+
+    /// Get all registered regions
+    pub fn regions(&self) -> &[Carveout] {
+        &self.regions
+    }
+}
+
+/// Reserved regions registered at boot
+static RESERVED: SpinLock<ReservedMemory> = SpinLock::new(ReservedMemory::new());
+
+/// Register a reserved region and take it away from the frame allocator
+pub fn reserve(name: &str, start: PhysAddr, size: u64) -> Result<()> {
+    RESERVED.lock().add(name, start, size)?;
+
+    if let Some(allocator) = crate::core::mm::frame::frame_allocator() {
+        allocator.reserve_region(start, size);
+    }
+    crate::core::mm::buddy::reserve(start as usize..(start + size) as usize);
+    crate::info!("Reserved {} [{:#x}..{:#x})", name, start, start + size);
+    Ok(())
+}
+
+/// Register the reserved regions described by a device tree
+///
+/// Covers both the memory reservation block and the children of
+/// `/reserved-memory` that have a `reg` property. Children that only ask
+/// for a dynamically placed `size` are not supported and are skipped, as
+/// are regions overlapping one already registered. Returns the number of
+/// regions registered.
+pub fn reserve_from_fdt(fdt: &Fdt) -> usize {
+    let mut registered = 0;
+
+    for (index, region) in fdt.reserved_memory().enumerate() {
+        let name = format!("rsvmap{}", index);
+        match reserve(&name, region.address, region.size) {
+            Ok(()) => registered += 1,
+            Err(e) => {
+                crate::warn!("Skipping reserved region {}: {:?}", name, e);
+            }
+        }
+    }
+
+    let mut in_reserved_memory = false;
+    for node in fdt.nodes() {
+        if node.depth() == 1 {
+            in_reserved_memory = node.unit_name() == "reserved-memory";
+            continue;
+        }
+        if node.depth() != 2 || !in_reserved_memory {
+            continue;
+        }
+
+        for region in node.reg() {
+            match reserve(node.name(), region.address, region.size) {
+                Ok(()) => registered += 1,
+                Err(e) => {
+                    crate::warn!("Skipping reserved region {}: {:?}", node.name(), e);
+                }
+            }
+        }
+    }
+
+    registered
+}
+
+/// Allocate `size` bytes from the named carveout
+pub fn alloc_from(name: &str, size: u64) -> Result<PhysAddr> {
+    RESERVED.lock().alloc_from(name, size)
+}
+
+/// Check if an address is in a reserved region
+pub fn is_reserved(addr: PhysAddr) -> bool {
+    RESERVED.lock().contains(addr)
+}
+
+/// Get a copy of a reserved region by name
+pub fn get(name: &str) -> Option<Carveout> {
+    RESERVED.lock().get(name).cloned()
+}
+
+/// Physical ranges of all registered regions
+pub fn ranges() -> Vec<Range<usize>> {
+    RESERVED.lock().regions()
+        .iter()
+        .map(|r| r.start as usize..r.end() as usize)
+        .collect()
+}
+
+/// Take all registered regions away from a frame allocator
+///
+/// Used when the frame allocator is set up after regions were reserved.
+pub fn exclude_from(allocator: &FrameAllocator) {
+    for region in RESERVED.lock().regions() {
+        allocator.reserve_region(region.start, region.size);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::mm::PAGE_SIZE;
+
+    #[test]
+    fn test_reserved_region_is_never_allocated() {
+        const FRAMES: u64 = 64;
+        let base: PhysAddr = 0x9000_0000;
+        let mut bitmap = [0u64; 1];
+        let allocator = unsafe { FrameAllocator::new(bitmap.as_mut_ptr(), base, FRAMES * PAGE_SIZE) };
+
+        // Reserved before the memory is handed to the allocator...
+        reserve("test-firmware", base + 8 * PAGE_SIZE, 4 * PAGE_SIZE).unwrap();
+        exclude_from(&allocator);
+        allocator.add_free_region(base, FRAMES * PAGE_SIZE);
+        // ...and after
+        allocator.reserve_region(base + 40 * PAGE_SIZE, 2 * PAGE_SIZE);
+
+        let mut allocated = 0;
+        while let Some(addr) = allocator.allocate_frame() {
+            assert!(!is_reserved(addr));
+            assert!(!(base + 40 * PAGE_SIZE..base + 42 * PAGE_SIZE).contains(&addr));
+            allocated += 1;
+        }
+        assert_eq!(allocated, FRAMES - 6);
+
+        // Freeing reserved frames is refused...
+        assert!(!allocator.deallocate_frame(base + 8 * PAGE_SIZE));
+        assert!(!allocator.deallocate_frames(base + 38 * PAGE_SIZE, 4));
+
+        // ...and freeing every frame again does not release them
+        allocator.add_free_region(base, FRAMES * PAGE_SIZE);
+        assert_eq!(allocator.free_frames() as u64, FRAMES - 6);
+        assert!(allocator.allocate_frames(4).is_some());
+        assert!(allocator.allocate_frame_at((base + 8 * PAGE_SIZE) / PAGE_SIZE).is_none());
+    }
+
+    #[test]
+    fn test_carveout_allocation_stays_within_region() {
+        let base: PhysAddr = 0xa000_0000;
+        let mut reserved = ReservedMemory::new();
+        reserved.add("dma", base, 4 * PAGE_SIZE).unwrap();
+        assert_eq!(reserved.add("dma", 0xb000_0000, PAGE_SIZE), Err(Error::ResourceBusy));
+        assert_eq!(reserved.add("other", base + PAGE_SIZE, PAGE_SIZE), Err(Error::ResourceBusy));
+
+        let first = reserved.alloc_from("dma", 100).unwrap();
+        let second = reserved.alloc_from("dma", PAGE_SIZE + 1).unwrap();
+        assert_eq!(first, base);
+        assert_eq!(second, base + PAGE_SIZE);
+
+        // Only one page is left
+        assert_eq!(reserved.alloc_from("dma", 2 * PAGE_SIZE), Err(Error::OutOfMemory));
+        let last = reserved.alloc_from("dma", PAGE_SIZE).unwrap();
+        assert!(last + PAGE_SIZE <= base + 4 * PAGE_SIZE);
+        assert_eq!(reserved.alloc_from("dma", 1), Err(Error::OutOfMemory));
+
+        assert_eq!(reserved.alloc_from("missing", PAGE_SIZE), Err(Error::NotFound));
+    }
+}
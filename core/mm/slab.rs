@@ -11,10 +11,12 @@
 //! - Thread-safe allocation/deallocation
 
 use crate::core::mm::{PAGE_SIZE, align_up, frame::alloc_frame, frame::dealloc_frame};
+use crate::core::mm::fsck::{ConsistencyReport, Violation};
 use crate::core::sync::SpinLock;
 use core::alloc::{GlobalAlloc, Layout};
 use core::ptr::NonNull;
 use core::sync::atomic::{AtomicUsize, AtomicU64, Ordering};
+use alloc::vec::Vec;
 
 /// Slab allocator errors
 #[derive(Debug, Clone, PartialEq)]
@@ -257,6 +259,73 @@ impl SlabCache {
         freed_pages
     }
 
+    /// Check the page lists, adding any violations to `report`
+    ///
+    /// Every page must be on at most one list, and on the list its use
+    /// count calls for. With the `debug` feature, every object header is
+    /// checked as well and the allocated ones counted.
+    pub fn check(&self, report: &mut ConsistencyReport) {
+        if self.magic != Self::MAGIC {
+            report.report(Violation::SlabBadCacheMagic { cache: self.name });
+            return;
+        }
+
+        let partial = self.partial_pages.lock();
+        let free = self.free_pages.lock();
+        let full = self.full_pages.lock();
+        let lists: [(&[*mut SlabPage], fn(u32, u32) -> bool); 3] = [
+            (&free[..], |inuse, _| inuse == 0),
+            (&partial[..], |inuse, total| inuse > 0 && inuse < total),
+            (&full[..], |inuse, total| inuse == total),
+        ];
+
+        for (i, &(pages, fits)) in lists.iter().enumerate() {
+            for (j, &page) in pages.iter().enumerate() {
+                let page_addr = page as usize;
+                let seen = pages[..j].contains(&page)
+                    || lists[..i].iter().any(|(other, _)| other.contains(&page));
+                if seen {
+                    report.report(Violation::SlabPageOnTwoLists { cache: self.name, page: page_addr });
+                    continue;
+                }
+
+                report.slab_pages += 1;
+                let (inuse, total) = unsafe { ((*page).inuse, (*page).total) };
+                if !fits(inuse, total) {
+                    report.report(Violation::SlabPageWrongList { cache: self.name, page: page_addr, inuse, total });
+                }
+
+                #[cfg(feature = "debug")]
+                self.check_objects(page, report);
+            }
+        }
+    }
+
+    /// Check the object headers of a page
+    #[cfg(feature = "debug")]
+    fn check_objects(&self, page: *mut SlabPage, report: &mut ConsistencyReport) {
+        let base = page as usize;
+        let header_size = core::mem::size_of::<SlabPage>();
+        let object_size = core::mem::size_of::<SlabObject>() +
+                        align_up(self.object_size, self.alignment);
+
+        let mut allocated = 0;
+        for i in 0..self.objects_per_page {
+            let object_addr = base + header_size + (i * object_size);
+            let magic = unsafe { (*(object_addr as *const SlabObject)).magic };
+            if magic == Self::OBJECT_MAGIC ^ 0xFFFFFFFFFFFFFFFF {
+                allocated += 1;
+            } else if magic != Self::OBJECT_MAGIC {
+                report.report(Violation::SlabBadObjectMagic { cache: self.name, addr: object_addr });
+            }
+        }
+
+        let recorded = unsafe { (*page).inuse };
+        if allocated != recorded {
+            report.report(Violation::SlabInUseMismatch { cache: self.name, page: base, counted: allocated, recorded });
+        }
+    }
+
     /// Get an object from a partial page
     fn get_partial_page(&self) -> Option<*mut SlabPage> {
         self.partial_pages.lock().pop()
@@ -539,6 +608,13 @@ impl SlabAllocator {
         total_freed
     }
 
+    /// Check every cache, adding any violations to `report`
+    pub fn check(&self, report: &mut ConsistencyReport) {
+        for cache in self.caches.lock().iter().filter_map(Option::as_ref) {
+            cache.check(report);
+        }
+    }
+
     /// Find the appropriate size class for a given size
     fn find_size_class(&self, size: usize) -> Option<usize> {
         for (i, &class_size) in Self::SIZE_CLASSES.iter().enumerate() {
@@ -604,6 +680,173 @@ pub fn shrink_all() -> usize {
     get_slab_allocator().shrink_all()
 }
 
+/// Check the caches of the global slab allocator, if initialized
+pub(crate) fn check_caches(report: &mut ConsistencyReport) {
+    if let Some(allocator) = unsafe { SLAB_ALLOCATOR.as_ref() } {
+        allocator.check(report);
+    }
+}
+
+/// Allocates `count` contiguous page frames
+pub type RingPagesAllocFn = fn(count: usize) -> Option<u64>;
+
+/// Frees `count` contiguous page frames
+pub type RingPagesFreeFn = fn(addr: u64, count: usize);
+
+/// Object sizes cached by `RingCache`, in bytes
+///
+/// Covers the descriptor table and both rings of queues of 8 to 1024
+/// entries. Objects are naturally aligned to their size.
+pub const RING_CACHE_SIZES: [usize; 9] = [64, 128, 256, 512, 1024, 2048, 4096, 8192, 16384];
+
+/// Ring cache hit/miss statistics
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RingCacheStats {
+    /// Allocations served from a free list
+    pub hits: u64,
+    /// Allocations that had to take pages from the backing allocator
+    pub misses: u64,
+    /// Allocations too large to cache, passed to the backing allocator
+    pub fallbacks: u64,
+    /// Pages taken from the backing allocator for cached objects
+    pub backing_pages: u64,
+    /// Cached objects currently allocated
+    pub in_use: u64,
+}
+
+/// Slab cache for VirtQueue descriptor tables and rings
+///
+/// Freed rings go back to a per-size free list instead of the frame
+/// allocator, so queues that are torn down and set up again reuse the same
+/// memory. Objects smaller than a page are carved out of whole pages.
+/// Sizes above the largest class fall back to the backing allocator.
+pub struct RingCache {
+    /// Free objects of each `RING_CACHE_SIZES` class
+    free: SpinLock<[Vec<u64>; RING_CACHE_SIZES.len()]>,
+    /// Backing page allocator
+    alloc_pages: RingPagesAllocFn,
+    /// Backing page deallocator
+    free_pages: RingPagesFreeFn,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    fallbacks: AtomicU64,
+    backing_pages: AtomicU64,
+    in_use: AtomicU64,
+}
+
+impl RingCache {
+    /// Create an empty cache drawing pages from the given allocator
+    pub const fn new(alloc_pages: RingPagesAllocFn, free_pages: RingPagesFreeFn) -> Self {
+        const EMPTY: Vec<u64> = Vec::new();
+        Self {
+            free: SpinLock::new([EMPTY; RING_CACHE_SIZES.len()]),
+            alloc_pages,
+            free_pages,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            fallbacks: AtomicU64::new(0),
+            backing_pages: AtomicU64::new(0),
+            in_use: AtomicU64::new(0),
+        }
+    }
+
+    /// Class index for an object of `size` bytes
+    fn class_of(size: usize) -> Option<usize> {
+        RING_CACHE_SIZES.iter().position(|&class| size <= class)
+    }
+
+    /// Pages needed for `size` bytes
+    fn pages_for(size: usize) -> usize {
+        (align_up(size as u64) / PAGE_SIZE) as usize
+    }
+
+    /// Allocate `size` bytes, aligned to at least `size` rounded up to a
+    /// power of two for cached sizes and to a page otherwise
+    pub fn allocate(&self, size: usize) -> Result<u64, SlabError> {
+        if size == 0 {
+            return Err(SlabError::InvalidSize);
+        }
+
+        let class = match Self::class_of(size) {
+            Some(class) => class,
+            None => {
+                self.fallbacks.fetch_add(1, Ordering::Relaxed);
+                return (self.alloc_pages)(Self::pages_for(size)).ok_or(SlabError::OutOfMemory);
+            }
+        };
+
+        let mut free = self.free.lock();
+        let addr = match free[class].pop() {
+            Some(addr) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                addr
+            }
+            None => {
+                // Carve a fresh backing block into objects of this class
+                let object_size = RING_CACHE_SIZES[class];
+                let pages = Self::pages_for(object_size);
+                let base = (self.alloc_pages)(pages).ok_or(SlabError::OutOfMemory)?;
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                self.backing_pages.fetch_add(pages as u64, Ordering::Relaxed);
+
+                let block = pages * PAGE_SIZE as usize;
+                for offset in (object_size..block).step_by(object_size).rev() {
+                    free[class].push(base + offset as u64);
+                }
+                base
+            }
+        };
+
+        self.in_use.fetch_add(1, Ordering::Relaxed);
+        Ok(addr)
+    }
+
+    /// Return an object obtained from `allocate(size)`
+    pub fn free(&self, addr: u64, size: usize) {
+        match Self::class_of(size) {
+            Some(class) => {
+                self.free.lock()[class].push(addr);
+                self.in_use.fetch_sub(1, Ordering::Relaxed);
+            }
+            None => (self.free_pages)(addr, Self::pages_for(size)),
+        }
+    }
+
+    /// Hit/miss statistics
+    pub fn stats(&self) -> RingCacheStats {
+        RingCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            fallbacks: self.fallbacks.load(Ordering::Relaxed),
+            backing_pages: self.backing_pages.load(Ordering::Relaxed),
+            in_use: self.in_use.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Allocate ring backing pages from the frame allocator
+fn ring_alloc_frames(count: usize) -> Option<u64> {
+    crate::core::mm::frame::alloc_frames(count)
+}
+
+/// Return ring backing pages to the frame allocator
+fn ring_dealloc_frames(addr: u64, count: usize) {
+    crate::core::mm::frame::dealloc_frames(addr, count);
+}
+
+/// VirtQueue ring cache
+static RING_CACHE: RingCache = RingCache::new(ring_alloc_frames, ring_dealloc_frames);
+
+/// Get the VirtQueue ring cache
+pub fn ring_cache() -> &'static RingCache {
+    &RING_CACHE
+}
+
+/// Get VirtQueue ring cache statistics
+pub fn ring_cache_stats() -> RingCacheStats {
+    RING_CACHE.stats()
+}
+
 /// Simple formatted string helper for compile-time strings
 mod boxleak {
     pub fn format(args: core::fmt::Arguments<'_>) -> &'static str {
@@ -652,4 +895,49 @@ mod tests {
         assert_eq!(allocator.find_size_class(100), Some(9)); // 128-byte class
         assert_eq!(allocator.find_size_class(5000), None); // Too large
     }
-}
\ No newline at end of file
+
+    /// Backing pages for the ring cache test, from the host heap
+    fn test_alloc_pages(count: usize) -> Option<u64> {
+        let layout = Layout::from_size_align(count * PAGE_SIZE as usize, PAGE_SIZE as usize).ok()?;
+        TEST_BACKING_PAGES.fetch_add(count, Ordering::Relaxed);
+        Some(unsafe { alloc::alloc::alloc(layout) } as u64)
+    }
+
+    fn test_free_pages(addr: u64, count: usize) {
+        let layout = Layout::from_size_align(count * PAGE_SIZE as usize, PAGE_SIZE as usize).unwrap();
+        TEST_BACKING_PAGES.fetch_sub(count, Ordering::Relaxed);
+        unsafe { alloc::alloc::dealloc(addr as *mut u8, layout) };
+    }
+
+    static TEST_BACKING_PAGES: AtomicUsize = AtomicUsize::new(0);
+
+    #[test]
+    fn test_ring_cache_reuses_objects() {
+        let cache = RingCache::new(test_alloc_pages, test_free_pages);
+
+        // Descriptor table, available and used ring of a 128-entry queue
+        let sizes = [2048, 266, 1052];
+        for _ in 0..100 {
+            let rings: Vec<u64> = sizes.iter().map(|&size| cache.allocate(size).unwrap()).collect();
+            for (&addr, &size) in rings.iter().zip(sizes.iter()) {
+                assert_eq!(addr % size.next_power_of_two() as u64, 0);
+            }
+            for (&addr, &size) in rings.iter().zip(sizes.iter()) {
+                cache.free(addr, size);
+            }
+        }
+
+        // One page per class, then every queue reuses the freed objects
+        let stats = cache.stats();
+        assert_eq!(stats.misses, 2);
+        assert_eq!(stats.backing_pages, 2);
+        assert_eq!(stats.hits, 298);
+        assert_eq!(stats.in_use, 0);
+
+        // Oversized rings bypass the cache
+        let large = cache.allocate(64 * 1024).unwrap();
+        assert_eq!(cache.stats().fallbacks, 1);
+        cache.free(large, 64 * 1024);
+        assert_eq!(TEST_BACKING_PAGES.load(Ordering::Relaxed), 2);
+    }
+}
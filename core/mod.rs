@@ -9,6 +9,8 @@ pub mod sched;
 pub mod mm;
 pub mod irq;
 pub mod sync;
+pub mod iommu;
+pub mod time;
 
 use crate::Result;
 
@@ -53,6 +55,18 @@ pub fn init() -> Result<()> {
     // Initialize virtual machine manager
     vmm::init()?;
 
+    // Initialize DMA remapping for passthrough devices
+    iommu::init()?;
+
+    // Initialize high-resolution timers
+    time::init()?;
+
+    // Arm the boot CPU's scheduler tick
+    time::cpu_online()?;
+
+    // Zero released reclaimable memory in the background
+    mm::allocator::start_scrubber()?;
+
     // Initialize scheduler
     sched::init()?;
 
@@ -0,0 +1,189 @@
+//! CPU bandwidth control
+//!
+//! Limits a VM to `quota_ns` of CPU time per `period_ns`, summed over all
+//! of its vCPUs, in the style of CFS bandwidth control. The scheduler
+//! charges vCPU runtime to the VM; once the quota for the current period
+//! is used up, the VM's vCPUs are not dispatched again until the next
+//! period boundary replenishes it.
+
+use crate::{Result, Error};
+use crate::core::vmm::VmId;
+use alloc::collections::BTreeMap;
+
+/// Smallest accepted bandwidth period
+pub const MIN_PERIOD_NS: u64 = 1_000_000;
+
+/// A VM's CPU time budget
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuQuota {
+    /// Length of a budget period
+    pub period_ns: u64,
+    /// CPU time allowed per period, may exceed the period on SMP guests
+    pub quota_ns: u64,
+}
+
+/// Budget state of one VM
+#[derive(Debug, Clone, Copy)]
+struct VmBandwidth {
+    /// Configured budget
+    quota: CpuQuota,
+    /// Start of the current period
+    period_start_ns: u64,
+    /// CPU time used in the current period
+    used_ns: u64,
+    /// Periods in which the VM was throttled
+    throttled_periods: u64,
+    /// Whether the current period has been counted as throttled
+    throttled: bool,
+}
+
+impl VmBandwidth {
+    /// Move to the period containing `now_ns`, refilling the budget
+    fn replenish(&mut self, now_ns: u64) {
+        let elapsed = now_ns.saturating_sub(self.period_start_ns);
+        if elapsed >= self.quota.period_ns {
+            self.period_start_ns += elapsed - elapsed % self.quota.period_ns;
+            self.used_ns = 0;
+            self.throttled = false;
+        }
+    }
+}
+
+/// Per-VM CPU bandwidth accounting
+#[derive(Debug)]
+pub struct BandwidthController {
+    /// VMs with a quota
+    vms: BTreeMap<VmId, VmBandwidth>,
+}
+
+impl BandwidthController {
+    /// Create a controller with no VM limited
+    pub const fn new() -> Self {
+        Self {
+            vms: BTreeMap::new(),
+        }
+    }
+
+    /// Limit a VM to `quota_ns` of CPU time every `period_ns`
+    ///
+    /// The first period starts at `now_ns`.
+    pub fn set_quota(&mut self, vm_id: VmId, quota: CpuQuota, now_ns: u64) -> Result<()> {
+        if quota.period_ns < MIN_PERIOD_NS || quota.quota_ns == 0 {
+            return Err(Error::InvalidArgument);
+        }
+
+        self.vms.insert(vm_id, VmBandwidth {
+            quota,
+            period_start_ns: now_ns,
+            used_ns: 0,
+            throttled_periods: 0,
+            throttled: false,
+        });
+        Ok(())
+    }
+
+    /// Remove a VM's limit
+    pub fn clear_quota(&mut self, vm_id: VmId) {
+        self.vms.remove(&vm_id);
+    }
+
+    /// Get a VM's budget
+    pub fn quota(&self, vm_id: VmId) -> Option<CpuQuota> {
+        self.vms.get(&vm_id).map(|vm| vm.quota)
+    }
+
+    /// Charge `runtime_ns` of CPU time, ending at `now_ns`, to a VM
+    pub fn charge(&mut self, vm_id: VmId, runtime_ns: u64, now_ns: u64) {
+        if let Some(vm) = self.vms.get_mut(&vm_id) {
+            vm.replenish(now_ns);
+            vm.used_ns = vm.used_ns.saturating_add(runtime_ns);
+        }
+    }
+
+    /// Check whether a VM has used up its budget for the period at `now_ns`
+    pub fn is_throttled(&mut self, vm_id: VmId, now_ns: u64) -> bool {
+        let vm = match self.vms.get_mut(&vm_id) {
+            Some(vm) => vm,
+            None => return false,
+        };
+
+        vm.replenish(now_ns);
+        if vm.used_ns < vm.quota.quota_ns {
+            return false;
+        }
+
+        if !vm.throttled {
+            vm.throttled = true;
+            vm.throttled_periods += 1;
+        }
+        true
+    }
+
+    /// Number of periods in which a VM ran out of budget
+    pub fn throttled_periods(&self, vm_id: VmId) -> u64 {
+        self.vms.get(&vm_id).map_or(0, |vm| vm.throttled_periods)
+    }
+}
+
+impl Default for BandwidthController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: u64 = 1_000_000;
+
+    /// Run a VM that always wants the CPU in 1ms slices, returning its CPU time
+    fn run_saturated(bandwidth: &mut BandwidthController, vm_id: VmId, until_ns: u64) -> u64 {
+        let mut used = 0;
+        let mut now = 0;
+        while now < until_ns {
+            if !bandwidth.is_throttled(vm_id, now) {
+                bandwidth.charge(vm_id, MS, now + MS);
+                used += MS;
+            }
+            now += MS;
+        }
+        used
+    }
+
+    #[test]
+    fn test_half_quota_limits_cpu_time() {
+        let mut bandwidth = BandwidthController::new();
+        let quota = CpuQuota { period_ns: 100 * MS, quota_ns: 50 * MS };
+        bandwidth.set_quota(1, quota, 0).unwrap();
+
+        // Ten periods of a CPU-bound guest
+        let used = run_saturated(&mut bandwidth, 1, 1000 * MS);
+        assert!(used <= 500 * MS + MS, "used {} ns", used);
+        assert!(used >= 500 * MS - 10 * MS, "used {} ns", used);
+        assert_eq!(bandwidth.throttled_periods(1), 10);
+
+        // An unlimited VM gets the whole CPU
+        assert_eq!(run_saturated(&mut bandwidth, 2, 1000 * MS), 1000 * MS);
+    }
+
+    #[test]
+    fn test_quota_replenished_at_period_boundary() {
+        let mut bandwidth = BandwidthController::new();
+        let quota = CpuQuota { period_ns: 10 * MS, quota_ns: 2 * MS };
+        assert_eq!(bandwidth.set_quota(1, CpuQuota { period_ns: 0, quota_ns: 1 }, 0), Err(Error::InvalidArgument));
+        bandwidth.set_quota(1, quota, 0).unwrap();
+        assert_eq!(bandwidth.quota(1), Some(quota));
+
+        bandwidth.charge(1, 2 * MS, 2 * MS);
+        assert!(bandwidth.is_throttled(1, 5 * MS));
+        assert!(!bandwidth.is_throttled(1, 10 * MS));
+
+        // Several idle periods do not bank budget
+        bandwidth.charge(1, 2 * MS, 45 * MS);
+        assert!(bandwidth.is_throttled(1, 49 * MS));
+
+        bandwidth.clear_quota(1);
+        assert!(!bandwidth.is_throttled(1, 49 * MS));
+    }
+}
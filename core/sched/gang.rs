@@ -0,0 +1,183 @@
+//! Gang scheduling
+//!
+//! Guests that spin on locks suffer when the lock holder's vCPU is
+//! preempted while its siblings keep spinning. With gang scheduling
+//! enabled for a VM, all of its runnable vCPUs are placed onto distinct
+//! physical CPUs in the same quantum, or none of them run.
+
+use crate::core::sched::ThreadId;
+use crate::core::vmm::VmId;
+use crate::libs::cpumask::CpuMask;
+use alloc::vec::Vec;
+
+/// A runnable task considered for placement
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GangTask {
+    /// Thread ID
+    pub tid: ThreadId,
+    /// Owning VM, if this is a vCPU thread
+    pub vm_id: Option<VmId>,
+    /// CPUs the task may run on
+    pub cpu_affinity: CpuMask,
+}
+
+/// Quantum planner for gang-scheduled VMs
+#[derive(Debug)]
+pub struct GangPlanner {
+    /// VMs with gang scheduling enabled
+    gang_vms: Vec<VmId>,
+}
+
+impl GangPlanner {
+    /// Create a planner with gang scheduling disabled for all VMs
+    pub const fn new() -> Self {
+        Self {
+            gang_vms: Vec::new(),
+        }
+    }
+
+    /// Enable or disable gang scheduling for a VM
+    pub fn set_enabled(&mut self, vm_id: VmId, enabled: bool) {
+        let present = self.gang_vms.contains(&vm_id);
+        if enabled && !present {
+            self.gang_vms.push(vm_id);
+        } else if !enabled && present {
+            self.gang_vms.retain(|&vm| vm != vm_id);
+        }
+    }
+
+    /// Check whether a VM is gang scheduled
+    pub fn is_enabled(&self, vm_id: VmId) -> bool {
+        self.gang_vms.contains(&vm_id)
+    }
+
+    /// Check whether a task belongs to a gang-scheduled VM
+    pub fn is_gang_task(&self, task: &GangTask) -> bool {
+        task.vm_id.map_or(false, |vm| self.is_enabled(vm))
+    }
+
+    /// Plan one quantum
+    ///
+    /// `tasks` are runnable tasks in priority order and `free_cpus` the
+    /// physical CPUs available this quantum. Independent tasks take the
+    /// first free CPU they are allowed on; a gang is placed only if every
+    /// runnable member gets its own CPU. Returns `(cpu, tid)` pairs.
+    pub fn plan(&self, tasks: &[GangTask], mut free_cpus: CpuMask) -> Vec<(usize, ThreadId)> {
+        let mut placement = Vec::new();
+        let mut seen_gangs: Vec<VmId> = Vec::new();
+
+        for task in tasks {
+            if free_cpus.is_empty() {
+                break;
+            }
+
+            match task.vm_id.filter(|&vm| self.is_enabled(vm)) {
+                Some(vm_id) => {
+                    if seen_gangs.contains(&vm_id) {
+                        continue;
+                    }
+                    seen_gangs.push(vm_id);
+
+                    let members: Vec<&GangTask> = tasks.iter()
+                        .filter(|t| t.vm_id == Some(vm_id))
+                        .collect();
+                    if let Some(gang) = place_gang(&members, free_cpus) {
+                        for &(cpu, _) in &gang {
+                            free_cpus.clear(cpu as u32);
+                        }
+                        placement.extend(gang);
+                    }
+                }
+                None => {
+                    if let Some(cpu) = free_cpus.intersection(&task.cpu_affinity).first() {
+                        free_cpus.clear(cpu);
+                        placement.push((cpu as usize, task.tid));
+                    }
+                }
+            }
+        }
+
+        placement
+    }
+
+    /// Place the rest of a gang around a member already chosen to run
+    ///
+    /// Returns `None` if some sibling can't get its own CPU, in which case
+    /// the whole gang must wait.
+    pub fn place_siblings(&self, siblings: &[GangTask], free_cpus: CpuMask) -> Option<Vec<(usize, ThreadId)>> {
+        let members: Vec<&GangTask> = siblings.iter().collect();
+        place_gang(&members, free_cpus)
+    }
+}
+
+/// Place every member of a gang on a distinct CPU, or none
+///
+/// Members with the fewest allowed CPUs are placed first.
+fn place_gang(members: &[&GangTask], mut free_cpus: CpuMask) -> Option<Vec<(usize, ThreadId)>> {
+    let mut order: Vec<&GangTask> = members.to_vec();
+    order.sort_by_key(|t| t.cpu_affinity.intersection(&free_cpus).count());
+
+    let mut gang = Vec::with_capacity(order.len());
+    for task in order {
+        let cpu = free_cpus.intersection(&task.cpu_affinity).first()?;
+        free_cpus.clear(cpu);
+        gang.push((cpu as usize, task.tid));
+    }
+
+    Some(gang)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vcpu(tid: ThreadId, vm_id: VmId) -> GangTask {
+        GangTask { tid, vm_id: Some(vm_id), cpu_affinity: CpuMask::all() }
+    }
+
+    #[test]
+    fn test_gang_vcpus_selected_together() {
+        let mut planner = GangPlanner::new();
+        planner.set_enabled(1, true);
+
+        // VM 1's vCPUs are separated by another thread in priority order
+        let tasks = [vcpu(10, 1), GangTask { tid: 20, vm_id: None, cpu_affinity: CpuMask::all() }, vcpu(11, 1)];
+        let plan = planner.plan(&tasks, CpuMask::from_bits(0b1111));
+
+        let gang_cpus: Vec<usize> = plan.iter()
+            .filter(|(_, tid)| *tid == 10 || *tid == 11)
+            .map(|&(cpu, _)| cpu)
+            .collect();
+        assert_eq!(gang_cpus.len(), 2);
+        assert_ne!(gang_cpus[0], gang_cpus[1]);
+        assert!(plan.iter().any(|&(_, tid)| tid == 20));
+    }
+
+    #[test]
+    fn test_gang_not_split_without_enough_cpus() {
+        let mut planner = GangPlanner::new();
+        planner.set_enabled(1, true);
+
+        let tasks = [vcpu(10, 1), vcpu(11, 1), vcpu(12, 1)];
+        assert!(planner.plan(&tasks, CpuMask::from_bits(0b11)).is_empty());
+
+        // Without gang scheduling the same tasks are placed piecemeal
+        planner.set_enabled(1, false);
+        assert_eq!(planner.plan(&tasks, CpuMask::from_bits(0b11)).len(), 2);
+    }
+
+    #[test]
+    fn test_place_siblings_respects_affinity() {
+        let planner = GangPlanner::new();
+        let siblings = [
+            GangTask { tid: 11, vm_id: Some(1), cpu_affinity: CpuMask::all() },
+            GangTask { tid: 12, vm_id: Some(1), cpu_affinity: CpuMask::from_cpu(2) },
+        ];
+
+        let placed = planner.place_siblings(&siblings, CpuMask::from_bits(0b0110)).unwrap();
+        assert!(placed.contains(&(2, 12)));
+        assert!(placed.contains(&(1, 11)));
+
+        assert_eq!(planner.place_siblings(&siblings, CpuMask::from_bits(0b0011)), None);
+    }
+}
@@ -0,0 +1,144 @@
+//! vCPU idle detection
+//!
+//! A guest that executes WFI/WFE, or makes the yield hypercall, has nothing
+//! to do until an interrupt arrives. Rather than keep dispatching its vCPU,
+//! the scheduler blocks the vCPU's host thread and wakes it when an
+//! interrupt is injected. An interrupt injected before the guest goes idle
+//! is remembered, so the guest never sleeps on an interrupt it hasn't
+//! taken yet.
+
+use crate::core::vmm::{VmId, VcpuId};
+use alloc::collections::BTreeMap;
+
+/// Why a vCPU asked to go idle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdleReason {
+    /// Wait-for-interrupt
+    Wfi,
+    /// Wait-for-event
+    Wfe,
+    /// Yield hypercall
+    Yield,
+}
+
+/// Idle state of one vCPU
+#[derive(Debug, Clone, Copy, Default)]
+struct VcpuIdle {
+    /// An interrupt is pending that the guest hasn't taken
+    interrupt_pending: bool,
+    /// The vCPU is descheduled waiting for an interrupt
+    idle: bool,
+    /// What the vCPU last went idle on
+    reason: Option<IdleReason>,
+    /// Times the vCPU was descheduled
+    idle_entries: u64,
+}
+
+/// Per-vCPU idle tracking
+#[derive(Debug)]
+pub struct VcpuIdleTracker {
+    /// vCPUs that have gone idle or had interrupts injected
+    vcpus: BTreeMap<(VmId, VcpuId), VcpuIdle>,
+}
+
+impl VcpuIdleTracker {
+    /// Create a tracker with no vCPU idle
+    pub const fn new() -> Self {
+        Self {
+            vcpus: BTreeMap::new(),
+        }
+    }
+
+    /// A vCPU trapped on WFI/WFE or yielded
+    ///
+    /// Returns true if the vCPU must be descheduled, false if an interrupt
+    /// is already pending and it should keep running.
+    pub fn enter_idle(&mut self, vm_id: VmId, vcpu_id: VcpuId, reason: IdleReason) -> bool {
+        let vcpu = self.vcpus.entry((vm_id, vcpu_id)).or_default();
+        if vcpu.interrupt_pending {
+            return false;
+        }
+
+        vcpu.idle = true;
+        vcpu.reason = Some(reason);
+        vcpu.idle_entries += 1;
+        true
+    }
+
+    /// An interrupt was injected into a vCPU
+    ///
+    /// Returns true if the vCPU was idle and must be made runnable.
+    pub fn interrupt_pending(&mut self, vm_id: VmId, vcpu_id: VcpuId) -> bool {
+        let vcpu = self.vcpus.entry((vm_id, vcpu_id)).or_default();
+        vcpu.interrupt_pending = true;
+        core::mem::take(&mut vcpu.idle)
+    }
+
+    /// The guest took all pending interrupts of a vCPU
+    pub fn interrupts_handled(&mut self, vm_id: VmId, vcpu_id: VcpuId) {
+        if let Some(vcpu) = self.vcpus.get_mut(&(vm_id, vcpu_id)) {
+            vcpu.interrupt_pending = false;
+        }
+    }
+
+    /// Check whether a vCPU is descheduled waiting for an interrupt
+    pub fn is_idle(&self, vm_id: VmId, vcpu_id: VcpuId) -> bool {
+        self.vcpus.get(&(vm_id, vcpu_id)).map_or(false, |vcpu| vcpu.idle)
+    }
+
+    /// What a vCPU last went idle on
+    pub fn idle_reason(&self, vm_id: VmId, vcpu_id: VcpuId) -> Option<IdleReason> {
+        self.vcpus.get(&(vm_id, vcpu_id)).and_then(|vcpu| vcpu.reason)
+    }
+
+    /// Number of times a vCPU was descheduled for being idle
+    pub fn idle_entries(&self, vm_id: VmId, vcpu_id: VcpuId) -> u64 {
+        self.vcpus.get(&(vm_id, vcpu_id)).map_or(0, |vcpu| vcpu.idle_entries)
+    }
+
+    /// Drop the state of a destroyed vCPU
+    pub fn forget(&mut self, vm_id: VmId, vcpu_id: VcpuId) {
+        self.vcpus.remove(&(vm_id, vcpu_id));
+    }
+}
+
+impl Default for VcpuIdleTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wfi_without_interrupt_deschedules() {
+        let mut tracker = VcpuIdleTracker::new();
+
+        assert!(tracker.enter_idle(1, 0, IdleReason::Wfi));
+        assert!(tracker.is_idle(1, 0));
+        assert!(!tracker.is_idle(1, 1));
+        assert_eq!(tracker.idle_reason(1, 0), Some(IdleReason::Wfi));
+
+        // Injecting an interrupt makes it runnable again, once
+        assert!(tracker.interrupt_pending(1, 0));
+        assert!(!tracker.is_idle(1, 0));
+        assert!(!tracker.interrupt_pending(1, 0));
+        assert_eq!(tracker.idle_entries(1, 0), 1);
+    }
+
+    #[test]
+    fn test_pending_interrupt_keeps_vcpu_running() {
+        let mut tracker = VcpuIdleTracker::new();
+
+        // Interrupt injected before the guest reaches WFI
+        assert!(!tracker.interrupt_pending(2, 3));
+        assert!(!tracker.enter_idle(2, 3, IdleReason::Yield));
+        assert!(!tracker.is_idle(2, 3));
+
+        tracker.interrupts_handled(2, 3);
+        assert!(tracker.enter_idle(2, 3, IdleReason::Wfe));
+        assert_eq!(tracker.idle_entries(2, 3), 1);
+    }
+}
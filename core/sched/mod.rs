@@ -4,17 +4,33 @@
 //! managing VCPU threads and orphan threads.
 
 use crate::Result;
+use crate::libs::cpumask::CpuMask;
 
 pub mod scheduler;
 pub mod rr;
 pub mod fifo;
+pub mod gang;
+pub mod bandwidth;
+pub mod idle;
 
 // Re-export from scheduler
 pub use scheduler::ThreadControlBlock;
+pub use idle::IdleReason;
 
 /// Thread ID type
 pub type ThreadId = u64;
 
+/// Restrict an affinity mask to online CPUs
+///
+/// Fails with `Error::InvalidArgument` if no online CPU remains.
+pub fn effective_affinity(mask: CpuMask, online: CpuMask) -> Result<CpuMask> {
+    let effective = mask.intersection(&online);
+    if effective.is_empty() {
+        return Err(crate::Error::InvalidArgument);
+    }
+    Ok(effective)
+}
+
 /// Thread priority levels
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Priority {
@@ -61,7 +77,7 @@ pub struct Thread {
     /// Thread priority
     priority: Priority,
     /// CPU affinity (which CPUs this thread can run on)
-    cpu_affinity: u64,
+    cpu_affinity: CpuMask,
     /// Time slice remaining
     time_slice: u32,
     /// Total CPU time consumed
@@ -88,7 +104,7 @@ impl Thread {
             name,
             state: ThreadState::Ready,
             priority,
-            cpu_affinity: u64::MAX, // Run on any CPU by default
+            cpu_affinity: CpuMask::all(), // Run on any CPU by default
             time_slice: 10, // Default time slice
             cpu_time: 0,
             context_data,
@@ -121,12 +137,12 @@ impl Thread {
     }
 
     /// Get the CPU affinity mask
-    pub fn cpu_affinity(&self) -> u64 {
+    pub fn cpu_affinity(&self) -> CpuMask {
         self.cpu_affinity
     }
 
     /// Set the CPU affinity mask
-    pub fn set_cpu_affinity(&mut self, affinity: u64) {
+    pub fn set_cpu_affinity(&mut self, affinity: CpuMask) {
         self.cpu_affinity = affinity;
     }
 
@@ -214,4 +230,77 @@ pub fn get_stats() -> scheduler::SchedulerStats {
     scheduler::get_stats()
 }
 
-use core::ptr::NonNull;
\ No newline at end of file
+/// Get context switch, vCPU migration and per-thread runtime accounting
+pub fn stats() -> scheduler::DispatchStats {
+    scheduler::dispatch_stats()
+}
+
+/// Pin a vCPU's host thread to a set of physical CPUs
+pub fn set_vcpu_affinity(
+    vm_id: crate::core::vmm::VmId,
+    vcpu_id: crate::core::vmm::VcpuId,
+    mask: CpuMask,
+) -> Result<(), crate::Error> {
+    scheduler::set_vcpu_affinity(vm_id, vcpu_id, mask)
+}
+
+/// Enable or disable gang scheduling for a VM
+///
+/// When enabled, the VM's runnable vCPUs are co-scheduled onto distinct
+/// physical CPUs in the same quantum, or not at all.
+pub fn set_gang_scheduling(vm_id: crate::core::vmm::VmId, enabled: bool) {
+    scheduler::set_gang_scheduling(vm_id, enabled)
+}
+
+/// Limit a VM to `quota_ns` of CPU time every `period_ns`
+///
+/// Runtime of all the VM's vCPUs is charged against the quota; once it
+/// is used up they are not scheduled until the next period.
+pub fn set_cpu_quota(vm_id: crate::core::vmm::VmId, period_ns: u64, quota_ns: u64) -> Result<(), crate::Error> {
+    scheduler::set_cpu_quota(vm_id, period_ns, quota_ns)
+}
+
+/// Remove a VM's CPU time limit
+pub fn clear_cpu_quota(vm_id: crate::core::vmm::VmId) {
+    scheduler::clear_cpu_quota(vm_id)
+}
+
+/// Deschedule the current vCPU until an interrupt is pending for it
+///
+/// Called when the guest executes WFI/WFE or makes the yield hypercall.
+/// Returns false if an interrupt is already pending and the vCPU should
+/// keep running.
+pub fn vcpu_idle(reason: IdleReason) -> Result<bool, crate::Error> {
+    scheduler::vcpu_idle(reason)
+}
+
+/// Note an interrupt injected into a vCPU, waking it if it is idle
+pub fn vcpu_interrupt_pending(
+    vm_id: crate::core::vmm::VmId,
+    vcpu_id: crate::core::vmm::VcpuId,
+) -> Result<(), crate::Error> {
+    scheduler::vcpu_interrupt_pending(vm_id, vcpu_id)
+}
+
+/// Note that the guest took all pending interrupts of a vCPU
+pub fn vcpu_interrupts_handled(vm_id: crate::core::vmm::VmId, vcpu_id: crate::core::vmm::VcpuId) {
+    scheduler::vcpu_interrupts_handled(vm_id, vcpu_id)
+}
+
+use core::ptr::NonNull;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_effective_affinity() {
+        let online = CpuMask::from_bits(0b1111);
+        assert_eq!(effective_affinity(CpuMask::from_cpu(2), online), Ok(CpuMask::from_cpu(2)));
+        assert_eq!(effective_affinity(CpuMask::all(), CpuMask::from_bits(0b0011)), Ok(CpuMask::from_bits(0b0011)));
+
+        // Only offline CPUs, or nothing at all
+        assert_eq!(effective_affinity(CpuMask::from_cpu(7), online), Err(crate::Error::InvalidArgument));
+        assert_eq!(effective_affinity(CpuMask::new(), online), Err(crate::Error::InvalidArgument));
+    }
+}
\ No newline at end of file
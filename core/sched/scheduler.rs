@@ -5,12 +5,19 @@
 
 use crate::{Result, Error};
 use crate::core::sched::{Thread, ThreadId, Priority, ThreadState};
+use crate::libs::cpumask::CpuMask;
+use crate::core::sched::gang::{GangPlanner, GangTask};
+use crate::core::sched::bandwidth::{BandwidthController, CpuQuota};
+use crate::core::sched::idle::{IdleReason, VcpuIdleTracker};
 use crate::core::vmm::{VmId, VcpuId};
 use crate::core::sync::SpinLock;
 use crate::utils::list::{List, ListNode};
 use crate::utils::bitmap::Bitmap;
 use core::ptr::NonNull;
 use core::sync::atomic::{AtomicUsize, Ordering};
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
 
 // Import the impl_list_node macro from crate root
 use crate::impl_list_node;
@@ -35,6 +42,95 @@ pub struct SchedulerStats {
     pub scheduler_runs: u64,
 }
 
+/// Dispatch accounting snapshot returned by `stats()`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DispatchStats {
+    /// Total context switches across all CPUs
+    pub context_switches: u64,
+    /// vCPU threads dispatched on a different CPU than last time
+    pub migrations: u64,
+    /// Cumulative runtime per thread, in timestamp ticks
+    pub runtime: BTreeMap<ThreadId, u64>,
+}
+
+impl DispatchStats {
+    /// Get the cumulative runtime of a thread
+    pub fn runtime_of(&self, tid: ThreadId) -> u64 {
+        self.runtime.get(&tid).copied().unwrap_or(0)
+    }
+
+    /// Get the runtime summed over all threads
+    pub fn total_runtime(&self) -> u64 {
+        self.runtime.values().sum()
+    }
+}
+
+/// Per-dispatch bookkeeping behind `DispatchStats`
+struct DispatchAccounting {
+    /// Accumulated statistics
+    stats: DispatchStats,
+    /// CPU each vCPU thread last ran on
+    last_cpu: BTreeMap<ThreadId, usize>,
+    /// Thread running on each CPU and when it was dispatched
+    running: [Option<(ThreadId, u64)>; 64],
+}
+
+impl DispatchAccounting {
+    /// Create empty accounting
+    const fn new() -> Self {
+        Self {
+            stats: DispatchStats {
+                context_switches: 0,
+                migrations: 0,
+                runtime: BTreeMap::new(),
+            },
+            last_cpu: BTreeMap::new(),
+            running: [None; 64],
+        }
+    }
+
+    /// Record a scheduling decision on `cpu_id` at time `now`
+    ///
+    /// Charges the outgoing thread for the time since its dispatch and
+    /// counts a context switch if the thread changes. Returns true on a
+    /// context switch.
+    fn dispatch(&mut self, cpu_id: usize, next: Option<(ThreadId, bool)>, now: u64) -> bool {
+        let prev = self.running[cpu_id].take();
+        if let Some((tid, since)) = prev {
+            *self.stats.runtime.entry(tid).or_insert(0) += now.saturating_sub(since);
+        }
+
+        let switched = prev.map(|(tid, _)| tid) != next.map(|(tid, _)| tid);
+        if switched {
+            self.stats.context_switches += 1;
+        }
+
+        if let Some((tid, is_vcpu)) = next {
+            if is_vcpu {
+                if let Some(last) = self.last_cpu.insert(tid, cpu_id) {
+                    if last != cpu_id {
+                        self.stats.migrations += 1;
+                    }
+                }
+            }
+            self.running[cpu_id] = Some((tid, now));
+        }
+
+        switched
+    }
+
+    /// Stop charging a thread that is going away
+    fn forget(&mut self, tid: ThreadId) {
+        self.last_cpu.remove(&tid);
+        self.stats.runtime.remove(&tid);
+        for slot in self.running.iter_mut() {
+            if slot.map_or(false, |(running, _)| running == tid) {
+                *slot = None;
+            }
+        }
+    }
+}
+
 /// Thread control block
 #[derive(Debug)]
 pub struct ThreadControlBlock {
@@ -55,7 +151,7 @@ pub struct ThreadControlBlock {
     /// Last run time
     pub last_run_time: u64,
     /// CPU affinity mask
-    pub cpu_affinity: u64,
+    pub cpu_affinity: CpuMask,
     /// List node for scheduler queues
     pub node: ListNode,
 }
@@ -72,7 +168,7 @@ impl ThreadControlBlock {
             time_slice: 10, // Default 10ms time slice
             cpu_time: 0,
             last_run_time: 0,
-            cpu_affinity: u64::MAX, // Run on any CPU
+            cpu_affinity: CpuMask::all(), // Run on any CPU
             node: ListNode::new(),
         }
     }
@@ -90,6 +186,11 @@ impl ThreadControlBlock {
         self.vcpu_id.is_some()
     }
 
+    /// Check whether this thread may run on `cpu_id`
+    pub fn allowed_on(&self, cpu_id: usize) -> bool {
+        self.cpu_affinity.contains(cpu_id as u32)
+    }
+
     /// Reset time slice
     pub fn reset_time_slice(&mut self) {
         self.time_slice = match self.priority {
@@ -215,12 +316,62 @@ impl ReadyQueue {
         }
     }
 
+    /// Remove and return the highest priority thread allowed on `cpu_id`
+    ///
+    /// Threads pinned elsewhere are skipped and stay queued.
+    pub fn dequeue_highest_for(&mut self, cpu_id: usize) -> Option<&mut ThreadControlBlock> {
+        self.dequeue_highest_matching(cpu_id, |_| true)
+    }
+
+    /// Remove and return the highest priority thread allowed on `cpu_id`
+    /// for which `accept` holds
+    ///
+    /// Rejected threads stay queued in their place.
+    pub fn dequeue_highest_matching(
+        &mut self,
+        cpu_id: usize,
+        mut accept: impl FnMut(&ThreadControlBlock) -> bool,
+    ) -> Option<&mut ThreadControlBlock> {
+        for index in (0..5).rev() {
+            let found = first_allowed(
+                self.queues[index].iter()
+                    .map(|node| unsafe { tcb_from_node(node) })
+                    .filter(|tcb| accept(tcb)),
+                cpu_id,
+            ).map(|tcb| tcb as *const ThreadControlBlock as *mut ThreadControlBlock);
+
+            if let Some(tcb_ptr) = found {
+                let tcb = unsafe { &mut *tcb_ptr };
+                self.dequeue(tcb);
+                return Some(tcb);
+            }
+        }
+
+        None
+    }
+
     /// Check if the queue is empty
     pub fn is_empty(&self) -> bool {
         self.bitmap.count_zeros() == 5
     }
 }
 
+/// Get the thread control block containing a list node
+///
+/// # Safety
+/// `node` must be the `node` field of a live `ThreadControlBlock`.
+unsafe fn tcb_from_node(node: &ListNode) -> &ThreadControlBlock {
+    &*(node as *const ListNode as *const u8 as *const ThreadControlBlock)
+}
+
+/// Find the first thread, in queue order, allowed to run on `cpu_id`
+fn first_allowed<'a>(
+    mut threads: impl Iterator<Item = &'a ThreadControlBlock>,
+    cpu_id: usize,
+) -> Option<&'a ThreadControlBlock> {
+    threads.find(|tcb| tcb.allowed_on(cpu_id))
+}
+
 /// Main scheduler
 pub struct Scheduler {
     /// Thread control blocks
@@ -237,6 +388,16 @@ pub struct Scheduler {
     stats: SpinLock<SchedulerStats>,
     /// Scheduler tick counter
     tick_counter: AtomicUsize,
+    /// Gang scheduling planner
+    gang: SpinLock<GangPlanner>,
+    /// Gang members handed to a CPU for its next schedule
+    gang_dispatch: SpinLock<[Option<ThreadId>; 64]>,
+    /// Context switch, migration and runtime accounting
+    accounting: SpinLock<DispatchAccounting>,
+    /// Per-VM CPU bandwidth limits
+    bandwidth: SpinLock<BandwidthController>,
+    /// vCPUs descheduled until an interrupt is pending
+    idle: SpinLock<VcpuIdleTracker>,
 }
 
 impl Scheduler {
@@ -259,6 +420,11 @@ impl Scheduler {
                 scheduler_runs: 0,
             }),
             tick_counter: AtomicUsize::new(0),
+            gang: SpinLock::new(GangPlanner::new()),
+            gang_dispatch: SpinLock::new([None; 64]),
+            accounting: SpinLock::new(DispatchAccounting::new()),
+            bandwidth: SpinLock::new(BandwidthController::new()),
+            idle: SpinLock::new(VcpuIdleTracker::new()),
         }
     }
 
@@ -348,6 +514,10 @@ impl Scheduler {
                     let mut ready_queue = self.ready_queue.lock();
                     ready_queue.dequeue(tcb_mut);
                 }
+
+                if let (Some(vm_id), Some(vcpu_id)) = (tcb_mut.vm_id, tcb_mut.vcpu_id) {
+                    self.idle.lock().forget(vm_id, vcpu_id);
+                }
             }
         }
 
@@ -371,6 +541,7 @@ impl Scheduler {
             let mut stats = self.stats.lock();
             stats.total_threads = stats.total_threads.saturating_sub(1);
         }
+        self.accounting.lock().forget(tid);
 
         Ok(())
     }
@@ -384,6 +555,8 @@ impl Scheduler {
     /// Schedule next thread to run on current CPU
     pub fn schedule(&self, cpu_id: usize) -> Result<Option<ThreadId>> {
         let current_time = crate::utils::get_timestamp();
+        let freq = crate::utils::time::timer_frequency();
+        let now_ns = crate::utils::time::ticks_to_ns(current_time, freq);
 
         // Get current thread
         let current_tid = {
@@ -399,11 +572,19 @@ impl Scheduler {
 
                     if tcb_mut.state == ThreadState::Running {
                         // Update CPU time
-                        tcb_mut.cpu_time += current_time - tcb_mut.last_run_time;
+                        let ran = current_time - tcb_mut.last_run_time;
+                        tcb_mut.cpu_time += ran;
+
+                        // Charge the VM's bandwidth budget
+                        let throttled = tcb_mut.vm_id.map_or(false, |vm| {
+                            let mut bandwidth = self.bandwidth.lock();
+                            bandwidth.charge(vm, crate::utils::time::ticks_to_ns(ran, freq), now_ns);
+                            bandwidth.is_throttled(vm, now_ns)
+                        });
 
                         // Check time slice
-                        if !tcb_mut.dec_time_slice() {
-                            // Time slice expired
+                        if throttled || !tcb_mut.dec_time_slice() {
+                            // Time slice or VM budget expired
                             tcb_mut.state = ThreadState::Ready;
                             tcb_mut.reset_time_slice();
 
@@ -413,6 +594,7 @@ impl Scheduler {
                         } else {
                             // Still has time slice, continue running
                             tcb_mut.last_run_time = current_time;
+                            self.accounting.lock().dispatch(cpu_id, Some((tid, tcb_mut.is_vcpu())), current_time);
                             return Ok(Some(tid));
                         }
                     }
@@ -420,21 +602,60 @@ impl Scheduler {
             }
         }
 
+        // A gang member placed on this CPU takes precedence
+        let gang_tid = self.gang_dispatch.lock()[cpu_id].take();
+
         // Get next thread from ready queue
-        let next_tid = {
-            let mut ready_queue = self.ready_queue.lock();
-            if let Some(tcb) = ready_queue.dequeue_highest() {
+        let next_tid = if let Some(tid) = gang_tid {
+            if let Some(tcb) = self.get_thread(tid) {
                 unsafe {
                     let tcb_mut = tcb.as_mut();
                     tcb_mut.state = ThreadState::Running;
                     tcb_mut.last_run_time = current_time;
-                    Some(tcb_mut.id)
                 }
-            } else {
-                // No ready threads, use idle thread
+            }
+            Some(tid)
+        } else {
+            let picked = {
+                let mut ready_queue = self.ready_queue.lock();
+                let mut bandwidth = self.bandwidth.lock();
+
+                // vCPUs of VMs out of budget stay queued for the next period
+                ready_queue.dequeue_highest_matching(cpu_id, |tcb| {
+                    tcb.vm_id.map_or(true, |vm| !bandwidth.is_throttled(vm, now_ns))
+                }).map(|tcb| tcb as *mut ThreadControlBlock)
+            };
+
+            let runnable = picked.and_then(|tcb_ptr| {
+                let mut tcb_mut = unsafe { &mut *tcb_ptr };
+                let gang_member = tcb_mut.vm_id.map_or(false, |vm| self.gang.lock().is_enabled(vm));
+                if gang_member && !self.dispatch_gang(cpu_id, tcb_mut) {
+                    // Siblings can't all run this quantum, so neither does this
+                    // one; run the best thread outside any gang instead
+                    tcb_mut.state = ThreadState::Ready;
+                    let mut ready_queue = self.ready_queue.lock();
+                    ready_queue.enqueue(tcb_mut);
+
+                    let mut bandwidth = self.bandwidth.lock();
+                    let gang = self.gang.lock();
+                    let other = ready_queue.dequeue_highest_matching(cpu_id, |tcb| {
+                        tcb.vm_id.map_or(true, |vm| {
+                            !gang.is_enabled(vm) && !bandwidth.is_throttled(vm, now_ns)
+                        })
+                    })?;
+                    tcb_mut = unsafe { &mut *(other as *mut ThreadControlBlock) };
+                }
+
+                tcb_mut.state = ThreadState::Running;
+                tcb_mut.last_run_time = current_time;
+                Some(tcb_mut.id)
+            });
+
+            runnable.or_else(|| {
+                // No runnable threads, use idle thread
                 let idle_threads = self.idle_threads.lock();
                 idle_threads.get(cpu_id).copied()
-            }
+            })
         };
 
         // Update current thread
@@ -443,11 +664,19 @@ impl Scheduler {
             current_threads[cpu_id] = next_tid;
         }
 
+        // Account the dispatch
+        let next = next_tid.map(|tid| {
+            let is_vcpu = self.get_thread(tid)
+                .map_or(false, |tcb| unsafe { tcb.as_ref().is_vcpu() });
+            (tid, is_vcpu)
+        });
+        let switched = self.accounting.lock().dispatch(cpu_id, next, current_time);
+
         // Update statistics
         {
             let mut stats = self.stats.lock();
             stats.scheduler_runs += 1;
-            if current_tid != next_tid {
+            if switched {
                 stats.context_switches += 1;
             }
         }
@@ -455,6 +684,165 @@ impl Scheduler {
         Ok(next_tid)
     }
 
+    /// Pin the host thread of a vCPU to `mask`
+    ///
+    /// The mask must include at least one online CPU. A thread currently
+    /// running outside the new mask keeps its CPU until its next schedule.
+    pub fn set_vcpu_affinity(&self, vm_id: VmId, vcpu_id: VcpuId, mask: CpuMask) -> Result<()> {
+        let mask = crate::core::sched::effective_affinity(mask, crate::arch::cpu::get_online_cpu_mask())?;
+
+        let threads = self.threads.lock();
+        let tcb = threads.iter()
+            .flatten()
+            .find(|tcb| unsafe {
+                let tcb = tcb.as_ref();
+                tcb.vm_id == Some(vm_id) && tcb.vcpu_id == Some(vcpu_id)
+            })
+            .ok_or(Error::NotFound)?;
+
+        unsafe {
+            (*tcb.as_ptr()).cpu_affinity = mask;
+        }
+
+        Ok(())
+    }
+
+    /// Find the host thread of a vCPU
+    fn vcpu_thread(&self, vm_id: VmId, vcpu_id: VcpuId) -> Option<ThreadId> {
+        let threads = self.threads.lock();
+        threads.iter()
+            .flatten()
+            .map(|tcb| unsafe { tcb.as_ref() })
+            .find(|tcb| tcb.vm_id == Some(vm_id) && tcb.vcpu_id == Some(vcpu_id))
+            .map(|tcb| tcb.id)
+    }
+
+    /// Deschedule the vCPU running on `cpu_id` until an interrupt is
+    /// pending for it
+    ///
+    /// Called when the guest executes WFI/WFE or yields. Returns false,
+    /// leaving the vCPU running, if an interrupt is already pending.
+    pub fn vcpu_idle(&self, cpu_id: usize, reason: IdleReason) -> Result<bool> {
+        let tid = self.get_current_thread(cpu_id).ok_or(Error::InvalidState)?;
+        let tcb = self.get_thread(tid).ok_or(Error::NotFound)?;
+
+        {
+            // Hold the tracker while blocking so a concurrent injection
+            // either sees the vCPU idle or is seen by enter_idle
+            let mut idle = self.idle.lock();
+            let tcb_mut = unsafe { &mut *tcb.as_ptr() };
+            let (vm_id, vcpu_id) = match (tcb_mut.vm_id, tcb_mut.vcpu_id) {
+                (Some(vm_id), Some(vcpu_id)) => (vm_id, vcpu_id),
+                _ => return Err(Error::InvalidState),
+            };
+
+            if !idle.enter_idle(vm_id, vcpu_id, reason) {
+                return Ok(false);
+            }
+            tcb_mut.state = ThreadState::Blocked;
+        }
+
+        self.stats.lock().blocked_threads += 1;
+        self.schedule(cpu_id)?;
+        Ok(true)
+    }
+
+    /// Note an interrupt injected into a vCPU, making it runnable if idle
+    pub fn vcpu_interrupt_pending(&self, vm_id: VmId, vcpu_id: VcpuId) -> Result<()> {
+        let mut idle = self.idle.lock();
+        if !idle.interrupt_pending(vm_id, vcpu_id) {
+            return Ok(());
+        }
+
+        let tid = self.vcpu_thread(vm_id, vcpu_id).ok_or(Error::NotFound)?;
+        self.unblock_thread(tid)
+    }
+
+    /// Note that the guest took all pending interrupts of a vCPU
+    pub fn vcpu_interrupts_handled(&self, vm_id: VmId, vcpu_id: VcpuId) {
+        self.idle.lock().interrupts_handled(vm_id, vcpu_id);
+    }
+
+    /// Check whether a vCPU is descheduled waiting for an interrupt
+    pub fn is_vcpu_idle(&self, vm_id: VmId, vcpu_id: VcpuId) -> bool {
+        self.idle.lock().is_idle(vm_id, vcpu_id)
+    }
+
+    /// Enable or disable gang scheduling for a VM
+    pub fn set_gang_scheduling(&self, vm_id: VmId, enabled: bool) {
+        self.gang.lock().set_enabled(vm_id, enabled);
+    }
+
+    /// Limit a VM to `quota_ns` of CPU time every `period_ns`
+    pub fn set_cpu_quota(&self, vm_id: VmId, period_ns: u64, quota_ns: u64) -> Result<()> {
+        let quota = CpuQuota { period_ns, quota_ns };
+        self.bandwidth.lock().set_quota(vm_id, quota, crate::utils::time::timestamp_ns())
+    }
+
+    /// Remove a VM's CPU time limit
+    pub fn clear_cpu_quota(&self, vm_id: VmId) {
+        self.bandwidth.lock().clear_quota(vm_id);
+    }
+
+    /// Place the ready siblings of a gang member about to run on `cpu_id`
+    ///
+    /// Siblings are removed from the ready queue and handed to idle CPUs,
+    /// which are kicked to reschedule. Returns false, leaving everything
+    /// queued, if the siblings don't all fit.
+    fn dispatch_gang(&self, cpu_id: usize, leader: &ThreadControlBlock) -> bool {
+        let siblings: Vec<GangTask> = {
+            let threads = self.threads.lock();
+            threads.iter()
+                .flatten()
+                .map(|tcb| unsafe { tcb.as_ref() })
+                .filter(|tcb| tcb.id != leader.id
+                    && tcb.vm_id == leader.vm_id
+                    && tcb.state == ThreadState::Ready)
+                .map(|tcb| GangTask {
+                    tid: tcb.id,
+                    vm_id: tcb.vm_id,
+                    cpu_affinity: tcb.cpu_affinity,
+                })
+                .collect()
+        };
+
+        if siblings.is_empty() {
+            return true;
+        }
+
+        let free_cpus = {
+            let online = crate::arch::cpu::get_online_cpu_mask();
+            let current = self.current_thread.lock();
+            let idle = self.idle_threads.lock();
+            let dispatch = self.gang_dispatch.lock();
+            online.iter()
+                .filter(|&cpu| cpu as usize != cpu_id)
+                .filter(|&cpu| dispatch[cpu as usize].is_none())
+                .filter(|&cpu| current[cpu as usize].map_or(true, |tid| tid == idle[cpu as usize]))
+                .collect::<CpuMask>()
+        };
+
+        let placement = match self.gang.lock().place_siblings(&siblings, free_cpus) {
+            Some(placement) => placement,
+            None => return false,
+        };
+
+        for (cpu, tid) in placement {
+            if let Some(tcb) = self.get_thread(tid) {
+                unsafe {
+                    self.ready_queue.lock().dequeue(tcb.as_mut());
+                }
+            }
+            self.gang_dispatch.lock()[cpu] = Some(tid);
+
+            if let Err(e) = crate::core::irq::send_ipi(cpu, crate::core::irq::IpiType::Reschedule) {
+                crate::warn!("Failed to kick CPU {} for gang member {}: {:?}", cpu, tid, e);
+            }
+        }
+
+        true
+    }
+
     /// Block the current thread
     pub fn block_current(&self, cpu_id: usize) -> Result<()> {
         let current_tid = {
@@ -542,6 +930,11 @@ impl Scheduler {
         *self.stats.lock()
     }
 
+    /// Get context switch, migration and per-thread runtime accounting
+    pub fn dispatch_stats(&self) -> DispatchStats {
+        self.accounting.lock().stats.clone()
+    }
+
     /// Get current thread on a CPU
     pub fn get_current_thread(&self, cpu_id: usize) -> Option<ThreadId> {
         let current_threads = self.current_thread.lock();
@@ -648,4 +1041,133 @@ pub fn yield_current() -> Result<()> {
 /// Get scheduler statistics
 pub fn get_stats() -> SchedulerStats {
     get().get_stats()
+}
+
+/// Get dispatch accounting, empty before the scheduler is initialized
+pub fn dispatch_stats() -> DispatchStats {
+    if !SCHEDULER_INIT.load(Ordering::Acquire) {
+        return DispatchStats::default();
+    }
+    get().dispatch_stats()
+}
+
+/// Limit a VM's CPU time
+pub fn set_cpu_quota(vm_id: VmId, period_ns: u64, quota_ns: u64) -> Result<()> {
+    if !SCHEDULER_INIT.load(Ordering::Acquire) {
+        return Err(Error::NotInitialized);
+    }
+    get().set_cpu_quota(vm_id, period_ns, quota_ns)
+}
+
+/// Remove a VM's CPU time limit
+pub fn clear_cpu_quota(vm_id: VmId) {
+    if SCHEDULER_INIT.load(Ordering::Acquire) {
+        get().clear_cpu_quota(vm_id);
+    }
+}
+
+/// Deschedule the current vCPU until an interrupt is pending for it
+pub fn vcpu_idle(reason: IdleReason) -> Result<bool> {
+    if !SCHEDULER_INIT.load(Ordering::Acquire) {
+        return Err(Error::NotInitialized);
+    }
+    get().vcpu_idle(crate::core::cpu_id(), reason)
+}
+
+/// Note an interrupt injected into a vCPU
+pub fn vcpu_interrupt_pending(vm_id: VmId, vcpu_id: VcpuId) -> Result<()> {
+    if !SCHEDULER_INIT.load(Ordering::Acquire) {
+        return Ok(());
+    }
+    get().vcpu_interrupt_pending(vm_id, vcpu_id)
+}
+
+/// Note that the guest took all pending interrupts of a vCPU
+pub fn vcpu_interrupts_handled(vm_id: VmId, vcpu_id: VcpuId) {
+    if SCHEDULER_INIT.load(Ordering::Acquire) {
+        get().vcpu_interrupts_handled(vm_id, vcpu_id);
+    }
+}
+
+/// Pin the host thread of a vCPU
+pub fn set_vcpu_affinity(vm_id: VmId, vcpu_id: VcpuId, mask: CpuMask) -> Result<()> {
+    get().set_vcpu_affinity(vm_id, vcpu_id, mask)
+}
+
+/// Enable or disable gang scheduling for a VM
+pub fn set_gang_scheduling(vm_id: VmId, enabled: bool) {
+    get().set_gang_scheduling(vm_id, enabled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pinned_vcpu_only_selected_on_its_cpu() {
+        let mut pinned = ThreadControlBlock::new_vcpu(1, 0, 0, Priority::Normal);
+        pinned.cpu_affinity = CpuMask::from_cpu(2);
+        let threads = [pinned];
+
+        for cpu in 0..8 {
+            let selected = first_allowed(threads.iter(), cpu).map(|tcb| tcb.id);
+            if cpu == 2 {
+                assert_eq!(selected, Some(1));
+            } else {
+                assert_eq!(selected, None);
+            }
+        }
+    }
+
+    #[test]
+    fn test_dispatch_accounting_two_tasks() {
+        let mut accounting = DispatchAccounting::new();
+
+        // Two vCPU threads alternate on CPU 0 every 10 ticks
+        let mut now = 0;
+        for round in 0..4 {
+            let tid = if round % 2 == 0 { 1 } else { 2 };
+            accounting.dispatch(0, Some((tid, true)), now);
+            now += 10;
+        }
+        // Thread 1 moves to CPU 1, CPU 0 goes idle
+        accounting.dispatch(0, None, now);
+        accounting.dispatch(1, Some((1, true)), now);
+        now += 5;
+        accounting.dispatch(1, Some((1, true)), now);
+
+        let stats = &accounting.stats;
+        // 1, 2, 1, 2, idle on CPU 0 and 1 on CPU 1; the last re-dispatch isn't a switch
+        assert_eq!(stats.context_switches, 6);
+        assert_eq!(stats.migrations, 1);
+        assert_eq!(stats.runtime_of(1), 25);
+        assert_eq!(stats.runtime_of(2), 20);
+        assert_eq!(stats.total_runtime(), 45);
+    }
+
+    #[test]
+    fn test_dispatch_accounting_forget() {
+        let mut accounting = DispatchAccounting::new();
+        accounting.dispatch(0, Some((7, false)), 0);
+        accounting.dispatch(0, Some((8, false)), 10);
+        accounting.forget(7);
+
+        assert_eq!(accounting.stats.runtime_of(7), 0);
+        accounting.dispatch(0, None, 15);
+        assert_eq!(accounting.stats.runtime_of(8), 5);
+        // Non-vCPU threads never count as migrations
+        accounting.dispatch(1, Some((8, false)), 20);
+        assert_eq!(accounting.stats.migrations, 0);
+    }
+
+    #[test]
+    fn test_selection_skips_threads_pinned_elsewhere() {
+        let mut pinned = ThreadControlBlock::new(1, Priority::Normal);
+        pinned.cpu_affinity = CpuMask::from_cpu(3);
+        let free = ThreadControlBlock::new(2, Priority::Normal);
+        let threads = [pinned, free];
+
+        assert_eq!(first_allowed(threads.iter(), 0).map(|tcb| tcb.id), Some(2));
+        assert_eq!(first_allowed(threads.iter(), 3).map(|tcb| tcb.id), Some(1));
+    }
 }
\ No newline at end of file
@@ -6,11 +6,13 @@
 use crate::Result;
 
 pub mod mutex;
+pub mod once;
 pub mod spinlock;
 pub mod semaphore;
 
 // Re-export SpinLock for convenience
 pub use spinlock::SpinLock;
+pub use once::Once;
 
 /// Initialize synchronization subsystem
 pub fn init() -> Result<()> {
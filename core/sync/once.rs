@@ -0,0 +1,137 @@
+//! One-time initialization
+//!
+//! `Once<T>` holds a value that is set once, during initialization, and
+//! shared read-only afterwards. It replaces `static mut Option<T>` globals:
+//! the value is published with release ordering and read with acquire
+//! ordering, so a CPU that sees the value also sees everything written
+//! before it was set. Globals that are modified after initialization keep
+//! their value behind a `SpinLock` inside the `Once`.
+
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+/// No value, nobody initializing
+const INCOMPLETE: u8 = 0;
+/// A CPU is storing the value
+const RUNNING: u8 = 1;
+/// The value is set
+const COMPLETE: u8 = 2;
+
+/// A value set at most once
+pub struct Once<T> {
+    /// Initialization state
+    state: AtomicU8,
+    /// The value, initialized once `state` is `COMPLETE`
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+unsafe impl<T: Send> Send for Once<T> {}
+unsafe impl<T: Send + Sync> Sync for Once<T> {}
+
+impl<T> Once<T> {
+    /// Create an empty cell
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU8::new(INCOMPLETE),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// Claim the cell for initialization
+    fn claim(&self) -> bool {
+        self.state
+            .compare_exchange(INCOMPLETE, RUNNING, Ordering::Acquire, Ordering::Acquire)
+            .is_ok()
+    }
+
+    /// Store the value of a claimed cell and publish it
+    fn publish(&self, value: T) -> &T {
+        // The claim gives this CPU exclusive access until COMPLETE is stored
+        let value = unsafe { (*self.value.get()).write(value) };
+        self.state.store(COMPLETE, Ordering::Release);
+        value
+    }
+
+    /// Set the value
+    ///
+    /// Fails, handing the value back, if the cell is already set or
+    /// another CPU is setting it.
+    pub fn set(&self, value: T) -> core::result::Result<&T, T> {
+        if self.claim() {
+            Ok(self.publish(value))
+        } else {
+            Err(value)
+        }
+    }
+
+    /// Get the value, running `init` to set it if the cell is empty
+    ///
+    /// If another CPU is setting the value, waits for it.
+    pub fn call_once(&self, init: impl FnOnce() -> T) -> &T {
+        if self.claim() {
+            return self.publish(init());
+        }
+        loop {
+            if let Some(value) = self.get() {
+                return value;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Get the value, if it is set
+    pub fn get(&self) -> Option<&T> {
+        if self.is_completed() {
+            // COMPLETE is only stored after the value is written, and the
+            // value is never written again
+            Some(unsafe { (*self.value.get()).assume_init_ref() })
+        } else {
+            None
+        }
+    }
+
+    /// Check whether the value is set
+    pub fn is_completed(&self) -> bool {
+        self.state.load(Ordering::Acquire) == COMPLETE
+    }
+}
+
+impl<T> Default for Once<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for Once<T> {
+    fn drop(&mut self) {
+        if *self.state.get_mut() == COMPLETE {
+            unsafe { self.value.get_mut().assume_init_drop() };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_racing_initializers_see_one_instance() {
+        let once: Once<u64> = Once::new();
+        assert_eq!(once.get(), None);
+
+        // A second CPU arriving while the first is still initializing
+        // neither sees a partial value nor gets to store its own
+        let first = once.call_once(|| {
+            assert_eq!(once.get(), None);
+            assert_eq!(once.set(2), Err(2));
+            1
+        });
+        assert_eq!(*first, 1);
+
+        let late = once.call_once(|| 3);
+        assert!(core::ptr::eq(first, late));
+        assert_eq!(once.set(4), Err(4));
+        assert!(core::ptr::eq(once.get().unwrap(), first));
+    }
+}
@@ -2,15 +2,54 @@
 //!
 //! Provides a basic spinlock that busy-waits until the lock is acquired.
 //! Suitable for short critical sections.
+//!
+//! With the `debug` feature, `SpinLock::lock()` reports a likely deadlock
+//! once it has spun `DEADLOCK_SPIN_THRESHOLD` times, naming the lock's
+//! creation site and the owning CPU.
 
 use core::cell::UnsafeCell;
 use core::ops::{Deref, DerefMut};
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+#[cfg(feature = "debug")]
+use core::panic::Location;
+
+/// Spins after which a contended `SpinLock::lock()` is reported as deadlocked
+#[cfg(feature = "debug")]
+pub const DEADLOCK_SPIN_THRESHOLD: usize = 1 << 22;
+
+/// Panic instead of logging when a deadlock is detected
+#[cfg(feature = "debug")]
+static DEADLOCK_PANIC: AtomicBool = AtomicBool::new(false);
+
+/// Panic, rather than log and keep spinning, on a detected deadlock
+#[cfg(feature = "debug")]
+pub fn set_deadlock_panic(enabled: bool) {
+    DEADLOCK_PANIC.store(enabled, Ordering::Relaxed);
+}
+
+/// Spin-wait hint for the current architecture
+#[inline(always)]
+fn cpu_relax() {
+    #[cfg(target_arch = "aarch64")]
+    unsafe { core::arch::asm!("yield") };
+
+    #[cfg(target_arch = "riscv64")]
+    unsafe { core::arch::asm!("pause") };
+
+    #[cfg(target_arch = "x86_64")]
+    unsafe { core::arch::asm!("pause") };
+}
 
 /// A simple spinlock
 pub struct SpinLock<T> {
     /// Atomic flag indicating if the lock is held
     locked: AtomicBool,
+    /// CPU holding the lock plus one, zero when free
+    #[cfg(feature = "debug")]
+    owner: AtomicUsize,
+    /// Where the lock was created
+    #[cfg(feature = "debug")]
+    site: &'static Location<'static>,
     /// The data protected by the lock
     data: UnsafeCell<T>,
 }
@@ -20,9 +59,14 @@ unsafe impl<T: Send> Sync for SpinLock<T> {}
 
 impl<T> SpinLock<T> {
     /// Create a new spinlock
+    #[cfg_attr(feature = "debug", track_caller)]
     pub const fn new(data: T) -> Self {
         Self {
             locked: AtomicBool::new(false),
+            #[cfg(feature = "debug")]
+            owner: AtomicUsize::new(0),
+            #[cfg(feature = "debug")]
+            site: Location::caller(),
             data: UnsafeCell::new(data),
         }
     }
@@ -35,6 +79,8 @@ impl<T> SpinLock<T> {
             Ordering::Acquire,
             Ordering::Relaxed,
         ).is_ok() {
+            #[cfg(feature = "debug")]
+            self.owner.store(crate::core::cpu_id() + 1, Ordering::Relaxed);
             Some(SpinLockGuard { lock: self })
         } else {
             None
@@ -43,20 +89,43 @@ impl<T> SpinLock<T> {
 
     /// Acquire the lock, blocking until it's available
     pub fn lock(&self) -> SpinLockGuard<T> {
-        while !self.try_lock().is_some() {
+        #[cfg(feature = "debug")]
+        let mut spins = 0usize;
+
+        loop {
+            if let Some(guard) = self.try_lock() {
+                return guard;
+            }
+
+            #[cfg(feature = "debug")]
+            {
+                spins += 1;
+                if spins == DEADLOCK_SPIN_THRESHOLD {
+                    self.report_deadlock();
+                }
+            }
+
             // Spin until we can acquire the lock
-            #[cfg(target_arch = "aarch64")]
-            unsafe { core::arch::asm!("yield") };
+            cpu_relax();
+        }
+    }
 
-            #[cfg(target_arch = "riscv64")]
-            unsafe { core::arch::asm!("pause") };
+    /// Report a lock that has been spun on for too long
+    #[cfg(feature = "debug")]
+    #[cold]
+    fn report_deadlock(&self) {
+        let cpu = crate::core::cpu_id();
+        let owner = self.owner.load(Ordering::Relaxed);
 
-            #[cfg(target_arch = "x86_64")]
-            unsafe { core::arch::asm!("pause") };
+        if DEADLOCK_PANIC.load(Ordering::Relaxed) {
+            panic!("SpinLock deadlock: lock created at {} spinning on CPU {}, owner CPU {}",
+                   self.site, cpu, owner.wrapping_sub(1) as isize);
         }
 
-        // The try_lock() call succeeded, so we can unwrap
-        self.try_lock().unwrap()
+        // The stuck lock may be the console's, so bypass it
+        crate::utils::console::early_print_fmt(format_args!(
+            "Possible SpinLock deadlock: lock created at {} spinning on CPU {}, owner CPU {}\n",
+            self.site, cpu, owner.wrapping_sub(1) as isize));
     }
 
     /// Force unlock the lock (DANGEROUS!)
@@ -66,6 +135,8 @@ impl<T> SpinLock<T> {
     /// guarantees of the lock. It should only be used in exceptional
     /// circumstances, such as during panic handling.
     pub unsafe fn force_unlock(&self) {
+        #[cfg(feature = "debug")]
+        self.owner.store(0, Ordering::Relaxed);
         self.locked.store(false, Ordering::Release);
     }
 
@@ -96,6 +167,8 @@ impl<'a, T> DerefMut for SpinLockGuard<'a, T> {
 
 impl<'a, T> Drop for SpinLockGuard<'a, T> {
     fn drop(&mut self) {
+        #[cfg(feature = "debug")]
+        self.lock.owner.store(0, Ordering::Relaxed);
         self.lock.locked.store(false, Ordering::Release);
     }
 }
@@ -121,14 +194,7 @@ impl RawSpinLock {
             Ordering::Acquire,
             Ordering::Relaxed,
         ).is_err() {
-            #[cfg(target_arch = "aarch64")]
-            unsafe { core::arch::asm!("yield") };
-
-            #[cfg(target_arch = "riscv64")]
-            unsafe { core::arch::asm!("pause") };
-
-            #[cfg(target_arch = "x86_64")]
-            unsafe { core::arch::asm!("pause") };
+            cpu_relax();
         }
     }
 
@@ -177,14 +243,7 @@ impl TicketLock {
     pub fn lock(&self) -> TicketLockGuard {
         let ticket = self.next_ticket.fetch_add(1, Ordering::Acquire);
         while self.serving.load(Ordering::Acquire) != ticket {
-            #[cfg(target_arch = "aarch64")]
-            unsafe { core::arch::asm!("yield") };
-
-            #[cfg(target_arch = "riscv64")]
-            unsafe { core::arch::asm!("pause") };
-
-            #[cfg(target_arch = "x86_64")]
-            unsafe { core::arch::asm!("pause") };
+            cpu_relax();
         }
         TicketLockGuard { lock: self }
     }
@@ -229,4 +288,29 @@ impl<'a> Drop for TicketLockGuard<'a> {
     fn drop(&mut self) {
         self.lock.serving.fetch_add(1, Ordering::Release);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(all(test, feature = "debug"))]
+mod tests {
+    use super::*;
+
+    /// Restores the deadlock panic setting, including when the test unwinds
+    struct DeadlockPanicGuard(bool);
+
+    impl Drop for DeadlockPanicGuard {
+        fn drop(&mut self) {
+            set_deadlock_panic(self.0);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "SpinLock deadlock: lock created at")]
+    fn test_reacquire_trips_deadlock_detector() {
+        let _restore = DeadlockPanicGuard(DEADLOCK_PANIC.swap(true, Ordering::Relaxed));
+
+        let lock = SpinLock::new(0u32);
+        let _held = lock.lock();
+        // Same context, lock never released
+        let _again = lock.lock();
+    }
+}
@@ -0,0 +1,473 @@
+//! High-resolution timers
+//!
+//! One-shot and periodic timers with callbacks, backed by the
+//! architectural comparator (CLINT mtimecmp on RISC-V, CNTV_CVAL_EL0 on
+//! ARM64). Pending timers are kept sorted by deadline; the comparator is
+//! always programmed to the earliest one and the timer interrupt fires
+//! everything that is due.
+//!
+//! The scheduler tick shares the comparator: it is programmed to
+//! whichever comes first, the next timer deadline or the next tick, so
+//! timed callbacks fire precisely rather than at tick granularity.
+//!
+//! The comparator is per-hart, so every CPU keeps its own timer list and
+//! tick. Timers fire on the CPU that armed them, and each CPU arms its
+//! own tick through [`cpu_online`] as it is brought up.
+//!
+//! Deadlines are in nanoseconds on the `crate::utils::time::timestamp_ns`
+//! clock.
+
+use crate::{Result, Error};
+use crate::core::sync::SpinLock;
+use crate::libs::cpumask::MAX_CPUS;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Handle identifying an armed timer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TimerHandle(u64);
+
+/// Timer expiry callback
+pub type TimerCallback = fn(handle: TimerHandle);
+
+/// Comparator programming function, given an absolute deadline in ns
+pub type ComparatorFn = fn(deadline_ns: u64);
+
+/// An armed timer
+#[derive(Debug, Clone, Copy)]
+struct TimerEntry {
+    /// Absolute deadline
+    deadline_ns: u64,
+    /// Re-arm interval for periodic timers
+    interval_ns: Option<u64>,
+    /// Handle
+    handle: TimerHandle,
+    /// Expiry callback
+    callback: TimerCallback,
+}
+
+/// Timer list driving one comparator
+pub struct Timer {
+    /// Armed timers, sorted by deadline
+    pending: Vec<TimerEntry>,
+    /// Comparator programming
+    program: ComparatorFn,
+    /// Deadline currently programmed into the comparator
+    programmed_ns: Option<u64>,
+    /// Scheduler tick period, if the tick is running
+    tick_period_ns: Option<u64>,
+    /// Next scheduler tick
+    next_tick_ns: u64,
+}
+
+impl Timer {
+    /// Create an empty timer list driving `program`
+    pub const fn new(program: ComparatorFn) -> Self {
+        Self {
+            pending: Vec::new(),
+            program,
+            programmed_ns: None,
+            tick_period_ns: None,
+            next_tick_ns: 0,
+        }
+    }
+
+    /// Arm a timer that fires once at `deadline_ns`
+    pub fn oneshot(&mut self, deadline_ns: u64, callback: TimerCallback) -> TimerHandle {
+        self.arm(deadline_ns, None, callback)
+    }
+
+    /// Arm a timer that fires every `interval_ns`, starting at `now_ns + interval_ns`
+    pub fn periodic(&mut self, now_ns: u64, interval_ns: u64, callback: TimerCallback) -> Result<TimerHandle> {
+        if interval_ns == 0 {
+            return Err(Error::InvalidArgument);
+        }
+        Ok(self.arm(now_ns.saturating_add(interval_ns), Some(interval_ns), callback))
+    }
+
+    /// Disarm a timer
+    ///
+    /// Returns `NotFound` if the timer already fired or was canceled.
+    pub fn cancel(&mut self, handle: TimerHandle) -> Result<()> {
+        self.remove(handle)?;
+        self.reprogram();
+        Ok(())
+    }
+
+    /// Disarm a timer without touching the comparator
+    ///
+    /// Used for another CPU's list: the comparator belongs to that CPU,
+    /// which at worst takes one spurious interrupt and reprograms.
+    pub fn remove(&mut self, handle: TimerHandle) -> Result<()> {
+        let index = self.pending.iter()
+            .position(|entry| entry.handle == handle)
+            .ok_or(Error::NotFound)?;
+        self.pending.remove(index);
+        Ok(())
+    }
+
+    /// Earliest pending deadline
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.pending.first().map(|entry| entry.deadline_ns)
+    }
+
+    /// Start the scheduler tick, first due at `now_ns + period_ns`
+    pub fn start_tick(&mut self, now_ns: u64, period_ns: u64) -> Result<()> {
+        if period_ns == 0 {
+            return Err(Error::InvalidArgument);
+        }
+        self.tick_period_ns = Some(period_ns);
+        self.next_tick_ns = now_ns.saturating_add(period_ns);
+        self.reprogram();
+        Ok(())
+    }
+
+    /// Stop the scheduler tick
+    pub fn stop_tick(&mut self) {
+        self.tick_period_ns = None;
+        self.reprogram();
+    }
+
+    /// Scheduler tick period, if the tick is running
+    pub fn tick_period(&self) -> Option<u64> {
+        self.tick_period_ns
+    }
+
+    /// Next scheduler tick, if the tick is running
+    pub fn next_tick(&self) -> Option<u64> {
+        self.tick_period_ns.map(|_| self.next_tick_ns)
+    }
+
+    /// Earliest event the comparator must fire for
+    pub fn next_event(&self) -> Option<u64> {
+        match (self.next_deadline(), self.next_tick()) {
+            (Some(deadline), Some(tick)) => Some(deadline.min(tick)),
+            (deadline, tick) => deadline.or(tick),
+        }
+    }
+
+    /// Consume the scheduler tick if it is due at `now_ns`
+    ///
+    /// Advances the next tick past `now_ns`, dropping missed ticks.
+    pub fn take_tick(&mut self, now_ns: u64) -> bool {
+        let period = match self.tick_period_ns {
+            Some(period) if self.next_tick_ns <= now_ns => period,
+            _ => return false,
+        };
+
+        let missed = (now_ns - self.next_tick_ns) / period;
+        self.next_tick_ns = self.next_tick_ns.saturating_add((missed + 1) * period);
+        self.reprogram();
+        true
+    }
+
+    /// Deadline currently programmed into the comparator
+    pub fn programmed_deadline(&self) -> Option<u64> {
+        self.programmed_ns
+    }
+
+    /// Number of armed timers
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Remove every timer due at `now_ns`
+    ///
+    /// Periodic timers are re-armed. Returns the callbacks to invoke, in
+    /// deadline order; the caller runs them without holding the list so
+    /// they may arm or cancel timers themselves.
+    pub fn expire(&mut self, now_ns: u64) -> Vec<(TimerHandle, TimerCallback)> {
+        let due = self.pending.iter()
+            .take_while(|entry| entry.deadline_ns <= now_ns)
+            .count();
+        let expired: Vec<TimerEntry> = self.pending.drain(..due).collect();
+
+        let mut fired = Vec::with_capacity(expired.len());
+        for entry in expired {
+            fired.push((entry.handle, entry.callback));
+
+            if let Some(interval) = entry.interval_ns {
+                // Skip periods missed while the interrupt was delayed
+                let missed = (now_ns - entry.deadline_ns) / interval;
+                let deadline = entry.deadline_ns.saturating_add((missed + 1) * interval);
+                self.insert(TimerEntry { deadline_ns: deadline, ..entry });
+            }
+        }
+
+        self.reprogram();
+        fired
+    }
+
+    /// Arm a new timer
+    fn arm(&mut self, deadline_ns: u64, interval_ns: Option<u64>, callback: TimerCallback) -> TimerHandle {
+        // Handles are unique across CPUs so a timer can be canceled anywhere
+        let handle = TimerHandle(NEXT_HANDLE.fetch_add(1, Ordering::Relaxed));
+
+        self.insert(TimerEntry { deadline_ns, interval_ns, handle, callback });
+        self.reprogram();
+        handle
+    }
+
+    /// Insert keeping the list sorted; equal deadlines fire in arming order
+    fn insert(&mut self, entry: TimerEntry) {
+        let index = self.pending.partition_point(|other| other.deadline_ns <= entry.deadline_ns);
+        self.pending.insert(index, entry);
+    }
+
+    /// Program the comparator to the earliest event if it changed
+    fn reprogram(&mut self) {
+        let next = self.next_event();
+        if next != self.programmed_ns {
+            self.programmed_ns = next;
+            // With nothing armed the comparator is pushed out of reach
+            (self.program)(next.unwrap_or(u64::MAX));
+        }
+    }
+}
+
+/// Program the architectural timer comparator
+fn program_comparator(deadline_ns: u64) {
+    let freq = crate::utils::time::timer_frequency();
+    let ticks = match deadline_ns {
+        u64::MAX => u64::MAX,
+        ns => crate::utils::time::ns_to_ticks(ns, freq),
+    };
+
+    #[cfg(target_arch = "riscv64")]
+    {
+        if let Some(clint) = crate::arch::riscv64::platform::clint::get_clint() {
+            let hart = crate::arch::riscv64::cpu::current_cpu_id() as u32;
+            if let Err(e) = clint.set_timer_comparator(hart, ticks) {
+                crate::error!("hrtimer: failed to program mtimecmp: {}", e);
+            }
+        }
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    {
+        use crate::arch::arm64::timer::{ctrl, generic::virtual_};
+        virtual_::write_cval(ticks);
+        virtual_::write_ctl(ctrl::ENABLE);
+    }
+
+    #[cfg(not(any(target_arch = "riscv64", target_arch = "aarch64")))]
+    {
+        let _ = ticks;
+    }
+}
+
+/// Next timer handle, shared by every CPU's list
+static NEXT_HANDLE: AtomicU64 = AtomicU64::new(1);
+
+/// Default scheduler tick period (10 ms)
+pub const DEFAULT_TICK_PERIOD_NS: u64 = 10_000_000;
+
+/// Tick period CPUs arm when they come online
+static TICK_PERIOD_NS: AtomicU64 = AtomicU64::new(DEFAULT_TICK_PERIOD_NS);
+
+/// Per-CPU timer lists, each driving that CPU's comparator
+static TIMERS: [SpinLock<Timer>; MAX_CPUS] =
+    [const { SpinLock::new(Timer::new(program_comparator)) }; MAX_CPUS];
+
+/// Timer list of the calling CPU
+fn local_timer() -> &'static SpinLock<Timer> {
+    &TIMERS[crate::core::cpu_id() % MAX_CPUS]
+}
+
+/// Arm a one-shot timer at the absolute `deadline_ns` on this CPU
+pub fn oneshot(deadline_ns: u64, callback: TimerCallback) -> TimerHandle {
+    local_timer().lock().oneshot(deadline_ns, callback)
+}
+
+/// Arm a periodic timer firing every `interval_ns` from now on this CPU
+pub fn periodic(interval_ns: u64, callback: TimerCallback) -> Result<TimerHandle> {
+    let now = crate::utils::time::timestamp_ns();
+    local_timer().lock().periodic(now, interval_ns, callback)
+}
+
+/// Disarm a timer armed on any CPU
+pub fn cancel(handle: TimerHandle) -> Result<()> {
+    let local = local_timer();
+    if local.lock().cancel(handle).is_ok() {
+        return Ok(());
+    }
+
+    TIMERS.iter()
+        .filter(|timer| !core::ptr::eq(*timer, local))
+        .find_map(|timer| timer.lock().remove(handle).ok())
+        .ok_or(Error::NotFound)
+}
+
+/// Start the scheduler tick on this CPU's comparator
+///
+/// The period is also used by CPUs brought online afterwards.
+pub fn start_tick(period_ns: u64) -> Result<()> {
+    let now = crate::utils::time::timestamp_ns();
+    local_timer().lock().start_tick(now, period_ns)?;
+    TICK_PERIOD_NS.store(period_ns, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Check whether the scheduler tick is running on this CPU
+pub fn tick_running() -> bool {
+    local_timer().lock().tick_period().is_some()
+}
+
+/// Arm this CPU's comparator for the scheduler tick
+///
+/// Called on each CPU as it comes online; the comparator is per-hart, so
+/// no other CPU can arm it.
+pub fn cpu_online() -> Result<()> {
+    let now = crate::utils::time::timestamp_ns();
+    let period = TICK_PERIOD_NS.load(Ordering::Relaxed);
+    local_timer().lock().start_tick(now, period)
+}
+
+/// Service the timer interrupt on this CPU
+///
+/// Fires every timer due on this CPU and reprograms its comparator to
+/// the next event. Returns whether the scheduler tick is due; the caller runs
+/// it after this returns.
+pub fn handle_interrupt() -> bool {
+    let now = crate::utils::time::timestamp_ns();
+    let (fired, tick) = {
+        let mut timer = local_timer().lock();
+        let fired = timer.expire(now);
+        let tick = timer.take_tick(now);
+        (fired, tick)
+    };
+
+    for &(handle, callback) in &fired {
+        callback(handle);
+    }
+    tick
+}
+
+/// Initialize the timer subsystem
+pub fn init() -> Result<()> {
+    crate::info!("Initializing high-resolution timers");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+    fn no_comparator(_deadline_ns: u64) {}
+
+    fn run(timer: &mut Timer, now_ns: u64) -> usize {
+        let fired = timer.expire(now_ns);
+        for &(handle, callback) in &fired {
+            callback(handle);
+        }
+        fired.len()
+    }
+
+    #[test]
+    fn test_oneshot_fires_once_after_deadline() {
+        static FIRED: AtomicU32 = AtomicU32::new(0);
+        fn on_expiry(_handle: TimerHandle) {
+            FIRED.fetch_add(1, Ordering::SeqCst);
+        }
+
+        let mut timer = Timer::new(no_comparator);
+        timer.oneshot(1_000, on_expiry);
+
+        assert_eq!(run(&mut timer, 999), 0);
+        assert_eq!(FIRED.load(Ordering::SeqCst), 0);
+
+        assert_eq!(run(&mut timer, 1_000), 1);
+        assert_eq!(run(&mut timer, 5_000), 0);
+        assert_eq!(FIRED.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_canceled_timer_never_fires() {
+        static FIRED: AtomicU32 = AtomicU32::new(0);
+        fn on_expiry(_handle: TimerHandle) {
+            FIRED.fetch_add(1, Ordering::SeqCst);
+        }
+
+        let mut timer = Timer::new(no_comparator);
+        let handle = timer.oneshot(1_000, on_expiry);
+        timer.cancel(handle).unwrap();
+
+        assert_eq!(run(&mut timer, 10_000), 0);
+        assert_eq!(FIRED.load(Ordering::SeqCst), 0);
+        assert_eq!(timer.cancel(handle), Err(Error::NotFound));
+    }
+
+    #[test]
+    fn test_handles_are_unique_across_cpu_lists() {
+        static PROGRAMMED: AtomicU64 = AtomicU64::new(0);
+        fn comparator(deadline_ns: u64) {
+            PROGRAMMED.store(deadline_ns, Ordering::SeqCst);
+        }
+        fn noop(_handle: TimerHandle) {}
+
+        let mut cpu0 = Timer::new(no_comparator);
+        let mut cpu1 = Timer::new(comparator);
+        let first = cpu0.oneshot(1_000, noop);
+        let second = cpu1.oneshot(2_000, noop);
+        assert_ne!(first, second);
+        assert_eq!(cpu0.remove(second), Err(Error::NotFound));
+
+        // Removing from a remote list leaves its comparator for that CPU
+        cpu1.remove(second).unwrap();
+        assert_eq!(PROGRAMMED.load(Ordering::SeqCst), 2_000);
+        assert_eq!(cpu1.pending(), 0);
+    }
+
+    #[test]
+    fn test_periodic_rearms_and_comparator_tracks_earliest() {
+        static PROGRAMMED: AtomicU64 = AtomicU64::new(0);
+        fn comparator(deadline_ns: u64) {
+            PROGRAMMED.store(deadline_ns, Ordering::SeqCst);
+        }
+        fn noop(_handle: TimerHandle) {}
+
+        let mut timer = Timer::new(comparator);
+        let tick = timer.periodic(0, 100, noop).unwrap();
+        timer.oneshot(50, noop);
+        assert_eq!(PROGRAMMED.load(Ordering::SeqCst), 50);
+
+        assert_eq!(run(&mut timer, 100), 2);
+        assert_eq!(PROGRAMMED.load(Ordering::SeqCst), 200);
+
+        // A late interrupt skips missed periods
+        assert_eq!(run(&mut timer, 450), 1);
+        assert_eq!(timer.next_deadline(), Some(500));
+
+        timer.cancel(tick).unwrap();
+        assert_eq!(PROGRAMMED.load(Ordering::SeqCst), u64::MAX);
+        assert_eq!(timer.periodic(0, 0, noop), Err(Error::InvalidArgument));
+    }
+
+    #[test]
+    fn test_timer_between_ticks_fires_at_reprogrammed_comparator() {
+        static FIRED: AtomicU32 = AtomicU32::new(0);
+        fn on_expiry(_handle: TimerHandle) {
+            FIRED.fetch_add(1, Ordering::SeqCst);
+        }
+
+        // 1ms tick with a timer due halfway between the first two ticks
+        let mut timer = Timer::new(no_comparator);
+        timer.start_tick(0, 1_000_000).unwrap();
+        timer.oneshot(1_500_000, on_expiry);
+        assert_eq!(timer.programmed_deadline(), Some(1_000_000));
+
+        // Tick interrupt: nothing expires, comparator moves to the timer
+        assert_eq!(run(&mut timer, 1_000_000), 0);
+        assert!(timer.take_tick(1_000_000));
+        assert_eq!(timer.programmed_deadline(), Some(1_500_000));
+
+        // The next interrupt arrives at the comparator: the timer fires
+        // without a scheduler tick
+        let now = timer.programmed_deadline().unwrap();
+        assert_eq!(run(&mut timer, now), 1);
+        assert_eq!(FIRED.load(Ordering::SeqCst), 1);
+        assert!(!timer.take_tick(now));
+        assert_eq!(timer.programmed_deadline(), Some(2_000_000));
+    }
+}
@@ -0,0 +1,272 @@
+//! Live migration state stream
+//!
+//! `VirtualMachine::serialize()` captures the state a destination host
+//! needs besides guest RAM: the guest memory map, vCPU register contexts
+//! and the register state of attached emulators. Guest pages are streamed
+//! separately. The stream opens with a magic and version so a destination
+//! rejects streams it cannot parse.
+//!
+//! Integers are little-endian; variable-length fields are prefixed with
+//! their length as a u32.
+
+use crate::{Result, Error};
+use crate::core::vmm::VcpuRegisters;
+use crate::core::vmm::vcpu::reset_registers;
+use alloc::vec::Vec;
+
+/// Stream magic, "FRVM"
+pub const MIGRATION_MAGIC: u32 = 0x4D56_5246;
+
+/// Current stream format version
+pub const MIGRATION_VERSION: u32 = 1;
+
+/// Appends fields to a migration stream
+#[derive(Debug, Default)]
+pub struct StateWriter {
+    buf: Vec<u8>,
+}
+
+impl StateWriter {
+    /// Create an empty stream
+    pub fn new() -> Self {
+        Self { buf: Vec::new() }
+    }
+
+    /// Append a byte
+    pub fn put_u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    /// Append a u32
+    pub fn put_u32(&mut self, value: u32) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Append a u64
+    pub fn put_u64(&mut self, value: u64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Append a length-prefixed byte string
+    pub fn put_bytes(&mut self, bytes: &[u8]) {
+        self.put_u32(bytes.len() as u32);
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Take the stream
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// Reads fields back from a migration stream
+///
+/// Every getter fails with `Error::InvalidArgument` on a truncated stream.
+#[derive(Debug)]
+pub struct StateReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    /// Read from the start of `buf`
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    /// Take the next `len` bytes
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(len).ok_or(Error::InvalidArgument)?;
+        let bytes = self.buf.get(self.pos..end).ok_or(Error::InvalidArgument)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    /// Read a byte
+    pub fn get_u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// Read a u32
+    pub fn get_u32(&mut self) -> Result<u32> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    /// Read a u64
+    pub fn get_u64(&mut self) -> Result<u64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    /// Read a length-prefixed byte string
+    pub fn get_bytes(&mut self) -> Result<&'a [u8]> {
+        let len = self.get_u32()? as usize;
+        self.take(len)
+    }
+
+    /// Bytes not yet read
+    pub fn remaining(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+}
+
+/// Start a stream with the magic and current version
+pub fn write_header(writer: &mut StateWriter) {
+    writer.put_u32(MIGRATION_MAGIC);
+    writer.put_u32(MIGRATION_VERSION);
+}
+
+/// Check a stream's magic and version
+///
+/// Fails with `Error::InvalidArgument` if the stream is not a migration
+/// stream and `Error::NotImplemented` if it was written by a newer version.
+pub fn read_header(reader: &mut StateReader) -> Result<u32> {
+    if reader.get_u32()? != MIGRATION_MAGIC {
+        return Err(Error::InvalidArgument);
+    }
+
+    let version = reader.get_u32()?;
+    if version == 0 || version > MIGRATION_VERSION {
+        return Err(Error::NotImplemented);
+    }
+    Ok(version)
+}
+
+/// Append a vCPU register context
+///
+/// The architecture-specific block is the one for the architecture the
+/// hypervisor runs on; source and destination must match.
+pub fn write_registers(writer: &mut StateWriter, regs: &VcpuRegisters) {
+    for &gpr in regs.gpr.iter() {
+        writer.put_u64(gpr);
+    }
+    writer.put_u64(regs.pc);
+    writer.put_u64(regs.sp);
+    writer.put_u64(regs.psr);
+
+    #[cfg(target_arch = "riscv64")]
+    {
+        let arch = &regs.arch_regs;
+        for value in [arch.satp, arch.sstatus, arch.sie, arch.stvec, arch.sscratch,
+                      arch.sepc, arch.scause, arch.stval, arch.sip] {
+            writer.put_u64(value);
+        }
+        write_optional_regs(writer, arch.fp_regs.as_ref().map(|fp| &fp[..]));
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    {
+        let arch = &regs.arch_regs;
+        for value in [arch.sctlr_el1, arch.tcr_el1, arch.ttbr0_el1, arch.ttbr1_el1,
+                      arch.mair_el1, arch.amair_el1, arch.vbar_el1, arch.cntvoff_el2,
+                      arch.cntkctl_el1] {
+            writer.put_u64(value);
+        }
+        writer.put_u8(arch.fp_simd.is_some() as u8);
+        for &value in arch.fp_simd.iter().flatten() {
+            writer.put_u64(value as u64);
+            writer.put_u64((value >> 64) as u64);
+        }
+    }
+}
+
+/// Append an optional bank of 64-bit registers
+#[cfg(target_arch = "riscv64")]
+fn write_optional_regs(writer: &mut StateWriter, regs: Option<&[u64]>) {
+    writer.put_u8(regs.is_some() as u8);
+    for &value in regs.into_iter().flatten() {
+        writer.put_u64(value);
+    }
+}
+
+/// Read a vCPU register context written by `write_registers`
+pub fn read_registers(reader: &mut StateReader) -> Result<VcpuRegisters> {
+    let mut gpr = [0; 32];
+    for value in gpr.iter_mut() {
+        *value = reader.get_u64()?;
+    }
+    let pc = reader.get_u64()?;
+
+    let mut regs = reset_registers(pc);
+    regs.gpr = gpr;
+    regs.sp = reader.get_u64()?;
+    regs.psr = reader.get_u64()?;
+
+    #[cfg(target_arch = "riscv64")]
+    {
+        let mut csrs = [0; 9];
+        for value in csrs.iter_mut() {
+            *value = reader.get_u64()?;
+        }
+        let fp_regs = match reader.get_u8()? {
+            0 => None,
+            _ => {
+                let mut fp = [0; 32];
+                for value in fp.iter_mut() {
+                    *value = reader.get_u64()?;
+                }
+                Some(fp)
+            }
+        };
+        let [satp, sstatus, sie, stvec, sscratch, sepc, scause, stval, sip] = csrs;
+        regs.arch_regs = crate::core::vmm::VcpuRiscv64Registers {
+            satp, sstatus, sie, stvec, sscratch, sepc, scause, stval, sip, fp_regs,
+        };
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    {
+        let mut sysregs = [0; 9];
+        for value in sysregs.iter_mut() {
+            *value = reader.get_u64()?;
+        }
+        let fp_simd = match reader.get_u8()? {
+            0 => None,
+            _ => {
+                let mut fp = [0u128; 32];
+                for value in fp.iter_mut() {
+                    let low = reader.get_u64()? as u128;
+                    *value = low | ((reader.get_u64()? as u128) << 64);
+                }
+                Some(fp)
+            }
+        };
+        let [sctlr_el1, tcr_el1, ttbr0_el1, ttbr1_el1, mair_el1, amair_el1, vbar_el1,
+             cntvoff_el2, cntkctl_el1] = sysregs;
+        regs.arch_regs = crate::core::vmm::VcpuArm64Registers {
+            sctlr_el1, tcr_el1, ttbr0_el1, ttbr1_el1, mair_el1, amair_el1, vbar_el1,
+            cntvoff_el2, cntkctl_el1, fp_simd,
+        };
+    }
+
+    Ok(regs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_checks_magic_and_version() {
+        let mut writer = StateWriter::new();
+        write_header(&mut writer);
+        writer.put_bytes(b"uart");
+        let stream = writer.finish();
+
+        let mut reader = StateReader::new(&stream);
+        assert_eq!(read_header(&mut reader), Ok(MIGRATION_VERSION));
+        assert_eq!(reader.get_bytes(), Ok(&b"uart"[..]));
+        assert_eq!(reader.remaining(), 0);
+        assert_eq!(reader.get_u8(), Err(Error::InvalidArgument));
+
+        // A stream from a newer hypervisor is refused
+        let mut newer = stream.clone();
+        newer[4..8].copy_from_slice(&(MIGRATION_VERSION + 1).to_le_bytes());
+        assert_eq!(read_header(&mut StateReader::new(&newer)), Err(Error::NotImplemented));
+
+        assert_eq!(read_header(&mut StateReader::new(&stream[4..])), Err(Error::InvalidArgument));
+    }
+}
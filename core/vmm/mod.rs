@@ -12,6 +12,7 @@ use crate::core::sched::{Thread, ThreadId, Priority};
 pub mod vm;
 pub mod vcpu;
 pub mod vmcs;
+pub mod migration;
 
 /// VM ID type
 pub type VmId = u32;
@@ -207,9 +208,27 @@ pub fn run_vcpu(vm_id: VmId, vcpu_id: VcpuId) -> Result<VmExitInfo> {
     vcpu::run_vcpu(vm_id, vcpu_id)
 }
 
-/// Inject an interrupt into a VCPU
+/// Inject an interrupt into a VCPU, subject to the VM's interrupt rate limit
 pub fn inject_interrupt(vm_id: VmId, vcpu_id: VcpuId, vector: u32) -> Result<()> {
-    vcpu::inject_interrupt(vm_id, vcpu_id, vector)
+    vm::inject_interrupt(vm_id, vcpu_id, vector)
+}
+
+/// Remap a passthrough device's MSI to an interrupt of a VCPU
+///
+/// The host MSI `data` written to `addr` is injected into the VCPU as
+/// `guest_vector` instead of being handled by the host.
+pub fn map_msi(vm_id: VmId, vcpu_id: VcpuId, addr: PhysAddr, data: u32, guest_vector: u32) -> Result<()> {
+    if get_vm_state(vm_id).is_none() {
+        return Err(crate::Error::NotFound);
+    }
+    crate::core::irq::msi::map_msi(addr, data, vm_id, vcpu_id, guest_vector)
+}
+
+/// Stop remapping a passthrough device's MSI
+pub fn unmap_msi(addr: PhysAddr, data: u32) -> Result<()> {
+    crate::core::irq::msi::unmap_msi(addr, data)
+        .map(|_| ())
+        .ok_or(crate::Error::NotFound)
 }
 
 /// Inject an exception into a VCPU
@@ -275,19 +294,23 @@ pub struct VcpuRegisters {
     pub arch_regs: VcpuArchRegisters,
 }
 
-/// Architecture-specific VCPU registers
-#[repr(C)]
-pub union VcpuArchRegisters {
-    /// ARM64 specific registers
-    pub arm64: VcpuArm64Registers,
-    /// RISC-V specific registers
-    pub riscv64: VcpuRiscv64Registers,
-    /// x86_64 specific registers
-    pub x86_64: VcpuX86_64Registers,
-}
+/// Architecture-specific VCPU registers of the architecture the
+/// hypervisor runs on
+#[cfg(target_arch = "aarch64")]
+pub type VcpuArchRegisters = VcpuArm64Registers;
+
+/// Architecture-specific VCPU registers of the architecture the
+/// hypervisor runs on
+#[cfg(target_arch = "riscv64")]
+pub type VcpuArchRegisters = VcpuRiscv64Registers;
+
+/// Architecture-specific VCPU registers of the architecture the
+/// hypervisor runs on
+#[cfg(target_arch = "x86_64")]
+pub type VcpuArchRegisters = VcpuX86_64Registers;
 
 /// ARM64-specific VCPU registers
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct VcpuArm64Registers {
     /// System registers
     pub sctlr_el1: u64,
@@ -304,7 +327,7 @@ pub struct VcpuArm64Registers {
 }
 
 /// RISC-V-specific VCPU registers
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct VcpuRiscv64Registers {
     /// System registers
     pub satp: u64,
@@ -321,7 +344,7 @@ pub struct VcpuRiscv64Registers {
 }
 
 /// x86_64-specific VCPU registers
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct VcpuX86_64Registers {
     /// Control registers
     pub cr0: u64,
@@ -357,7 +380,7 @@ pub struct VcpuX86_64Registers {
 }
 
 /// Segment register
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Default)]
 pub struct SegmentRegister {
     /// Selector
     pub selector: u16,
@@ -3,14 +3,19 @@
 //! This module handles the lifecycle and management of virtual CPUs.
 
 use crate::{Result, Error};
-use crate::core::vmm::{VmId, VcpuId, VmExitInfo, VmExitReason, VmExitArchData, VcpuRegisters};
+use crate::core::vmm::{VmId, VcpuId, VmExitInfo, VmExitReason, VmExitArchData, VcpuRegisters,
+                       VcpuArchRegisters};
 use crate::core::sched::{Thread, ThreadId, Priority};
 use crate::core::sync::SpinLock;
 use core::ptr::NonNull;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 /// Maximum number of VCPUs
 pub const MAX_VCPUS: usize = 256;
 
+/// Polls of a running VCPU before `stop` gives up on it leaving the guest
+const STOP_TIMEOUT_SPINS: usize = 10_000_000;
+
 /// VCPU states
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum VcpuState {
@@ -69,6 +74,10 @@ pub struct VirtualCpu {
     exec_time: u64,
     /// Number of exits
     exit_count: u64,
+    /// Set while the VCPU executes guest code
+    in_guest: AtomicBool,
+    /// Keeps the VCPU out of the guest until it is reset
+    stop_requested: AtomicBool,
     /// Architecture-specific data
     arch_data: VcpuArchData,
 }
@@ -162,6 +171,17 @@ struct VcpuManager {
     active_vcpus: usize,
 }
 
+/// Register state of a VCPU coming out of reset at `entry`
+pub fn reset_registers(entry: u64) -> VcpuRegisters {
+    VcpuRegisters {
+        gpr: [0; 32],
+        pc: entry,
+        sp: 0,
+        psr: 0,
+        arch_regs: VcpuArchRegisters::default(),
+    }
+}
+
 impl VirtualCpu {
     /// Create a new VCPU
     pub fn new(id: VcpuId, vm_id: VmId) -> Result<Self> {
@@ -221,29 +241,14 @@ impl VirtualCpu {
             vm_id,
             state: VcpuState::Ready,
             priority: VcpuPriority::default(),
-            registers: SpinLock::new(VcpuRegisters {
-                gpr: [0; 32],
-                pc: 0,
-                sp: 0,
-                psr: 0,
-                arch_regs: VcpuArchRegisters { arm64: VcpuArm64Registers {
-                    sctlr_el1: 0,
-                    tcr_el1: 0,
-                    ttbr0_el1: 0,
-                    ttbr1_el1: 0,
-                    mair_el1: 0,
-                    amair_el1: 0,
-                    vbar_el1: 0,
-                    cntvoff_el2: 0,
-                    cntkctl_el1: 0,
-                    fp_simd: None,
-                }},
-            }),
+            registers: SpinLock::new(reset_registers(0)),
             host_thread: SpinLock::new(None),
             exit_info: SpinLock::new(None),
             time_slice: 10, // Default 10ms
             exec_time: 0,
             exit_count: 0,
+            in_guest: AtomicBool::new(false),
+            stop_requested: AtomicBool::new(false),
             arch_data,
         })
     }
@@ -289,6 +294,39 @@ impl VirtualCpu {
         Ok(())
     }
 
+    /// Return the VCPU to its reset state with the PC at `entry`
+    ///
+    /// A VCPU still in the guest must be stopped first.
+    pub fn reset(&mut self, entry: u64) -> Result<()> {
+        if self.in_guest.load(Ordering::Acquire) {
+            return Err(Error::ResourceBusy);
+        }
+
+        *self.registers.lock() = reset_registers(entry);
+        *self.exit_info.lock() = None;
+        self.state = VcpuState::Ready;
+        self.stop_requested.store(false, Ordering::Release);
+        Ok(())
+    }
+
+    /// Take the VCPU out of the guest and keep it out until it is reset
+    ///
+    /// A running VCPU leaves at its next exit, which the host timer tick
+    /// bounds; fails with `Error::Timeout` if it does not.
+    pub fn stop(&self) -> Result<()> {
+        self.stop_requested.store(true, Ordering::Release);
+
+        let mut spins = 0;
+        while self.in_guest.load(Ordering::Acquire) {
+            if spins == STOP_TIMEOUT_SPINS {
+                return Err(Error::Timeout);
+            }
+            spins += 1;
+            core::hint::spin_loop();
+        }
+        Ok(())
+    }
+
     /// Initialize VCPU for first run
     pub fn initialize(&mut self) -> Result<()> {
         // Architecture-specific initialization
@@ -377,8 +415,20 @@ impl VirtualCpu {
             return Err(Error::InvalidState);
         }
 
+        // Pairs with the check in `stop`: either the stopper sees us in the
+        // guest and waits, or we see its request and stay out
+        self.in_guest.store(true, Ordering::SeqCst);
+        if self.stop_requested.load(Ordering::SeqCst) {
+            self.in_guest.store(false, Ordering::Release);
+            return Err(Error::InvalidState);
+        }
+
         self.state = VcpuState::Running;
 
+        // Point the xAPIC window at this vCPU's LAPIC
+        #[cfg(target_arch = "x86_64")]
+        crate::emulators::lapic::load_vcpu(self.vm_id, self.id);
+
         // Save host context
         let mut host_context = crate::arch::common::CpuContext::default();
         unsafe {
@@ -393,20 +443,20 @@ impl VirtualCpu {
         }
 
         // Load guest context and enter guest
-        let exit_info = unsafe {
+        let result = unsafe {
             #[cfg(target_arch = "aarch64")]
             {
-                self.run_arm64()?
+                self.run_arm64()
             }
 
             #[cfg(target_arch = "riscv64")]
             {
-                self.run_riscv64()?
+                self.run_riscv64()
             }
 
             #[cfg(target_arch = "x86_64")]
             {
-                self.run_x86_64()?
+                self.run_x86_64()
             }
 
             #[cfg(not(any(target_arch = "aarch64", target_arch = "riscv64", target_arch = "x86_64")))]
@@ -425,6 +475,8 @@ impl VirtualCpu {
             crate::arch::x86_64::restore_context(&host_context);
         }
 
+        self.in_guest.store(false, Ordering::Release);
+        let exit_info = result?;
         self.state = VcpuState::Exited;
         *self.exit_info.lock() = Some(exit_info.clone());
 
@@ -629,6 +681,11 @@ pub fn create_vcpu(vm_id: VmId, vcpu_id: VcpuId) -> Result<()> {
     // Initialize VCPU
     vcpu.initialize()?;
 
+    // Start where a reset would put it
+    if let Some(entry) = crate::core::vmm::vm::get_entry_point(vm_id) {
+        vcpu.reset(entry)?;
+    }
+
     // Store VCPU in manager
     let vcpu_ptr = NonNull::new(Box::into_raw(Box::new(vcpu)) as *mut VirtualCpu)
         .ok_or(Error::OutOfMemory)?;
@@ -717,7 +774,10 @@ pub fn inject_interrupt(vm_id: VmId, vcpu_id: VcpuId, vector: u32) -> Result<()>
         return Err(Error::InvalidArgument);
     }
 
-    vcpu.inject_interrupt(vector)
+    vcpu.inject_interrupt(vector)?;
+
+    // Wake the vCPU if it is idle in WFI
+    crate::core::sched::vcpu_interrupt_pending(vm_id, vcpu_id)
 }
 
 /// Inject an exception into a VCPU
@@ -778,6 +838,46 @@ pub fn set_vcpu_regs(vm_id: VmId, vcpu_id: VcpuId, regs: &VcpuRegisters) -> Resu
     vcpu.set_registers(regs)
 }
 
+/// Stop a VCPU, waiting for it to leave the guest
+pub fn stop_vcpu(vm_id: VmId, vcpu_id: VcpuId) -> Result<()> {
+    let manager = VcpuManager::get();
+
+    if vcpu_id as usize >= MAX_VCPUS {
+        return Err(Error::InvalidArgument);
+    }
+
+    let vcpu_ptr = manager.vcpus[vcpu_id as usize]
+        .ok_or(Error::NotFound)?;
+
+    let vcpu = unsafe { vcpu_ptr.as_ref() };
+
+    if vcpu.vm_id() != vm_id {
+        return Err(Error::InvalidArgument);
+    }
+
+    vcpu.stop()
+}
+
+/// Reset a VCPU to start executing at `entry`
+pub fn reset_vcpu(vm_id: VmId, vcpu_id: VcpuId, entry: u64) -> Result<()> {
+    let manager = VcpuManager::get();
+
+    if vcpu_id as usize >= MAX_VCPUS {
+        return Err(Error::InvalidArgument);
+    }
+
+    let mut vcpu_ptr = manager.vcpus[vcpu_id as usize]
+        .ok_or(Error::NotFound)?;
+
+    let vcpu = unsafe { vcpu_ptr.as_mut() };
+
+    if vcpu.vm_id() != vm_id {
+        return Err(Error::InvalidArgument);
+    }
+
+    vcpu.reset(entry)
+}
+
 /// Get number of VCPUs
 pub fn get_vcpu_count() -> usize {
     let manager = VcpuManager::get();
@@ -3,18 +3,597 @@
 //! This module handles the lifecycle and management of virtual machines.
 
 use crate::{Result, Error};
-use crate::config::{VmConfig, DeviceConfig, validate_vm_config};
-use crate::core::vmm::{VmId, VmState, VcpuId};
-use crate::core::mm::{VirtAddr, PhysAddr, AddressSpace, PAGE_SIZE, align_up};
-use crate::core::sync::SpinLock;
+use crate::config::{VmConfig, DeviceConfig, DeviceType, validate_vm_config};
+use crate::core::vmm::{VmId, VmState, VcpuId, VcpuRegisters};
+use crate::core::vmm::migration::{self, StateReader, StateWriter};
+use crate::core::mm::{VirtAddr, PhysAddr, AddressSpace, MemoryRegionFlags, PageSize, PAGE_SIZE, align_up};
+use crate::core::mm::gstage::{self, Gpa, Vmid};
+use crate::libs::cpumask::CpuMask;
+use crate::core::sync::{Once, SpinLock};
+use crate::core::sync::spinlock::SpinLockGuard;
+use crate::emulator::Emulator;
 use crate::utils::bitmap::Bitmap;
+use crate::utils::time::NSEC_PER_SEC;
 use alloc::vec::Vec;
 use alloc::boxed::Box;
+use alloc::string::String;
+use core::marker::PhantomData;
 use core::ptr::NonNull;
+use core::sync::atomic::{AtomicI64, Ordering};
 
 /// Maximum number of VMs
 pub const MAX_VMS: usize = 64;
 
+/// Guest physical address of guest RAM, as on the `virt` machines
+#[cfg(target_arch = "aarch64")]
+pub const GUEST_RAM_BASE: Gpa = 0x4000_0000;
+
+/// Guest physical address of guest RAM, as on the `virt` machines
+#[cfg(not(target_arch = "aarch64"))]
+pub const GUEST_RAM_BASE: Gpa = 0x8000_0000;
+
+/// Permissions of emulated device windows
+const DEVICE_REGION_FLAGS: MemoryRegionFlags = MemoryRegionFlags {
+    readable: true,
+    writable: true,
+    executable: false,
+    cached: false,
+    device: true,
+};
+
+/// What backs a guest physical memory region
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GuestRegionKind {
+    /// RAM backed by stage-2 mappings
+    Ram,
+    /// Read-only memory backed by stage-2 mappings
+    Rom,
+    /// Device registers handled by MMIO emulation
+    Mmio,
+}
+
+/// Guest physical memory region
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GuestRegion {
+    /// Start guest physical address
+    pub gpa: Gpa,
+    /// Size in bytes
+    pub size: u64,
+    /// Region kind
+    pub kind: GuestRegionKind,
+    /// Access permissions
+    pub perms: MemoryRegionFlags,
+    /// Largest stage-2 leaf the region may be backed with
+    pub backing: PageSize,
+}
+
+impl GuestRegion {
+    /// One past the last address of the region
+    pub fn end(&self) -> Gpa {
+        self.gpa + self.size
+    }
+
+    /// Check whether `gpa` lies inside the region
+    pub fn contains(&self, gpa: Gpa) -> bool {
+        gpa >= self.gpa && gpa < self.end()
+    }
+
+    /// Check whether the region permits an access
+    pub fn permits(&self, write: bool, execute: bool) -> bool {
+        if execute {
+            self.perms.executable
+        } else if write {
+            self.perms.writable && self.kind != GuestRegionKind::Rom
+        } else {
+            self.perms.readable
+        }
+    }
+}
+
+/// A stage-2 leaf mapping
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stage2Leaf {
+    /// Guest physical address, aligned to `size`
+    pub gpa: Gpa,
+    /// Host physical address, aligned to `size`
+    pub hpa: PhysAddr,
+    /// Leaf size
+    pub size: PageSize,
+}
+
+/// Host memory and page tables behind a VM's stage-2 translation
+pub trait Stage2Backing {
+    /// Allocate a host block of `size`, ideally aligned to it
+    fn alloc(&mut self, size: PageSize) -> Option<PhysAddr>;
+
+    /// Free a block returned by `alloc`
+    fn free(&mut self, hpa: PhysAddr, size: PageSize);
+
+    /// Install a leaf entry
+    fn map(&mut self, leaf: Stage2Leaf, perms: MemoryRegionFlags) -> Result<()>;
+
+    /// Remove a leaf entry installed by `map`
+    fn unmap(&mut self, leaf: Stage2Leaf) -> Result<()>;
+}
+
+/// Stage-2 backing of a VM through its G-stage context
+///
+/// Host blocks come from the frame allocator; unmapping shoots the range
+/// down on every CPU running the VM before the block may be freed.
+pub struct GStageBacking {
+    /// G-stage context of the VM
+    vmid: Vmid,
+}
+
+impl GStageBacking {
+    /// Back the VM translated by G-stage context `vmid`
+    pub fn new(vmid: Vmid) -> Self {
+        Self { vmid }
+    }
+
+    /// G-stage leaf flags granting `perms`
+    fn leaf_flags(perms: MemoryRegionFlags) -> u64 {
+        use crate::core::mm::gstage::gstage_pte::{R, W, X, U, A, D};
+
+        let mut flags = U | A;
+        if perms.readable {
+            flags |= R;
+        }
+        if perms.writable {
+            flags |= W | D;
+        }
+        if perms.executable {
+            flags |= X;
+        }
+        flags
+    }
+}
+
+impl Stage2Backing for GStageBacking {
+    fn alloc(&mut self, size: PageSize) -> Option<PhysAddr> {
+        crate::core::mm::frame::alloc_contiguous_frames(size.size() / PAGE_SIZE)
+    }
+
+    fn free(&mut self, hpa: PhysAddr, size: PageSize) {
+        crate::core::mm::frame::dealloc_contiguous_frames(hpa, size.size() / PAGE_SIZE);
+    }
+
+    fn map(&mut self, leaf: Stage2Leaf, perms: MemoryRegionFlags) -> Result<()> {
+        let context = gstage::get()
+            .and_then(|manager| manager.get_context(self.vmid))
+            .ok_or(Error::NotInitialized)?;
+        context.map(leaf.gpa, leaf.hpa, leaf.size.size(), Self::leaf_flags(perms))
+    }
+
+    fn unmap(&mut self, leaf: Stage2Leaf) -> Result<()> {
+        gstage::get()
+            .ok_or(Error::NotInitialized)?
+            .unmap(self.vmid, leaf.gpa, leaf.size.size())
+    }
+}
+
+/// Back the memory around `gpa` after a stage-2 translation fault
+///
+/// Installs the largest leaf, up to the region's backing size, whose
+/// aligned block lies inside the region, does not cover any leaf in
+/// `backed`, and for which an aligned host block can be allocated and
+/// charged to `account`. Faults near the region's edges or next to
+/// smaller leaves, on a fragmented host or close to the memory limit fall
+/// back to smaller leaves, down to 4K. A fault on memory already in
+/// `backed` returns that leaf without installing anything.
+fn back_fault(region: &GuestRegion, gpa: Gpa, backed: &[Stage2Leaf], account: &mut MemoryAccount,
+              stage2: &mut dyn Stage2Backing) -> Result<Stage2Leaf> {
+    if region.kind == GuestRegionKind::Mmio || !region.contains(gpa) {
+        return Err(Error::InvalidArgument);
+    }
+
+    let overlaps = |base: Gpa, len: u64| {
+        backed.iter().find(|leaf| leaf.gpa < base + len && base < leaf.gpa + leaf.size.size())
+    };
+    if let Some(leaf) = overlaps(gpa, 1) {
+        return Ok(*leaf);
+    }
+
+    let mut error = Error::OutOfMemory;
+    for size in [PageSize::Size1G, PageSize::Size2M, PageSize::Size4K] {
+        let base = size.align_down(gpa);
+        if size > region.backing || base < region.gpa || base + size.size() > region.end() {
+            continue;
+        }
+        if overlaps(base, size.size()).is_some() {
+            continue;
+        }
+        if let Err(e) = account.charge(size.size()) {
+            error = e;
+            continue;
+        }
+
+        let hpa = match stage2.alloc(size) {
+            Some(hpa) if size.is_aligned(hpa) => hpa,
+            Some(hpa) => {
+                stage2.free(hpa, size);
+                account.uncharge(size.size());
+                continue;
+            }
+            None => {
+                account.uncharge(size.size());
+                continue;
+            }
+        };
+
+        let leaf = Stage2Leaf { gpa: base, hpa, size };
+        if let Err(e) = stage2.map(leaf, region.perms) {
+            stage2.free(hpa, size);
+            account.uncharge(size.size());
+            return Err(e);
+        }
+        return Ok(leaf);
+    }
+
+    Err(error)
+}
+
+/// Unmap the backed leaves inside `[gpa, gpa + size)`
+///
+/// Each leaf is unmapped, its host block freed and its size uncharged
+/// from `account`. A huge leaf only partly inside the range cannot be
+/// split, so the call fails with `Error::InvalidArgument` before touching
+/// anything. Returns the bytes released.
+fn unmap_backed(leaves: &mut Vec<Stage2Leaf>, gpa: Gpa, size: u64, account: &mut MemoryAccount,
+                stage2: &mut dyn Stage2Backing) -> Result<u64> {
+    let end = gpa.checked_add(size).ok_or(Error::InvalidArgument)?;
+    let overlaps = |leaf: &Stage2Leaf| leaf.gpa < end && gpa < leaf.gpa + leaf.size.size();
+    if leaves.iter().filter(|leaf| overlaps(leaf))
+        .any(|leaf| leaf.gpa < gpa || leaf.gpa + leaf.size.size() > end)
+    {
+        return Err(Error::InvalidArgument);
+    }
+
+    let mut released = 0;
+    let mut result = Ok(());
+    leaves.retain(|leaf| {
+        if !overlaps(leaf) || result.is_err() {
+            return true;
+        }
+        if let Err(e) = stage2.unmap(*leaf) {
+            result = Err(e);
+            return true;
+        }
+        stage2.free(leaf.hpa, leaf.size);
+        account.uncharge(leaf.size.size());
+        released += leaf.size.size();
+        false
+    });
+
+    result.map(|()| released)
+}
+
+/// Non-overlapping guest physical memory regions, sorted by address
+#[derive(Debug, Default)]
+pub struct GuestMemoryMap {
+    regions: Vec<GuestRegion>,
+}
+
+impl GuestMemoryMap {
+    /// Create an empty memory map
+    pub const fn new() -> Self {
+        Self { regions: Vec::new() }
+    }
+
+    /// Add a region
+    ///
+    /// Fails with `Error::InvalidArgument` if the region is empty, wraps
+    /// the address space or overlaps an existing region.
+    pub fn add(&mut self, region: GuestRegion) -> Result<()> {
+        if region.size == 0 || region.gpa.checked_add(region.size).is_none() {
+            return Err(Error::InvalidArgument);
+        }
+
+        let index = self.regions.partition_point(|r| r.gpa < region.gpa);
+        let overlaps_prev = index > 0 && self.regions[index - 1].end() > region.gpa;
+        let overlaps_next = self.regions.get(index).map_or(false, |r| r.gpa < region.end());
+        if overlaps_prev || overlaps_next {
+            return Err(Error::InvalidArgument);
+        }
+
+        self.regions.insert(index, region);
+        Ok(())
+    }
+
+    /// Remove the region starting at `gpa`
+    pub fn remove(&mut self, gpa: Gpa) -> Result<GuestRegion> {
+        let index = self.regions.binary_search_by_key(&gpa, |r| r.gpa)
+            .map_err(|_| Error::NotFound)?;
+        Ok(self.regions.remove(index))
+    }
+
+    /// Find the region containing `gpa`
+    pub fn find(&self, gpa: Gpa) -> Option<GuestRegion> {
+        let index = self.regions.partition_point(|r| r.gpa <= gpa);
+        let region = self.regions.get(index.checked_sub(1)?)?;
+        region.contains(gpa).then_some(*region)
+    }
+
+    /// All regions in address order
+    pub fn regions(&self) -> &[GuestRegion] {
+        &self.regions
+    }
+}
+
+/// Guest memory backed on behalf of a VM, with an optional cap
+#[derive(Debug, Default)]
+pub struct MemoryAccount {
+    /// Bytes currently backed
+    usage: u64,
+    /// Maximum bytes that may be backed, `None` for no limit
+    limit: Option<u64>,
+}
+
+impl MemoryAccount {
+    /// Create an unlimited account
+    pub const fn new() -> Self {
+        Self { usage: 0, limit: None }
+    }
+
+    /// Bytes currently backed
+    pub fn usage(&self) -> u64 {
+        self.usage
+    }
+
+    /// Current limit
+    pub fn limit(&self) -> Option<u64> {
+        self.limit
+    }
+
+    /// Set the limit
+    ///
+    /// Lowering it below current usage does not reclaim anything; further
+    /// charges fail until usage drops back under it.
+    pub fn set_limit(&mut self, limit: Option<u64>) {
+        self.limit = limit;
+    }
+
+    /// Charge `bytes` of newly backed memory
+    ///
+    /// Fails with `Error::OutOfMemory`, charging nothing, if it would
+    /// exceed the limit.
+    pub fn charge(&mut self, bytes: u64) -> Result<()> {
+        let usage = self.usage.checked_add(bytes).ok_or(Error::OutOfMemory)?;
+        if self.limit.map_or(false, |limit| usage > limit) {
+            return Err(Error::OutOfMemory);
+        }
+        self.usage = usage;
+        Ok(())
+    }
+
+    /// Return `bytes` of memory that is no longer backed
+    pub fn uncharge(&mut self, bytes: u64) {
+        self.usage = self.usage.saturating_sub(bytes);
+    }
+}
+
+/// First guest IRQ handed out to emulated devices; IRQ 0 means "no
+/// interrupt" on most interrupt controllers
+pub const GUEST_IRQ_BASE: u32 = 1;
+
+/// Number of guest IRQs available to emulated devices per VM
+pub const MAX_GUEST_IRQS: usize = 64;
+
+/// Called to drive an input of the guest's interrupt controller
+pub type GuestIrqSetFn = fn(vm_id: VmId, pin: u32, level: bool);
+
+/// Route from an emulated device's interrupt line to the guest's
+/// interrupt controller
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IrqRoute {
+    /// Device name
+    pub device: String,
+    /// Guest IRQ number, as described to the guest
+    pub guest_irq: u32,
+    /// Interrupt controller input the IRQ is wired to
+    pub controller_pin: u32,
+}
+
+/// Guest IRQ allocation and routing for a VM's emulated devices
+///
+/// Each device gets its own guest IRQ, so emulated devices never share a
+/// line. Guest IRQ `n` is wired to controller input `n + pin_base`, e.g.
+/// SPI `n` is GIC INTID `n + 32`.
+#[derive(Debug, Default)]
+pub struct IrqRoutingTable {
+    /// Allocated (device, guest IRQ), sorted by guest IRQ
+    lines: Vec<(String, u32)>,
+    /// Controller input of guest IRQ 0
+    pin_base: u32,
+}
+
+impl IrqRoutingTable {
+    /// Create an empty table wiring guest IRQ `n` to input `n + pin_base`
+    pub const fn new(pin_base: u32) -> Self {
+        Self { lines: Vec::new(), pin_base }
+    }
+
+    /// Allocate the lowest free guest IRQ to `device`
+    ///
+    /// Fails with `Error::ResourceBusy` if the device already has one and
+    /// `Error::ResourceUnavailable` if all are taken.
+    pub fn alloc(&mut self, device: &str) -> Result<u32> {
+        if self.lines.iter().any(|(name, _)| name == device) {
+            return Err(Error::ResourceBusy);
+        }
+
+        // Lines are sorted, so the first gap is the lowest free IRQ
+        let index = self.lines
+            .iter()
+            .enumerate()
+            .position(|(i, &(_, irq))| irq != GUEST_IRQ_BASE + i as u32)
+            .unwrap_or(self.lines.len());
+        if index == MAX_GUEST_IRQS {
+            return Err(Error::ResourceUnavailable);
+        }
+
+        let irq = GUEST_IRQ_BASE + index as u32;
+        self.lines.insert(index, (String::from(device), irq));
+        Ok(irq)
+    }
+
+    /// Release the guest IRQ of `device`
+    pub fn free(&mut self, device: &str) -> Result<u32> {
+        let index = self.lines.iter().position(|(name, _)| name == device).ok_or(Error::NotFound)?;
+        Ok(self.lines.remove(index).1)
+    }
+
+    /// Route of `device`
+    pub fn route(&self, device: &str) -> Option<IrqRoute> {
+        self.lines.iter().find(|(name, _)| name == device).map(|(name, irq)| IrqRoute {
+            device: name.clone(),
+            guest_irq: *irq,
+            controller_pin: irq + self.pin_base,
+        })
+    }
+
+    /// Number of allocated guest IRQs
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// Check whether no guest IRQ is allocated
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    /// Controller input the interrupt line of `device` drives
+    pub fn controller_pin(&self, device: &str) -> Option<u32> {
+        self.route(device).map(|route| route.controller_pin)
+    }
+}
+
+/// Priority of an injected guest interrupt, higher is more urgent
+pub type IrqPriority = u8;
+
+/// Priority of emulated device interrupts injected without a guest
+/// interrupt controller
+pub const IRQ_PRIORITY_DEVICE: IrqPriority = 0x40;
+
+/// Interrupts at or above this priority are never throttled by default
+pub const DEFAULT_IRQ_PRIORITY_FLOOR: IrqPriority = 0x80;
+
+/// Length of an interrupt rate limiting window
+const IRQ_RATE_WINDOW_NS: u64 = crate::utils::time::NSEC_PER_SEC;
+
+/// Priority of an interrupt injected through `inject_interrupt`
+///
+/// The priority class is the upper nibble of the vector, as with the x86
+/// local APIC.
+pub fn vector_priority(vector: u32) -> IrqPriority {
+    vector.min(0xff) as IrqPriority & 0xf0
+}
+
+/// Per-VM limit on the rate of injected interrupts
+///
+/// Interrupts below the priority floor count against a per-second budget;
+/// those at or above the floor always pass. Injected interrupts are
+/// edge-triggered, so those over budget are held rather than lost, repeats
+/// of a held interrupt coalescing, and released in a later window.
+#[derive(Debug)]
+pub struct IrqThrottle {
+    /// Low-priority interrupts allowed per second, `None` for no limit
+    rate_limit: Option<u32>,
+    /// Lowest priority that bypasses the limit
+    priority_floor: IrqPriority,
+    /// Start of the current window
+    window_start: u64,
+    /// Low-priority interrupts admitted in the current window
+    window_count: u32,
+    /// Interrupts held back by the limit
+    throttled: u64,
+    /// Held interrupts in the order they were raised: VCPU and vector
+    pending: Vec<(VcpuId, u32)>,
+}
+
+impl IrqThrottle {
+    /// Create a throttle without a limit
+    pub const fn new() -> Self {
+        Self {
+            rate_limit: None,
+            priority_floor: DEFAULT_IRQ_PRIORITY_FLOOR,
+            window_start: 0,
+            window_count: 0,
+            throttled: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Set the low-priority interrupts allowed per second
+    pub fn set_rate_limit(&mut self, per_sec: Option<u32>) {
+        self.rate_limit = per_sec;
+    }
+
+    /// Set the lowest priority that bypasses the limit
+    pub fn set_priority_floor(&mut self, floor: IrqPriority) {
+        self.priority_floor = floor;
+    }
+
+    /// Decide whether interrupt `vector` of `priority` for `vcpu_id`,
+    /// raised at `now`, is injected right away
+    ///
+    /// An interrupt over budget is held for `release`.
+    pub fn admit(&mut self, vcpu_id: VcpuId, vector: u32, priority: IrqPriority, now: u64) -> bool {
+        let limit = match self.rate_limit {
+            Some(limit) if priority < self.priority_floor => limit,
+            _ => return true,
+        };
+
+        self.roll_window(now);
+        if self.window_count < limit {
+            self.window_count += 1;
+            return true;
+        }
+
+        self.throttled += 1;
+        if !self.pending.contains(&(vcpu_id, vector)) {
+            self.pending.push((vcpu_id, vector));
+        }
+        false
+    }
+
+    /// Take the held interrupts the budget at `now` has room for
+    ///
+    /// They count against the budget like newly raised ones.
+    pub fn release(&mut self, now: u64) -> Vec<(VcpuId, u32)> {
+        if self.pending.is_empty() {
+            return Vec::new();
+        }
+        let Some(limit) = self.rate_limit else {
+            return core::mem::take(&mut self.pending);
+        };
+
+        self.roll_window(now);
+        let room = limit.saturating_sub(self.window_count).min(self.pending.len() as u32);
+        self.window_count += room;
+        self.pending.drain(..room as usize).collect()
+    }
+
+    /// Start a new window if the current one is over at `now`
+    fn roll_window(&mut self, now: u64) {
+        if now.saturating_sub(self.window_start) >= IRQ_RATE_WINDOW_NS {
+            self.window_start = now;
+            self.window_count = 0;
+        }
+    }
+
+    /// Interrupts held back by the limit
+    pub fn throttled(&self) -> u64 {
+        self.throttled
+    }
+}
+
+impl Default for IrqThrottle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// VM structure
 pub struct VirtualMachine {
     /// Unique VM ID
@@ -33,14 +612,40 @@ pub struct VirtualMachine {
     phys_memory_size: u64,
     /// List of VCPUs
     vcpus: SpinLock<[Option<VcpuId>; 16]>, // Max 16 VCPUs per VM
+    /// Physical CPU affinity of each VCPU slot
+    vcpu_affinity: SpinLock<[CpuMask; 16]>,
     /// Number of active VCPUs
     vcpu_count: SpinLock<usize>,
     /// Mapped devices
     devices: SpinLock<Vec<DeviceConfig>>,
+    /// G-stage context translating this VM's guest physical addresses
+    gstage_vmid: Option<Vmid>,
+    /// Guest physical memory layout
+    regions: SpinLock<GuestMemoryMap>,
+    /// Memory backed through stage-2 faults
+    memory_account: SpinLock<MemoryAccount>,
+    /// Leaves installed by `back_stage2_fault`, charged to `memory_account`
+    backed: SpinLock<Vec<Stage2Leaf>>,
+    /// Guest address VCPUs start executing at after reset
+    entry_point: Gpa,
+    /// Emulated devices attached to this VM
+    emulators: SpinLock<Vec<Box<dyn Emulator>>>,
+    /// Guest IRQs of emulated devices
+    irq_routes: SpinLock<IrqRoutingTable>,
+    /// Guest interrupt controller the routes lead to
+    irq_controller: Option<GuestIrqSetFn>,
+    /// Rate limit on injected interrupts
+    irq_throttle: SpinLock<IrqThrottle>,
+    /// Guest wall-clock time minus host time, in seconds; only changed
+    /// with `emulators` locked
+    wallclock_offset: AtomicI64,
 }
 
 /// VM Manager
 struct VmManager {
+    /// Storage of `vm_id_bitmap`, on the heap so it stays put when the
+    /// manager moves
+    _vm_id_words: Box<[u64]>,
     /// Bitmap tracking allocated VM IDs
     vm_id_bitmap: Bitmap,
     /// Array of VM references
@@ -64,7 +669,7 @@ impl VirtualMachine {
         let memory_bitmap_size = (aligned_memory_size / PAGE_SIZE + 63) / 64;
 
         // Allocate VM structure
-        let vm = Self {
+        let mut vm = Self {
             id,
             config,
             state: VmState::Created,
@@ -76,11 +681,41 @@ impl VirtualMachine {
             phys_memory_base: 0, // TODO: Allocate physical memory
             phys_memory_size: aligned_memory_size,
             vcpus: SpinLock::new([None; 16]),
+            vcpu_affinity: SpinLock::new([CpuMask::all(); 16]),
             vcpu_count: SpinLock::new(0),
             devices: SpinLock::new(Vec::new()),
+            gstage_vmid: None,
+            regions: SpinLock::new(GuestMemoryMap::new()),
+            memory_account: SpinLock::new(MemoryAccount::new()),
+            backed: SpinLock::new(Vec::new()),
+            // Kernels are loaded at the start of guest RAM unless moved
+            entry_point: GUEST_RAM_BASE,
+            emulators: SpinLock::new(Vec::new()),
+            irq_routes: SpinLock::new(IrqRoutingTable::new(0)),
+            irq_controller: None,
+            irq_throttle: SpinLock::new(IrqThrottle::new()),
+            wallclock_offset: AtomicI64::new(0),
         };
 
-        // TODO: Initialize guest memory
+        if let Some(set_pin) = guest_irq_controller(id) {
+            vm.set_irq_controller(0, set_pin);
+        }
+
+        // Guest RAM is backed on demand by stage-2 faults
+        vm.add_memory_region(GUEST_RAM_BASE, aligned_memory_size, GuestRegionKind::Ram,
+                             MemoryRegionFlags { executable: true, ..MemoryRegionFlags::default() },
+                             PageSize::Size2M)?;
+
+        // Configured device windows trap to MMIO emulation
+        for device in &vm.config.devices {
+            if let (Some(base), Some(size)) = (device.base_address, device.size) {
+                vm.add_memory_region(base, size, GuestRegionKind::Mmio, DEVICE_REGION_FLAGS, PageSize::Size4K)?;
+            }
+            if let Some(emulator) = device_emulator(device) {
+                vm.attach_emulator(emulator);
+            }
+        }
+
         // TODO: Load kernel image
         // TODO: Setup initial state
 
@@ -118,10 +753,11 @@ impl VirtualMachine {
         let mut count = self.vcpu_count.lock();
 
         // Find a free slot
-        for slot in vcpus.iter_mut() {
+        for (index, slot) in vcpus.iter_mut().enumerate() {
             if slot.is_none() {
                 *slot = Some(vcpu_id);
                 *count += 1;
+                self.vcpu_affinity.lock()[index] = CpuMask::all();
                 return Ok(());
             }
         }
@@ -150,6 +786,47 @@ impl VirtualMachine {
         *self.vcpu_count.lock()
     }
 
+    /// Find the slot holding a VCPU
+    fn vcpu_slot(&self, vcpu_id: VcpuId) -> Option<usize> {
+        self.vcpus.lock().iter().position(|slot| *slot == Some(vcpu_id))
+    }
+
+    /// Restrict a VCPU to the physical CPUs in `mask`
+    ///
+    /// Offline CPUs are dropped from the mask; a mask with no online CPU
+    /// left is rejected with `Error::InvalidArgument`.
+    pub fn set_vcpu_affinity(&self, vcpu_id: VcpuId, mask: CpuMask) -> Result<()> {
+        let slot = self.vcpu_slot(vcpu_id).ok_or(Error::NotFound)?;
+        let mask = crate::core::sched::effective_affinity(mask, crate::arch::cpu::get_online_cpu_mask().bits())?;
+
+        self.vcpu_affinity.lock()[slot] = mask;
+
+        // A VCPU without a host thread yet only records the mask
+        match crate::core::sched::set_vcpu_affinity(self.id, vcpu_id, mask) {
+            Ok(()) | Err(Error::NotFound) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Get the physical CPU affinity of a VCPU
+    pub fn vcpu_affinity(&self, vcpu_id: VcpuId) -> Option<CpuMask> {
+        let slot = self.vcpu_slot(vcpu_id)?;
+        Some(self.vcpu_affinity.lock()[slot])
+    }
+
+    /// Limit the VM to `quota_ns` of CPU time every `period_ns`
+    ///
+    /// The quota covers all VCPUs together, so a 2-VCPU VM with a quota
+    /// of half the period gets at most half of one physical CPU.
+    pub fn set_cpu_quota(&self, period_ns: u64, quota_ns: u64) -> Result<()> {
+        crate::core::sched::set_cpu_quota(self.id, period_ns, quota_ns)
+    }
+
+    /// Remove the VM's CPU time limit
+    pub fn clear_cpu_quota(&self) {
+        crate::core::sched::clear_cpu_quota(self.id);
+    }
+
     /// Map a device into VM's address space
     pub fn map_device(&self, device: &DeviceConfig) -> Result<()> {
         let base_addr = device.base_address.ok_or(Error::InvalidArgument)?;
@@ -170,6 +847,9 @@ impl VirtualMachine {
                 accessed: false,
                 dirty: false,
                 global: false,
+                cow: false,
+                write_protected: false,
+                memory_type: crate::core::mm::MemoryType::Uncached,
             },
         )?;
 
@@ -195,8 +875,9 @@ impl VirtualMachine {
         self.address_space.unmap_page(base_addr)
             .map_err(|_| Error::InvalidState)?;
 
-        // Remove from device list
+        // Remove from device list and release its guest IRQ
         devices.remove(device_index);
+        let _ = self.irq_routes.lock().free(device_name);
 
         Ok(())
     }
@@ -206,6 +887,81 @@ impl VirtualMachine {
         self.devices.lock().clone()
     }
 
+    /// Add a region to the guest physical memory map
+    ///
+    /// `backing` is the largest stage-2 leaf used to back the region on
+    /// faults; `PageSize::Size4K` backs it one page at a time. Fails with
+    /// `Error::InvalidArgument` if it overlaps an existing region.
+    pub fn add_memory_region(&self, gpa: Gpa, size: u64, kind: GuestRegionKind,
+                             perms: MemoryRegionFlags, backing: PageSize) -> Result<()> {
+        self.regions.lock().add(GuestRegion { gpa, size, kind, perms, backing })
+    }
+
+    /// Remove the region starting at `gpa` from the guest physical memory map
+    pub fn remove_region(&self, gpa: Gpa) -> Result<GuestRegion> {
+        self.regions.lock().remove(gpa)
+    }
+
+    /// Find the region containing `gpa`
+    pub fn find_region(&self, gpa: Gpa) -> Option<GuestRegion> {
+        self.regions.lock().find(gpa)
+    }
+
+    /// Bytes of guest memory currently backed by stage-2 mappings
+    pub fn memory_usage(&self) -> u64 {
+        self.memory_account.lock().usage()
+    }
+
+    /// Cap the guest memory backed through stage-2 faults
+    pub fn set_memory_limit(&self, bytes: u64) {
+        self.memory_account.lock().set_limit(Some(bytes));
+    }
+
+    /// Remove the guest memory cap
+    pub fn clear_memory_limit(&self) {
+        self.memory_account.lock().set_limit(None);
+    }
+
+    /// Current guest memory cap
+    pub fn memory_limit(&self) -> Option<u64> {
+        self.memory_account.lock().limit()
+    }
+
+    /// Charge `bytes` of guest memory about to be backed
+    ///
+    /// Fails with `Error::OutOfMemory` if it would exceed the limit.
+    pub fn charge_memory(&self, bytes: u64) -> Result<()> {
+        self.memory_account.lock().charge(bytes)
+    }
+
+    /// Return `bytes` of guest memory that is no longer backed
+    pub fn uncharge_memory(&self, bytes: u64) {
+        self.memory_account.lock().uncharge(bytes)
+    }
+
+    /// Back guest memory after a stage-2 translation fault at `gpa`
+    ///
+    /// Returns the leaf installed, which is charged against the memory
+    /// limit and may be a huge page if the region allows it.
+    pub fn back_stage2_fault(&self, gpa: Gpa, stage2: &mut dyn Stage2Backing) -> Result<Stage2Leaf> {
+        let region = self.find_region(gpa).ok_or(Error::NotFound)?;
+        let mut backed = self.backed.lock();
+        let leaf = back_fault(&region, gpa, &backed, &mut self.memory_account.lock(), stage2)?;
+        if !backed.contains(&leaf) {
+            backed.push(leaf);
+        }
+        Ok(leaf)
+    }
+
+    /// Unmap guest memory backed by `back_stage2_fault`
+    ///
+    /// Frees the host memory of every leaf inside `[gpa, gpa + size)` and
+    /// returns its size to the memory limit. Returns the bytes released.
+    pub fn unmap_memory(&self, gpa: Gpa, size: u64, stage2: &mut dyn Stage2Backing) -> Result<u64> {
+        let mut backed = self.backed.lock();
+        unmap_backed(&mut backed, gpa, size, &mut self.memory_account.lock(), stage2)
+    }
+
     /// Allocate physical memory for guest
     pub fn allocate_guest_memory(&self, size: u64) -> Option<PhysAddr> {
         // TODO: Implement guest physical memory allocation
@@ -218,80 +974,495 @@ impl VirtualMachine {
         false
     }
 
-    /// Translate guest physical to host physical address
-    pub fn translate_guest_phys(&self, guest_phys: PhysAddr) -> Option<PhysAddr> {
-        // Check if within guest physical memory range
-        if guest_phys >= self.phys_memory_base &&
-           guest_phys < self.phys_memory_base + self.phys_memory_size {
-            Some(guest_phys - self.phys_memory_base + self.phys_memory_base)
-        } else {
-            // Check mapped devices
-            let devices = self.devices.lock();
-            for device in devices.iter() {
-                if let Some(base) = device.base_address {
-                    if let Some(size) = device.size {
-                        if guest_phys >= base && guest_phys < base + size {
-                            return Some(guest_phys); // Pass-through
-                        }
-                    }
-                }
-            }
-            None
-        }
+    /// Attach the G-stage context used for guest memory access
+    pub fn set_gstage_vmid(&mut self, vmid: Vmid) {
+        self.gstage_vmid = Some(vmid);
     }
-}
 
-// VM Manager implementation
-static mut VM_MANAGER: Option<VmManager> = None;
-static VM_MANAGER_INIT: core::sync::atomic::AtomicBool = core::sync::atomic::AtomicBool::new(false);
+    /// Get the attached G-stage context
+    pub fn gstage_vmid(&self) -> Option<Vmid> {
+        self.gstage_vmid
+    }
 
-impl VmManager {
-    /// Create a new VM manager
-    const fn new() -> Self {
-        Self {
-            vm_id_bitmap: Bitmap::new(core::ptr::null_mut(), MAX_VMS),
-            vms: [None; MAX_VMS],
-            active_vms: 0,
-        }
+    /// Set the guest address VCPUs start at after reset
+    pub fn set_entry_point(&mut self, entry: Gpa) {
+        self.entry_point = entry;
     }
 
-    /// Initialize the VM manager
-    fn init() -> Result<()> {
-        unsafe {
-            if VM_MANAGER.is_none() {
-                // TODO: Allocate memory for VM ID bitmap
-                let bitmap_data = [0u64; (MAX_VMS + 63) / 64];
-                VM_MANAGER = Some(VmManager {
-                    vm_id_bitmap: Bitmap::new(bitmap_data.as_ptr() as *mut u64, MAX_VMS),
-                    vms: [None; MAX_VMS],
-                    active_vms: 0,
-                });
-                VM_MANAGER_INIT.store(true, core::sync::atomic::Ordering::Release);
-            }
-        }
-        Ok(())
+    /// Get the guest address VCPUs start at after reset
+    pub fn entry_point(&self) -> Gpa {
+        self.entry_point
     }
 
-    /// Get the VM manager instance
-    fn get() -> &'static mut VmManager {
-        unsafe {
-            VM_MANAGER.as_mut().unwrap()
-        }
+    /// Attach an emulated device
+    pub fn attach_emulator(&self, mut emulator: Box<dyn Emulator>) {
+        let mut emulators = self.emulators.lock();
+        emulator.set_wallclock_offset(self.wallclock_offset());
+        emulators.push(emulator);
     }
 
-    /// Allocate a VM ID
-    fn allocate_vm_id(&mut self) -> Result<VmId> {
-        if let Some(index) = self.vm_id_bitmap.find_and_set() {
-            Ok(index as VmId)
-        } else {
-            Err(Error::ResourceUnavailable)
-        }
+    /// Number of attached emulated devices
+    pub fn emulator_count(&self) -> usize {
+        self.emulators.lock().len()
     }
 
-    /// Free a VM ID
-    fn free_vm_id(&mut self, vm_id: VmId) -> Result<()> {
-        if vm_id as usize >= MAX_VMS {
-            return Err(Error::InvalidArgument);
+    /// Set how far the guest's wall clock runs ahead of the host's
+    ///
+    /// Applies to the RTCs already attached and to those attached later;
+    /// a negative offset puts the guest in the past.
+    pub fn set_wallclock_offset(&self, seconds: i64) {
+        let mut emulators = self.emulators.lock();
+        self.wallclock_offset.store(seconds, Ordering::Relaxed);
+        offset_wallclocks(&mut emulators, seconds);
+    }
+
+    /// Get the guest's wall-clock offset from host time, in seconds
+    pub fn wallclock_offset(&self) -> i64 {
+        self.wallclock_offset.load(Ordering::Relaxed)
+    }
+
+    /// Guest wall-clock time, in seconds
+    pub fn wallclock(&self) -> u64 {
+        let host_time = crate::utils::time::timestamp_ns() / NSEC_PER_SEC;
+        host_time.saturating_add_signed(self.wallclock_offset())
+    }
+
+    /// Attach the guest interrupt controller device IRQs are routed to
+    ///
+    /// Guest IRQ `n` drives controller input `n + pin_base`.
+    pub fn set_irq_controller(&mut self, pin_base: u32, set_pin: GuestIrqSetFn) {
+        self.irq_routes.lock().pin_base = pin_base;
+        self.irq_controller = Some(set_pin);
+    }
+
+    /// Allocate a guest IRQ for an emulated device
+    pub fn alloc_guest_irq(&self, device: &str) -> Result<u32> {
+        self.irq_routes.lock().alloc(device)
+    }
+
+    /// Release the guest IRQ of an emulated device
+    pub fn free_guest_irq(&self, device: &str) -> Result<()> {
+        self.irq_routes.lock().free(device).map(|_| ())
+    }
+
+    /// Get the IRQ route of an emulated device
+    pub fn irq_route(&self, device: &str) -> Option<IrqRoute> {
+        self.irq_routes.lock().route(device)
+    }
+
+    /// Limit the low-priority interrupts injected into the VM per second
+    ///
+    /// Interrupts below the priority floor beyond the limit are held until
+    /// a later second; 0 removes the limit.
+    pub fn set_irq_rate_limit(&self, per_sec: u32) {
+        self.irq_throttle.lock().set_rate_limit((per_sec != 0).then_some(per_sec));
+    }
+
+    /// Set the lowest interrupt priority exempt from the rate limit
+    pub fn set_irq_priority_floor(&self, floor: IrqPriority) {
+        self.irq_throttle.lock().set_priority_floor(floor);
+    }
+
+    /// Interrupts held back by the rate limit
+    pub fn irq_throttled(&self) -> u64 {
+        self.irq_throttle.lock().throttled()
+    }
+
+    /// Inject an interrupt of `priority` into a VCPU, subject to the rate limit
+    ///
+    /// A throttled interrupt is held and injected once the limit allows,
+    /// ahead of newer ones, by a later injection or by
+    /// `release_throttled_irqs`.
+    pub fn inject_irq(&self, vcpu_id: VcpuId, vector: u32, priority: IrqPriority) -> Result<()> {
+        let now = crate::utils::time::timestamp_ns();
+        let (held, admitted) = {
+            let mut throttle = self.irq_throttle.lock();
+            let held = throttle.release(now);
+            (held, throttle.admit(vcpu_id, vector, priority, now))
+        };
+
+        self.inject_held_irqs(&held);
+        if !admitted {
+            return Ok(());
+        }
+        super::vcpu::inject_interrupt(self.id, vcpu_id, vector)
+    }
+
+    /// Inject the held interrupts the rate limit now allows
+    pub fn release_throttled_irqs(&self) {
+        let held = self.irq_throttle.lock().release(crate::utils::time::timestamp_ns());
+        self.inject_held_irqs(&held);
+    }
+
+    /// Inject interrupts released by the rate limit
+    fn inject_held_irqs(&self, irqs: &[(VcpuId, u32)]) {
+        for &(vcpu_id, vector) in irqs {
+            if let Err(e) = super::vcpu::inject_interrupt(self.id, vcpu_id, vector) {
+                crate::warn!("VM {}: failed to inject held IRQ {}: {:?}", self.id, vector, e);
+            }
+        }
+    }
+
+    /// Assert or deassert an emulated device's interrupt line
+    ///
+    /// Without an interrupt controller attached, asserting injects the
+    /// controller input number as a vector into VCPU 0, at
+    /// `IRQ_PRIORITY_DEVICE`.
+    pub fn set_device_irq(&self, device: &str, level: bool) -> Result<()> {
+        // Drive the line without the routing table locked
+        let pin = self.irq_routes.lock().controller_pin(device).ok_or(Error::NotFound)?;
+        match self.irq_controller {
+            Some(set_pin) => set_pin(self.id, pin, level),
+            None if level => {
+                if let Err(e) = self.inject_irq(0, pin, IRQ_PRIORITY_DEVICE) {
+                    crate::warn!("VM {}: failed to inject IRQ {}: {:?}", self.id, pin, e);
+                }
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Warm-reset the VM in place
+    ///
+    /// Stops the VCPUs of a running VM, resets every attached emulator,
+    /// puts each VCPU back at the entry point and flushes the VM's stage-2
+    /// TLB entries on every CPU that has run it; a VM without a VMID has
+    /// all guest translations flushed. Guest memory and the memory map are
+    /// kept; the VM ends up `Created`, ready to start.
+    pub fn reset(&mut self) -> Result<()> {
+        match self.state {
+            VmState::Created | VmState::Running | VmState::Paused => {}
+            _ => return Err(Error::InvalidState),
+        }
+
+        let vm_id = self.id;
+        let vcpus = *self.vcpus.lock();
+        if self.state == VmState::Running {
+            for &vcpu_id in vcpus.iter().flatten() {
+                crate::core::vmm::vcpu::stop_vcpu(vm_id, vcpu_id)?;
+            }
+        }
+
+        self.state = VmState::Resetting;
+        let result = reset_guest(&mut self.emulators.lock(), &vcpus, self.entry_point,
+            |vcpu_id, entry| crate::core::vmm::vcpu::reset_vcpu(vm_id, vcpu_id, entry));
+
+        let flushed = match self.gstage_vmid.zip(gstage::get()) {
+            Some((vmid, manager)) => manager.flush_vmid(vmid),
+            None => gstage::flush_all_guests(),
+        };
+
+        self.state = VmState::Created;
+        result.and(flushed)
+    }
+
+    /// Serialize the VM for live migration
+    ///
+    /// Captures the memory map, every VCPU's registers and the state of
+    /// attached emulators; guest RAM streams separately. The VM must not be
+    /// running.
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        if self.state == VmState::Running {
+            return Err(Error::InvalidState);
+        }
+
+        let vm_id = self.id;
+        let vcpus = *self.vcpus.lock();
+        save_guest(&self.regions.lock(), &vcpus, &self.emulators.lock(), self.entry_point,
+            |vcpu_id| crate::core::vmm::vcpu::get_vcpu_regs(vm_id, vcpu_id).ok_or(Error::NotFound))
+    }
+
+    /// Load state produced by `serialize` on the source host
+    ///
+    /// The VM must have been created from the same configuration, with the
+    /// same VCPUs and emulators, and must not be running. It is left
+    /// `Paused`, ready to resume.
+    pub fn deserialize(&mut self, stream: &[u8]) -> Result<()> {
+        if self.state == VmState::Running {
+            return Err(Error::InvalidState);
+        }
+
+        let vm_id = self.id;
+        let vcpus = *self.vcpus.lock();
+        let (regions, entry_point) = restore_guest(stream, &vcpus, &mut self.emulators.lock(),
+            |vcpu_id, regs| crate::core::vmm::vcpu::set_vcpu_regs(vm_id, vcpu_id, &regs))?;
+
+        *self.regions.lock() = regions;
+        self.entry_point = entry_point;
+        self.state = VmState::Paused;
+        Ok(())
+    }
+
+    /// Translate a GPA to a host virtual address through the G-stage mapping
+    fn gpa_to_host(&self, gpa: Gpa) -> Result<VirtAddr> {
+        let vmid = self.gstage_vmid.ok_or(Error::NotInitialized)?;
+        let context = gstage::get()
+            .and_then(|manager| manager.get_context(vmid))
+            .ok_or(Error::NotInitialized)?;
+        let hpa = context.translate(gpa)?;
+        Ok(crate::core::mm::frame::phys_to_virt(hpa))
+    }
+
+    /// Read guest physical memory into `buf`
+    ///
+    /// Fails with `Error::NotFound` if any page in the range is unmapped.
+    pub fn read_gpa(&self, gpa: Gpa, buf: &mut [u8]) -> Result<()> {
+        copy_from_guest(|gpa| self.gpa_to_host(gpa), gpa, buf)
+    }
+
+    /// Write `buf` to guest physical memory
+    ///
+    /// The whole range is translated before anything is written, so an
+    /// unmapped page leaves guest memory untouched.
+    pub fn write_gpa(&self, gpa: Gpa, buf: &[u8]) -> Result<()> {
+        copy_to_guest(|gpa| self.gpa_to_host(gpa), gpa, buf)
+    }
+
+    /// Map `len` bytes of guest memory at `gpa` for direct host access
+    ///
+    /// Fails with `Error::NotFound` if any page in the range is unmapped.
+    pub fn map_buffer(&self, gpa: Gpa, len: usize) -> Result<GuestBufferMap<'_>> {
+        GuestBufferMap::new(|gpa| self.gpa_to_host(gpa), gpa, len)
+    }
+
+    /// Translate guest physical to host physical address
+    pub fn translate_guest_phys(&self, guest_phys: PhysAddr) -> Option<PhysAddr> {
+        // Check if within guest physical memory range
+        if guest_phys >= self.phys_memory_base &&
+           guest_phys < self.phys_memory_base + self.phys_memory_size {
+            Some(guest_phys - self.phys_memory_base + self.phys_memory_base)
+        } else {
+            // Check mapped devices
+            let devices = self.devices.lock();
+            for device in devices.iter() {
+                if let Some(base) = device.base_address {
+                    if let Some(size) = device.size {
+                        if guest_phys >= base && guest_phys < base + size {
+                            return Some(guest_phys); // Pass-through
+                        }
+                    }
+                }
+            }
+            None
+        }
+    }
+}
+
+/// Split `[gpa, gpa + len)` at page boundaries and translate each piece
+///
+/// Returns `(host address, buffer offset, length)` for every piece.
+fn guest_chunks<F>(mut translate: F, gpa: Gpa, len: usize) -> Result<Vec<(VirtAddr, usize, usize)>>
+where
+    F: FnMut(Gpa) -> Result<VirtAddr>,
+{
+    let mut chunks = Vec::new();
+    let mut offset = 0;
+
+    while offset < len {
+        let current = gpa.checked_add(offset as u64).ok_or(Error::InvalidArgument)?;
+        let page_left = (PAGE_SIZE - (current & (PAGE_SIZE - 1))) as usize;
+        let chunk = page_left.min(len - offset);
+
+        chunks.push((translate(current)?, offset, chunk));
+        offset += chunk;
+    }
+
+    Ok(chunks)
+}
+
+/// Copy guest memory into `buf` using `translate` for GPA lookups
+fn copy_from_guest<F>(translate: F, gpa: Gpa, buf: &mut [u8]) -> Result<()>
+where
+    F: FnMut(Gpa) -> Result<VirtAddr>,
+{
+    for (host, offset, len) in guest_chunks(translate, gpa, buf.len())? {
+        unsafe {
+            core::ptr::copy_nonoverlapping(host as *const u8, buf[offset..].as_mut_ptr(), len);
+        }
+    }
+
+    Ok(())
+}
+
+/// Copy `buf` into guest memory using `translate` for GPA lookups
+fn copy_to_guest<F>(translate: F, gpa: Gpa, buf: &[u8]) -> Result<()>
+where
+    F: FnMut(Gpa) -> Result<VirtAddr>,
+{
+    for (host, offset, len) in guest_chunks(translate, gpa, buf.len())? {
+        unsafe {
+            core::ptr::copy_nonoverlapping(buf[offset..].as_ptr(), host as *mut u8, len);
+        }
+    }
+
+    Ok(())
+}
+
+/// Guest buffer mapped into the host address space
+///
+/// A buffer that is contiguous in guest physical memory may be backed by
+/// scattered host frames. The map keeps one host segment per run of
+/// contiguous host memory and presents them as one logical buffer without
+/// copying. It borrows the VM, so it cannot outlive the stage-2 mappings it
+/// was built from.
+pub struct GuestBufferMap<'vm> {
+    /// Host segments as `(host address, length)`, in buffer order
+    segments: Vec<(VirtAddr, usize)>,
+    /// Total length in bytes
+    len: usize,
+    /// Lifetime of the VM's stage-2 mappings
+    _vm: PhantomData<&'vm VirtualMachine>,
+}
+
+impl<'vm> GuestBufferMap<'vm> {
+    /// Map `[gpa, gpa + len)` using `translate` for GPA lookups
+    fn new<F>(translate: F, gpa: Gpa, len: usize) -> Result<Self>
+    where
+        F: FnMut(Gpa) -> Result<VirtAddr>,
+    {
+        let mut segments: Vec<(VirtAddr, usize)> = Vec::new();
+        for (host, _, chunk) in guest_chunks(translate, gpa, len)? {
+            match segments.last_mut() {
+                Some((start, seg_len)) if *start + *seg_len as u64 == host => *seg_len += chunk,
+                _ => segments.push((host, chunk)),
+            }
+        }
+
+        Ok(Self { segments, len, _vm: PhantomData })
+    }
+
+    /// Length of the buffer in bytes
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Check whether the buffer is empty
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Host segments making up the buffer, in order
+    pub fn segments(&self) -> impl Iterator<Item = &[u8]> + '_ {
+        self.segments.iter().map(|&(host, len)| unsafe {
+            core::slice::from_raw_parts(host as *const u8, len)
+        })
+    }
+
+    /// Mutable host segments making up the buffer, in order
+    pub fn segments_mut(&mut self) -> impl Iterator<Item = &mut [u8]> + '_ {
+        self.segments.iter().map(|&(host, len)| unsafe {
+            core::slice::from_raw_parts_mut(host as *mut u8, len)
+        })
+    }
+
+    /// Copy bytes starting at `offset` into `buf`
+    pub fn read(&self, offset: usize, buf: &mut [u8]) -> Result<()> {
+        for (host, buf_offset, len) in self.spans(offset, buf.len())? {
+            unsafe {
+                core::ptr::copy_nonoverlapping(host as *const u8, buf[buf_offset..].as_mut_ptr(), len);
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy `buf` into the buffer starting at `offset`
+    pub fn write(&mut self, offset: usize, buf: &[u8]) -> Result<()> {
+        for (host, buf_offset, len) in self.spans(offset, buf.len())? {
+            unsafe {
+                core::ptr::copy_nonoverlapping(buf[buf_offset..].as_ptr(), host as *mut u8, len);
+            }
+        }
+        Ok(())
+    }
+
+    /// Split `[offset, offset + len)` of the buffer at segment boundaries
+    ///
+    /// Returns `(host address, offset in the range, length)` for every
+    /// piece. Fails with `Error::InvalidArgument` if the range runs past
+    /// the end of the buffer.
+    fn spans(&self, offset: usize, len: usize) -> Result<Vec<(VirtAddr, usize, usize)>> {
+        let end = offset.checked_add(len).ok_or(Error::InvalidArgument)?;
+        if end > self.len {
+            return Err(Error::InvalidArgument);
+        }
+
+        let mut spans = Vec::new();
+        let mut seg_start = 0;
+        for &(host, seg_len) in self.segments.iter() {
+            let seg_end = seg_start + seg_len;
+            let start = offset.max(seg_start);
+            let stop = end.min(seg_end);
+            if start < stop {
+                spans.push((host + (start - seg_start) as u64, start - offset, stop - start));
+            }
+            seg_start = seg_end;
+        }
+        Ok(spans)
+    }
+}
+
+/// The VM manager, once `init` has run
+static VM_MANAGER: Once<SpinLock<VmManager>> = Once::new();
+
+// The VMs are only reached through the manager's lock
+unsafe impl Send for VmManager {}
+
+impl VmManager {
+    /// Create a new VM manager
+    fn new() -> Self {
+        let mut words = alloc::vec![0u64; (MAX_VMS + 63) / 64].into_boxed_slice();
+        let vm_id_bitmap = unsafe { Bitmap::new(words.as_mut_ptr(), MAX_VMS) };
+        Self {
+            _vm_id_words: words,
+            vm_id_bitmap,
+            vms: [None; MAX_VMS],
+            active_vms: 0,
+        }
+    }
+
+    /// Initialize the VM manager
+    fn init() -> Result<()> {
+        if VM_MANAGER.get().is_none() {
+            // A racing initializer already installed an equivalent manager
+            let _ = VM_MANAGER.set(SpinLock::new(VmManager::new()));
+        }
+        Ok(())
+    }
+
+    /// Lock the VM manager
+    ///
+    /// Keep the guard short: VM operations may re-enter the manager, so
+    /// callers copy the VM pointer out and drop the guard first.
+    fn lock() -> Result<SpinLockGuard<'static, VmManager>> {
+        VM_MANAGER.get().map(SpinLock::lock).ok_or(Error::NotInitialized)
+    }
+
+    /// The VM with ID `vm_id`
+    fn slot(&self, vm_id: VmId) -> Result<NonNull<VirtualMachine>> {
+        self.vms.get(vm_id as usize)
+            .ok_or(Error::InvalidArgument)?
+            .ok_or(Error::NotFound)
+    }
+
+    /// Look up the VM with ID `vm_id`
+    fn vm(vm_id: VmId) -> Result<NonNull<VirtualMachine>> {
+        Self::lock()?.slot(vm_id)
+    }
+
+    /// Allocate a VM ID
+    fn allocate_vm_id(&mut self) -> Result<VmId> {
+        if let Some(index) = self.vm_id_bitmap.find_and_set() {
+            Ok(index as VmId)
+        } else {
+            Err(Error::ResourceUnavailable)
+        }
+    }
+
+    /// Free a VM ID
+    fn free_vm_id(&mut self, vm_id: VmId) -> Result<()> {
+        if vm_id as usize >= MAX_VMS {
+            return Err(Error::InvalidArgument);
         }
 
         if self.vm_id_bitmap.clear_bit(vm_id as usize) {
@@ -309,13 +1480,18 @@ pub fn init() -> Result<()> {
 
 /// Create a new virtual machine
 pub fn create_vm(config: &VmConfig) -> Result<VmId> {
-    let manager = VmManager::get();
-    let vm_id = manager.allocate_vm_id()?;
+    let vm_id = VmManager::lock()?.allocate_vm_id()?;
 
-    // Create VM
-    let vm = VirtualMachine::new(vm_id, config.clone())
+    // Platform devices first: the VM wires its IRQs to their interrupt
+    // controller
+    let vm = crate::emulators::attach(vm_id, config)
+        .and_then(|_| VirtualMachine::new(vm_id, config.clone()))
+        .and_then(|vm| attach_virtio_devices(&vm).map(|_| vm))
         .map_err(|e| {
-            manager.free_vm_id(vm_id).ok();
+            crate::emulators::detach(vm_id);
+            if let Ok(mut manager) = VmManager::lock() {
+                manager.free_vm_id(vm_id).ok();
+            }
             e
         })?;
 
@@ -323,8 +1499,10 @@ pub fn create_vm(config: &VmConfig) -> Result<VmId> {
     let vm_ptr = NonNull::new(Box::into_raw(Box::new(vm)) as *mut VirtualMachine)
         .ok_or(Error::OutOfMemory)?;
 
+    let mut manager = VmManager::lock()?;
     manager.vms[vm_id as usize] = Some(vm_ptr);
     manager.active_vms += 1;
+    drop(manager);
 
     crate::info!("Created VM {} with name '{}'", vm_id, config.name);
 
@@ -333,15 +1511,8 @@ pub fn create_vm(config: &VmConfig) -> Result<VmId> {
 
 /// Destroy a virtual machine
 pub fn destroy_vm(vm_id: VmId) -> Result<()> {
-    let manager = VmManager::get();
-
-    if vm_id as usize >= MAX_VMS {
-        return Err(Error::InvalidArgument);
-    }
-
     // Get VM reference
-    let vm_ptr = manager.vms[vm_id as usize]
-        .ok_or(Error::NotFound)?;
+    let vm_ptr = VmManager::vm(vm_id)?;
 
     let vm = unsafe { vm_ptr.as_ref() };
 
@@ -355,16 +1526,29 @@ pub fn destroy_vm(vm_id: VmId) -> Result<()> {
     // Cleanup VCPUs
     // TODO: Destroy all VCPUs
 
+    // Stop remapping passthrough MSIs into the VM
+    crate::core::irq::msi::unmap_vm_msis(vm_id);
+    crate::drivers::virtio::balloon::detach(vm_id);
+    crate::drivers::virtio::block::detach(vm_id);
+    crate::drivers::virtio::net::detach(vm_id);
+    crate::drivers::virtio::input::detach(vm_id);
+    crate::drivers::virtio::gpu::detach(vm_id);
+    crate::drivers::virtio::rng::detach(vm_id);
+    crate::emulators::detach(vm_id);
+
     // Cleanup memory
     // TODO: Deallocate all guest memory
 
-    // Free VM
-    let _ = unsafe { Box::from_raw(vm_ptr.as_ptr()) };
+    // Unpublish the VM and free its ID
+    let mut manager = VmManager::lock()?;
     manager.vms[vm_id as usize] = None;
     manager.active_vms -= 1;
+    let freed = manager.free_vm_id(vm_id);
+    drop(manager);
 
-    // Free VM ID
-    manager.free_vm_id(vm_id)?;
+    // Free VM
+    let _ = unsafe { Box::from_raw(vm_ptr.as_ptr()) };
+    freed?;
 
     crate::info!("Destroyed VM {}", vm_id);
 
@@ -373,14 +1557,7 @@ pub fn destroy_vm(vm_id: VmId) -> Result<()> {
 
 /// Start a virtual machine
 pub fn start_vm(vm_id: VmId) -> Result<()> {
-    let manager = VmManager::get();
-
-    if vm_id as usize >= MAX_VMS {
-        return Err(Error::InvalidArgument);
-    }
-
-    let vm_ptr = manager.vms[vm_id as usize]
-        .ok_or(Error::NotFound)?;
+    let mut vm_ptr = VmManager::vm(vm_id)?;
 
     let vm = unsafe { vm_ptr.as_mut() };
 
@@ -398,14 +1575,7 @@ pub fn start_vm(vm_id: VmId) -> Result<()> {
 
 /// Stop a virtual machine
 pub fn stop_vm(vm_id: VmId) -> Result<()> {
-    let manager = VmManager::get();
-
-    if vm_id as usize >= MAX_VMS {
-        return Err(Error::InvalidArgument);
-    }
-
-    let vm_ptr = manager.vms[vm_id as usize]
-        .ok_or(Error::NotFound)?;
+    let mut vm_ptr = VmManager::vm(vm_id)?;
 
     let vm = unsafe { vm_ptr.as_mut() };
 
@@ -432,23 +1602,13 @@ pub fn resume_vm(vm_id: VmId) -> Result<()> {
 
 /// Reset a virtual machine
 pub fn reset_vm(vm_id: VmId) -> Result<()> {
-    let manager = VmManager::get();
-
-    if vm_id as usize >= MAX_VMS {
-        return Err(Error::InvalidArgument);
-    }
-
-    let vm_ptr = manager.vms[vm_id as usize]
-        .ok_or(Error::NotFound)?;
+    let mut vm_ptr = VmManager::vm(vm_id)?;
 
     let vm = unsafe { vm_ptr.as_mut() };
 
     match vm.state() {
         VmState::Running | VmState::Paused => {
-            vm.set_state(VmState::Resetting);
-            // TODO: Reset all VCPUs
-            // TODO: Reset device state
-            vm.set_state(VmState::Created);
+            vm.reset()?;
             crate::info!("Reset VM {}", vm_id);
             Ok(())
         }
@@ -456,28 +1616,267 @@ pub fn reset_vm(vm_id: VmId) -> Result<()> {
     }
 }
 
-/// Get VM state
-pub fn get_vm_state(vm_id: VmId) -> Option<VmState> {
-    let manager = VmManager::get();
+/// Get the guest address a VM's VCPUs start at
+pub fn get_entry_point(vm_id: VmId) -> Option<Gpa> {
+    let vm_ptr = VmManager::vm(vm_id).ok()?;
+    Some(unsafe { vm_ptr.as_ref() }.entry_point())
+}
+
+/// Set the guest address a VM's VCPUs start at after reset
+pub fn set_entry_point(vm_id: VmId, entry: Gpa) -> Result<()> {
+    let mut vm_ptr = VmManager::vm(vm_id)?;
 
-    if vm_id as usize >= MAX_VMS {
-        return None;
+    unsafe { vm_ptr.as_mut() }.set_entry_point(entry);
+    Ok(())
+}
+
+/// Guest interrupt controller a new VM's device IRQs are wired to, pin for
+/// pin, if the platform emulates one
+fn guest_irq_controller(vm_id: VmId) -> Option<GuestIrqSetFn> {
+    if cfg!(target_arch = "x86_64") && crate::emulators::ioapic::has_ioapic(vm_id) {
+        return Some(crate::emulators::ioapic::set_vm_irq);
     }
+    None
+}
 
-    let vm_ptr = manager.vms[vm_id as usize]?;
-    Some(unsafe { vm_ptr.as_ref().state() })
+/// Register the virtio-mmio devices configured for a VM
+///
+/// Each device sits at its configured MMIO window on an interrupt line
+/// allocated from the VM's routing table. Every type but the balloon
+/// hands the VM the host's global device of that type.
+fn attach_virtio_devices(vm: &VirtualMachine) -> Result<()> {
+    use crate::drivers::virtio::{balloon, block, gpu, input, net, rng};
+
+    let guest_pages = (vm.config.memory_size / PAGE_SIZE).min(u32::MAX as u64) as u32;
+    for device in vm.config.devices.iter() {
+        let name = match device.device_type {
+            DeviceType::VirtioBalloon => balloon::device_name(vm.id),
+            DeviceType::VirtioBlk => block::device_name(vm.id),
+            DeviceType::VirtioNet => net::device_name(vm.id),
+            DeviceType::VirtioInput => input::device_name(vm.id),
+            DeviceType::VirtioGpu => gpu::device_name(vm.id),
+            DeviceType::VirtioRng => rng::device_name(vm.id),
+            _ => continue,
+        };
+        let Some(base) = device.base_address else {
+            crate::warn!("VM {}: {} has no MMIO window", vm.id, device.name);
+            continue;
+        };
+
+        let irq = vm.alloc_guest_irq(&name)?;
+        let result = match device.device_type {
+            DeviceType::VirtioBalloon => {
+                let size = device.size.unwrap_or(crate::emulators::virtio_mmio::VIRTIO_MMIO_SIZE);
+                balloon::attach(vm.id, guest_pages, base, size, irq)
+            }
+            DeviceType::VirtioBlk => block::attach(vm.id, base, irq),
+            DeviceType::VirtioNet => net::attach(vm.id, base, irq),
+            DeviceType::VirtioInput => input::attach(vm.id, base, irq),
+            DeviceType::VirtioGpu => gpu::attach(vm.id, base, irq),
+            _ => rng::attach(vm.id, base, irq),
+        };
+        if let Err(e) = result {
+            vm.free_guest_irq(&name).ok();
+            return Err(e);
+        }
+    }
+    Ok(())
 }
 
-/// Map a device into a VM's address space
-pub fn map_device(vm_id: VmId, config: &DeviceConfig) -> Result<()> {
-    let manager = VmManager::get();
+/// Per-VM emulator for a configured device, if its type has one
+fn device_emulator(device: &DeviceConfig) -> Option<Box<dyn Emulator>> {
+    let base = device.base_address?;
+    match device.device_type {
+        DeviceType::Uart if cfg!(target_arch = "aarch64") => {
+            Some(Box::new(crate::emulators::uart::Pl011Uart::new(base)))
+        }
+        DeviceType::Uart => Some(Box::new(crate::emulators::uart::Uart16550::new(base))),
+        DeviceType::Rtc => Some(Box::new(crate::emulators::rtc::Pl031Rtc::new(base))),
+        DeviceType::Gpio => Some(Box::new(crate::emulators::gpio::Pl061Gpio::new(base))),
+        _ => None,
+    }
+}
 
-    if vm_id as usize >= MAX_VMS {
+/// Offset the wall clocks of attached emulators from host time by `seconds`
+fn offset_wallclocks(emulators: &mut [Box<dyn Emulator>], seconds: i64) {
+    for emulator in emulators.iter_mut() {
+        emulator.set_wallclock_offset(seconds);
+    }
+}
+
+/// Reset attached emulators, then each VCPU to `entry`
+fn reset_guest(
+    emulators: &mut [Box<dyn Emulator>],
+    vcpus: &[Option<VcpuId>],
+    entry: Gpa,
+    mut reset_vcpu: impl FnMut(VcpuId, Gpa) -> Result<()>,
+) -> Result<()> {
+    for emulator in emulators.iter_mut() {
+        emulator.reset().map_err(|err| {
+            crate::error!("Failed to reset emulator {}: {:?}", emulator.name(), err);
+            Error::InvalidState
+        })?;
+    }
+
+    for &vcpu_id in vcpus.iter().flatten() {
+        reset_vcpu(vcpu_id, entry)?;
+    }
+
+    Ok(())
+}
+
+/// Encode a memory region kind for the migration stream
+fn region_kind_to_u8(kind: GuestRegionKind) -> u8 {
+    match kind {
+        GuestRegionKind::Ram => 0,
+        GuestRegionKind::Rom => 1,
+        GuestRegionKind::Mmio => 2,
+    }
+}
+
+/// Decode a memory region kind from the migration stream
+fn region_kind_from_u8(value: u8) -> Result<GuestRegionKind> {
+    match value {
+        0 => Ok(GuestRegionKind::Ram),
+        1 => Ok(GuestRegionKind::Rom),
+        2 => Ok(GuestRegionKind::Mmio),
+        _ => Err(Error::InvalidArgument),
+    }
+}
+
+/// Encode a region's backing page size in the top bits of its flags byte
+///
+/// Streams written before huge-page backing have zero there: 4K.
+fn region_backing_to_u8(backing: PageSize) -> u8 {
+    match backing {
+        PageSize::Size4K => 0,
+        PageSize::Size2M => 1,
+        PageSize::Size1G => 2,
+    }
+}
+
+/// Decode a region's backing page size
+fn region_backing_from_u8(value: u8) -> Result<PageSize> {
+    match value {
+        0 => Ok(PageSize::Size4K),
+        1 => Ok(PageSize::Size2M),
+        2 => Ok(PageSize::Size1G),
+        _ => Err(Error::InvalidArgument),
+    }
+}
+
+/// Write the migration stream of a guest
+fn save_guest(
+    regions: &GuestMemoryMap,
+    vcpus: &[Option<VcpuId>],
+    emulators: &[Box<dyn Emulator>],
+    entry: Gpa,
+    mut vcpu_regs: impl FnMut(VcpuId) -> Result<VcpuRegisters>,
+) -> Result<Vec<u8>> {
+    let mut writer = StateWriter::new();
+    migration::write_header(&mut writer);
+    writer.put_u64(entry);
+
+    writer.put_u32(regions.regions().len() as u32);
+    for region in regions.regions() {
+        let perms = &region.perms;
+        writer.put_u64(region.gpa);
+        writer.put_u64(region.size);
+        writer.put_u8(region_kind_to_u8(region.kind));
+        writer.put_u8(perms.readable as u8 | (perms.writable as u8) << 1 | (perms.executable as u8) << 2
+            | (perms.cached as u8) << 3 | (perms.device as u8) << 4
+            | region_backing_to_u8(region.backing) << 5);
+    }
+
+    writer.put_u32(vcpus.iter().flatten().count() as u32);
+    for &vcpu_id in vcpus.iter().flatten() {
+        writer.put_u32(vcpu_id);
+        migration::write_registers(&mut writer, &vcpu_regs(vcpu_id)?);
+    }
+
+    writer.put_u32(emulators.len() as u32);
+    for emulator in emulators {
+        writer.put_bytes(emulator.name().as_bytes());
+        writer.put_bytes(&emulator.save()?);
+    }
+
+    Ok(writer.finish())
+}
+
+/// Apply the migration stream of a guest
+///
+/// Everything is parsed before emulator state is loaded, and VCPUs and
+/// emulators must match the ones the stream was taken from. Returns the
+/// memory map and entry point to install.
+fn restore_guest(
+    stream: &[u8],
+    vcpus: &[Option<VcpuId>],
+    emulators: &mut [Box<dyn Emulator>],
+    mut set_vcpu_regs: impl FnMut(VcpuId, VcpuRegisters) -> Result<()>,
+) -> Result<(GuestMemoryMap, Gpa)> {
+    let mut reader = StateReader::new(stream);
+    migration::read_header(&mut reader)?;
+    let entry = reader.get_u64()?;
+
+    let mut regions = GuestMemoryMap::new();
+    for _ in 0..reader.get_u32()? {
+        let gpa = reader.get_u64()?;
+        let size = reader.get_u64()?;
+        let kind = region_kind_from_u8(reader.get_u8()?)?;
+        let bits = reader.get_u8()?;
+        let perms = MemoryRegionFlags {
+            readable: bits & 1 != 0,
+            writable: bits & 2 != 0,
+            executable: bits & 4 != 0,
+            cached: bits & 8 != 0,
+            device: bits & 16 != 0,
+        };
+        let backing = region_backing_from_u8(bits >> 5)?;
+        regions.add(GuestRegion { gpa, size, kind, perms, backing })?;
+    }
+
+    let mut contexts = Vec::new();
+    for _ in 0..reader.get_u32()? {
+        let vcpu_id = reader.get_u32()?;
+        if !vcpus.contains(&Some(vcpu_id)) {
+            return Err(Error::NotFound);
+        }
+        contexts.push((vcpu_id, migration::read_registers(&mut reader)?));
+    }
+
+    if reader.get_u32()? as usize != emulators.len() {
+        return Err(Error::InvalidArgument);
+    }
+    let mut states = Vec::new();
+    for emulator in emulators.iter() {
+        if reader.get_bytes()? != emulator.name().as_bytes() {
+            return Err(Error::InvalidArgument);
+        }
+        states.push(reader.get_bytes()?);
+    }
+    if reader.remaining() != 0 {
         return Err(Error::InvalidArgument);
     }
 
-    let vm_ptr = manager.vms[vm_id as usize]
-        .ok_or(Error::NotFound)?;
+    for (emulator, state) in emulators.iter_mut().zip(states) {
+        emulator.restore(state)?;
+    }
+    for (vcpu_id, regs) in contexts {
+        set_vcpu_regs(vcpu_id, regs)?;
+    }
+
+    Ok((regions, entry))
+}
+
+/// Get VM state
+pub fn get_vm_state(vm_id: VmId) -> Option<VmState> {
+    let vm_ptr = VmManager::vm(vm_id).ok()?;
+    Some(unsafe { vm_ptr.as_ref().state() })
+}
+
+/// Map a device into a VM's address space
+pub fn map_device(vm_id: VmId, config: &DeviceConfig) -> Result<()> {
+    let vm_ptr = VmManager::vm(vm_id)?;
 
     let vm = unsafe { vm_ptr.as_ref() };
     vm.map_device(config)
@@ -485,28 +1884,113 @@ pub fn map_device(vm_id: VmId, config: &DeviceConfig) -> Result<()> {
 
 /// Unmap a device from a VM's address space
 pub fn unmap_device(vm_id: VmId, device_name: &str) -> Result<()> {
-    let manager = VmManager::get();
+    let vm_ptr = VmManager::vm(vm_id)?;
 
-    if vm_id as usize >= MAX_VMS {
-        return Err(Error::InvalidArgument);
+    let vm = unsafe { vm_ptr.as_ref() };
+    vm.unmap_device(device_name)
+}
+
+/// Find the memory region containing `gpa` in a VM's memory map
+pub fn find_region(vm_id: VmId, gpa: Gpa) -> Option<GuestRegion> {
+    let vm_ptr = VmManager::vm(vm_id).ok()?;
+    unsafe { vm_ptr.as_ref().find_region(gpa) }
+}
+
+/// Run `f` on a VM
+fn with_vm<T>(vm_id: VmId, f: impl FnOnce(&VirtualMachine) -> Result<T>) -> Result<T> {
+    let vm_ptr = VmManager::vm(vm_id)?;
+
+    f(unsafe { vm_ptr.as_ref() })
+}
+
+/// Map guest memory of a VM and run `f` on the mapping
+///
+/// Used by device emulation to access descriptor buffers in place; the
+/// mapping is only valid for the duration of `f`.
+pub fn with_guest_buffer<T>(vm_id: VmId, gpa: Gpa, len: usize,
+                            f: impl FnOnce(&mut GuestBufferMap) -> Result<T>) -> Result<T> {
+    with_vm(vm_id, |vm| f(&mut vm.map_buffer(gpa, len)?))
+}
+
+/// Read guest physical memory of a VM into `buf`
+pub fn read_guest(vm_id: VmId, gpa: Gpa, buf: &mut [u8]) -> Result<()> {
+    with_vm(vm_id, |vm| vm.read_gpa(gpa, buf))
+}
+
+/// Write `buf` to guest physical memory of a VM
+pub fn write_guest(vm_id: VmId, gpa: Gpa, buf: &[u8]) -> Result<()> {
+    with_vm(vm_id, |vm| vm.write_gpa(gpa, buf))
+}
+
+/// Assert or deassert the interrupt line of an emulated device of a VM
+pub fn set_device_irq(vm_id: VmId, device: &str, level: bool) -> Result<()> {
+    with_vm(vm_id, |vm| vm.set_device_irq(device, level))
+}
+
+/// Inject an interrupt into a VCPU, subject to the VM's rate limit
+///
+/// The interrupt's priority is that of its vector (see `vector_priority`).
+pub fn inject_interrupt(vm_id: VmId, vcpu_id: VcpuId, vector: u32) -> Result<()> {
+    with_vm(vm_id, |vm| vm.inject_irq(vcpu_id, vector, vector_priority(vector)))
+}
+
+/// Inject the held interrupts every VM's rate limit now allows
+pub fn release_throttled_irqs() {
+    // Injection may re-enter the manager: work on a copy of the table
+    let Ok(vms) = VmManager::lock().map(|manager| manager.vms) else {
+        return;
+    };
+    for vm_ptr in vms.iter().flatten() {
+        unsafe { vm_ptr.as_ref() }.release_throttled_irqs();
     }
+}
 
-    let vm_ptr = manager.vms[vm_id as usize]
-        .ok_or(Error::NotFound)?;
+/// Set how far a VM's wall clock runs ahead of the host's, in seconds
+pub fn set_wallclock_offset(vm_id: VmId, seconds: i64) -> Result<()> {
+    with_vm(vm_id, |vm| {
+        vm.set_wallclock_offset(seconds);
+        Ok(())
+    })
+}
 
-    let vm = unsafe { vm_ptr.as_ref() };
-    vm.unmap_device(device_name)
+/// Back guest memory of a VM after a stage-2 translation fault
+pub fn back_stage2_fault(vm_id: VmId, gpa: Gpa, stage2: &mut dyn Stage2Backing) -> Result<Stage2Leaf> {
+    with_vm(vm_id, |vm| vm.back_stage2_fault(gpa, stage2))
+}
+
+/// Back guest memory of a VM after a stage-2 translation fault, through
+/// the VM's G-stage context
+pub fn resolve_stage2_fault(vm_id: VmId, gpa: Gpa) -> Result<Stage2Leaf> {
+    with_vm(vm_id, |vm| {
+        let vmid = vm.gstage_vmid().ok_or(Error::NotInitialized)?;
+        vm.back_stage2_fault(gpa, &mut GStageBacking::new(vmid))
+    })
+}
+
+/// Unmap stage-2 backed guest memory of a VM, returning the bytes released
+pub fn unmap_memory(vm_id: VmId, gpa: Gpa, size: u64, stage2: &mut dyn Stage2Backing) -> Result<u64> {
+    with_vm(vm_id, |vm| vm.unmap_memory(gpa, size, stage2))
+}
+
+/// Give guest memory of a VM back to the host, through the VM's G-stage
+/// context; the range is backed again on the guest's next access
+pub fn release_memory(vm_id: VmId, gpa: Gpa, size: u64) -> Result<u64> {
+    with_vm(vm_id, |vm| {
+        let vmid = vm.gstage_vmid().ok_or(Error::NotInitialized)?;
+        vm.unmap_memory(gpa, size, &mut GStageBacking::new(vmid))
+    })
 }
 
 /// Get number of VMs
 pub fn get_vm_count() -> usize {
-    let manager = VmManager::get();
-    manager.active_vms
+    VmManager::lock().map_or(0, |manager| manager.active_vms)
 }
 
 /// Get number of running VMs
 pub fn get_running_vm_count() -> usize {
-    let manager = VmManager::get();
+    let Ok(manager) = VmManager::lock() else {
+        return 0;
+    };
     let mut count = 0;
 
     for vm_ptr in manager.vms.iter().flatten() {
@@ -517,4 +2001,551 @@ pub fn get_running_vm_count() -> usize {
     }
 
     count
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    /// Two guest pages at GPA 0x1000 and 0x2000, backed by host pages in
+    /// reverse order so page-crossing accesses must be split
+    struct GuestRam {
+        host: Vec<u8>,
+        base: VirtAddr,
+    }
+
+    impl GuestRam {
+        fn new() -> Self {
+            let mut host = vec![0; 2 * PAGE_SIZE as usize];
+            let base = host.as_mut_ptr() as VirtAddr;
+            Self { host, base }
+        }
+
+        fn translate(&self, gpa: Gpa) -> Result<VirtAddr> {
+            let base = self.base;
+            let offset = gpa & (PAGE_SIZE - 1);
+            match gpa & !(PAGE_SIZE - 1) {
+                0x1000 => Ok(base + PAGE_SIZE + offset),
+                0x2000 => Ok(base + offset),
+                _ => Err(Error::NotFound),
+            }
+        }
+    }
+
+    #[test]
+    fn test_gpa_write_read_round_trip() {
+        let ram = GuestRam::new();
+        let pattern: Vec<u8> = (0..64).collect();
+
+        copy_to_guest(|gpa| ram.translate(gpa), 0x1010, &pattern).unwrap();
+
+        let mut readback = [0u8; 64];
+        copy_from_guest(|gpa| ram.translate(gpa), 0x1010, &mut readback).unwrap();
+        assert_eq!(&readback[..], &pattern[..]);
+
+        // Landed in the host page backing GPA 0x1000
+        assert_eq!(&ram.host[PAGE_SIZE as usize + 0x10..][..64], &pattern[..]);
+    }
+
+    #[test]
+    fn test_gpa_access_crosses_page_boundary() {
+        let ram = GuestRam::new();
+        let pattern = [0xa5u8; 32];
+
+        copy_to_guest(|gpa| ram.translate(gpa), 0x2000 - 8, &pattern).unwrap();
+
+        // Last 8 bytes of the first guest page, first 24 of the second
+        assert_eq!(&ram.host[2 * PAGE_SIZE as usize - 8..], &[0xa5; 8]);
+        assert_eq!(&ram.host[..24], &[0xa5; 24]);
+        assert_eq!(ram.host[24], 0);
+
+        let mut readback = [0u8; 32];
+        copy_from_guest(|gpa| ram.translate(gpa), 0x2000 - 8, &mut readback).unwrap();
+        assert_eq!(readback, pattern);
+    }
+
+    #[test]
+    fn test_gpa_access_unmapped() {
+        let ram = GuestRam::new();
+
+        let mut buf = [0u8; 16];
+        assert_eq!(copy_from_guest(|gpa| ram.translate(gpa), 0x4000, &mut buf), Err(Error::NotFound));
+
+        // Partially mapped range is rejected without writing anything
+        assert_eq!(copy_to_guest(|gpa| ram.translate(gpa), 0x3000 - 8, &[0xff; 16]), Err(Error::NotFound));
+        assert!(ram.host.iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_buffer_map_spans_scattered_frames() {
+        let mut ram = GuestRam::new();
+        let mut map = GuestBufferMap::new(|gpa| ram.translate(gpa), 0x2000 - 8, 32).unwrap();
+
+        // The guest pages are backed in reverse, so the buffer is split
+        assert_eq!(map.len(), 32);
+        assert_eq!(map.segments().map(|s| s.len()).collect::<Vec<_>>(), [8, 24]);
+
+        let pattern: Vec<u8> = (0..32).collect();
+        map.write(0, &pattern).unwrap();
+        let mut readback = [0u8; 32];
+        map.read(0, &mut readback).unwrap();
+        assert_eq!(&readback[..], &pattern[..]);
+
+        // A read straddling the split sees the logical order
+        let mut middle = [0u8; 4];
+        map.read(6, &mut middle).unwrap();
+        assert_eq!(middle, [6, 7, 8, 9]);
+        assert_eq!(map.read(30, &mut middle), Err(Error::InvalidArgument));
+
+        // Writes through the segments land in guest memory
+        for segment in map.segments_mut() {
+            segment[0] = 0xff;
+        }
+        let mut guest = [0u8; 32];
+        copy_from_guest(|gpa| ram.translate(gpa), 0x2000 - 8, &mut guest).unwrap();
+        assert_eq!((guest[0], guest[8], guest[9]), (0xff, 0xff, 9));
+        assert_eq!(ram.host[2 * PAGE_SIZE as usize - 8], 0xff);
+        assert_eq!(&ram.host[1..24], &pattern[9..]);
+
+        // Contiguous host memory is a single segment
+        let base = ram.host.as_mut_ptr() as VirtAddr;
+        let map = GuestBufferMap::new(|gpa| Ok(base + gpa - 0x1000), 0x1ff0, 0x20).unwrap();
+        assert_eq!(map.segments().count(), 1);
+    }
+
+    #[test]
+    fn test_device_irqs_are_distinct_and_routed() {
+        // GIC-style wiring: guest IRQ n is INTID n + 32
+        let mut table = IrqRoutingTable::new(32);
+        let uart = table.alloc("uart").unwrap();
+        let blk = table.alloc("virtio-blk").unwrap();
+        assert_ne!(uart, blk);
+        assert_eq!(table.alloc("uart"), Err(Error::ResourceBusy));
+
+        // Asserting a line drives the controller input it is wired to
+        assert_eq!(table.controller_pin("virtio-blk"), Some(blk + 32));
+        assert_eq!(table.controller_pin("rtc"), None);
+
+        // A freed IRQ is reused, never shared
+        assert_eq!(table.free("uart"), Ok(uart));
+        assert_eq!(table.alloc("rtc"), Ok(uart));
+        assert_eq!(table.route("rtc").unwrap().controller_pin, uart + 32);
+
+        for i in table.len()..MAX_GUEST_IRQS {
+            table.alloc(&alloc::format!("dev{}", i)).unwrap();
+        }
+        assert_eq!(table.alloc("uart"), Err(Error::ResourceUnavailable));
+    }
+
+    fn ram(gpa: Gpa, size: u64) -> GuestRegion {
+        GuestRegion { gpa, size, kind: GuestRegionKind::Ram, perms: MemoryRegionFlags::default(),
+            backing: PageSize::Size4K }
+    }
+
+    #[test]
+    fn test_irq_rate_limit_spares_high_priority() {
+        let mut throttle = IrqThrottle::new();
+        throttle.set_rate_limit(Some(2));
+
+        let now = 5 * IRQ_RATE_WINDOW_NS;
+        assert!(throttle.admit(0, 32, IRQ_PRIORITY_DEVICE, now));
+        assert!(throttle.admit(0, 33, IRQ_PRIORITY_DEVICE, now + 1));
+        assert!(!throttle.admit(0, 34, IRQ_PRIORITY_DEVICE, now + 2));
+        assert!(!throttle.admit(1, 35, DEFAULT_IRQ_PRIORITY_FLOOR - 1, now + 3));
+        // A repeat of a held interrupt coalesces with it
+        assert!(!throttle.admit(0, 34, IRQ_PRIORITY_DEVICE, now + 4));
+        assert_eq!(throttle.throttled(), 3);
+
+        // High-priority interrupts pass however many are injected
+        for i in 0..10 {
+            assert!(throttle.admit(0, 0xf0, DEFAULT_IRQ_PRIORITY_FLOOR, now + 5 + i));
+        }
+        assert_eq!(throttle.throttled(), 3);
+        assert!(throttle.release(now + 20).is_empty());
+
+        // Held interrupts go out first in the next window, using its budget
+        assert_eq!(throttle.release(now + IRQ_RATE_WINDOW_NS), [(0, 34), (1, 35)]);
+        assert!(!throttle.admit(0, 36, IRQ_PRIORITY_DEVICE, now + IRQ_RATE_WINDOW_NS + 1));
+        assert_eq!(throttle.release(now + 2 * IRQ_RATE_WINDOW_NS), [(0, 36)]);
+        assert!(throttle.admit(0, 37, IRQ_PRIORITY_DEVICE, now + 2 * IRQ_RATE_WINDOW_NS + 1));
+        assert_eq!(vector_priority(0x31), 0x30);
+    }
+
+    #[test]
+    fn test_memory_map_rejects_overlap() {
+        let mut map = GuestMemoryMap::new();
+        map.add(ram(0x4000_0000, 0x1000_0000)).unwrap();
+        map.add(ram(0x0900_0000, 0x1000)).unwrap();
+
+        // Overlapping start, end, containment and exact duplicate
+        assert_eq!(map.add(ram(0x3fff_f000, 0x2000)), Err(Error::InvalidArgument));
+        assert_eq!(map.add(ram(0x4fff_f000, 0x2000)), Err(Error::InvalidArgument));
+        assert_eq!(map.add(ram(0x4100_0000, 0x1000)), Err(Error::InvalidArgument));
+        assert_eq!(map.add(ram(0x0800_0000, 0x4000_0000)), Err(Error::InvalidArgument));
+        assert_eq!(map.add(ram(0x0900_0000, 0x1000)), Err(Error::InvalidArgument));
+        assert_eq!(map.add(ram(0x1000, 0)), Err(Error::InvalidArgument));
+        assert_eq!(map.add(ram(u64::MAX - 0xfff, 0x2000)), Err(Error::InvalidArgument));
+
+        // Adjacent regions are fine
+        map.add(ram(0x3fff_f000, 0x1000)).unwrap();
+        map.add(ram(0x5000_0000, 0x1000)).unwrap();
+        assert_eq!(map.regions().len(), 4);
+
+        assert_eq!(map.remove(0x4000_1000), Err(Error::NotFound));
+        map.remove(0x4000_0000).unwrap();
+        map.add(ram(0x4100_0000, 0x1000)).unwrap();
+    }
+
+    #[test]
+    fn test_memory_map_lookup_across_boundaries() {
+        let mut map = GuestMemoryMap::new();
+        let uart = GuestRegion {
+            gpa: 0x0900_0000,
+            size: 0x1000,
+            kind: GuestRegionKind::Mmio,
+            perms: MemoryRegionFlags { cached: false, device: true, ..MemoryRegionFlags::default() },
+            backing: PageSize::Size4K,
+        };
+        map.add(ram(0x4000_0000, 0x1000_0000)).unwrap();
+        map.add(uart).unwrap();
+        map.add(ram(0x0900_1000, 0x1000)).unwrap();
+
+        assert_eq!(map.find(0x08ff_ffff), None);
+        assert_eq!(map.find(0x0900_0000), Some(uart));
+        assert_eq!(map.find(0x0900_0fff), Some(uart));
+        assert_eq!(map.find(0x0900_1000).unwrap().kind, GuestRegionKind::Ram);
+        assert_eq!(map.find(0x0900_2000), None);
+        assert_eq!(map.find(0x3fff_ffff), None);
+        assert_eq!(map.find(0x4000_0000).unwrap().gpa, 0x4000_0000);
+        assert_eq!(map.find(0x4fff_ffff).unwrap().gpa, 0x4000_0000);
+        assert_eq!(map.find(0x5000_0000), None);
+    }
+
+    #[test]
+    fn test_region_permissions() {
+        let rom = GuestRegion { kind: GuestRegionKind::Rom, ..ram(0, 0x1000) };
+        assert!(rom.permits(false, false));
+        assert!(!rom.permits(true, false));
+        assert!(ram(0, 0x1000).permits(true, false));
+        assert!(!ram(0, 0x1000).permits(false, true));
+    }
+
+    #[test]
+    fn test_memory_charge_past_limit_fails() {
+        let mut account = MemoryAccount::new();
+        account.set_limit(Some(4 * PAGE_SIZE));
+
+        for _ in 0..4 {
+            account.charge(PAGE_SIZE).unwrap();
+        }
+        assert_eq!(account.charge(PAGE_SIZE), Err(Error::OutOfMemory));
+        assert_eq!(account.usage(), 4 * PAGE_SIZE);
+
+        // Unmapping a page makes room for another
+        account.uncharge(PAGE_SIZE);
+        account.charge(PAGE_SIZE).unwrap();
+
+        // Lowering the limit blocks new charges without touching usage
+        account.set_limit(Some(2 * PAGE_SIZE));
+        assert_eq!(account.charge(PAGE_SIZE), Err(Error::OutOfMemory));
+        assert_eq!(account.usage(), 4 * PAGE_SIZE);
+
+        account.set_limit(None);
+        account.charge(PAGE_SIZE).unwrap();
+    }
+
+    /// Stage-2 backing handing out host blocks from a bump pointer and
+    /// recording the leaves installed
+    struct RecordingStage2 {
+        next: PhysAddr,
+        leaves: Vec<Stage2Leaf>,
+    }
+
+    impl Stage2Backing for RecordingStage2 {
+        fn alloc(&mut self, size: PageSize) -> Option<PhysAddr> {
+            let hpa = size.align_up(self.next);
+            self.next = hpa + size.size();
+            Some(hpa)
+        }
+
+        fn free(&mut self, _hpa: PhysAddr, _size: PageSize) {}
+
+        fn map(&mut self, leaf: Stage2Leaf, _perms: MemoryRegionFlags) -> Result<()> {
+            self.leaves.push(leaf);
+            Ok(())
+        }
+
+        fn unmap(&mut self, leaf: Stage2Leaf) -> Result<()> {
+            let index = self.leaves.iter().position(|l| *l == leaf).ok_or(Error::NotFound)?;
+            self.leaves.remove(index);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_huge_backed_fault_installs_one_2m_leaf() {
+        // 4M + 4K of RAM starting 2M-aligned
+        let region = GuestRegion { backing: PageSize::Size2M, ..ram(0x8000_0000, 0x40_1000) };
+        let mut account = MemoryAccount::new();
+        let mut stage2 = RecordingStage2 { next: 0x1_0000_0000, leaves: Vec::new() };
+        let mut backed = Vec::new();
+
+        let leaf = back_fault(&region, 0x8002_3456, &backed, &mut account, &mut stage2).unwrap();
+        assert_eq!(leaf, Stage2Leaf { gpa: 0x8000_0000, hpa: 0x1_0000_0000, size: PageSize::Size2M });
+        assert_eq!(stage2.leaves, [leaf]);
+        assert_eq!(account.usage(), PageSize::Size2M.size());
+        backed.push(leaf);
+
+        // A second fault in the same block reuses the leaf
+        assert_eq!(back_fault(&region, 0x801f_f000, &backed, &mut account, &mut stage2), Ok(leaf));
+        assert_eq!(stage2.leaves.len(), 1);
+
+        // The 4K tail past the last 2M block falls back to a small page
+        let leaf = back_fault(&region, 0x8040_0010, &backed, &mut account, &mut stage2).unwrap();
+        assert_eq!((leaf.gpa, leaf.size), (0x8040_0000, PageSize::Size4K));
+        backed.push(leaf);
+
+        // So does a fault that would take the VM over its limit with 2M
+        account.set_limit(Some(account.usage() + PAGE_SIZE));
+        let leaf = back_fault(&region, 0x8020_0000, &backed, &mut account, &mut stage2).unwrap();
+        assert_eq!(leaf.size, PageSize::Size4K);
+        assert_eq!(stage2.leaves.len(), 3);
+        backed.push(leaf);
+
+        // Once the limit allows it, the rest of that block still gets 4K
+        // leaves: a 2M block would cover the 4K leaf already installed
+        account.set_limit(None);
+        let leaf = back_fault(&region, 0x8020_1000, &backed, &mut account, &mut stage2).unwrap();
+        assert_eq!((leaf.gpa, leaf.size), (0x8020_1000, PageSize::Size4K));
+
+        // 4K-backed regions never get huge leaves
+        let mut account = MemoryAccount::new();
+        let leaf = back_fault(&ram(0x8000_0000, 0x40_0000), 0x8000_0000, &[], &mut account, &mut stage2).unwrap();
+        assert_eq!(leaf.size, PageSize::Size4K);
+    }
+
+    #[test]
+    fn test_unmap_uncharges_backed_leaf_size() {
+        let region = GuestRegion { backing: PageSize::Size2M, ..ram(0x8000_0000, 0x40_1000) };
+        let mut account = MemoryAccount::new();
+        let mut stage2 = RecordingStage2 { next: 0x1_0000_0000, leaves: Vec::new() };
+        let mut backed = Vec::new();
+        for gpa in [0x8000_0000, 0x8020_0000, 0x8040_0000] {
+            let leaf = back_fault(&region, gpa, &backed, &mut account, &mut stage2).unwrap();
+            backed.push(leaf);
+        }
+        assert_eq!(account.usage(), 2 * PageSize::Size2M.size() + PAGE_SIZE);
+
+        // Half of a 2M leaf cannot be unmapped
+        assert_eq!(unmap_backed(&mut backed, 0x8000_0000, 0x10_0000, &mut account, &mut stage2),
+                   Err(Error::InvalidArgument));
+        assert_eq!(backed.len(), 3);
+
+        // The second 2M leaf and the 4K tail
+        let released = unmap_backed(&mut backed, 0x8020_0000, 0x20_1000, &mut account, &mut stage2).unwrap();
+        assert_eq!(released, PageSize::Size2M.size() + PAGE_SIZE);
+        assert_eq!(account.usage(), PageSize::Size2M.size());
+        assert_eq!(stage2.leaves, backed);
+
+        // Unmapping an unbacked range releases nothing
+        assert_eq!(unmap_backed(&mut backed, 0x9000_0000, PAGE_SIZE, &mut account, &mut stage2), Ok(0));
+    }
+
+    #[test]
+    fn test_memory_usage_tracks_mapped_pages() {
+        let mut account = MemoryAccount::new();
+        assert_eq!(account.usage(), 0);
+
+        account.charge(PAGE_SIZE).unwrap();
+        account.charge(2 * 1024 * 1024).unwrap();
+        assert_eq!(account.usage(), PAGE_SIZE + 2 * 1024 * 1024);
+
+        account.uncharge(2 * 1024 * 1024);
+        assert_eq!(account.usage(), PAGE_SIZE);
+        account.uncharge(2 * PAGE_SIZE);
+        assert_eq!(account.usage(), 0);
+    }
+
+    /// Emulator counting resets
+    struct CountingEmulator {
+        resets: alloc::sync::Arc<core::sync::atomic::AtomicUsize>,
+    }
+
+    impl Emulator for CountingEmulator {
+        fn name(&self) -> &str {
+            "counting"
+        }
+
+        fn read(&self, _offset: u64, _size: u32) -> core::result::Result<u64, crate::emulator::Error> {
+            Ok(0)
+        }
+
+        fn write(&mut self, _offset: u64, _value: u64, _size: u32) -> core::result::Result<(), crate::emulator::Error> {
+            Ok(())
+        }
+
+        fn reset(&mut self) -> core::result::Result<(), crate::emulator::Error> {
+            self.resets.fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_reset_restores_entry_and_resets_emulators() {
+        use crate::core::vmm::vcpu::reset_registers;
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        let resets = alloc::sync::Arc::new(AtomicUsize::new(0));
+        let mut emulators: Vec<Box<dyn Emulator>> = vec![
+            Box::new(CountingEmulator { resets: resets.clone() }),
+            Box::new(CountingEmulator { resets: resets.clone() }),
+        ];
+
+        // Two VCPUs that have run for a while
+        let mut regs = vec![reset_registers(0x8000_0000), reset_registers(0x8000_0000)];
+        for r in regs.iter_mut() {
+            r.pc = 0x8020_1234;
+            r.gpr[10] = 0xdead;
+        }
+        let vcpus = [Some(0), None, Some(1)];
+
+        reset_guest(&mut emulators, &vcpus, 0x8000_0000, |vcpu_id, entry| {
+            regs[vcpu_id as usize] = reset_registers(entry);
+            Ok(())
+        }).unwrap();
+
+        assert_eq!(resets.load(Ordering::Relaxed), 2);
+        for r in &regs {
+            assert_eq!(r.pc, 0x8000_0000);
+            assert_eq!(r.gpr[10], 0);
+        }
+    }
+
+    /// Emulator that reports the wall-clock offset it was given
+    struct ClockEmulator {
+        offset: alloc::sync::Arc<AtomicI64>,
+    }
+
+    impl Emulator for ClockEmulator {
+        fn name(&self) -> &str {
+            "clock"
+        }
+
+        fn read(&self, _offset: u64, _size: u32) -> core::result::Result<u64, crate::emulator::Error> {
+            Ok(self.offset.load(Ordering::Relaxed) as u64)
+        }
+
+        fn write(&mut self, _offset: u64, _value: u64, _size: u32) -> core::result::Result<(), crate::emulator::Error> {
+            Ok(())
+        }
+
+        fn reset(&mut self) -> core::result::Result<(), crate::emulator::Error> {
+            Ok(())
+        }
+
+        fn set_wallclock_offset(&mut self, seconds: i64) {
+            self.offset.store(seconds, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_wallclock_offset_reaches_attached_clocks() {
+        let clocks = [alloc::sync::Arc::new(AtomicI64::new(0)), alloc::sync::Arc::new(AtomicI64::new(0))];
+        let mut emulators: Vec<Box<dyn Emulator>> = vec![
+            Box::new(ClockEmulator { offset: clocks[0].clone() }),
+            Box::new(CountingEmulator { resets: alloc::sync::Arc::default() }),
+            Box::new(ClockEmulator { offset: clocks[1].clone() }),
+        ];
+
+        // A day in the past, then an hour ahead
+        offset_wallclocks(&mut emulators, -86_400);
+        assert!(clocks.iter().all(|clock| clock.load(Ordering::Relaxed) == -86_400));
+        offset_wallclocks(&mut emulators, 3_600);
+        assert_eq!(emulators[0].read(0, 64), Ok(3_600));
+        assert_eq!(emulators[2].read(0, 64), Ok(3_600));
+    }
+
+    /// Emulator with one byte-wide register per offset
+    struct RegisterEmulator {
+        regs: [u8; 4],
+    }
+
+    impl Emulator for RegisterEmulator {
+        fn name(&self) -> &str {
+            "regs"
+        }
+
+        fn read(&self, offset: u64, _size: u32) -> core::result::Result<u64, crate::emulator::Error> {
+            Ok(self.regs[offset as usize] as u64)
+        }
+
+        fn write(&mut self, offset: u64, value: u64, _size: u32) -> core::result::Result<(), crate::emulator::Error> {
+            self.regs[offset as usize] = value as u8;
+            Ok(())
+        }
+
+        fn reset(&mut self) -> core::result::Result<(), crate::emulator::Error> {
+            self.regs = [0; 4];
+            Ok(())
+        }
+
+        fn save(&self) -> core::result::Result<Vec<u8>, crate::emulator::Error> {
+            Ok(self.regs.to_vec())
+        }
+
+        fn restore(&mut self, state: &[u8]) -> core::result::Result<(), crate::emulator::Error> {
+            self.regs = state.try_into().map_err(|_| crate::emulator::Error::InvalidConfiguration)?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_migration_stream_round_trip() {
+        use crate::core::vmm::vcpu::reset_registers;
+
+        let mut map = GuestMemoryMap::new();
+        map.add(GuestRegion { gpa: 0x8000_0000, size: 0x10_0000, kind: GuestRegionKind::Ram,
+            perms: MemoryRegionFlags::default(), backing: PageSize::Size2M }).unwrap();
+        map.add(GuestRegion { gpa: 0x1000_0000, size: 0x1000, kind: GuestRegionKind::Mmio,
+            perms: MemoryRegionFlags { executable: false, device: true, ..Default::default() },
+            backing: PageSize::Size4K }).unwrap();
+
+        let mut source: Vec<Box<dyn Emulator>> = vec![Box::new(RegisterEmulator { regs: [0; 4] })];
+        source[0].write(2, 0x5a, 8).unwrap();
+
+        let mut regs = vec![reset_registers(0x8000_0000), reset_registers(0x8000_0000)];
+        regs[1].pc = 0x8020_1234;
+        regs[1].sp = 0x8010_0000;
+        regs[1].gpr[10] = 0xdead_beef;
+        let vcpus = [Some(0), None, Some(1)];
+
+        let stream = save_guest(&map, &vcpus, &source, 0x8000_0000,
+            |vcpu_id| Ok(regs[vcpu_id as usize].clone())).unwrap();
+
+        // Destination: same devices in power-on state
+        let mut dest: Vec<Box<dyn Emulator>> = vec![Box::new(RegisterEmulator { regs: [0; 4] })];
+        let mut restored = vec![reset_registers(0), reset_registers(0)];
+        let (dest_map, entry) = restore_guest(&stream, &vcpus, &mut dest, |vcpu_id, r| {
+            restored[vcpu_id as usize] = r;
+            Ok(())
+        }).unwrap();
+
+        assert_eq!(entry, 0x8000_0000);
+        assert_eq!(dest_map.regions(), map.regions());
+        assert_eq!(dest[0].read(2, 8), Ok(0x5a));
+        assert_eq!(restored[1].pc, 0x8020_1234);
+        assert_eq!(restored[1].sp, 0x8010_0000);
+        assert_eq!(restored[1].gpr, regs[1].gpr);
+        assert_eq!(restored[0].pc, 0x8000_0000);
+
+        // A destination with a different device set is refused untouched
+        let mut other: Vec<Box<dyn Emulator>> = vec![Box::new(CountingEmulator {
+            resets: Default::default(),
+        })];
+        assert_eq!(restore_guest(&stream, &vcpus, &mut other, |_, _| Ok(())).err(),
+            Some(Error::InvalidArgument));
+        assert_eq!(restore_guest(&stream[..stream.len() - 1], &vcpus, &mut dest, |_, _| Ok(())).err(),
+            Some(Error::InvalidArgument));
+    }
+}
@@ -1,10 +1,229 @@
 //! Console device driver
+//!
+//! Console output is broadcast to every registered `ConsoleSink`, so boot
+//! output can go to the UART and a capture buffer at the same time.
 
 use crate::{Result, Error};
 use crate::drivers::{DeviceType, DeviceOps, DeviceInfo, DeviceStatus};
+use crate::core::sync::SpinLock;
+use crate::utils::console::{Console, UartConsole};
+use alloc::vec::Vec;
+
+/// Destination for console output
+///
+/// Sinks are written with the console lock held and must not print.
+pub trait ConsoleSink: Send + Sync {
+    /// Write a buffer of characters
+    fn write(&self, buf: &[u8]);
+
+    /// Flush any buffered output
+    fn flush(&self) {}
+}
+
+impl ConsoleSink for UartConsole {
+    fn write(&self, buf: &[u8]) {
+        Console::write(self, buf);
+    }
+
+    fn flush(&self) {
+        Console::flush(self);
+    }
+}
+
+/// Console sink identifier
+pub type ConsoleId = u32;
+
+/// Broadcasts console output to a set of sinks
+pub struct ConsoleMux {
+    /// Registered sinks in registration order
+    sinks: Vec<(ConsoleId, &'static dyn ConsoleSink)>,
+    /// Next identifier to hand out
+    next_id: ConsoleId,
+}
+
+impl ConsoleMux {
+    /// Create a multiplexer with no sinks
+    pub const fn new() -> Self {
+        Self {
+            sinks: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Add a sink
+    pub fn register(&mut self, sink: &'static dyn ConsoleSink) -> ConsoleId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.sinks.push((id, sink));
+        id
+    }
+
+    /// Remove a sink
+    pub fn unregister(&mut self, id: ConsoleId) -> Result<()> {
+        let index = self.sinks.iter().position(|(sink_id, _)| *sink_id == id)
+            .ok_or(Error::NotFound)?;
+        self.sinks.remove(index);
+        Ok(())
+    }
+
+    /// Write a buffer to every sink
+    pub fn write(&self, buf: &[u8]) {
+        for (_, sink) in &self.sinks {
+            sink.write(buf);
+        }
+    }
+
+    /// Flush every sink
+    pub fn flush(&self) {
+        for (_, sink) in &self.sinks {
+            sink.flush();
+        }
+    }
+
+    /// Number of registered sinks
+    pub fn len(&self) -> usize {
+        self.sinks.len()
+    }
+
+    /// Check if no sinks are registered
+    pub fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+}
+
+/// Fixed-size sink keeping the most recent output
+pub struct RingBufferSink<const N: usize> {
+    /// Buffer, write position and number of valid bytes
+    inner: SpinLock<([u8; N], usize, usize)>,
+}
+
+impl<const N: usize> RingBufferSink<N> {
+    /// Create an empty ring buffer
+    pub const fn new() -> Self {
+        Self {
+            inner: SpinLock::new(([0; N], 0, 0)),
+        }
+    }
+
+    /// Captured output, oldest byte first
+    pub fn contents(&self) -> Vec<u8> {
+        let inner = self.inner.lock();
+        let (buf, head, len) = (&inner.0, inner.1, inner.2);
+        let start = (head + N - len) % N;
+        (0..len).map(|i| buf[(start + i) % N]).collect()
+    }
+
+    /// Discard captured output
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock();
+        inner.1 = 0;
+        inner.2 = 0;
+    }
+}
+
+impl<const N: usize> ConsoleSink for RingBufferSink<N> {
+    fn write(&self, data: &[u8]) {
+        let mut inner = self.inner.lock();
+        for &byte in data {
+            let head = inner.1;
+            inner.0[head] = byte;
+            inner.1 = (head + 1) % N;
+            inner.2 = (inner.2 + 1).min(N);
+        }
+    }
+}
+
+/// Registered console sinks
+static CONSOLES: SpinLock<ConsoleMux> = SpinLock::new(ConsoleMux::new());
+
+/// Register a console sink to receive all console output
+pub fn register_console(sink: &'static dyn ConsoleSink) -> ConsoleId {
+    CONSOLES.lock().register(sink)
+}
+
+/// Stop sending console output to a sink
+pub fn unregister_console(id: ConsoleId) -> Result<()> {
+    CONSOLES.lock().unregister(id)
+}
+
+/// Write to all registered sinks
+///
+/// Returns false, writing nothing, if no sink is registered.
+pub fn write(buf: &[u8]) -> bool {
+    let consoles = CONSOLES.lock();
+    if consoles.is_empty() {
+        return false;
+    }
+    consoles.write(buf);
+    true
+}
+
+/// Write to all registered sinks without waiting for the console lock
+///
+/// Returns false, writing nothing, if no sink is registered or the lock
+/// is held, e.g. by a CPU that panicked while printing.
+pub fn try_write(buf: &[u8]) -> bool {
+    match CONSOLES.try_lock() {
+        Some(consoles) if !consoles.is_empty() => {
+            consoles.write(buf);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Flush all registered sinks
+pub fn flush() {
+    CONSOLES.lock().flush();
+}
 
 /// Initialize console driver
 pub fn init() -> Result<()> {
     crate::info!("Initializing console driver");
+
+    // Keep boot output on the early UART alongside later sinks
+    if CONSOLES.lock().is_empty() {
+        register_console(crate::utils::console::early_console());
+    }
+
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_reaches_all_sinks() {
+        static FIRST: RingBufferSink<64> = RingBufferSink::new();
+        static SECOND: RingBufferSink<64> = RingBufferSink::new();
+
+        let mut mux = ConsoleMux::new();
+        let first = mux.register(&FIRST);
+        mux.register(&SECOND);
+        assert_eq!(mux.len(), 2);
+
+        mux.write(b"boot: cpu0 online\n");
+        assert_eq!(FIRST.contents(), b"boot: cpu0 online\n");
+        assert_eq!(SECOND.contents(), b"boot: cpu0 online\n");
+
+        mux.unregister(first).unwrap();
+        assert_eq!(mux.unregister(first), Err(Error::NotFound));
+        mux.write(b"more\n");
+        assert_eq!(FIRST.contents(), b"boot: cpu0 online\n");
+        assert_eq!(SECOND.contents(), b"boot: cpu0 online\nmore\n");
+    }
+
+    #[test]
+    fn test_ring_buffer_keeps_latest_output() {
+        let ring: RingBufferSink<8> = RingBufferSink::new();
+        ring.write(b"abc");
+        assert_eq!(ring.contents(), b"abc");
+
+        ring.write(b"defghij");
+        assert_eq!(ring.contents(), b"cdefghij");
+
+        ring.clear();
+        assert!(ring.contents().is_empty());
+    }
+}
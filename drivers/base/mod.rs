@@ -424,6 +424,9 @@ pub fn init() -> Result<()> {
     // Register built-in drivers
     register_builtin_drivers()?;
 
+    // Attach the early UART to the console multiplexer
+    console::init()?;
+
     crate::info!("Base driver framework initialized");
     Ok(())
 }
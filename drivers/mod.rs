@@ -6,6 +6,11 @@
 use crate::{Result, Error};
 use crate::core::sync::SpinLock;
 use crate::core::mm::{PhysAddr, VirtAddr};
+use crate::core::irq::IrqManager;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+pub use crate::core::irq::IrqModeration;
 
 pub mod base;
 pub mod platform;
@@ -121,6 +126,31 @@ pub trait DeviceOps: Send + Sync {
 
     /// Perform I/O control operation
     fn ioctl(&mut self, cmd: u32, arg: u64) -> Result<u64>;
+
+    /// IRQ line raised by the device, if any
+    fn irq(&self) -> Option<u32> {
+        None
+    }
+
+    /// Interrupt moderation the device wants for its IRQ
+    ///
+    /// Defaults to none: every interrupt is delivered immediately.
+    fn irq_moderation(&self) -> IrqModeration {
+        IrqModeration::NONE
+    }
+
+    /// Change the device's interrupt moderation
+    fn set_irq_moderation(&mut self, _moderation: IrqModeration) -> Result<()> {
+        Err(Error::NotImplemented)
+    }
+}
+
+/// Pass a device's interrupt moderation on to the coalescing layer
+pub fn apply_irq_moderation(device: &dyn DeviceOps, irqs: &IrqManager) -> Result<()> {
+    match device.irq() {
+        Some(irq) => irqs.set_irq_moderation(irq, device.irq_moderation()),
+        None => Ok(()),
+    }
 }
 
 /// Device information
@@ -281,6 +311,11 @@ impl DeviceManager {
 
     /// Register a device
     pub fn register_device(&self, device: Box<dyn DeviceOps>) -> Result<u32> {
+        apply_irq_moderation(device.as_ref(), crate::core::irq::get()?)?;
+        if let Some(irq) = device.irq() {
+            crate::config::register_irq(device.name(), irq);
+        }
+
         let device_id = {
             let mut id = self.next_device_id.lock();
             *id += 1;
@@ -312,6 +347,17 @@ impl DeviceManager {
         Ok(())
     }
 
+    /// Change the interrupt moderation of a device
+    pub fn set_irq_moderation(&self, name: &str, moderation: IrqModeration) -> Result<()> {
+        let mut devices = self.devices.lock();
+        let device = devices.iter_mut()
+            .find(|device| device.name() == name)
+            .ok_or(Error::NotFound)?;
+
+        device.set_irq_moderation(moderation)?;
+        apply_irq_moderation(device.as_ref(), crate::core::irq::get()?)
+    }
+
     /// Register a driver
     pub fn register_driver(&self, driver: Box<dyn Driver>) -> Result<()> {
         {
@@ -399,6 +445,60 @@ impl DeviceManager {
     }
 }
 
+impl PowerDomain for DeviceManager {
+    fn suspend_devices(&self) -> Result<()> {
+        suspend_all(&mut self.devices.lock())
+    }
+
+    fn resume_devices(&self) -> Result<()> {
+        resume_all(&mut self.devices.lock())
+    }
+}
+
+/// A set of devices suspended and resumed together
+pub trait PowerDomain {
+    /// Suspend every device in the domain
+    fn suspend_devices(&self) -> Result<()>;
+
+    /// Resume every device in the domain
+    fn resume_devices(&self) -> Result<()>;
+}
+
+/// Suspend devices in order
+///
+/// If a device fails to suspend, the devices already suspended are
+/// resumed again and the error is returned.
+pub fn suspend_all(devices: &mut [Box<dyn DeviceOps>]) -> Result<()> {
+    for index in 0..devices.len() {
+        if let Err(e) = devices[index].suspend() {
+            crate::error!("Failed to suspend device '{}': {:?}", devices[index].name(), e);
+            for device in devices[..index].iter_mut().rev() {
+                let _ = device.resume();
+            }
+            return Err(e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Resume devices in reverse order
+///
+/// Every device is resumed even if an earlier one fails; the last error
+/// is returned.
+pub fn resume_all(devices: &mut [Box<dyn DeviceOps>]) -> Result<()> {
+    let mut result = Ok(());
+
+    for device in devices.iter_mut().rev() {
+        if let Err(e) = device.resume() {
+            crate::error!("Failed to resume device '{}': {:?}", device.name(), e);
+            result = Err(e);
+        }
+    }
+
+    result
+}
+
 /// Global device manager instance
 static DEVICE_MANAGER: SpinLock<Option<DeviceManager>> = SpinLock::new(None);
 
@@ -460,4 +560,55 @@ pub fn handle_interrupt(device_type: DeviceType, irq: u32) -> Result<()> {
     } else {
         Err(Error::NotInitialized)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Device on IRQ 40 that records its moderation
+    struct ModeratedDevice {
+        moderation: IrqModeration,
+    }
+
+    impl DeviceOps for ModeratedDevice {
+        fn init(&mut self) -> Result<()> { Ok(()) }
+        fn probe(&mut self) -> Result<bool> { Ok(true) }
+        fn remove(&mut self) -> Result<()> { Ok(()) }
+        fn suspend(&mut self) -> Result<()> { Ok(()) }
+        fn resume(&mut self) -> Result<()> { Ok(()) }
+        fn device_type(&self) -> DeviceType { DeviceType::Network }
+        fn name(&self) -> &'static str { "moderated-net" }
+        fn status(&self) -> DeviceStatus { DeviceStatus::Ready }
+        fn get_info(&self) -> DeviceInfo { DeviceInfo::default() }
+        fn handle_interrupt(&mut self, _irq: u32) -> Result<()> { Ok(()) }
+        fn ioctl(&mut self, _cmd: u32, _arg: u64) -> Result<u64> { Ok(0) }
+
+        fn irq(&self) -> Option<u32> {
+            Some(40)
+        }
+
+        fn irq_moderation(&self) -> IrqModeration {
+            self.moderation
+        }
+
+        fn set_irq_moderation(&mut self, moderation: IrqModeration) -> Result<()> {
+            self.moderation = moderation;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_device_moderation_reaches_coalescer() {
+        let irqs = IrqManager::new();
+        let mut device = ModeratedDevice { moderation: IrqModeration::NONE };
+        apply_irq_moderation(&device, &irqs).unwrap();
+        assert_eq!(irqs.irq_moderation(40), IrqModeration::NONE);
+
+        let coalesced = IrqModeration { max_events: 8, max_usec: 100 };
+        device.set_irq_moderation(coalesced).unwrap();
+        apply_irq_moderation(&device, &irqs).unwrap();
+        assert_eq!(irqs.irq_moderation(40), coalesced);
+        assert_eq!(irqs.irq_moderation(41), IrqModeration::NONE);
+    }
+}
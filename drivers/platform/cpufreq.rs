@@ -0,0 +1,274 @@
+//! CPU frequency scaling (DVFS) interface
+//!
+//! Provides a platform-neutral way to query and change per-CPU operating
+//! frequencies. The actual mechanism is supplied by a `CpuFreqBackend`:
+//! - `FixedCpuFreqBackend` for platforms without DVFS (e.g. QEMU), where
+//!   requests are recorded but no hardware is touched
+//! - `MmioCpuFreqBackend` for platforms exposing a per-CPU performance
+//!   level register
+//!
+//! Power-aware scheduling can consult `get_frequency` to weigh CPU load
+//! against the current operating point.
+
+use crate::{Result, Error};
+use crate::arch::common::MmioRegion;
+use crate::core::sync::SpinLock;
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Frequency-setting backend
+pub trait CpuFreqBackend: Send {
+    /// Backend name
+    fn name(&self) -> &'static str;
+
+    /// Supported operating frequencies in kHz, ascending
+    fn available_frequencies(&self) -> &[u32];
+
+    /// Program `khz` on `cpu`
+    ///
+    /// Only called with a frequency from `available_frequencies`.
+    fn apply(&mut self, cpu: usize, khz: u32) -> Result<()>;
+}
+
+/// Backend for platforms without frequency control
+///
+/// Reports a single fixed operating point and accepts only that value.
+pub struct FixedCpuFreqBackend {
+    /// The only supported frequency
+    frequencies: [u32; 1],
+}
+
+impl FixedCpuFreqBackend {
+    /// Create a backend reporting a fixed frequency
+    pub const fn new(khz: u32) -> Self {
+        Self { frequencies: [khz] }
+    }
+}
+
+impl CpuFreqBackend for FixedCpuFreqBackend {
+    fn name(&self) -> &'static str {
+        "fixed"
+    }
+
+    fn available_frequencies(&self) -> &[u32] {
+        &self.frequencies
+    }
+
+    fn apply(&mut self, _cpu: usize, _khz: u32) -> Result<()> {
+        // Nothing to program
+        Ok(())
+    }
+}
+
+/// Backend for a per-CPU MMIO performance-level register
+///
+/// CPU `n`'s register lives at `base + n * stride`; writing the index of
+/// an entry in the frequency table selects that operating point.
+pub struct MmioCpuFreqBackend {
+    /// Register window covering all CPUs
+    region: MmioRegion,
+    /// Distance between per-CPU registers
+    stride: usize,
+    /// Operating points in kHz, indexed by performance level
+    frequencies: Vec<u32>,
+}
+
+impl MmioCpuFreqBackend {
+    /// Create a backend for `num_cpus` registers at `base`
+    pub fn new(base: usize, stride: usize, num_cpus: usize, mut frequencies: Vec<u32>) -> Self {
+        frequencies.sort_unstable();
+        frequencies.dedup();
+        Self {
+            region: MmioRegion::new(base, stride * num_cpus),
+            stride,
+            frequencies,
+        }
+    }
+}
+
+impl CpuFreqBackend for MmioCpuFreqBackend {
+    fn name(&self) -> &'static str {
+        "mmio-perf-level"
+    }
+
+    fn available_frequencies(&self) -> &[u32] {
+        &self.frequencies
+    }
+
+    fn apply(&mut self, cpu: usize, khz: u32) -> Result<()> {
+        let level = self.frequencies.iter()
+            .position(|&f| f == khz)
+            .ok_or(Error::InvalidArgument)?;
+        self.region.write::<u32>(cpu * self.stride, level as u32)
+    }
+}
+
+/// CPU frequency controller
+pub struct CpuFreq {
+    /// Active backend
+    backend: Box<dyn CpuFreqBackend>,
+    /// Current frequency per CPU in kHz
+    current: Vec<u32>,
+}
+
+impl CpuFreq {
+    /// Create a controller for `num_cpus` CPUs
+    ///
+    /// All CPUs start at the backend's highest frequency.
+    pub fn new(backend: Box<dyn CpuFreqBackend>, num_cpus: usize) -> Self {
+        let max = backend.available_frequencies().last().copied().unwrap_or(0);
+        Self {
+            backend,
+            current: vec![max; num_cpus],
+        }
+    }
+
+    /// Get backend name
+    pub fn backend_name(&self) -> &'static str {
+        self.backend.name()
+    }
+
+    /// Get supported frequencies in kHz
+    pub fn available_frequencies(&self) -> &[u32] {
+        self.backend.available_frequencies()
+    }
+
+    /// Set the frequency of a CPU
+    pub fn set_frequency(&mut self, cpu: usize, khz: u32) -> Result<()> {
+        if cpu >= self.current.len() {
+            return Err(Error::InvalidArgument);
+        }
+
+        if !self.backend.available_frequencies().contains(&khz) {
+            return Err(Error::InvalidArgument);
+        }
+
+        self.backend.apply(cpu, khz)?;
+        self.current[cpu] = khz;
+        Ok(())
+    }
+
+    /// Get the frequency of a CPU
+    pub fn get_frequency(&self, cpu: usize) -> Result<u32> {
+        self.current.get(cpu).copied().ok_or(Error::InvalidArgument)
+    }
+}
+
+/// Nominal frequency reported on platforms without DVFS (1GHz)
+const DEFAULT_FIXED_KHZ: u32 = 1_000_000;
+
+/// Global CPU frequency controller
+static CPUFREQ: SpinLock<Option<CpuFreq>> = SpinLock::new(None);
+
+/// Initialize CPU frequency scaling with the no-op backend
+pub fn init() -> Result<()> {
+    let num_cpus = crate::arch::cpu::get_cpu_count().unwrap_or(1) as usize;
+    register_backend(Box::new(FixedCpuFreqBackend::new(DEFAULT_FIXED_KHZ)), num_cpus);
+    Ok(())
+}
+
+/// Install a frequency backend, replacing any previous one
+pub fn register_backend(backend: Box<dyn CpuFreqBackend>, num_cpus: usize) {
+    crate::info!("cpufreq: using '{}' backend for {} CPUs", backend.name(), num_cpus);
+    *CPUFREQ.lock() = Some(CpuFreq::new(backend, num_cpus));
+}
+
+/// Get supported frequencies in kHz
+pub fn available_frequencies() -> Vec<u32> {
+    CPUFREQ.lock()
+        .as_ref()
+        .map(|cpufreq| cpufreq.available_frequencies().to_vec())
+        .unwrap_or_default()
+}
+
+/// Set the frequency of a CPU in kHz
+pub fn set_frequency(cpu: usize, khz: u32) -> Result<()> {
+    CPUFREQ.lock()
+        .as_mut()
+        .ok_or(Error::NotInitialized)?
+        .set_frequency(cpu, khz)
+}
+
+/// Get the frequency of a CPU in kHz
+pub fn get_frequency(cpu: usize) -> Result<u32> {
+    CPUFREQ.lock()
+        .as_ref()
+        .ok_or(Error::NotInitialized)?
+        .get_frequency(cpu)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Backend with three operating points and no hardware behind it
+    struct MockBackend {
+        frequencies: [u32; 3],
+    }
+
+    impl CpuFreqBackend for MockBackend {
+        fn name(&self) -> &'static str {
+            "mock"
+        }
+
+        fn available_frequencies(&self) -> &[u32] {
+            &self.frequencies
+        }
+
+        fn apply(&mut self, _cpu: usize, _khz: u32) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn mock() -> Box<MockBackend> {
+        Box::new(MockBackend {
+            frequencies: [600_000, 1_200_000, 1_800_000],
+        })
+    }
+
+    #[test]
+    fn test_set_get_round_trip() {
+        let mut cpufreq = CpuFreq::new(mock(), 4);
+        assert_eq!(cpufreq.get_frequency(2).unwrap(), 1_800_000);
+
+        cpufreq.set_frequency(2, 600_000).unwrap();
+        assert_eq!(cpufreq.get_frequency(2).unwrap(), 600_000);
+        assert_eq!(cpufreq.get_frequency(1).unwrap(), 1_800_000);
+    }
+
+    #[test]
+    fn test_rejects_unsupported_frequency() {
+        let mut cpufreq = CpuFreq::new(mock(), 2);
+
+        assert_eq!(cpufreq.set_frequency(0, 1_000_000), Err(Error::InvalidArgument));
+        assert_eq!(cpufreq.get_frequency(0).unwrap(), 1_800_000);
+
+        // Unknown CPU
+        assert_eq!(cpufreq.set_frequency(2, 600_000), Err(Error::InvalidArgument));
+        assert_eq!(cpufreq.get_frequency(2), Err(Error::InvalidArgument));
+    }
+
+    #[test]
+    fn test_fixed_backend() {
+        let mut cpufreq = CpuFreq::new(Box::new(FixedCpuFreqBackend::new(1_000_000)), 1);
+        assert_eq!(cpufreq.available_frequencies(), [1_000_000]);
+        cpufreq.set_frequency(0, 1_000_000).unwrap();
+        assert!(cpufreq.set_frequency(0, 500_000).is_err());
+    }
+
+    #[test]
+    fn test_mmio_backend_writes_level() {
+        let mut regs = [0u32; 2];
+        let backend = MmioCpuFreqBackend::new(
+            regs.as_mut_ptr() as usize,
+            4,
+            2,
+            vec![1_800_000, 600_000, 1_200_000],
+        );
+        let mut cpufreq = CpuFreq::new(Box::new(backend), 2);
+
+        cpufreq.set_frequency(1, 1_200_000).unwrap();
+        assert_eq!(unsafe { core::ptr::read_volatile(&regs[1]) }, 1);
+    }
+}
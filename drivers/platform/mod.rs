@@ -4,16 +4,22 @@
 //! such as ARM PL011 UART, generic timers, and system registers.
 
 use crate::{Result, Error};
-use crate::drivers::{DeviceType, DeviceOps, DeviceInfo, DeviceStatus};
+use crate::drivers::{DeviceType, DeviceOps, DeviceInfo, DeviceStatus, PowerDomain};
 use crate::core::mm::VirtAddr;
+use crate::core::irq::IrqEnableSet;
 use crate::core::sync::SpinLock;
 use crate::arch::common::MmioAccess;
+use crate::libs::cpumask::CpuMask;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use alloc::boxed::Box;
 use alloc::format;
+use alloc::vec::Vec;
 
 pub mod uart;
 pub mod timer;
 pub mod sysreg;
 pub mod gpio;
+pub mod cpufreq;
 
 /// Initialize platform-specific drivers
 pub fn init() -> Result<()> {
@@ -31,6 +37,9 @@ pub fn init() -> Result<()> {
     // Initialize GPIO driver
     gpio::init()?;
 
+    // Initialize CPU frequency scaling
+    cpufreq::init()?;
+
     crate::info!("Platform-specific drivers initialized");
     Ok(())
 }
@@ -243,6 +252,16 @@ impl PlatformBus {
     }
 }
 
+impl PowerDomain for PlatformBus {
+    fn suspend_devices(&self) -> Result<()> {
+        crate::drivers::suspend_all(&mut self.devices.lock())
+    }
+
+    fn resume_devices(&self) -> Result<()> {
+        crate::drivers::resume_all(&mut self.devices.lock())
+    }
+}
+
 /// Global platform bus instance
 static PLATFORM_BUS: SpinLock<Option<PlatformBus>> = SpinLock::new(None);
 
@@ -273,4 +292,344 @@ pub fn register_device(device: Box<dyn DeviceOps>) -> Result<()> {
     } else {
         Err(Error::NotInitialized)
     }
-}
\ No newline at end of file
+}
+
+/// Platform hooks for entering system sleep
+pub trait SleepOps {
+    /// Save CPU and interrupt-controller state
+    fn save_state(&mut self) -> Result<()>;
+
+    /// Enter the low-power state, returning on wakeup
+    fn enter_sleep(&mut self) -> Result<()>;
+
+    /// Restore CPU and interrupt-controller state after wakeup
+    fn restore_state(&mut self) -> Result<()>;
+}
+
+/// Sleep through the architecture's retentive suspend state
+///
+/// RAM and CPU registers are retained. Secondary harts are parked for the
+/// duration, and the interrupt controller is reprogrammed on wakeup with
+/// the IRQs that were enabled before sleeping.
+pub struct ArchSleep {
+    /// Interrupts were enabled before sleeping
+    irqs_enabled: bool,
+    /// IRQs enabled at the controller before sleeping
+    enabled_irqs: Option<IrqEnableSet>,
+    /// Secondary harts parked while the system sleeps
+    parked: CpuMask,
+}
+
+impl ArchSleep {
+    /// Create the architecture sleep hooks
+    pub const fn new() -> Self {
+        Self {
+            irqs_enabled: false,
+            enabled_irqs: None,
+            parked: CpuMask::new(),
+        }
+    }
+
+    /// Park every other online hart until `unpark_secondaries`
+    #[cfg(target_arch = "riscv64")]
+    fn park_secondaries(&mut self) -> Result<()> {
+        use crate::arch::riscv64::smp::ipi::call_on_cpu;
+
+        let current = crate::arch::cpu::get_current_cpu_id().unwrap_or(0);
+        SLEEPING.store(true, Ordering::Release);
+        for cpu in crate::arch::cpu::get_online_cpu_mask().iter().filter(|&cpu| cpu != current) {
+            if call_on_cpu(cpu as usize, park_hart, 0, false).is_err() {
+                self.unpark_secondaries();
+                return Err(Error::ResourceBusy);
+            }
+            self.parked.set(cpu);
+        }
+
+        if !wait_parked(self.parked.count() as usize) {
+            crate::error!("Secondary harts did not park for suspend");
+            self.unpark_secondaries();
+            return Err(Error::Timeout);
+        }
+        Ok(())
+    }
+
+    /// Park every other online CPU until `unpark_secondaries`
+    ///
+    /// Secondary CPUs can only be parked on RISC-V, so elsewhere the system
+    /// only sleeps with them offline.
+    #[cfg(not(target_arch = "riscv64"))]
+    fn park_secondaries(&mut self) -> Result<()> {
+        if crate::arch::cpu::get_online_cpu_mask().count() > 1 {
+            return Err(Error::ResourceBusy);
+        }
+        Ok(())
+    }
+
+    /// Release the harts parked by `park_secondaries`
+    fn unpark_secondaries(&mut self) {
+        SLEEPING.store(false, Ordering::Release);
+
+        #[cfg(target_arch = "riscv64")]
+        for cpu in self.parked.iter() {
+            let _ = crate::arch::riscv64::smp::send_wake_up_ipi(cpu as usize);
+        }
+        if !wait_parked(0) {
+            crate::warn!("Secondary harts still parked after resume");
+        }
+        self.parked = CpuMask::new();
+    }
+}
+
+/// Set while the system sleeps; parked harts wait for it to clear
+static SLEEPING: AtomicBool = AtomicBool::new(false);
+
+/// Number of secondary harts parked for system sleep
+static PARKED_HARTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Spins allowed for secondary harts to park or leave
+const PARK_WAIT_SPINS: usize = 10_000_000;
+
+/// Wait until `count` harts are parked
+fn wait_parked(count: usize) -> bool {
+    for _ in 0..PARK_WAIT_SPINS {
+        if PARKED_HARTS.load(Ordering::Acquire) == count {
+            return true;
+        }
+        core::hint::spin_loop();
+    }
+    false
+}
+
+/// Hold the calling hart in retentive suspend while the system sleeps
+///
+/// Runs on each secondary hart as a remote call from `park_secondaries`.
+#[cfg(target_arch = "riscv64")]
+fn park_hart(_arg: usize) {
+    use crate::arch::riscv64::smp::sbi::{sbi_hart_suspend, SuspendType};
+
+    let irqs_enabled = crate::core::irq::are_interrupts_enabled();
+    crate::core::irq::disable_interrupts();
+    PARKED_HARTS.fetch_add(1, Ordering::AcqRel);
+
+    // A pending interrupt ends the suspend even while masked
+    while SLEEPING.load(Ordering::Acquire) {
+        let _ = sbi_hart_suspend(SuspendType::Retentive, 0, 0);
+    }
+
+    PARKED_HARTS.fetch_sub(1, Ordering::AcqRel);
+    if irqs_enabled {
+        crate::core::irq::enable_interrupts();
+    }
+}
+
+impl SleepOps for ArchSleep {
+    fn save_state(&mut self) -> Result<()> {
+        self.irqs_enabled = crate::core::irq::are_interrupts_enabled();
+        crate::core::irq::disable_interrupts();
+        self.enabled_irqs = crate::core::irq::get().ok().map(|irq| irq.enabled_irqs());
+
+        if let Err(e) = self.park_secondaries() {
+            if self.irqs_enabled {
+                crate::core::irq::enable_interrupts();
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn enter_sleep(&mut self) -> Result<()> {
+        #[cfg(target_arch = "riscv64")]
+        {
+            use crate::arch::riscv64::smp::sbi::{sbi_hart_suspend, SuspendType};
+            sbi_hart_suspend(SuspendType::Retentive, 0, 0)
+                .map_err(|_| Error::ResourceUnavailable)
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        {
+            use crate::arch::arm64::psci::PSCI_0_2_FN64_CPU_SUSPEND;
+            // Standby power state: a retention state that returns here
+            let ret: i64;
+            unsafe {
+                core::arch::asm!("smc #0",
+                                 inout("x0") PSCI_0_2_FN64_CPU_SUSPEND as u64 => ret,
+                                 in("x1") 0u64, in("x2") 0u64, in("x3") 0u64);
+            }
+            if ret == 0 { Ok(()) } else { Err(Error::ResourceUnavailable) }
+        }
+
+        #[cfg(not(any(target_arch = "riscv64", target_arch = "aarch64")))]
+        {
+            Err(Error::NotImplemented)
+        }
+    }
+
+    fn restore_state(&mut self) -> Result<()> {
+        let restored = match self.enabled_irqs.take() {
+            Some(enabled) => crate::core::irq::get()
+                .and_then(|irq| irq.restore_controller(&enabled)),
+            None => Ok(()),
+        };
+
+        self.unpark_secondaries();
+        if self.irqs_enabled {
+            crate::core::irq::enable_interrupts();
+        }
+        restored
+    }
+}
+
+/// Devices of the device manager
+///
+/// The manager is locked only while its devices change state, never
+/// across the sleep itself.
+struct ManagedDevices;
+
+impl PowerDomain for ManagedDevices {
+    fn suspend_devices(&self) -> Result<()> {
+        crate::drivers::get_manager().lock().as_ref().map_or(Ok(()), |m| m.suspend_devices())
+    }
+
+    fn resume_devices(&self) -> Result<()> {
+        crate::drivers::get_manager().lock().as_ref().map_or(Ok(()), |m| m.resume_devices())
+    }
+}
+
+/// Devices on the platform bus
+///
+/// Like `ManagedDevices`, the bus is only locked while its devices change
+/// state.
+struct PlatformDevices;
+
+impl PowerDomain for PlatformDevices {
+    fn suspend_devices(&self) -> Result<()> {
+        PLATFORM_BUS.lock().as_ref().map_or(Ok(()), |bus| bus.suspend_devices())
+    }
+
+    fn resume_devices(&self) -> Result<()> {
+        PLATFORM_BUS.lock().as_ref().map_or(Ok(()), |bus| bus.resume_devices())
+    }
+}
+
+/// Suspend `domains` and put the system to sleep through `sleep`
+///
+/// Domains are suspended in order and resumed in reverse order after
+/// wakeup. Devices are resumed even if entering sleep failed.
+pub fn suspend_to_ram_with(domains: &[&dyn PowerDomain], sleep: &mut dyn SleepOps) -> Result<()> {
+    for (index, domain) in domains.iter().enumerate() {
+        if let Err(e) = domain.suspend_devices() {
+            for domain in domains[..index].iter().rev() {
+                let _ = domain.resume_devices();
+            }
+            return Err(e);
+        }
+    }
+
+    let result = match sleep.save_state() {
+        Ok(()) => {
+            let slept = sleep.enter_sleep();
+            sleep.restore_state().and(slept)
+        }
+        Err(e) => Err(e),
+    };
+
+    for domain in domains.iter().rev() {
+        if let Err(e) = domain.resume_devices() {
+            crate::error!("Failed to resume devices after sleep: {:?}", e);
+        }
+    }
+
+    result
+}
+
+/// Suspend the whole system to RAM
+///
+/// Quiesces all registered devices, enters the platform's low-power
+/// state and brings everything back on wakeup.
+pub fn suspend_to_ram() -> Result<()> {
+    crate::info!("Suspending to RAM");
+
+    let result = suspend_to_ram_with(&[&ManagedDevices, &PlatformDevices], &mut ArchSleep::new());
+    crate::info!("Resumed from suspend-to-RAM: {:?}", result);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drivers::DeviceManager;
+
+    /// Power events in the order they happened
+    static EVENTS: SpinLock<Vec<&'static str>> = SpinLock::new(Vec::new());
+
+    fn record(event: &'static str) {
+        EVENTS.lock().push(event);
+    }
+
+    /// Device that records its suspend/resume calls
+    struct MockDevice {
+        suspend_event: &'static str,
+        resume_event: &'static str,
+    }
+
+    impl DeviceOps for MockDevice {
+        fn init(&mut self) -> Result<()> { Ok(()) }
+        fn probe(&mut self) -> Result<bool> { Ok(true) }
+        fn remove(&mut self) -> Result<()> { Ok(()) }
+
+        fn suspend(&mut self) -> Result<()> {
+            record(self.suspend_event);
+            Ok(())
+        }
+
+        fn resume(&mut self) -> Result<()> {
+            record(self.resume_event);
+            Ok(())
+        }
+
+        fn device_type(&self) -> DeviceType { DeviceType::Virtual }
+        fn name(&self) -> &'static str { "mock" }
+        fn status(&self) -> DeviceStatus { DeviceStatus::Ready }
+        fn get_info(&self) -> DeviceInfo { DeviceInfo::default() }
+        fn handle_interrupt(&mut self, _irq: u32) -> Result<()> { Ok(()) }
+        fn ioctl(&mut self, _cmd: u32, _arg: u64) -> Result<u64> { Ok(0) }
+    }
+
+    /// Sleep hooks that only record the sequence
+    struct MockSleep;
+
+    impl SleepOps for MockSleep {
+        fn save_state(&mut self) -> Result<()> {
+            record("save");
+            Ok(())
+        }
+
+        fn enter_sleep(&mut self) -> Result<()> {
+            record("sleep");
+            Ok(())
+        }
+
+        fn restore_state(&mut self) -> Result<()> {
+            record("restore");
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_suspend_resume_order() {
+        let manager = DeviceManager::new();
+        manager.register_device(Box::new(MockDevice { suspend_event: "a:suspend", resume_event: "a:resume" })).unwrap();
+        manager.register_device(Box::new(MockDevice { suspend_event: "b:suspend", resume_event: "b:resume" })).unwrap();
+
+        let bus = PlatformBus::new();
+        bus.add_device(Box::new(MockDevice { suspend_event: "c:suspend", resume_event: "c:resume" })).unwrap();
+
+        suspend_to_ram_with(&[&manager, &bus], &mut MockSleep).unwrap();
+
+        assert_eq!(*EVENTS.lock(), [
+            "a:suspend", "b:suspend", "c:suspend",
+            "save", "sleep", "restore",
+            "c:resume", "b:resume", "a:resume",
+        ]);
+    }
+}
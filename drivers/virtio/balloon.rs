@@ -0,0 +1,424 @@
+//! VirtIO memory balloon device
+//!
+//! Lets the hypervisor take memory back from guests. Raising a balloon's
+//! target (`num_pages` in the device config) asks the guest driver to
+//! inflate: it allocates that many of its free pages and reports them on
+//! the inflate queue, after which the host unmaps and reuses them. The
+//! guest reports progress by writing `actual`. Pages returned on the
+//! deflate queue need no work: the guest's next access backs them again
+//! through a stage-2 fault.
+//!
+//! Balloons register with the allocator's memory pressure notifier, so an
+//! exhausted host asks guests to inflate before declaring OOM. Inflation
+//! completes asynchronously, so the notifier counts nothing as freed.
+
+use crate::{Result, Error};
+use crate::core::mm::{PhysAddr, PAGE_SIZE};
+use crate::core::sync::SpinLock;
+use crate::core::vmm::VmId;
+use crate::emulator::DeviceClass;
+use crate::emulators::virtio_mmio::{QueueConfig, VirtioBackend, VirtioMmioTransport,
+                                    VIRTIO_MMIO_INT_CONFIG, VIRTIO_MMIO_INT_VRING};
+use crate::emulators::virtio_mmio::queue::{DeviceQueue, GuestMemory, VmMemory};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Priority of the balloon memory pressure callback
+///
+/// Above in-hypervisor caches: guest free pages are the cheapest memory to
+/// take back.
+pub const BALLOON_PRESSURE_PRIORITY: u8 = 200;
+
+/// Balloon config space offset of `num_pages` (target, host-written)
+pub const CONFIG_NUM_PAGES: u64 = 0x00;
+
+/// Balloon config space offset of `actual` (guest-written)
+pub const CONFIG_ACTUAL: u64 = 0x04;
+
+/// VirtIO device ID of the memory balloon
+pub const VIRTIO_ID_BALLOON: u32 = 5;
+
+/// Queue the guest reports inflated pages on
+pub const INFLATE_QUEUE: usize = 0;
+
+/// Queue the guest reports deflated pages on
+pub const DEFLATE_QUEUE: usize = 1;
+
+/// Largest queue size offered to the driver
+const BALLOON_QUEUE_MAX: u16 = 256;
+
+/// Balloon PFNs are in 4 KiB units whatever the guest page size
+const VIRTIO_BALLOON_PFN_SHIFT: u32 = 12;
+
+/// Balloon of one VM
+#[derive(Debug)]
+pub struct VirtioBalloon {
+    /// Owning VM
+    vm_id: VmId,
+    /// Guest RAM in pages; the balloon never asks for more
+    guest_pages: u32,
+    /// Pages the guest is asked to give up
+    num_pages: u32,
+    /// Pages the guest has given up
+    actual: u32,
+    /// Target changed since the guest last read the config
+    config_changed: bool,
+}
+
+impl VirtioBalloon {
+    /// Create an empty balloon for a VM with `guest_pages` pages of RAM
+    pub fn new(vm_id: VmId, guest_pages: u32) -> Self {
+        Self {
+            vm_id,
+            guest_pages,
+            num_pages: 0,
+            actual: 0,
+            config_changed: false,
+        }
+    }
+
+    /// Owning VM
+    pub fn vm_id(&self) -> VmId {
+        self.vm_id
+    }
+
+    /// Pages the guest is asked to give up
+    pub fn target(&self) -> u32 {
+        self.num_pages
+    }
+
+    /// Pages the guest has given up
+    pub fn actual(&self) -> u32 {
+        self.actual
+    }
+
+    /// Check and clear the config-changed flag, used to raise the
+    /// configuration change interrupt
+    pub fn take_config_changed(&mut self) -> bool {
+        core::mem::take(&mut self.config_changed)
+    }
+
+    /// Ask the guest to give up `pages` more pages
+    ///
+    /// Returns the number of pages actually requested, less than `pages`
+    /// when the guest has too little RAM left.
+    pub fn inflate(&mut self, pages: u32) -> u32 {
+        let pages = pages.min(self.guest_pages - self.num_pages);
+        if pages > 0 {
+            self.num_pages += pages;
+            self.config_changed = true;
+        }
+        pages
+    }
+
+    /// Let the guest take back up to `pages` pages
+    pub fn deflate(&mut self, pages: u32) {
+        let pages = pages.min(self.num_pages);
+        if pages > 0 {
+            self.num_pages -= pages;
+            self.config_changed = true;
+        }
+    }
+
+    /// Read a 32-bit config field
+    pub fn read_config(&self, offset: u64) -> Result<u32> {
+        match offset {
+            CONFIG_NUM_PAGES => Ok(self.num_pages),
+            CONFIG_ACTUAL => Ok(self.actual),
+            _ => Err(Error::InvalidArgument),
+        }
+    }
+
+    /// Write a 32-bit config field; only `actual` is guest-writable
+    pub fn write_config(&mut self, offset: u64, value: u32) -> Result<()> {
+        match offset {
+            CONFIG_ACTUAL => {
+                self.actual = value.min(self.guest_pages);
+                Ok(())
+            }
+            CONFIG_NUM_PAGES => Err(Error::PermissionDenied),
+            _ => Err(Error::InvalidArgument),
+        }
+    }
+
+    /// The driver reset the device, reclaiming the pages it had given up
+    pub fn driver_reset(&mut self) {
+        self.actual = 0;
+    }
+}
+
+/// Complete every chain the driver made available on a balloon queue
+///
+/// Each device-readable buffer holds little-endian 32-bit PFNs; `release`
+/// is called with the guest physical address of every page listed.
+/// Returns the number of chains completed.
+fn drain_queue(queue: &mut DeviceQueue, mem: &dyn GuestMemory,
+               mut release: impl FnMut(PhysAddr)) -> Result<usize> {
+    let mut completed = 0;
+    while let Some(chain) = queue.pop(mem)? {
+        for desc in chain.readable() {
+            for i in 0..(desc.len / 4) as u64 {
+                let pfn = mem.read_u32(desc.addr + 4 * i)?;
+                release((pfn as PhysAddr) << VIRTIO_BALLOON_PFN_SHIFT);
+            }
+        }
+        queue.push_used(mem, chain.head, 0)?;
+        completed += 1;
+    }
+    Ok(completed)
+}
+
+/// Device model of a VM's balloon; the balloon state itself stays in the
+/// registry, where the memory pressure notifier reaches it
+pub struct BalloonDevice {
+    /// Owning VM
+    vm_id: VmId,
+    /// Inflate and deflate queues, once live
+    queues: [Option<DeviceQueue>; 2],
+}
+
+impl BalloonDevice {
+    /// Create the device model of the balloon of `vm_id`
+    pub fn new(vm_id: VmId) -> Self {
+        Self { vm_id, queues: [None; 2] }
+    }
+}
+
+impl VirtioBackend for BalloonDevice {
+    fn device_id(&self) -> u32 {
+        VIRTIO_ID_BALLOON
+    }
+
+    fn features(&self) -> u64 {
+        0
+    }
+
+    fn num_queues(&self) -> usize {
+        self.queues.len()
+    }
+
+    fn queue_max(&self, _index: usize) -> u16 {
+        BALLOON_QUEUE_MAX
+    }
+
+    fn activate_queue(&mut self, index: usize, config: QueueConfig) -> Result<()> {
+        let queue = self.queues.get_mut(index).ok_or(Error::InvalidArgument)?;
+        *queue = Some(DeviceQueue::new(config));
+        Ok(())
+    }
+
+    fn deactivate_queue(&mut self, index: usize) {
+        if let Some(queue) = self.queues.get_mut(index) {
+            *queue = None;
+        }
+    }
+
+    fn notify(&mut self, index: usize) -> u32 {
+        let vm_id = self.vm_id;
+        let Some(Some(queue)) = self.queues.get_mut(index) else {
+            return 0;
+        };
+
+        let mut released = 0;
+        let result = drain_queue(queue, &VmMemory { vm_id }, |gpa| {
+            if index != INFLATE_QUEUE {
+                return;
+            }
+            // A page inside a huge stage-2 leaf stays backed until the
+            // whole leaf is given up
+            match crate::core::vmm::vm::release_memory(vm_id, gpa, PAGE_SIZE) {
+                Ok(bytes) => released += bytes / PAGE_SIZE,
+                Err(e) => crate::debug!("virtio-balloon: VM {} keeps page {:#x}: {:?}", vm_id, gpa, e),
+            }
+        });
+        if released > 0 {
+            crate::debug!("virtio-balloon: VM {} released {} pages", vm_id, released);
+        }
+
+        match result {
+            Ok(0) => 0,
+            Ok(_) => VIRTIO_MMIO_INT_VRING,
+            Err(e) => {
+                crate::warn!("virtio-balloon: VM {}: bad queue {}: {:?}", vm_id, index, e);
+                VIRTIO_MMIO_INT_VRING
+            }
+        }
+    }
+
+    fn read_config(&self, offset: u64, size: u32) -> u64 {
+        if size != 32 {
+            return 0;
+        }
+        with_balloon(self.vm_id, |b| b.read_config(offset))
+            .and_then(|value| value)
+            .unwrap_or(0) as u64
+    }
+
+    fn write_config(&mut self, offset: u64, value: u64, size: u32) {
+        if size == 32 {
+            let _ = with_balloon(self.vm_id, |b| b.write_config(offset, value as u32));
+        }
+    }
+
+    fn reset(&mut self) {
+        self.queues = [None; 2];
+        let _ = with_balloon(self.vm_id, |b| b.driver_reset());
+    }
+
+    fn poll(&mut self) -> u32 {
+        match with_balloon(self.vm_id, |b| b.take_config_changed()) {
+            Ok(true) => VIRTIO_MMIO_INT_CONFIG,
+            _ => 0,
+        }
+    }
+}
+
+/// Spread a request for `pages` pages over `balloons`
+///
+/// Each balloon is asked for an equal share, capped by what its guest has
+/// left, and the shortfall goes to the remaining balloons. Returns the
+/// number of pages requested.
+fn inflate_balloons(balloons: &mut [VirtioBalloon], pages: usize) -> usize {
+    let mut remaining = pages;
+    let mut left = balloons.len();
+    for balloon in balloons.iter_mut() {
+        if remaining == 0 {
+            break;
+        }
+        let share = remaining.div_ceil(left).min(u32::MAX as usize) as u32;
+        remaining -= balloon.inflate(share) as usize;
+        left -= 1;
+    }
+    pages - remaining
+}
+
+/// Balloons of all VMs
+static BALLOONS: SpinLock<Vec<VirtioBalloon>> = SpinLock::new(Vec::new());
+
+/// Memory pressure callback: ask guests to inflate their balloons
+///
+/// Nothing is freed until the guests report pages on the inflate queue,
+/// so this always returns 0. Allocations made with the registry held
+/// (e.g. by `add_balloon`) skip the balloons rather than deadlock.
+fn balloon_pressure(pages: usize) -> usize {
+    let Some(mut balloons) = BALLOONS.try_lock() else {
+        return 0;
+    };
+    let requested = inflate_balloons(&mut balloons, pages);
+    drop(balloons);
+
+    if requested > 0 {
+        crate::info!("virtio-balloon: asking guests for {} pages", requested);
+        // Raise the configuration change interrupts
+        crate::emulator::poll_emulators();
+    }
+    0
+}
+
+/// Add a balloon for a VM with `guest_pages` pages of RAM
+pub fn add_balloon(vm_id: VmId, guest_pages: u32) -> Result<()> {
+    let mut balloons = BALLOONS.lock();
+    if balloons.iter().any(|b| b.vm_id == vm_id) {
+        return Err(Error::ResourceBusy);
+    }
+    balloons.push(VirtioBalloon::new(vm_id, guest_pages));
+    Ok(())
+}
+
+/// Remove the balloon of a VM
+pub fn remove_balloon(vm_id: VmId) -> Result<()> {
+    let mut balloons = BALLOONS.lock();
+    let pos = balloons.iter().position(|b| b.vm_id == vm_id).ok_or(Error::NotFound)?;
+    balloons.remove(pos);
+    Ok(())
+}
+
+/// Emulator registry name of the balloon device of a VM, also the name of
+/// its interrupt route
+pub fn device_name(vm_id: VmId) -> String {
+    format!("virtio-balloon.{}", vm_id)
+}
+
+/// Add a balloon for a VM with `guest_pages` pages of RAM and register its
+/// virtio-mmio device at `base..base + size`, interrupting on guest `irq`
+pub fn attach(vm_id: VmId, guest_pages: u32, base: u64, size: u64, irq: u32) -> Result<()> {
+    add_balloon(vm_id, guest_pages)?;
+
+    let name = device_name(vm_id);
+    let mut transport = VirtioMmioTransport::new(base, irq, BalloonDevice::new(vm_id));
+    transport.set_guest_irq(vm_id, &name);
+    if let Err(e) = crate::emulator::register_mmio_emulator(vm_id, &name, DeviceClass::Virtio, base, size,
+                                                            alloc::boxed::Box::new(transport)) {
+        remove_balloon(vm_id).ok();
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Remove the balloon of a VM and its device, if it has one
+pub fn detach(vm_id: VmId) {
+    if remove_balloon(vm_id).is_ok() {
+        crate::emulator::unregister_emulator(vm_id, &device_name(vm_id)).ok();
+    }
+}
+
+/// Run `f` on the balloon of a VM
+pub fn with_balloon<R>(vm_id: VmId, f: impl FnOnce(&mut VirtioBalloon) -> R) -> Result<R> {
+    BALLOONS.lock()
+        .iter_mut()
+        .find(|b| b.vm_id == vm_id)
+        .map(f)
+        .ok_or(Error::NotFound)
+}
+
+pub fn init() -> Result<()> {
+    crate::core::mm::pressure::on_memory_pressure("virtio-balloon", BALLOON_PRESSURE_PRIORITY, balloon_pressure)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn test_pressure_spreads_over_balloons() {
+        let mut balloons = vec![VirtioBalloon::new(1, 4), VirtioBalloon::new(2, 100)];
+
+        // VM 1 can only give 4 pages; VM 2 covers the rest
+        assert_eq!(inflate_balloons(&mut balloons, 10), 10);
+        assert_eq!(balloons[0].target(), 4);
+        assert_eq!(balloons[1].target(), 6);
+        assert!(balloons[1].take_config_changed());
+        assert!(!balloons[1].take_config_changed());
+
+        // Guest reports progress; the target is host-owned
+        assert_eq!(balloons[1].write_config(CONFIG_ACTUAL, 6), Ok(()));
+        assert_eq!(balloons[1].read_config(CONFIG_ACTUAL), Ok(6));
+        assert_eq!(balloons[1].write_config(CONFIG_NUM_PAGES, 0), Err(Error::PermissionDenied));
+
+        assert_eq!(inflate_balloons(&mut balloons, 200), 94);
+        balloons[1].deflate(50);
+        assert_eq!(balloons[1].target(), 50);
+    }
+
+    #[test]
+    fn test_inflate_queue_releases_listed_pages() {
+        use crate::emulators::virtio_mmio::queue::FlatMemory;
+
+        let (mem, mut queue) = FlatMemory::with_queue(0x5000, 4);
+        assert_eq!(drain_queue(&mut queue, &mem, |_| unreachable!()), Ok(0));
+
+        // One buffer listing PFNs 0x80010 and 0x80011
+        mem.write(0x4000, &0x80010u32.to_le_bytes()).unwrap();
+        mem.write(0x4004, &0x80011u32.to_le_bytes()).unwrap();
+        mem.write_desc(0, 0x4000, 8, 0, 0);
+        mem.make_available(0, 0);
+
+        let mut released = Vec::new();
+        assert_eq!(drain_queue(&mut queue, &mem, |gpa| released.push(gpa)), Ok(1));
+        assert_eq!(released, [0x8001_0000, 0x8001_1000]);
+        assert_eq!(mem.read_u16(0x3002), Ok(1));
+        assert_eq!(mem.read_u32(0x3004), Ok(0));
+    }
+}
@@ -1,7 +1,725 @@
-//! Empty stub for VirtIO block.rs
+//! VirtIO block device
+//!
+//! Block requests are dispatched to a pluggable `BlockBackend`, so the
+//! same driver can sit on top of RAM, a host file, or a read-only image:
+//! - `RamBlockBackend` keeps the whole disk in memory
+//! - `ReadOnlyBackend` wraps another backend and rejects writes
+//!
+//! Each device keeps I/O statistics, read with `stats()`.
+//!
+//! The device is given to a guest as a virtio-mmio device: requests are
+//! taken from its request virtqueue, each a chain of a header, the data
+//! buffers and a device-writable status byte.
 
 use crate::{Result, Error};
+use crate::core::sync::SpinLock;
+use crate::core::vmm::VmId;
+use crate::emulators::virtio_mmio::{self, QueueConfig, VirtioBackend, VirtioMmioTransport, VIRTIO_MMIO_INT_VRING};
+use crate::emulators::virtio_mmio::queue::{DescriptorChain, DeviceQueue, GuestMemory, VmMemory};
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Size of a VirtIO block sector in bytes
+pub const SECTOR_SIZE: usize = 512;
+
+/// VirtIO device ID of the block device
+pub const VIRTIO_ID_BLOCK: u32 = 2;
+
+/// Queue the driver submits requests on
+pub const REQUEST_QUEUE: usize = 0;
+
+/// Largest queue size offered to the driver
+const BLOCK_QUEUE_MAX: u16 = 256;
+
+/// VirtIO block feature bits
+pub mod features {
+    /// Device is read-only
+    pub const RO: u64 = 1 << 5;
+    /// Cache flush command support
+    pub const FLUSH: u64 = 1 << 9;
+}
+
+/// VirtIO block request types
+pub mod req_type {
+    /// Read sectors
+    pub const IN: u32 = 0;
+    /// Write sectors
+    pub const OUT: u32 = 1;
+    /// Flush volatile write cache
+    pub const FLUSH: u32 = 4;
+    /// Get device ID string
+    pub const GET_ID: u32 = 8;
+}
+
+/// VirtIO block request status codes
+pub mod req_status {
+    /// Request completed
+    pub const OK: u8 = 0;
+    /// Device or driver error
+    pub const IOERR: u8 = 1;
+    /// Request type not supported
+    pub const UNSUPP: u8 = 2;
+}
+
+/// Length of the device ID string returned by GET_ID
+pub const ID_BYTES: usize = 20;
+
+/// VirtIO block request header
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VirtioBlkReqHeader {
+    /// Request type (see `req_type`)
+    pub type_: u32,
+    /// Reserved
+    pub reserved: u32,
+    /// Starting sector
+    pub sector: u64,
+}
+
+/// Size of `VirtioBlkReqHeader` in guest memory
+pub const REQ_HEADER_LEN: usize = 16;
+
+impl VirtioBlkReqHeader {
+    /// Decode a little-endian header
+    pub fn from_bytes(bytes: &[u8; REQ_HEADER_LEN]) -> Self {
+        let word = |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        Self {
+            type_: word(0),
+            reserved: word(4),
+            sector: word(8) as u64 | (word(12) as u64) << 32,
+        }
+    }
+}
+
+/// Storage behind a VirtIO block device
+///
+/// Blocks are `SECTOR_SIZE` bytes. `read_block`/`write_block` transfer
+/// `buf.len() / SECTOR_SIZE` consecutive blocks starting at `block`.
+pub trait BlockBackend: Send {
+    /// Read blocks starting at `block` into `buf`
+    fn read_block(&mut self, block: u64, buf: &mut [u8]) -> Result<()>;
+
+    /// Write blocks starting at `block` from `buf`
+    fn write_block(&mut self, block: u64, buf: &[u8]) -> Result<()>;
+
+    /// Make previous writes durable
+    fn flush(&mut self) -> Result<()>;
+
+    /// Capacity in blocks
+    fn capacity(&self) -> u64;
+
+    /// Whether the backend rejects writes
+    fn is_read_only(&self) -> bool {
+        false
+    }
+}
+
+/// Validate a transfer against a backend's capacity
+///
+/// Returns the byte offset of `block`.
+fn check_range(block: u64, len: usize, capacity: u64) -> Result<usize> {
+    if len % SECTOR_SIZE != 0 {
+        return Err(Error::InvalidArgument);
+    }
+
+    let count = (len / SECTOR_SIZE) as u64;
+    let end = block.checked_add(count).ok_or(Error::InvalidArgument)?;
+    if end > capacity {
+        return Err(Error::InvalidArgument);
+    }
+
+    Ok(block as usize * SECTOR_SIZE)
+}
+
+/// In-memory block backend
+pub struct RamBlockBackend {
+    /// Disk contents
+    data: Vec<u8>,
+}
+
+impl RamBlockBackend {
+    /// Create a zero-filled disk of `blocks` blocks
+    pub fn new(blocks: u64) -> Self {
+        Self {
+            data: vec![0; blocks as usize * SECTOR_SIZE],
+        }
+    }
+
+    /// Create a disk from an existing image
+    ///
+    /// The image is padded with zeros to a whole number of blocks.
+    pub fn from_image(mut image: Vec<u8>) -> Self {
+        let padded = (image.len() + SECTOR_SIZE - 1) / SECTOR_SIZE * SECTOR_SIZE;
+        image.resize(padded, 0);
+        Self { data: image }
+    }
+}
+
+impl BlockBackend for RamBlockBackend {
+    fn read_block(&mut self, block: u64, buf: &mut [u8]) -> Result<()> {
+        let start = check_range(block, buf.len(), self.capacity())?;
+        buf.copy_from_slice(&self.data[start..start + buf.len()]);
+        Ok(())
+    }
+
+    fn write_block(&mut self, block: u64, buf: &[u8]) -> Result<()> {
+        let start = check_range(block, buf.len(), self.capacity())?;
+        self.data[start..start + buf.len()].copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        // Nothing volatile to flush
+        Ok(())
+    }
+
+    fn capacity(&self) -> u64 {
+        (self.data.len() / SECTOR_SIZE) as u64
+    }
+}
+
+/// Read-only view of another backend
+pub struct ReadOnlyBackend<B: BlockBackend> {
+    /// Wrapped backend
+    inner: B,
+}
+
+impl<B: BlockBackend> ReadOnlyBackend<B> {
+    /// Wrap `inner`, rejecting all writes
+    pub fn new(inner: B) -> Self {
+        Self { inner }
+    }
+
+    /// Unwrap the inner backend
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+}
+
+impl<B: BlockBackend> BlockBackend for ReadOnlyBackend<B> {
+    fn read_block(&mut self, block: u64, buf: &mut [u8]) -> Result<()> {
+        self.inner.read_block(block, buf)
+    }
+
+    fn write_block(&mut self, _block: u64, _buf: &[u8]) -> Result<()> {
+        Err(Error::PermissionDenied)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        // No writes ever reach the inner backend
+        Ok(())
+    }
+
+    fn capacity(&self) -> u64 {
+        self.inner.capacity()
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+}
+
+/// Weight of a new sample in the latency average, as a shift (1/8)
+const LATENCY_EWMA_SHIFT: u32 = 3;
+
+/// I/O statistics of a block device
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BlockStats {
+    /// Completed read requests
+    pub reads: u64,
+    /// Completed write requests
+    pub writes: u64,
+    /// Completed flush requests
+    pub flushes: u64,
+    /// Bytes read
+    pub bytes_read: u64,
+    /// Bytes written
+    pub bytes_written: u64,
+    /// Failed requests
+    pub errors: u64,
+    /// Moving average of request completion latency in nanoseconds
+    pub latency_ewma_ns: u64,
+}
+
+impl BlockStats {
+    /// Account a completed request of `type_` that moved `bytes` bytes
+    ///
+    /// Only requests that reach the backend are counted: `GET_ID` is
+    /// answered from the device's own ID string, so its latency would
+    /// drag the storage average down.
+    pub fn record(&mut self, type_: u32, bytes: usize, latency_ns: u64) {
+        match type_ {
+            req_type::IN => {
+                self.reads += 1;
+                self.bytes_read += bytes as u64;
+            }
+            req_type::OUT => {
+                self.writes += 1;
+                self.bytes_written += bytes as u64;
+            }
+            req_type::FLUSH => self.flushes += 1,
+            _ => return,
+        }
+
+        self.latency_ewma_ns = if self.reads + self.writes + self.flushes == 1 {
+            latency_ns
+        } else {
+            self.latency_ewma_ns - (self.latency_ewma_ns >> LATENCY_EWMA_SHIFT)
+                + (latency_ns >> LATENCY_EWMA_SHIFT)
+        };
+    }
+}
+
+/// VirtIO block device
+pub struct VirtioBlock {
+    /// Storage backend
+    backend: Box<dyn BlockBackend>,
+    /// Device ID string
+    id: [u8; ID_BYTES],
+    /// I/O statistics
+    stats: BlockStats,
+    /// VM whose memory holds the request queue
+    vm_id: Option<VmId>,
+    /// Request virtqueue, once the driver made it live
+    queue: Option<DeviceQueue>,
+}
+
+impl VirtioBlock {
+    /// Create a block device on top of `backend`
+    pub fn new(backend: Box<dyn BlockBackend>) -> Self {
+        let mut id = [0u8; ID_BYTES];
+        let name = b"ferrovisor-blk";
+        id[..name.len()].copy_from_slice(name);
+        Self { backend, id, stats: BlockStats::default(), vm_id: None, queue: None }
+    }
+
+    /// Capacity in sectors, as reported in the device configuration
+    pub fn capacity(&self) -> u64 {
+        self.backend.capacity()
+    }
+
+    /// Features offered to the driver
+    pub fn device_features(&self) -> u64 {
+        let mut features = features::FLUSH;
+        if self.backend.is_read_only() {
+            features |= features::RO;
+        }
+        features
+    }
+
+    /// Replace the storage backend
+    pub fn set_backend(&mut self, backend: Box<dyn BlockBackend>) {
+        self.backend = backend;
+    }
+
+    /// I/O statistics since the device was created
+    pub fn stats(&self) -> BlockStats {
+        self.stats
+    }
+
+    /// Serve the guest `vm_id`, whose memory holds the request queue
+    pub fn set_vm(&mut self, vm_id: VmId) {
+        self.vm_id = Some(vm_id);
+    }
+
+    /// Process one request
+    ///
+    /// `data` is the request's data buffer: the source for OUT, the
+    /// destination for IN and GET_ID. Returns the status byte to write
+    /// back to the driver.
+    pub fn process_request(&mut self, header: &VirtioBlkReqHeader, data: &mut [u8]) -> u8 {
+        let start = crate::utils::time::timestamp_ns();
+        let result = match header.type_ {
+            req_type::IN => self.backend.read_block(header.sector, data),
+            req_type::OUT => self.backend.write_block(header.sector, data),
+            req_type::FLUSH => self.backend.flush(),
+            req_type::GET_ID => {
+                let len = data.len().min(ID_BYTES);
+                data[..len].copy_from_slice(&self.id[..len]);
+                Ok(())
+            }
+            _ => return req_status::UNSUPP,
+        };
+
+        match result {
+            Ok(()) => {
+                let latency = crate::utils::time::timestamp_ns().saturating_sub(start);
+                self.stats.record(header.type_, data.len(), latency);
+                req_status::OK
+            }
+            Err(e) => {
+                crate::debug!("virtio-blk: request type {} sector {} failed: {:?}",
+                             header.type_, header.sector, e);
+                self.stats.errors += 1;
+                req_status::IOERR
+            }
+        }
+    }
+
+    /// Complete every request the driver made available in `mem`
+    ///
+    /// Returns the number of chains returned to the driver.
+    fn service_queue(&mut self, mem: &dyn GuestMemory) -> Result<usize> {
+        let mut completed = 0;
+        while let Some(chain) = self.queue.as_mut().ok_or(Error::NotInitialized)?.pop(mem)? {
+            let written = self.complete_chain(mem, &chain).unwrap_or_else(|e| {
+                crate::warn!("virtio-blk: malformed request chain {}: {:?}", chain.head, e);
+                0
+            });
+            self.queue.as_mut().ok_or(Error::NotInitialized)?.push_used(mem, chain.head, written)?;
+            completed += 1;
+        }
+        Ok(completed)
+    }
+
+    /// Run the request in `chain` and write its status byte
+    ///
+    /// Returns the number of bytes written to the chain. Data buffers
+    /// facing the wrong way, or larger than the disk, fail the request
+    /// with `IOERR`.
+    fn complete_chain(&mut self, mem: &dyn GuestMemory, chain: &DescriptorChain) -> Result<u32> {
+        let descriptors = &chain.descriptors;
+        let (Some(head), Some(status)) = (descriptors.first(), descriptors.last()) else {
+            return Err(Error::InvalidArgument);
+        };
+        if descriptors.len() < 2 || head.write || (head.len as usize) < REQ_HEADER_LEN
+            || !status.write || status.len == 0
+        {
+            return Err(Error::InvalidArgument);
+        }
+
+        let mut bytes = [0u8; REQ_HEADER_LEN];
+        mem.read(head.addr, &mut bytes)?;
+        let header = VirtioBlkReqHeader::from_bytes(&bytes);
+
+        let buffers = &descriptors[1..descriptors.len() - 1];
+        let to_guest = matches!(header.type_, req_type::IN | req_type::GET_ID);
+        let len: usize = buffers.iter().map(|desc| desc.len as usize).sum();
+        let disk_bytes = self.capacity().saturating_mul(SECTOR_SIZE as u64).max(ID_BYTES as u64);
+
+        let result = if buffers.iter().any(|desc| desc.write != to_guest) || len as u64 > disk_bytes {
+            req_status::IOERR
+        } else {
+            let mut data = vec![0u8; len];
+            let mut offset = 0;
+            if !to_guest {
+                for desc in buffers {
+                    mem.read(desc.addr, &mut data[offset..offset + desc.len as usize])?;
+                    offset += desc.len as usize;
+                }
+            }
+            let result = self.process_request(&header, &mut data);
+            if to_guest && result == req_status::OK {
+                for desc in buffers {
+                    mem.write(desc.addr, &data[offset..offset + desc.len as usize])?;
+                    offset += desc.len as usize;
+                }
+            }
+            result
+        };
+
+        mem.write(status.addr, &[result])?;
+        let written = if to_guest && result == req_status::OK { len + 1 } else { 1 };
+        Ok(written as u32)
+    }
+}
+
+impl VirtioBackend for VirtioBlock {
+    fn device_id(&self) -> u32 {
+        VIRTIO_ID_BLOCK
+    }
+
+    fn features(&self) -> u64 {
+        self.device_features()
+    }
+
+    fn num_queues(&self) -> usize {
+        1
+    }
+
+    fn queue_max(&self, _index: usize) -> u16 {
+        BLOCK_QUEUE_MAX
+    }
+
+    fn activate_queue(&mut self, index: usize, config: QueueConfig) -> Result<()> {
+        if index != REQUEST_QUEUE {
+            return Err(Error::InvalidArgument);
+        }
+        self.queue = Some(DeviceQueue::new(config));
+        Ok(())
+    }
+
+    fn deactivate_queue(&mut self, _index: usize) {
+        self.queue = None;
+    }
+
+    fn notify(&mut self, index: usize) -> u32 {
+        let Some(vm_id) = self.vm_id else {
+            return 0;
+        };
+        if index != REQUEST_QUEUE {
+            return 0;
+        }
+
+        match self.service_queue(&VmMemory { vm_id }) {
+            Ok(0) => 0,
+            Ok(_) => VIRTIO_MMIO_INT_VRING,
+            Err(e) => {
+                crate::warn!("virtio-blk: VM {}: bad request queue: {:?}", vm_id, e);
+                VIRTIO_MMIO_INT_VRING
+            }
+        }
+    }
+
+    /// Configuration space: the capacity in sectors
+    fn read_config(&self, offset: u64, size: u32) -> u64 {
+        virtio_mmio::read_config_bytes(&self.capacity().to_le_bytes(), offset, size)
+    }
+
+    fn reset(&mut self) {
+        self.queue = None;
+    }
+}
+
+/// Global block device, shared with the transport of the VM it serves
+static BLOCK_DEVICE: SpinLock<Option<Arc<SpinLock<VirtioBlock>>>> = SpinLock::new(None);
+
+/// Attach a storage backend to the block device
+pub fn attach_backend(backend: Box<dyn BlockBackend>) {
+    crate::info!("virtio-blk: attached backend with {} sectors{}",
+                 backend.capacity(),
+                 if backend.is_read_only() { " (read-only)" } else { "" });
+
+    let mut device = BLOCK_DEVICE.lock();
+    match device.as_ref() {
+        Some(device) => device.lock().set_backend(backend),
+        None => *device = Some(Arc::new(SpinLock::new(VirtioBlock::new(backend)))),
+    }
+}
+
+/// Process a request against the global block device
+pub fn process_request(header: &VirtioBlkReqHeader, data: &mut [u8]) -> Result<u8> {
+    BLOCK_DEVICE.lock()
+        .as_ref()
+        .map(|device| device.lock().process_request(header, data))
+        .ok_or(Error::NotInitialized)
+}
+
+/// I/O statistics of the global block device
+pub fn stats() -> Option<BlockStats> {
+    BLOCK_DEVICE.lock().as_ref().map(|device| device.lock().stats())
+}
+
+/// Emulator registry name of the block device of a VM, also the name of
+/// its interrupt route
+pub fn device_name(vm_id: VmId) -> String {
+    format!("virtio-blk.{}", vm_id)
+}
+
+/// Give a VM the global block device as a virtio-mmio device at `base`,
+/// interrupting on guest `irq`
+///
+/// The device serves one VM at a time: fails with `Error::ResourceBusy`
+/// while another VM has it, and with `Error::NotInitialized` before a
+/// backend is attached.
+pub fn attach(vm_id: VmId, base: u64, irq: u32) -> Result<()> {
+    let device = BLOCK_DEVICE.lock().clone().ok_or(Error::NotInitialized)?;
+    {
+        let mut device = device.lock();
+        if device.vm_id.is_some() {
+            return Err(Error::ResourceBusy);
+        }
+        device.set_vm(vm_id);
+    }
+
+    let name = device_name(vm_id);
+    let mut transport = VirtioMmioTransport::new(base, irq, device.clone());
+    transport.set_guest_irq(vm_id, &name);
+    if let Err(e) = virtio_mmio::register(vm_id, &name, transport) {
+        device.lock().vm_id = None;
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Take the global block device back from a VM, if it has it
+pub fn detach(vm_id: VmId) {
+    let Some(device) = BLOCK_DEVICE.lock().clone() else {
+        return;
+    };
+    if device.lock().vm_id != Some(vm_id) {
+        return;
+    }
+
+    crate::emulator::unregister_emulator(vm_id, &device_name(vm_id)).ok();
+    let mut device = device.lock();
+    device.vm_id = None;
+    VirtioBackend::reset(&mut *device);
+}
 
 pub fn init() -> Result<()> {
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(type_: u32, sector: u64) -> VirtioBlkReqHeader {
+        VirtioBlkReqHeader { type_, reserved: 0, sector }
+    }
+
+    #[test]
+    fn test_ram_backend_write_then_read() {
+        let mut device = VirtioBlock::new(Box::new(RamBlockBackend::new(8)));
+        assert_eq!(device.capacity(), 8);
+
+        let mut data = vec![0u8; 2 * SECTOR_SIZE];
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        let written = data.clone();
+        assert_eq!(device.process_request(&request(req_type::OUT, 3), &mut data), req_status::OK);
+
+        let mut read = vec![0u8; 2 * SECTOR_SIZE];
+        assert_eq!(device.process_request(&request(req_type::IN, 3), &mut read), req_status::OK);
+        assert_eq!(read, written);
+
+        // Out of range
+        assert_eq!(device.process_request(&request(req_type::IN, 7), &mut read), req_status::IOERR);
+    }
+
+    #[test]
+    fn test_read_only_backend_rejects_writes() {
+        let mut ram = RamBlockBackend::new(4);
+        ram.write_block(0, &[0xAA; SECTOR_SIZE]).unwrap();
+
+        let mut backend = ReadOnlyBackend::new(ram);
+        assert_eq!(backend.write_block(0, &[0x55; SECTOR_SIZE]), Err(Error::PermissionDenied));
+
+        let mut device = VirtioBlock::new(Box::new(backend));
+        assert_ne!(device.device_features() & features::RO, 0);
+
+        let mut data = vec![0x55u8; SECTOR_SIZE];
+        assert_eq!(device.process_request(&request(req_type::OUT, 0), &mut data), req_status::IOERR);
+        assert_eq!(device.process_request(&request(req_type::FLUSH, 0), &mut []), req_status::OK);
+
+        // Original contents untouched
+        assert_eq!(device.process_request(&request(req_type::IN, 0), &mut data), req_status::OK);
+        assert!(data.iter().all(|&b| b == 0xAA));
+    }
+
+    /// RAM disk whose reads take at least one timestamp tick
+    struct SlowRamBackend(RamBlockBackend);
+
+    impl BlockBackend for SlowRamBackend {
+        fn read_block(&mut self, block: u64, buf: &mut [u8]) -> Result<()> {
+            let start = crate::utils::time::timestamp_ns();
+            while crate::utils::time::timestamp_ns() == start {
+                core::hint::spin_loop();
+            }
+            self.0.read_block(block, buf)
+        }
+
+        fn write_block(&mut self, block: u64, buf: &[u8]) -> Result<()> {
+            self.0.write_block(block, buf)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            self.0.flush()
+        }
+
+        fn capacity(&self) -> u64 {
+            self.0.capacity()
+        }
+    }
+
+    #[test]
+    fn test_completed_read_updates_stats() {
+        let mut device = VirtioBlock::new(Box::new(SlowRamBackend(RamBlockBackend::new(8))));
+        assert_eq!(device.stats(), BlockStats::default());
+
+        let mut data = vec![0u8; 4 * SECTOR_SIZE];
+        assert_eq!(device.process_request(&request(req_type::IN, 2), &mut data), req_status::OK);
+
+        let stats = device.stats();
+        assert_eq!(stats.reads, 1);
+        assert_eq!(stats.bytes_read, 4 * SECTOR_SIZE as u64);
+        assert_eq!(stats.writes, 0);
+        assert_ne!(stats.latency_ewma_ns, 0);
+
+        // Failed requests count as errors, not transfers
+        assert_eq!(device.process_request(&request(req_type::IN, 7), &mut data), req_status::IOERR);
+        assert_eq!(device.stats().reads, 1);
+        assert_eq!(device.stats().errors, 1);
+    }
+
+    #[test]
+    fn test_requests_served_from_queue() {
+        use crate::emulators::virtio_mmio::queue::{FlatMemory, VIRTQ_DESC_F_NEXT, VIRTQ_DESC_F_WRITE};
+
+        let mut device = VirtioBlock::new(Box::new(RamBlockBackend::new(8)));
+        let (mem, queue) = FlatMemory::with_queue(0x10000, 8);
+        device.queue = Some(queue);
+        assert_eq!(device.read_config(0, 64), 8);
+        assert_eq!(device.read_config(4, 32), 0);
+
+        let write_header = |addr: u64, type_: u32, sector: u64| {
+            mem.write(addr, &type_.to_le_bytes()).unwrap();
+            mem.write(addr + 8, &sector.to_le_bytes()).unwrap();
+        };
+
+        // Write sector 3 from two buffers, then read it back into one
+        write_header(0x8000, req_type::OUT, 3);
+        mem.write(0x9000, &[0xAB; 256]).unwrap();
+        mem.write(0x9100, &[0xCD; 256]).unwrap();
+        mem.write_desc(0, 0x8000, 16, VIRTQ_DESC_F_NEXT, 1);
+        mem.write_desc(1, 0x9000, 256, VIRTQ_DESC_F_NEXT, 2);
+        mem.write_desc(2, 0x9100, 256, VIRTQ_DESC_F_NEXT, 3);
+        mem.write_desc(3, 0xA000, 1, VIRTQ_DESC_F_WRITE, 0);
+        mem.make_available(0, 0);
+        write_header(0x8100, req_type::IN, 3);
+        mem.write(0xA001, &[0xFF]).unwrap();
+        mem.write_desc(4, 0x8100, 16, VIRTQ_DESC_F_NEXT, 5);
+        mem.write_desc(5, 0xB000, 512, VIRTQ_DESC_F_WRITE | VIRTQ_DESC_F_NEXT, 6);
+        mem.write_desc(6, 0xA001, 1, VIRTQ_DESC_F_WRITE, 0);
+        mem.make_available(1, 4);
+
+        assert_eq!(device.service_queue(&mem), Ok(2));
+        assert_eq!(mem.read_u16(0x3002), Ok(2));
+        assert_eq!((mem.read_u32(0x3004), mem.read_u32(0x3008)), (Ok(0), Ok(1)));
+        assert_eq!((mem.read_u32(0x300C), mem.read_u32(0x3010)), (Ok(4), Ok(513)));
+        let mut status = [0xFF; 2];
+        mem.read(0xA000, &mut status).unwrap();
+        assert_eq!(status, [req_status::OK; 2]);
+        let mut data = vec![0u8; SECTOR_SIZE];
+        mem.read(0xB000, &mut data).unwrap();
+        assert_eq!(&data[..256], &[0xAB; 256]);
+        assert_eq!(&data[256..], &[0xCD; 256]);
+
+        // A read into a device-readable buffer fails without touching it
+        mem.write_desc(5, 0xB000, 512, VIRTQ_DESC_F_NEXT, 6);
+        mem.write(0xB000, &[0; 512]).unwrap();
+        mem.make_available(2, 4);
+        assert_eq!(device.service_queue(&mem), Ok(1));
+        assert_eq!(mem.read_u32(0x3018), Ok(1));
+        mem.read(0xA001, &mut status[..1]).unwrap();
+        assert_eq!(status[0], req_status::IOERR);
+        assert_eq!(mem.read_u32(0xB000), Ok(0));
+    }
+
+    #[test]
+    fn test_latency_average_moves_toward_new_samples() {
+        let mut stats = BlockStats::default();
+        stats.record(req_type::OUT, SECTOR_SIZE, 8_000);
+        assert_eq!(stats.latency_ewma_ns, 8_000);
+
+        stats.record(req_type::FLUSH, 0, 16_000);
+        assert_eq!(stats.latency_ewma_ns, 9_000);
+        assert_eq!((stats.writes, stats.flushes, stats.bytes_written), (1, 1, SECTOR_SIZE as u64));
+    }
+}
@@ -1,7 +1,1045 @@
-//! Empty stub for VirtIO gpu.rs
+//! VirtIO GPU device (2D)
+//!
+//! Implements the 2D subset of the control queue:
+//! - `RESOURCE_CREATE_2D` allocates a host-side pixel buffer
+//! - `RESOURCE_ATTACH_BACKING` records the guest pages behind it
+//! - `TRANSFER_TO_HOST_2D` copies a rectangle from guest pages to the host buffer
+//! - `SET_SCANOUT` binds a resource region to a display
+//! - `RESOURCE_FLUSH` pushes a rectangle of the host buffer to the display
+//!
+//! Displays are `FramebufferSink`s, so flushed pixels can go to a memory
+//! framebuffer, a host window, or a real display controller.
+//!
+//! The device is given to a VM over virtio-mmio: requests on the control
+//! queue (queue 0) are answered in the writable part of their chain, and
+//! cursor queue (queue 1) requests are completed without effect.
 
 use crate::{Result, Error};
+use crate::core::sync::SpinLock;
+use crate::core::vmm::VmId;
+use crate::emulators::virtio_mmio::{self, QueueConfig, VirtioBackend, VirtioMmioTransport, VIRTIO_MMIO_INT_VRING};
+use crate::emulators::virtio_mmio::queue::{DescriptorChain, DeviceQueue, GuestMemory as QueueMemory, VmMemory};
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
 
+/// VirtIO device ID of a GPU
+pub const VIRTIO_ID_GPU: u32 = 16;
+
+/// Queue commands arrive on
+pub const CONTROL_QUEUE: usize = 0;
+
+/// Queue cursor updates arrive on
+pub const CURSOR_QUEUE: usize = 1;
+
+/// Largest queue size offered to the driver
+const GPU_QUEUE_MAX: u16 = 64;
+
+/// Largest control request read from the driver
+const MAX_REQUEST_BYTES: usize = 1 << 20;
+
+/// Control header flag: the driver wants the fence ID echoed
+const FLAG_FENCE: u32 = 1 << 0;
+
+/// Offset of `num_scanouts` in the device configuration space
+const CONFIG_NUM_SCANOUTS: usize = 8;
+
+/// Bytes of the device configuration space
+const CONFIG_SIZE: usize = 16;
+
+/// Maximum number of scanouts (displays)
+pub const MAX_SCANOUTS: usize = 16;
+
+/// Bytes per pixel of every supported format
+pub const BYTES_PER_PIXEL: usize = 4;
+
+/// Largest host pixel buffer a single resource may use
+pub const MAX_RESOURCE_BYTES: usize = 64 << 20;
+
+/// Default host memory all resources of one device may use together
+pub const MAX_TOTAL_RESOURCE_BYTES: usize = 256 << 20;
+
+/// Control queue command types
+pub mod cmd_type {
+    /// Get display information
+    pub const GET_DISPLAY_INFO: u32 = 0x0100;
+    /// Create a 2D resource
+    pub const RESOURCE_CREATE_2D: u32 = 0x0101;
+    /// Destroy a resource
+    pub const RESOURCE_UNREF: u32 = 0x0102;
+    /// Bind a resource to a scanout
+    pub const SET_SCANOUT: u32 = 0x0103;
+    /// Push a resource rectangle to its scanouts
+    pub const RESOURCE_FLUSH: u32 = 0x0104;
+    /// Copy guest backing into the host resource
+    pub const TRANSFER_TO_HOST_2D: u32 = 0x0105;
+    /// Attach guest pages as resource backing
+    pub const RESOURCE_ATTACH_BACKING: u32 = 0x0106;
+    /// Detach resource backing
+    pub const RESOURCE_DETACH_BACKING: u32 = 0x0107;
+}
+
+/// Control queue response types
+pub mod resp_type {
+    /// Success, no data
+    pub const OK_NODATA: u32 = 0x1100;
+    /// Success, display info follows
+    pub const OK_DISPLAY_INFO: u32 = 0x1101;
+    /// Unspecified error
+    pub const ERR_UNSPEC: u32 = 0x1200;
+    /// Out of memory
+    pub const ERR_OUT_OF_MEMORY: u32 = 0x1201;
+    /// Invalid scanout ID
+    pub const ERR_INVALID_SCANOUT_ID: u32 = 0x1202;
+    /// Invalid resource ID
+    pub const ERR_INVALID_RESOURCE_ID: u32 = 0x1203;
+    /// Invalid parameter
+    pub const ERR_INVALID_PARAMETER: u32 = 0x1205;
+}
+
+/// Pixel formats (all 32 bits per pixel)
+pub mod format {
+    pub const B8G8R8A8_UNORM: u32 = 1;
+    pub const B8G8R8X8_UNORM: u32 = 2;
+    pub const A8R8G8B8_UNORM: u32 = 3;
+    pub const X8R8G8B8_UNORM: u32 = 4;
+    pub const R8G8B8A8_UNORM: u32 = 67;
+    pub const X8B8G8R8_UNORM: u32 = 68;
+    pub const A8B8G8R8_UNORM: u32 = 121;
+    pub const R8G8B8X8_UNORM: u32 = 134;
+
+    /// Check whether a format is supported
+    pub fn is_supported(format: u32) -> bool {
+        matches!(format, B8G8R8A8_UNORM | B8G8R8X8_UNORM | A8R8G8B8_UNORM | X8R8G8B8_UNORM
+                 | R8G8B8A8_UNORM | X8B8G8R8_UNORM | A8B8G8R8_UNORM | R8G8B8X8_UNORM)
+    }
+}
+
+/// Size of the control header preceding every command
+pub const CTRL_HDR_SIZE: usize = 24;
+
+/// Rectangle in pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    /// Create a rectangle
+    pub const fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self { x, y, width, height }
+    }
+
+    /// Check whether the rectangle covers no pixels
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    /// Check whether the rectangle lies within a `width` x `height` area
+    pub fn fits_in(&self, width: u32, height: u32) -> bool {
+        self.x.checked_add(self.width).map_or(false, |end| end <= width)
+            && self.y.checked_add(self.height).map_or(false, |end| end <= height)
+    }
+
+    /// Intersection of two rectangles
+    pub fn intersect(&self, other: &Rect) -> Rect {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = (self.x + self.width).min(other.x + other.width);
+        let bottom = (self.y + self.height).min(other.y + other.height);
+        Rect::new(x, y, right.saturating_sub(x), bottom.saturating_sub(y))
+    }
+}
+
+/// One guest memory region of a resource's backing store
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemEntry {
+    /// Guest physical address
+    pub addr: u64,
+    /// Length in bytes
+    pub length: u32,
+}
+
+/// Decoded control queue command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GpuCommand {
+    GetDisplayInfo,
+    ResourceCreate2d { resource_id: u32, format: u32, width: u32, height: u32 },
+    ResourceUnref { resource_id: u32 },
+    SetScanout { rect: Rect, scanout_id: u32, resource_id: u32 },
+    ResourceFlush { rect: Rect, resource_id: u32 },
+    TransferToHost2d { rect: Rect, offset: u64, resource_id: u32 },
+    ResourceAttachBacking { resource_id: u32, entries: Vec<MemEntry> },
+    ResourceDetachBacking { resource_id: u32 },
+}
+
+/// Little-endian field reader over a command buffer
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn u32(&mut self) -> Result<u32> {
+        let bytes = self.buf.get(self.pos..self.pos + 4).ok_or(Error::InvalidArgument)?;
+        self.pos += 4;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        let bytes = self.buf.get(self.pos..self.pos + 8).ok_or(Error::InvalidArgument)?;
+        self.pos += 8;
+        Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn rect(&mut self) -> Result<Rect> {
+        Ok(Rect::new(self.u32()?, self.u32()?, self.u32()?, self.u32()?))
+    }
+}
+
+impl GpuCommand {
+    /// Decode a command from the driver-readable part of a request
+    pub fn parse(buf: &[u8]) -> Result<Self> {
+        let mut r = Reader { buf, pos: 0 };
+        let type_ = r.u32()?;
+        // flags, fence_id, ctx_id, padding
+        r.pos = CTRL_HDR_SIZE;
+
+        let cmd = match type_ {
+            cmd_type::GET_DISPLAY_INFO => GpuCommand::GetDisplayInfo,
+            cmd_type::RESOURCE_CREATE_2D => GpuCommand::ResourceCreate2d {
+                resource_id: r.u32()?,
+                format: r.u32()?,
+                width: r.u32()?,
+                height: r.u32()?,
+            },
+            cmd_type::RESOURCE_UNREF => GpuCommand::ResourceUnref { resource_id: r.u32()? },
+            cmd_type::SET_SCANOUT => GpuCommand::SetScanout {
+                rect: r.rect()?,
+                scanout_id: r.u32()?,
+                resource_id: r.u32()?,
+            },
+            cmd_type::RESOURCE_FLUSH => GpuCommand::ResourceFlush {
+                rect: r.rect()?,
+                resource_id: r.u32()?,
+            },
+            cmd_type::TRANSFER_TO_HOST_2D => GpuCommand::TransferToHost2d {
+                rect: r.rect()?,
+                offset: r.u64()?,
+                resource_id: r.u32()?,
+            },
+            cmd_type::RESOURCE_ATTACH_BACKING => {
+                let resource_id = r.u32()?;
+                let nr_entries = r.u32()?;
+                let mut entries = Vec::new();
+                for _ in 0..nr_entries {
+                    let addr = r.u64()?;
+                    let length = r.u32()?;
+                    let _padding = r.u32()?;
+                    entries.push(MemEntry { addr, length });
+                }
+                GpuCommand::ResourceAttachBacking { resource_id, entries }
+            }
+            cmd_type::RESOURCE_DETACH_BACKING => GpuCommand::ResourceDetachBacking { resource_id: r.u32()? },
+            _ => return Err(Error::NotImplemented),
+        };
+
+        Ok(cmd)
+    }
+}
+
+/// Control queue response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GpuResponse {
+    /// Success without payload
+    OkNoData,
+    /// Display info: rectangle and enabled flag per scanout
+    OkDisplayInfo(Vec<(Rect, bool)>),
+    /// Error response type
+    Err(u32),
+}
+
+impl GpuResponse {
+    /// Response type written to the response header
+    pub fn resp_type(&self) -> u32 {
+        match self {
+            GpuResponse::OkNoData => resp_type::OK_NODATA,
+            GpuResponse::OkDisplayInfo(_) => resp_type::OK_DISPLAY_INFO,
+            GpuResponse::Err(code) => *code,
+        }
+    }
+
+    /// Encode the response to a request whose header is `request`
+    ///
+    /// A fenced request has its fence echoed. Display info always lists
+    /// `MAX_SCANOUTS` modes, unused ones zeroed.
+    pub fn to_bytes(&self, request: &[u8]) -> Vec<u8> {
+        let mut r = Reader { buf: request, pos: 4 };
+        let flags = r.u32().unwrap_or(0) & FLAG_FENCE;
+        let fence_id = if flags != 0 { r.u64().unwrap_or(0) } else { 0 };
+
+        let mut buf = vec![0u8; CTRL_HDR_SIZE];
+        buf[0..4].copy_from_slice(&self.resp_type().to_le_bytes());
+        buf[4..8].copy_from_slice(&flags.to_le_bytes());
+        buf[8..16].copy_from_slice(&fence_id.to_le_bytes());
+
+        if let GpuResponse::OkDisplayInfo(modes) = self {
+            for i in 0..MAX_SCANOUTS {
+                let (rect, enabled) = modes.get(i).copied().unwrap_or_default();
+                for word in [rect.x, rect.y, rect.width, rect.height, enabled as u32, 0] {
+                    buf.extend_from_slice(&word.to_le_bytes());
+                }
+            }
+        }
+        buf
+    }
+}
+
+/// Access to guest physical memory for resource backing
+pub trait GuestMemory {
+    /// Read `buf.len()` bytes at guest physical address `addr`
+    fn read(&self, addr: u64, buf: &mut [u8]) -> Result<()>;
+}
+
+/// Resource backing read through the memory the virtqueues live in
+struct QueueBacking<'a>(&'a dyn QueueMemory);
+
+impl GuestMemory for QueueBacking<'_> {
+    fn read(&self, addr: u64, buf: &mut [u8]) -> Result<()> {
+        self.0.read(addr, buf)
+    }
+}
+
+/// Display receiving flushed pixels
+pub trait FramebufferSink: Send {
+    /// Display size in pixels
+    fn size(&self) -> (u32, u32);
+
+    /// Copy `rect.width` x `rect.height` pixels to `rect` on the display
+    ///
+    /// `src` starts at the first source pixel; rows are `src_stride`
+    /// bytes apart.
+    fn blit(&mut self, rect: &Rect, src: &[u8], src_stride: usize);
+}
+
+/// Framebuffer held in memory
+pub struct MemoryFramebuffer {
+    width: u32,
+    height: u32,
+    /// Pixels, 32 bits each, row-major
+    pixels: Vec<u8>,
+}
+
+impl MemoryFramebuffer {
+    /// Create a black framebuffer
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; width as usize * height as usize * BYTES_PER_PIXEL],
+        }
+    }
+
+    /// Read one pixel
+    pub fn pixel(&self, x: u32, y: u32) -> u32 {
+        let offset = (y as usize * self.width as usize + x as usize) * BYTES_PER_PIXEL;
+        u32::from_le_bytes(self.pixels[offset..offset + BYTES_PER_PIXEL].try_into().unwrap())
+    }
+
+    /// Raw pixel bytes
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.pixels
+    }
+}
+
+impl FramebufferSink for MemoryFramebuffer {
+    fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn blit(&mut self, rect: &Rect, src: &[u8], src_stride: usize) {
+        let rect = rect.intersect(&Rect::new(0, 0, self.width, self.height));
+        let row_bytes = rect.width as usize * BYTES_PER_PIXEL;
+        let dst_stride = self.width as usize * BYTES_PER_PIXEL;
+
+        for row in 0..rect.height as usize {
+            let src_off = row * src_stride;
+            let dst_off = (rect.y as usize + row) * dst_stride + rect.x as usize * BYTES_PER_PIXEL;
+            self.pixels[dst_off..dst_off + row_bytes].copy_from_slice(&src[src_off..src_off + row_bytes]);
+        }
+    }
+}
+
+/// Host-side 2D resource
+struct Resource2d {
+    format: u32,
+    width: u32,
+    height: u32,
+    /// Host pixel buffer
+    data: Vec<u8>,
+    /// Guest backing pages
+    backing: Vec<MemEntry>,
+}
+
+impl Resource2d {
+    fn stride(&self) -> usize {
+        self.width as usize * BYTES_PER_PIXEL
+    }
+
+    /// Read from the backing store as one linear buffer
+    fn read_backing(&self, mem: &dyn GuestMemory, mut offset: u64, buf: &mut [u8]) -> Result<()> {
+        let mut done = 0;
+        for entry in &self.backing {
+            if done == buf.len() {
+                break;
+            }
+            if offset >= entry.length as u64 {
+                offset -= entry.length as u64;
+                continue;
+            }
+            let take = ((entry.length as u64 - offset) as usize).min(buf.len() - done);
+            mem.read(entry.addr + offset, &mut buf[done..done + take])?;
+            done += take;
+            offset = 0;
+        }
+
+        if done == buf.len() { Ok(()) } else { Err(Error::InvalidArgument) }
+    }
+}
+
+/// Scanout binding
+#[derive(Debug, Clone, Copy)]
+struct Scanout {
+    resource_id: u32,
+    /// Region of the resource shown on the display
+    rect: Rect,
+}
+
+/// VirtIO GPU device
+pub struct VirtioGpu {
+    /// Resources by ID
+    resources: Vec<(u32, Resource2d)>,
+    /// Displays
+    displays: Vec<Box<dyn FramebufferSink>>,
+    /// Current scanout bindings, indexed like `displays`
+    scanouts: Vec<Option<Scanout>>,
+    /// Host memory all resource pixel buffers may use
+    memory_limit: usize,
+    /// VM whose memory the virtqueues live in
+    vm_id: Option<VmId>,
+    /// Control and cursor virtqueues, once live
+    queues: [Option<DeviceQueue>; 2],
+}
+
+impl VirtioGpu {
+    /// Create a GPU without displays
+    pub fn new() -> Self {
+        Self::with_memory_limit(MAX_TOTAL_RESOURCE_BYTES)
+    }
+
+    /// Create a GPU whose resources may use at most `memory_limit` bytes
+    pub fn with_memory_limit(memory_limit: usize) -> Self {
+        Self {
+            resources: Vec::new(),
+            displays: Vec::new(),
+            scanouts: Vec::new(),
+            memory_limit,
+            vm_id: None,
+            queues: [None; 2],
+        }
+    }
+
+    /// Add a display, returning its scanout ID
+    pub fn add_display(&mut self, display: Box<dyn FramebufferSink>) -> Result<u32> {
+        if self.displays.len() >= MAX_SCANOUTS {
+            return Err(Error::ResourceUnavailable);
+        }
+        self.displays.push(display);
+        self.scanouts.push(None);
+        Ok(self.displays.len() as u32 - 1)
+    }
+
+    /// Number of scanouts, as reported in the device configuration
+    pub fn num_scanouts(&self) -> u32 {
+        self.displays.len() as u32
+    }
+
+    fn resource(&mut self, id: u32) -> core::result::Result<&mut Resource2d, u32> {
+        self.resources.iter_mut()
+            .find(|(rid, _)| *rid == id)
+            .map(|(_, res)| res)
+            .ok_or(resp_type::ERR_INVALID_RESOURCE_ID)
+    }
+
+    /// Process one control queue command
+    pub fn process_command(&mut self, cmd: &GpuCommand, mem: &dyn GuestMemory) -> GpuResponse {
+        let result = match cmd {
+            GpuCommand::GetDisplayInfo => {
+                let info = self.displays.iter()
+                    .map(|d| {
+                        let (width, height) = d.size();
+                        (Rect::new(0, 0, width, height), true)
+                    })
+                    .collect();
+                return GpuResponse::OkDisplayInfo(info);
+            }
+            GpuCommand::ResourceCreate2d { resource_id, format, width, height } => {
+                self.create_2d(*resource_id, *format, *width, *height)
+            }
+            GpuCommand::ResourceUnref { resource_id } => self.unref(*resource_id),
+            GpuCommand::ResourceAttachBacking { resource_id, entries } => {
+                self.resource(*resource_id).map(|res| res.backing = entries.clone())
+            }
+            GpuCommand::ResourceDetachBacking { resource_id } => {
+                self.resource(*resource_id).map(|res| res.backing.clear())
+            }
+            GpuCommand::SetScanout { rect, scanout_id, resource_id } => {
+                self.set_scanout(*scanout_id, *resource_id, *rect)
+            }
+            GpuCommand::TransferToHost2d { rect, offset, resource_id } => {
+                self.transfer_to_host_2d(*resource_id, *rect, *offset, mem)
+            }
+            GpuCommand::ResourceFlush { rect, resource_id } => self.flush(*resource_id, *rect),
+        };
+
+        match result {
+            Ok(()) => GpuResponse::OkNoData,
+            Err(code) => {
+                crate::debug!("virtio-gpu: {:?} failed with {:#x}", cmd, code);
+                GpuResponse::Err(code)
+            }
+        }
+    }
+
+    fn create_2d(&mut self, id: u32, format: u32, width: u32, height: u32) -> core::result::Result<(), u32> {
+        if id == 0 || self.resources.iter().any(|(rid, _)| *rid == id) {
+            return Err(resp_type::ERR_INVALID_RESOURCE_ID);
+        }
+        if !format::is_supported(format) || width == 0 || height == 0 {
+            return Err(resp_type::ERR_INVALID_PARAMETER);
+        }
+
+        // Guest-chosen dimensions: bound the host allocation before making it
+        let size = (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|px| px.checked_mul(BYTES_PER_PIXEL))
+            .filter(|&size| size <= MAX_RESOURCE_BYTES)
+            .ok_or(resp_type::ERR_OUT_OF_MEMORY)?;
+        if self.resource_bytes() + size > self.memory_limit {
+            return Err(resp_type::ERR_OUT_OF_MEMORY);
+        }
+
+        let mut data = Vec::new();
+        data.try_reserve_exact(size).map_err(|_| resp_type::ERR_OUT_OF_MEMORY)?;
+        data.resize(size, 0);
+
+        self.resources.push((id, Resource2d {
+            format,
+            width,
+            height,
+            data,
+            backing: Vec::new(),
+        }));
+        Ok(())
+    }
+
+    /// Host memory used by all resource pixel buffers
+    fn resource_bytes(&self) -> usize {
+        self.resources.iter().map(|(_, res)| res.data.len()).sum()
+    }
+
+    fn unref(&mut self, id: u32) -> core::result::Result<(), u32> {
+        let index = self.resources.iter()
+            .position(|(rid, _)| *rid == id)
+            .ok_or(resp_type::ERR_INVALID_RESOURCE_ID)?;
+        self.resources.remove(index);
+
+        // A destroyed resource can no longer be displayed
+        for scanout in self.scanouts.iter_mut() {
+            if scanout.map_or(false, |s| s.resource_id == id) {
+                *scanout = None;
+            }
+        }
+        Ok(())
+    }
+
+    fn set_scanout(&mut self, scanout_id: u32, resource_id: u32, rect: Rect) -> core::result::Result<(), u32> {
+        let index = scanout_id as usize;
+        if index >= self.scanouts.len() {
+            return Err(resp_type::ERR_INVALID_SCANOUT_ID);
+        }
+
+        // Resource 0 disables the scanout
+        if resource_id == 0 {
+            self.scanouts[index] = None;
+            return Ok(());
+        }
+
+        let res = self.resource(resource_id)?;
+        if rect.is_empty() || !rect.fits_in(res.width, res.height) {
+            return Err(resp_type::ERR_INVALID_PARAMETER);
+        }
+
+        self.scanouts[index] = Some(Scanout { resource_id, rect });
+        Ok(())
+    }
+
+    fn transfer_to_host_2d(&mut self, id: u32, rect: Rect, offset: u64,
+                           mem: &dyn GuestMemory) -> core::result::Result<(), u32> {
+        let res = self.resource(id)?;
+        if !rect.fits_in(res.width, res.height) {
+            return Err(resp_type::ERR_INVALID_PARAMETER);
+        }
+        if res.backing.is_empty() {
+            return Err(resp_type::ERR_UNSPEC);
+        }
+
+        let stride = res.stride();
+        let row_bytes = rect.width as usize * BYTES_PER_PIXEL;
+        let mut row = vec![0u8; row_bytes];
+        for h in 0..rect.height as usize {
+            // The guest buffer has the resource's layout, starting at `offset`
+            let src = offset + (stride * h) as u64;
+            res.read_backing(mem, src, &mut row).map_err(|_| resp_type::ERR_INVALID_PARAMETER)?;
+
+            let dst = (rect.y as usize + h) * stride + rect.x as usize * BYTES_PER_PIXEL;
+            res.data[dst..dst + row_bytes].copy_from_slice(&row);
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self, id: u32, rect: Rect) -> core::result::Result<(), u32> {
+        let (_, res) = self.resources.iter()
+            .find(|(rid, _)| *rid == id)
+            .ok_or(resp_type::ERR_INVALID_RESOURCE_ID)?;
+        if !rect.fits_in(res.width, res.height) {
+            return Err(resp_type::ERR_INVALID_PARAMETER);
+        }
+
+        let stride = res.stride();
+        for (scanout, display) in self.scanouts.iter().zip(self.displays.iter_mut()) {
+            let scanout = match scanout {
+                Some(s) if s.resource_id == id => s,
+                _ => continue,
+            };
+
+            let visible = rect.intersect(&scanout.rect);
+            if visible.is_empty() {
+                continue;
+            }
+
+            let src = visible.y as usize * stride + visible.x as usize * BYTES_PER_PIXEL;
+            let dst = Rect::new(visible.x - scanout.rect.x, visible.y - scanout.rect.y,
+                                visible.width, visible.height);
+            display.blit(&dst, &res.data[src..], stride);
+        }
+        Ok(())
+    }
+
+    /// Pixel format of a resource
+    pub fn resource_format(&self, id: u32) -> Option<u32> {
+        self.resources.iter().find(|(rid, _)| *rid == id).map(|(_, res)| res.format)
+    }
+
+    /// Answer one control queue request
+    fn serve_request(&mut self, mem: &dyn QueueMemory, chain: &DescriptorChain) -> Result<u32> {
+        let len: usize = chain.readable().map(|desc| desc.len as usize).sum();
+        if len < CTRL_HDR_SIZE || len > MAX_REQUEST_BYTES {
+            return Err(Error::InvalidArgument);
+        }
+
+        let mut request = vec![0u8; len];
+        let mut offset = 0;
+        for desc in chain.readable() {
+            mem.read(desc.addr, &mut request[offset..offset + desc.len as usize])?;
+            offset += desc.len as usize;
+        }
+
+        let response = match GpuCommand::parse(&request) {
+            Ok(cmd) => self.process_command(&cmd, &QueueBacking(mem)),
+            Err(Error::NotImplemented) => GpuResponse::Err(resp_type::ERR_UNSPEC),
+            Err(_) => GpuResponse::Err(resp_type::ERR_INVALID_PARAMETER),
+        };
+
+        // Whatever part of the response fits the driver's buffers
+        let bytes = response.to_bytes(&request);
+        let mut rest = &bytes[..];
+        let mut written = 0;
+        for desc in chain.writable() {
+            let (head, tail) = rest.split_at(rest.len().min(desc.len as usize));
+            mem.write(desc.addr, head)?;
+            written += head.len();
+            rest = tail;
+        }
+        Ok(written as u32)
+    }
+
+    /// Serve every request the driver made available on queue `index` of
+    /// `mem`, returning the number completed
+    fn service_queue(&mut self, mem: &dyn QueueMemory, index: usize) -> Result<usize> {
+        let Some(mut queue) = self.queues.get(index).copied().flatten() else {
+            return Ok(0);
+        };
+
+        let mut completed = 0;
+        let result = loop {
+            let chain = match queue.pop(mem) {
+                Ok(Some(chain)) => chain,
+                Ok(None) => break Ok(completed),
+                Err(e) => break Err(e),
+            };
+            // Cursor updates have no effect: there is no hardware cursor
+            let len = if index == CONTROL_QUEUE {
+                self.serve_request(mem, &chain).unwrap_or_else(|e| {
+                    crate::warn!("virtio-gpu: bad control request: {:?}", e);
+                    0
+                })
+            } else {
+                0
+            };
+            if let Err(e) = queue.push_used(mem, chain.head, len) {
+                break Err(e);
+            }
+            completed += 1;
+        };
+        self.queues[index] = Some(queue);
+        result
+    }
+}
+
+impl VirtioBackend for VirtioGpu {
+    fn device_id(&self) -> u32 {
+        VIRTIO_ID_GPU
+    }
+
+    fn features(&self) -> u64 {
+        0
+    }
+
+    fn num_queues(&self) -> usize {
+        self.queues.len()
+    }
+
+    fn queue_max(&self, _index: usize) -> u16 {
+        GPU_QUEUE_MAX
+    }
+
+    fn activate_queue(&mut self, index: usize, config: QueueConfig) -> Result<()> {
+        let queue = self.queues.get_mut(index).ok_or(Error::InvalidArgument)?;
+        *queue = Some(DeviceQueue::new(config));
+        Ok(())
+    }
+
+    fn deactivate_queue(&mut self, index: usize) {
+        if let Some(queue) = self.queues.get_mut(index) {
+            *queue = None;
+        }
+    }
+
+    fn notify(&mut self, index: usize) -> u32 {
+        let Some(vm_id) = self.vm_id else {
+            return 0;
+        };
+
+        match self.service_queue(&VmMemory { vm_id }, index) {
+            Ok(0) => 0,
+            Ok(_) => VIRTIO_MMIO_INT_VRING,
+            Err(e) => {
+                crate::warn!("virtio-gpu: VM {}: bad queue {}: {:?}", vm_id, index, e);
+                VIRTIO_MMIO_INT_VRING
+            }
+        }
+    }
+
+    /// `events_read`, `events_clear` and `num_capsets` read as zero: no
+    /// display events are raised and 3D is not offered
+    fn read_config(&self, offset: u64, size: u32) -> u64 {
+        let mut config = [0u8; CONFIG_SIZE];
+        config[CONFIG_NUM_SCANOUTS..CONFIG_NUM_SCANOUTS + 4].copy_from_slice(&self.num_scanouts().to_le_bytes());
+        virtio_mmio::read_config_bytes(&config, offset, size)
+    }
+
+    /// Resources and scanout bindings die with the driver
+    fn reset(&mut self) {
+        self.queues = [None; 2];
+        self.resources.clear();
+        self.scanouts.fill(None);
+    }
+}
+
+/// Global GPU device, shared with the transport of the VM it serves
+static GPU_DEVICE: SpinLock<Option<Arc<SpinLock<VirtioGpu>>>> = SpinLock::new(None);
+
+/// The global GPU device, created on first use
+fn gpu_device() -> Arc<SpinLock<VirtioGpu>> {
+    GPU_DEVICE.lock()
+        .get_or_insert_with(|| Arc::new(SpinLock::new(VirtioGpu::new())))
+        .clone()
+}
+
+/// Attach a display to the GPU device
+pub fn attach_display(display: Box<dyn FramebufferSink>) -> Result<u32> {
+    let (width, height) = display.size();
+    let scanout = gpu_device().lock().add_display(display)?;
+    crate::info!("virtio-gpu: scanout {} is {}x{}", scanout, width, height);
+    Ok(scanout)
+}
+
+/// Emulator registry name of the GPU of a VM, also the name of its
+/// interrupt route
+pub fn device_name(vm_id: VmId) -> String {
+    format!("virtio-gpu.{}", vm_id)
+}
+
+/// Give a VM the global GPU device as a virtio-mmio device at `base`,
+/// interrupting on guest `irq`
+///
+/// The device serves one VM at a time: fails with `Error::ResourceBusy`
+/// while another VM has it.
+pub fn attach(vm_id: VmId, base: u64, irq: u32) -> Result<()> {
+    let device = GPU_DEVICE.lock().clone().ok_or(Error::NotInitialized)?;
+    {
+        let mut device = device.lock();
+        if device.vm_id.is_some() {
+            return Err(Error::ResourceBusy);
+        }
+        device.vm_id = Some(vm_id);
+    }
+
+    let name = device_name(vm_id);
+    let mut transport = VirtioMmioTransport::new(base, irq, device.clone());
+    transport.set_guest_irq(vm_id, &name);
+    if let Err(e) = virtio_mmio::register(vm_id, &name, transport) {
+        device.lock().vm_id = None;
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Take the global GPU device back from a VM, if it has it
+pub fn detach(vm_id: VmId) {
+    let Some(device) = GPU_DEVICE.lock().clone() else {
+        return;
+    };
+    if device.lock().vm_id != Some(vm_id) {
+        return;
+    }
+
+    crate::emulator::unregister_emulator(vm_id, &device_name(vm_id)).ok();
+    let mut device = device.lock();
+    device.vm_id = None;
+    VirtioBackend::reset(&mut *device);
+}
+
+/// Process a control queue request against the global GPU device
+pub fn process_command(request: &[u8], mem: &dyn GuestMemory) -> Result<GpuResponse> {
+    let cmd = match GpuCommand::parse(request) {
+        Ok(cmd) => cmd,
+        Err(Error::NotImplemented) => return Ok(GpuResponse::Err(resp_type::ERR_UNSPEC)),
+        Err(_) => return Ok(GpuResponse::Err(resp_type::ERR_INVALID_PARAMETER)),
+    };
+
+    let device = GPU_DEVICE.lock().clone().ok_or(Error::NotInitialized)?;
+    let response = device.lock().process_command(&cmd, mem);
+    Ok(response)
+}
+
+/// Create the GPU device so control queue requests can be processed
+///
+/// Displays attached before or after this call are kept.
 pub fn init() -> Result<()> {
+    gpu_device();
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::sync::Arc;
+
+    /// Guest memory starting at a fixed guest physical address
+    struct TestMemory {
+        base: u64,
+        bytes: Vec<u8>,
+    }
+
+    impl GuestMemory for TestMemory {
+        fn read(&self, addr: u64, buf: &mut [u8]) -> Result<()> {
+            let start = addr.checked_sub(self.base).ok_or(Error::InvalidArgument)? as usize;
+            let src = self.bytes.get(start..start + buf.len()).ok_or(Error::InvalidArgument)?;
+            buf.copy_from_slice(src);
+            Ok(())
+        }
+    }
+
+    /// Framebuffer shared with the test after being handed to the device
+    struct SharedFramebuffer(Arc<SpinLock<MemoryFramebuffer>>);
+
+    impl FramebufferSink for SharedFramebuffer {
+        fn size(&self) -> (u32, u32) {
+            self.0.lock().size()
+        }
+
+        fn blit(&mut self, rect: &Rect, src: &[u8], src_stride: usize) {
+            self.0.lock().blit(rect, src, src_stride)
+        }
+    }
+
+    /// 8x4 guest image whose pixel (x, y) is `0xFF00_0000 | y << 8 | x`,
+    /// split across two non-contiguous guest regions
+    fn guest_image() -> (TestMemory, Vec<MemEntry>) {
+        let mut image = Vec::new();
+        for y in 0..4u32 {
+            for x in 0..8u32 {
+                image.extend_from_slice(&(0xFF00_0000 | y << 8 | x).to_le_bytes());
+            }
+        }
+        let mut bytes = vec![0u8; 0x200];
+        bytes[..64].copy_from_slice(&image[..64]);
+        bytes[0x100..0x140].copy_from_slice(&image[64..]);
+
+        let base = 0x8000_0000;
+        let entries = vec![
+            MemEntry { addr: base, length: 64 },
+            MemEntry { addr: base + 0x100, length: 64 },
+        ];
+        (TestMemory { base, bytes }, entries)
+    }
+
+    #[test]
+    fn test_flush_transfers_pixels_to_scanout() {
+        let (mem, entries) = guest_image();
+        let fb = Arc::new(SpinLock::new(MemoryFramebuffer::new(4, 4)));
+
+        let mut gpu = VirtioGpu::new();
+        let scanout_id = gpu.add_display(Box::new(SharedFramebuffer(fb.clone()))).unwrap();
+
+        let commands = [
+            GpuCommand::ResourceCreate2d { resource_id: 1, format: format::B8G8R8X8_UNORM, width: 8, height: 4 },
+            GpuCommand::ResourceAttachBacking { resource_id: 1, entries },
+            // Show the right half of the resource
+            GpuCommand::SetScanout { rect: Rect::new(4, 0, 4, 4), scanout_id, resource_id: 1 },
+            GpuCommand::TransferToHost2d { rect: Rect::new(0, 0, 8, 4), offset: 0, resource_id: 1 },
+            // Only rows 1..3 of columns 5..8 are pushed
+            GpuCommand::ResourceFlush { rect: Rect::new(5, 1, 3, 2), resource_id: 1 },
+        ];
+        for cmd in &commands {
+            assert_eq!(gpu.process_command(cmd, &mem), GpuResponse::OkNoData, "{:?}", cmd);
+        }
+
+        let fb = fb.lock();
+        assert_eq!(fb.pixel(1, 1), 0xFF00_0105);
+        assert_eq!(fb.pixel(3, 2), 0xFF00_0207);
+        // Outside the flushed rectangle nothing was pushed
+        assert_eq!(fb.pixel(0, 1), 0);
+        assert_eq!(fb.pixel(1, 0), 0);
+        assert_eq!(fb.pixel(1, 3), 0);
+    }
+
+    #[test]
+    fn test_invalid_commands_rejected() {
+        let (mem, _) = guest_image();
+        let mut gpu = VirtioGpu::new();
+        gpu.add_display(Box::new(MemoryFramebuffer::new(4, 4))).unwrap();
+
+        let create = GpuCommand::ResourceCreate2d { resource_id: 1, format: 0xDEAD, width: 4, height: 4 };
+        assert_eq!(gpu.process_command(&create, &mem), GpuResponse::Err(resp_type::ERR_INVALID_PARAMETER));
+
+        let flush = GpuCommand::ResourceFlush { rect: Rect::new(0, 0, 1, 1), resource_id: 9 };
+        assert_eq!(gpu.process_command(&flush, &mem), GpuResponse::Err(resp_type::ERR_INVALID_RESOURCE_ID));
+
+        let create = GpuCommand::ResourceCreate2d { resource_id: 1, format: format::B8G8R8A8_UNORM, width: 4, height: 4 };
+        assert_eq!(gpu.process_command(&create, &mem), GpuResponse::OkNoData);
+
+        // No backing attached yet
+        let transfer = GpuCommand::TransferToHost2d { rect: Rect::new(0, 0, 4, 4), offset: 0, resource_id: 1 };
+        assert_eq!(gpu.process_command(&transfer, &mem), GpuResponse::Err(resp_type::ERR_UNSPEC));
+
+        let scanout = GpuCommand::SetScanout { rect: Rect::new(0, 0, 4, 4), scanout_id: 3, resource_id: 1 };
+        assert_eq!(gpu.process_command(&scanout, &mem), GpuResponse::Err(resp_type::ERR_INVALID_SCANOUT_ID));
+    }
+
+    #[test]
+    fn test_create_2d_bounds_host_memory() {
+        let mem = TestMemory { base: 0, bytes: Vec::new() };
+        let create = |id, width, height| GpuCommand::ResourceCreate2d {
+            resource_id: id, format: format::B8G8R8A8_UNORM, width, height,
+        };
+        let mut gpu = VirtioGpu::with_memory_limit(2 * 64 * 64 * BYTES_PER_PIXEL);
+
+        // width * height * 4 overflows 32 bits
+        assert_eq!(gpu.process_command(&create(1, u32::MAX, u32::MAX), &mem),
+                   GpuResponse::Err(resp_type::ERR_OUT_OF_MEMORY));
+        // One row past the per-resource limit
+        assert_eq!(gpu.process_command(&create(1, 4096, 4097), &mem),
+                   GpuResponse::Err(resp_type::ERR_OUT_OF_MEMORY));
+
+        assert_eq!(gpu.process_command(&create(1, 64, 64), &mem), GpuResponse::OkNoData);
+        assert_eq!(gpu.process_command(&create(2, 64, 64), &mem), GpuResponse::OkNoData);
+        // The device-wide budget is used up
+        assert_eq!(gpu.process_command(&create(3, 1, 1), &mem),
+                   GpuResponse::Err(resp_type::ERR_OUT_OF_MEMORY));
+
+        // Destroying a resource returns its share
+        assert_eq!(gpu.process_command(&GpuCommand::ResourceUnref { resource_id: 1 }, &mem), GpuResponse::OkNoData);
+        assert_eq!(gpu.process_command(&create(3, 1, 1), &mem), GpuResponse::OkNoData);
+    }
+
+    #[test]
+    fn test_parse_attach_backing() {
+        let mut buf = vec![0u8; CTRL_HDR_SIZE];
+        buf[..4].copy_from_slice(&cmd_type::RESOURCE_ATTACH_BACKING.to_le_bytes());
+        for word in [7u32, 1] {
+            buf.extend_from_slice(&word.to_le_bytes());
+        }
+        buf.extend_from_slice(&0x4000_1000u64.to_le_bytes());
+        buf.extend_from_slice(&4096u32.to_le_bytes());
+        buf.extend_from_slice(&0u32.to_le_bytes());
+
+        assert_eq!(GpuCommand::parse(&buf), Ok(GpuCommand::ResourceAttachBacking {
+            resource_id: 7,
+            entries: vec![MemEntry { addr: 0x4000_1000, length: 4096 }],
+        }));
+
+        // Truncated entry list
+        assert_eq!(GpuCommand::parse(&buf[..buf.len() - 4]), Err(Error::InvalidArgument));
+    }
+
+    #[test]
+    fn test_control_queue_answers_display_info() {
+        use crate::emulators::virtio_mmio::queue::{FlatMemory, VIRTQ_DESC_F_NEXT, VIRTQ_DESC_F_WRITE};
+
+        let mut gpu = VirtioGpu::new();
+        gpu.add_display(Box::new(MemoryFramebuffer::new(640, 480))).unwrap();
+        let (mem, queue) = FlatMemory::with_queue(0x10000, 4);
+        gpu.queues[CONTROL_QUEUE] = Some(queue);
+
+        // Fenced GET_DISPLAY_INFO, response in its own buffer
+        let mut request = vec![0u8; CTRL_HDR_SIZE];
+        request[..4].copy_from_slice(&cmd_type::GET_DISPLAY_INFO.to_le_bytes());
+        request[4..8].copy_from_slice(&FLAG_FENCE.to_le_bytes());
+        request[8..16].copy_from_slice(&42u64.to_le_bytes());
+        mem.write(0x8000, &request).unwrap();
+        let response_len = (CTRL_HDR_SIZE + MAX_SCANOUTS * 24) as u32;
+        mem.write_desc(0, 0x8000, CTRL_HDR_SIZE as u32, VIRTQ_DESC_F_NEXT, 1);
+        mem.write_desc(1, 0x9000, response_len, VIRTQ_DESC_F_WRITE, 0);
+        mem.make_available(0, 0);
+
+        assert_eq!(gpu.service_queue(&mem, CONTROL_QUEUE), Ok(1));
+        assert_eq!(mem.read_u16(0x3002), Ok(1));
+        assert_eq!(mem.read_u32(0x3008), Ok(response_len));
+        assert_eq!(mem.read_u32(0x9000), Ok(resp_type::OK_DISPLAY_INFO));
+        assert_eq!(mem.read_u64(0x9008), Ok(42));
+        // First mode: 640x480 at the origin, enabled
+        assert_eq!(mem.read_u32(0x9000 + CTRL_HDR_SIZE as u64 + 8), Ok(640));
+        assert_eq!(mem.read_u32(0x9000 + CTRL_HDR_SIZE as u64 + 16), Ok(1));
+
+        assert_eq!(gpu.read_config(CONFIG_NUM_SCANOUTS as u64, 32), 1);
+    }
+}
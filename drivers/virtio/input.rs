@@ -1,7 +1,606 @@
-//! Empty stub for VirtIO input.rs
+//! VirtIO input device
+//!
+//! Host-side event injection for virtio-input. Injected events wait in
+//! the device until the driver has a buffer for them on the event queue
+//! (queue 0), where each is written in `virtio_input_event` layout and
+//! the guest is interrupted. Buffers the driver posts on the status queue
+//! (queue 1), e.g. LED changes, are consumed and ignored. The device
+//! configuration space advertises the supported event types and codes.
 
 use crate::{Result, Error};
+use crate::core::sync::SpinLock;
+use crate::core::vmm::VmId;
+use crate::emulators::virtio_mmio::{self, QueueConfig, VirtioBackend, VirtioMmioTransport, VIRTIO_MMIO_INT_VRING};
+use crate::emulators::virtio_mmio::queue::{DeviceQueue, GuestMemory, VmMemory};
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// VirtIO device ID of an input device
+pub const VIRTIO_ID_INPUT: u32 = 18;
+
+/// Queue events are delivered on
+pub const EVENT_QUEUE: usize = 0;
+
+/// Queue the driver reports status (e.g. LEDs) on
+pub const STATUS_QUEUE: usize = 1;
+
+/// Largest queue size offered to the driver
+const INPUT_QUEUE_MAX: u16 = 64;
+
+/// Bytes of a `virtio_input_event`
+const EVENT_LEN: usize = 8;
+
+/// Config space offset of `select`
+const CONFIG_SELECT: u64 = 0;
+
+/// Config space offset of `subsel`
+const CONFIG_SUBSEL: u64 = 1;
+
+/// Config space offset of the `u` union, after `select`, `subsel`,
+/// `size` and five reserved bytes
+const CONFIG_PAYLOAD: usize = 8;
+
+/// Size of the `u` union
+const CONFIG_PAYLOAD_MAX: usize = 128;
+
+/// Input event types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum EvType {
+    /// Synchronization marker
+    Syn = 0x00,
+    /// Key or button
+    Key = 0x01,
+    /// Relative axis (mouse motion, wheel)
+    Rel = 0x02,
+    /// Absolute axis (touchscreen, tablet)
+    Abs = 0x03,
+}
+
+/// SYN_REPORT code
+pub const SYN_REPORT: u16 = 0;
+
+/// Key values
+pub const KEY_RELEASED: u32 = 0;
+/// Key values
+pub const KEY_PRESSED: u32 = 1;
+
+/// Configuration space selectors
+pub mod config_select {
+    /// No selection
+    pub const UNSET: u8 = 0x00;
+    /// Device name string
+    pub const ID_NAME: u8 = 0x01;
+    /// Serial number string
+    pub const ID_SERIAL: u8 = 0x02;
+    /// Bus/vendor/product/version IDs
+    pub const ID_DEVIDS: u8 = 0x03;
+    /// Input properties bitmap
+    pub const PROP_BITS: u8 = 0x10;
+    /// Supported codes for event type `subsel`
+    pub const EV_BITS: u8 = 0x11;
+    /// Range of absolute axis `subsel`
+    pub const ABS_INFO: u8 = 0x12;
+}
+
+/// Event as written into the guest's event buffers
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VirtioInputEvent {
+    /// Event type
+    pub type_: u16,
+    /// Event code
+    pub code: u16,
+    /// Event value
+    pub value: u32,
+}
+
+impl VirtioInputEvent {
+    /// Encode in little-endian wire format
+    pub fn to_bytes(&self) -> [u8; 8] {
+        let mut bytes = [0u8; 8];
+        bytes[0..2].copy_from_slice(&self.type_.to_le_bytes());
+        bytes[2..4].copy_from_slice(&self.code.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.value.to_le_bytes());
+        bytes
+    }
+}
+
+/// Absolute axis range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AbsInfo {
+    /// Minimum value
+    pub min: u32,
+    /// Maximum value
+    pub max: u32,
+    /// Noise filter
+    pub fuzz: u32,
+    /// Dead zone
+    pub flat: u32,
+    /// Resolution
+    pub res: u32,
+}
+
+/// Capabilities advertised through the configuration space
+#[derive(Debug, Clone)]
+pub struct InputCapabilities {
+    /// Device name
+    pub name: &'static str,
+    /// Supported key codes
+    pub keys: Vec<u16>,
+    /// Supported relative axes
+    pub rel_axes: Vec<u16>,
+    /// Supported absolute axes and their ranges
+    pub abs_axes: Vec<(u16, AbsInfo)>,
+}
+
+impl InputCapabilities {
+    /// Whether `code` of `ev_type` is advertised
+    pub fn supports(&self, ev_type: EvType, code: u16) -> bool {
+        match ev_type {
+            EvType::Syn => true,
+            EvType::Key => self.keys.contains(&code),
+            EvType::Rel => self.rel_axes.contains(&code),
+            EvType::Abs => self.abs_axes.iter().any(|(axis, _)| *axis == code),
+        }
+    }
+
+    /// Read the configuration payload for `select`/`subsel`
+    ///
+    /// Returns the bytes the device exposes in the config `u` union; its
+    /// length is the config `size` field. Unknown selections are empty.
+    pub fn query(&self, select: u8, subsel: u8) -> Vec<u8> {
+        match select {
+            config_select::ID_NAME => self.name.as_bytes().to_vec(),
+            config_select::EV_BITS => {
+                let codes: Vec<u16> = match subsel {
+                    x if x == EvType::Syn as u8 => {
+                        // EV_SYN lists the event types themselves
+                        let mut types = Vec::from([EvType::Syn as u16]);
+                        if !self.keys.is_empty() { types.push(EvType::Key as u16); }
+                        if !self.rel_axes.is_empty() { types.push(EvType::Rel as u16); }
+                        if !self.abs_axes.is_empty() { types.push(EvType::Abs as u16); }
+                        types
+                    }
+                    x if x == EvType::Key as u8 => self.keys.clone(),
+                    x if x == EvType::Rel as u8 => self.rel_axes.clone(),
+                    x if x == EvType::Abs as u8 => self.abs_axes.iter().map(|(axis, _)| *axis).collect(),
+                    _ => Vec::new(),
+                };
+                bitmap(&codes)
+            }
+            config_select::ABS_INFO => {
+                match self.abs_axes.iter().find(|(axis, _)| *axis == subsel as u16) {
+                    Some((_, info)) => {
+                        let mut bytes = Vec::with_capacity(20);
+                        for field in [info.min, info.max, info.fuzz, info.flat, info.res] {
+                            bytes.extend_from_slice(&field.to_le_bytes());
+                        }
+                        bytes
+                    }
+                    None => Vec::new(),
+                }
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Build a code bitmap, trimmed to the highest set byte
+fn bitmap(codes: &[u16]) -> Vec<u8> {
+    let len = codes.iter().map(|&code| code as usize / 8 + 1).max().unwrap_or(0);
+    let mut bits = alloc::vec![0u8; len];
+    for &code in codes {
+        bits[code as usize / 8] |= 1 << (code % 8);
+    }
+    bits
+}
+
+/// Callback used to notify the guest of new events
+pub type InputNotifyFn = fn(irq: u32);
+
+/// VirtIO input device
+pub struct VirtioInput {
+    /// Advertised capabilities
+    caps: InputCapabilities,
+    /// Events waiting for a guest buffer, in event queue order
+    event_queue: VecDeque<VirtioInputEvent>,
+    /// Most events kept waiting
+    queue_size: usize,
+    /// Device interrupt
+    irq: u32,
+    /// Guest notification
+    notify: Option<InputNotifyFn>,
+    /// VM whose memory the virtqueues live in
+    vm_id: Option<VmId>,
+    /// Event and status virtqueues, once live
+    queues: [Option<DeviceQueue>; 2],
+    /// Whether buffers were used since the last interrupt
+    used: bool,
+    /// Config `select`
+    select: u8,
+    /// Config `subsel`
+    subsel: u8,
+}
+
+impl VirtioInput {
+    /// Create a device with the given capabilities
+    pub fn new(caps: InputCapabilities, queue_size: usize, irq: u32) -> Self {
+        Self {
+            caps,
+            event_queue: VecDeque::with_capacity(queue_size),
+            queue_size,
+            irq,
+            notify: None,
+            vm_id: None,
+            queues: [None; 2],
+            used: false,
+            select: config_select::UNSET,
+            subsel: 0,
+        }
+    }
+
+    /// Serve the VM `vm_id`
+    pub fn set_vm(&mut self, vm_id: VmId) {
+        self.vm_id = Some(vm_id);
+    }
+
+    /// Get the advertised capabilities
+    pub fn capabilities(&self) -> &InputCapabilities {
+        &self.caps
+    }
+
+    /// Set the guest notification callback
+    pub fn set_notify(&mut self, notify: InputNotifyFn) {
+        self.notify = Some(notify);
+    }
+
+    /// Queue an event for the guest and notify it
+    pub fn inject_event(&mut self, ev_type: EvType, code: u16, value: u32) -> Result<()> {
+        if !self.caps.supports(ev_type, code) {
+            return Err(Error::InvalidArgument);
+        }
+
+        if self.event_queue.len() >= self.queue_size {
+            return Err(Error::ResourceBusy);
+        }
+
+        self.event_queue.push_back(VirtioInputEvent {
+            type_: ev_type as u16,
+            code,
+            value,
+        });
+
+        if let Some(vm_id) = self.vm_id {
+            if let Err(e) = self.fill_buffers(&VmMemory { vm_id }) {
+                crate::warn!("virtio-input: VM {}: bad event queue: {:?}", vm_id, e);
+            }
+        }
+
+        if let Some(notify) = self.notify {
+            notify(self.irq);
+        }
+
+        Ok(())
+    }
+
+    /// Move waiting events into the buffers the driver made available on
+    /// the event queue in `mem`
+    ///
+    /// Events stay queued while the driver has no buffer for them.
+    /// Returns the number of buffers filled.
+    fn fill_buffers(&mut self, mem: &dyn GuestMemory) -> Result<usize> {
+        let Some(queue) = self.queues[EVENT_QUEUE].as_mut() else {
+            return Ok(0);
+        };
+
+        let mut filled = 0;
+        while let Some(event) = self.event_queue.front() {
+            let Some(chain) = queue.pop(mem)? else {
+                break;
+            };
+
+            // A buffer too small for an event goes back empty
+            let len = match chain.writable().next().filter(|desc| desc.len as usize >= EVENT_LEN) {
+                Some(desc) => {
+                    mem.write(desc.addr, &event.to_bytes())?;
+                    self.event_queue.pop_front();
+                    EVENT_LEN as u32
+                }
+                None => 0,
+            };
+            queue.push_used(mem, chain.head, len)?;
+            self.used = true;
+            filled += 1;
+        }
+        Ok(filled)
+    }
+
+    /// Return every buffer the driver made available on the status queue
+    /// in `mem`
+    fn drain_status(&mut self, mem: &dyn GuestMemory) -> Result<usize> {
+        let Some(queue) = self.queues[STATUS_QUEUE].as_mut() else {
+            return Ok(0);
+        };
+
+        let mut completed = 0;
+        while let Some(chain) = queue.pop(mem)? {
+            queue.push_used(mem, chain.head, 0)?;
+            completed += 1;
+        }
+        Ok(completed)
+    }
+
+    /// Configuration space for the current `select` and `subsel`
+    fn config_space(&self) -> Vec<u8> {
+        let mut payload = self.caps.query(self.select, self.subsel);
+        payload.truncate(CONFIG_PAYLOAD_MAX);
+
+        let mut config = alloc::vec![0u8; CONFIG_PAYLOAD + CONFIG_PAYLOAD_MAX];
+        config[CONFIG_SELECT as usize] = self.select;
+        config[CONFIG_SUBSEL as usize] = self.subsel;
+        config[2] = payload.len() as u8;
+        config[CONFIG_PAYLOAD..CONFIG_PAYLOAD + payload.len()].copy_from_slice(&payload);
+        config
+    }
+
+    /// Queue a SYN_REPORT closing the current event group
+    pub fn inject_sync(&mut self) -> Result<()> {
+        self.inject_event(EvType::Syn, SYN_REPORT, 0)
+    }
+
+    /// Number of events waiting for the guest
+    pub fn pending(&self) -> usize {
+        self.event_queue.len()
+    }
+
+    /// Take the next event for a guest buffer
+    pub fn pop_event(&mut self) -> Option<VirtioInputEvent> {
+        self.event_queue.pop_front()
+    }
+}
+
+impl VirtioBackend for VirtioInput {
+    fn device_id(&self) -> u32 {
+        VIRTIO_ID_INPUT
+    }
+
+    fn features(&self) -> u64 {
+        0
+    }
+
+    fn num_queues(&self) -> usize {
+        self.queues.len()
+    }
+
+    fn queue_max(&self, _index: usize) -> u16 {
+        INPUT_QUEUE_MAX
+    }
+
+    fn activate_queue(&mut self, index: usize, config: QueueConfig) -> Result<()> {
+        let queue = self.queues.get_mut(index).ok_or(Error::InvalidArgument)?;
+        *queue = Some(DeviceQueue::new(config));
+        Ok(())
+    }
+
+    fn deactivate_queue(&mut self, index: usize) {
+        if let Some(queue) = self.queues.get_mut(index) {
+            *queue = None;
+        }
+    }
+
+    fn notify(&mut self, index: usize) -> u32 {
+        let Some(vm_id) = self.vm_id else {
+            return 0;
+        };
+
+        let mem = VmMemory { vm_id };
+        let result = match index {
+            EVENT_QUEUE => self.fill_buffers(&mem),
+            STATUS_QUEUE => self.drain_status(&mem),
+            _ => Ok(0),
+        };
+        match result {
+            Ok(0) => 0,
+            Ok(_) => VIRTIO_MMIO_INT_VRING,
+            Err(e) => {
+                crate::warn!("virtio-input: VM {}: bad queue {}: {:?}", vm_id, index, e);
+                VIRTIO_MMIO_INT_VRING
+            }
+        }
+    }
+
+    fn read_config(&self, offset: u64, size: u32) -> u64 {
+        virtio_mmio::read_config_bytes(&self.config_space(), offset, size)
+    }
+
+    /// Only `select` and `subsel` are writable
+    fn write_config(&mut self, offset: u64, value: u64, size: u32) {
+        for (i, byte) in value.to_le_bytes().iter().take((size / 8) as usize).enumerate() {
+            match offset + i as u64 {
+                CONFIG_SELECT => self.select = *byte,
+                CONFIG_SUBSEL => self.subsel = *byte,
+                _ => {}
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.queues = [None; 2];
+        self.used = false;
+        self.select = config_select::UNSET;
+        self.subsel = 0;
+    }
+
+    /// Interrupt for events delivered by `inject_event`
+    fn poll(&mut self) -> u32 {
+        if core::mem::take(&mut self.used) { VIRTIO_MMIO_INT_VRING } else { 0 }
+    }
+}
+
+/// Capabilities of the default keyboard/mouse device
+fn default_capabilities() -> InputCapabilities {
+    InputCapabilities {
+        name: "ferrovisor-input",
+        // KEY_ESC..KEY_KPDOT, BTN_LEFT/RIGHT/MIDDLE
+        keys: (1..=83).chain(0x110..=0x112).collect(),
+        // REL_X, REL_Y, REL_WHEEL
+        rel_axes: Vec::from([0x00, 0x01, 0x08]),
+        abs_axes: Vec::new(),
+    }
+}
+
+/// Global input device, shared with the transport of the VM it serves
+static INPUT_DEVICE: SpinLock<Option<Arc<SpinLock<VirtioInput>>>> = SpinLock::new(None);
+
+/// Inject an event into the input device
+pub fn inject_event(ev_type: EvType, code: u16, value: u32) -> Result<()> {
+    INPUT_DEVICE.lock()
+        .as_ref()
+        .ok_or(Error::NotInitialized)?
+        .lock()
+        .inject_event(ev_type, code, value)
+}
+
+/// Emulator registry name of the input device of a VM, also the name of
+/// its interrupt route
+pub fn device_name(vm_id: VmId) -> String {
+    format!("virtio-input.{}", vm_id)
+}
+
+/// Give a VM the global input device as a virtio-mmio device at `base`,
+/// interrupting on guest `irq`
+///
+/// The device serves one VM at a time: fails with `Error::ResourceBusy`
+/// while another VM has it.
+pub fn attach(vm_id: VmId, base: u64, irq: u32) -> Result<()> {
+    let device = INPUT_DEVICE.lock().clone().ok_or(Error::NotInitialized)?;
+    {
+        let mut device = device.lock();
+        if device.vm_id.is_some() {
+            return Err(Error::ResourceBusy);
+        }
+        device.set_vm(vm_id);
+    }
+
+    let name = device_name(vm_id);
+    let mut transport = VirtioMmioTransport::new(base, irq, device.clone());
+    transport.set_guest_irq(vm_id, &name);
+    if let Err(e) = virtio_mmio::register(vm_id, &name, transport) {
+        device.lock().vm_id = None;
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Take the global input device back from a VM, if it has it
+pub fn detach(vm_id: VmId) {
+    let Some(device) = INPUT_DEVICE.lock().clone() else {
+        return;
+    };
+    if device.lock().vm_id != Some(vm_id) {
+        return;
+    }
+
+    crate::emulator::unregister_emulator(vm_id, &device_name(vm_id)).ok();
+    let mut device = device.lock();
+    device.vm_id = None;
+    VirtioBackend::reset(&mut *device);
+}
 
 pub fn init() -> Result<()> {
+    *INPUT_DEVICE.lock() = Some(Arc::new(SpinLock::new(VirtioInput::new(default_capabilities(), 64, 0))));
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// KEY_A
+    const KEY_A: u16 = 30;
+
+    #[test]
+    fn test_inject_key_press_release() {
+        let mut input = VirtioInput::new(default_capabilities(), 8, 0);
+
+        input.inject_event(EvType::Key, KEY_A, KEY_PRESSED).unwrap();
+        input.inject_event(EvType::Key, KEY_A, KEY_RELEASED).unwrap();
+        assert_eq!(input.pending(), 2);
+
+        let down = input.pop_event().unwrap();
+        assert_eq!(down.to_bytes(), [0x01, 0x00, 30, 0x00, 0x01, 0x00, 0x00, 0x00]);
+        let up = input.pop_event().unwrap();
+        assert_eq!(up.to_bytes(), [0x01, 0x00, 30, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        assert_eq!(input.pop_event(), None);
+    }
+
+    #[test]
+    fn test_inject_notifies_guest() {
+        static NOTIFIED: core::sync::atomic::AtomicU32 = core::sync::atomic::AtomicU32::new(0);
+        fn notify(_irq: u32) {
+            NOTIFIED.fetch_add(1, core::sync::atomic::Ordering::SeqCst);
+        }
+
+        let mut input = VirtioInput::new(default_capabilities(), 1, 0);
+        input.set_notify(notify);
+        input.inject_event(EvType::Rel, 0x00, 5).unwrap();
+        assert_eq!(NOTIFIED.load(core::sync::atomic::Ordering::SeqCst), 1);
+
+        // Queue full, unsupported code
+        assert_eq!(input.inject_event(EvType::Rel, 0x01, 5), Err(Error::ResourceBusy));
+        assert_eq!(input.inject_event(EvType::Abs, 0x00, 5), Err(Error::InvalidArgument));
+    }
+
+    #[test]
+    fn test_config_ev_bits() {
+        let caps = default_capabilities();
+
+        let types = caps.query(config_select::EV_BITS, EvType::Syn as u8);
+        assert_eq!(types, [0b0000_0111]);
+
+        let rel = caps.query(config_select::EV_BITS, EvType::Rel as u8);
+        assert_eq!(rel, [0b0000_0011, 0b0000_0001]);
+
+        assert_eq!(caps.query(config_select::ID_NAME, 0), b"ferrovisor-input");
+    }
+
+    #[test]
+    fn test_events_fill_event_queue_buffers() {
+        use crate::emulators::virtio_mmio::queue::{FlatMemory, VIRTQ_DESC_F_WRITE};
+
+        let mut input = VirtioInput::new(default_capabilities(), 8, 0);
+        let (mem, queue) = FlatMemory::with_queue(0x10000, 4);
+        input.queues[EVENT_QUEUE] = Some(queue);
+
+        // No buffer yet: the event waits
+        input.inject_event(EvType::Key, KEY_A, KEY_PRESSED).unwrap();
+        assert_eq!(input.fill_buffers(&mem), Ok(0));
+        assert_eq!(input.pending(), 1);
+
+        mem.write_desc(2, 0x8000, EVENT_LEN as u32, VIRTQ_DESC_F_WRITE, 0);
+        mem.make_available(0, 2);
+        assert_eq!(input.fill_buffers(&mem), Ok(1));
+        assert_eq!(input.pending(), 0);
+        assert_eq!(mem.read_u16(0x3002), Ok(1));
+        assert_eq!((mem.read_u32(0x3004), mem.read_u32(0x3008)), (Ok(2), Ok(EVENT_LEN as u32)));
+        assert_eq!(input.poll(), VIRTIO_MMIO_INT_VRING);
+        assert_eq!(input.poll(), 0);
+
+        let mut event = [0; EVENT_LEN];
+        mem.read(0x8000, &mut event).unwrap();
+        assert_eq!(event, [0x01, 0x00, 30, 0x00, 0x01, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn test_config_space_follows_select() {
+        let mut input = VirtioInput::new(default_capabilities(), 8, 0);
+        input.write_config(CONFIG_SELECT, config_select::EV_BITS as u64 | (EvType::Rel as u64) << 8, 16);
+
+        assert_eq!(input.read_config(2, 8), 2);
+        assert_eq!(input.read_config(CONFIG_PAYLOAD as u64, 16), 0x0103);
+    }
+}
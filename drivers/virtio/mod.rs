@@ -6,15 +6,17 @@
 use crate::{Result, Error};
 use crate::drivers::{DeviceType, DeviceOps, DeviceInfo, DeviceStatus};
 use crate::core::mm::{PhysAddr, VirtAddr};
+use crate::core::mm::slab::ring_cache;
 use crate::core::sync::SpinLock;
-use crate::arch::common::MmioAccess;
-use core::sync::atomic::{AtomicU16, AtomicU32, Ordering};
+use crate::arch::common::MmioRegion;
+use core::sync::atomic::{AtomicU16, AtomicU32, AtomicU64, Ordering};
 use alloc::format;
 
 pub mod net;
 pub mod block;
 pub mod console;
 pub mod rng;
+pub mod balloon;
 pub mod gpu;
 pub mod input;
 
@@ -45,6 +47,8 @@ pub struct VirtioCommonConfig {
     pub config_generation: u8,
     /// Queue size
     pub queue_size: u16,
+    /// Largest size the device supports for the selected queue
+    pub queue_num_max: u16,
     /// Queue MSI vector
     pub queue_msi_vector: u16,
     /// Queue address
@@ -179,10 +183,87 @@ pub struct VirtQueue {
     last_used_idx: AtomicU16,
     /// Available index
     avail_idx: AtomicU16,
+    /// Total buffers returned through the used ring
+    completed: AtomicU64,
     /// Queue index
     queue_index: u16,
 }
 
+/// Snapshot of a virtqueue's progress
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VirtQueueStats {
+    /// Next available ring index the driver will fill
+    pub avail_idx: u16,
+    /// Next used ring index the driver will consume
+    pub last_used_idx: u16,
+    /// Buffers made available but not yet consumed
+    pub outstanding: u16,
+    /// Total buffers consumed from the used ring
+    pub completed: u64,
+}
+
+impl VirtQueueStats {
+    /// Pack the ring indices into an `ioctl` return value
+    ///
+    /// Layout: bits 0-15 available index, 16-31 last used index,
+    /// 32-47 outstanding descriptors.
+    pub fn pack_indices(&self) -> u64 {
+        self.avail_idx as u64
+            | (self.last_used_idx as u64) << 16
+            | (self.outstanding as u64) << 32
+    }
+}
+
+/// Byte offset of `config_generation` in the common configuration
+const CONFIG_GENERATION_OFFSET: usize = core::mem::offset_of!(VirtioCommonConfig, config_generation);
+
+/// Byte offset of `status` in the common configuration
+const STATUS_OFFSET: usize = core::mem::offset_of!(VirtioCommonConfig, status);
+
+/// Byte offset of `queue_num_max` in the common configuration
+const QUEUE_NUM_MAX_OFFSET: usize = core::mem::offset_of!(VirtioCommonConfig, queue_num_max);
+
+/// Times a configuration read is retried while the generation keeps changing
+pub const CONFIG_READ_RETRIES: usize = 16;
+
+/// Run `read` until the configuration generation is the same before and after
+///
+/// Fails with `Error::Timeout` if the configuration was still changing
+/// after `CONFIG_READ_RETRIES` attempts.
+fn read_generation_checked<T>(
+    mut generation: impl FnMut() -> Result<u8>,
+    mut read: impl FnMut() -> Result<T>,
+) -> Result<T> {
+    for _ in 0..CONFIG_READ_RETRIES {
+        let before = generation()?;
+        let value = read()?;
+        if generation()? == before {
+            return Ok(value);
+        }
+    }
+    Err(Error::Timeout)
+}
+
+/// `VirtioDevice::ioctl` command numbers
+pub mod ioctl_cmd {
+    /// Read device-offered features
+    pub const DEVICE_FEATURES: u32 = 0x3000;
+    /// Read driver-selected features
+    pub const DRIVER_FEATURES: u32 = 0x3001;
+    /// Packed indices of queue N (see `VirtQueueStats::pack_indices`)
+    pub const QUEUE_STATS: u32 = 0x3100;
+    /// Last queue stats command (queue 255)
+    pub const QUEUE_STATS_LAST: u32 = 0x31ff;
+    /// Total completed buffers of queue N
+    pub const QUEUE_COMPLETED: u32 = 0x3200;
+    /// Last completed-count command (queue 255)
+    pub const QUEUE_COMPLETED_LAST: u32 = 0x32ff;
+    /// Resize queue N to `arg` entries
+    pub const QUEUE_RESIZE: u32 = 0x3300;
+    /// Last resize command (queue 255)
+    pub const QUEUE_RESIZE_LAST: u32 = 0x33ff;
+}
+
 impl VirtQueue {
     /// Create a new virtqueue
     pub fn new(queue_index: u16, size: u16) -> Result<Self> {
@@ -190,20 +271,32 @@ impl VirtQueue {
             return Err(Error::InvalidArgument); // Size must be power of 2
         }
 
-        let desc_size = core::mem::size_of::<VirtQueueDesc>() * size as usize;
-        let avail_size = core::mem::size_of::<VirtQueueAvail>() + (size as usize + 3) * core::mem::size_of::<u16>();
-        let used_size = core::mem::size_of::<VirtQueueUsed>() + (size as usize + 3) * core::mem::size_of::<VirtQueueUsedElem>();
+        let (desc_size, avail_size, used_size) = Self::ring_sizes(size);
 
-        // Allocate memory for descriptor table and rings
-        let desc = PageFrameAllocator::alloc(desc_size)?;
-        let avail = PageFrameAllocator::alloc(avail_size)?;
-        let used = PageFrameAllocator::alloc(used_size)?;
+        // Allocate the descriptor table and rings from the ring cache
+        let rings = ring_cache();
+        let desc = rings.allocate(desc_size).map_err(|_| Error::OutOfMemory)?;
+        let avail = match rings.allocate(avail_size) {
+            Ok(avail) => avail,
+            Err(_) => {
+                rings.free(desc, desc_size);
+                return Err(Error::OutOfMemory);
+            }
+        };
+        let used = match rings.allocate(used_size) {
+            Ok(used) => used,
+            Err(_) => {
+                rings.free(desc, desc_size);
+                rings.free(avail, avail_size);
+                return Err(Error::OutOfMemory);
+            }
+        };
 
         // Initialize allocated memory
         unsafe {
-            core::ptr::write_bytes(desc.as_mut_ptr(), 0, desc_size);
-            core::ptr::write_bytes(avail.as_mut_ptr(), 0, avail_size);
-            core::ptr::write_bytes(used.as_mut_ptr(), 0, used_size);
+            core::ptr::write_bytes(desc as *mut u8, 0, desc_size);
+            core::ptr::write_bytes(avail as *mut u8, 0, avail_size);
+            core::ptr::write_bytes(used as *mut u8, 0, used_size);
         }
 
         Ok(Self {
@@ -213,15 +306,56 @@ impl VirtQueue {
             used,
             last_used_idx: AtomicU16::new(0),
             avail_idx: AtomicU16::new(0),
+            completed: AtomicU64::new(0),
             queue_index,
         })
     }
 
+    /// Bytes of the descriptor table, available ring and used ring of a
+    /// queue of `size` entries
+    fn ring_sizes(size: u16) -> (usize, usize, usize) {
+        let size = size as usize;
+        (
+            core::mem::size_of::<VirtQueueDesc>() * size,
+            core::mem::size_of::<VirtQueueAvail>() + (size + 3) * core::mem::size_of::<u16>(),
+            core::mem::size_of::<VirtQueueUsed>() + (size + 3) * core::mem::size_of::<VirtQueueUsedElem>(),
+        )
+    }
+
+    /// Return the rings of a queue the device no longer uses to the ring
+    /// cache
+    fn free_rings(self) {
+        let (desc_size, avail_size, used_size) = Self::ring_sizes(self.size);
+        let rings = ring_cache();
+        rings.free(self.desc, desc_size);
+        rings.free(self.avail, avail_size);
+        rings.free(self.used, used_size);
+    }
+
+    /// Check that the queue may be replaced by one of `new_size` entries
+    ///
+    /// Fails with `Error::InvalidArgument` if `new_size` is not a power of
+    /// two and `Error::ResourceBusy` while buffers are still in flight.
+    pub fn check_resize(&self, new_size: u16) -> Result<()> {
+        if new_size == 0 || !new_size.is_power_of_two() {
+            return Err(Error::InvalidArgument);
+        }
+        if self.stats().outstanding != 0 {
+            return Err(Error::ResourceBusy);
+        }
+        Ok(())
+    }
+
     /// Get queue size
     pub fn size(&self) -> u16 {
         self.size
     }
 
+    /// Get queue index
+    pub fn queue_index(&self) -> u16 {
+        self.queue_index
+    }
+
     /// Get descriptor table address
     pub fn desc_addr(&self) -> VirtAddr {
         self.desc
@@ -299,12 +433,25 @@ impl VirtQueue {
             if idx < ring.len() {
                 let elem = &ring[idx];
                 self.last_used_idx.store(last_used + 1, Ordering::Release);
+                self.completed.fetch_add(1, Ordering::Relaxed);
                 return Some((elem.id, elem.len));
             }
         }
 
         None
     }
+
+    /// Get a snapshot of the queue's progress
+    pub fn stats(&self) -> VirtQueueStats {
+        let avail_idx = self.avail_idx.load(Ordering::Acquire);
+        let last_used_idx = self.last_used_idx.load(Ordering::Acquire);
+        VirtQueueStats {
+            avail_idx,
+            last_used_idx,
+            outstanding: avail_idx.wrapping_sub(last_used_idx),
+            completed: self.completed.load(Ordering::Relaxed),
+        }
+    }
 }
 
 /// VirtIO device base
@@ -329,6 +476,10 @@ pub struct VirtioDevice {
     irq: u32,
     /// Common configuration
     common_config: VirtAddr,
+    /// Bounds-checked view of the common configuration registers
+    config_region: MmioRegion,
+    /// Bounds-checked view of the device-specific configuration
+    device_config: MmioRegion,
 }
 
 impl VirtioDevice {
@@ -352,9 +503,26 @@ impl VirtioDevice {
             driver_features: SpinLock::new(0),
             irq,
             common_config,
+            config_region: MmioRegion::new(
+                common_config as usize,
+                core::mem::size_of::<VirtioCommonConfig>(),
+            ),
+            device_config: MmioRegion::new(
+                common_config as usize + core::mem::size_of::<VirtioCommonConfig>(),
+                0,
+            ),
         }
     }
 
+    /// Expose `len` bytes of device-specific configuration at `addr`
+    ///
+    /// Until this is called the device has no device-specific
+    /// configuration and every block access fails.
+    pub fn with_device_config(mut self, addr: VirtAddr, len: usize) -> Self {
+        self.device_config = MmioRegion::new(addr as usize, len);
+        self
+    }
+
     /// Reset the device
     pub fn reset(&self) -> Result<()> {
         {
@@ -363,7 +531,7 @@ impl VirtioDevice {
         }
 
         // Write reset value to device status register
-        self.write_config_u32(0, 0);
+        self.write_status(0)?;
 
         Ok(())
     }
@@ -375,7 +543,7 @@ impl VirtioDevice {
             status.set(VirtioDeviceStatus::ACKNOWLEDGE);
         }
 
-        self.write_config_u32(0, self.status.lock().value());
+        self.write_status(self.status.lock().value())?;
         Ok(())
     }
 
@@ -386,19 +554,19 @@ impl VirtioDevice {
             status.set(VirtioDeviceStatus::DRIVER);
         }
 
-        self.write_config_u32(0, self.status.lock().value());
+        self.write_status(self.status.lock().value())?;
         Ok(())
     }
 
     /// Read device features
     pub fn read_device_features(&self) -> Result<u64> {
         // Select feature bits 0-31
-        self.write_config_u32(0, 0);
-        let features_lo = self.read_config_u32(1);
+        self.write_config_u32(0, 0)?;
+        let features_lo = self.read_config_u32(1)?;
 
         // Select feature bits 32-63
-        self.write_config_u32(0, 1);
-        let features_hi = self.read_config_u32(1);
+        self.write_config_u32(0, 1)?;
+        let features_hi = self.read_config_u32(1)?;
 
         let features = ((features_hi as u64) << 32) | (features_lo as u64);
         {
@@ -417,21 +585,42 @@ impl VirtioDevice {
         }
 
         // Write feature bits 0-31
-        self.write_config_u32(2, 0);
-        self.write_config_u32(3, features as u32);
+        self.write_config_u32(2, 0)?;
+        self.write_config_u32(3, features as u32)?;
 
         // Write feature bits 32-63
-        self.write_config_u32(2, 1);
-        self.write_config_u32(3, (features >> 32) as u32);
+        self.write_config_u32(2, 1)?;
+        self.write_config_u32(3, (features >> 32) as u32)?;
 
+        self.set_features_ok(|| self.read_status())
+    }
+
+    /// Set FEATURES_OK and check that the device accepted the features
+    ///
+    /// A device that cannot work with the driver's feature subset clears
+    /// FEATURES_OK again, so the status is read back with `read_status`.
+    /// If the bit is gone the device is marked FAILED and
+    /// `Error::ResourceUnavailable` is returned.
+    fn set_features_ok(&self, read_status: impl FnOnce() -> Result<u32>) -> Result<()> {
         {
             let mut status = self.status.lock();
             status.set(VirtioDeviceStatus::FEATURES_OK);
         }
 
-        self.write_config_u32(0, self.status.lock().value());
+        self.write_status(self.status.lock().value())?;
 
-        Ok(())
+        if read_status()? & VirtioDeviceStatus::FEATURES_OK != 0 {
+            return Ok(());
+        }
+
+        crate::warn!("VirtIO device '{}' rejected the driver features", self.name);
+        {
+            let mut status = self.status.lock();
+            status.clear(VirtioDeviceStatus::FEATURES_OK);
+            status.set(VirtioDeviceStatus::FAILED);
+        }
+        self.write_status(self.status.lock().value())?;
+        Err(Error::ResourceUnavailable)
     }
 
     /// Set DRIVER_OK
@@ -441,27 +630,14 @@ impl VirtioDevice {
             status.set(VirtioDeviceStatus::DRIVER_OK);
         }
 
-        self.write_config_u32(0, self.status.lock().value());
+        self.write_status(self.status.lock().value())?;
         Ok(())
     }
 
     /// Set up a virtqueue
     pub fn setup_queue(&self, queue_index: u16, size: u16) -> Result<()> {
         let queue = VirtQueue::new(queue_index, size)?;
-
-        // Select queue
-        self.write_config_u32(4, queue_index as u32);
-        // Set queue size
-        self.write_config_u32(7, size as u32);
-        // Set queue addresses
-        self.write_config_u32(8, queue.desc_addr().value() as u64 as u32);
-        self.write_config_u32(9, (queue.desc_addr().value() >> 32) as u32);
-        self.write_config_u32(10, queue.avail_addr().value() as u64 as u32);
-        self.write_config_u32(11, (queue.avail_addr().value() >> 32) as u32);
-        self.write_config_u32(12, queue.used_addr().value() as u64 as u32);
-        self.write_config_u32(13, (queue.used_addr().value() >> 32) as u32);
-        // Set queue ready
-        self.write_config_u32(5, 1);
+        self.program_queue(&queue)?;
 
         {
             let mut queues = self.queues.lock();
@@ -475,23 +651,144 @@ impl VirtioDevice {
         Ok(())
     }
 
+    /// Replace a set-up queue with one of `new_size` entries
+    ///
+    /// The queue is disabled, its rings freed and new rings programmed into
+    /// the device. Refused with `Error::ResourceBusy` while the old queue
+    /// still has buffers in flight.
+    pub fn resize_queue(&self, queue_index: u16, new_size: u16) -> Result<()> {
+        // The device bounds the size of each queue
+        self.write_config_u32(4, queue_index as u32)?;
+        if new_size > self.queue_num_max()? {
+            return Err(Error::InvalidArgument);
+        }
+
+        let old = {
+            let mut queues = self.queues.lock();
+            let slot = queues.get_mut(queue_index as usize).ok_or(Error::NotFound)?;
+            slot.as_ref().ok_or(Error::NotFound)?.check_resize(new_size)?;
+            slot.take().ok_or(Error::NotFound)?
+        };
+
+        // The old rings are freed only once the device uses the new ones
+        let result = VirtQueue::new(queue_index, new_size).and_then(|queue| {
+            match self.write_config_u32(5, 0).and_then(|_| self.program_queue(&queue)) {
+                Ok(()) => Ok(queue),
+                Err(e) => {
+                    queue.free_rings();
+                    Err(e)
+                }
+            }
+        });
+
+        let mut queues = self.queues.lock();
+        match result {
+            Ok(queue) => {
+                queues[queue_index as usize] = Some(queue);
+                drop(queues);
+                old.free_rings();
+                crate::info!("Resized VirtIO queue {} to {}", queue_index, new_size);
+                Ok(())
+            }
+            Err(e) => {
+                queues[queue_index as usize] = Some(old);
+                Err(e)
+            }
+        }
+    }
+
+    /// Program a queue's size and rings into the device and enable it
+    fn program_queue(&self, queue: &VirtQueue) -> Result<()> {
+        // Select queue
+        self.write_config_u32(4, queue.queue_index() as u32)?;
+        // Set queue size
+        self.write_config_u32(7, queue.size() as u32)?;
+        // Set queue addresses
+        self.write_config_u32(8, queue.desc_addr().value() as u64 as u32)?;
+        self.write_config_u32(9, (queue.desc_addr().value() >> 32) as u32)?;
+        self.write_config_u32(10, queue.avail_addr().value() as u64 as u32)?;
+        self.write_config_u32(11, (queue.avail_addr().value() >> 32) as u32)?;
+        self.write_config_u32(12, queue.used_addr().value() as u64 as u32)?;
+        self.write_config_u32(13, (queue.used_addr().value() >> 32) as u32)?;
+        // Set queue ready
+        self.write_config_u32(5, 1)
+    }
+
+    /// Largest size the device supports for the selected queue
+    fn queue_num_max(&self) -> Result<u16> {
+        self.config_region.read::<u16>(QUEUE_NUM_MAX_OFFSET)
+    }
+
     /// Notify queue
     pub fn notify_queue(&self, queue_index: u16) -> Result<()> {
         // Write to queue notify register
-        self.write_config_u32(6, queue_index as u32);
+        self.write_config_u32(6, queue_index as u32)?;
         Ok(())
     }
 
     /// Read configuration register
-    fn read_config_u32(&self, offset: usize) -> u32 {
-        let mmio = MmioAccess;
-        mmio.read_u32(self.common_config + offset as u64 * 4)
+    fn read_config_u32(&self, offset: usize) -> Result<u32> {
+        self.config_region.read::<u32>(offset * 4)
     }
 
     /// Write configuration register
-    fn write_config_u32(&self, offset: usize, value: u32) {
-        let mmio = MmioAccess;
-        mmio.write_u32(self.common_config + offset as u64 * 4, value);
+    fn write_config_u32(&self, offset: usize, value: u32) -> Result<()> {
+        self.config_region.write::<u32>(offset * 4, value)
+    }
+
+    /// Read the device status register
+    fn read_status(&self) -> Result<u32> {
+        self.config_region.read::<u32>(STATUS_OFFSET)
+    }
+
+    /// Write the device status register
+    fn write_status(&self, status: u32) -> Result<()> {
+        self.config_region.write::<u32>(STATUS_OFFSET, status)
+    }
+
+    /// Get the configuration generation
+    pub fn config_generation(&self) -> Result<u8> {
+        self.config_region.read::<u8>(CONFIG_GENERATION_OFFSET)
+    }
+
+    /// Read consecutive device-specific configuration words starting at
+    /// word `offset`
+    ///
+    /// The block is re-read if the device changed the configuration while
+    /// it was being read, so multi-field values are never torn. Fails with
+    /// `Error::InvalidArgument` if the block runs past the device config.
+    pub fn read_config_block(&self, offset: usize, values: &mut [u32]) -> Result<()> {
+        read_generation_checked(
+            || self.config_generation(),
+            || {
+                for (i, value) in values.iter_mut().enumerate() {
+                    *value = self.device_config.read::<u32>((offset + i) * 4)?;
+                }
+                Ok(())
+            },
+        )
+    }
+
+    /// Write consecutive device-specific configuration words starting at
+    /// word `offset`
+    ///
+    /// The generation is owned by the device, which bumps it when it
+    /// changes the configuration; the driver never writes it.
+    pub fn write_config_block(&self, offset: usize, values: &[u32]) -> Result<()> {
+        let end = offset.checked_add(values.len()).and_then(|end| end.checked_mul(4));
+        if end.map_or(true, |end| end > self.device_config.len()) {
+            return Err(Error::InvalidArgument);
+        }
+        for (i, &value) in values.iter().enumerate() {
+            self.device_config.write::<u32>((offset + i) * 4, value)?;
+        }
+        Ok(())
+    }
+
+    /// Get statistics for a queue
+    pub fn queue_stats(&self, index: u16) -> Option<VirtQueueStats> {
+        let queues = self.queues.lock();
+        queues.get(index as usize)?.as_ref().map(|queue| queue.stats())
     }
 
     /// Get queue
@@ -540,8 +837,8 @@ impl DeviceOps for VirtioDevice {
 
     fn probe(&mut self) -> Result<bool> {
         // Read magic value and version
-        let magic = self.read_config_u32(0);
-        let version = self.read_config_u32(4);
+        let magic = self.read_config_u32(0)?;
+        let version = self.read_config_u32(4)?;
 
         // VirtIO magic value: 0x74726976 ("virt" in little endian)
         if magic != 0x74726976 {
@@ -581,7 +878,9 @@ impl DeviceOps for VirtioDevice {
     fn status(&self) -> DeviceStatus {
         // Convert VirtIO status to device status
         let virtio_status = self.status.lock();
-        if virtio_status.has(VirtioDeviceStatus::DRIVER_OK) {
+        if virtio_status.has(VirtioDeviceStatus::FAILED) {
+            DeviceStatus::Error
+        } else if virtio_status.has(VirtioDeviceStatus::DRIVER_OK) {
             DeviceStatus::Ready
         } else if virtio_status.has(VirtioDeviceStatus::ACKNOWLEDGE) {
             DeviceStatus::Initializing
@@ -604,22 +903,35 @@ impl DeviceOps for VirtioDevice {
 
     fn handle_interrupt(&mut self, irq: u32) -> Result<()> {
         // Read interrupt status
-        let status = self.read_config_u32(2);
+        let status = self.read_config_u32(2)?;
 
         if status != 0 {
             crate::debug!("VirtIO device '{}' interrupt status: 0x{:x}", self.name, status);
 
             // Acknowledge interrupt
-            self.write_config_u32(3, status);
+            self.write_config_u32(3, status)?;
         }
 
         Ok(())
     }
 
     fn ioctl(&mut self, cmd: u32, arg: u64) -> Result<u64> {
+        use ioctl_cmd::*;
+
         match cmd {
-            0x3000 => Ok(self.read_device_features()?),
-            0x3001 => Ok(*self.driver_features.lock()),
+            DEVICE_FEATURES => Ok(self.read_device_features()?),
+            DRIVER_FEATURES => Ok(*self.driver_features.lock()),
+            QUEUE_STATS..=QUEUE_STATS_LAST => self.queue_stats((cmd - QUEUE_STATS) as u16)
+                .map(|stats| stats.pack_indices())
+                .ok_or(Error::InvalidArgument),
+            QUEUE_COMPLETED..=QUEUE_COMPLETED_LAST => self.queue_stats((cmd - QUEUE_COMPLETED) as u16)
+                .map(|stats| stats.completed)
+                .ok_or(Error::InvalidArgument),
+            QUEUE_RESIZE..=QUEUE_RESIZE_LAST => {
+                let new_size = u16::try_from(arg).map_err(|_| Error::InvalidArgument)?;
+                self.resize_queue((cmd - QUEUE_RESIZE) as u16, new_size)?;
+                Ok(0)
+            }
             _ => Err(Error::NotSupported),
         }
     }
@@ -641,6 +953,9 @@ pub fn init() -> Result<()> {
     // Initialize VirtIO RNG driver
     rng::init()?;
 
+    // Initialize VirtIO balloon driver
+    balloon::init()?;
+
     // Initialize VirtIO GPU driver
     gpu::init()?;
 
@@ -688,4 +1003,172 @@ pub fn scan_devices() -> Result<()> {
 
     crate::info!("VirtIO device scan complete");
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    /// Build an 8-entry queue over heap buffers instead of page frames
+    fn test_queue(desc: &mut [u64], avail: &mut [u64], used: &mut [u64]) -> VirtQueue {
+        VirtQueue {
+            size: 8,
+            desc: desc.as_mut_ptr() as VirtAddr,
+            avail: avail.as_mut_ptr() as VirtAddr,
+            used: used.as_mut_ptr() as VirtAddr,
+            last_used_idx: AtomicU16::new(0),
+            avail_idx: AtomicU16::new(0),
+            completed: AtomicU64::new(0),
+            queue_index: 0,
+        }
+    }
+
+    #[test]
+    fn test_queue_stats_outstanding() {
+        let mut desc = vec![0u64; 16];
+        let mut avail = vec![0u64; 8];
+        let mut used = vec![0u64; 16];
+        let queue = test_queue(&mut desc, &mut avail, &mut used);
+
+        queue.add_buf(0, 512, false, false).unwrap();
+        queue.add_buf(1, 512, true, false).unwrap();
+
+        // Device returns descriptor 0
+        unsafe {
+            let ring = &mut *(used.as_mut_ptr() as *mut VirtQueueUsed);
+            let elem = ring.ring.as_mut_ptr() as *mut VirtQueueUsedElem;
+            (*elem).id = 0;
+            (*elem).len = 512;
+            ring.idx = 1;
+        }
+        assert_eq!(queue.get_used_buf(), Some((0, 512)));
+
+        let stats = queue.stats();
+        assert_eq!(stats.avail_idx, 2);
+        assert_eq!(stats.last_used_idx, 1);
+        assert_eq!(stats.outstanding, 1);
+        assert_eq!(stats.completed, 1);
+        assert_eq!(stats.pack_indices(), 2 | 1 << 16 | 1 << 32);
+    }
+
+    #[test]
+    fn test_resize_idle_queue_only() {
+        let mut desc = vec![0u64; 16];
+        let mut avail = vec![0u64; 8];
+        let mut used = vec![0u64; 16];
+        let queue = test_queue(&mut desc, &mut avail, &mut used);
+
+        assert_eq!(queue.check_resize(256), Ok(()));
+        assert_eq!(queue.check_resize(0), Err(Error::InvalidArgument));
+        assert_eq!(queue.check_resize(100), Err(Error::InvalidArgument));
+
+        // A buffer in flight pins the rings
+        queue.add_buf(0, 512, false, false).unwrap();
+        assert_eq!(queue.check_resize(256), Err(Error::ResourceBusy));
+
+        unsafe {
+            let ring = &mut *(used.as_mut_ptr() as *mut VirtQueueUsed);
+            ring.idx = 1;
+        }
+        queue.get_used_buf().unwrap();
+        assert_eq!(queue.check_resize(16), Ok(()));
+    }
+
+    #[test]
+    fn test_resize_bounded_by_queue_num_max() {
+        let mut config: VirtioCommonConfig = unsafe { core::mem::zeroed() };
+        config.queue_num_max = 8;
+        let device = VirtioDevice::new(DeviceType::Block, "virtio-blk", 0, 1, 0,
+                                       &mut config as *mut _ as VirtAddr);
+
+        // Checked before anything is torn down
+        assert_eq!(device.resize_queue(0, 16), Err(Error::InvalidArgument));
+        assert_eq!(device.resize_queue(0, 8), Err(Error::NotFound));
+    }
+
+    #[test]
+    fn test_config_read_retries_on_generation_change() {
+        let mut config: VirtioCommonConfig = unsafe { core::mem::zeroed() };
+        let common = &mut config as *mut VirtioCommonConfig;
+        let mut device_config = [0u32; 4];
+        let words = device_config.as_mut_ptr();
+        let device = VirtioDevice::new(DeviceType::Block, "virtio-blk", 0, 1, 0, common as VirtAddr)
+            .with_device_config(words as VirtAddr, 16);
+
+        // The driver writes device config without touching the generation
+        device.write_config_block(2, &[0x1000, 0x2]).unwrap();
+        assert_eq!(device.config_generation(), Ok(0));
+        let mut values = [0u32; 2];
+        device.read_config_block(2, &mut values).unwrap();
+        assert_eq!(values, [0x1000, 0x2]);
+
+        // Blocks that run past the device config are refused
+        assert_eq!(device.write_config_block(3, &[0, 0]), Err(Error::InvalidArgument));
+        assert_eq!(device.read_config_block(3, &mut values), Err(Error::InvalidArgument));
+
+        // The device updates both halves between the driver's two reads
+        let reads = core::cell::Cell::new(0);
+        let value = read_generation_checked(
+            || device.config_generation(),
+            || {
+                reads.set(reads.get() + 1);
+                let mut lo = [0];
+                device.read_config_block(2, &mut lo)?;
+                if reads.get() == 1 {
+                    unsafe {
+                        words.add(2).write_volatile(0x3000);
+                        words.add(3).write_volatile(0x4);
+                        (*common).config_generation += 1;
+                    }
+                }
+                let mut hi = [0];
+                device.read_config_block(3, &mut hi)?;
+                Ok((hi[0] as u64) << 32 | lo[0] as u64)
+            },
+        );
+        assert_eq!(value, Ok(0x4_0000_3000));
+        assert_eq!(reads.get(), 2);
+
+        // A configuration that never settles gives up
+        let generation = core::cell::Cell::new(0u8);
+        let never_settles = read_generation_checked(
+            || {
+                generation.set(generation.get().wrapping_add(1));
+                Ok(generation.get())
+            },
+            || Ok(()),
+        );
+        assert_eq!(never_settles, Err(Error::Timeout));
+    }
+
+    #[test]
+    fn test_rejected_features_fail_the_device() {
+        let mut config: VirtioCommonConfig = unsafe { core::mem::zeroed() };
+        let device = VirtioDevice::new(DeviceType::Block, "virtio-blk", 0, 1, 0,
+                                       &mut config as *mut _ as VirtAddr);
+        device.acknowledge().unwrap();
+        device.set_driver().unwrap();
+
+        // The device keeps FEATURES_OK: negotiation succeeds
+        device.write_driver_features(features::VERSION_1).unwrap();
+        assert!(device.status.lock().has(VirtioDeviceStatus::FEATURES_OK));
+        assert_eq!(device.read_status(), Ok(device.status.lock().value()));
+
+        // The device clears FEATURES_OK: the driver gives up on it
+        device.reset().unwrap();
+        device.acknowledge().unwrap();
+        device.set_driver().unwrap();
+        let rejected = device.set_features_ok(|| {
+            let status = device.read_status()?;
+            Ok(status & !VirtioDeviceStatus::FEATURES_OK)
+        });
+        assert_eq!(rejected, Err(Error::ResourceUnavailable));
+
+        let status = device.status.lock().value();
+        assert_ne!(status & VirtioDeviceStatus::FAILED, 0);
+        assert_eq!(status & VirtioDeviceStatus::FEATURES_OK, 0);
+        assert_eq!(device.read_status(), Ok(status));
+        assert_eq!(device.status(), DeviceStatus::Error);
+    }
+}
@@ -1,7 +1,1037 @@
-//! Empty stub for VirtIO net.rs
+//! VirtIO network device
+//!
+//! Frames travel with a `virtio_net_hdr` describing pending checksum and
+//! segmentation work. The device offers checksum and TSO offloads in both
+//! directions and hands frames to a pluggable `NetBackend`:
+//! - on TX, a large TCP segment goes to the backend as-is if it can do
+//!   TSO itself, otherwise it is cut into MTU-sized frames here
+//! - on RX, offloads the guest did not negotiate are completed in
+//!   software before the frame is delivered
+//!
+//! Received frames are written into the buffer chains the driver made
+//! available on the receive virtqueue in guest memory; frames arriving
+//! while the ring is empty are dropped. Frames to send are taken from the
+//! transmit virtqueue when the driver notifies it.
 
 use crate::{Result, Error};
+use crate::core::sync::SpinLock;
+use crate::core::vmm::VmId;
+use crate::emulators::virtio_mmio::{self, QueueConfig, VirtioBackend, VirtioMmioTransport, VIRTIO_MMIO_INT_VRING};
+use crate::emulators::virtio_mmio::queue::{DescriptorChain, DeviceQueue, GuestMemory, VmMemory};
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// VirtIO network feature bits
+pub mod features {
+    /// Device handles packets with partial checksum
+    pub const CSUM: u64 = 1 << 0;
+    /// Driver handles packets with partial checksum
+    pub const GUEST_CSUM: u64 = 1 << 1;
+    /// Device reports its MTU
+    pub const MTU: u64 = 1 << 3;
+    /// Driver can receive TSOv4
+    pub const GUEST_TSO4: u64 = 1 << 7;
+    /// Driver can receive TSOv6
+    pub const GUEST_TSO6: u64 = 1 << 8;
+    /// Device can receive TSOv4
+    pub const HOST_TSO4: u64 = 1 << 11;
+    /// Device can receive TSOv6
+    pub const HOST_TSO6: u64 = 1 << 12;
+}
+
+/// `virtio_net_hdr` flags
+pub mod hdr_flags {
+    /// Checksum from `csum_start` must be filled in at `csum_offset`
+    pub const NEEDS_CSUM: u8 = 1;
+    /// Checksum already validated
+    pub const DATA_VALID: u8 = 2;
+}
+
+/// `virtio_net_hdr` GSO types
+pub mod gso_type {
+    /// Not a GSO frame
+    pub const NONE: u8 = 0;
+    /// TCP over IPv4
+    pub const TCPV4: u8 = 1;
+    /// UDP fragmentation offload
+    pub const UDP: u8 = 3;
+    /// TCP over IPv6
+    pub const TCPV6: u8 = 4;
+    /// ECN bit set on the segments
+    pub const ECN: u8 = 0x80;
+}
+
+/// Size of `virtio_net_hdr` with VIRTIO_F_VERSION_1
+pub const NET_HDR_LEN: usize = 12;
+
+/// Default MTU
+pub const DEFAULT_MTU: u16 = 1500;
+
+/// Ethernet header length
+const ETH_HLEN: usize = 14;
+/// IPv6 fixed header length
+const IPV6_HLEN: usize = 40;
+/// IPv4 ethertype
+const ETH_P_IP: u16 = 0x0800;
+/// IPv6 ethertype
+const ETH_P_IPV6: u16 = 0x86DD;
+/// TCP protocol number
+const IPPROTO_TCP: u8 = 6;
+/// TCP FIN, PSH and CWR flags
+const TCP_FIN: u8 = 0x01;
+const TCP_PSH: u8 = 0x08;
+const TCP_CWR: u8 = 0x80;
+
+/// VirtIO network packet header
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct VirtioNetHdr {
+    /// Header flags (see `hdr_flags`)
+    pub flags: u8,
+    /// GSO type (see `gso_type`)
+    pub gso_type: u8,
+    /// Length of the Ethernet, IP and TCP headers
+    pub hdr_len: u16,
+    /// Maximum payload per segment
+    pub gso_size: u16,
+    /// Offset at which checksumming starts
+    pub csum_start: u16,
+    /// Offset of the checksum field from `csum_start`
+    pub csum_offset: u16,
+    /// Number of merged receive buffers
+    pub num_buffers: u16,
+}
+
+impl VirtioNetHdr {
+    /// Decode a little-endian header
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < NET_HDR_LEN {
+            return Err(Error::InvalidArgument);
+        }
+        let word = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+        Ok(Self {
+            flags: bytes[0],
+            gso_type: bytes[1],
+            hdr_len: word(2),
+            gso_size: word(4),
+            csum_start: word(6),
+            csum_offset: word(8),
+            num_buffers: word(10),
+        })
+    }
+
+    /// Encode as little-endian bytes
+    pub fn to_bytes(&self) -> [u8; NET_HDR_LEN] {
+        let mut bytes = [0u8; NET_HDR_LEN];
+        bytes[0] = self.flags;
+        bytes[1] = self.gso_type;
+        for (i, word) in [self.hdr_len, self.gso_size, self.csum_start, self.csum_offset, self.num_buffers]
+            .iter()
+            .enumerate()
+        {
+            bytes[2 + i * 2..4 + i * 2].copy_from_slice(&word.to_le_bytes());
+        }
+        bytes
+    }
+}
+
+/// Network backend behind the device
+pub trait NetBackend: Send {
+    /// Offloads the backend performs itself
+    ///
+    /// A combination of `features::CSUM`, `HOST_TSO4` and `HOST_TSO6`.
+    /// Frames needing anything else are finished in software first.
+    fn offloads(&self) -> u64 {
+        0
+    }
+
+    /// Send one frame
+    ///
+    /// `hdr` only requests offloads reported by `offloads`.
+    fn transmit(&mut self, hdr: &VirtioNetHdr, frame: &[u8]) -> Result<()>;
+}
+
+/// Offloads the receiving side of a frame can handle
+#[derive(Debug, Clone, Copy)]
+struct OffloadCaps {
+    csum: bool,
+    tso4: bool,
+    tso6: bool,
+}
+
+/// Header offsets of an Ethernet TCP frame
+#[derive(Debug, Clone, Copy)]
+struct TcpLayout {
+    ipv6: bool,
+    /// Offset of the TCP header
+    tcp: usize,
+    /// Offset of the TCP payload
+    payload: usize,
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn write_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+}
+
+/// Locate the IP and TCP headers of a frame
+fn parse_tcp(frame: &[u8]) -> Result<TcpLayout> {
+    if frame.len() < ETH_HLEN {
+        return Err(Error::InvalidArgument);
+    }
+
+    let (ipv6, tcp) = match read_u16(frame, 12) {
+        ETH_P_IP => {
+            let ihl = (*frame.get(ETH_HLEN).ok_or(Error::InvalidArgument)? as usize & 0xF) * 4;
+            if ihl < 20 || frame.len() < ETH_HLEN + ihl || frame[ETH_HLEN + 9] != IPPROTO_TCP {
+                return Err(Error::InvalidArgument);
+            }
+            (false, ETH_HLEN + ihl)
+        }
+        ETH_P_IPV6 => {
+            if frame.len() < ETH_HLEN + IPV6_HLEN || frame[ETH_HLEN + 6] != IPPROTO_TCP {
+                return Err(Error::InvalidArgument);
+            }
+            (true, ETH_HLEN + IPV6_HLEN)
+        }
+        _ => return Err(Error::InvalidArgument),
+    };
+
+    if frame.len() < tcp + 20 {
+        return Err(Error::InvalidArgument);
+    }
+    let payload = tcp + (frame[tcp + 12] >> 4) as usize * 4;
+    if payload < tcp + 20 || frame.len() < payload {
+        return Err(Error::InvalidArgument);
+    }
+
+    Ok(TcpLayout { ipv6, tcp, payload })
+}
+
+/// Add big-endian 16-bit words to a ones' complement sum
+fn checksum_add(mut sum: u32, data: &[u8]) -> u32 {
+    let mut words = data.chunks_exact(2);
+    for word in &mut words {
+        sum += u16::from_be_bytes([word[0], word[1]]) as u32;
+    }
+    if let [byte] = words.remainder() {
+        sum += (*byte as u32) << 8;
+    }
+    sum
+}
+
+/// Fold a ones' complement sum into a checksum
+fn checksum_fold(mut sum: u32) -> u16 {
+    while sum >> 16 != 0 {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Recompute the IPv4 header checksum
+fn fill_ipv4_checksum(frame: &mut [u8], layout: &TcpLayout) {
+    write_u16(frame, ETH_HLEN + 10, 0);
+    let csum = checksum_fold(checksum_add(0, &frame[ETH_HLEN..layout.tcp]));
+    write_u16(frame, ETH_HLEN + 10, csum);
+}
+
+/// Recompute the TCP checksum including the pseudo-header
+fn fill_tcp_checksum(frame: &mut [u8], layout: &TcpLayout) {
+    let tcp_len = frame.len() - layout.tcp;
+    let pseudo = if layout.ipv6 {
+        let sum = checksum_add(0, &frame[ETH_HLEN + 8..ETH_HLEN + 40]);
+        checksum_add(sum, &(tcp_len as u32).to_be_bytes()) + IPPROTO_TCP as u32
+    } else {
+        let sum = checksum_add(0, &frame[ETH_HLEN + 12..ETH_HLEN + 20]);
+        sum + IPPROTO_TCP as u32 + tcp_len as u32
+    };
+
+    write_u16(frame, layout.tcp + 16, 0);
+    let csum = checksum_fold(checksum_add(pseudo, &frame[layout.tcp..]));
+    write_u16(frame, layout.tcp + 16, csum);
+}
+
+/// Fill in a partial checksum as requested by `NEEDS_CSUM`
+///
+/// The checksum field holds the pseudo-header sum on entry.
+fn complete_checksum(frame: &mut [u8], start: usize, offset: usize) -> Result<()> {
+    if start + offset + 2 > frame.len() {
+        return Err(Error::InvalidArgument);
+    }
+    let csum = checksum_fold(checksum_add(0, &frame[start..]));
+    write_u16(frame, start + offset, csum);
+    Ok(())
+}
+
+/// Split a TCP frame into frames of at most `mss` payload bytes
+///
+/// The MSS is further limited so each IP packet fits in `mtu`. Every
+/// segment gets fresh lengths, sequence number and checksums.
+fn segment_tcp(frame: &[u8], mss: usize, mtu: usize) -> Result<Vec<Vec<u8>>> {
+    let layout = parse_tcp(frame)?;
+    let mss = mss.min(mtu.saturating_sub(layout.payload - ETH_HLEN));
+    if mss == 0 {
+        return Err(Error::InvalidArgument);
+    }
+
+    let headers = &frame[..layout.payload];
+    let payload = &frame[layout.payload..];
+    let seq = u32::from_be_bytes([
+        frame[layout.tcp + 4], frame[layout.tcp + 5], frame[layout.tcp + 6], frame[layout.tcp + 7],
+    ]);
+    let ip_id = read_u16(frame, ETH_HLEN + 4);
+    let count = payload.len().div_ceil(mss).max(1);
+
+    let mut segments = Vec::with_capacity(count);
+    for i in 0..count {
+        let chunk = &payload[(i * mss).min(payload.len())..((i + 1) * mss).min(payload.len())];
+        let mut segment = Vec::with_capacity(headers.len() + chunk.len());
+        segment.extend_from_slice(headers);
+        segment.extend_from_slice(chunk);
+
+        if layout.ipv6 {
+            let len = segment.len() - ETH_HLEN - IPV6_HLEN;
+            write_u16(&mut segment, ETH_HLEN + 4, len as u16);
+        } else {
+            let len = segment.len() - ETH_HLEN;
+            write_u16(&mut segment, ETH_HLEN + 2, len as u16);
+            write_u16(&mut segment, ETH_HLEN + 4, ip_id.wrapping_add(i as u16));
+            fill_ipv4_checksum(&mut segment, &layout);
+        }
+
+        let seg_seq = seq.wrapping_add((i * mss) as u32);
+        segment[layout.tcp + 4..layout.tcp + 8].copy_from_slice(&seg_seq.to_be_bytes());
+        if i + 1 < count {
+            segment[layout.tcp + 13] &= !(TCP_FIN | TCP_PSH);
+        }
+        if i > 0 {
+            segment[layout.tcp + 13] &= !TCP_CWR;
+        }
+        fill_tcp_checksum(&mut segment, &layout);
+
+        segments.push(segment);
+    }
+
+    Ok(segments)
+}
+
+/// Finish whatever offloads the receiver of a frame cannot do itself
+fn apply_offloads(hdr: &VirtioNetHdr, mut frame: Vec<u8>, caps: OffloadCaps, mtu: usize)
+    -> Result<Vec<(VirtioNetHdr, Vec<u8>)>>
+{
+    match hdr.gso_type & !gso_type::ECN {
+        gso_type::NONE => {
+            let mut out = *hdr;
+            if hdr.flags & hdr_flags::NEEDS_CSUM != 0 && !caps.csum {
+                complete_checksum(&mut frame, hdr.csum_start as usize, hdr.csum_offset as usize)?;
+                out.flags &= !hdr_flags::NEEDS_CSUM;
+            }
+            if !caps.csum {
+                out.flags &= !hdr_flags::DATA_VALID;
+            }
+            Ok(vec![(out, frame)])
+        }
+        gso @ (gso_type::TCPV4 | gso_type::TCPV6) => {
+            let layout = parse_tcp(&frame)?;
+            if hdr.gso_size == 0 || layout.ipv6 != (gso == gso_type::TCPV6) {
+                return Err(Error::InvalidArgument);
+            }
+
+            let offloaded = if layout.ipv6 { caps.tso6 } else { caps.tso4 };
+            if offloaded {
+                let mut out = *hdr;
+                out.hdr_len = layout.payload as u16;
+                return Ok(vec![(out, frame)]);
+            }
+
+            let segments = segment_tcp(&frame, hdr.gso_size as usize, mtu)?;
+            Ok(segments.into_iter().map(|segment| (VirtioNetHdr::default(), segment)).collect())
+        }
+        _ => Err(Error::InvalidArgument),
+    }
+}
+
+/// Callback used to notify the guest of received frames
+pub type NetNotifyFn = fn(irq: u32);
+
+/// VirtIO device ID of the network device
+pub const VIRTIO_ID_NET: u32 = 1;
+
+/// Index of the receive virtqueue
+pub const RX_QUEUE: usize = 0;
+
+/// Index of the transmit virtqueue
+pub const TX_QUEUE: usize = 1;
+
+/// Largest queue size offered to the driver
+const NET_QUEUE_MAX: u16 = 256;
+
+/// Offset of `mtu` in the device configuration space
+const CONFIG_MTU: usize = 10;
+
+/// Scatter `data` over the device-writable buffers of `chain`
+///
+/// Fails with `Error::InvalidArgument`, writing nothing, if the buffers
+/// are too small.
+fn write_chain(mem: &dyn GuestMemory, chain: &DescriptorChain, data: &[u8]) -> Result<()> {
+    let room: u64 = chain.writable().map(|desc| desc.len as u64).sum();
+    if room < data.len() as u64 {
+        return Err(Error::InvalidArgument);
+    }
+
+    let mut rest = data;
+    for desc in chain.writable() {
+        if rest.is_empty() {
+            break;
+        }
+        let (head, tail) = rest.split_at(rest.len().min(desc.len as usize));
+        mem.write(desc.addr, head)?;
+        rest = tail;
+    }
+    Ok(())
+}
+
+/// VirtIO network device
+pub struct VirtioNet {
+    /// Network backend
+    backend: Box<dyn NetBackend>,
+    /// Device MTU
+    mtu: u16,
+    /// Features accepted by the driver
+    driver_features: u64,
+    /// VM whose memory holds the virtqueues
+    vm_id: Option<VmId>,
+    /// Receive virtqueue, once the driver made it live
+    rx_queue: Option<DeviceQueue>,
+    /// Transmit virtqueue, once the driver made it live
+    tx_queue: Option<DeviceQueue>,
+    /// Receive buffers were used since the transport last polled
+    rx_used: bool,
+    /// Frames dropped for lack of a receive buffer
+    rx_dropped: u64,
+    /// Device interrupt
+    irq: u32,
+    /// Guest notification
+    notify: Option<NetNotifyFn>,
+}
+
+impl VirtioNet {
+    /// Create a network device on top of `backend`
+    pub fn new(backend: Box<dyn NetBackend>, mtu: u16) -> Self {
+        Self {
+            backend,
+            mtu,
+            driver_features: 0,
+            vm_id: None,
+            rx_queue: None,
+            tx_queue: None,
+            rx_used: false,
+            rx_dropped: 0,
+            irq: 0,
+            notify: None,
+        }
+    }
+
+    /// Device MTU, as reported in the device configuration
+    pub fn mtu(&self) -> u16 {
+        self.mtu
+    }
+
+    /// Features offered to the driver
+    ///
+    /// Offloads are offered regardless of the backend; whatever it cannot
+    /// do is done in software.
+    pub fn device_features(&self) -> u64 {
+        features::CSUM | features::GUEST_CSUM | features::MTU
+            | features::HOST_TSO4 | features::HOST_TSO6
+            | features::GUEST_TSO4 | features::GUEST_TSO6
+    }
+
+    /// Accept the driver's feature selection
+    ///
+    /// TSO in either direction depends on checksum offload in that
+    /// direction.
+    pub fn set_driver_features(&mut self, driver: u64) -> Result<()> {
+        if driver & !self.device_features() != 0 {
+            return Err(Error::InvalidArgument);
+        }
+        if driver & (features::HOST_TSO4 | features::HOST_TSO6) != 0 && driver & features::CSUM == 0 {
+            return Err(Error::InvalidArgument);
+        }
+        if driver & (features::GUEST_TSO4 | features::GUEST_TSO6) != 0 && driver & features::GUEST_CSUM == 0 {
+            return Err(Error::InvalidArgument);
+        }
+        self.driver_features = driver;
+        Ok(())
+    }
+
+    /// Features accepted by the driver
+    pub fn driver_features(&self) -> u64 {
+        self.driver_features
+    }
+
+    fn negotiated(&self, feature: u64) -> bool {
+        self.driver_features & feature != 0
+    }
+
+    /// Replace the network backend
+    pub fn set_backend(&mut self, backend: Box<dyn NetBackend>) {
+        self.backend = backend;
+    }
+
+    /// Serve the guest `vm_id`, whose memory holds the virtqueues
+    pub fn set_vm(&mut self, vm_id: VmId) {
+        self.vm_id = Some(vm_id);
+    }
+
+    /// Start servicing the receive virtqueue set up as `config`
+    pub fn activate_rx_queue(&mut self, config: QueueConfig) {
+        self.rx_queue = Some(DeviceQueue::new(config));
+    }
+
+    /// Stop servicing the receive virtqueue
+    pub fn deactivate_rx_queue(&mut self) {
+        self.rx_queue = None;
+    }
+
+    /// Set the guest notification callback and the interrupt it raises
+    pub fn set_notify(&mut self, irq: u32, notify: NetNotifyFn) {
+        self.irq = irq;
+        self.notify = Some(notify);
+    }
+
+    /// Frames dropped because no receive buffer was available
+    pub fn rx_dropped(&self) -> u64 {
+        self.rx_dropped
+    }
+
+    /// Deliver a frame from the host to the guest
+    ///
+    /// The frame is written, after a `virtio_net_hdr` requesting no
+    /// offloads, into the next chain available on the receive queue,
+    /// which is marked used, and the guest is notified. Without a receive
+    /// buffer the frame is dropped and counted, failing with
+    /// `Error::ResourceBusy`. A frame that does not fit the chain is
+    /// dropped too, failing with `Error::InvalidArgument`; the chain goes
+    /// back to the driver empty.
+    pub fn receive_frame(&mut self, frame: &[u8]) -> Result<()> {
+        let vm_id = self.vm_id.ok_or(Error::NotInitialized)?;
+        self.deliver(&VmMemory { vm_id }, frame)
+    }
+
+    /// `receive_frame` into the receive queue in `mem`
+    fn deliver(&mut self, mem: &dyn GuestMemory, frame: &[u8]) -> Result<()> {
+        let queue = self.rx_queue.as_mut().ok_or(Error::NotInitialized)?;
+        let Some(chain) = queue.pop(mem)? else {
+            self.rx_dropped += 1;
+            return Err(Error::ResourceBusy);
+        };
+
+        let hdr = VirtioNetHdr { num_buffers: 1, ..VirtioNetHdr::default() };
+        let mut data = Vec::with_capacity(NET_HDR_LEN + frame.len());
+        data.extend_from_slice(&hdr.to_bytes());
+        data.extend_from_slice(frame);
+        let written = write_chain(mem, &chain, &data);
+        queue.push_used(mem, chain.head, if written.is_ok() { data.len() as u32 } else { 0 })?;
+        self.rx_used = true;
+        if let Some(notify) = self.notify {
+            notify(self.irq);
+        }
+        if written.is_err() {
+            self.rx_dropped += 1;
+        }
+        written
+    }
+
+    /// Transmit a frame from the driver
+    ///
+    /// Rejects headers requesting offloads the driver did not negotiate.
+    /// Returns the number of frames handed to the backend.
+    pub fn transmit(&mut self, hdr: &VirtioNetHdr, frame: Vec<u8>) -> Result<usize> {
+        if hdr.flags & hdr_flags::NEEDS_CSUM != 0 && !self.negotiated(features::CSUM) {
+            return Err(Error::InvalidArgument);
+        }
+        let allowed = match hdr.gso_type {
+            gso_type::NONE => true,
+            gso_type::TCPV4 => self.negotiated(features::HOST_TSO4),
+            gso_type::TCPV6 => self.negotiated(features::HOST_TSO6),
+            _ => false,
+        };
+        if !allowed {
+            return Err(Error::InvalidArgument);
+        }
+
+        let offloads = self.backend.offloads();
+        let caps = OffloadCaps {
+            csum: offloads & features::CSUM != 0,
+            tso4: offloads & features::HOST_TSO4 != 0,
+            tso6: offloads & features::HOST_TSO6 != 0,
+        };
+        let frames = apply_offloads(hdr, frame, caps, self.mtu as usize)?;
+        for (hdr, frame) in &frames {
+            self.backend.transmit(hdr, frame)?;
+        }
+        Ok(frames.len())
+    }
+
+    /// Send every frame the driver made available on the transmit queue
+    /// in `mem`
+    ///
+    /// Each chain holds a `virtio_net_hdr` followed by the frame. Frames
+    /// the device cannot send are dropped. Returns the number of chains
+    /// returned to the driver.
+    fn service_tx(&mut self, mem: &dyn GuestMemory) -> Result<usize> {
+        let mut completed = 0;
+        while let Some(chain) = self.tx_queue.as_mut().ok_or(Error::NotInitialized)?.pop(mem)? {
+            if let Err(e) = self.send_chain(mem, &chain) {
+                crate::debug!("virtio-net: dropped frame in chain {}: {:?}", chain.head, e);
+            }
+            self.tx_queue.as_mut().ok_or(Error::NotInitialized)?.push_used(mem, chain.head, 0)?;
+            completed += 1;
+        }
+        Ok(completed)
+    }
+
+    /// Gather the header and frame of `chain` and transmit them
+    fn send_chain(&mut self, mem: &dyn GuestMemory, chain: &DescriptorChain) -> Result<()> {
+        let len: usize = chain.readable().map(|desc| desc.len as usize).sum();
+        if len < NET_HDR_LEN || len > NET_HDR_LEN + u16::MAX as usize {
+            return Err(Error::InvalidArgument);
+        }
+
+        let mut data = vec![0u8; len];
+        let mut offset = 0;
+        for desc in chain.readable() {
+            mem.read(desc.addr, &mut data[offset..offset + desc.len as usize])?;
+            offset += desc.len as usize;
+        }
+        let hdr = VirtioNetHdr::from_bytes(&data)?;
+        self.transmit(&hdr, data.split_off(NET_HDR_LEN)).map(|_| ())
+    }
+
+    /// Prepare a frame from the backend for delivery to the driver
+    ///
+    /// `hdr` describes offloads still pending on the frame. Returns the
+    /// frames to place in the receive queue with their headers.
+    pub fn receive(&self, hdr: &VirtioNetHdr, frame: Vec<u8>) -> Result<Vec<(VirtioNetHdr, Vec<u8>)>> {
+        let caps = OffloadCaps {
+            csum: self.negotiated(features::GUEST_CSUM),
+            tso4: self.negotiated(features::GUEST_TSO4),
+            tso6: self.negotiated(features::GUEST_TSO6),
+        };
+        apply_offloads(hdr, frame, caps, self.mtu as usize)
+    }
+}
+
+impl VirtioBackend for VirtioNet {
+    fn device_id(&self) -> u32 {
+        VIRTIO_ID_NET
+    }
+
+    fn features(&self) -> u64 {
+        self.device_features()
+    }
+
+    fn num_queues(&self) -> usize {
+        2
+    }
+
+    fn queue_max(&self, _index: usize) -> u16 {
+        NET_QUEUE_MAX
+    }
+
+    fn set_driver_features(&mut self, features: u64) {
+        if let Err(e) = VirtioNet::set_driver_features(self, features) {
+            crate::warn!("virtio-net: rejected driver features {:#x}: {:?}", features, e);
+        }
+    }
+
+    fn activate_queue(&mut self, index: usize, config: QueueConfig) -> Result<()> {
+        match index {
+            RX_QUEUE => self.activate_rx_queue(config),
+            TX_QUEUE => self.tx_queue = Some(DeviceQueue::new(config)),
+            _ => return Err(Error::InvalidArgument),
+        }
+        Ok(())
+    }
+
+    fn deactivate_queue(&mut self, index: usize) {
+        match index {
+            RX_QUEUE => self.deactivate_rx_queue(),
+            TX_QUEUE => self.tx_queue = None,
+            _ => {}
+        }
+    }
+
+    fn notify(&mut self, index: usize) -> u32 {
+        // New receive buffers are picked up by the next received frame
+        let Some(vm_id) = self.vm_id.filter(|_| index == TX_QUEUE) else {
+            return 0;
+        };
+
+        match self.service_tx(&VmMemory { vm_id }) {
+            Ok(0) => 0,
+            Ok(_) => VIRTIO_MMIO_INT_VRING,
+            Err(e) => {
+                crate::warn!("virtio-net: VM {}: bad transmit queue: {:?}", vm_id, e);
+                VIRTIO_MMIO_INT_VRING
+            }
+        }
+    }
+
+    /// Configuration space: only `mtu` is offered
+    fn read_config(&self, offset: u64, size: u32) -> u64 {
+        let mut config = [0u8; CONFIG_MTU + 2];
+        config[CONFIG_MTU..].copy_from_slice(&self.mtu.to_le_bytes());
+        virtio_mmio::read_config_bytes(&config, offset, size)
+    }
+
+    fn reset(&mut self) {
+        self.rx_queue = None;
+        self.tx_queue = None;
+        self.rx_used = false;
+        self.driver_features = 0;
+    }
+
+    /// Interrupt for frames delivered by `receive_frame`
+    fn poll(&mut self) -> u32 {
+        if core::mem::take(&mut self.rx_used) { VIRTIO_MMIO_INT_VRING } else { 0 }
+    }
+}
+
+/// Global network device, shared with the transport of the VM it serves
+static NET_DEVICE: SpinLock<Option<Arc<SpinLock<VirtioNet>>>> = SpinLock::new(None);
+
+/// Attach a network backend to the network device
+pub fn attach_backend(backend: Box<dyn NetBackend>) {
+    crate::info!("virtio-net: attached backend (offloads {:#x})", backend.offloads());
+
+    let mut device = NET_DEVICE.lock();
+    match device.as_ref() {
+        Some(device) => device.lock().set_backend(backend),
+        None => *device = Some(Arc::new(SpinLock::new(VirtioNet::new(backend, DEFAULT_MTU)))),
+    }
+}
+
+/// Transmit a frame through the global network device
+pub fn transmit(hdr: &VirtioNetHdr, frame: Vec<u8>) -> Result<usize> {
+    NET_DEVICE.lock()
+        .as_ref()
+        .ok_or(Error::NotInitialized)?
+        .lock()
+        .transmit(hdr, frame)
+}
+
+/// Deliver a frame from the host to the guest through the global network device
+pub fn receive_frame(frame: &[u8]) -> Result<()> {
+    NET_DEVICE.lock()
+        .as_ref()
+        .ok_or(Error::NotInitialized)?
+        .lock()
+        .receive_frame(frame)
+}
+
+/// Emulator registry name of the network device of a VM, also the name
+/// of its interrupt route
+pub fn device_name(vm_id: VmId) -> String {
+    format!("virtio-net.{}", vm_id)
+}
+
+/// Give a VM the global network device as a virtio-mmio device at
+/// `base`, interrupting on guest `irq`
+///
+/// The device serves one VM at a time: fails with `Error::ResourceBusy`
+/// while another VM has it, and with `Error::NotInitialized` before a
+/// backend is attached.
+pub fn attach(vm_id: VmId, base: u64, irq: u32) -> Result<()> {
+    let device = NET_DEVICE.lock().clone().ok_or(Error::NotInitialized)?;
+    {
+        let mut device = device.lock();
+        if device.vm_id.is_some() {
+            return Err(Error::ResourceBusy);
+        }
+        device.set_vm(vm_id);
+    }
+
+    let name = device_name(vm_id);
+    let mut transport = VirtioMmioTransport::new(base, irq, device.clone());
+    transport.set_guest_irq(vm_id, &name);
+    if let Err(e) = virtio_mmio::register(vm_id, &name, transport) {
+        device.lock().vm_id = None;
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Take the global network device back from a VM, if it has it
+pub fn detach(vm_id: VmId) {
+    let Some(device) = NET_DEVICE.lock().clone() else {
+        return;
+    };
+    if device.lock().vm_id != Some(vm_id) {
+        return;
+    }
+
+    crate::emulator::unregister_emulator(vm_id, &device_name(vm_id)).ok();
+    let mut device = device.lock();
+    device.vm_id = None;
+    VirtioBackend::reset(&mut *device);
+}
 
 pub fn init() -> Result<()> {
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::sync::Arc;
+
+    type Sent = Arc<SpinLock<Vec<(VirtioNetHdr, Vec<u8>)>>>;
+
+    /// Backend recording transmitted frames
+    struct CaptureBackend {
+        offloads: u64,
+        sent: Sent,
+    }
+
+    fn capture(offloads: u64) -> (Box<dyn NetBackend>, Sent) {
+        let sent = Arc::new(SpinLock::new(Vec::new()));
+        (Box::new(CaptureBackend { offloads, sent: sent.clone() }), sent)
+    }
+
+    impl NetBackend for CaptureBackend {
+        fn offloads(&self) -> u64 {
+            self.offloads
+        }
+
+        fn transmit(&mut self, hdr: &VirtioNetHdr, frame: &[u8]) -> Result<()> {
+            self.sent.lock().push((*hdr, frame.to_vec()));
+            Ok(())
+        }
+    }
+
+    /// IPv4 TCP frame with `payload_len` bytes of payload and PSH|FIN set
+    fn tcp4_frame(payload_len: usize) -> Vec<u8> {
+        let mut frame = vec![0u8; ETH_HLEN + 20 + 20];
+        write_u16(&mut frame, 12, ETH_P_IP);
+        frame[ETH_HLEN] = 0x45;
+        write_u16(&mut frame, ETH_HLEN + 2, (40 + payload_len) as u16);
+        write_u16(&mut frame, ETH_HLEN + 4, 0x100);
+        frame[ETH_HLEN + 8] = 64;
+        frame[ETH_HLEN + 9] = IPPROTO_TCP;
+        frame[ETH_HLEN + 12..ETH_HLEN + 16].copy_from_slice(&[10, 0, 0, 1]);
+        frame[ETH_HLEN + 16..ETH_HLEN + 20].copy_from_slice(&[10, 0, 0, 2]);
+        let tcp = ETH_HLEN + 20;
+        frame[tcp + 4..tcp + 8].copy_from_slice(&1000u32.to_be_bytes());
+        frame[tcp + 12] = 5 << 4;
+        frame[tcp + 13] = TCP_PSH | TCP_FIN | 0x10;
+        frame.extend((0..payload_len).map(|i| i as u8));
+        frame
+    }
+
+    fn tso4_hdr(gso_size: u16) -> VirtioNetHdr {
+        VirtioNetHdr {
+            flags: hdr_flags::NEEDS_CSUM,
+            gso_type: gso_type::TCPV4,
+            hdr_len: 54,
+            gso_size,
+            csum_start: 34,
+            csum_offset: 16,
+            num_buffers: 0,
+        }
+    }
+
+    fn offload_driver_features() -> u64 {
+        features::CSUM | features::HOST_TSO4 | features::GUEST_CSUM
+    }
+
+    #[test]
+    fn test_header_round_trip() {
+        let hdr = tso4_hdr(1448);
+        assert_eq!(VirtioNetHdr::from_bytes(&hdr.to_bytes()), Ok(hdr));
+        assert_eq!(VirtioNetHdr::from_bytes(&[0; 4]), Err(Error::InvalidArgument));
+    }
+
+    #[test]
+    fn test_feature_dependencies() {
+        let (backend, _) = capture(0);
+        let mut net = VirtioNet::new(backend, DEFAULT_MTU);
+        assert_eq!(net.set_driver_features(features::HOST_TSO4), Err(Error::InvalidArgument));
+        assert_eq!(net.set_driver_features(features::GUEST_TSO6 | features::CSUM), Err(Error::InvalidArgument));
+        assert_eq!(net.set_driver_features(1 << 40), Err(Error::InvalidArgument));
+        net.set_driver_features(offload_driver_features()).unwrap();
+
+        // TSOv6 was not negotiated
+        let mut hdr = tso4_hdr(1448);
+        hdr.gso_type = gso_type::TCPV6;
+        assert_eq!(net.transmit(&hdr, tcp4_frame(4000)), Err(Error::InvalidArgument));
+    }
+
+    #[test]
+    fn test_tso4_passed_through_to_capable_backend() {
+        let (backend, sent) = capture(features::CSUM | features::HOST_TSO4);
+        let mut net = VirtioNet::new(backend, DEFAULT_MTU);
+        net.set_driver_features(offload_driver_features()).unwrap();
+
+        let frame = tcp4_frame(4000);
+        assert_eq!(net.transmit(&tso4_hdr(1448), frame.clone()), Ok(1));
+
+        let sent = sent.lock();
+        assert_eq!(sent[0].0.gso_type, gso_type::TCPV4);
+        assert_eq!(sent[0].0.gso_size, 1448);
+        assert_eq!(sent[0].1, frame);
+    }
+
+    #[test]
+    fn test_tso4_segmented_for_plain_backend() {
+        let (backend, sent) = capture(0);
+        let mut net = VirtioNet::new(backend, DEFAULT_MTU);
+        net.set_driver_features(offload_driver_features()).unwrap();
+
+        // gso_size above what fits the MTU is clamped to 1460
+        let frame = tcp4_frame(4000);
+        assert_eq!(net.transmit(&tso4_hdr(9000), frame.clone()), Ok(3));
+
+        let sent = sent.lock();
+        let mut payload = Vec::new();
+        for (i, (hdr, segment)) in sent.iter().enumerate() {
+            assert_eq!(*hdr, VirtioNetHdr::default());
+            assert!(segment.len() - ETH_HLEN <= DEFAULT_MTU as usize);
+            assert_eq!(read_u16(segment, ETH_HLEN + 2) as usize, segment.len() - ETH_HLEN);
+            assert_eq!(read_u16(segment, ETH_HLEN + 4), 0x100 + i as u16);
+
+            let tcp = ETH_HLEN + 20;
+            let seq = u32::from_be_bytes([segment[tcp + 4], segment[tcp + 5], segment[tcp + 6], segment[tcp + 7]]);
+            assert_eq!(seq, 1000 + (i * 1460) as u32);
+            let last = i == sent.len() - 1;
+            assert_eq!(segment[tcp + 13] & (TCP_FIN | TCP_PSH) != 0, last);
+
+            // Checksums verify to zero
+            assert_eq!(checksum_fold(checksum_add(0, &segment[ETH_HLEN..tcp])), 0);
+            let pseudo = checksum_add(0, &segment[ETH_HLEN + 12..ETH_HLEN + 20])
+                + IPPROTO_TCP as u32 + (segment.len() - tcp) as u32;
+            assert_eq!(checksum_fold(checksum_add(pseudo, &segment[tcp..])), 0);
+
+            payload.extend_from_slice(&segment[tcp + 20..]);
+        }
+        assert_eq!(payload, frame[ETH_HLEN + 40..]);
+    }
+
+    #[test]
+    fn test_rx_offloads_completed_for_guest() {
+        let (backend, _) = capture(0);
+        let mut net = VirtioNet::new(backend, DEFAULT_MTU);
+        net.set_driver_features(features::GUEST_CSUM | features::GUEST_TSO4).unwrap();
+        let frames = net.receive(&tso4_hdr(1448), tcp4_frame(4000)).unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].0.gso_type, gso_type::TCPV4);
+
+        net.set_driver_features(0).unwrap();
+        let frames = net.receive(&tso4_hdr(1448), tcp4_frame(4000)).unwrap();
+        assert_eq!(frames.len(), 3);
+        assert!(frames.iter().all(|(hdr, _)| hdr.flags == 0 && hdr.gso_type == gso_type::NONE));
+
+        let mut bad = tso4_hdr(0);
+        assert_eq!(net.receive(&bad, tcp4_frame(100)), Err(Error::InvalidArgument));
+        bad.gso_type = gso_type::UDP;
+        assert_eq!(net.receive(&bad, tcp4_frame(100)), Err(Error::InvalidArgument));
+    }
+
+    static RX_NOTIFIED: core::sync::atomic::AtomicU32 = core::sync::atomic::AtomicU32::new(0);
+
+    fn rx_notify(_irq: u32) {
+        RX_NOTIFIED.fetch_add(1, core::sync::atomic::Ordering::SeqCst);
+    }
+
+    #[test]
+    fn test_receive_frame_fills_rx_buffer() {
+        use crate::emulators::virtio_mmio::queue::{FlatMemory, VIRTQ_DESC_F_NEXT, VIRTQ_DESC_F_WRITE};
+
+        let (backend, _) = capture(0);
+        let mut net = VirtioNet::new(backend, DEFAULT_MTU);
+        net.set_notify(5, rx_notify);
+        let (mem, queue) = FlatMemory::with_queue(0x10000, 4);
+        net.rx_queue = Some(queue);
+
+        // RX starved: the frame is dropped and counted
+        let frame = tcp4_frame(100);
+        assert_eq!(net.deliver(&mem, &frame), Err(Error::ResourceBusy));
+        assert_eq!(net.rx_dropped(), 1);
+        assert_eq!(mem.read_u16(0x3002), Ok(0));
+
+        // A two-buffer chain: the header and part of the frame land in
+        // the first buffer, the rest in the second
+        mem.write_desc(3, 0x8000, 64, VIRTQ_DESC_F_WRITE | VIRTQ_DESC_F_NEXT, 1);
+        mem.write_desc(1, 0x9000, 1514, VIRTQ_DESC_F_WRITE, 0);
+        mem.make_available(0, 3);
+        net.deliver(&mem, &frame).unwrap();
+        assert_eq!(mem.read_u16(0x3002), Ok(1));
+        assert_eq!(mem.read_u32(0x3004), Ok(3));
+        assert_eq!(mem.read_u32(0x3008), Ok((NET_HDR_LEN + frame.len()) as u32));
+        assert_eq!(RX_NOTIFIED.load(core::sync::atomic::Ordering::SeqCst), 1);
+
+        let mut hdr = [0; NET_HDR_LEN];
+        mem.read(0x8000, &mut hdr).unwrap();
+        assert_eq!(VirtioNetHdr::from_bytes(&hdr), Ok(VirtioNetHdr { num_buffers: 1, ..VirtioNetHdr::default() }));
+        let mut received = vec![0; frame.len()];
+        let split = 64 - NET_HDR_LEN;
+        mem.read(0x8000 + NET_HDR_LEN as u64, &mut received[..split]).unwrap();
+        mem.read(0x9000, &mut received[split..]).unwrap();
+        assert_eq!(received, frame);
+
+        // The chain was consumed
+        assert_eq!(net.deliver(&mem, &frame), Err(Error::ResourceBusy));
+        assert_eq!(net.rx_dropped(), 2);
+
+        // A chain too small for the frame is returned empty
+        mem.write_desc(0, 0x8000, 64, VIRTQ_DESC_F_WRITE, 0);
+        mem.make_available(1, 0);
+        assert_eq!(net.deliver(&mem, &frame), Err(Error::InvalidArgument));
+        assert_eq!(mem.read_u16(0x3002), Ok(2));
+        assert_eq!(mem.read_u32(0x3010), Ok(0));
+        assert_eq!(net.rx_dropped(), 3);
+
+        // The transport raises the interrupt for the used buffers once
+        assert_eq!(net.poll(), VIRTIO_MMIO_INT_VRING);
+        assert_eq!(net.poll(), 0);
+    }
+
+    #[test]
+    fn test_transmit_queue_frames_sent() {
+        use crate::emulators::virtio_mmio::queue::{FlatMemory, VIRTQ_DESC_F_NEXT};
+
+        let (backend, sent) = capture(0);
+        let mut net = VirtioNet::new(backend, DEFAULT_MTU);
+        assert_eq!(net.read_config(CONFIG_MTU as u64, 16), DEFAULT_MTU as u64);
+        let (mem, queue) = FlatMemory::with_queue(0x10000, 4);
+        net.tx_queue = Some(queue);
+
+        // Header in its own buffer, the frame in the next
+        let frame = tcp4_frame(100);
+        mem.write(0x8000, &VirtioNetHdr::default().to_bytes()).unwrap();
+        mem.write(0x9000, &frame).unwrap();
+        mem.write_desc(0, 0x8000, NET_HDR_LEN as u32, VIRTQ_DESC_F_NEXT, 1);
+        mem.write_desc(1, 0x9000, frame.len() as u32, 0, 0);
+        mem.make_available(0, 0);
+
+        // A header requesting offloads that were not negotiated is dropped
+        mem.write(0xA000, &tso4_hdr(1448).to_bytes()).unwrap();
+        mem.write_desc(2, 0xA000, NET_HDR_LEN as u32, VIRTQ_DESC_F_NEXT, 1);
+        mem.make_available(1, 2);
+
+        assert_eq!(net.service_tx(&mem), Ok(2));
+        assert_eq!(mem.read_u16(0x3002), Ok(2));
+        assert_eq!((mem.read_u32(0x3004), mem.read_u32(0x300C)), (Ok(0), Ok(2)));
+        let sent = sent.lock();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0], (VirtioNetHdr::default(), frame));
+    }
+}
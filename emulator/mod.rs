@@ -3,54 +3,22 @@
 //! Provides virtualization support for emulating hardware devices
 //! that guests expect to find in the system.
 
-use crate::{Error, Result};
+use crate::core::sync::SpinLock;
+use crate::core::vmm::VmId;
+use crate::Result;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
 
 /// Initialize device emulators
+///
+/// Devices are instantiated per VM when it is created; see
+/// `crate::emulators::attach`.
 pub fn init() -> Result<()> {
-    log::info!("Initializing device emulators");
-
-    // Initialize common device emulators
-    init_basic_devices()?;
-
     log::info!("Device emulators initialized successfully");
     Ok(())
 }
 
-/// Initialize basic device emulators
-fn init_basic_devices() -> Result<()> {
-    // Initialize UART emulator
-    init_uart_emulator()?;
-
-    // Initialize timer emulator
-    init_timer_emulator()?;
-
-    // Initialize interrupt controller emulator
-    init_interrupt_controller_emulator()?;
-
-    Ok(())
-}
-
-/// Initialize UART emulator
-fn init_uart_emulator() -> Result<()> {
-    log::debug!("Initializing UART emulator");
-    // TODO: Implement UART emulator
-    Ok(())
-}
-
-/// Initialize timer emulator
-fn init_timer_emulator() -> Result<()> {
-    log::debug!("Initializing timer emulator");
-    // TODO: Implement timer emulator
-    Ok(())
-}
-
-/// Initialize interrupt controller emulator
-fn init_interrupt_controller_emulator() -> Result<()> {
-    log::debug!("Initializing interrupt controller emulator");
-    // TODO: Implement interrupt controller emulator
-    Ok(())
-}
-
 /// Run device emulator main loop
 pub fn run() -> ! {
     log::info!("Starting device emulator main loop");
@@ -73,7 +41,8 @@ pub fn run() -> ! {
 
 /// Process device emulation events
 fn process_emulation_events() {
-    // TODO: Process pending device emulation events
+    poll_emulators();
+    crate::core::vmm::vm::release_throttled_irqs();
 }
 
 /// Emulator error types
@@ -81,6 +50,8 @@ fn process_emulation_events() {
 pub enum EmulatorError {
     /// Device not found
     DeviceNotFound,
+    /// Access at an unsupported offset or with an unsupported size
+    InvalidAccess,
     /// Unsupported operation
     UnsupportedOperation,
     /// Invalid configuration
@@ -91,8 +62,449 @@ pub enum EmulatorError {
     Timeout,
 }
 
-impl From<EmulatorError> for Error {
+impl From<EmulatorError> for crate::Error {
     fn from(err: EmulatorError) -> Self {
-        Error::CoreError(crate::core::Error::EmulatorError(err))
+        crate::Error::CoreError(crate::core::Error::EmulatorError(err))
+    }
+}
+
+/// Error type returned by `Emulator` methods
+pub type Error = EmulatorError;
+
+/// Emulated MMIO device
+///
+/// Offsets are relative to the device's base address and access sizes
+/// are in bits (8, 16, 32 or 64).
+pub trait Emulator: Send {
+    /// Device name
+    fn name(&self) -> &str;
+
+    /// Handle a guest read
+    fn read(&self, offset: u64, size: u32) -> core::result::Result<u64, Error>;
+
+    /// Handle a guest write
+    fn write(&mut self, offset: u64, value: u64, size: u32) -> core::result::Result<(), Error>;
+
+    /// Return the device to its power-on state
+    fn reset(&mut self) -> core::result::Result<(), Error>;
+
+    /// Capture the device's register state for live migration
+    ///
+    /// The default fails with `UnsupportedOperation`, so a device that has
+    /// not implemented migration blocks it instead of losing its state.
+    /// Devices without guest-visible state return an empty blob.
+    fn save(&self) -> core::result::Result<Vec<u8>, Error> {
+        Err(Error::UnsupportedOperation)
+    }
+
+    /// Load register state captured by `save` on the source host
+    fn restore(&mut self, state: &[u8]) -> core::result::Result<(), Error> {
+        if state.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::UnsupportedOperation)
+        }
+    }
+
+    /// Offset the device's wall-clock time from host time by `seconds`
+    ///
+    /// Only clock devices use this; others keep the default, which ignores it.
+    fn set_wallclock_offset(&mut self, _seconds: i64) {}
+
+    /// Deliver device-side events raised outside a guest access, such as
+    /// a host-initiated configuration change
+    fn poll(&mut self) {}
+
+    /// Whether the device decodes guest-physical `addr` outside its
+    /// registered window, such as through a BAR the guest programmed
+    fn decodes(&self, _addr: u64) -> bool {
+        false
+    }
+
+    /// Handle a guest read at guest-physical `addr`, which `decodes` claimed
+    fn read_decoded(&self, _addr: u64, _size: u32) -> core::result::Result<u64, Error> {
+        Err(Error::InvalidAccess)
+    }
+
+    /// Handle a guest write at guest-physical `addr`, which `decodes` claimed
+    fn write_decoded(&mut self, _addr: u64, _value: u64, _size: u32) -> core::result::Result<(), Error> {
+        Err(Error::InvalidAccess)
+    }
+}
+
+/// Kind of device an emulator presents to the guest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceClass {
+    /// Serial port
+    Uart,
+    /// Real time clock
+    Rtc,
+    /// General purpose I/O controller
+    Gpio,
+    /// Timer or core-local interruptor
+    Timer,
+    /// Interrupt controller
+    InterruptController,
+    /// Watchdog timer
+    Watchdog,
+    /// Flash memory
+    Flash,
+    /// PCI host bridge
+    PciHost,
+    /// VirtIO device on an MMIO transport
+    Virtio,
+}
+
+/// An emulator in the registry
+pub struct RegisteredEmulator {
+    /// VM the device belongs to
+    pub vm_id: VmId,
+    /// Registration name, unique among the VM's devices
+    pub name: String,
+    /// Device class
+    pub class: DeviceClass,
+    /// Guest-physical MMIO window as `(base, size)`, if memory mapped
+    pub mmio: Option<(u64, u64)>,
+    /// The emulator
+    pub emulator: Box<dyn Emulator>,
+}
+
+/// Registered emulators of all VMs, in registration order
+///
+/// Each VM has its own set of devices; names and MMIO windows only need
+/// to be unique within a VM.
+pub struct EmulatorRegistry {
+    entries: Vec<RegisteredEmulator>,
+}
+
+impl EmulatorRegistry {
+    /// Create an empty registry
+    pub const fn new() -> Self {
+        Self { entries: Vec::new() }
+    }
+
+    /// Add an emulator to a VM
+    ///
+    /// Fails with `Error::ResourceBusy` if the VM already has a device of
+    /// that name.
+    pub fn register(&mut self, vm_id: VmId, name: &str, class: DeviceClass,
+                    emulator: Box<dyn Emulator>) -> Result<()> {
+        self.insert(vm_id, name, class, None, emulator)
+    }
+
+    /// Add an emulator that decodes the guest MMIO window `base..base + size`
+    /// of a VM
+    ///
+    /// Fails with `Error::ResourceBusy` if the VM already has a device of
+    /// that name or one whose window overlaps.
+    pub fn register_mmio(
+        &mut self,
+        vm_id: VmId,
+        name: &str,
+        class: DeviceClass,
+        base: u64,
+        size: u64,
+        emulator: Box<dyn Emulator>,
+    ) -> Result<()> {
+        if size == 0 || base.checked_add(size).is_none() {
+            return Err(crate::Error::InvalidArgument);
+        }
+        let overlaps = self.entries.iter().any(|entry| match entry.mmio {
+            Some((other, other_size)) => entry.vm_id == vm_id && base < other + other_size && other < base + size,
+            None => false,
+        });
+        if overlaps {
+            log::warn!("VM {}: emulator {} window {:#x}..{:#x} overlaps another device",
+                       vm_id, name, base, base + size);
+            return Err(crate::Error::ResourceBusy);
+        }
+        self.insert(vm_id, name, class, Some((base, size)), emulator)
+    }
+
+    fn insert(
+        &mut self,
+        vm_id: VmId,
+        name: &str,
+        class: DeviceClass,
+        mmio: Option<(u64, u64)>,
+        emulator: Box<dyn Emulator>,
+    ) -> Result<()> {
+        if self.get(vm_id, name).is_some() {
+            log::warn!("VM {}: emulator {} already registered", vm_id, name);
+            return Err(crate::Error::ResourceBusy);
+        }
+        self.entries.push(RegisteredEmulator { vm_id, name: String::from(name), class, mmio, emulator });
+        Ok(())
+    }
+
+    /// Remove a VM's emulator by name
+    pub fn unregister(&mut self, vm_id: VmId, name: &str) -> Option<RegisteredEmulator> {
+        let pos = self.entries.iter().position(|entry| entry.vm_id == vm_id && entry.name == name)?;
+        Some(self.entries.remove(pos))
+    }
+
+    /// Remove every emulator of a VM, returning how many there were
+    pub fn remove_vm(&mut self, vm_id: VmId) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|entry| entry.vm_id != vm_id);
+        before - self.entries.len()
+    }
+
+    /// Poll every emulator for pending events
+    pub fn poll_all(&mut self) {
+        for entry in self.entries.iter_mut() {
+            entry.emulator.poll();
+        }
+    }
+
+    /// Find the emulator of a VM whose MMIO window contains `addr`
+    ///
+    /// Returns the emulator and the offset of `addr` in its window.
+    pub fn find_mmio(&mut self, vm_id: VmId, addr: u64) -> Option<(&mut RegisteredEmulator, u64)> {
+        self.entries.iter_mut().find_map(|entry| match entry.mmio {
+            Some((base, size)) if entry.vm_id == vm_id && addr >= base && addr - base < size => {
+                Some((entry, addr - base))
+            }
+            _ => None,
+        })
+    }
+
+    /// Whether a VM's emulator decodes guest-physical `addr`, in its window
+    /// or through `Emulator::decodes`
+    pub fn claims(&mut self, vm_id: VmId, addr: u64) -> bool {
+        self.find_mmio(vm_id, addr).is_some() || self.find_decoder(vm_id, addr).is_some()
+    }
+
+    /// Dispatch a guest read of `size` bits at guest-physical `addr` of a VM
+    ///
+    /// Returns `None` if no emulator of the VM decodes `addr`.
+    pub fn read(&mut self, vm_id: VmId, addr: u64, size: u32) -> Option<core::result::Result<u64, Error>> {
+        if let Some((entry, offset)) = self.find_mmio(vm_id, addr) {
+            return Some(entry.emulator.read(offset, size));
+        }
+        let entry = self.find_decoder(vm_id, addr)?;
+        Some(entry.emulator.read_decoded(addr, size))
     }
-}
\ No newline at end of file
+
+    /// Dispatch a guest write of `size` bits at guest-physical `addr` of a VM
+    ///
+    /// Returns `None` if no emulator of the VM decodes `addr`.
+    pub fn write(&mut self, vm_id: VmId, addr: u64, value: u64,
+                 size: u32) -> Option<core::result::Result<(), Error>> {
+        if let Some((entry, offset)) = self.find_mmio(vm_id, addr) {
+            return Some(entry.emulator.write(offset, value, size));
+        }
+        let entry = self.find_decoder(vm_id, addr)?;
+        Some(entry.emulator.write_decoded(addr, value, size))
+    }
+
+    /// Find the emulator of a VM that decodes `addr` outside its window
+    fn find_decoder(&mut self, vm_id: VmId, addr: u64) -> Option<&mut RegisteredEmulator> {
+        self.entries.iter_mut().find(|entry| entry.vm_id == vm_id && entry.emulator.decodes(addr))
+    }
+
+    /// Look up a VM's emulator by name
+    pub fn get(&self, vm_id: VmId, name: &str) -> Option<&RegisteredEmulator> {
+        self.entries.iter().find(|entry| entry.vm_id == vm_id && entry.name == name)
+    }
+
+    /// Iterate over a VM's emulators of one class, in registration order
+    pub fn iter_by_class(&self, vm_id: VmId, class: DeviceClass) -> impl Iterator<Item = &RegisteredEmulator> {
+        self.entries.iter().filter(move |entry| entry.vm_id == vm_id && entry.class == class)
+    }
+
+    /// Number of registered emulators
+    pub fn count(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+impl Default for EmulatorRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Emulators of every VM, registered as the VMs are created
+static EMULATORS: SpinLock<EmulatorRegistry> = SpinLock::new(EmulatorRegistry::new());
+
+/// Register an emulator under a name unique among the VM's devices
+pub fn register_emulator(vm_id: VmId, name: &str, class: DeviceClass, emulator: Box<dyn Emulator>) -> Result<()> {
+    EMULATORS.lock().register(vm_id, name, class, emulator)?;
+    log::debug!("VM {}: registered {:?} emulator {}", vm_id, class, name);
+    Ok(())
+}
+
+/// Register an emulator for the guest MMIO window `base..base + size` of
+/// a VM
+pub fn register_mmio_emulator(
+    vm_id: VmId,
+    name: &str,
+    class: DeviceClass,
+    base: u64,
+    size: u64,
+    emulator: Box<dyn Emulator>,
+) -> Result<()> {
+    EMULATORS.lock().register_mmio(vm_id, name, class, base, size, emulator)?;
+    log::debug!("VM {}: registered {:?} emulator {} at {:#x}..{:#x}", vm_id, class, name, base, base + size);
+    Ok(())
+}
+
+/// Remove an emulator of a VM
+pub fn unregister_emulator(vm_id: VmId, name: &str) -> Result<()> {
+    EMULATORS.lock().unregister(vm_id, name).ok_or(crate::Error::NotFound)?;
+    log::debug!("VM {}: unregistered emulator {}", vm_id, name);
+    Ok(())
+}
+
+/// Remove every emulator of a VM
+pub fn remove_vm_emulators(vm_id: VmId) {
+    let removed = EMULATORS.lock().remove_vm(vm_id);
+    log::debug!("VM {}: removed {} emulators", vm_id, removed);
+}
+
+/// Deliver pending events of all emulators
+///
+/// Skipped if the registry is busy, e.g. when called from an allocation
+/// made during an MMIO access; the events stay pending until the next poll.
+pub fn poll_emulators() {
+    if let Some(mut emulators) = EMULATORS.try_lock() {
+        emulators.poll_all();
+    }
+}
+
+/// Whether an emulator of a VM decodes guest-physical `addr`
+pub fn claims_mmio(vm_id: VmId, addr: u64) -> bool {
+    EMULATORS.lock().claims(vm_id, addr)
+}
+
+/// Dispatch a guest MMIO read of `size` bits at guest-physical `addr` of
+/// a VM
+///
+/// Returns `None` if no emulator of the VM decodes `addr`.
+pub fn mmio_read(vm_id: VmId, addr: u64, size: u32) -> Option<Result<u64>> {
+    let result = EMULATORS.lock().read(vm_id, addr, size)?;
+    Some(result.map_err(crate::Error::from))
+}
+
+/// Dispatch a guest MMIO write of `size` bits at guest-physical `addr` of
+/// a VM
+///
+/// Returns `None` if no emulator of the VM decodes `addr`.
+pub fn mmio_write(vm_id: VmId, addr: u64, value: u64, size: u32) -> Option<Result<()>> {
+    let result = EMULATORS.lock().write(vm_id, addr, value, size)?;
+    Some(result.map_err(crate::Error::from))
+}
+
+/// Names of a VM's emulators of one class, in registration order
+pub fn iter_by_class(vm_id: VmId, class: DeviceClass) -> impl Iterator<Item = String> {
+    let names: Vec<String> = EMULATORS.lock()
+        .iter_by_class(vm_id, class)
+        .map(|entry| entry.name.clone())
+        .collect();
+    names.into_iter()
+}
+
+/// Number of registered emulators, across all VMs
+pub fn count() -> usize {
+    EMULATORS.lock().count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Emulator without registers
+    struct NullDevice;
+
+    impl Emulator for NullDevice {
+        fn name(&self) -> &str {
+            "null"
+        }
+
+        fn read(&self, _offset: u64, _size: u32) -> core::result::Result<u64, Error> {
+            Ok(0)
+        }
+
+        fn write(&mut self, _offset: u64, _value: u64, _size: u32) -> core::result::Result<(), Error> {
+            Ok(())
+        }
+
+        fn reset(&mut self) -> core::result::Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_iter_by_class_returns_members_of_class() {
+        let mut registry = EmulatorRegistry::new();
+        registry.register(1, "uart-pl011", DeviceClass::Uart, Box::new(NullDevice)).unwrap();
+        registry.register(1, "rtc-pl031", DeviceClass::Rtc, Box::new(NullDevice)).unwrap();
+        registry.register(2, "uart-pl011", DeviceClass::Uart, Box::new(NullDevice)).unwrap();
+        registry.register(1, "uart-16550", DeviceClass::Uart, Box::new(NullDevice)).unwrap();
+        assert_eq!(registry.count(), 4);
+
+        let uarts: Vec<&str> = registry.iter_by_class(1, DeviceClass::Uart)
+            .map(|entry| entry.name.as_str())
+            .collect();
+        assert_eq!(uarts, ["uart-pl011", "uart-16550"]);
+        assert_eq!(registry.iter_by_class(1, DeviceClass::Rtc).count(), 1);
+        assert_eq!(registry.iter_by_class(1, DeviceClass::Gpio).count(), 0);
+        assert_eq!(registry.iter_by_class(2, DeviceClass::Rtc).count(), 0);
+    }
+
+    #[test]
+    fn test_duplicate_name_is_rejected() {
+        let mut registry = EmulatorRegistry::new();
+        registry.register(1, "rtc-pl031", DeviceClass::Rtc, Box::new(NullDevice)).unwrap();
+        assert_eq!(registry.register(1, "rtc-pl031", DeviceClass::Uart, Box::new(NullDevice)),
+                   Err(crate::Error::ResourceBusy));
+        assert_eq!(registry.count(), 1);
+        assert_eq!(registry.get(1, "rtc-pl031").unwrap().class, DeviceClass::Rtc);
+    }
+
+    #[test]
+    fn test_find_mmio_dispatches_by_window() {
+        let mut registry = EmulatorRegistry::new();
+        registry.register(1, "uart-16550", DeviceClass::Uart, Box::new(NullDevice)).unwrap();
+        registry.register_mmio(1, "uart-pl011", DeviceClass::Uart, 0x0900_0000, 0x1000, Box::new(NullDevice))
+            .unwrap();
+        registry.register_mmio(1, "ioapic", DeviceClass::InterruptController, 0xFEC0_0000, 0x1000,
+                               Box::new(NullDevice))
+            .unwrap();
+
+        let (entry, offset) = registry.find_mmio(1, 0xFEC0_0010).unwrap();
+        assert_eq!((entry.name.as_str(), offset), ("ioapic", 0x10));
+        let (entry, offset) = registry.find_mmio(1, 0x0900_0FFC).unwrap();
+        assert_eq!((entry.name.as_str(), offset), ("uart-pl011", 0xFFC));
+
+        // Past the end of a window, and a device without one
+        assert!(registry.find_mmio(1, 0x0900_1000).is_none());
+        assert!(registry.find_mmio(1, 0x3F8).is_none());
+
+        assert_eq!(registry.unregister(1, "ioapic").map(|entry| entry.class),
+                   Some(DeviceClass::InterruptController));
+        assert!(registry.find_mmio(1, 0xFEC0_0010).is_none());
+        assert!(registry.unregister(1, "ioapic").is_none());
+    }
+
+    #[test]
+    fn test_vms_have_separate_devices_at_the_same_window() {
+        let mut registry = EmulatorRegistry::new();
+        registry.register_mmio(1, "uart-pl011", DeviceClass::Uart, 0x0900_0000, 0x1000, Box::new(NullDevice))
+            .unwrap();
+        registry.register_mmio(2, "uart-pl011", DeviceClass::Uart, 0x0900_0000, 0x1000, Box::new(NullDevice))
+            .unwrap();
+
+        // Overlapping windows within one VM are rejected
+        assert_eq!(registry.register_mmio(1, "gpio", DeviceClass::Gpio, 0x0900_0800, 0x1000, Box::new(NullDevice)),
+                   Err(crate::Error::ResourceBusy));
+
+        assert_eq!(registry.find_mmio(2, 0x0900_0010).map(|(entry, _)| entry.vm_id), Some(2));
+        assert!(registry.find_mmio(3, 0x0900_0010).is_none());
+
+        assert_eq!(registry.remove_vm(1), 1);
+        assert!(registry.find_mmio(1, 0x0900_0010).is_none());
+        assert!(registry.claims(2, 0x0900_0010));
+    }
+}
@@ -0,0 +1,490 @@
+//! CLINT (Core Local Interruptor) Emulator
+//!
+//! This module provides the SiFive-compatible CLINT that RISC-V guests on
+//! the virt platform expect:
+//! - a shared, free-running `mtime` counter at the platform timebase
+//! - a per-hart `mtimecmp`; the hart's timer interrupt is pending while
+//!   `mtime >= mtimecmp`
+//! - a per-hart `msip` software interrupt bit
+//!
+//! The guest runs in VS-mode, so a pending hart timer is delivered as the
+//! virtual supervisor timer interrupt (VSTIP) of the matching vCPU, and
+//! `msip` as its software interrupt (VSSIP). Writing an `mtimecmp` that has
+//! already passed raises the interrupt immediately; a future value is
+//! recorded as a deadline and raised by the emulator poll loop once `mtime`
+//! reaches it.
+
+use crate::{Result, Error};
+use crate::emulator::{Emulator, Error as EmulatorError};
+use crate::core::mm::PhysAddr;
+use crate::core::vmm::VmId;
+use crate::core::sync::SpinLock;
+use crate::core::vmm::migration::{StateReader, StateWriter};
+use alloc::vec;
+use alloc::vec::Vec;
+#[cfg(target_arch = "riscv64")]
+use crate::arch::riscv64::virtualization::vintc::{self, VirtualInterruptFlags, VirtualInterruptType};
+#[cfg(target_arch = "riscv64")]
+use crate::emulator::DeviceClass;
+#[cfg(target_arch = "riscv64")]
+use alloc::boxed::Box;
+
+/// Offset of the per-hart MSIP registers (4 bytes each)
+pub const CLINT_MSIP_BASE: u64 = 0x0000;
+/// Offset of the per-hart MTIMECMP registers (8 bytes each)
+pub const CLINT_MTIMECMP_BASE: u64 = 0x4000;
+/// Offset of the shared MTIME register
+pub const CLINT_MTIME: u64 = 0xBFF8;
+/// Size of the CLINT register window
+pub const CLINT_SIZE: u64 = 0x10000;
+/// Highest number of harts the register layout can address
+pub const CLINT_MAX_HARTS: usize = 4095;
+
+/// Timebase of the QEMU virt platform in Hz
+pub const CLINT_VIRT_TIMEBASE_HZ: u64 = 10_000_000;
+
+/// Callback invoked to raise or lower a vCPU's virtual supervisor timer
+/// interrupt
+pub type ClintTimerFn = fn(vm_id: VmId, hart: usize, level: bool);
+
+/// Callback invoked to raise or lower a vCPU's software interrupt
+pub type ClintSoftwareFn = fn(vm_id: VmId, hart: usize, level: bool);
+
+/// CLINT state
+#[derive(Debug, Clone)]
+pub struct ClintState {
+    /// Per-hart software interrupt pending
+    msip: Vec<bool>,
+    /// Per-hart timer compare value
+    mtimecmp: Vec<u64>,
+    /// Per-hart timer interrupt pending
+    timer_pending: Vec<bool>,
+    /// Value added to the host-derived counter to get the guest's mtime
+    mtime_offset: u64,
+}
+
+impl ClintState {
+    /// Create the power-on state for `harts` harts
+    fn new(harts: usize) -> Self {
+        Self {
+            msip: vec![false; harts],
+            mtimecmp: vec![u64::MAX; harts],
+            timer_pending: vec![false; harts],
+            mtime_offset: 0,
+        }
+    }
+
+    /// Number of harts
+    fn harts(&self) -> usize {
+        self.mtimecmp.len()
+    }
+
+    /// Set a hart's compare value at `mtime`
+    ///
+    /// Returns the new timer interrupt level if it changed.
+    fn write_mtimecmp(&mut self, hart: usize, value: u64, mtime: u64) -> Option<bool> {
+        self.mtimecmp[hart] = value;
+
+        let pending = mtime >= value;
+        if self.timer_pending[hart] == pending {
+            return None;
+        }
+        self.timer_pending[hart] = pending;
+        Some(pending)
+    }
+
+    /// Replace one 32-bit half of a hart's compare value, as RV32 guests do
+    fn write_mtimecmp_half(&mut self, hart: usize, high: bool, value: u32, mtime: u64) -> Option<bool> {
+        let old = self.mtimecmp[hart];
+        let new = if high {
+            (old & 0xFFFF_FFFF) | ((value as u64) << 32)
+        } else {
+            (old & !0xFFFF_FFFF) | value as u64
+        };
+        self.write_mtimecmp(hart, new, mtime)
+    }
+
+    /// Raise the timer interrupt of every hart whose deadline is at or
+    /// before `mtime`
+    ///
+    /// Returns the harts that became pending.
+    fn poll(&mut self, mtime: u64) -> Vec<usize> {
+        let mut fired = Vec::new();
+        for hart in 0..self.harts() {
+            if !self.timer_pending[hart] && mtime >= self.mtimecmp[hart] {
+                self.timer_pending[hart] = true;
+                fired.push(hart);
+            }
+        }
+        fired
+    }
+
+    /// Earliest deadline of a hart whose timer is not yet pending
+    fn next_deadline(&self) -> Option<u64> {
+        self.mtimecmp.iter()
+            .zip(self.timer_pending.iter())
+            .filter(|&(&cmp, &pending)| !pending && cmp != u64::MAX)
+            .map(|(&cmp, _)| cmp)
+            .min()
+    }
+}
+
+/// CLINT emulator for one VM
+pub struct Clint {
+    /// Base address
+    base_addr: PhysAddr,
+    /// VM the CLINT belongs to
+    vm_id: VmId,
+    /// mtime frequency in Hz
+    timebase_hz: u64,
+    /// Host timestamp (ns) at which mtime was zero
+    epoch_ns: u64,
+    /// Device state
+    state: SpinLock<ClintState>,
+    /// Timer interrupt action
+    on_timer: Option<ClintTimerFn>,
+    /// Software interrupt action
+    on_software: Option<ClintSoftwareFn>,
+}
+
+impl Clint {
+    /// Create a CLINT for `harts` vCPUs of `vm_id`
+    pub fn new(base_addr: PhysAddr, vm_id: VmId, harts: usize, timebase_hz: u64) -> Result<Self> {
+        if harts == 0 || harts > CLINT_MAX_HARTS || timebase_hz == 0 {
+            return Err(Error::InvalidArgument);
+        }
+
+        Ok(Self {
+            base_addr,
+            vm_id,
+            timebase_hz,
+            epoch_ns: crate::utils::time::timestamp_ns(),
+            state: SpinLock::new(ClintState::new(harts)),
+            on_timer: None,
+            on_software: None,
+        })
+    }
+
+    /// Get the base address
+    pub fn base_address(&self) -> PhysAddr {
+        self.base_addr
+    }
+
+    /// Number of harts
+    pub fn harts(&self) -> usize {
+        self.state.lock().harts()
+    }
+
+    /// Set the timer interrupt action
+    pub fn set_timer_handler(&mut self, handler: ClintTimerFn) {
+        self.on_timer = Some(handler);
+    }
+
+    /// Set the software interrupt action
+    pub fn set_software_handler(&mut self, handler: ClintSoftwareFn) {
+        self.on_software = Some(handler);
+    }
+
+    /// Current guest mtime
+    pub fn mtime(&self) -> u64 {
+        let offset = self.state.lock().mtime_offset;
+        self.host_ticks().wrapping_add(offset)
+    }
+
+    /// mtime ticks elapsed on the host since the CLINT was created
+    fn host_ticks(&self) -> u64 {
+        let elapsed = crate::utils::time::timestamp_ns().saturating_sub(self.epoch_ns);
+        (elapsed as u128 * self.timebase_hz as u128 / crate::utils::time::NSEC_PER_SEC as u128) as u64
+    }
+
+    /// Check whether a hart's timer interrupt is pending
+    pub fn timer_pending(&self, hart: usize) -> bool {
+        self.state.lock().timer_pending.get(hart).copied().unwrap_or(false)
+    }
+
+    /// Earliest mtime at which a hart's timer will fire
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.state.lock().next_deadline()
+    }
+
+    /// Drive a hart's timer interrupt
+    fn set_timer_irq(&self, hart: usize, level: bool) {
+        if let Some(on_timer) = self.on_timer {
+            on_timer(self.vm_id, hart, level);
+        }
+    }
+
+    /// Drive a hart's software interrupt
+    fn set_software_irq(&self, hart: usize, level: bool) {
+        if let Some(on_software) = self.on_software {
+            on_software(self.vm_id, hart, level);
+        }
+    }
+
+    /// Map an offset to the hart it addresses within a register bank
+    fn hart_of(&self, offset: u64, base: u64, stride: u64) -> Option<usize> {
+        let hart = ((offset - base) / stride) as usize;
+        (hart < self.harts()).then_some(hart)
+    }
+}
+
+impl Emulator for Clint {
+    fn name(&self) -> &str {
+        "CLINT"
+    }
+
+    fn read(&self, offset: u64, size: u32) -> core::result::Result<u64, EmulatorError> {
+        if size != 32 && size != 64 {
+            return Err(EmulatorError::InvalidAccess);
+        }
+
+        let value = match offset {
+            CLINT_MTIME..=0xBFFF => self.mtime(),
+            CLINT_MTIMECMP_BASE..=0xBFF7 => {
+                let hart = self.hart_of(offset, CLINT_MTIMECMP_BASE, 8)
+                    .ok_or(EmulatorError::InvalidAccess)?;
+                self.state.lock().mtimecmp[hart]
+            }
+            CLINT_MSIP_BASE..=0x3FFF => {
+                let hart = self.hart_of(offset, CLINT_MSIP_BASE, 4)
+                    .ok_or(EmulatorError::InvalidAccess)?;
+                return Ok(self.state.lock().msip[hart] as u64);
+            }
+            _ => {
+                crate::warn!("CLINT: Unhandled read from offset 0x{:x}", offset);
+                return Ok(0);
+            }
+        };
+
+        // 32-bit reads of the high half
+        Ok(match (size, offset & 0x4) {
+            (32, 0) => value & 0xFFFF_FFFF,
+            (32, _) => value >> 32,
+            _ => value,
+        })
+    }
+
+    fn write(&mut self, offset: u64, value: u64, size: u32) -> core::result::Result<(), EmulatorError> {
+        if size != 32 && size != 64 {
+            return Err(EmulatorError::InvalidAccess);
+        }
+
+        match offset {
+            CLINT_MTIME..=0xBFFF => {
+                // Moving mtime re-evaluates every hart's deadline
+                let host = self.host_ticks();
+                let changes: Vec<(usize, bool)> = {
+                    let mut state = self.state.lock();
+                    let current = host.wrapping_add(state.mtime_offset);
+                    let mtime = match (size, offset & 0x4) {
+                        (32, 0) => (current & !0xFFFF_FFFF) | (value & 0xFFFF_FFFF),
+                        (32, _) => (current & 0xFFFF_FFFF) | (value << 32),
+                        _ => value,
+                    };
+                    state.mtime_offset = mtime.wrapping_sub(host);
+
+                    (0..state.harts())
+                        .filter_map(|hart| {
+                            let cmp = state.mtimecmp[hart];
+                            state.write_mtimecmp(hart, cmp, mtime).map(|level| (hart, level))
+                        })
+                        .collect()
+                };
+
+                for (hart, level) in changes {
+                    self.set_timer_irq(hart, level);
+                }
+            }
+            CLINT_MTIMECMP_BASE..=0xBFF7 => {
+                let hart = self.hart_of(offset, CLINT_MTIMECMP_BASE, 8)
+                    .ok_or(EmulatorError::InvalidAccess)?;
+                let mtime = self.mtime();
+                let changed = {
+                    let mut state = self.state.lock();
+                    if size == 64 {
+                        state.write_mtimecmp(hart, value, mtime)
+                    } else {
+                        state.write_mtimecmp_half(hart, offset & 0x4 != 0, value as u32, mtime)
+                    }
+                };
+
+                if let Some(level) = changed {
+                    self.set_timer_irq(hart, level);
+                }
+            }
+            CLINT_MSIP_BASE..=0x3FFF => {
+                let hart = self.hart_of(offset, CLINT_MSIP_BASE, 4)
+                    .ok_or(EmulatorError::InvalidAccess)?;
+                let level = value & 1 != 0;
+                let changed = {
+                    let mut state = self.state.lock();
+                    core::mem::replace(&mut state.msip[hart], level) != level
+                };
+
+                if changed {
+                    self.set_software_irq(hart, level);
+                }
+            }
+            _ => {
+                crate::warn!("CLINT: Unhandled write 0x{:x} to offset 0x{:x}", value, offset);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn reset(&mut self) -> core::result::Result<(), EmulatorError> {
+        let harts = self.harts();
+        *self.state.lock() = ClintState::new(harts);
+        self.epoch_ns = crate::utils::time::timestamp_ns();
+
+        for hart in 0..harts {
+            self.set_timer_irq(hart, false);
+            self.set_software_irq(hart, false);
+        }
+
+        Ok(())
+    }
+
+    /// Raise the timer interrupts whose deadline has passed
+    fn poll(&mut self) {
+        let mtime = self.mtime();
+        let fired = self.state.lock().poll(mtime);
+        for hart in fired {
+            self.set_timer_irq(hart, true);
+        }
+    }
+
+    fn save(&self) -> core::result::Result<Vec<u8>, EmulatorError> {
+        let mtime = self.mtime();
+        let state = self.state.lock();
+
+        let mut writer = StateWriter::new();
+        writer.put_u64(mtime);
+        writer.put_u32(state.harts() as u32);
+        for hart in 0..state.harts() {
+            writer.put_u8(state.msip[hart] as u8);
+            writer.put_u64(state.mtimecmp[hart]);
+        }
+        Ok(writer.finish())
+    }
+
+    fn restore(&mut self, data: &[u8]) -> core::result::Result<(), EmulatorError> {
+        let mut reader = StateReader::new(data);
+        let parse_error = |_| EmulatorError::InvalidConfiguration;
+
+        let mtime = reader.get_u64().map_err(parse_error)?;
+        let harts = reader.get_u32().map_err(parse_error)? as usize;
+        if harts != self.harts() {
+            return Err(EmulatorError::InvalidConfiguration);
+        }
+
+        let mut restored = ClintState::new(harts);
+        for hart in 0..harts {
+            restored.msip[hart] = reader.get_u8().map_err(parse_error)? != 0;
+            restored.mtimecmp[hart] = reader.get_u64().map_err(parse_error)?;
+        }
+        // Keep the guest's mtime running on from where the source left it
+        restored.mtime_offset = mtime.wrapping_sub(self.host_ticks());
+        let fired = restored.poll(mtime);
+        let msip = restored.msip.clone();
+        *self.state.lock() = restored;
+
+        for (hart, &pending) in msip.iter().enumerate() {
+            self.set_software_irq(hart, pending);
+            self.set_timer_irq(hart, fired.contains(&hart));
+        }
+
+        Ok(())
+    }
+}
+
+/// Raise or lower an interrupt of the vCPU that is hart `hart` of a VM
+#[cfg(target_arch = "riscv64")]
+fn drive_vcpu_irq(vm_id: VmId, hart: usize, interrupt: VirtualInterruptType, level: bool) {
+    let Some(controller) = vintc::get_controller_mut() else {
+        crate::warn!("CLINT: no virtual interrupt controller for VM {}", vm_id);
+        return;
+    };
+    let Some(vcpu) = controller.find_vm_vcpu(vm_id as _, hart as _) else {
+        crate::warn!("CLINT: VM {} has no vCPU for hart {}", vm_id, hart);
+        return;
+    };
+    if level {
+        let result = controller.inject_interrupt(vcpu, interrupt, VirtualInterruptFlags::LEVEL_TRIGGERED);
+        if let Some(e) = result.error {
+            crate::warn!("CLINT: failed to raise {:?} on VM {} hart {}: {}", interrupt, vm_id, hart, e);
+        }
+    } else {
+        let _ = controller.clear_interrupt(vcpu, interrupt);
+    }
+}
+
+/// Drive a hart's VSTIP
+#[cfg(target_arch = "riscv64")]
+fn set_vcpu_timer(vm_id: VmId, hart: usize, level: bool) {
+    drive_vcpu_irq(vm_id, hart, VirtualInterruptType::SupervisorTimer, level);
+}
+
+/// Drive a hart's VSSIP
+#[cfg(target_arch = "riscv64")]
+fn set_vcpu_software(vm_id: VmId, hart: usize, level: bool) {
+    drive_vcpu_irq(vm_id, hart, VirtualInterruptType::SupervisorSoftware, level);
+}
+
+/// Give a VM with `harts` harts a CLINT at the QEMU virt location, wired
+/// to its vCPUs
+#[cfg(target_arch = "riscv64")]
+pub fn attach(vm_id: VmId, harts: usize) -> Result<()> {
+    let mut clint = Clint::new(0x0200_0000, vm_id, harts, CLINT_VIRT_TIMEBASE_HZ)?;
+    clint.set_timer_handler(set_vcpu_timer);
+    clint.set_software_handler(set_vcpu_software);
+    let base = clint.base_address();
+    crate::emulator::register_mmio_emulator(vm_id, "clint", DeviceClass::Timer, base, CLINT_SIZE, Box::new(clint))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_past_mtimecmp_flags_timer_immediately() {
+        let mut state = ClintState::new(2);
+
+        assert_eq!(state.write_mtimecmp(1, 500, 1_000), Some(true));
+        assert!(state.timer_pending[1]);
+        assert!(!state.timer_pending[0]);
+
+        // Rewriting a past value does not raise it again
+        assert_eq!(state.write_mtimecmp(1, 900, 1_000), None);
+        assert_eq!(state.next_deadline(), None);
+    }
+
+    #[test]
+    fn test_future_mtimecmp_schedules_timer() {
+        let mut state = ClintState::new(2);
+
+        assert_eq!(state.write_mtimecmp(0, 2_000, 1_000), None);
+        assert!(!state.timer_pending[0]);
+        assert_eq!(state.next_deadline(), Some(2_000));
+
+        assert!(state.poll(1_999).is_empty());
+        assert_eq!(state.poll(2_000), [0]);
+        assert!(state.timer_pending[0]);
+        assert!(state.poll(3_000).is_empty());
+
+        // Pushing the deadline out again acknowledges the interrupt
+        assert_eq!(state.write_mtimecmp(0, 5_000, 3_000), Some(false));
+        assert_eq!(state.next_deadline(), Some(5_000));
+    }
+
+    #[test]
+    fn test_rv32_split_mtimecmp_write() {
+        let mut state = ClintState::new(1);
+
+        state.write_mtimecmp_half(0, false, 0x1000, 0);
+        state.write_mtimecmp_half(0, true, 0x2, 0);
+        assert_eq!(state.mtimecmp[0], 0x2_0000_1000);
+        assert!(!state.timer_pending[0]);
+    }
+}
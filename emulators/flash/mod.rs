@@ -0,0 +1,483 @@
+//! ROM/Flash Emulator
+//!
+//! This module exposes a backing byte array to the guest as firmware
+//! storage. Two flavours are supported:
+//! - ROM: reads return the backing contents and all writes are ignored
+//! - CFI flash: an Intel/Sharp command set (as used by QEMU's pflash on
+//!   the virt platforms) with block erase, word program and per-block
+//!   locking
+//!
+//! Flash blocks start locked, so the device is read-only until the guest
+//! unlocks a block. A hardware write-protect, controlled by the host,
+//! makes program and erase fail even on unlocked blocks.
+
+use crate::{Result, Error};
+use crate::config::DeviceConfig;
+use crate::emulator::{DeviceClass, Emulator, Error as EmulatorError};
+use crate::core::vmm::VmId;
+use crate::core::mm::PhysAddr;
+use crate::core::sync::SpinLock;
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Command: return to read-array mode
+pub const FLASH_CMD_READ_ARRAY: u8 = 0xFF;
+/// Command: read manufacturer/device ID and block lock state
+pub const FLASH_CMD_READ_ID: u8 = 0x90;
+/// Command: CFI query
+pub const FLASH_CMD_CFI_QUERY: u8 = 0x98;
+/// Command: read status register
+pub const FLASH_CMD_READ_STATUS: u8 = 0x70;
+/// Command: clear status register errors
+pub const FLASH_CMD_CLEAR_STATUS: u8 = 0x50;
+/// Command: program setup, the next write carries the data
+pub const FLASH_CMD_PROGRAM: u8 = 0x40;
+/// Command: alternate program setup
+pub const FLASH_CMD_PROGRAM_ALT: u8 = 0x10;
+/// Command: block erase setup, confirmed by `FLASH_CMD_CONFIRM`
+pub const FLASH_CMD_ERASE: u8 = 0x20;
+/// Command: block lock setup, followed by `FLASH_CMD_LOCK` or `FLASH_CMD_CONFIRM`
+pub const FLASH_CMD_LOCK_SETUP: u8 = 0x60;
+/// Command: set block lock (after lock setup)
+pub const FLASH_CMD_LOCK: u8 = 0x01;
+/// Command: confirm erase, or clear block lock after lock setup
+pub const FLASH_CMD_CONFIRM: u8 = 0xD0;
+
+/// Status: device ready
+pub const FLASH_STATUS_READY: u8 = 1 << 7;
+/// Status: erase or unlock failed
+pub const FLASH_STATUS_ERASE_ERROR: u8 = 1 << 5;
+/// Status: program or lock failed
+pub const FLASH_STATUS_PROGRAM_ERROR: u8 = 1 << 4;
+/// Status: operation aborted on a locked block or by write-protect
+pub const FLASH_STATUS_LOCKED: u8 = 1 << 1;
+
+/// Manufacturer ID reported by READ_ID (Intel)
+const FLASH_MANUFACTURER_ID: u8 = 0x89;
+/// Device ID reported by READ_ID
+const FLASH_DEVICE_ID: u8 = 0x18;
+
+/// Command state machine mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FlashMode {
+    /// Reads return array contents
+    ReadArray,
+    /// Reads return the status register
+    ReadStatus,
+    /// Reads return identification data
+    ReadId,
+    /// Reads return the CFI query table
+    CfiQuery,
+    /// Next write is program data
+    Program,
+    /// Next write must confirm an erase
+    EraseSetup,
+    /// Next write selects lock or unlock
+    LockSetup,
+}
+
+/// Flash state
+#[derive(Debug, Clone)]
+pub struct FlashState {
+    /// Backing contents
+    data: Vec<u8>,
+    /// Erase block size in bytes
+    block_size: usize,
+    /// Per-block lock bits
+    locked: Vec<bool>,
+    /// Command state machine mode
+    mode: FlashMode,
+    /// Status register
+    status: u8,
+    /// Hardware write-protect asserted
+    write_protect: bool,
+}
+
+impl FlashState {
+    /// Create flash state over `data`, with every block locked
+    fn new(data: Vec<u8>, block_size: usize) -> Self {
+        let blocks = data.len().div_ceil(block_size);
+        Self {
+            data,
+            block_size,
+            locked: vec![true; blocks],
+            mode: FlashMode::ReadArray,
+            status: FLASH_STATUS_READY,
+            write_protect: false,
+        }
+    }
+
+    /// Block containing `offset`
+    fn block_of(&self, offset: usize) -> usize {
+        offset / self.block_size
+    }
+
+    /// Read `len` bytes at `offset` in the current mode
+    fn read(&self, offset: usize, len: usize) -> u64 {
+        match self.mode {
+            FlashMode::ReadArray => {
+                let end = (offset + len).min(self.data.len());
+                self.data.get(offset..end).unwrap_or(&[])
+                    .iter()
+                    .rev()
+                    .fold(0, |value, &byte| (value << 8) | byte as u64)
+            }
+            FlashMode::ReadId => match offset % self.block_size {
+                0 => FLASH_MANUFACTURER_ID as u64,
+                1 => FLASH_DEVICE_ID as u64,
+                2 => self.locked.get(self.block_of(offset)).copied().unwrap_or(true) as u64,
+                _ => 0,
+            },
+            FlashMode::CfiQuery => self.cfi_query(offset) as u64,
+            _ => self.status as u64,
+        }
+    }
+
+    /// Byte of the CFI query table at `offset`
+    fn cfi_query(&self, offset: usize) -> u8 {
+        match offset {
+            0x10 => b'Q',
+            0x11 => b'R',
+            0x12 => b'Y',
+            // Primary command set: Intel/Sharp extended
+            0x13 => 0x01,
+            // Device size as a power of two
+            0x27 => self.data.len().next_power_of_two().trailing_zeros() as u8,
+            // One erase block region
+            0x2C => 1,
+            0x2D => ((self.locked.len() - 1) & 0xFF) as u8,
+            0x2E => ((self.locked.len() - 1) >> 8) as u8,
+            0x2F => ((self.block_size >> 8) & 0xFF) as u8,
+            0x30 => (self.block_size >> 16) as u8,
+            _ => 0,
+        }
+    }
+
+    /// Check that a block may be modified, flagging `error` in the status
+    /// register if not
+    fn check_writable(&mut self, offset: usize, error: u8) -> bool {
+        let locked = self.locked.get(self.block_of(offset)).copied().unwrap_or(true);
+        if self.write_protect || locked {
+            self.status |= error | FLASH_STATUS_LOCKED;
+            return false;
+        }
+        true
+    }
+
+    /// Handle a guest write of `len` bytes at `offset`
+    fn write(&mut self, offset: usize, value: u64, len: usize) {
+        match self.mode {
+            FlashMode::Program => {
+                self.mode = FlashMode::ReadStatus;
+                if offset + len > self.data.len()
+                    || !self.check_writable(offset, FLASH_STATUS_PROGRAM_ERROR)
+                {
+                    return;
+                }
+                // Programming can only clear bits
+                for (i, byte) in self.data[offset..offset + len].iter_mut().enumerate() {
+                    *byte &= (value >> (8 * i)) as u8;
+                }
+            }
+            FlashMode::EraseSetup => {
+                self.mode = FlashMode::ReadStatus;
+                if value as u8 != FLASH_CMD_CONFIRM {
+                    self.status |= FLASH_STATUS_ERASE_ERROR | FLASH_STATUS_PROGRAM_ERROR;
+                    return;
+                }
+                if !self.check_writable(offset, FLASH_STATUS_ERASE_ERROR) {
+                    return;
+                }
+                let start = self.block_of(offset) * self.block_size;
+                let end = (start + self.block_size).min(self.data.len());
+                self.data[start..end].fill(0xFF);
+            }
+            FlashMode::LockSetup => {
+                self.mode = FlashMode::ReadStatus;
+                let block = self.block_of(offset);
+                if block >= self.locked.len() {
+                    return;
+                }
+                match value as u8 {
+                    FLASH_CMD_LOCK => self.locked[block] = true,
+                    FLASH_CMD_CONFIRM if self.write_protect => {
+                        self.status |= FLASH_STATUS_ERASE_ERROR | FLASH_STATUS_LOCKED;
+                    }
+                    FLASH_CMD_CONFIRM => self.locked[block] = false,
+                    _ => self.status |= FLASH_STATUS_ERASE_ERROR | FLASH_STATUS_PROGRAM_ERROR,
+                }
+            }
+            _ => self.command(value as u8),
+        }
+    }
+
+    /// Start a new command
+    fn command(&mut self, cmd: u8) {
+        self.mode = match cmd {
+            FLASH_CMD_READ_ARRAY => FlashMode::ReadArray,
+            FLASH_CMD_READ_ID => FlashMode::ReadId,
+            FLASH_CMD_CFI_QUERY => FlashMode::CfiQuery,
+            FLASH_CMD_READ_STATUS => FlashMode::ReadStatus,
+            FLASH_CMD_CLEAR_STATUS => {
+                self.status = FLASH_STATUS_READY;
+                self.mode
+            }
+            FLASH_CMD_PROGRAM | FLASH_CMD_PROGRAM_ALT => FlashMode::Program,
+            FLASH_CMD_ERASE => FlashMode::EraseSetup,
+            FLASH_CMD_LOCK_SETUP => FlashMode::LockSetup,
+            _ => {
+                crate::warn!("Flash: Unknown command 0x{:02x}", cmd);
+                self.mode
+            }
+        };
+    }
+}
+
+/// ROM/flash emulator
+pub struct Flash {
+    /// Base address
+    base_addr: PhysAddr,
+    /// Accept CFI commands; a ROM ignores all writes
+    writable: bool,
+    /// Device state
+    state: SpinLock<FlashState>,
+}
+
+impl Flash {
+    /// Create a read-only ROM over `data`
+    pub fn rom(base_addr: PhysAddr, data: Vec<u8>) -> Result<Self> {
+        Self::build(base_addr, data, 4096, false)
+    }
+
+    /// Create a CFI flash over `data` with `block_size` erase blocks
+    ///
+    /// All blocks start locked.
+    pub fn cfi(base_addr: PhysAddr, data: Vec<u8>, block_size: usize) -> Result<Self> {
+        Self::build(base_addr, data, block_size, true)
+    }
+
+    /// Create the device after validating the geometry
+    fn build(base_addr: PhysAddr, data: Vec<u8>, block_size: usize, writable: bool) -> Result<Self> {
+        if data.is_empty() || block_size == 0 || !block_size.is_power_of_two() {
+            return Err(Error::InvalidArgument);
+        }
+
+        Ok(Self {
+            base_addr,
+            writable,
+            state: SpinLock::new(FlashState::new(data, block_size)),
+        })
+    }
+
+    /// Get the base address
+    pub fn base_address(&self) -> PhysAddr {
+        self.base_addr
+    }
+
+    /// Size of the backing contents
+    pub fn size(&self) -> usize {
+        self.state.lock().data.len()
+    }
+
+    /// Check whether the device accepts guest writes
+    pub fn is_writable(&self) -> bool {
+        self.writable
+    }
+
+    /// Assert or release the hardware write-protect
+    pub fn set_write_protect(&self, enabled: bool) {
+        self.state.lock().write_protect = enabled;
+    }
+
+    /// Check whether the hardware write-protect is asserted
+    pub fn write_protected(&self) -> bool {
+        self.state.lock().write_protect
+    }
+
+    /// Check whether the block containing `offset` is locked
+    pub fn is_locked(&self, offset: usize) -> bool {
+        let state = self.state.lock();
+        state.locked.get(state.block_of(offset)).copied().unwrap_or(true)
+    }
+
+    /// Copy the backing contents out, e.g. to persist guest variables
+    pub fn contents(&self) -> Vec<u8> {
+        self.state.lock().data.clone()
+    }
+}
+
+impl Emulator for Flash {
+    fn name(&self) -> &str {
+        if self.writable { "CFI-Flash" } else { "ROM" }
+    }
+
+    fn read(&self, offset: u64, size: u32) -> core::result::Result<u64, EmulatorError> {
+        if size != 8 && size != 16 && size != 32 && size != 64 {
+            return Err(EmulatorError::InvalidAccess);
+        }
+
+        let state = self.state.lock();
+        let offset = offset as usize;
+        if offset >= state.data.len() {
+            return Err(EmulatorError::InvalidAccess);
+        }
+
+        Ok(state.read(offset, size as usize / 8))
+    }
+
+    fn write(&mut self, offset: u64, value: u64, size: u32) -> core::result::Result<(), EmulatorError> {
+        if size != 8 && size != 16 && size != 32 && size != 64 {
+            return Err(EmulatorError::InvalidAccess);
+        }
+
+        let offset = offset as usize;
+        if !self.writable {
+            crate::warn!("ROM: Ignoring write 0x{:x} to offset 0x{:x}", value, offset);
+            return Ok(());
+        }
+
+        let mut state = self.state.lock();
+        if offset >= state.data.len() {
+            return Err(EmulatorError::InvalidAccess);
+        }
+        state.write(offset, value, size as usize / 8);
+
+        Ok(())
+    }
+
+    fn reset(&mut self) -> core::result::Result<(), EmulatorError> {
+        // Contents survive reset, the command state and lock bits do not
+        let mut state = self.state.lock();
+        state.mode = FlashMode::ReadArray;
+        state.status = FLASH_STATUS_READY;
+        state.locked.fill(true);
+
+        Ok(())
+    }
+}
+
+/// Where a flash bank sits when its config names no base (QEMU virt)
+const DEFAULT_FLASH_BASE: PhysAddr = 0x0400_0000;
+
+/// Erase block size of configured flash banks
+const FLASH_BLOCK_SIZE: usize = 256 << 10;
+
+/// Size of the bank configured by `device`: a non-zero multiple of the
+/// erase block
+fn bank_size(device: &DeviceConfig) -> Result<usize> {
+    let size = device.size
+        .and_then(|size| usize::try_from(size).ok())
+        .ok_or(Error::InvalidArgument)?;
+    if size == 0 || size % FLASH_BLOCK_SIZE != 0 {
+        return Err(Error::InvalidArgument);
+    }
+    Ok(size)
+}
+
+/// Give a VM the empty flash bank configured by `device`
+///
+/// The bank is as large as the configured size and sits at the
+/// configured base, or the QEMU virt location if none is given.
+pub fn attach(vm_id: VmId, device: &DeviceConfig) -> Result<()> {
+    let size = bank_size(device)?;
+    let mut data = Vec::new();
+    data.try_reserve_exact(size).map_err(|_| Error::OutOfMemory)?;
+    data.resize(size, 0xFF);
+
+    let flash = Flash::cfi(device.base_address.unwrap_or(DEFAULT_FLASH_BASE), data, FLASH_BLOCK_SIZE)?;
+    let (base, size) = (flash.base_address(), flash.size() as u64);
+    crate::emulator::register_mmio_emulator(vm_id, &device.name, DeviceClass::Flash, base, size, Box::new(flash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK: usize = 64;
+
+    fn image() -> Vec<u8> {
+        (0..4 * BLOCK).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn test_reads_return_backing_contents() {
+        let state = FlashState::new(image(), BLOCK);
+
+        assert_eq!(state.read(0, 1), 0x00);
+        assert_eq!(state.read(0x10, 4), 0x1312_1110);
+        assert_eq!(state.read(0x80, 8), 0x8786_8584_8382_8180);
+    }
+
+    #[test]
+    fn test_program_locked_region_fails() {
+        let mut state = FlashState::new(image(), BLOCK);
+
+        state.write(0x40, FLASH_CMD_PROGRAM as u64, 1);
+        state.write(0x40, 0x00, 1);
+        assert_ne!(state.status & FLASH_STATUS_PROGRAM_ERROR, 0);
+        assert_ne!(state.status & FLASH_STATUS_LOCKED, 0);
+        assert_eq!(state.data[0x40], 0x40);
+
+        // Erase is refused as well
+        state.write(0x40, FLASH_CMD_ERASE as u64, 1);
+        state.write(0x40, FLASH_CMD_CONFIRM as u64, 1);
+        assert_ne!(state.status & FLASH_STATUS_ERASE_ERROR, 0);
+        assert_eq!(state.data[0x41], 0x41);
+    }
+
+    #[test]
+    fn test_unlock_then_program_updates_bytes() {
+        let mut state = FlashState::new(image(), BLOCK);
+
+        state.write(BLOCK, FLASH_CMD_LOCK_SETUP as u64, 1);
+        state.write(BLOCK, FLASH_CMD_CONFIRM as u64, 1);
+        assert!(!state.locked[1]);
+
+        state.write(BLOCK, FLASH_CMD_ERASE as u64, 1);
+        state.write(BLOCK, FLASH_CMD_CONFIRM as u64, 1);
+        state.write(BLOCK + 4, FLASH_CMD_PROGRAM as u64, 1);
+        state.write(BLOCK + 4, 0xCAFE, 2);
+        assert_eq!(state.status, FLASH_STATUS_READY);
+
+        state.write(0, FLASH_CMD_READ_ARRAY as u64, 1);
+        assert_eq!(state.read(BLOCK + 4, 2), 0xCAFE);
+        assert_eq!(state.read(BLOCK, 4), 0xFFFF_FFFF);
+        // Neighbouring blocks are untouched
+        assert_eq!(state.read(BLOCK - 1, 1), (BLOCK - 1) as u64);
+    }
+
+    #[test]
+    fn test_write_protect_rejects_program() {
+        let mut state = FlashState::new(image(), BLOCK);
+        state.write(0, FLASH_CMD_LOCK_SETUP as u64, 1);
+        state.write(0, FLASH_CMD_CONFIRM as u64, 1);
+        state.write_protect = true;
+
+        state.write(1, FLASH_CMD_PROGRAM as u64, 1);
+        state.write(1, 0x00, 1);
+        assert_ne!(state.status & FLASH_STATUS_PROGRAM_ERROR, 0);
+
+        state.write(0, FLASH_CMD_READ_ARRAY as u64, 1);
+        assert_eq!(state.read(1, 1), 0x01);
+    }
+
+    #[test]
+    fn test_bank_size_from_config() {
+        let mut device = DeviceConfig {
+            device_type: crate::config::DeviceType::Flash,
+            name: alloc::string::String::from("flash0"),
+            base_address: None,
+            size: None,
+            irq: None,
+            params: alloc::collections::BTreeMap::new(),
+        };
+        assert_eq!(bank_size(&device), Err(Error::InvalidArgument));
+
+        device.size = Some(FLASH_BLOCK_SIZE as u64 + 1);
+        assert_eq!(bank_size(&device), Err(Error::InvalidArgument));
+
+        device.size = Some(4 << 20);
+        assert_eq!(bank_size(&device), Ok(4 << 20));
+    }
+}
@@ -4,10 +4,12 @@
 //! supporting GPIO controllers like PL061, etc.
 
 use crate::{Result, Error};
-use crate::emulator::{Emulator, Error as EmulatorError};
+use crate::emulator::{DeviceClass, Emulator, Error as EmulatorError};
+use crate::core::vmm::VmId;
 use crate::core::mm::{VirtAddr, PhysAddr};
 use crate::arch::common::MmioAccess;
 use crate::core::sync::SpinLock;
+use alloc::boxed::Box;
 
 /// PL061 GPIO registers
 #[allow(dead_code)]
@@ -437,13 +439,13 @@ impl Emulator for Pl061Gpio {
     }
 }
 
-/// Initialize GPIO emulators
-pub fn init() -> Result<(), crate::Error> {
-    crate::info!("Initializing GPIO emulators");
+/// Size of the PL061 register window
+pub const PL061_SIZE: u64 = 0x1000;
 
-    // Register PL061 GPIO
+/// Give a VM a PL061 GPIO controller
+pub fn attach(vm_id: VmId) -> Result<(), crate::Error> {
     let pl061 = Pl061Gpio::new(0x40000000);
-    crate::emulator::register_emulator("gpio-pl061", &pl061)?;
-
-    Ok(())
+    let base = pl061.base_address();
+    crate::emulator::register_mmio_emulator(vm_id, "gpio-pl061", DeviceClass::Gpio, base, PL061_SIZE,
+                                            Box::new(pl061))
 }
\ No newline at end of file
@@ -0,0 +1,483 @@
+//! I/O APIC Emulator
+//!
+//! This module provides the 82093AA-compatible I/O APIC that x86 guests
+//! route device interrupts through:
+//! - the IOREGSEL/IOWIN index/data window onto the indirect registers
+//! - ID, version and arbitration registers
+//! - a 24-entry redirection table mapping each input pin to a vector,
+//!   delivery mode and destination LAPIC
+//!
+//! Edge-triggered pins deliver on an asserting edge. Level-triggered pins
+//! deliver while asserted and set Remote IRR, which blocks further
+//! deliveries until the guest EOIs the vector; a pin still asserted at EOI
+//! is delivered again.
+//!
+//! A VM's I/O APIC is its guest interrupt controller: `set_vm_irq` drives
+//! its pins for the VM's IRQ routing table without going through the
+//! emulator registry.
+
+use crate::{Result, Error};
+use crate::emulator::{DeviceClass, Emulator, Error as EmulatorError};
+use crate::core::mm::PhysAddr;
+use crate::core::vmm::VmId;
+use crate::core::sync::SpinLock;
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// Default I/O APIC base address
+pub const IOAPIC_DEFAULT_BASE: PhysAddr = 0xFEC0_0000;
+/// Size of the I/O APIC register window
+pub const IOAPIC_SIZE: u64 = 0x1000;
+/// Number of input pins / redirection entries
+pub const IOAPIC_NUM_PINS: usize = 24;
+/// Version register value: version 0x20 (with EOI register)
+pub const IOAPIC_VERSION: u32 = 0x20;
+
+/// Offset of the register select (index) register
+pub const IOAPIC_IOREGSEL: u64 = 0x00;
+/// Offset of the register window (data) register
+pub const IOAPIC_IOWIN: u64 = 0x10;
+/// Offset of the EOI register
+pub const IOAPIC_EOI: u64 = 0x40;
+
+/// Indirect register: I/O APIC ID
+pub const IOAPIC_REG_ID: u32 = 0x00;
+/// Indirect register: version and maximum redirection entry
+pub const IOAPIC_REG_VER: u32 = 0x01;
+/// Indirect register: arbitration ID
+pub const IOAPIC_REG_ARB: u32 = 0x02;
+/// Indirect register: first redirection table dword
+pub const IOAPIC_REG_REDTBL: u32 = 0x10;
+
+/// Redirection entry bit definitions
+pub mod redir {
+    /// Interrupt vector
+    pub const VECTOR_MASK: u64 = 0xFF;
+    /// Delivery mode shift
+    pub const DELIVERY_MODE_SHIFT: u64 = 8;
+    /// Delivery mode mask (after shift)
+    pub const DELIVERY_MODE_MASK: u64 = 0x7;
+    /// Logical destination mode
+    pub const DEST_MODE_LOGICAL: u64 = 1 << 11;
+    /// Delivery status (read-only)
+    pub const DELIVERY_STATUS: u64 = 1 << 12;
+    /// Active-low input pin
+    pub const POLARITY_LOW: u64 = 1 << 13;
+    /// Remote IRR (read-only)
+    pub const REMOTE_IRR: u64 = 1 << 14;
+    /// Level-triggered
+    pub const TRIGGER_LEVEL: u64 = 1 << 15;
+    /// Masked
+    pub const MASKED: u64 = 1 << 16;
+    /// Destination field shift
+    pub const DEST_SHIFT: u64 = 56;
+    /// Bits the guest cannot write
+    pub const READ_ONLY: u64 = DELIVERY_STATUS | REMOTE_IRR;
+}
+
+/// Interrupt message sent to the local APICs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoapicMessage {
+    /// Interrupt vector
+    pub vector: u8,
+    /// Delivery mode (0 = fixed, 1 = lowest priority, ...)
+    pub delivery_mode: u8,
+    /// Destination is a logical APIC ID set
+    pub logical: bool,
+    /// Destination APIC ID (physical) or set (logical)
+    pub destination: u8,
+    /// Level-triggered; the LAPIC must EOI-broadcast the vector
+    pub level: bool,
+}
+
+/// Callback invoked to deliver a message to the guest's local APICs
+pub type IoapicDeliverFn = fn(vm_id: VmId, msg: IoapicMessage);
+
+/// I/O APIC state
+#[derive(Debug, Clone)]
+pub struct IoapicState {
+    /// APIC ID
+    id: u8,
+    /// Selected indirect register
+    ioregsel: u32,
+    /// Redirection table
+    redirtbl: [u64; IOAPIC_NUM_PINS],
+    /// Raw input pin levels, one bit per pin
+    pin_levels: u32,
+}
+
+impl IoapicState {
+    /// Create the power-on state: all pins masked
+    fn new(id: u8) -> Self {
+        Self {
+            id,
+            ioregsel: 0,
+            redirtbl: [redir::MASKED; IOAPIC_NUM_PINS],
+            pin_levels: 0,
+        }
+    }
+
+    /// Whether a pin's input is active, taking polarity into account
+    fn pin_active(&self, pin: usize) -> bool {
+        let level = self.pin_levels & (1 << pin) != 0;
+        level != (self.redirtbl[pin] & redir::POLARITY_LOW != 0)
+    }
+
+    /// Build the message for a pin's redirection entry
+    fn message(&self, pin: usize) -> IoapicMessage {
+        let entry = self.redirtbl[pin];
+        IoapicMessage {
+            vector: (entry & redir::VECTOR_MASK) as u8,
+            delivery_mode: ((entry >> redir::DELIVERY_MODE_SHIFT) & redir::DELIVERY_MODE_MASK) as u8,
+            logical: entry & redir::DEST_MODE_LOGICAL != 0,
+            destination: (entry >> redir::DEST_SHIFT) as u8,
+            level: entry & redir::TRIGGER_LEVEL != 0,
+        }
+    }
+
+    /// Deliver a level-triggered pin if it is active, unmasked and not
+    /// waiting for EOI
+    fn service_level(&mut self, pin: usize) -> Option<IoapicMessage> {
+        let entry = self.redirtbl[pin];
+        if entry & redir::TRIGGER_LEVEL == 0
+            || entry & (redir::MASKED | redir::REMOTE_IRR) != 0
+            || !self.pin_active(pin)
+        {
+            return None;
+        }
+        self.redirtbl[pin] |= redir::REMOTE_IRR;
+        Some(self.message(pin))
+    }
+
+    /// Drive an input pin
+    ///
+    /// Returns the message to deliver, if any.
+    fn set_irq(&mut self, pin: usize, level: bool) -> Option<IoapicMessage> {
+        let was_active = self.pin_active(pin);
+        if level {
+            self.pin_levels |= 1 << pin;
+        } else {
+            self.pin_levels &= !(1 << pin);
+        }
+
+        let entry = self.redirtbl[pin];
+        if entry & redir::TRIGGER_LEVEL != 0 {
+            self.service_level(pin)
+        } else if !was_active && self.pin_active(pin) && entry & redir::MASKED == 0 {
+            Some(self.message(pin))
+        } else {
+            None
+        }
+    }
+
+    /// End of interrupt for `vector`
+    ///
+    /// Clears Remote IRR of the level-triggered pins using the vector and
+    /// returns the messages of those still asserted.
+    fn eoi(&mut self, vector: u8) -> Vec<IoapicMessage> {
+        let mut redeliver = Vec::new();
+        for pin in 0..IOAPIC_NUM_PINS {
+            let entry = self.redirtbl[pin];
+            if entry & redir::REMOTE_IRR != 0 && (entry & redir::VECTOR_MASK) as u8 == vector {
+                self.redirtbl[pin] &= !redir::REMOTE_IRR;
+                redeliver.extend(self.service_level(pin));
+            }
+        }
+        redeliver
+    }
+
+    /// Read an indirect register
+    fn read_indirect(&self, index: u32) -> u32 {
+        match index {
+            IOAPIC_REG_ID | IOAPIC_REG_ARB => (self.id as u32 & 0xF) << 24,
+            IOAPIC_REG_VER => ((IOAPIC_NUM_PINS as u32 - 1) << 16) | IOAPIC_VERSION,
+            _ => match Self::redir_index(index) {
+                Some((pin, true)) => (self.redirtbl[pin] >> 32) as u32,
+                Some((pin, false)) => self.redirtbl[pin] as u32,
+                None => 0,
+            },
+        }
+    }
+
+    /// Write an indirect register
+    ///
+    /// Returns the message to deliver if the write unmasked an asserted
+    /// level-triggered pin.
+    fn write_indirect(&mut self, index: u32, value: u32) -> Option<IoapicMessage> {
+        match index {
+            IOAPIC_REG_ID => {
+                self.id = ((value >> 24) & 0xF) as u8;
+                None
+            }
+            _ => {
+                let (pin, high) = Self::redir_index(index)?;
+                let old = self.redirtbl[pin];
+                let new = if high {
+                    (old & 0xFFFF_FFFF) | ((value as u64) << 32)
+                } else {
+                    (old & !0xFFFF_FFFF) | (value as u64 & !redir::READ_ONLY) | (old & redir::READ_ONLY)
+                };
+                self.redirtbl[pin] = new;
+
+                // Switching to edge mode drops a stale Remote IRR
+                if new & redir::TRIGGER_LEVEL == 0 {
+                    self.redirtbl[pin] &= !redir::REMOTE_IRR;
+                }
+                self.service_level(pin)
+            }
+        }
+    }
+
+    /// Map an indirect register index to (pin, high dword)
+    fn redir_index(index: u32) -> Option<(usize, bool)> {
+        let offset = index.checked_sub(IOAPIC_REG_REDTBL)? as usize;
+        (offset < IOAPIC_NUM_PINS * 2).then_some((offset / 2, offset % 2 == 1))
+    }
+}
+
+/// I/O APIC emulator for one VM
+///
+/// Clones share the device state.
+#[derive(Clone)]
+pub struct Ioapic {
+    /// Base address
+    base_addr: PhysAddr,
+    /// VM the I/O APIC belongs to
+    vm_id: VmId,
+    /// Device state
+    state: Arc<SpinLock<IoapicState>>,
+    /// Message delivery action
+    on_deliver: Option<IoapicDeliverFn>,
+}
+
+impl Ioapic {
+    /// Create an I/O APIC with APIC ID `id` for `vm_id`
+    pub fn new(base_addr: PhysAddr, vm_id: VmId, id: u8) -> Self {
+        Self {
+            base_addr,
+            vm_id,
+            state: Arc::new(SpinLock::new(IoapicState::new(id))),
+            on_deliver: None,
+        }
+    }
+
+    /// Get the base address
+    pub fn base_address(&self) -> PhysAddr {
+        self.base_addr
+    }
+
+    /// Set the message delivery action
+    pub fn set_deliver_handler(&mut self, handler: IoapicDeliverFn) {
+        self.on_deliver = Some(handler);
+    }
+
+    /// Drive input pin `pin`
+    pub fn set_irq(&self, pin: usize, level: bool) -> Result<()> {
+        if pin >= IOAPIC_NUM_PINS {
+            return Err(Error::InvalidArgument);
+        }
+        let msg = self.state.lock().set_irq(pin, level);
+        self.deliver(msg);
+        Ok(())
+    }
+
+    /// EOI broadcast from a local APIC for `vector`
+    pub fn eoi(&self, vector: u8) {
+        let messages = self.state.lock().eoi(vector);
+        for msg in messages {
+            self.deliver(Some(msg));
+        }
+    }
+
+    /// Get a pin's redirection entry
+    pub fn redirection_entry(&self, pin: usize) -> Option<u64> {
+        self.state.lock().redirtbl.get(pin).copied()
+    }
+
+    /// Send a message to the local APICs
+    fn deliver(&self, msg: Option<IoapicMessage>) {
+        if let (Some(msg), Some(on_deliver)) = (msg, self.on_deliver) {
+            on_deliver(self.vm_id, msg);
+        }
+    }
+}
+
+impl Emulator for Ioapic {
+    fn name(&self) -> &str {
+        "IOAPIC"
+    }
+
+    fn read(&self, offset: u64, size: u32) -> core::result::Result<u64, EmulatorError> {
+        if size != 32 {
+            return Err(EmulatorError::InvalidAccess);
+        }
+
+        let state = self.state.lock();
+        match offset {
+            IOAPIC_IOREGSEL => Ok(state.ioregsel as u64),
+            IOAPIC_IOWIN => Ok(state.read_indirect(state.ioregsel) as u64),
+            IOAPIC_EOI => Ok(0),
+            _ => {
+                crate::warn!("IOAPIC: Unhandled read from offset 0x{:x}", offset);
+                Ok(0)
+            }
+        }
+    }
+
+    fn write(&mut self, offset: u64, value: u64, size: u32) -> core::result::Result<(), EmulatorError> {
+        if size != 32 {
+            return Err(EmulatorError::InvalidAccess);
+        }
+
+        match offset {
+            IOAPIC_IOREGSEL => self.state.lock().ioregsel = value as u32 & 0xFF,
+            IOAPIC_IOWIN => {
+                let msg = {
+                    let mut state = self.state.lock();
+                    let index = state.ioregsel;
+                    state.write_indirect(index, value as u32)
+                };
+                self.deliver(msg);
+            }
+            IOAPIC_EOI => self.eoi(value as u8),
+            _ => {
+                crate::warn!("IOAPIC: Unhandled write 0x{:x} to offset 0x{:x}", value, offset);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn reset(&mut self) -> core::result::Result<(), EmulatorError> {
+        let mut state = self.state.lock();
+        *state = IoapicState::new(state.id);
+        Ok(())
+    }
+}
+
+/// Deliver an I/O APIC message to the LAPIC of the destination vCPU
+///
+/// Physical destinations name the vCPU by APIC ID, which matches the vCPU
+/// ID. Logical destinations go to the lowest vCPU in the set. The LAPIC
+/// records level-triggered vectors in its TMR and clears Remote IRR through
+/// `eoi_vm` when the guest EOIs them.
+fn deliver_to_vcpu(vm_id: VmId, msg: IoapicMessage) {
+    let vcpu = if msg.logical {
+        if msg.destination == 0 {
+            return;
+        }
+        msg.destination.trailing_zeros()
+    } else {
+        msg.destination as u32
+    };
+
+    if let Err(e) = crate::emulators::lapic::deliver(vm_id, vcpu, msg.vector, msg.level) {
+        crate::warn!("IOAPIC: failed to deliver vector {} to vCPU {}: {:?}", msg.vector, vcpu, e);
+    }
+}
+
+/// I/O APICs serving as guest interrupt controllers
+static VM_IOAPICS: SpinLock<Vec<Ioapic>> = SpinLock::new(Vec::new());
+
+/// Check whether a VM has an I/O APIC
+pub fn has_ioapic(vm_id: VmId) -> bool {
+    VM_IOAPICS.lock().iter().any(|ioapic| ioapic.vm_id == vm_id)
+}
+
+/// Broadcast a LAPIC's EOI of a level-triggered `vector` to a VM's I/O APIC
+pub fn eoi_vm(vm_id: VmId, vector: u8) {
+    let ioapic = VM_IOAPICS.lock().iter().find(|ioapic| ioapic.vm_id == vm_id).cloned();
+    if let Some(ioapic) = ioapic {
+        ioapic.eoi(vector);
+    }
+}
+
+/// Drive pin `pin` of a VM's I/O APIC
+///
+/// Used as the VM's guest interrupt controller.
+pub fn set_vm_irq(vm_id: VmId, pin: u32, level: bool) {
+    let ioapic = VM_IOAPICS.lock().iter().find(|ioapic| ioapic.vm_id == vm_id).cloned();
+    let result = ioapic.ok_or(Error::NotFound).and_then(|ioapic| ioapic.set_irq(pin as usize, level));
+    if let Err(e) = result {
+        crate::warn!("IOAPIC: VM {} cannot drive pin {}: {:?}", vm_id, pin, e);
+    }
+}
+
+/// Give a VM an I/O APIC at the architectural location, which becomes its
+/// guest interrupt controller
+pub fn attach(vm_id: VmId) -> Result<()> {
+    let mut ioapic = Ioapic::new(IOAPIC_DEFAULT_BASE, vm_id, 0);
+    ioapic.set_deliver_handler(deliver_to_vcpu);
+
+    let base = ioapic.base_address();
+    crate::emulator::register_mmio_emulator(vm_id, "ioapic", DeviceClass::InterruptController, base, IOAPIC_SIZE,
+                                            Box::new(ioapic.clone()))?;
+    VM_IOAPICS.lock().push(ioapic);
+
+    Ok(())
+}
+
+/// Forget a VM's I/O APIC; its emulator is removed with the VM's others
+pub fn detach(vm_id: VmId) {
+    VM_IOAPICS.lock().retain(|ioapic| ioapic.vm_id != vm_id);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Program a pin's redirection entry through the index/data window
+    fn program(state: &mut IoapicState, pin: u32, entry: u64) {
+        state.write_indirect(IOAPIC_REG_REDTBL + pin * 2 + 1, (entry >> 32) as u32);
+        state.write_indirect(IOAPIC_REG_REDTBL + pin * 2, entry as u32);
+    }
+
+    #[test]
+    fn test_edge_pin_delivers_to_programmed_destination() {
+        let mut state = IoapicState::new(0);
+        assert_eq!(state.read_indirect(IOAPIC_REG_VER), 0x0017_0020);
+
+        // Masked at power-on
+        assert_eq!(state.set_irq(4, true), None);
+        state.set_irq(4, false);
+
+        program(&mut state, 4, 0x31 | (2u64 << redir::DEST_SHIFT));
+        assert_eq!(state.read_indirect(IOAPIC_REG_REDTBL + 8), 0x31);
+        assert_eq!(state.read_indirect(IOAPIC_REG_REDTBL + 9), 0x0200_0000);
+
+        let expected = IoapicMessage {
+            vector: 0x31,
+            delivery_mode: 0,
+            logical: false,
+            destination: 2,
+            level: false,
+        };
+        assert_eq!(state.set_irq(4, true), Some(expected));
+        // Held high: no new edge
+        assert_eq!(state.set_irq(4, true), None);
+        state.set_irq(4, false);
+        assert_eq!(state.set_irq(4, true), Some(expected));
+    }
+
+    #[test]
+    fn test_level_pin_waits_for_eoi() {
+        let mut state = IoapicState::new(0);
+        program(&mut state, 9, 0x41 | redir::TRIGGER_LEVEL | redir::MASKED | (1u64 << redir::DEST_SHIFT));
+
+        // Asserted while masked; unmasking delivers it
+        assert_eq!(state.set_irq(9, true), None);
+        let msg = state.write_indirect(IOAPIC_REG_REDTBL + 18, 0x41 | redir::TRIGGER_LEVEL as u32);
+        assert_eq!(msg.map(|m| (m.vector, m.destination, m.level)), Some((0x41, 1, true)));
+        assert_ne!(state.redirtbl[9] & redir::REMOTE_IRR, 0);
+
+        // Remote IRR blocks redelivery until EOI; still asserted, so EOI
+        // delivers again
+        assert_eq!(state.set_irq(9, true), None);
+        assert_eq!(state.eoi(0x41).len(), 1);
+
+        state.set_irq(9, false);
+        assert!(state.eoi(0x41).is_empty());
+        assert_eq!(state.redirtbl[9] & redir::REMOTE_IRR, 0);
+    }
+}
@@ -0,0 +1,861 @@
+//! Local APIC Emulator
+//!
+//! This module provides the xAPIC that each x86 vCPU owns:
+//! - the spurious vector register, which software-enables the APIC
+//! - the task priority, in-service, request and trigger mode registers
+//! - EOI, with an EOI broadcast for level-triggered vectors
+//! - the interrupt command register, used to send IPIs
+//! - the LVT timer in one-shot and periodic mode
+//!
+//! The xAPIC window sits at the same guest physical address on every vCPU;
+//! accesses are dispatched to the LAPIC of the vCPU loaded on the host CPU
+//! that made them (see `load_vcpu`). The APIC ID is the vCPU ID and cannot
+//! be changed by the guest.
+//!
+//! An interrupt is injected into the owning vCPU as soon as it is the
+//! highest pending vector above the processor priority, moving it from the
+//! IRR to the ISR. The guest's EOI retires the highest in-service vector.
+
+use crate::{Result, Error};
+use crate::emulator::{DeviceClass, Emulator, Error as EmulatorError};
+use crate::core::mm::PhysAddr;
+use crate::core::vmm::{VmId, VcpuId};
+use crate::core::sync::SpinLock;
+use crate::libs::cpumask::MAX_CPUS;
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// Default local APIC base address
+pub const LAPIC_DEFAULT_BASE: PhysAddr = 0xFEE0_0000;
+/// Size of the local APIC register window
+pub const LAPIC_SIZE: u64 = 0x1000;
+/// Version register value: integrated APIC, five LVT entries
+pub const LAPIC_VERSION: u32 = 0x0005_0014;
+/// Default APIC bus (timer input) frequency
+pub const LAPIC_BUS_HZ: u64 = 100_000_000;
+/// Maximum number of vCPUs an IPI can address
+pub const LAPIC_MAX_VCPUS: usize = 64;
+
+/// Register offsets
+pub mod reg {
+    /// Local APIC ID
+    pub const ID: u64 = 0x020;
+    /// Version
+    pub const VERSION: u64 = 0x030;
+    /// Task priority
+    pub const TPR: u64 = 0x080;
+    /// Processor priority
+    pub const PPR: u64 = 0x0A0;
+    /// End of interrupt
+    pub const EOI: u64 = 0x0B0;
+    /// Logical destination
+    pub const LDR: u64 = 0x0D0;
+    /// Destination format
+    pub const DFR: u64 = 0x0E0;
+    /// Spurious interrupt vector
+    pub const SVR: u64 = 0x0F0;
+    /// In-service register, eight words
+    pub const ISR: u64 = 0x100;
+    /// Trigger mode register, eight words
+    pub const TMR: u64 = 0x180;
+    /// Interrupt request register, eight words
+    pub const IRR: u64 = 0x200;
+    /// Error status
+    pub const ESR: u64 = 0x280;
+    /// Interrupt command, low dword
+    pub const ICR_LOW: u64 = 0x300;
+    /// Interrupt command, high dword
+    pub const ICR_HIGH: u64 = 0x310;
+    /// LVT timer
+    pub const LVT_TIMER: u64 = 0x320;
+    /// LVT thermal sensor
+    pub const LVT_THERMAL: u64 = 0x330;
+    /// LVT performance counter
+    pub const LVT_PERF: u64 = 0x340;
+    /// LVT LINT0
+    pub const LVT_LINT0: u64 = 0x350;
+    /// LVT LINT1
+    pub const LVT_LINT1: u64 = 0x360;
+    /// LVT error
+    pub const LVT_ERROR: u64 = 0x370;
+    /// Timer initial count
+    pub const TIMER_INITIAL: u64 = 0x380;
+    /// Timer current count
+    pub const TIMER_CURRENT: u64 = 0x390;
+    /// Timer divide configuration
+    pub const TIMER_DIVIDE: u64 = 0x3E0;
+}
+
+/// Spurious vector register: APIC software enable
+pub const SVR_APIC_ENABLED: u32 = 1 << 8;
+/// LVT entry: masked
+pub const LVT_MASKED: u32 = 1 << 16;
+/// LVT timer entry: periodic mode
+pub const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+/// Error status: received illegal vector
+pub const ESR_RECV_ILLEGAL_VECTOR: u32 = 1 << 6;
+/// Error status: sent illegal vector
+pub const ESR_SEND_ILLEGAL_VECTOR: u32 = 1 << 5;
+
+/// Interrupt command register bit definitions
+pub mod icr {
+    /// Interrupt vector
+    pub const VECTOR_MASK: u32 = 0xFF;
+    /// Delivery mode shift
+    pub const DELIVERY_MODE_SHIFT: u32 = 8;
+    /// Delivery mode mask (after shift)
+    pub const DELIVERY_MODE_MASK: u32 = 0x7;
+    /// Logical destination mode
+    pub const DEST_MODE_LOGICAL: u32 = 1 << 11;
+    /// Delivery status (read-only)
+    pub const DELIVERY_STATUS: u32 = 1 << 12;
+    /// Destination shorthand shift
+    pub const SHORTHAND_SHIFT: u32 = 18;
+    /// Destination field shift, in the high dword
+    pub const DEST_SHIFT: u32 = 24;
+
+    /// Fixed delivery mode
+    pub const DM_FIXED: u8 = 0;
+    /// NMI delivery mode
+    pub const DM_NMI: u8 = 4;
+    /// INIT delivery mode
+    pub const DM_INIT: u8 = 5;
+    /// Start-up delivery mode
+    pub const DM_STARTUP: u8 = 6;
+
+    /// No shorthand: use the destination field
+    pub const SHORTHAND_NONE: u32 = 0;
+    /// Self
+    pub const SHORTHAND_SELF: u32 = 1;
+    /// All including self
+    pub const SHORTHAND_ALL: u32 = 2;
+    /// All excluding self
+    pub const SHORTHAND_OTHERS: u32 = 3;
+}
+
+/// IPI sent through the interrupt command register
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LapicIpi {
+    /// Interrupt vector (start-up page for SIPIs)
+    pub vector: u8,
+    /// Delivery mode (see `icr::DM_*`)
+    pub delivery_mode: u8,
+    /// Destination vCPUs, one bit per vCPU ID
+    pub targets: u64,
+}
+
+/// Callback invoked to inject a vector into the owning vCPU
+pub type LapicInterruptFn = fn(vm_id: VmId, vcpu_id: VcpuId, vector: u8);
+
+/// Callback invoked to send an IPI to other vCPUs
+pub type LapicIpiFn = fn(vm_id: VmId, ipi: LapicIpi);
+
+/// Callback invoked on EOI of a level-triggered vector, to be broadcast to
+/// the I/O APICs
+pub type LapicEoiFn = fn(vm_id: VmId, vector: u8);
+
+/// Side effect of a register write
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LapicEvent {
+    /// A level-triggered vector was EOIed
+    LevelEoi(u8),
+    /// An IPI must be sent to other vCPUs
+    Ipi(LapicIpi),
+}
+
+/// Local APIC state
+#[derive(Debug, Clone)]
+pub struct LapicState {
+    /// APIC ID
+    id: u8,
+    /// Task priority
+    tpr: u8,
+    /// Spurious vector register
+    svr: u32,
+    /// Logical destination register
+    ldr: u32,
+    /// Destination format register
+    dfr: u32,
+    /// Error status
+    esr: u32,
+    /// Interrupt request register, one bit per vector
+    irr: [u32; 8],
+    /// In-service register
+    isr: [u32; 8],
+    /// Trigger mode register; set for level-triggered vectors
+    tmr: [u32; 8],
+    /// Interrupt command register
+    icr: u64,
+    /// LVT timer entry
+    lvt_timer: u32,
+    /// LVT LINT0 entry
+    lvt_lint0: u32,
+    /// LVT LINT1 entry
+    lvt_lint1: u32,
+    /// LVT error entry
+    lvt_error: u32,
+    /// Timer initial count
+    timer_initial: u32,
+    /// Timer divide configuration
+    timer_divide: u32,
+    /// Bus tick at which the timer fires next
+    timer_deadline: Option<u64>,
+}
+
+impl LapicState {
+    /// Create the power-on state: software-disabled, all LVTs masked
+    fn new(id: u8) -> Self {
+        Self {
+            id,
+            tpr: 0,
+            svr: 0xFF,
+            ldr: 0,
+            dfr: 0xFFFF_FFFF,
+            esr: 0,
+            irr: [0; 8],
+            isr: [0; 8],
+            tmr: [0; 8],
+            icr: 0,
+            lvt_timer: LVT_MASKED,
+            lvt_lint0: LVT_MASKED,
+            lvt_lint1: LVT_MASKED,
+            lvt_error: LVT_MASKED,
+            timer_initial: 0,
+            timer_divide: 0,
+            timer_deadline: None,
+        }
+    }
+
+    /// Whether the APIC is software-enabled
+    fn enabled(&self) -> bool {
+        self.svr & SVR_APIC_ENABLED != 0
+    }
+
+    /// Processor priority: the higher of the task priority and the
+    /// priority class of the highest in-service vector
+    fn ppr(&self) -> u8 {
+        let isrv = highest_vector(&self.isr).unwrap_or(0);
+        if self.tpr >> 4 >= isrv >> 4 {
+            self.tpr
+        } else {
+            isrv & 0xF0
+        }
+    }
+
+    /// Highest requested vector whose priority class is above the
+    /// processor priority
+    fn pending(&self) -> Option<u8> {
+        let vector = highest_vector(&self.irr)?;
+        (vector >> 4 > self.ppr() >> 4).then_some(vector)
+    }
+
+    /// Request `vector`
+    fn accept(&mut self, vector: u8, level: bool) {
+        if vector < 16 {
+            self.esr |= ESR_RECV_ILLEGAL_VECTOR;
+            return;
+        }
+        set_vector(&mut self.irr, vector, true);
+        set_vector(&mut self.tmr, vector, level);
+    }
+
+    /// Move the highest pending vector from the IRR to the ISR
+    ///
+    /// Returns the vector to inject into the vCPU, if any.
+    fn ack(&mut self) -> Option<u8> {
+        let vector = self.pending()?;
+        set_vector(&mut self.irr, vector, false);
+        set_vector(&mut self.isr, vector, true);
+        Some(vector)
+    }
+
+    /// Retire the highest in-service vector
+    ///
+    /// Returns the vector and whether it was level-triggered.
+    fn eoi(&mut self) -> Option<(u8, bool)> {
+        let vector = highest_vector(&self.isr)?;
+        set_vector(&mut self.isr, vector, false);
+        let level = test_vector(&self.tmr, vector);
+        set_vector(&mut self.tmr, vector, false);
+        Some((vector, level))
+    }
+
+    /// Send the IPI described by the interrupt command register
+    ///
+    /// A fixed IPI that includes this vCPU is accepted locally; the
+    /// remaining destinations are returned. Logical destinations assume
+    /// the flat model with vCPU n at logical ID bit n.
+    fn send_ipi(&mut self, nr_vcpus: usize) -> Option<LapicIpi> {
+        let low = self.icr as u32;
+        let vector = (low & icr::VECTOR_MASK) as u8;
+        let delivery_mode = ((low >> icr::DELIVERY_MODE_SHIFT) & icr::DELIVERY_MODE_MASK) as u8;
+        let dest = (self.icr >> 32 >> icr::DEST_SHIFT) as u8;
+
+        let all = if nr_vcpus >= LAPIC_MAX_VCPUS { u64::MAX } else { (1u64 << nr_vcpus) - 1 };
+        let this = 1u64 << self.id;
+        let mut targets = match (low >> icr::SHORTHAND_SHIFT) & 0x3 {
+            icr::SHORTHAND_SELF => this,
+            icr::SHORTHAND_ALL => all,
+            icr::SHORTHAND_OTHERS => all & !this,
+            _ if dest == 0xFF => all,
+            _ if low & icr::DEST_MODE_LOGICAL != 0 => dest as u64 & all,
+            _ => 1u64.checked_shl(dest as u32).unwrap_or(0) & all,
+        };
+
+        if delivery_mode == icr::DM_FIXED {
+            if vector < 16 {
+                self.esr |= ESR_SEND_ILLEGAL_VECTOR;
+                return None;
+            }
+            if targets & this != 0 {
+                self.accept(vector, false);
+                targets &= !this;
+            }
+        }
+
+        (targets != 0).then_some(LapicIpi { vector, delivery_mode, targets })
+    }
+
+    /// Timer divisor selected by the divide configuration register
+    fn timer_divisor(&self) -> u64 {
+        let code = (self.timer_divide & 0x3) | ((self.timer_divide & 0x8) >> 1);
+        if code == 0x7 {
+            1
+        } else {
+            2 << code
+        }
+    }
+
+    /// Timer period in bus ticks
+    fn timer_period(&self) -> u64 {
+        self.timer_initial as u64 * self.timer_divisor()
+    }
+
+    /// Current count of the timer at bus tick `now`
+    fn timer_current(&self, now: u64) -> u32 {
+        self.timer_deadline
+            .map_or(0, |deadline| (deadline.saturating_sub(now) / self.timer_divisor()) as u32)
+    }
+
+    /// Fire the timer if its deadline has passed
+    ///
+    /// Periodic timers are re-armed; missed periods are not replayed.
+    fn poll_timer(&mut self, now: u64) {
+        let Some(deadline) = self.timer_deadline.filter(|&deadline| deadline <= now) else {
+            return;
+        };
+
+        self.timer_deadline = if self.lvt_timer & LVT_TIMER_PERIODIC != 0 {
+            let period = self.timer_period();
+            Some(deadline + ((now - deadline) / period + 1) * period)
+        } else {
+            None
+        };
+
+        if self.lvt_timer & LVT_MASKED == 0 {
+            self.accept(self.lvt_timer as u8, false);
+        }
+    }
+
+    /// Read a register at bus tick `now`
+    fn read(&self, offset: u64, now: u64) -> u32 {
+        match offset {
+            reg::ID => (self.id as u32) << 24,
+            reg::VERSION => LAPIC_VERSION,
+            reg::TPR => self.tpr as u32,
+            reg::PPR => self.ppr() as u32,
+            reg::LDR => self.ldr,
+            reg::DFR => self.dfr,
+            reg::SVR => self.svr,
+            reg::ESR => self.esr,
+            reg::ICR_LOW => self.icr as u32 & !icr::DELIVERY_STATUS,
+            reg::ICR_HIGH => (self.icr >> 32) as u32,
+            reg::LVT_TIMER => self.lvt_timer,
+            reg::LVT_THERMAL | reg::LVT_PERF => LVT_MASKED,
+            reg::LVT_LINT0 => self.lvt_lint0,
+            reg::LVT_LINT1 => self.lvt_lint1,
+            reg::LVT_ERROR => self.lvt_error,
+            reg::TIMER_INITIAL => self.timer_initial,
+            reg::TIMER_CURRENT => self.timer_current(now),
+            reg::TIMER_DIVIDE => self.timer_divide,
+            _ => match Self::bank_index(offset) {
+                Some((reg::ISR, i)) => self.isr[i],
+                Some((reg::TMR, i)) => self.tmr[i],
+                Some((reg::IRR, i)) => self.irr[i],
+                _ => 0,
+            },
+        }
+    }
+
+    /// Write a register at bus tick `now`
+    ///
+    /// Returns the side effect the caller must carry out, if any.
+    fn write(&mut self, offset: u64, value: u32, now: u64, nr_vcpus: usize) -> Option<LapicEvent> {
+        // LVT entries stay masked while the APIC is software-disabled
+        let lvt = |value: u32, enabled: bool| if enabled { value } else { value | LVT_MASKED };
+        let enabled = self.enabled();
+
+        match offset {
+            // The APIC ID names the vCPU in IPI and I/O APIC destinations
+            reg::ID => {}
+            reg::TPR => self.tpr = value as u8,
+            reg::EOI => {
+                let (vector, level) = self.eoi()?;
+                return level.then_some(LapicEvent::LevelEoi(vector));
+            }
+            reg::LDR => self.ldr = value & 0xFF00_0000,
+            reg::DFR => self.dfr = value | 0x0FFF_FFFF,
+            reg::SVR => {
+                self.svr = value & 0x1FF;
+                if !self.enabled() {
+                    self.lvt_timer |= LVT_MASKED;
+                    self.lvt_lint0 |= LVT_MASKED;
+                    self.lvt_lint1 |= LVT_MASKED;
+                    self.lvt_error |= LVT_MASKED;
+                }
+            }
+            // Writes to the ESR latch the errors seen since the last write
+            reg::ESR => {}
+            reg::ICR_HIGH => self.icr = (self.icr & 0xFFFF_FFFF) | ((value as u64) << 32),
+            reg::ICR_LOW => {
+                self.icr = (self.icr & !0xFFFF_FFFF) | (value & !icr::DELIVERY_STATUS) as u64;
+                return self.send_ipi(nr_vcpus).map(LapicEvent::Ipi);
+            }
+            reg::LVT_TIMER => self.lvt_timer = lvt(value & 0x3_10FF, enabled),
+            reg::LVT_LINT0 => self.lvt_lint0 = lvt(value & 0x1_A7FF, enabled),
+            reg::LVT_LINT1 => self.lvt_lint1 = lvt(value & 0x1_A7FF, enabled),
+            reg::LVT_ERROR => self.lvt_error = lvt(value & 0x1_00FF, enabled),
+            reg::TIMER_INITIAL => {
+                self.timer_initial = value;
+                self.timer_deadline = (value != 0).then(|| now + self.timer_period());
+            }
+            reg::TIMER_DIVIDE => self.timer_divide = value & 0xB,
+            _ => {}
+        }
+        None
+    }
+
+    /// Map an offset within the ISR, TMR or IRR banks to (bank, word)
+    fn bank_index(offset: u64) -> Option<(u64, usize)> {
+        let bank = offset & !0x7F;
+        matches!(bank, reg::ISR | reg::TMR | reg::IRR).then_some((bank, ((offset & 0x7F) >> 4) as usize))
+    }
+}
+
+/// Highest vector set in a 256-bit register bank
+fn highest_vector(bank: &[u32; 8]) -> Option<u8> {
+    bank.iter()
+        .enumerate()
+        .rev()
+        .find(|(_, &word)| word != 0)
+        .map(|(i, &word)| (i * 32 + 31 - word.leading_zeros() as usize) as u8)
+}
+
+/// Check a vector in a 256-bit register bank
+fn test_vector(bank: &[u32; 8], vector: u8) -> bool {
+    bank[vector as usize / 32] & (1 << (vector % 32)) != 0
+}
+
+/// Set or clear a vector in a 256-bit register bank
+fn set_vector(bank: &mut [u32; 8], vector: u8, value: bool) {
+    let bit = 1 << (vector % 32);
+    if value {
+        bank[vector as usize / 32] |= bit;
+    } else {
+        bank[vector as usize / 32] &= !bit;
+    }
+}
+
+/// Local APIC emulator for one vCPU
+///
+/// Clones share the device state.
+#[derive(Clone)]
+pub struct Lapic {
+    /// Base address
+    base_addr: PhysAddr,
+    /// VM the LAPIC belongs to
+    vm_id: VmId,
+    /// vCPU the LAPIC belongs to
+    vcpu_id: VcpuId,
+    /// Number of vCPUs in the VM, bounding IPI destinations
+    nr_vcpus: usize,
+    /// Host timestamp (ns) at which the bus clock was zero
+    epoch_ns: u64,
+    /// Device state
+    state: Arc<SpinLock<LapicState>>,
+    /// Interrupt injection action
+    on_interrupt: Option<LapicInterruptFn>,
+    /// IPI action
+    on_ipi: Option<LapicIpiFn>,
+    /// Level-triggered EOI action
+    on_eoi: Option<LapicEoiFn>,
+}
+
+impl Lapic {
+    /// Create the LAPIC of `vcpu_id` in a VM with `nr_vcpus` vCPUs
+    ///
+    /// The APIC ID is the vCPU ID.
+    pub fn new(base_addr: PhysAddr, vm_id: VmId, vcpu_id: VcpuId, nr_vcpus: usize) -> Result<Self> {
+        if nr_vcpus == 0 || nr_vcpus > LAPIC_MAX_VCPUS || vcpu_id as usize >= nr_vcpus {
+            return Err(Error::InvalidArgument);
+        }
+
+        Ok(Self {
+            base_addr,
+            vm_id,
+            vcpu_id,
+            nr_vcpus,
+            epoch_ns: crate::utils::time::timestamp_ns(),
+            state: Arc::new(SpinLock::new(LapicState::new(vcpu_id as u8))),
+            on_interrupt: None,
+            on_ipi: None,
+            on_eoi: None,
+        })
+    }
+
+    /// Get the base address
+    pub fn base_address(&self) -> PhysAddr {
+        self.base_addr
+    }
+
+    /// Set the interrupt injection action
+    pub fn set_interrupt_handler(&mut self, handler: LapicInterruptFn) {
+        self.on_interrupt = Some(handler);
+    }
+
+    /// Set the IPI action
+    pub fn set_ipi_handler(&mut self, handler: LapicIpiFn) {
+        self.on_ipi = Some(handler);
+    }
+
+    /// Set the level-triggered EOI action
+    pub fn set_eoi_handler(&mut self, handler: LapicEoiFn) {
+        self.on_eoi = Some(handler);
+    }
+
+    /// Bus ticks elapsed since the LAPIC was created
+    fn bus_ticks(&self) -> u64 {
+        let elapsed = crate::utils::time::timestamp_ns().saturating_sub(self.epoch_ns);
+        (elapsed as u128 * LAPIC_BUS_HZ as u128 / crate::utils::time::NSEC_PER_SEC as u128) as u64
+    }
+
+    /// Request `vector` on this LAPIC, e.g. from an I/O APIC or another
+    /// vCPU's IPI
+    pub fn accept_irq(&self, vector: u8, level: bool) {
+        self.state.lock().accept(vector, level);
+        self.inject_pending();
+    }
+
+    /// Bus tick at which the timer fires next
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.state.lock().timer_deadline
+    }
+
+    /// Inject deliverable vectors into the owning vCPU
+    ///
+    /// Each injected vector moves to the ISR, raising the processor
+    /// priority, so this stops at the first vector that cannot preempt.
+    fn inject_pending(&self) {
+        let Some(on_interrupt) = self.on_interrupt else {
+            return;
+        };
+        while let Some(vector) = self.state.lock().ack() {
+            on_interrupt(self.vm_id, self.vcpu_id, vector);
+        }
+    }
+}
+
+impl Emulator for Lapic {
+    fn name(&self) -> &str {
+        "LAPIC"
+    }
+
+    fn read(&self, offset: u64, size: u32) -> core::result::Result<u64, EmulatorError> {
+        if size != 32 || offset & 0xF != 0 {
+            return Err(EmulatorError::InvalidAccess);
+        }
+
+        let now = self.bus_ticks();
+        Ok(self.state.lock().read(offset, now) as u64)
+    }
+
+    fn write(&mut self, offset: u64, value: u64, size: u32) -> core::result::Result<(), EmulatorError> {
+        if size != 32 || offset & 0xF != 0 {
+            return Err(EmulatorError::InvalidAccess);
+        }
+
+        let now = self.bus_ticks();
+        let event = self.state.lock().write(offset, value as u32, now, self.nr_vcpus);
+        match event {
+            Some(LapicEvent::LevelEoi(vector)) => {
+                if let Some(on_eoi) = self.on_eoi {
+                    on_eoi(self.vm_id, vector);
+                }
+            }
+            Some(LapicEvent::Ipi(ipi)) => {
+                if let Some(on_ipi) = self.on_ipi {
+                    on_ipi(self.vm_id, ipi);
+                }
+            }
+            None => {}
+        }
+
+        // EOI, TPR and self-IPIs can make a vector deliverable
+        self.inject_pending();
+        Ok(())
+    }
+
+    fn reset(&mut self) -> core::result::Result<(), EmulatorError> {
+        let mut state = self.state.lock();
+        *state = LapicState::new(self.vcpu_id as u8);
+        Ok(())
+    }
+
+    /// Fire the timer if it has expired
+    ///
+    /// Called from the emulator poll loop and before entering the vCPU.
+    fn poll(&mut self) {
+        let now = self.bus_ticks();
+        self.state.lock().poll_timer(now);
+        self.inject_pending();
+    }
+}
+
+/// The xAPIC window of a VM, shared by its vCPUs' LAPICs
+struct LapicWindow {
+    /// VM the LAPICs belong to
+    vm_id: VmId,
+    /// LAPICs indexed by vCPU ID
+    lapics: Vec<Lapic>,
+}
+
+impl LapicWindow {
+    /// LAPIC of the vCPU loaded on this host CPU
+    fn current(&self) -> core::result::Result<&Lapic, EmulatorError> {
+        match LOADED_VCPUS.lock()[crate::core::cpu_id() % MAX_CPUS] {
+            Some((vm_id, vcpu_id)) if vm_id == self.vm_id => {
+                self.lapics.get(vcpu_id as usize).ok_or(EmulatorError::DeviceNotFound)
+            }
+            _ => Err(EmulatorError::DeviceNotFound),
+        }
+    }
+}
+
+impl Emulator for LapicWindow {
+    fn name(&self) -> &str {
+        "LAPIC"
+    }
+
+    fn read(&self, offset: u64, size: u32) -> core::result::Result<u64, EmulatorError> {
+        self.current()?.read(offset, size)
+    }
+
+    fn write(&mut self, offset: u64, value: u64, size: u32) -> core::result::Result<(), EmulatorError> {
+        let mut lapic = self.current()?.clone();
+        lapic.write(offset, value, size)
+    }
+
+    fn reset(&mut self) -> core::result::Result<(), EmulatorError> {
+        self.lapics.iter_mut().try_for_each(|lapic| lapic.reset())
+    }
+
+    fn poll(&mut self) {
+        for lapic in self.lapics.iter_mut() {
+            lapic.poll();
+        }
+    }
+}
+
+/// LAPICs of every VM, for delivery from IPIs and I/O APICs
+static VM_LAPICS: SpinLock<Vec<Lapic>> = SpinLock::new(Vec::new());
+
+/// vCPU loaded on each host CPU, as `(vm, vcpu)`
+static LOADED_VCPUS: SpinLock<[Option<(VmId, VcpuId)>; MAX_CPUS]> = SpinLock::new([None; MAX_CPUS]);
+
+/// Find the LAPIC of a vCPU
+fn vcpu_lapic(vm_id: VmId, vcpu_id: VcpuId) -> Option<Lapic> {
+    VM_LAPICS.lock().iter().find(|lapic| lapic.vm_id == vm_id && lapic.vcpu_id == vcpu_id).cloned()
+}
+
+/// Request `vector` on a vCPU's LAPIC, e.g. for an I/O APIC message
+///
+/// A level-triggered vector is marked in the TMR, so its EOI is broadcast
+/// back to the I/O APIC.
+pub fn deliver(vm_id: VmId, vcpu_id: VcpuId, vector: u8, level: bool) -> Result<()> {
+    let lapic = vcpu_lapic(vm_id, vcpu_id).ok_or(Error::NotFound)?;
+    lapic.accept_irq(vector, level);
+    Ok(())
+}
+
+/// Make the xAPIC window of this host CPU decode to a vCPU's LAPIC and
+/// deliver its expired timer, before the vCPU is entered
+pub fn load_vcpu(vm_id: VmId, vcpu_id: VcpuId) {
+    LOADED_VCPUS.lock()[crate::core::cpu_id() % MAX_CPUS] = Some((vm_id, vcpu_id));
+    if let Some(mut lapic) = vcpu_lapic(vm_id, vcpu_id) {
+        lapic.poll();
+    }
+}
+
+/// Inject a vector into a vCPU
+fn inject_to_vcpu(vm_id: VmId, vcpu_id: VcpuId, vector: u8) {
+    if let Err(e) = crate::core::vmm::inject_interrupt(vm_id, vcpu_id, vector as u32) {
+        crate::warn!("LAPIC: failed to inject vector {} into vCPU {}: {:?}", vector, vcpu_id, e);
+    }
+}
+
+/// Deliver a fixed IPI to the LAPICs of its destination vCPUs
+fn deliver_ipi(vm_id: VmId, ipi: LapicIpi) {
+    if ipi.delivery_mode != icr::DM_FIXED {
+        crate::debug!("LAPIC: delivery mode {} IPI to 0x{:x} not emulated", ipi.delivery_mode, ipi.targets);
+        return;
+    }
+
+    let mut targets = ipi.targets;
+    while targets != 0 {
+        let vcpu = targets.trailing_zeros();
+        targets &= targets - 1;
+        match vcpu_lapic(vm_id, vcpu) {
+            Some(lapic) => lapic.accept_irq(ipi.vector, false),
+            None => crate::warn!("LAPIC: VM {} has no vCPU {} for IPI", vm_id, vcpu),
+        }
+    }
+}
+
+/// Broadcast the EOI of a level-triggered vector to the VM's I/O APIC
+fn broadcast_eoi(vm_id: VmId, vector: u8) {
+    crate::emulators::ioapic::eoi_vm(vm_id, vector);
+}
+
+/// Give each of a VM's `nr_vcpus` vCPUs a LAPIC at the architectural
+/// location
+pub fn attach(vm_id: VmId, nr_vcpus: usize) -> Result<()> {
+    let mut lapics = Vec::with_capacity(nr_vcpus);
+    for vcpu_id in 0..nr_vcpus {
+        let mut lapic = Lapic::new(LAPIC_DEFAULT_BASE, vm_id, vcpu_id as VcpuId, nr_vcpus)?;
+        lapic.set_interrupt_handler(inject_to_vcpu);
+        lapic.set_ipi_handler(deliver_ipi);
+        lapic.set_eoi_handler(broadcast_eoi);
+        lapics.push(lapic);
+    }
+
+    let window = LapicWindow { vm_id, lapics: lapics.clone() };
+    crate::emulator::register_mmio_emulator(vm_id, "lapic", DeviceClass::InterruptController, LAPIC_DEFAULT_BASE,
+                                            LAPIC_SIZE, Box::new(window))?;
+    VM_LAPICS.lock().extend(lapics);
+
+    Ok(())
+}
+
+/// Forget a VM's LAPICs; their emulator is removed with the VM's others
+pub fn detach(vm_id: VmId) {
+    VM_LAPICS.lock().retain(|lapic| lapic.vm_id != vm_id);
+    for loaded in LOADED_VCPUS.lock().iter_mut() {
+        if matches!(loaded, Some((loaded_vm, _)) if *loaded_vm == vm_id) {
+            *loaded = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Send an IPI from `state` through the interrupt command register
+    fn write_icr(state: &mut LapicState, dest: u8, low: u32) -> Option<LapicEvent> {
+        state.write(reg::ICR_HIGH, (dest as u32) << icr::DEST_SHIFT, 0, 4);
+        state.write(reg::ICR_LOW, low, 0, 4)
+    }
+
+    #[test]
+    fn test_self_ipi_sets_irr_and_eoi_advances_isr() {
+        let mut state = LapicState::new(1);
+        state.write(reg::SVR, SVR_APIC_ENABLED | 0xFF, 0, 4);
+
+        assert_eq!(write_icr(&mut state, 0, 0x40 | (icr::SHORTHAND_SELF << icr::SHORTHAND_SHIFT)), None);
+        assert_eq!(state.read(reg::IRR + 0x20, 0), 1);
+        assert_eq!(state.ack(), Some(0x40));
+        assert_eq!(state.read(reg::IRR + 0x20, 0), 0);
+        assert_eq!(state.read(reg::ISR + 0x20, 0), 1);
+
+        // A physical IPI to our own ID is local too; its higher priority
+        // class preempts 0x40
+        assert_eq!(write_icr(&mut state, 1, 0x50), None);
+        assert_eq!(state.ack(), Some(0x50));
+        assert_eq!(state.read(reg::PPR, 0), 0x50);
+
+        // Same class as the in-service 0x50: held in the IRR
+        write_icr(&mut state, 1, 0x51);
+        assert_eq!(state.ack(), None);
+
+        // Each EOI retires the highest in-service vector
+        assert_eq!(state.write(reg::EOI, 0, 0, 4), None);
+        assert_eq!(state.read(reg::ISR + 0x20, 0), 1);
+        assert_eq!(state.ack(), Some(0x51));
+        state.write(reg::EOI, 0, 0, 4);
+        state.write(reg::EOI, 0, 0, 4);
+        assert_eq!(highest_vector(&state.isr), None);
+
+        // Level-triggered vectors are broadcast on EOI
+        state.accept(0x60, true);
+        assert_eq!(state.ack(), Some(0x60));
+        assert_eq!(state.write(reg::EOI, 0, 0, 4), Some(LapicEvent::LevelEoi(0x60)));
+    }
+
+    #[test]
+    fn test_ipi_destinations_exclude_self() {
+        let mut state = LapicState::new(1);
+        state.write(reg::SVR, SVR_APIC_ENABLED, 0, 4);
+
+        let ipi = |targets| Some(LapicEvent::Ipi(LapicIpi { vector: 0x30, delivery_mode: icr::DM_FIXED, targets }));
+        assert_eq!(write_icr(&mut state, 0, 0x30 | (icr::SHORTHAND_ALL << icr::SHORTHAND_SHIFT)), ipi(0b1101));
+        assert_eq!(write_icr(&mut state, 0, 0x30 | (icr::SHORTHAND_OTHERS << icr::SHORTHAND_SHIFT)), ipi(0b1101));
+        assert_eq!(write_icr(&mut state, 0b0110, 0x30 | icr::DEST_MODE_LOGICAL), ipi(0b0100));
+        assert_eq!(write_icr(&mut state, 9, 0x30), None);
+        assert!(test_vector(&state.irr, 0x30));
+
+        // Illegal vectors are flagged, not sent
+        assert_eq!(write_icr(&mut state, 2, 0x05), None);
+        assert_eq!(state.read(reg::ESR, 0), ESR_SEND_ILLEGAL_VECTOR);
+    }
+
+    #[test]
+    fn test_apic_id_is_read_only() {
+        let mut state = LapicState::new(1);
+        state.write(reg::SVR, SVR_APIC_ENABLED, 0, 4);
+
+        state.write(reg::ID, 0xFF << 24, 0, 4);
+        assert_eq!(state.read(reg::ID, 0), 1 << 24);
+
+        // A self-IPI still lands on this vCPU
+        assert_eq!(write_icr(&mut state, 0, 0x40 | (icr::SHORTHAND_SELF << icr::SHORTHAND_SHIFT)), None);
+        assert!(test_vector(&state.irr, 0x40));
+    }
+
+    #[test]
+    fn test_timer_one_shot_and_periodic() {
+        let mut state = LapicState::new(0);
+        state.write(reg::SVR, SVR_APIC_ENABLED, 0, 1);
+
+        // Divide by 4: one count every four bus ticks
+        state.write(reg::TIMER_DIVIDE, 0x1, 0, 1);
+        state.write(reg::LVT_TIMER, 0xEC, 0, 1);
+        state.write(reg::TIMER_INITIAL, 100, 1_000, 1);
+        assert_eq!(state.read(reg::TIMER_CURRENT, 1_200), 50);
+
+        state.poll_timer(1_399);
+        assert_eq!(state.pending(), None);
+        state.poll_timer(1_400);
+        assert_eq!(state.pending(), Some(0xEC));
+        assert_eq!(state.read(reg::TIMER_CURRENT, 1_500), 0);
+        state.ack();
+        state.eoi();
+
+        // Periodic: re-armed from the missed deadline
+        state.write(reg::LVT_TIMER, 0xEC | LVT_TIMER_PERIODIC, 2_000, 1);
+        state.write(reg::TIMER_INITIAL, 100, 2_000, 1);
+        state.poll_timer(2_900);
+        assert_eq!(state.pending(), Some(0xEC));
+        assert_eq!(state.timer_deadline, Some(3_200));
+    }
+}
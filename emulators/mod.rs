@@ -0,0 +1,66 @@
+//! Emulated guest devices
+//!
+//! Each submodule implements `crate::emulator::Emulator` for one device
+//! family. Every VM gets its own instances of the platform devices of the
+//! host architecture when it is created.
+
+use crate::config::VmConfig;
+use crate::core::vmm::VmId;
+use crate::Result;
+
+pub mod clint;
+pub mod flash;
+pub mod gpio;
+pub mod ioapic;
+pub mod lapic;
+pub mod pci_host;
+pub mod rtc;
+pub mod uart;
+pub mod virtio_mmio;
+pub mod watchdog;
+
+/// Register the platform devices of a new VM
+///
+/// On failure the devices registered so far are removed again.
+pub fn attach(vm_id: VmId, config: &VmConfig) -> Result<()> {
+    let result = attach_platform(vm_id, config);
+    if result.is_err() {
+        detach(vm_id);
+    }
+    result
+}
+
+/// Remove the devices of a VM being destroyed
+pub fn detach(vm_id: VmId) {
+    ioapic::detach(vm_id);
+    lapic::detach(vm_id);
+    crate::emulator::remove_vm_emulators(vm_id);
+}
+
+#[cfg(target_arch = "aarch64")]
+fn attach_platform(vm_id: VmId, config: &VmConfig) -> Result<()> {
+    uart::attach_pl011(vm_id)?;
+    rtc::attach_pl031(vm_id)?;
+    gpio::attach(vm_id)?;
+    watchdog::attach(vm_id)?;
+    let flash_banks = config.devices.iter()
+        .filter(|device| device.device_type == crate::config::DeviceType::Flash);
+    for device in flash_banks {
+        flash::attach(vm_id, device)?;
+    }
+    pci_host::attach(vm_id)
+}
+
+#[cfg(target_arch = "riscv64")]
+fn attach_platform(vm_id: VmId, config: &VmConfig) -> Result<()> {
+    clint::attach(vm_id, config.vcpu_count)?;
+    pci_host::attach(vm_id)
+}
+
+#[cfg(target_arch = "x86_64")]
+fn attach_platform(vm_id: VmId, config: &VmConfig) -> Result<()> {
+    uart::attach_16550(vm_id)?;
+    rtc::attach_mc146818(vm_id)?;
+    ioapic::attach(vm_id)?;
+    lapic::attach(vm_id, config.vcpu_count)
+}
@@ -0,0 +1,553 @@
+//! PCIe Host Bridge Emulator
+//!
+//! This module provides a generic ECAM PCIe host bridge for guests, like
+//! the `pci-host-ecam-generic` bridge on QEMU virt. Configuration space of
+//! each function is a 4KiB window in the ECAM region:
+//!
+//! ```text
+//! offset = bus << 20 | device << 15 | function << 12 | register
+//! ```
+//!
+//! Functions are synthesized from a `VirtualFunction` description: the
+//! bridge builds a type 0 header from its IDs and BAR sizes, handles BAR
+//! sizing and assignment, and routes accesses to assigned memory BARs to
+//! the function's backing emulator. Empty slots read as all ones.
+//!
+//! The bridge is registered for its ECAM window only; guest accesses to
+//! BARs the guest enabled reach it through `Emulator::decodes`, so the
+//! BARs move with every reprogramming.
+
+use crate::{Result, Error};
+use crate::emulator::{DeviceClass, Emulator, Error as EmulatorError};
+use crate::core::vmm::VmId;
+use crate::core::mm::PhysAddr;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+/// Type 0 configuration header registers
+#[allow(dead_code)]
+#[repr(usize)]
+enum PciConfigRegister {
+    VendorDevice = 0x00,
+    CommandStatus = 0x04,
+    ClassRevision = 0x08,
+    HeaderType = 0x0C,
+    Bar0 = 0x10,
+    Bar5 = 0x24,
+    Subsystem = 0x2C,
+    InterruptLine = 0x3C,
+}
+
+/// Size of one function's configuration space
+const PCI_CONFIG_SIZE: u64 = 0x1000;
+/// Number of BARs in a type 0 header
+const PCI_NUM_BARS: usize = 6;
+/// Devices per bus
+const PCI_DEVICES_PER_BUS: u8 = 32;
+/// Functions per device
+const PCI_FUNCTIONS_PER_DEVICE: u8 = 8;
+
+/// Command register: memory space decode enable
+pub const PCI_COMMAND_MEMORY: u16 = 1 << 1;
+/// Command register: bus master enable
+pub const PCI_COMMAND_MASTER: u16 = 1 << 2;
+/// Command register: INTx disable
+pub const PCI_COMMAND_INTX_DISABLE: u16 = 1 << 10;
+/// Writable command register bits
+const PCI_COMMAND_MASK: u16 = PCI_COMMAND_MEMORY | PCI_COMMAND_MASTER | PCI_COMMAND_INTX_DISABLE;
+
+/// Header type field value of a multi-function device
+const PCI_HEADER_MULTIFUNCTION: u8 = 0x80;
+
+/// Class code of a host bridge
+pub const PCI_CLASS_HOST_BRIDGE: u32 = 0x06_00_00;
+
+/// Location of a function on the bus
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PciAddress {
+    /// Bus number
+    pub bus: u8,
+    /// Device number (0-31)
+    pub device: u8,
+    /// Function number (0-7)
+    pub function: u8,
+}
+
+impl PciAddress {
+    /// Create a bus/device/function address
+    pub const fn new(bus: u8, device: u8, function: u8) -> Self {
+        Self { bus, device, function }
+    }
+
+    /// Decode an ECAM offset into a function address and register offset
+    pub fn from_ecam_offset(offset: u64) -> (Self, usize) {
+        let addr = Self {
+            bus: (offset >> 20) as u8,
+            device: ((offset >> 15) & 0x1F) as u8,
+            function: ((offset >> 12) & 0x7) as u8,
+        };
+        (addr, (offset & (PCI_CONFIG_SIZE - 1)) as usize)
+    }
+}
+
+/// Description of a synthesized PCI function
+pub struct VirtualFunction {
+    /// Vendor ID
+    pub vendor_id: u16,
+    /// Device ID
+    pub device_id: u16,
+    /// Class code (base class, subclass, programming interface)
+    pub class_code: u32,
+    /// Revision ID
+    pub revision: u8,
+    /// Subsystem vendor ID
+    pub subsystem_vendor_id: u16,
+    /// Subsystem ID
+    pub subsystem_id: u16,
+    /// Size of each 32-bit memory BAR, 0 if unimplemented
+    ///
+    /// Sizes must be powers of two of at least 16 bytes.
+    pub bar_sizes: [u32; PCI_NUM_BARS],
+    /// Interrupt pin (1 = INTA# .. 4 = INTD#, 0 for none)
+    pub interrupt_pin: u8,
+    /// Emulator handling accesses to the function's BARs
+    pub backend: Option<Box<dyn Emulator>>,
+}
+
+/// Configuration state of a function
+struct PciSlot {
+    /// Bus location
+    addr: PciAddress,
+    /// Function description
+    function: VirtualFunction,
+    /// Command register
+    command: u16,
+    /// Programmed BAR values
+    bars: [u32; PCI_NUM_BARS],
+    /// Interrupt line
+    interrupt_line: u8,
+}
+
+impl PciSlot {
+    /// Create the power-on state of a function
+    fn new(addr: PciAddress, function: VirtualFunction) -> Self {
+        Self {
+            addr,
+            function,
+            command: 0,
+            bars: [0; PCI_NUM_BARS],
+            interrupt_line: 0xFF,
+        }
+    }
+
+    /// BAR value with its low bits forced to the size mask
+    ///
+    /// Writing all ones to a BAR and reading it back yields `!(size - 1)`,
+    /// from which the guest works out the BAR size.
+    fn bar_value(&self, index: usize) -> u32 {
+        match self.function.bar_sizes[index] {
+            0 => 0,
+            size => self.bars[index] & !(size - 1),
+        }
+    }
+
+    /// Read an aligned configuration dword
+    fn read_dword(&self, reg: usize, multifunction: bool) -> u32 {
+        let f = &self.function;
+        match reg {
+            x if x == PciConfigRegister::VendorDevice as usize => {
+                f.vendor_id as u32 | (f.device_id as u32) << 16
+            }
+            x if x == PciConfigRegister::CommandStatus as usize => self.command as u32,
+            x if x == PciConfigRegister::ClassRevision as usize => {
+                f.revision as u32 | (f.class_code & 0xFF_FFFF) << 8
+            }
+            x if x == PciConfigRegister::HeaderType as usize => {
+                let header = if multifunction { PCI_HEADER_MULTIFUNCTION } else { 0 };
+                (header as u32) << 16
+            }
+            x if (PciConfigRegister::Bar0 as usize..=PciConfigRegister::Bar5 as usize).contains(&x) => {
+                self.bar_value((x - PciConfigRegister::Bar0 as usize) / 4)
+            }
+            x if x == PciConfigRegister::Subsystem as usize => {
+                f.subsystem_vendor_id as u32 | (f.subsystem_id as u32) << 16
+            }
+            x if x == PciConfigRegister::InterruptLine as usize => {
+                self.interrupt_line as u32 | (f.interrupt_pin as u32) << 8
+            }
+            _ => 0,
+        }
+    }
+
+    /// Write an aligned configuration dword
+    ///
+    /// Only the command register, BARs and interrupt line are writable.
+    fn write_dword(&mut self, reg: usize, value: u32) {
+        match reg {
+            x if x == PciConfigRegister::CommandStatus as usize => {
+                self.command = value as u16 & PCI_COMMAND_MASK;
+            }
+            x if (PciConfigRegister::Bar0 as usize..=PciConfigRegister::Bar5 as usize).contains(&x) => {
+                let index = (x - PciConfigRegister::Bar0 as usize) / 4;
+                self.bars[index] = value;
+            }
+            x if x == PciConfigRegister::InterruptLine as usize => {
+                self.interrupt_line = value as u8;
+            }
+            _ => {}
+        }
+    }
+
+    /// Find the memory BAR decoding `addr`, returning it and the offset
+    fn decode_bar(&self, addr: PhysAddr) -> Option<(usize, u64)> {
+        if self.command & PCI_COMMAND_MEMORY == 0 {
+            return None;
+        }
+
+        (0..PCI_NUM_BARS).find_map(|index| {
+            let size = self.function.bar_sizes[index] as u64;
+            let base = self.bar_value(index) as u64;
+            (size != 0 && base != 0 && addr >= base && addr - base < size)
+                .then(|| (index, addr - base))
+        })
+    }
+}
+
+/// Mask of the low `size` bits of an access
+fn access_mask(size: u32) -> u32 {
+    match size {
+        8 => 0xFF,
+        16 => 0xFFFF,
+        _ => 0xFFFF_FFFF,
+    }
+}
+
+/// ECAM PCIe host bridge emulator
+pub struct PciHostBridge {
+    /// ECAM window base address
+    ecam_base: PhysAddr,
+    /// Number of buses covered by the ECAM window
+    bus_count: u16,
+    /// Synthesized functions, sorted by address
+    slots: Vec<PciSlot>,
+}
+
+impl PciHostBridge {
+    /// Create a host bridge decoding `bus_count` buses at `ecam_base`
+    pub fn new(ecam_base: PhysAddr, bus_count: u16) -> Self {
+        Self {
+            ecam_base,
+            bus_count: bus_count.clamp(1, 256),
+            slots: Vec::new(),
+        }
+    }
+
+    /// Get the ECAM base address
+    pub fn base_address(&self) -> PhysAddr {
+        self.ecam_base
+    }
+
+    /// Get the size of the ECAM window
+    pub fn ecam_size(&self) -> u64 {
+        self.bus_count as u64 * PCI_DEVICES_PER_BUS as u64
+            * PCI_FUNCTIONS_PER_DEVICE as u64 * PCI_CONFIG_SIZE
+    }
+
+    /// Add a function at `addr`
+    ///
+    /// Fails with `Error::InvalidArgument` if the address is outside the
+    /// bridge's buses or a BAR size is not a power of two of at least 16
+    /// bytes, and with `Error::ResourceBusy` if the slot is taken.
+    pub fn add_function(&mut self, addr: PciAddress, function: VirtualFunction) -> Result<()> {
+        if addr.bus as u16 >= self.bus_count
+            || addr.device >= PCI_DEVICES_PER_BUS
+            || addr.function >= PCI_FUNCTIONS_PER_DEVICE
+        {
+            return Err(Error::InvalidArgument);
+        }
+
+        if function.bar_sizes.iter().any(|&size| size != 0 && (size < 16 || !size.is_power_of_two())) {
+            return Err(Error::InvalidArgument);
+        }
+
+        match self.slots.binary_search_by_key(&addr, |slot| slot.addr) {
+            Ok(_) => Err(Error::ResourceBusy),
+            Err(index) => {
+                self.slots.insert(index, PciSlot::new(addr, function));
+                Ok(())
+            }
+        }
+    }
+
+    /// Find the function at `addr`
+    fn slot(&self, addr: PciAddress) -> Option<&PciSlot> {
+        let index = self.slots.binary_search_by_key(&addr, |slot| slot.addr).ok()?;
+        Some(&self.slots[index])
+    }
+
+    /// Check whether a device has functions other than function 0
+    fn is_multifunction(&self, addr: PciAddress) -> bool {
+        self.slots.iter().any(|slot| {
+            slot.addr.bus == addr.bus && slot.addr.device == addr.device && slot.addr.function != 0
+        })
+    }
+
+    /// Read `size` bits of configuration space
+    pub fn config_read(&self, addr: PciAddress, reg: usize, size: u32) -> u32 {
+        let dword = match self.slot(addr) {
+            Some(slot) => slot.read_dword(reg & !3, self.is_multifunction(addr)),
+            None => return access_mask(size),
+        };
+        (dword >> ((reg & 3) * 8)) & access_mask(size)
+    }
+
+    /// Write `size` bits of configuration space
+    ///
+    /// Sub-dword writes are merged into the current register value.
+    /// Writes to empty slots are ignored.
+    pub fn config_write(&mut self, addr: PciAddress, reg: usize, value: u32, size: u32) {
+        let multifunction = self.is_multifunction(addr);
+        let index = match self.slots.binary_search_by_key(&addr, |slot| slot.addr) {
+            Ok(index) => index,
+            Err(_) => return,
+        };
+        let slot = &mut self.slots[index];
+
+        let aligned = reg & !3;
+        let shift = (reg & 3) * 8;
+        let mask = access_mask(size) << shift;
+        let current = match size {
+            32 => 0,
+            _ => slot.read_dword(aligned, multifunction),
+        };
+        slot.write_dword(aligned, (current & !mask) | ((value << shift) & mask));
+    }
+
+    /// Read from a guest physical address decoded by a function's BAR
+    ///
+    /// Returns `None` if no enabled BAR decodes the address.
+    pub fn bar_read(&self, addr: PhysAddr, size: u32) -> Option<core::result::Result<u64, EmulatorError>> {
+        self.slots.iter().find_map(|slot| {
+            let (_, offset) = slot.decode_bar(addr)?;
+            let backend = slot.function.backend.as_ref()?;
+            Some(backend.read(offset, size))
+        })
+    }
+
+    /// Write to a guest physical address decoded by a function's BAR
+    ///
+    /// Returns `None` if no enabled BAR decodes the address.
+    pub fn bar_write(&mut self, addr: PhysAddr, value: u64,
+                     size: u32) -> Option<core::result::Result<(), EmulatorError>> {
+        self.slots.iter_mut().find_map(|slot| {
+            let (_, offset) = slot.decode_bar(addr)?;
+            let backend = slot.function.backend.as_mut()?;
+            Some(backend.write(offset, value, size))
+        })
+    }
+}
+
+impl Emulator for PciHostBridge {
+    fn name(&self) -> &str {
+        "PCIe-ECAM"
+    }
+
+    fn read(&self, offset: u64, size: u32) -> core::result::Result<u64, EmulatorError> {
+        if size != 8 && size != 16 && size != 32 {
+            return Err(EmulatorError::InvalidAccess);
+        }
+        if offset >= self.ecam_size() || offset % (size as u64 / 8) != 0 {
+            return Err(EmulatorError::InvalidAccess);
+        }
+
+        let (addr, reg) = PciAddress::from_ecam_offset(offset);
+        Ok(self.config_read(addr, reg, size) as u64)
+    }
+
+    fn write(&mut self, offset: u64, value: u64, size: u32) -> core::result::Result<(), EmulatorError> {
+        if size != 8 && size != 16 && size != 32 {
+            return Err(EmulatorError::InvalidAccess);
+        }
+        if offset >= self.ecam_size() || offset % (size as u64 / 8) != 0 {
+            return Err(EmulatorError::InvalidAccess);
+        }
+
+        let (addr, reg) = PciAddress::from_ecam_offset(offset);
+        self.config_write(addr, reg, value as u32, size);
+        Ok(())
+    }
+
+    fn reset(&mut self) -> core::result::Result<(), EmulatorError> {
+        for slot in &mut self.slots {
+            slot.command = 0;
+            slot.bars = [0; PCI_NUM_BARS];
+            slot.interrupt_line = 0xFF;
+            if let Some(backend) = slot.function.backend.as_mut() {
+                backend.reset()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn decodes(&self, addr: u64) -> bool {
+        self.slots.iter().any(|slot| slot.function.backend.is_some() && slot.decode_bar(addr).is_some())
+    }
+
+    fn read_decoded(&self, addr: u64, size: u32) -> core::result::Result<u64, EmulatorError> {
+        self.bar_read(addr, size).unwrap_or(Err(EmulatorError::InvalidAccess))
+    }
+
+    fn write_decoded(&mut self, addr: u64, value: u64, size: u32) -> core::result::Result<(), EmulatorError> {
+        self.bar_write(addr, value, size).unwrap_or(Err(EmulatorError::InvalidAccess))
+    }
+}
+
+/// Give a VM a generic ECAM bridge at the QEMU virt location, 16 buses
+pub fn attach(vm_id: VmId) -> Result<()> {
+    let mut bridge = PciHostBridge::new(0x3f00_0000, 16);
+
+    // Host bridge function at 00:00.0 (Red Hat generic PCIe host bridge)
+    bridge.add_function(PciAddress::new(0, 0, 0), VirtualFunction {
+        vendor_id: 0x1b36,
+        device_id: 0x0008,
+        class_code: PCI_CLASS_HOST_BRIDGE,
+        revision: 0,
+        subsystem_vendor_id: 0x1af4,
+        subsystem_id: 0x1100,
+        bar_sizes: [0; PCI_NUM_BARS],
+        interrupt_pin: 0,
+        backend: None,
+    })?;
+
+    let (base, size) = (bridge.base_address(), bridge.ecam_size());
+    crate::emulator::register_mmio_emulator(vm_id, "pci-host-ecam", DeviceClass::PciHost, base, size,
+                                            Box::new(bridge))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Offset of a register in the ECAM window
+    fn ecam(device: u8, function: u8, reg: u64) -> u64 {
+        (device as u64) << 15 | (function as u64) << 12 | reg
+    }
+
+    /// Backend returning the accessed offset
+    struct EchoBar;
+
+    impl Emulator for EchoBar {
+        fn name(&self) -> &str {
+            "echo"
+        }
+
+        fn read(&self, offset: u64, _size: u32) -> core::result::Result<u64, EmulatorError> {
+            Ok(offset)
+        }
+
+        fn write(&mut self, _offset: u64, _value: u64, _size: u32) -> core::result::Result<(), EmulatorError> {
+            Ok(())
+        }
+
+        fn reset(&mut self) -> core::result::Result<(), EmulatorError> {
+            Ok(())
+        }
+    }
+
+    /// Bridge with a virtio-net function at 00:01.0
+    fn bridge_with_net() -> PciHostBridge {
+        let mut bridge = PciHostBridge::new(0x3f00_0000, 1);
+        bridge.add_function(PciAddress::new(0, 1, 0), VirtualFunction {
+            vendor_id: 0x1af4,
+            device_id: 0x1041,
+            class_code: 0x02_00_00,
+            revision: 1,
+            subsystem_vendor_id: 0x1af4,
+            subsystem_id: 0x1100,
+            bar_sizes: [0x4000, 0, 0x1000, 0, 0, 0],
+            interrupt_pin: 1,
+            backend: Some(Box::new(EchoBar)),
+        }).unwrap();
+        bridge
+    }
+
+    #[test]
+    fn test_config_read_returns_ids() {
+        let bridge = bridge_with_net();
+
+        assert_eq!(bridge.read(ecam(1, 0, 0x00), 32).unwrap(), 0x1041_1af4);
+        assert_eq!(bridge.read(ecam(1, 0, 0x02), 16).unwrap(), 0x1041);
+        assert_eq!(bridge.read(ecam(1, 0, 0x08), 32).unwrap(), 0x0200_0001);
+        assert_eq!(bridge.read(ecam(1, 0, 0x3D), 8).unwrap(), 1);
+
+        // Empty slots read as all ones
+        assert_eq!(bridge.read(ecam(2, 0, 0x00), 32).unwrap(), 0xFFFF_FFFF);
+        assert_eq!(bridge.read(ecam(1, 1, 0x00), 16).unwrap(), 0xFFFF);
+
+        assert!(bridge.read(ecam(1, 0, 0x02), 32).is_err());
+        assert!(bridge.read(bridge.ecam_size(), 32).is_err());
+    }
+
+    #[test]
+    fn test_bar_sizing_returns_mask() {
+        let mut bridge = bridge_with_net();
+
+        bridge.write(ecam(1, 0, 0x10), 0xFFFF_FFFF, 32).unwrap();
+        assert_eq!(bridge.read(ecam(1, 0, 0x10), 32).unwrap(), 0xFFFF_C000);
+        bridge.write(ecam(1, 0, 0x18), 0xFFFF_FFFF, 32).unwrap();
+        assert_eq!(bridge.read(ecam(1, 0, 0x18), 32).unwrap(), 0xFFFF_F000);
+
+        // Unimplemented BARs stay zero
+        bridge.write(ecam(1, 0, 0x14), 0xFFFF_FFFF, 32).unwrap();
+        assert_eq!(bridge.read(ecam(1, 0, 0x14), 32).unwrap(), 0);
+
+        // Assignment keeps only the size-aligned bits
+        bridge.write(ecam(1, 0, 0x10), 0x1000_0123, 32).unwrap();
+        assert_eq!(bridge.read(ecam(1, 0, 0x10), 32).unwrap(), 0x1000_0000);
+    }
+
+    #[test]
+    fn test_bar_access_routed_to_backend() {
+        let mut bridge = bridge_with_net();
+        bridge.write(ecam(1, 0, 0x10), 0x1000_0000, 32).unwrap();
+
+        // Memory decode disabled
+        assert!(bridge.bar_read(0x1000_0010, 32).is_none());
+
+        bridge.write(ecam(1, 0, 0x04), PCI_COMMAND_MEMORY as u64, 16).unwrap();
+        assert_eq!(bridge.bar_read(0x1000_0010, 32), Some(Ok(0x10)));
+        assert_eq!(bridge.bar_read(0x1000_3FFC, 32), Some(Ok(0x3FFC)));
+        assert!(bridge.bar_read(0x1000_4000, 32).is_none());
+        assert_eq!(bridge.bar_write(0x1000_0000, 1, 32), Some(Ok(())));
+
+        assert_eq!(bridge.add_function(PciAddress::new(0, 1, 0), VirtualFunction {
+            vendor_id: 0, device_id: 0, class_code: 0, revision: 0,
+            subsystem_vendor_id: 0, subsystem_id: 0, bar_sizes: [0; 6],
+            interrupt_pin: 0, backend: None,
+        }), Err(Error::ResourceBusy));
+    }
+
+    #[test]
+    fn test_registry_routes_enabled_bar_to_bridge() {
+        use crate::emulator::EmulatorRegistry;
+
+        let mut registry = EmulatorRegistry::new();
+        let bridge = bridge_with_net();
+        let (base, size) = (bridge.base_address(), bridge.ecam_size());
+        registry.register_mmio(1, "pci-host-ecam", DeviceClass::PciHost, base, size, Box::new(bridge)).unwrap();
+
+        // Program BAR0 and enable memory decode through ECAM
+        registry.write(1, base + ecam(1, 0, 0x10), 0x1000_0000, 32).unwrap().unwrap();
+        assert!(!registry.claims(1, 0x1000_0010));
+        registry.write(1, base + ecam(1, 0, 0x04), PCI_COMMAND_MEMORY as u64, 16).unwrap().unwrap();
+        assert!(registry.claims(1, 0x1000_0010));
+        assert_eq!(registry.read(1, 0x1000_0010, 32), Some(Ok(0x10)));
+        assert!(registry.read(2, 0x1000_0010, 32).is_none());
+
+        // Moving the BAR moves the window
+        registry.write(1, base + ecam(1, 0, 0x10), 0x2000_0000, 32).unwrap().unwrap();
+        assert!(!registry.claims(1, 0x1000_0010));
+        assert_eq!(registry.read(1, 0x2000_0004, 32), Some(Ok(0x4)));
+    }
+}
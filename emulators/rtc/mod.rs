@@ -4,11 +4,13 @@
 //! supporting RTC chips like PL031, MC146818, etc.
 
 use crate::{Result, Error};
-use crate::emulator::{Emulator, Error as EmulatorError};
+use crate::emulator::{DeviceClass, Emulator, Error as EmulatorError};
+use crate::core::vmm::VmId;
 use crate::core::mm::{VirtAddr, PhysAddr};
 use crate::arch::common::MmioAccess;
 use crate::core::sync::SpinLock;
 use core::sync::atomic::{AtomicU64, Ordering};
+use alloc::boxed::Box;
 
 /// PL031 RTC registers
 #[allow(dead_code)]
@@ -116,6 +118,16 @@ impl RtcTime {
     }
 }
 
+/// Host time in seconds, the time base guest wall clocks are offset from
+fn host_time() -> u64 {
+    crate::utils::time::timestamp_ns() / crate::utils::time::NSEC_PER_SEC
+}
+
+/// Guest wall-clock time for a host time and a VM's offset
+fn wallclock(host_time: u64, offset: i64) -> u64 {
+    host_time.saturating_add_signed(offset)
+}
+
 /// Check if a year is a leap year
 fn is_leap_year(year: u16) -> bool {
     (year % 4 == 0 && year % 100 != 0) || (year % 400 == 0)
@@ -148,13 +160,15 @@ pub struct Pl031Rtc {
     mmio: MmioAccess,
     /// Reference time when RTC was initialized
     ref_time: u64,
+    /// Guest wall-clock offset from host time, in seconds
+    wallclock_offset: i64,
 }
 
 impl Pl031Rtc {
     /// Create a new PL031 RTC emulator
     pub fn new(base_addr: PhysAddr) -> Self {
         // Get current time as reference
-        let ref_time = crate::utils::get_timestamp();
+        let ref_time = host_time();
 
         let state = Pl031State {
             current_time: AtomicU64::new(ref_time),
@@ -170,6 +184,7 @@ impl Pl031Rtc {
             state: SpinLock::new(state),
             mmio: MmioAccess,
             ref_time,
+            wallclock_offset: 0,
         }
     }
 
@@ -191,15 +206,17 @@ impl Pl031Rtc {
         state.current_time.store(time.as_unix_timestamp(), Ordering::Relaxed);
     }
 
+    /// Guest time at host time `host_time`
+    pub fn time_at(&self, host_time: u64) -> u64 {
+        wallclock(host_time, self.wallclock_offset)
+    }
+
     /// Update RTC (called periodically)
     pub fn update(&self) {
         let state = self.state.lock();
         if state.enabled {
-            let current = crate::utils::get_timestamp();
-            state.current_time.store(
-                self.ref_time + (current - self.ref_time),
-                Ordering::Relaxed
-            );
+            let current = host_time();
+            state.current_time.store(self.time_at(current), Ordering::Relaxed);
 
             // Check for match
             let current_value = (state.current_time.load(Ordering::Relaxed) & 0xFFFFFFFF) as u32;
@@ -262,7 +279,7 @@ impl Emulator for Pl031Rtc {
             x if x == Pl031Register::LoadRegister as usize => {
                 // Load register - set new time
                 state.current_time.store(byte_value as u64, Ordering::Relaxed);
-                state.ref_time = crate::utils::get_timestamp();
+                state.ref_time = host_time();
             }
             x if x == Pl031Register::MatchRegister as usize => {
                 state.match_value = byte_value;
@@ -291,16 +308,26 @@ impl Emulator for Pl031Rtc {
         let mut state = self.state.lock();
 
         // Reset to default state
-        state.current_time.store(crate::utils::get_timestamp(), Ordering::Relaxed);
+        state.current_time.store(self.time_at(host_time()), Ordering::Relaxed);
         state.match_value = 0;
         state.control = 0;
         state.int_status = 0;
         state.int_mask = 0;
         state.enabled = false;
-        self.ref_time = crate::utils::get_timestamp();
+        self.ref_time = host_time();
 
         Ok(())
     }
+
+    fn set_wallclock_offset(&mut self, seconds: i64) {
+        // Shift the running time by the change, keeping any time the guest loaded
+        let delta = seconds.wrapping_sub(self.wallclock_offset);
+        let state = self.state.lock();
+        let current = state.current_time.load(Ordering::Relaxed);
+        state.current_time.store(current.saturating_add_signed(delta), Ordering::Relaxed);
+        drop(state);
+        self.wallclock_offset = seconds;
+    }
 }
 
 /// MC146818-compatible RTC emulator
@@ -311,6 +338,8 @@ pub struct Mc146818Rtc {
     state: SpinLock<Mc146818State>,
     /// MMIO access interface
     mmio: MmioAccess,
+    /// Guest wall-clock offset from host time, in seconds
+    wallclock_offset: i64,
 }
 
 /// MC146818 RTC state
@@ -332,16 +361,10 @@ impl Mc146818Rtc {
     /// Create a new MC146818 RTC emulator
     pub fn new(base_addr: PhysAddr) -> Self {
         let mut regs = [0u8; 64];
-        let current_time = RtcTime::from_unix_timestamp(crate::utils::get_timestamp());
+        let current_time = RtcTime::from_unix_timestamp(host_time());
 
         // Initialize time registers (BCD format)
-        regs[0] = to_bcd(current_time.seconds);     // Seconds
-        regs[1] = to_bcd(current_time.minutes);     // Minutes
-        regs[2] = to_bcd(current_time.hours);       // Hours
-        regs[3] = to_bcd(current_time.weekday);     // Day of week
-        regs[4] = to_bcd(current_time.day);         // Day of month
-        regs[5] = to_bcd(current_time.month);       // Month
-        regs[6] = to_bcd((current_time.year % 100) as u8); // Year (2 digits)
+        load_time(&mut regs, &current_time);
 
         // Initialize status registers
         regs[0x0A] = 0x20; // Update in progress
@@ -357,9 +380,16 @@ impl Mc146818Rtc {
                 dst_enabled: false,
             }),
             mmio: MmioAccess,
+            wallclock_offset: 0,
         }
     }
 
+    /// Load the time registers with the guest time at host time `host_time`
+    pub fn latch_time(&self, host_time: u64) {
+        let time = RtcTime::from_unix_timestamp(wallclock(host_time, self.wallclock_offset));
+        load_time(&mut self.state.lock().regs, &time);
+    }
+
     /// Convert binary to BCD
     fn to_bcd(value: u8) -> u8 {
         ((value / 10) << 4) | (value % 10)
@@ -450,15 +480,9 @@ impl Emulator for Mc146818Rtc {
         let mut state = self.state.lock();
 
         // Reset registers to default
-        let current_time = RtcTime::from_unix_timestamp(crate::utils::get_timestamp());
+        let current_time = RtcTime::from_unix_timestamp(wallclock(host_time(), self.wallclock_offset));
 
-        state.regs[0] = to_bcd(current_time.seconds);
-        state.regs[1] = to_bcd(current_time.minutes);
-        state.regs[2] = to_bcd(current_time.hours);
-        state.regs[3] = to_bcd(current_time.weekday);
-        state.regs[4] = to_bcd(current_time.day);
-        state.regs[5] = to_bcd(current_time.month);
-        state.regs[6] = to_bcd((current_time.year % 100) as u8);
+        load_time(&mut state.regs, &current_time);
 
         state.regs[0x0A] = 0x20;
         state.regs[0x0B] = 0x82;
@@ -472,6 +496,22 @@ impl Emulator for Mc146818Rtc {
 
         Ok(())
     }
+
+    fn set_wallclock_offset(&mut self, seconds: i64) {
+        self.wallclock_offset = seconds;
+        self.latch_time(host_time());
+    }
+}
+
+/// Load the MC146818 time registers (BCD format)
+fn load_time(regs: &mut [u8; 64], time: &RtcTime) {
+    regs[0] = to_bcd(time.seconds);             // Seconds
+    regs[1] = to_bcd(time.minutes);             // Minutes
+    regs[2] = to_bcd(time.hours);               // Hours
+    regs[3] = to_bcd(time.weekday);             // Day of week
+    regs[4] = to_bcd(time.day);                 // Day of month
+    regs[5] = to_bcd(time.month);               // Month
+    regs[6] = to_bcd((time.year % 100) as u8);  // Year (2 digits)
 }
 
 /// Convert binary to BCD
@@ -479,17 +519,45 @@ fn to_bcd(value: u8) -> u8 {
     ((value / 10) << 4) | (value % 10)
 }
 
-/// Initialize RTC emulators
-pub fn init() -> Result<(), crate::Error> {
-    crate::info!("Initializing RTC emulators");
+/// Size of the PL031 register window
+pub const PL031_SIZE: u64 = 0x1000;
 
-    // Register PL031 RTC
+/// Give a VM a PL031 RTC at the QEMU virt location
+pub fn attach_pl031(vm_id: VmId) -> Result<(), crate::Error> {
     let pl031 = Pl031Rtc::new(0x9010000);
-    crate::emulator::register_emulator("rtc-pl031", &pl031)?;
+    let base = pl031.base_address();
+    crate::emulator::register_mmio_emulator(vm_id, "rtc-pl031", DeviceClass::Rtc, base, PL031_SIZE,
+                                            Box::new(pl031))
+}
 
-    // Register MC146818 RTC
+/// Give a VM an MC146818 RTC, reached through I/O ports rather than MMIO
+pub fn attach_mc146818(vm_id: VmId) -> Result<(), crate::Error> {
     let mc146818 = Mc146818Rtc::new(0x70);
-    crate::emulator::register_emulator("rtc-mc146818", &mc146818)?;
+    crate::emulator::register_emulator(vm_id, "rtc-mc146818", DeviceClass::Rtc, Box::new(mc146818))
+}
 
-    Ok(())
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2024-01-15 12:00:00 UTC
+    const HOST_TIME: u64 = 1_705_320_000;
+
+    #[test]
+    fn test_wallclock_offset_is_per_vm() {
+        let vm1 = Pl031Rtc::new(0x9010000);
+        let mut vm2 = Pl031Rtc::new(0x9010000);
+        vm2.set_wallclock_offset(-86_400);
+
+        // Same host time, a day apart in the guests
+        let time1 = RtcTime::from_unix_timestamp(vm1.time_at(HOST_TIME));
+        let time2 = RtcTime::from_unix_timestamp(vm2.time_at(HOST_TIME));
+        assert_eq!((time1.day, time1.hours, time1.minutes), (15, 12, 0));
+        assert_eq!((time2.day, time2.hours, time2.minutes), (14, 12, 0));
+
+        // Changing the offset moves the running clock by the difference
+        vm2.set_wallclock_offset(3_600);
+        assert_eq!(vm2.time_at(HOST_TIME), HOST_TIME + 3_600);
+        assert_eq!(wallclock(10, -20), 0);
+    }
+}
@@ -4,10 +4,14 @@
 //! supporting common UART chips like PL011, 16550, etc.
 
 use crate::{Result, Error};
-use crate::emulator::{Emulator, Error as EmulatorError};
+use crate::emulator::{DeviceClass, Emulator, Error as EmulatorError};
+use crate::core::vmm::VmId;
 use crate::core::mm::{VirtAddr, PhysAddr};
 use crate::arch::common::MmioAccess;
 use crate::core::sync::SpinLock;
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
 use core::sync::atomic::{AtomicUsize, Ordering};
 
 /// PL011 UART registers
@@ -28,6 +32,82 @@ enum Pl011Register {
     TestControl = 0x2C,
 }
 
+/// PL011 receive interrupt
+const PL011_INT_RX: u32 = 1 << 4;
+/// PL011 transmit interrupt
+const PL011_INT_TX: u32 = 1 << 5;
+/// PL011 receive timeout interrupt
+const PL011_INT_RT: u32 = 1 << 6;
+
+/// UARTFR.BUSY: TX FIFO holds data still being shifted out
+const PL011_FR_BUSY: u32 = 1 << 3;
+/// UARTFR.TXFF: TX FIFO is full, further writes are not accepted
+const PL011_FR_TXFF: u32 = 1 << 5;
+/// UARTFR.TXFE: TX FIFO is empty
+const PL011_FR_TXFE: u32 = 1 << 7;
+
+/// What a data register write does while the TX FIFO is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pl011TxPolicy {
+    /// Discard the byte, as the hardware does; guests are expected to
+    /// poll UARTFR.TXFF and back off
+    Drop,
+    /// Stall the writer until the oldest byte has gone out, then queue
+    /// the new one, so output from guests that ignore TXFF is not lost
+    Block,
+}
+
+/// Receive timeout in bit periods, as on real hardware
+const PL011_RX_TIMEOUT_BITS: u64 = 32;
+/// Bit period assumed until a baud rate is programmed (115200 baud)
+const PL011_DEFAULT_BIT_NS: u64 = 8_681;
+/// Default UART reference clock (QEMU virt uses 24MHz)
+const PL011_DEFAULT_CLOCK_HZ: u64 = 24_000_000;
+
+crate::bitfield! {
+    /// PL011 control register (UARTCR)
+    pub struct Pl011Cr(u32) {
+        /// UART enable
+        uarten / set_uarten: [0],
+        /// Loopback enable
+        lbe / set_lbe: [7],
+        /// Transmit enable
+        txe / set_txe: [8],
+        /// Receive enable
+        rxe / set_rxe: [9],
+        /// Data transmit ready
+        dtr / set_dtr: [10],
+    }
+}
+
+crate::bitfield! {
+    /// PL011 line control register (UARTLCR_H)
+    pub struct Pl011Lcr(u32) {
+        /// Parity enable
+        pen / set_pen: [1],
+        /// Two stop bits
+        stp2 / set_stp2: [3],
+        /// FIFO enable
+        fen / set_fen: [4],
+        /// Word length: 5 + WLEN data bits
+        wlen / set_wlen: [6:5],
+    }
+}
+
+/// Convert an IFLS level selector to a FIFO entry count
+///
+/// Selectors 0-4 pick 1/8, 1/4, 1/2, 3/4 and 7/8 of the FIFO; reserved
+/// encodings behave like 7/8.
+fn pl011_fifo_level(sel: u32, depth: usize) -> usize {
+    match sel & 0x7 {
+        0 => depth / 8,
+        1 => depth / 4,
+        2 => depth / 2,
+        3 => depth * 3 / 4,
+        _ => depth * 7 / 8,
+    }
+}
+
 /// PL011 UART state
 #[derive(Debug, Clone)]
 pub struct Pl011State {
@@ -38,9 +118,9 @@ pub struct Pl011State {
     /// Baud rate divisor
     baud_div: u32,
     /// Line control register
-    line_ctrl: u32,
+    line_ctrl: Pl011Lcr,
     /// Control register
-    ctrl: u32,
+    ctrl: Pl011Cr,
     /// FIFO level select
     ifls: u32,
     /// Interrupt mask
@@ -50,13 +130,219 @@ pub struct Pl011State {
     /// Masked interrupt status
     masked_int: u32,
     /// Transmit FIFO
-    tx_fifo: SpinLock<Vec<u8>>,
+    tx_fifo: VecDeque<u8>,
     /// Receive FIFO
-    rx_fifo: SpinLock<Vec<u8>>,
+    rx_fifo: VecDeque<u8>,
     /// FIFO depth
     fifo_depth: usize,
     /// Character received from host
     host_char: Option<u8>,
+    /// Time (ns) of the last RX FIFO activity
+    rx_last_activity_ns: u64,
+    /// UART reference clock in Hz
+    clock_hz: u64,
+    /// Time (ns) at which the byte at the head of the TX FIFO finishes
+    tx_done_ns: u64,
+    /// Behaviour of writes to a full TX FIFO
+    tx_policy: Pl011TxPolicy,
+    /// Bytes discarded because the TX FIFO was full
+    tx_dropped: u64,
+}
+
+impl Pl011State {
+    /// RX FIFO fill level that raises the RX interrupt
+    fn rx_level(&self) -> usize {
+        pl011_fifo_level(self.ifls >> 3, self.fifo_depth).max(1)
+    }
+
+    /// TX FIFO fill level at or below which the TX interrupt is raised
+    fn tx_level(&self) -> usize {
+        pl011_fifo_level(self.ifls, self.fifo_depth)
+    }
+
+    /// Bits per frame: start bit, data bits, optional parity and stop bits
+    fn frame_bits(&self) -> u64 {
+        let data_bits = 5 + self.line_ctrl.wlen() as u64;
+        let parity_bits = self.line_ctrl.pen() as u64;
+        let stop_bits = if self.line_ctrl.stp2() { 2 } else { 1 };
+        1 + data_bits + parity_bits + stop_bits
+    }
+
+    /// Time to shift out `bits` bits at the programmed baud rate
+    ///
+    /// Baud rate is `clock / (16 * divisor)`. Returns `None` until a
+    /// divisor has been programmed.
+    fn bits_ns(&self, bits: u64) -> Option<u64> {
+        if self.baud_div == 0 || self.clock_hz == 0 {
+            return None;
+        }
+        let ns = bits as u128 * 16 * self.baud_div as u128 * crate::utils::time::NSEC_PER_SEC as u128
+            / self.clock_hz as u128;
+        Some(ns as u64)
+    }
+
+    /// Time to transmit one byte, if a baud rate is programmed
+    fn byte_period_ns(&self) -> Option<u64> {
+        self.bits_ns(self.frame_bits())
+    }
+
+    /// Receive timeout period in nanoseconds
+    fn rx_timeout_ns(&self) -> u64 {
+        self.bits_ns(PL011_RX_TIMEOUT_BITS)
+            .unwrap_or(PL011_RX_TIMEOUT_BITS * PL011_DEFAULT_BIT_NS)
+    }
+
+    /// Recompute the masked interrupt status
+    fn update_masked(&mut self) {
+        self.masked_int = self.raw_int & !self.int_mask;
+    }
+
+    /// Update FIFO status flags and level interrupts after a FIFO change
+    ///
+    /// With `fifo_depth` bytes queued for transmit, UARTFR has TXFF and
+    /// BUSY set and TXFE clear; TXFF clears as soon as one byte has gone
+    /// out. RX asserts when the RX FIFO reaches its level and deasserts
+    /// when it drains below it; TX asserts when the TX FIFO drains to its
+    /// level and deasserts when it fills above it.
+    fn update_fifos(&mut self) {
+        self.status &= !0xF8;
+        if !self.tx_fifo.is_empty() {
+            self.status |= PL011_FR_BUSY;
+        }
+        if self.rx_fifo.is_empty() {
+            self.status |= 0x10; // RX FIFO empty
+        }
+        if self.rx_fifo.len() >= self.fifo_depth {
+            self.status |= 0x40; // RX FIFO full
+        }
+        if self.tx_fifo.len() >= self.fifo_depth {
+            self.status |= PL011_FR_TXFF;
+        }
+        if self.tx_fifo.is_empty() {
+            self.status |= PL011_FR_TXFE;
+        }
+
+        if self.rx_fifo.len() >= self.rx_level() {
+            self.raw_int |= PL011_INT_RX;
+        } else {
+            self.raw_int &= !PL011_INT_RX;
+        }
+
+        if self.tx_fifo.len() <= self.tx_level() {
+            self.raw_int |= PL011_INT_TX;
+        } else {
+            self.raw_int &= !PL011_INT_TX;
+        }
+
+        if self.rx_fifo.is_empty() {
+            self.raw_int &= !PL011_INT_RT;
+        }
+
+        self.update_masked();
+    }
+
+    /// Push a received character into the RX FIFO
+    fn receive(&mut self, c: u8, now_ns: u64) -> bool {
+        if !self.ctrl.uarten() || self.rx_fifo.len() >= self.fifo_depth {
+            return false;
+        }
+
+        self.rx_fifo.push_back(c);
+        self.rx_last_activity_ns = now_ns;
+        self.raw_int &= !PL011_INT_RT;
+        self.update_fifos();
+        true
+    }
+
+    /// Pop a character from the RX FIFO
+    fn read_rx(&mut self, now_ns: u64) -> Option<u8> {
+        let c = self.rx_fifo.pop_front()?;
+        self.rx_last_activity_ns = now_ns;
+        self.raw_int &= !PL011_INT_RT;
+        self.update_fifos();
+        Some(c)
+    }
+
+    /// Raise the receive timeout interrupt if RX data has sat idle
+    fn check_rx_timeout(&mut self, now_ns: u64) {
+        if !self.rx_fifo.is_empty()
+            && now_ns.saturating_sub(self.rx_last_activity_ns) >= self.rx_timeout_ns()
+        {
+            self.raw_int |= PL011_INT_RT;
+            self.update_masked();
+        }
+    }
+
+    /// Queue a character for transmission
+    ///
+    /// Without a programmed baud rate the character goes out at once;
+    /// otherwise it is paced by `service_tx`. A full FIFO is handled per
+    /// `tx_policy`; returns false if the character was dropped.
+    fn write_tx(&mut self, c: u8, now_ns: u64) -> bool {
+        if self.tx_fifo.len() >= self.fifo_depth {
+            match self.tx_policy {
+                Pl011TxPolicy::Drop => {
+                    self.tx_dropped += 1;
+                    return false;
+                }
+                Pl011TxPolicy::Block => self.stall_tx(),
+            }
+        }
+
+        let was_idle = self.tx_fifo.is_empty();
+        self.tx_fifo.push_back(c);
+
+        match self.byte_period_ns() {
+            Some(period) => {
+                if was_idle {
+                    self.tx_done_ns = now_ns + period;
+                }
+                self.update_fifos();
+            }
+            None => self.drain_tx(),
+        }
+        true
+    }
+
+    /// Send the byte at the head of the TX FIFO now, as if the writer had
+    /// waited for it to finish
+    fn stall_tx(&mut self) {
+        if let Some(c) = self.tx_fifo.pop_front() {
+            crate::print!("{}", c as char);
+        }
+        if let Some(period) = self.byte_period_ns() {
+            self.tx_done_ns += period;
+        }
+    }
+
+    /// Retire every TX byte whose transmission has completed by `now_ns`
+    fn service_tx(&mut self, now_ns: u64) {
+        let period = match self.byte_period_ns() {
+            Some(period) => period,
+            None => return self.drain_tx(),
+        };
+
+        let mut sent = false;
+        while !self.tx_fifo.is_empty() && now_ns >= self.tx_done_ns {
+            if let Some(c) = self.tx_fifo.pop_front() {
+                crate::print!("{}", c as char);
+            }
+            self.tx_done_ns += period;
+            sent = true;
+        }
+
+        if sent {
+            self.update_fifos();
+        }
+    }
+
+    /// Transmit everything in the TX FIFO
+    fn drain_tx(&mut self) {
+        while let Some(c) = self.tx_fifo.pop_front() {
+            crate::print!("{}", c as char);
+        }
+        self.update_fifos();
+    }
 }
 
 /// PL011 UART emulator
@@ -70,22 +356,32 @@ pub struct Pl011Uart {
 }
 
 impl Pl011Uart {
-    /// Create a new PL011 UART emulator
+    /// Create a new PL011 UART emulator that drops writes to a full TX FIFO
     pub fn new(base_addr: PhysAddr) -> Self {
+        Self::with_tx_policy(base_addr, Pl011TxPolicy::Drop)
+    }
+
+    /// Create a new PL011 UART emulator with the given full-FIFO policy
+    pub fn with_tx_policy(base_addr: PhysAddr, tx_policy: Pl011TxPolicy) -> Self {
         let state = Pl011State {
             data: 0,
             status: 0x90, // TX empty, RX empty
             baud_div: 0,
-            line_ctrl: 0,
-            ctrl: 0,
-            ifls: 0,
+            line_ctrl: Pl011Lcr::default(),
+            ctrl: Pl011Cr::default(),
+            ifls: 0x12, // 1/2 for both FIFOs
             int_mask: 0,
             raw_int: 0,
             masked_int: 0,
-            tx_fifo: SpinLock::new(Vec::new()),
-            rx_fifo: SpinLock::new(Vec::new()),
+            tx_fifo: VecDeque::new(),
+            rx_fifo: VecDeque::new(),
             fifo_depth: 16,
             host_char: None,
+            rx_last_activity_ns: 0,
+            clock_hz: PL011_DEFAULT_CLOCK_HZ,
+            tx_done_ns: 0,
+            tx_policy,
+            tx_dropped: 0,
         };
 
         Self {
@@ -102,7 +398,7 @@ impl Pl011Uart {
 
     /// Read a character from host (for testing)
     pub fn read_host_char(&self) -> Option<u8> {
-        let mut state = self.state.lock();
+        let state = self.state.lock();
         state.host_char
     }
 
@@ -112,15 +408,7 @@ impl Pl011Uart {
         crate::print!("{}", c as char);
 
         // Add to RX FIFO if UART is enabled for receive
-        let mut state = self.state.lock();
-        if state.ctrl & 0x01 != 0 { // UARTEN
-            let mut rx_fifo = state.rx_fifo.lock();
-            if rx_fifo.len() < state.fifo_depth {
-                rx_fifo.push(c);
-                state.raw_int |= 0x10; // RX interrupt
-                state.masked_int = state.raw_int & !state.int_mask;
-            }
-        }
+        self.state.lock().receive(c, crate::utils::time::timestamp_ns());
     }
 
     /// Write a string to host
@@ -129,6 +417,26 @@ impl Pl011Uart {
             self.write_host_char(c);
         }
     }
+
+    /// Set the UART reference clock used to derive the baud rate
+    pub fn set_clock_hz(&self, clock_hz: u64) {
+        self.state.lock().clock_hz = clock_hz;
+    }
+
+    /// Get the time to transmit one byte at the programmed baud rate
+    pub fn byte_period_ns(&self) -> Option<u64> {
+        self.state.lock().byte_period_ns()
+    }
+
+    /// Get the masked interrupt status
+    pub fn pending_interrupts(&self) -> u32 {
+        self.state.lock().masked_int
+    }
+
+    /// Number of bytes dropped because the TX FIFO was full
+    pub fn tx_dropped(&self) -> u64 {
+        self.state.lock().tx_dropped
+    }
 }
 
 impl Emulator for Pl011Uart {
@@ -147,34 +455,15 @@ impl Emulator for Pl011Uart {
         let value = match addr {
             x if x == Pl011Register::Data as usize => {
                 // Read from RX FIFO
-                let mut rx_fifo = state.rx_fifo.lock();
-                if let Some(c) = rx_fifo.pop_front() {
-                    // Update FIFO status
-                    state.status &= !0x10; // Clear RX FIFO full
-                    if rx_fifo.is_empty() {
-                        state.status |= 0x10; // RX FIFO empty
-                    }
-                    c as u64
-                } else {
-                    state.status & 0x10 // Return empty flag if no data
-                }
-            }
-            x if x == Pl011Register::Status as usize => {
-                let mut status = state.status;
-
-                // Update TX FIFO status
-                let tx_fifo = state.tx_fifo.lock();
-                if tx_fifo.is_empty() {
-                    status |= 0x80; // TX FIFO empty
-                } else {
-                    status &= !0x80;
+                match state.read_rx(crate::utils::time::timestamp_ns()) {
+                    Some(c) => c as u64,
+                    None => 0,
                 }
-
-                status as u64
             }
+            x if x == Pl011Register::Status as usize => state.status as u64,
             x if x == Pl011Register::BaudRateDiv as usize => state.baud_div as u64,
-            x if x == Pl011Register::LineControl as usize => state.line_ctrl as u64,
-            x if x == Pl011Register::Control as usize => state.ctrl as u64,
+            x if x == Pl011Register::LineControl as usize => state.line_ctrl.bits() as u64,
+            x if x == Pl011Register::Control as usize => state.ctrl.bits() as u64,
             x if x == Pl011Register::InterruptFifoLevelSelect as usize => state.ifls as u64,
             x if x == Pl011Register::RawInterruptStatus as usize => state.raw_int as u64,
             x if x == Pl011Register::MaskedInterruptStatus as usize => state.masked_int as u64,
@@ -186,10 +475,10 @@ impl Emulator for Pl011Uart {
 
         // Apply size mask
         match size {
-            8 => value & 0xFF,
-            32 => value & 0xFFFFFFFF,
-            64 => value,
-            _ => return Err(EmulatorError::InvalidAccess),
+            8 => Ok(value & 0xFF),
+            32 => Ok(value & 0xFFFFFFFF),
+            64 => Ok(value),
+            _ => Err(EmulatorError::InvalidAccess),
         }
     }
 
@@ -204,39 +493,31 @@ impl Emulator for Pl011Uart {
         match addr {
             x if x == Pl011Register::Data as usize => {
                 // Write to TX FIFO
-                if state.ctrl & 0x01 != 0 { // UARTEN
-                    let c = (value & 0xFF) as u8;
-
-                    // Echo to console
-                    crate::print!("{}", c as char);
-
-                    let mut tx_fifo = state.tx_fifo.lock();
-                    if tx_fifo.len() < state.fifo_depth {
-                        tx_fifo.push(c);
-                        state.status &= !0x20; // Clear TX FIFO full flag
-                    } else {
-                        state.status |= 0x20; // TX FIFO full
-                    }
+                if state.ctrl.uarten() {
+                    let now = crate::utils::time::timestamp_ns();
+                    state.service_tx(now);
+                    state.write_tx((value & 0xFF) as u8, now);
                 }
             }
             x if x == Pl011Register::BaudRateDiv as usize => {
                 state.baud_div = (value & 0xFFFF) as u32;
             }
             x if x == Pl011Register::LineControl as usize => {
-                state.line_ctrl = (value & 0xFF) as u32;
+                state.line_ctrl = Pl011Lcr::from_bits((value & 0xFF) as u32);
             }
             x if x == Pl011Register::Control as usize => {
-                let new_ctrl = (value & 0x7FF) as u32;
-                if (new_ctrl & 0x01) == 0 && (state.ctrl & 0x01) != 0 {
+                let new_ctrl = Pl011Cr::from_bits((value & 0x7FF) as u32);
+                if !new_ctrl.uarten() && state.ctrl.uarten() {
                     // UART being disabled - clear FIFOs
-                    state.tx_fifo.lock().clear();
-                    state.rx_fifo.lock().clear();
-                    state.status = 0x90; // Both FIFOs empty
+                    state.tx_fifo.clear();
+                    state.rx_fifo.clear();
                 }
                 state.ctrl = new_ctrl;
+                state.update_fifos();
             }
             x if x == Pl011Register::InterruptFifoLevelSelect as usize => {
                 state.ifls = (value & 0x3F) as u32;
+                state.update_fifos();
             }
             x if x == Pl011Register::InterruptMaskSetClear as usize => {
                 let mask = (value & 0x7FF) as u32;
@@ -247,12 +528,12 @@ impl Emulator for Pl011Uart {
                     // Set bits
                     state.int_mask |= mask;
                 }
-                state.masked_int = state.raw_int & !state.int_mask;
+                state.update_masked();
             }
             x if x == Pl011Register::InterruptClear as usize => {
                 let clear = (value & 0x7FF) as u32;
                 state.raw_int &= !clear;
-                state.masked_int = state.raw_int & !state.int_mask;
+                state.update_masked();
             }
             _ => {
                 crate::warn!("PL011: Unhandled write 0x{:x} to offset 0x{:x}", value, addr);
@@ -269,20 +550,93 @@ impl Emulator for Pl011Uart {
         state.data = 0;
         state.status = 0x90; // TX empty, RX empty
         state.baud_div = 0;
-        state.line_ctrl = 0;
-        state.ctrl = 0;
-        state.ifls = 0;
+        state.line_ctrl = Pl011Lcr::default();
+        state.ctrl = Pl011Cr::default();
+        state.ifls = 0x12;
         state.int_mask = 0;
         state.raw_int = 0;
         state.masked_int = 0;
-        state.tx_fifo.lock().clear();
-        state.rx_fifo.lock().clear();
+        state.tx_fifo.clear();
+        state.rx_fifo.clear();
         state.host_char = None;
+        state.rx_last_activity_ns = 0;
+        state.tx_done_ns = 0;
+        state.tx_dropped = 0;
 
         Ok(())
     }
+
+    /// Advance transmission and check for the receive timeout condition
+    ///
+    /// Driven by the emulator poll loop: it retires TX bytes at the
+    /// programmed baud rate and makes sure data left below the RX FIFO
+    /// level still gets an interrupt.
+    fn poll(&mut self) {
+        let now = crate::utils::time::timestamp_ns();
+        let mut state = self.state.lock();
+        state.service_tx(now);
+        state.check_rx_timeout(now);
+    }
 }
 
+/// 16550 IER: received data available interrupt
+const UART_IER_RDI: u8 = 1 << 0;
+/// 16550 IER: transmit holding register empty interrupt
+const UART_IER_THRI: u8 = 1 << 1;
+/// 16550 IER: modem status interrupt
+const UART_IER_MSI: u8 = 1 << 3;
+
+/// 16550 IIR: no interrupt pending
+const UART_IIR_NO_INT: u8 = 0x01;
+/// 16550 IIR: modem status change (lowest priority)
+const UART_IIR_MSI: u8 = 0x00;
+/// 16550 IIR: transmit holding register empty
+const UART_IIR_THRI: u8 = 0x02;
+/// 16550 IIR: received data available
+const UART_IIR_RDI: u8 = 0x04;
+/// 16550 IIR: FIFOs enabled
+const UART_IIR_FIFO: u8 = 0xC0;
+
+/// 16550 MCR: data terminal ready
+const UART_MCR_DTR: u8 = 1 << 0;
+/// 16550 MCR: request to send
+const UART_MCR_RTS: u8 = 1 << 1;
+/// 16550 MCR: user output 1
+const UART_MCR_OUT1: u8 = 1 << 2;
+/// 16550 MCR: user output 2
+const UART_MCR_OUT2: u8 = 1 << 3;
+/// 16550 MCR: loopback mode
+const UART_MCR_LOOP: u8 = 1 << 4;
+
+/// 16550 MSR: delta clear to send
+const UART_MSR_DCTS: u8 = 1 << 0;
+/// 16550 MSR: delta data set ready
+const UART_MSR_DDSR: u8 = 1 << 1;
+/// 16550 MSR: trailing edge ring indicator
+const UART_MSR_TERI: u8 = 1 << 2;
+/// 16550 MSR: delta data carrier detect
+const UART_MSR_DDCD: u8 = 1 << 3;
+/// 16550 MSR: delta bits, cleared when MSR is read
+const UART_MSR_DELTA: u8 = 0x0F;
+/// 16550 MSR: clear to send
+const UART_MSR_CTS: u8 = 1 << 4;
+/// 16550 MSR: data set ready
+const UART_MSR_DSR: u8 = 1 << 5;
+/// 16550 MSR: ring indicator
+const UART_MSR_RI: u8 = 1 << 6;
+/// 16550 MSR: data carrier detect
+const UART_MSR_DCD: u8 = 1 << 7;
+
+/// 16550 LSR: data ready
+const UART_LSR_DR: u8 = 1 << 0;
+/// 16550 LSR: transmit holding register empty
+const UART_LSR_THRE: u8 = 1 << 5;
+/// 16550 LSR: transmitter empty
+const UART_LSR_TEMT: u8 = 1 << 6;
+
+/// 16550 FIFO depth
+const UART16550_FIFO_DEPTH: usize = 16;
+
 /// 16550-compatible UART emulator
 pub struct Uart16550 {
     /// Base address
@@ -324,6 +678,170 @@ pub struct Uart16550State {
     tx_fifo: Vec<u8>,
     /// FIFO enabled flag
     fifo_enabled: bool,
+    /// THR-empty interrupt pending, cleared by an IIR read or THR write
+    thre_pending: bool,
+    /// Modem input lines (MSR bits 7:4) driven by the host side
+    modem_in: u8,
+}
+
+impl Uart16550State {
+    /// Number of bytes the RX path holds
+    fn rx_capacity(&self) -> usize {
+        if self.fifo_enabled { UART16550_FIFO_DEPTH } else { 1 }
+    }
+
+    /// Recompute IIR from the pending sources, highest priority first
+    ///
+    /// Received data beats THR empty, which beats a modem status change.
+    fn update_iir(&mut self) {
+        let id = if self.ier & UART_IER_RDI != 0 && self.lsr & UART_LSR_DR != 0 {
+            UART_IIR_RDI
+        } else if self.ier & UART_IER_THRI != 0 && self.thre_pending {
+            UART_IIR_THRI
+        } else if self.ier & UART_IER_MSI != 0 && self.msr & UART_MSR_DELTA != 0 {
+            UART_IIR_MSI
+        } else {
+            UART_IIR_NO_INT
+        };
+
+        self.iir = id | if self.fifo_enabled { UART_IIR_FIFO } else { 0 };
+    }
+
+    /// Read IIR; identifying a THR-empty interrupt acknowledges it
+    fn read_iir(&mut self) -> u8 {
+        let iir = self.iir;
+        if iir & 0x0F == UART_IIR_THRI {
+            self.thre_pending = false;
+            self.update_iir();
+        }
+        iir
+    }
+
+    /// Queue a received byte, returns false if the RX path is full
+    fn receive(&mut self, c: u8) -> bool {
+        if self.rx_fifo.len() >= self.rx_capacity() {
+            return false;
+        }
+
+        self.rx_fifo.push(c);
+        self.lsr |= UART_LSR_DR;
+        self.update_iir();
+        true
+    }
+
+    /// Pop the next received byte into RHR
+    fn read_rhr(&mut self) -> u8 {
+        if !self.rx_fifo.is_empty() {
+            self.rhr_thr = self.rx_fifo.remove(0);
+        }
+        if self.rx_fifo.is_empty() {
+            self.lsr &= !UART_LSR_DR;
+        }
+        self.update_iir();
+        self.rhr_thr
+    }
+
+    /// Transmit a byte, or loop it back to RX in loopback mode
+    fn write_thr(&mut self, c: u8) {
+        if self.mcr & UART_MCR_LOOP != 0 {
+            self.receive(c);
+        } else {
+            crate::print!("{}", c as char);
+        }
+
+        // The byte leaves at once, so the holding register is empty again
+        self.lsr |= UART_LSR_THRE | UART_LSR_TEMT;
+        self.thre_pending = true;
+        self.update_iir();
+    }
+
+    /// Write IER; enabling the THR-empty interrupt with THR empty raises it
+    fn write_ier(&mut self, value: u8) {
+        let enabling_thri = value & UART_IER_THRI != 0 && self.ier & UART_IER_THRI == 0;
+        self.ier = value & 0x0F;
+        if enabling_thri && self.lsr & UART_LSR_THRE != 0 {
+            self.thre_pending = true;
+        }
+        self.update_iir();
+    }
+
+    /// Current modem input lines
+    ///
+    /// In loopback mode the outputs are wired back: RTS to CTS, DTR to
+    /// DSR, OUT1 to RI and OUT2 to DCD.
+    fn modem_lines(&self) -> u8 {
+        if self.mcr & UART_MCR_LOOP == 0 {
+            return self.modem_in;
+        }
+
+        let mut lines = 0;
+        if self.mcr & UART_MCR_RTS != 0 {
+            lines |= UART_MSR_CTS;
+        }
+        if self.mcr & UART_MCR_DTR != 0 {
+            lines |= UART_MSR_DSR;
+        }
+        if self.mcr & UART_MCR_OUT1 != 0 {
+            lines |= UART_MSR_RI;
+        }
+        if self.mcr & UART_MCR_OUT2 != 0 {
+            lines |= UART_MSR_DCD;
+        }
+        lines
+    }
+
+    /// Latch the modem lines into MSR, setting delta bits for changes
+    fn update_msr(&mut self) {
+        let old = self.msr;
+        let new = self.modem_lines();
+        let changed = old ^ new;
+
+        let mut delta = old & UART_MSR_DELTA;
+        if changed & UART_MSR_CTS != 0 {
+            delta |= UART_MSR_DCTS;
+        }
+        if changed & UART_MSR_DSR != 0 {
+            delta |= UART_MSR_DDSR;
+        }
+        // Only the trailing edge of RI is reported
+        if old & UART_MSR_RI != 0 && new & UART_MSR_RI == 0 {
+            delta |= UART_MSR_TERI;
+        }
+        if changed & UART_MSR_DCD != 0 {
+            delta |= UART_MSR_DDCD;
+        }
+
+        self.msr = new | delta;
+        self.update_iir();
+    }
+
+    /// Write MCR
+    fn write_mcr(&mut self, value: u8) {
+        self.mcr = value & 0x1F;
+        self.update_msr();
+    }
+
+    /// Read MSR, clearing the delta bits
+    fn read_msr(&mut self) -> u8 {
+        let msr = self.msr;
+        self.msr &= !UART_MSR_DELTA;
+        self.update_iir();
+        msr
+    }
+
+    /// Write FCR
+    fn write_fcr(&mut self, value: u8) {
+        self.fcr = value;
+        self.fifo_enabled = value & 0x01 != 0;
+        if value & 0x02 != 0 {
+            self.rx_fifo.clear();
+            self.lsr &= !UART_LSR_DR;
+        }
+        if value & 0x04 != 0 {
+            self.tx_fifo.clear();
+        }
+        self.update_iir();
+    }
 }
 
 impl Uart16550 {
@@ -344,6 +862,8 @@ impl Uart16550 {
             rx_fifo: Vec::new(),
             tx_fifo: Vec::new(),
             fifo_enabled: false,
+            thre_pending: false,
+            modem_in: 0,
         };
 
         Self {
@@ -352,6 +872,32 @@ impl Uart16550 {
             mmio: MmioAccess,
         }
     }
+
+    /// Get the base address
+    pub fn base_address(&self) -> PhysAddr {
+        self.base_addr
+    }
+
+    /// Deliver a character from the host to the guest
+    ///
+    /// Returns false if the RX FIFO is full and the character was dropped.
+    pub fn write_host_char(&self, c: u8) -> bool {
+        self.state.lock().receive(c)
+    }
+
+    /// Drive the modem input lines (CTS, DSR, RI, DCD as MSR bits 7:4)
+    ///
+    /// Ignored by the guest-visible MSR while loopback is enabled.
+    pub fn set_modem_lines(&self, lines: u8) {
+        let mut state = self.state.lock();
+        state.modem_in = lines & 0xF0;
+        state.update_msr();
+    }
+
+    /// Check whether the UART is asserting its interrupt
+    pub fn interrupt_pending(&self) -> bool {
+        self.state.lock().iir & UART_IIR_NO_INT == 0
+    }
 }
 
 impl Emulator for Uart16550 {
@@ -372,23 +918,23 @@ impl Emulator for Uart16550 {
             (0, 0x80) => state.dll as u64,       // DLL
             (1, 0x80) => state.dlm as u64,       // DLM
             // Normal register access
-            (0, _) => state.rhr_thr as u64,      // RHR
+            (0, _) => state.read_rhr() as u64,   // RHR
             (1, _) => state.ier as u64,          // IER
-            (2, _) => state.iir as u64,          // IIR
+            (2, _) => state.read_iir() as u64,   // IIR
             (3, _) => state.lcr as u64,          // LCR
             (4, _) => state.mcr as u64,          // MCR
             (5, _) => state.lsr as u64,          // LSR
-            (6, _) => state.msr as u64,          // MSR
+            (6, _) => state.read_msr() as u64,   // MSR
             (7, _) => state.scr as u64,          // SCR
             _ => 0,
         };
 
         // Apply size mask
         match size {
-            8 => value & 0xFF,
-            16 => value & 0xFFFF,
-            32 => value & 0xFFFFFFFF,
-            _ => return Err(EmulatorError::InvalidAccess),
+            8 => Ok(value & 0xFF),
+            16 => Ok(value & 0xFFFF),
+            32 => Ok(value & 0xFFFFFFFF),
+            _ => Err(EmulatorError::InvalidAccess),
         }
     }
 
@@ -406,25 +952,11 @@ impl Emulator for Uart16550 {
             (0, 0x80) => state.dll = byte_value,     // DLL
             (1, 0x80) => state.dlm = byte_value,     // DLM
             // Normal register access
-            (0, _) => {
-                // THR - transmit holding register
-                crate::print!("{}", byte_value as char);
-                state.lsr |= 0x20; // TX empty
-                state.lsr |= 0x40; // TX holding register empty
-            }
-            (1, _) => state.ier = byte_value & 0x0F, // IER
-            (2, _) => {
-                // FCR - FIFO control register
-                state.fcr = byte_value;
-                if byte_value & 0x01 != 0 {
-                    state.fifo_enabled = true;
-                    state.rx_fifo.clear();
-                    state.tx_fifo.clear();
-                }
-                state.iir = if state.fifo_enabled { 0xC0 } else { 0x01 };
-            }
+            (0, _) => state.write_thr(byte_value),    // THR
+            (1, _) => state.write_ier(byte_value),    // IER
+            (2, _) => state.write_fcr(byte_value),    // FCR
             (3, _) => state.lcr = byte_value,         // LCR
-            (4, _) => state.mcr = byte_value & 0x1F, // MCR
+            (4, _) => state.write_mcr(byte_value),    // MCR
             (5, _) => {},                             // LSR (read-only)
             (6, _) => {},                             // MSR (read-only)
             (7, _) => state.scr = byte_value,         // SCR
@@ -452,22 +984,282 @@ impl Emulator for Uart16550 {
         state.rx_fifo.clear();
         state.tx_fifo.clear();
         state.fifo_enabled = false;
+        state.thre_pending = false;
+        state.update_msr();
+        state.msr &= !UART_MSR_DELTA;
+        state.update_iir();
 
         Ok(())
     }
 }
 
-/// Initialize UART emulators
-pub fn init() -> Result<(), crate::Error> {
-    crate::info!("Initializing UART emulators");
+/// Size of the PL011 register window
+pub const PL011_SIZE: u64 = 0x1000;
 
-    // Register PL011 UART at typical ARM location
+/// Give a VM a PL011 UART at the QEMU virt location
+pub fn attach_pl011(vm_id: VmId) -> Result<(), crate::Error> {
     let pl011 = Pl011Uart::new(0x9000000);
-    crate::emulator::register_emulator("uart-pl011", &pl011)?;
+    let base = pl011.base_address();
+    crate::emulator::register_mmio_emulator(vm_id, "uart-pl011", DeviceClass::Uart, base, PL011_SIZE,
+                                            Box::new(pl011))
+}
 
-    // Register 16550 UART at typical PC location
+/// Give a VM a 16550 UART at the typical PC location, an I/O port range
+pub fn attach_16550(vm_id: VmId) -> Result<(), crate::Error> {
     let uart16550 = Uart16550::new(0x3F8);
-    crate::emulator::register_emulator("uart-16550", &uart16550)?;
+    crate::emulator::register_emulator(vm_id, "uart-16550", DeviceClass::Uart, Box::new(uart16550))
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Enabled PL011 state with the given RX level selector
+    fn pl011_with_rx_level(rx_sel: u32) -> Pl011State {
+        let uart = Pl011Uart::new(0x9000000);
+        let mut state = uart.state.lock().clone();
+        state.ctrl.set_uarten(true);
+        state.ctrl.set_txe(true);
+        state.ctrl.set_rxe(true);
+        state.ifls = rx_sel << 3;
+        state.update_fifos();
+        state
+    }
+
+    /// Number of received characters needed to raise the RX interrupt
+    fn rx_trigger_count(rx_sel: u32) -> usize {
+        let mut state = pl011_with_rx_level(rx_sel);
+        for n in 1..=state.fifo_depth {
+            state.receive(b'a', 0);
+            if state.raw_int & PL011_INT_RX != 0 {
+                return n;
+            }
+        }
+        panic!("RX interrupt never raised");
+    }
+
+    #[test]
+    fn test_pl011_rx_interrupt_thresholds() {
+        // 16-entry FIFO: 1/8 = 2, 1/2 = 8, 7/8 = 14
+        assert_eq!(rx_trigger_count(0), 2);
+        assert_eq!(rx_trigger_count(2), 8);
+        assert_eq!(rx_trigger_count(4), 14);
+    }
+
+    #[test]
+    fn test_pl011_rx_interrupt_clears_below_level() {
+        let mut state = pl011_with_rx_level(2);
+        for _ in 0..8 {
+            state.receive(b'a', 0);
+        }
+        assert_ne!(state.masked_int & PL011_INT_RX, 0);
+
+        state.read_rx(0);
+        assert_eq!(state.raw_int & PL011_INT_RX, 0);
+    }
+
+    #[test]
+    fn test_pl011_tx_interrupt_on_drain() {
+        let mut state = pl011_with_rx_level(0);
+        state.ifls |= 2; // TX at 1/2
+        for _ in 0..12 {
+            state.tx_fifo.push_back(b'a');
+        }
+        state.update_fifos();
+        assert_eq!(state.raw_int & PL011_INT_TX, 0);
+
+        state.drain_tx();
+        assert_ne!(state.raw_int & PL011_INT_TX, 0);
+        assert_ne!(state.status & 0x80, 0);
+    }
+
+    #[test]
+    fn test_pl011_baud_byte_period() {
+        let mut state = pl011_with_rx_level(0);
+        assert_eq!(state.byte_period_ns(), None);
+
+        // 24MHz / (16 * 13) = 115384 baud, 8N1 = 10 bits per frame
+        state.baud_div = 13;
+        state.line_ctrl.set_wlen(3).unwrap();
+        assert_eq!(state.byte_period_ns(), Some(86_666));
+
+        // 8E2 adds a parity bit and a second stop bit
+        state.line_ctrl = Pl011Lcr::from_bits(0x6E);
+        assert_eq!(state.byte_period_ns(), Some(104_000));
+    }
+
+    #[test]
+    fn test_pl011_tx_empty_paced_by_baud() {
+        const TICK_NS: u64 = 10_000;
+
+        let mut state = pl011_with_rx_level(0);
+        state.baud_div = 13;
+        state.line_ctrl.set_wlen(3).unwrap();
+
+        for c in b"ping" {
+            state.write_tx(*c, 0);
+        }
+        assert_eq!(state.status & 0x80, 0);
+
+        let mut ticks = 0;
+        while state.status & 0x80 == 0 {
+            ticks += 1;
+            state.service_tx(ticks * TICK_NS);
+            assert!(ticks < 1_000, "TX never drained");
+        }
+
+        // 4 bytes * 86.666us = 346.664us -> 35 ticks of 10us
+        assert_eq!(ticks, 35);
+        assert_ne!(state.raw_int & PL011_INT_TX, 0);
+        assert_eq!(state.status & 0x08, 0);
+    }
+
+    #[test]
+    fn test_pl011_rx_timeout() {
+        let mut state = pl011_with_rx_level(4);
+        state.receive(b'a', 1_000);
+        assert_eq!(state.raw_int & (PL011_INT_RX | PL011_INT_RT), 0);
+
+        let timeout = state.rx_timeout_ns();
+        state.check_rx_timeout(1_000 + timeout - 1);
+        assert_eq!(state.raw_int & PL011_INT_RT, 0);
+
+        state.check_rx_timeout(1_000 + timeout);
+        assert_ne!(state.masked_int & PL011_INT_RT, 0);
+
+        // Draining the FIFO clears the timeout
+        state.read_rx(1_000 + timeout);
+        assert_eq!(state.raw_int & PL011_INT_RT, 0);
+    }
+
+    /// Enabled PL011 at 115200 baud so written bytes stay queued
+    fn pl011_paced(policy: Pl011TxPolicy) -> Pl011State {
+        let uart = Pl011Uart::with_tx_policy(0x9000000, policy);
+        let mut state = uart.state.lock().clone();
+        state.ctrl.set_uarten(true);
+        state.ctrl.set_txe(true);
+        state.baud_div = 13;
+        state.line_ctrl.set_wlen(3).unwrap();
+        state.update_fifos();
+        state
+    }
 
-    Ok(())
-}
\ No newline at end of file
+    #[test]
+    fn test_pl011_tx_full_flag() {
+        let mut state = pl011_paced(Pl011TxPolicy::Drop);
+        for c in 0..state.fifo_depth as u8 - 1 {
+            assert!(state.write_tx(c, 0));
+        }
+        assert_eq!(state.status & PL011_FR_TXFF, 0);
+
+        assert!(state.write_tx(b'x', 0));
+        assert_ne!(state.status & PL011_FR_TXFF, 0);
+        assert_ne!(state.status & PL011_FR_BUSY, 0);
+        assert_eq!(state.status & PL011_FR_TXFE, 0);
+
+        // One byte out makes room again
+        state.service_tx(86_666);
+        assert_eq!(state.status & PL011_FR_TXFF, 0);
+    }
+
+    #[test]
+    fn test_pl011_full_fifo_drop_policy() {
+        let mut state = pl011_paced(Pl011TxPolicy::Drop);
+        for c in 0..16 {
+            state.write_tx(c, 0);
+        }
+
+        assert!(!state.write_tx(0xff, 0));
+        assert_eq!(state.tx_dropped, 1);
+        assert_eq!(state.tx_fifo.len(), 16);
+        assert_eq!(state.tx_fifo.front(), Some(&0));
+        assert_eq!(state.tx_fifo.back(), Some(&15));
+    }
+
+    #[test]
+    fn test_pl011_full_fifo_block_policy() {
+        let mut state = pl011_paced(Pl011TxPolicy::Block);
+        for c in 0..16 {
+            state.write_tx(c, 0);
+        }
+
+        // The oldest byte goes out to make room for the new one
+        assert!(state.write_tx(0xff, 0));
+        assert_eq!(state.tx_dropped, 0);
+        assert_eq!(state.tx_fifo.len(), 16);
+        assert_eq!(state.tx_fifo.front(), Some(&1));
+        assert_eq!(state.tx_fifo.back(), Some(&0xff));
+        assert_ne!(state.status & PL011_FR_TXFF, 0);
+        assert_eq!(state.tx_done_ns, 2 * 86_666);
+    }
+
+    /// 16550 state with the given interrupts enabled
+    fn uart16550_with_ier(ier: u8) -> Uart16550State {
+        let uart = Uart16550::new(0x3F8);
+        let mut state = uart.state.lock().clone();
+        state.write_ier(ier);
+        state
+    }
+
+    #[test]
+    fn test_16550_loopback_routes_tx_to_rx() {
+        let mut state = uart16550_with_ier(0);
+        state.write_mcr(UART_MCR_LOOP);
+
+        state.write_thr(b'Z');
+        assert_ne!(state.lsr & UART_LSR_DR, 0);
+        assert_eq!(state.read_rhr(), b'Z');
+        assert_eq!(state.lsr & UART_LSR_DR, 0);
+
+        // Without loopback nothing comes back
+        state.write_mcr(0);
+        state.write_thr(b'Z');
+        assert_eq!(state.lsr & UART_LSR_DR, 0);
+    }
+
+    #[test]
+    fn test_16550_msr_delta_on_mcr_change() {
+        let mut state = uart16550_with_ier(UART_IER_MSI);
+        state.write_mcr(UART_MCR_LOOP);
+        assert_eq!(state.msr, 0);
+        assert_eq!(state.iir, UART_IIR_NO_INT);
+
+        // RTS and DTR show up as CTS and DSR, with their delta bits
+        state.write_mcr(UART_MCR_LOOP | UART_MCR_RTS | UART_MCR_DTR);
+        assert_eq!(state.msr, UART_MSR_CTS | UART_MSR_DSR | UART_MSR_DCTS | UART_MSR_DDSR);
+        assert_eq!(state.iir, UART_IIR_MSI);
+
+        // Reading MSR acknowledges the change
+        assert_eq!(state.read_msr() & UART_MSR_DELTA, UART_MSR_DCTS | UART_MSR_DDSR);
+        assert_eq!(state.msr, UART_MSR_CTS | UART_MSR_DSR);
+        assert_eq!(state.iir, UART_IIR_NO_INT);
+
+        // RI only reports its trailing edge
+        state.write_mcr(UART_MCR_LOOP | UART_MCR_RTS | UART_MCR_DTR | UART_MCR_OUT1);
+        assert_eq!(state.msr & UART_MSR_DELTA, 0);
+        state.write_mcr(UART_MCR_LOOP | UART_MCR_RTS | UART_MCR_DTR);
+        assert_eq!(state.msr & UART_MSR_DELTA, UART_MSR_TERI);
+    }
+
+    #[test]
+    fn test_16550_iir_priority() {
+        let mut state = uart16550_with_ier(UART_IER_RDI | UART_IER_THRI | UART_IER_MSI);
+        // Enabling THRI with an empty THR raises it straight away
+        assert_eq!(state.iir, UART_IIR_THRI);
+
+        state.write_mcr(UART_MCR_LOOP | UART_MCR_DTR);
+        state.receive(b'a');
+        assert_eq!(state.iir, UART_IIR_RDI);
+
+        state.read_rhr();
+        assert_eq!(state.iir, UART_IIR_THRI);
+
+        // Identifying THR empty acknowledges it, leaving the modem change
+        assert_eq!(state.read_iir(), UART_IIR_THRI);
+        assert_eq!(state.iir, UART_IIR_MSI);
+        state.read_msr();
+        assert_eq!(state.iir, UART_IIR_NO_INT);
+
+        state.write_fcr(0x01);
+        assert_eq!(state.iir, UART_IIR_FIFO | UART_IIR_NO_INT);
+    }
+}
@@ -0,0 +1,697 @@
+//! VirtIO MMIO Transport Emulator
+//!
+//! This module provides the guest-facing side of a virtio-mmio device
+//! (transport version 2, VirtIO 1.x spec section 4.2.2). The transport
+//! decodes the register set and keeps the per-queue setup the driver
+//! programs; everything device-specific lives in a `VirtioBackend`:
+//! - the device ID, feature bits and queue limits it reports
+//! - its configuration space at offset 0x100
+//! - what happens when a queue goes live or is notified
+//!
+//! A queue is handed to the backend when the driver writes 1 to
+//! QueueReady, with the size and ring addresses programmed before it;
+//! backends walk it with a `queue::DeviceQueue`. Writing 0 to Status
+//! resets both the transport and the backend.
+//!
+//! A device model the host also drives (e.g. to inject received frames)
+//! is shared with the transport as an `Arc<SpinLock<_>>`.
+
+pub mod queue;
+
+use crate::{Result, Error};
+use crate::emulator::{DeviceClass, Emulator, Error as EmulatorError};
+use crate::core::mm::PhysAddr;
+use crate::core::sync::SpinLock;
+use crate::core::vmm::VmId;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// virtio-mmio registers (version 2 layout)
+#[allow(dead_code)]
+#[repr(usize)]
+enum VirtioMmioRegister {
+    MagicValue = 0x000,
+    Version = 0x004,
+    DeviceId = 0x008,
+    VendorId = 0x00C,
+    DeviceFeatures = 0x010,
+    DeviceFeaturesSel = 0x014,
+    DriverFeatures = 0x020,
+    DriverFeaturesSel = 0x024,
+    QueueSel = 0x030,
+    QueueNumMax = 0x034,
+    QueueNum = 0x038,
+    QueueReady = 0x044,
+    QueueNotify = 0x050,
+    InterruptStatus = 0x060,
+    InterruptAck = 0x064,
+    Status = 0x070,
+    QueueDescLow = 0x080,
+    QueueDescHigh = 0x084,
+    QueueDriverLow = 0x090,
+    QueueDriverHigh = 0x094,
+    QueueDeviceLow = 0x0A0,
+    QueueDeviceHigh = 0x0A4,
+    ConfigGeneration = 0x0FC,
+    Config = 0x100,
+}
+
+/// MagicValue: "virt" in little-endian
+pub const VIRTIO_MMIO_MAGIC: u32 = 0x7472_6976;
+
+/// Transport version emulated
+pub const VIRTIO_MMIO_VERSION: u32 = 2;
+
+/// Vendor ID reported to the guest
+pub const VIRTIO_MMIO_VENDOR_ID: u32 = 0x554D_4551; // "QEMU"
+
+/// Size of the register window of one device
+pub const VIRTIO_MMIO_SIZE: u64 = 0x200;
+
+/// InterruptStatus: a used ring was updated
+pub const VIRTIO_MMIO_INT_VRING: u32 = 1 << 0;
+/// InterruptStatus: the configuration space changed
+pub const VIRTIO_MMIO_INT_CONFIG: u32 = 1 << 1;
+
+/// Feature bit every version 2 device must offer
+const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+/// Status: driver has written all features it understands
+const STATUS_FEATURES_OK: u32 = 0x08;
+
+/// Callback invoked to raise or lower the device interrupt line
+pub type VirtioIrqFn = fn(irq: u32, level: bool);
+
+/// Queue setup handed to the backend when a queue goes live
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueueConfig {
+    /// Queue size in descriptors
+    pub size: u16,
+    /// Guest physical address of the descriptor table
+    pub desc_addr: PhysAddr,
+    /// Guest physical address of the driver (available) ring
+    pub driver_addr: PhysAddr,
+    /// Guest physical address of the device (used) ring
+    pub device_addr: PhysAddr,
+}
+
+/// Device model behind a virtio-mmio transport
+pub trait VirtioBackend: Send {
+    /// VirtIO device ID (e.g. 2 for block)
+    fn device_id(&self) -> u32;
+
+    /// Feature bits offered to the driver
+    fn features(&self) -> u64;
+
+    /// Number of virtqueues
+    fn num_queues(&self) -> usize;
+
+    /// Largest size the driver may give queue `index`
+    fn queue_max(&self, index: usize) -> u16;
+
+    /// Features the driver accepted
+    fn set_driver_features(&mut self, _features: u64) {}
+
+    /// Start servicing a queue
+    fn activate_queue(&mut self, index: usize, config: QueueConfig) -> Result<()>;
+
+    /// Stop servicing a queue
+    fn deactivate_queue(&mut self, _index: usize) {}
+
+    /// Driver notified a queue
+    ///
+    /// Returns the InterruptStatus bits to raise, if any.
+    fn notify(&mut self, index: usize) -> u32;
+
+    /// Read `size` bits of configuration space at `offset`
+    fn read_config(&self, offset: u64, size: u32) -> u64;
+
+    /// Write `size` bits of configuration space at `offset`
+    fn write_config(&mut self, _offset: u64, _value: u64, _size: u32) {}
+
+    /// Return the device to its initial state
+    fn reset(&mut self);
+
+    /// Events raised outside a guest access
+    ///
+    /// Returns the InterruptStatus bits to raise; `VIRTIO_MMIO_INT_CONFIG`
+    /// also bumps ConfigGeneration.
+    fn poll(&mut self) -> u32 {
+        0
+    }
+}
+
+impl<B: VirtioBackend> VirtioBackend for Arc<SpinLock<B>> {
+    fn device_id(&self) -> u32 {
+        self.lock().device_id()
+    }
+
+    fn features(&self) -> u64 {
+        self.lock().features()
+    }
+
+    fn num_queues(&self) -> usize {
+        self.lock().num_queues()
+    }
+
+    fn queue_max(&self, index: usize) -> u16 {
+        self.lock().queue_max(index)
+    }
+
+    fn set_driver_features(&mut self, features: u64) {
+        self.lock().set_driver_features(features)
+    }
+
+    fn activate_queue(&mut self, index: usize, config: QueueConfig) -> Result<()> {
+        self.lock().activate_queue(index, config)
+    }
+
+    fn deactivate_queue(&mut self, index: usize) {
+        self.lock().deactivate_queue(index)
+    }
+
+    fn notify(&mut self, index: usize) -> u32 {
+        self.lock().notify(index)
+    }
+
+    fn read_config(&self, offset: u64, size: u32) -> u64 {
+        self.lock().read_config(offset, size)
+    }
+
+    fn write_config(&mut self, offset: u64, value: u64, size: u32) {
+        self.lock().write_config(offset, value, size)
+    }
+
+    fn reset(&mut self) {
+        self.lock().reset()
+    }
+
+    fn poll(&mut self) -> u32 {
+        self.lock().poll()
+    }
+}
+
+/// Read `size` bits at `offset` of a little-endian configuration space
+///
+/// Reads outside `config` return 0.
+pub fn read_config_bytes(config: &[u8], offset: u64, size: u32) -> u64 {
+    let start = offset as usize;
+    config.get(start..start + (size / 8) as usize)
+        .map_or(0, |bytes| bytes.iter().rev().fold(0, |value, &byte| value << 8 | byte as u64))
+}
+
+/// Transport state of one virtqueue
+#[derive(Debug, Clone, Copy, Default)]
+struct QueueState {
+    /// Queue setup programmed by the driver
+    config: QueueConfig,
+    /// QueueReady
+    ready: bool,
+}
+
+/// Replace the low or high half of a 64-bit address
+fn set_half(addr: &mut PhysAddr, high: bool, value: u32) {
+    if high {
+        *addr = (*addr & 0xFFFF_FFFF) | (value as u64) << 32;
+    } else {
+        *addr = (*addr & !0xFFFF_FFFF) | value as u64;
+    }
+}
+
+/// Select the low or high half of a 64-bit value
+fn get_half(value: u64, high: bool) -> u32 {
+    if high { (value >> 32) as u32 } else { value as u32 }
+}
+
+/// virtio-mmio version 2 transport
+pub struct VirtioMmioTransport<B: VirtioBackend> {
+    /// Base address
+    base_addr: PhysAddr,
+    /// Interrupt line
+    irq: u32,
+    /// Device model
+    backend: B,
+    /// DeviceFeaturesSel
+    device_features_sel: u32,
+    /// DriverFeaturesSel
+    driver_features_sel: u32,
+    /// Features accepted by the driver
+    driver_features: u64,
+    /// QueueSel
+    queue_sel: u32,
+    /// Per-queue state
+    queues: Vec<QueueState>,
+    /// InterruptStatus
+    interrupt_status: u32,
+    /// Device status
+    status: u32,
+    /// ConfigGeneration
+    config_generation: u32,
+    /// Interrupt action
+    on_irq: Option<VirtioIrqFn>,
+    /// Guest interrupt line, as the owning VM and the device's IRQ route name
+    guest_irq: Option<(VmId, String)>,
+}
+
+impl<B: VirtioBackend> VirtioMmioTransport<B> {
+    /// Create a transport for `backend`
+    pub fn new(base_addr: PhysAddr, irq: u32, backend: B) -> Self {
+        let queues = alloc::vec![QueueState::default(); backend.num_queues()];
+        Self {
+            base_addr,
+            irq,
+            backend,
+            device_features_sel: 0,
+            driver_features_sel: 0,
+            driver_features: 0,
+            queue_sel: 0,
+            queues,
+            interrupt_status: 0,
+            status: 0,
+            config_generation: 0,
+            on_irq: None,
+            guest_irq: None,
+        }
+    }
+
+    /// Get the base address
+    pub fn base_address(&self) -> PhysAddr {
+        self.base_addr
+    }
+
+    /// Set the interrupt action
+    pub fn set_irq_handler(&mut self, handler: VirtioIrqFn) {
+        self.on_irq = Some(handler);
+    }
+
+    /// Drive the guest interrupt line `device` routes to in VM `vm_id`
+    ///
+    /// Takes precedence over the interrupt action.
+    pub fn set_guest_irq(&mut self, vm_id: VmId, device: &str) {
+        self.guest_irq = Some((vm_id, String::from(device)));
+    }
+
+    /// Get the device model
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// Get the device model mutably
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
+    /// Get the device status
+    pub fn status(&self) -> u32 {
+        self.status
+    }
+
+    /// Check whether a queue is live
+    pub fn queue_ready(&self, index: usize) -> bool {
+        self.queues.get(index).is_some_and(|q| q.ready)
+    }
+
+    /// Raise InterruptStatus bits and assert the interrupt line
+    pub fn raise_interrupt(&mut self, bits: u32) {
+        if bits == 0 {
+            return;
+        }
+        self.interrupt_status |= bits;
+        self.set_irq(true);
+    }
+
+    /// Tell the driver the configuration space changed
+    pub fn config_changed(&mut self) {
+        self.config_generation = self.config_generation.wrapping_add(1);
+        self.raise_interrupt(VIRTIO_MMIO_INT_CONFIG);
+    }
+
+    /// Features offered to the driver
+    fn device_features(&self) -> u64 {
+        self.backend.features() | VIRTIO_F_VERSION_1
+    }
+
+    /// Queue selected by QueueSel, if it exists
+    fn selected_queue(&mut self) -> Option<&mut QueueState> {
+        self.queues.get_mut(self.queue_sel as usize)
+    }
+
+    /// Handle a QueueReady write
+    fn set_queue_ready(&mut self, ready: bool) -> Result<(), EmulatorError> {
+        let index = self.queue_sel as usize;
+        let max = self.backend.queue_max(index);
+        let Some(queue) = self.queues.get_mut(index) else {
+            return Err(EmulatorError::InvalidAccess);
+        };
+
+        if !ready {
+            if queue.ready {
+                queue.ready = false;
+                self.backend.deactivate_queue(index);
+            }
+            return Ok(());
+        }
+        if queue.ready {
+            return Ok(());
+        }
+
+        let size = queue.config.size;
+        if size == 0 || size > max || !size.is_power_of_two() {
+            crate::warn!("virtio-mmio: queue {} has invalid size {}", index, size);
+            return Err(EmulatorError::InvalidConfiguration);
+        }
+
+        let config = queue.config;
+        queue.ready = true;
+        if let Err(e) = self.backend.activate_queue(index, config) {
+            crate::warn!("virtio-mmio: failed to activate queue {}: {:?}", index, e);
+            self.queues[index].ready = false;
+            return Err(EmulatorError::ResourceUnavailable);
+        }
+        Ok(())
+    }
+
+    /// Handle a Status write
+    fn set_status(&mut self, value: u32) {
+        if value == 0 {
+            self.reset_device();
+            return;
+        }
+
+        if value & STATUS_FEATURES_OK != 0 && self.status & STATUS_FEATURES_OK == 0 {
+            if self.driver_features & !self.device_features() != 0 {
+                // Driver accepted something we never offered: refuse FEATURES_OK
+                crate::warn!("virtio-mmio: driver accepted unsupported features {:#x}", self.driver_features);
+                self.status = value & !STATUS_FEATURES_OK;
+                return;
+            }
+            self.backend.set_driver_features(self.driver_features);
+        }
+        self.status = value;
+    }
+
+    /// Return the transport and backend to their initial state
+    fn reset_device(&mut self) {
+        self.backend.reset();
+        for queue in self.queues.iter_mut() {
+            *queue = QueueState::default();
+        }
+        self.device_features_sel = 0;
+        self.driver_features_sel = 0;
+        self.driver_features = 0;
+        self.queue_sel = 0;
+        self.interrupt_status = 0;
+        self.status = 0;
+        self.set_irq(false);
+    }
+
+    /// Drive the interrupt line
+    fn set_irq(&self, level: bool) {
+        if let Some((vm_id, device)) = &self.guest_irq {
+            if let Err(e) = crate::core::vmm::vm::set_device_irq(*vm_id, device, level) {
+                crate::warn!("virtio-mmio: failed to drive IRQ of {}: {:?}", device, e);
+            }
+        } else if let Some(on_irq) = self.on_irq {
+            on_irq(self.irq, level);
+        }
+    }
+}
+
+impl<B: VirtioBackend> Emulator for VirtioMmioTransport<B> {
+    fn name(&self) -> &str {
+        "virtio-mmio"
+    }
+
+    fn read(&self, offset: u64, size: u32) -> Result<u64, EmulatorError> {
+        let addr = offset as usize;
+
+        if addr >= VirtioMmioRegister::Config as usize {
+            let config_offset = offset - VirtioMmioRegister::Config as u64;
+            return Ok(self.backend.read_config(config_offset, size));
+        }
+        if size != 32 {
+            return Err(EmulatorError::InvalidAccess);
+        }
+
+        let queue = self.queues.get(self.queue_sel as usize);
+        let value = match addr {
+            x if x == VirtioMmioRegister::MagicValue as usize => VIRTIO_MMIO_MAGIC,
+            x if x == VirtioMmioRegister::Version as usize => VIRTIO_MMIO_VERSION,
+            x if x == VirtioMmioRegister::DeviceId as usize => self.backend.device_id(),
+            x if x == VirtioMmioRegister::VendorId as usize => VIRTIO_MMIO_VENDOR_ID,
+            x if x == VirtioMmioRegister::DeviceFeatures as usize => match self.device_features_sel {
+                sel @ (0 | 1) => get_half(self.device_features(), sel == 1),
+                _ => 0,
+            },
+            x if x == VirtioMmioRegister::QueueNumMax as usize => {
+                if queue.is_some() { self.backend.queue_max(self.queue_sel as usize) as u32 } else { 0 }
+            }
+            x if x == VirtioMmioRegister::QueueNum as usize => queue.map_or(0, |q| q.config.size as u32),
+            x if x == VirtioMmioRegister::QueueReady as usize => queue.map_or(0, |q| q.ready as u32),
+            x if x == VirtioMmioRegister::QueueDescLow as usize => queue.map_or(0, |q| get_half(q.config.desc_addr, false)),
+            x if x == VirtioMmioRegister::QueueDescHigh as usize => queue.map_or(0, |q| get_half(q.config.desc_addr, true)),
+            x if x == VirtioMmioRegister::QueueDriverLow as usize => queue.map_or(0, |q| get_half(q.config.driver_addr, false)),
+            x if x == VirtioMmioRegister::QueueDriverHigh as usize => queue.map_or(0, |q| get_half(q.config.driver_addr, true)),
+            x if x == VirtioMmioRegister::QueueDeviceLow as usize => queue.map_or(0, |q| get_half(q.config.device_addr, false)),
+            x if x == VirtioMmioRegister::QueueDeviceHigh as usize => queue.map_or(0, |q| get_half(q.config.device_addr, true)),
+            x if x == VirtioMmioRegister::InterruptStatus as usize => self.interrupt_status,
+            x if x == VirtioMmioRegister::Status as usize => self.status,
+            x if x == VirtioMmioRegister::ConfigGeneration as usize => self.config_generation,
+            _ => {
+                crate::warn!("virtio-mmio: Unhandled read from offset 0x{:x}", addr);
+                0
+            }
+        };
+
+        Ok(value as u64)
+    }
+
+    fn write(&mut self, offset: u64, value: u64, size: u32) -> Result<(), EmulatorError> {
+        let addr = offset as usize;
+
+        if addr >= VirtioMmioRegister::Config as usize {
+            let config_offset = offset - VirtioMmioRegister::Config as u64;
+            self.backend.write_config(config_offset, value, size);
+            return Ok(());
+        }
+        if size != 32 {
+            return Err(EmulatorError::InvalidAccess);
+        }
+
+        let value = value as u32;
+        match addr {
+            x if x == VirtioMmioRegister::DeviceFeaturesSel as usize => self.device_features_sel = value,
+            x if x == VirtioMmioRegister::DriverFeatures as usize => {
+                let high = match self.driver_features_sel {
+                    0 => false,
+                    1 => true,
+                    _ => return Ok(()),
+                };
+                set_half(&mut self.driver_features, high, value);
+            }
+            x if x == VirtioMmioRegister::DriverFeaturesSel as usize => self.driver_features_sel = value,
+            x if x == VirtioMmioRegister::QueueSel as usize => self.queue_sel = value,
+            x if x == VirtioMmioRegister::QueueNum as usize => {
+                if let Some(queue) = self.selected_queue() {
+                    queue.config.size = value as u16;
+                }
+            }
+            x if x == VirtioMmioRegister::QueueReady as usize => return self.set_queue_ready(value & 1 != 0),
+            x if x == VirtioMmioRegister::QueueNotify as usize => {
+                let index = value as usize;
+                if self.queue_ready(index) {
+                    let bits = self.backend.notify(index);
+                    self.raise_interrupt(bits);
+                }
+            }
+            x if x == VirtioMmioRegister::InterruptAck as usize => {
+                self.interrupt_status &= !value;
+                if self.interrupt_status == 0 {
+                    self.set_irq(false);
+                }
+            }
+            x if x == VirtioMmioRegister::Status as usize => self.set_status(value),
+            x if x == VirtioMmioRegister::QueueDescLow as usize
+                || x == VirtioMmioRegister::QueueDescHigh as usize => {
+                let high = x == VirtioMmioRegister::QueueDescHigh as usize;
+                if let Some(queue) = self.selected_queue() {
+                    set_half(&mut queue.config.desc_addr, high, value);
+                }
+            }
+            x if x == VirtioMmioRegister::QueueDriverLow as usize
+                || x == VirtioMmioRegister::QueueDriverHigh as usize => {
+                let high = x == VirtioMmioRegister::QueueDriverHigh as usize;
+                if let Some(queue) = self.selected_queue() {
+                    set_half(&mut queue.config.driver_addr, high, value);
+                }
+            }
+            x if x == VirtioMmioRegister::QueueDeviceLow as usize
+                || x == VirtioMmioRegister::QueueDeviceHigh as usize => {
+                let high = x == VirtioMmioRegister::QueueDeviceHigh as usize;
+                if let Some(queue) = self.selected_queue() {
+                    set_half(&mut queue.config.device_addr, high, value);
+                }
+            }
+            _ => {
+                crate::warn!("virtio-mmio: Unhandled write 0x{:x} to offset 0x{:x}", value, addr);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Result<(), EmulatorError> {
+        self.reset_device();
+        self.config_generation = 0;
+        Ok(())
+    }
+
+    fn poll(&mut self) {
+        let bits = self.backend.poll();
+        if bits & VIRTIO_MMIO_INT_CONFIG != 0 {
+            self.config_generation = self.config_generation.wrapping_add(1);
+        }
+        self.raise_interrupt(bits);
+    }
+}
+
+/// Register a VM's virtio-mmio device with the emulator registry
+///
+/// The VM's accesses to the `VIRTIO_MMIO_SIZE` window at the transport's
+/// base address are routed to it.
+pub fn register<B: VirtioBackend + 'static>(vm_id: VmId, name: &str,
+                                            transport: VirtioMmioTransport<B>) -> Result<(), Error> {
+    let base = transport.base_address();
+    crate::emulator::register_mmio_emulator(vm_id, name, DeviceClass::Virtio, base, VIRTIO_MMIO_SIZE,
+                                            Box::new(transport))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Backend with two queues that records activations
+    #[derive(Default)]
+    struct MockBackend {
+        activated: Vec<(usize, QueueConfig)>,
+        notified: Vec<usize>,
+        resets: u32,
+    }
+
+    impl VirtioBackend for MockBackend {
+        fn device_id(&self) -> u32 {
+            2
+        }
+
+        fn features(&self) -> u64 {
+            1 << 6
+        }
+
+        fn num_queues(&self) -> usize {
+            2
+        }
+
+        fn queue_max(&self, _index: usize) -> u16 {
+            256
+        }
+
+        fn activate_queue(&mut self, index: usize, config: QueueConfig) -> Result<()> {
+            self.activated.push((index, config));
+            Ok(())
+        }
+
+        fn notify(&mut self, index: usize) -> u32 {
+            self.notified.push(index);
+            VIRTIO_MMIO_INT_VRING
+        }
+
+        fn read_config(&self, offset: u64, _size: u32) -> u64 {
+            0x1000 + offset
+        }
+
+        fn reset(&mut self) {
+            self.resets += 1;
+        }
+    }
+
+    fn transport() -> VirtioMmioTransport<MockBackend> {
+        VirtioMmioTransport::new(0x1000_1000, 1, MockBackend::default())
+    }
+
+    fn write_reg(dev: &mut VirtioMmioTransport<MockBackend>, reg: VirtioMmioRegister, value: u32) {
+        dev.write(reg as u64, value as u64, 32).unwrap();
+    }
+
+    fn read_reg(dev: &VirtioMmioTransport<MockBackend>, reg: VirtioMmioRegister) -> u32 {
+        dev.read(reg as u64, 32).unwrap() as u32
+    }
+
+    #[test]
+    fn test_identification_registers() {
+        let mut dev = transport();
+        assert_eq!(read_reg(&dev, VirtioMmioRegister::MagicValue), VIRTIO_MMIO_MAGIC);
+        assert_eq!(read_reg(&dev, VirtioMmioRegister::Version), 2);
+        assert_eq!(read_reg(&dev, VirtioMmioRegister::DeviceId), 2);
+        assert_eq!(read_reg(&dev, VirtioMmioRegister::VendorId), VIRTIO_MMIO_VENDOR_ID);
+
+        // VERSION_1 is always offered in the high word
+        assert_eq!(read_reg(&dev, VirtioMmioRegister::DeviceFeatures), 1 << 6);
+        write_reg(&mut dev, VirtioMmioRegister::DeviceFeaturesSel, 1);
+        assert_eq!(read_reg(&dev, VirtioMmioRegister::DeviceFeatures), 1);
+
+        // Registers are 32-bit; config space is passed to the backend
+        assert_eq!(dev.read(VirtioMmioRegister::MagicValue as u64, 8), Err(EmulatorError::InvalidAccess));
+        assert_eq!(dev.read(0x104, 8).unwrap(), 0x1004);
+    }
+
+    #[test]
+    fn test_queue_ready_activates_backend_queue() {
+        let mut dev = transport();
+        write_reg(&mut dev, VirtioMmioRegister::QueueSel, 1);
+        assert_eq!(read_reg(&dev, VirtioMmioRegister::QueueNumMax), 256);
+        write_reg(&mut dev, VirtioMmioRegister::QueueNum, 128);
+        write_reg(&mut dev, VirtioMmioRegister::QueueDescLow, 0x8000_0000);
+        write_reg(&mut dev, VirtioMmioRegister::QueueDescHigh, 0x1);
+        write_reg(&mut dev, VirtioMmioRegister::QueueDriverLow, 0x8000_1000);
+        write_reg(&mut dev, VirtioMmioRegister::QueueDeviceLow, 0x8000_2000);
+        assert!(dev.backend().activated.is_empty());
+
+        write_reg(&mut dev, VirtioMmioRegister::QueueReady, 1);
+        assert!(dev.queue_ready(1));
+        assert!(!dev.queue_ready(0));
+        assert_eq!(read_reg(&dev, VirtioMmioRegister::QueueReady), 1);
+        assert_eq!(dev.backend().activated, [(1, QueueConfig {
+            size: 128,
+            desc_addr: 0x1_8000_0000,
+            driver_addr: 0x8000_1000,
+            device_addr: 0x8000_2000,
+        })]);
+
+        // Notifying the live queue raises the used-ring interrupt
+        write_reg(&mut dev, VirtioMmioRegister::QueueNotify, 1);
+        write_reg(&mut dev, VirtioMmioRegister::QueueNotify, 0);
+        assert_eq!(dev.backend().notified, [1]);
+        assert_eq!(read_reg(&dev, VirtioMmioRegister::InterruptStatus), VIRTIO_MMIO_INT_VRING);
+        write_reg(&mut dev, VirtioMmioRegister::InterruptAck, VIRTIO_MMIO_INT_VRING);
+        assert_eq!(read_reg(&dev, VirtioMmioRegister::InterruptStatus), 0);
+
+        // Writing 0 to Status resets the queues and the backend
+        write_reg(&mut dev, VirtioMmioRegister::Status, 0);
+        assert!(!dev.queue_ready(1));
+        assert_eq!(dev.backend().resets, 1);
+    }
+
+    #[test]
+    fn test_queue_ready_rejects_invalid_size() {
+        let mut dev = transport();
+        write_reg(&mut dev, VirtioMmioRegister::QueueNum, 512);
+        assert_eq!(dev.write(VirtioMmioRegister::QueueReady as u64, 1, 32), Err(EmulatorError::InvalidConfiguration));
+        assert!(!dev.queue_ready(0));
+
+        write_reg(&mut dev, VirtioMmioRegister::QueueSel, 5);
+        assert_eq!(dev.write(VirtioMmioRegister::QueueReady as u64, 1, 32), Err(EmulatorError::InvalidAccess));
+        assert!(dev.backend().activated.is_empty());
+    }
+}
@@ -0,0 +1,262 @@
+//! Device side of a split virtqueue
+//!
+//! The driver owns the descriptor table and the available ring; the
+//! device pops descriptor chains from the available ring and hands them
+//! back through the used ring (VirtIO 1.x spec section 2.7). All rings
+//! live in guest memory, which is reached through a `GuestMemory`.
+//! Indirect descriptors are not supported.
+
+use crate::{Result, Error};
+use crate::core::mm::PhysAddr;
+use crate::core::vmm::VmId;
+use super::QueueConfig;
+use alloc::vec::Vec;
+
+/// Descriptor flag: the chain continues at `next`
+pub const VIRTQ_DESC_F_NEXT: u16 = 1;
+/// Descriptor flag: the buffer is device-writable
+pub const VIRTQ_DESC_F_WRITE: u16 = 2;
+/// Descriptor flag: the buffer holds an indirect descriptor table
+pub const VIRTQ_DESC_F_INDIRECT: u16 = 4;
+
+/// Bytes of a descriptor table entry
+const DESC_SIZE: u64 = 16;
+/// Bytes of a used ring element
+const USED_ELEM_SIZE: u64 = 8;
+
+/// Guest physical memory the rings and buffers live in
+pub trait GuestMemory {
+    /// Read `buf.len()` bytes at `gpa`
+    fn read(&self, gpa: PhysAddr, buf: &mut [u8]) -> Result<()>;
+
+    /// Write `buf` at `gpa`
+    fn write(&self, gpa: PhysAddr, buf: &[u8]) -> Result<()>;
+
+    /// Read a little-endian `u16`
+    fn read_u16(&self, gpa: PhysAddr) -> Result<u16> {
+        let mut bytes = [0; 2];
+        self.read(gpa, &mut bytes)?;
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    /// Read a little-endian `u32`
+    fn read_u32(&self, gpa: PhysAddr) -> Result<u32> {
+        let mut bytes = [0; 4];
+        self.read(gpa, &mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    /// Read a little-endian `u64`
+    fn read_u64(&self, gpa: PhysAddr) -> Result<u64> {
+        let mut bytes = [0; 8];
+        self.read(gpa, &mut bytes)?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+}
+
+/// Memory of a VM, accessed through its stage-2 mappings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VmMemory {
+    /// The VM
+    pub vm_id: VmId,
+}
+
+impl GuestMemory for VmMemory {
+    fn read(&self, gpa: PhysAddr, buf: &mut [u8]) -> Result<()> {
+        crate::core::vmm::vm::read_guest(self.vm_id, gpa, buf)
+    }
+
+    fn write(&self, gpa: PhysAddr, buf: &[u8]) -> Result<()> {
+        crate::core::vmm::vm::write_guest(self.vm_id, gpa, buf)
+    }
+}
+
+/// One buffer of a descriptor chain
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Descriptor {
+    /// Guest physical address of the buffer
+    pub addr: PhysAddr,
+    /// Buffer length
+    pub len: u32,
+    /// Device-writable rather than device-readable
+    pub write: bool,
+}
+
+/// A descriptor chain popped from the available ring
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DescriptorChain {
+    /// Index of the head descriptor, returned in the used ring
+    pub head: u16,
+    /// Buffers in chain order
+    pub descriptors: Vec<Descriptor>,
+}
+
+impl DescriptorChain {
+    /// Device-readable buffers, in chain order
+    pub fn readable(&self) -> impl Iterator<Item = &Descriptor> {
+        self.descriptors.iter().filter(|desc| !desc.write)
+    }
+
+    /// Device-writable buffers, in chain order
+    pub fn writable(&self) -> impl Iterator<Item = &Descriptor> {
+        self.descriptors.iter().filter(|desc| desc.write)
+    }
+}
+
+/// Device-side state of a live virtqueue
+#[derive(Debug, Clone, Copy)]
+pub struct DeviceQueue {
+    /// Setup programmed by the driver
+    config: QueueConfig,
+    /// Next available ring entry to consume
+    last_avail: u16,
+    /// Next used ring entry to fill
+    next_used: u16,
+}
+
+impl DeviceQueue {
+    /// Start servicing a queue set up as `config`
+    pub fn new(config: QueueConfig) -> Self {
+        Self { config, last_avail: 0, next_used: 0 }
+    }
+
+    /// Queue size in descriptors
+    pub fn size(&self) -> u16 {
+        self.config.size
+    }
+
+    /// Pop the next chain the driver made available
+    ///
+    /// Returns `None` once the available ring is drained. A chain that
+    /// loops, runs off the table or uses indirect descriptors fails with
+    /// `Error::InvalidArgument`; it is consumed so the queue can go on.
+    pub fn pop(&mut self, mem: &dyn GuestMemory) -> Result<Option<DescriptorChain>> {
+        let avail_idx = mem.read_u16(self.config.driver_addr + 2)?;
+        if avail_idx == self.last_avail {
+            return Ok(None);
+        }
+        // Read the ring entry only after seeing the index that covers it
+        core::sync::atomic::fence(core::sync::atomic::Ordering::Acquire);
+
+        let slot = (self.last_avail % self.config.size) as u64;
+        let head = mem.read_u16(self.config.driver_addr + 4 + 2 * slot)?;
+        self.last_avail = self.last_avail.wrapping_add(1);
+
+        let mut descriptors = Vec::new();
+        let mut index = head;
+        loop {
+            if index >= self.config.size || descriptors.len() == self.config.size as usize {
+                return Err(Error::InvalidArgument);
+            }
+            let entry = self.config.desc_addr + DESC_SIZE * index as u64;
+            let flags = mem.read_u16(entry + 12)?;
+            if flags & VIRTQ_DESC_F_INDIRECT != 0 {
+                return Err(Error::InvalidArgument);
+            }
+            descriptors.push(Descriptor {
+                addr: mem.read_u64(entry)?,
+                len: mem.read_u32(entry + 8)?,
+                write: flags & VIRTQ_DESC_F_WRITE != 0,
+            });
+            if flags & VIRTQ_DESC_F_NEXT == 0 {
+                break;
+            }
+            index = mem.read_u16(entry + 14)?;
+        }
+
+        Ok(Some(DescriptorChain { head, descriptors }))
+    }
+
+    /// Return chain `head` to the driver, `len` bytes having been written
+    pub fn push_used(&mut self, mem: &dyn GuestMemory, head: u16, len: u32) -> Result<()> {
+        let slot = (self.next_used % self.config.size) as u64;
+        let elem = self.config.device_addr + 4 + USED_ELEM_SIZE * slot;
+        mem.write(elem, &(head as u32).to_le_bytes())?;
+        mem.write(elem + 4, &len.to_le_bytes())?;
+
+        // The driver must see the element before the index that covers it
+        core::sync::atomic::fence(core::sync::atomic::Ordering::Release);
+        self.next_used = self.next_used.wrapping_add(1);
+        mem.write(self.config.device_addr + 2, &self.next_used.to_le_bytes())
+    }
+}
+
+/// Guest memory starting at GPA 0, for device model tests
+#[cfg(test)]
+pub(crate) struct FlatMemory(core::cell::RefCell<Vec<u8>>);
+
+#[cfg(test)]
+impl FlatMemory {
+    /// Queue of `size` descriptors at 0x1000 (table), 0x2000 (available
+    /// ring) and 0x3000 (used ring), in `len` bytes of zeroed memory
+    pub(crate) fn with_queue(len: usize, size: u16) -> (Self, DeviceQueue) {
+        let config = QueueConfig { size, desc_addr: 0x1000, driver_addr: 0x2000, device_addr: 0x3000 };
+        (Self(core::cell::RefCell::new(alloc::vec![0; len])), DeviceQueue::new(config))
+    }
+
+    /// Fill descriptor `index` of the queue made by `with_queue`
+    pub(crate) fn write_desc(&self, index: u16, addr: u64, len: u32, flags: u16, next: u16) {
+        let entry = 0x1000 + DESC_SIZE * index as u64;
+        self.write(entry, &addr.to_le_bytes()).unwrap();
+        self.write(entry + 8, &len.to_le_bytes()).unwrap();
+        self.write(entry + 12, &flags.to_le_bytes()).unwrap();
+        self.write(entry + 14, &next.to_le_bytes()).unwrap();
+    }
+
+    /// Make chain `head` available in slot `slot`, publishing `slot + 1`
+    /// entries
+    pub(crate) fn make_available(&self, slot: u16, head: u16) {
+        self.write(0x2004 + 2 * slot as u64, &head.to_le_bytes()).unwrap();
+        self.write(0x2002, &(slot + 1).to_le_bytes()).unwrap();
+    }
+}
+
+#[cfg(test)]
+impl GuestMemory for FlatMemory {
+    fn read(&self, gpa: PhysAddr, buf: &mut [u8]) -> Result<()> {
+        let mem = self.0.borrow();
+        let src = mem.get(gpa as usize..gpa as usize + buf.len()).ok_or(Error::NotFound)?;
+        buf.copy_from_slice(src);
+        Ok(())
+    }
+
+    fn write(&self, gpa: PhysAddr, buf: &[u8]) -> Result<()> {
+        let mut mem = self.0.borrow_mut();
+        let dst = mem.get_mut(gpa as usize..gpa as usize + buf.len()).ok_or(Error::NotFound)?;
+        dst.copy_from_slice(buf);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pop_chain_and_return_it_used() {
+        let (mem, mut queue) = FlatMemory::with_queue(0x4000, 4);
+        assert_eq!(queue.pop(&mem), Ok(None));
+
+        // Chain 2 -> 0: a request header and a device-writable status byte
+        mem.write_desc(2, 0x8000, 16, VIRTQ_DESC_F_NEXT, 0);
+        mem.write_desc(0, 0x9000, 1, VIRTQ_DESC_F_WRITE, 0);
+        mem.make_available(0, 2);
+
+        let chain = queue.pop(&mem).unwrap().unwrap();
+        assert_eq!(chain.head, 2);
+        assert_eq!(chain.readable().map(|d| (d.addr, d.len)).collect::<Vec<_>>(), [(0x8000, 16)]);
+        assert_eq!(chain.writable().map(|d| (d.addr, d.len)).collect::<Vec<_>>(), [(0x9000, 1)]);
+        assert_eq!(queue.pop(&mem), Ok(None));
+
+        queue.push_used(&mem, chain.head, 1).unwrap();
+        assert_eq!(mem.read_u16(0x3002), Ok(1));
+        assert_eq!(mem.read_u32(0x3004), Ok(2));
+        assert_eq!(mem.read_u32(0x3008), Ok(1));
+
+        // A chain that loops back on itself is rejected and consumed
+        mem.write_desc(1, 0x8000, 16, VIRTQ_DESC_F_NEXT, 1);
+        mem.make_available(1, 1);
+        assert_eq!(queue.pop(&mem), Err(Error::InvalidArgument));
+        assert_eq!(queue.pop(&mem), Ok(None));
+    }
+}
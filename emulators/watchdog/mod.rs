@@ -0,0 +1,427 @@
+//! Watchdog Timer Emulator
+//!
+//! This module provides an ARM SP805-compatible watchdog for guests that
+//! rely on a hardware watchdog to recover from hangs. The counter reloads
+//! from WdogLoad and counts down at the watchdog clock rate:
+//! - on the first expiry the interrupt is raised and the counter reloads
+//! - on a second expiry with the interrupt still pending, the guest is
+//!   reset through the VM reset path
+//!
+//! Any write to WdogIntClr "pets" the watchdog by clearing the interrupt
+//! and reloading the counter.
+//!
+//! The counter is brought up to date on every register access and from
+//! the emulator poll loop, so a hung guest that stops touching the device
+//! still gets reset.
+
+use crate::Result;
+use crate::emulator::{DeviceClass, Emulator, Error as EmulatorError};
+use crate::core::mm::PhysAddr;
+use crate::core::vmm::VmId;
+use crate::core::sync::SpinLock;
+use alloc::boxed::Box;
+
+/// SP805 watchdog registers
+#[allow(dead_code)]
+#[repr(usize)]
+enum Sp805Register {
+    Load = 0x000,
+    Value = 0x004,
+    Control = 0x008,
+    IntClr = 0x00C,
+    Ris = 0x010,
+    Mis = 0x014,
+    Lock = 0xC00,
+}
+
+/// WdogControl: enable counter and interrupt
+const CTRL_INTEN: u32 = 1 << 0;
+/// WdogControl: enable reset output
+const CTRL_RESEN: u32 = 1 << 1;
+
+/// Value written to WdogLock to allow register writes
+const UNLOCK_KEY: u32 = 0x1ACC_E551;
+
+/// Callback invoked when the watchdog resets its guest
+pub type WatchdogResetFn = fn(vm_id: VmId);
+
+/// Callback invoked to raise or lower the watchdog interrupt line
+pub type WatchdogIrqFn = fn(irq: u32, level: bool);
+
+/// SP805 watchdog state
+#[derive(Debug, Clone)]
+pub struct Sp805State {
+    /// Reload value
+    load: u32,
+    /// Current counter value
+    value: u32,
+    /// Control register
+    control: u32,
+    /// Raw interrupt status
+    raw_int: bool,
+    /// Register writes locked
+    locked: bool,
+    /// Number of resets issued
+    reset_count: u32,
+}
+
+impl Sp805State {
+    /// Create the power-on state
+    const fn new() -> Self {
+        Self {
+            load: 0xFFFF_FFFF,
+            value: 0xFFFF_FFFF,
+            control: 0,
+            raw_int: false,
+            locked: false,
+            reset_count: 0,
+        }
+    }
+
+    /// Reload the counter and clear the interrupt
+    fn pet(&mut self) {
+        self.value = self.load;
+        self.raw_int = false;
+    }
+}
+
+/// What an expiry asked the device to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Expiry {
+    /// Raise the interrupt
+    Interrupt,
+    /// Reset the guest
+    Reset,
+}
+
+/// SP805 watchdog emulator
+pub struct Sp805Watchdog {
+    /// Base address
+    base_addr: PhysAddr,
+    /// VM guarded by this watchdog
+    vm_id: VmId,
+    /// Interrupt line
+    irq: u32,
+    /// Watchdog clock in Hz
+    clock_hz: u64,
+    /// Device state
+    state: SpinLock<Sp805State>,
+    /// Timestamp (ns) of the last counter update
+    last_update_ns: SpinLock<u64>,
+    /// Reset action
+    on_reset: WatchdogResetFn,
+    /// Interrupt action
+    on_irq: Option<WatchdogIrqFn>,
+}
+
+impl Sp805Watchdog {
+    /// Create a new SP805 watchdog for `vm_id`
+    pub fn new(base_addr: PhysAddr, vm_id: VmId, irq: u32, clock_hz: u64) -> Self {
+        Self {
+            base_addr,
+            vm_id,
+            irq,
+            clock_hz,
+            state: SpinLock::new(Sp805State::new()),
+            last_update_ns: SpinLock::new(crate::utils::time::timestamp_ns()),
+            on_reset: reset_guest,
+            on_irq: None,
+        }
+    }
+
+    /// Get the base address
+    pub fn base_address(&self) -> PhysAddr {
+        self.base_addr
+    }
+
+    /// Replace the reset action
+    pub fn set_reset_handler(&mut self, handler: WatchdogResetFn) {
+        self.on_reset = handler;
+    }
+
+    /// Set the interrupt action
+    pub fn set_irq_handler(&mut self, handler: WatchdogIrqFn) {
+        self.on_irq = Some(handler);
+    }
+
+    /// Check whether the counter is running
+    pub fn is_enabled(&self) -> bool {
+        self.state.lock().control & CTRL_INTEN != 0
+    }
+
+    /// Get the current counter value
+    pub fn counter(&self) -> u32 {
+        self.state.lock().value
+    }
+
+    /// Get the number of guest resets issued
+    pub fn reset_count(&self) -> u32 {
+        self.state.lock().reset_count
+    }
+
+    /// Pet the watchdog
+    pub fn pet(&self) {
+        self.state.lock().pet();
+        self.set_irq(false);
+    }
+
+    /// Count down `cycles` watchdog clock cycles
+    pub fn advance(&self, mut cycles: u64) {
+        loop {
+            let expiry = {
+                let mut state = self.state.lock();
+                if state.control & CTRL_INTEN == 0 || cycles == 0 {
+                    return;
+                }
+
+                if cycles < state.value as u64 {
+                    state.value -= cycles as u32;
+                    return;
+                }
+
+                // Counter hit zero: reload and act
+                cycles -= state.value as u64;
+                state.value = state.load.max(1);
+
+                if state.raw_int && state.control & CTRL_RESEN != 0 {
+                    state.raw_int = false;
+                    state.reset_count += 1;
+                    Expiry::Reset
+                } else {
+                    state.raw_int = true;
+                    Expiry::Interrupt
+                }
+            };
+
+            match expiry {
+                Expiry::Interrupt => self.set_irq(true),
+                Expiry::Reset => {
+                    crate::warn!("SP805: watchdog expired, resetting VM {}", self.vm_id);
+                    self.set_irq(false);
+                    (self.on_reset)(self.vm_id);
+                }
+            }
+        }
+    }
+
+    /// Advance the counter to the current time
+    pub fn update(&self) {
+        let now = crate::utils::time::timestamp_ns();
+        let elapsed = {
+            let mut last = self.last_update_ns.lock();
+            let elapsed = now.saturating_sub(*last);
+            *last = now;
+            elapsed
+        };
+
+        let cycles = (elapsed as u128 * self.clock_hz as u128 / crate::utils::time::NSEC_PER_SEC as u128) as u64;
+        self.advance(cycles);
+    }
+
+    /// Drive the interrupt line
+    fn set_irq(&self, level: bool) {
+        if let Some(on_irq) = self.on_irq {
+            on_irq(self.irq, level);
+        }
+    }
+}
+
+/// Default reset action: reset the guest through the VMM
+fn reset_guest(vm_id: VmId) {
+    if let Err(e) = crate::core::vmm::reset_vm(vm_id) {
+        crate::error!("SP805: failed to reset VM {}: {:?}", vm_id, e);
+    }
+}
+
+impl Emulator for Sp805Watchdog {
+    fn name(&self) -> &str {
+        "SP805-WDT"
+    }
+
+    fn read(&self, offset: u64, size: u32) -> Result<u64, EmulatorError> {
+        if size != 32 {
+            return Err(EmulatorError::InvalidAccess);
+        }
+
+        self.update();
+
+        let state = self.state.lock();
+        let addr = offset as usize;
+
+        let value = match addr {
+            x if x == Sp805Register::Load as usize => state.load,
+            x if x == Sp805Register::Value as usize => state.value,
+            x if x == Sp805Register::Control as usize => state.control,
+            x if x == Sp805Register::Ris as usize => state.raw_int as u32,
+            x if x == Sp805Register::Mis as usize => {
+                (state.raw_int && state.control & CTRL_INTEN != 0) as u32
+            }
+            x if x == Sp805Register::Lock as usize => state.locked as u32,
+            _ => {
+                crate::warn!("SP805: Unhandled read from offset 0x{:x}", addr);
+                0
+            }
+        };
+
+        Ok(value as u64)
+    }
+
+    fn write(&mut self, offset: u64, value: u64, size: u32) -> Result<(), EmulatorError> {
+        if size != 32 {
+            return Err(EmulatorError::InvalidAccess);
+        }
+
+        self.update();
+
+        let addr = offset as usize;
+        let value = value as u32;
+
+        if addr == Sp805Register::Lock as usize {
+            self.state.lock().locked = value != UNLOCK_KEY;
+            return Ok(());
+        }
+
+        if self.state.lock().locked {
+            // Writes are ignored while locked
+            return Ok(());
+        }
+
+        match addr {
+            x if x == Sp805Register::Load as usize => {
+                let mut state = self.state.lock();
+                state.load = value;
+                state.value = value;
+            }
+            x if x == Sp805Register::Control as usize => {
+                let mut state = self.state.lock();
+                let was_enabled = state.control & CTRL_INTEN != 0;
+                state.control = value & (CTRL_INTEN | CTRL_RESEN);
+                if !was_enabled && state.control & CTRL_INTEN != 0 {
+                    // Enabling the counter reloads it
+                    state.value = state.load;
+                }
+            }
+            x if x == Sp805Register::IntClr as usize => self.pet(),
+            _ => {
+                crate::warn!("SP805: Unhandled write 0x{:x} to offset 0x{:x}", value, addr);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Result<(), EmulatorError> {
+        let reset_count = self.state.lock().reset_count;
+        *self.state.lock() = Sp805State {
+            reset_count,
+            ..Sp805State::new()
+        };
+        *self.last_update_ns.lock() = crate::utils::time::timestamp_ns();
+        self.set_irq(false);
+
+        Ok(())
+    }
+
+    fn poll(&mut self) {
+        self.update();
+    }
+}
+
+/// Size of the SP805 register window
+pub const SP805_SIZE: u64 = 0x1000;
+
+/// Give a VM an SP805 watchdog at the QEMU virt-style location, 1MHz clock
+pub fn attach(vm_id: VmId) -> Result<(), crate::Error> {
+    let sp805 = Sp805Watchdog::new(0x9030000, vm_id, 32, 1_000_000);
+    let base = sp805.base_address();
+    crate::emulator::register_mmio_emulator(vm_id, "wdt-sp805", DeviceClass::Watchdog, base, SP805_SIZE,
+                                            Box::new(sp805))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicU32, Ordering};
+
+    fn armed(reset: WatchdogResetFn) -> Sp805Watchdog {
+        let mut wdt = Sp805Watchdog::new(0x9030000, 7, 32, 1_000_000);
+        wdt.set_reset_handler(reset);
+        wdt.write(Sp805Register::Load as u64, 1000, 32).unwrap();
+        wdt.write(Sp805Register::Control as u64, (CTRL_INTEN | CTRL_RESEN) as u64, 32).unwrap();
+        wdt
+    }
+
+    #[test]
+    fn test_timeout_without_pet_resets_guest() {
+        static RESETS: AtomicU32 = AtomicU32::new(0);
+        static RESET_VM: AtomicU32 = AtomicU32::new(0);
+        fn on_reset(vm_id: VmId) {
+            RESETS.fetch_add(1, Ordering::SeqCst);
+            RESET_VM.store(vm_id, Ordering::SeqCst);
+        }
+
+        let wdt = armed(on_reset);
+
+        // First expiry only raises the interrupt
+        wdt.advance(1000);
+        assert_eq!(RESETS.load(Ordering::SeqCst), 0);
+        assert_eq!(wdt.read(Sp805Register::Ris as u64, 32).unwrap() & 1, 1);
+
+        // Second expiry with the interrupt unserviced resets the guest
+        wdt.advance(1000);
+        assert_eq!(RESETS.load(Ordering::SeqCst), 1);
+        assert_eq!(RESET_VM.load(Ordering::SeqCst), 7);
+        assert_eq!(wdt.reset_count(), 1);
+    }
+
+    #[test]
+    fn test_poll_expires_without_guest_access() {
+        static RESETS: AtomicU32 = AtomicU32::new(0);
+        fn on_reset(_vm_id: VmId) {
+            RESETS.fetch_add(1, Ordering::SeqCst);
+        }
+
+        let mut wdt = armed(on_reset);
+
+        // 2.5ms at 1MHz covers both expiries; only the poll loop runs
+        *wdt.last_update_ns.lock() -= 2_500_000;
+        wdt.poll();
+
+        assert_eq!(RESETS.load(Ordering::SeqCst), 1);
+        assert_eq!(wdt.reset_count(), 1);
+    }
+
+    #[test]
+    fn test_pet_reloads_counter() {
+        static RESETS: AtomicU32 = AtomicU32::new(0);
+        fn on_reset(_vm_id: VmId) {
+            RESETS.fetch_add(1, Ordering::SeqCst);
+        }
+
+        let mut wdt = armed(on_reset);
+
+        for _ in 0..10 {
+            wdt.advance(900);
+            assert!(wdt.counter() <= 100);
+            wdt.write(Sp805Register::IntClr as u64, 1, 32).unwrap();
+            assert_eq!(wdt.counter(), 1000);
+        }
+
+        assert_eq!(RESETS.load(Ordering::SeqCst), 0);
+        assert_eq!(wdt.read(Sp805Register::Ris as u64, 32).unwrap() & 1, 0);
+    }
+
+    #[test]
+    fn test_lock_blocks_writes() {
+        fn on_reset(_vm_id: VmId) {}
+
+        let mut wdt = armed(on_reset);
+        wdt.write(Sp805Register::Lock as u64, 0, 32).unwrap();
+        wdt.write(Sp805Register::Control as u64, 0, 32).unwrap();
+        assert!(wdt.is_enabled());
+
+        wdt.write(Sp805Register::Lock as u64, UNLOCK_KEY as u64, 32).unwrap();
+        wdt.write(Sp805Register::Control as u64, 0, 32).unwrap();
+        assert!(!wdt.is_enabled());
+    }
+}
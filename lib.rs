@@ -7,6 +7,7 @@
 #![no_main]
 #![feature(lang_args)]
 #![feature(offset_of)]
+#![cfg_attr(feature = "debug", feature(const_caller_location))]
 
 extern crate alloc;
 use ::core::ptr;
@@ -24,18 +25,23 @@ use alloc::alloc::{GlobalAlloc, Layout};
 // Global allocator using our unified allocator
 struct FerrovisorAllocator;
 
+impl FerrovisorAllocator {
+    /// Configuration for heap allocations with `layout`
+    fn config(layout: Layout) -> crate::core::mm::allocator::AllocationConfig {
+        crate::core::mm::allocator::AllocationConfig {
+            strategy: crate::core::mm::allocator::AllocationStrategy::Auto,
+            alignment: layout.align(),
+            zero: false,
+            reclaimable: true,
+            tag: "global_alloc",
+            owner: None,
+        }
+    }
+}
+
 unsafe impl GlobalAlloc for FerrovisorAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        match crate::core::mm::allocator::allocate_with_config(
-            layout.size(),
-            crate::core::mm::allocator::AllocationConfig {
-                strategy: crate::core::mm::allocator::AllocationStrategy::Auto,
-                alignment: layout.align(),
-                zero: false,
-                reclaimable: true,
-                tag: "global_alloc",
-            }
-        ) {
+        match crate::core::mm::allocator::allocate_with_config(layout.size(), Self::config(layout)) {
             Ok(ptr) => ptr.as_ptr(),
             Err(_) => ptr::null_mut(),
         }
@@ -43,11 +49,9 @@ unsafe impl GlobalAlloc for FerrovisorAllocator {
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         if let Some(ptr) = NonNull::new(ptr) {
-            let _ = crate::core::mm::allocator::deallocate(
-                ptr,
-                layout.size(),
-                crate::core::mm::allocator::AllocationStrategy::Auto
-            );
+            // Released with the allocation's config so reclaimable pages
+            // are scrubbed before reuse
+            let _ = crate::core::mm::allocator::release_with_config(ptr, layout.size(), &Self::config(layout));
         }
     }
 }
@@ -71,6 +75,7 @@ pub mod drivers;
 
 // Device emulators
 pub mod emulator;
+pub mod emulators;
 
 // Common libraries
 pub mod libs;
@@ -87,6 +92,10 @@ pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 /// Ferrovisor initialization
 pub fn init() -> Result<(), Error> {
+    // Annotate panic backtraces with offsets into the image text
+    #[cfg(target_os = "none")]
+    utils::backtrace::set_symbol_resolver(utils::backtrace::image_text_resolver);
+
     // Initialize architecture-specific code
     arch::init()?;
 
@@ -99,6 +108,9 @@ pub fn init() -> Result<(), Error> {
     // Initialize emulators
     emulator::init()?;
 
+    // Check that devices and emulators claimed disjoint resources
+    config::validate()?;
+
     // log::info!("Ferrovisor v{} initialized successfully", VERSION);
 
     Ok(())
@@ -143,6 +155,8 @@ pub enum Error {
     DriverError(drivers::Error),
 }
 
+// RISC-V maps its arch errors onto the generic variants, see arch::riscv64::error
+#[cfg(not(target_arch = "riscv64"))]
 impl From<arch::Error> for Error {
     fn from(err: arch::Error) -> Self {
         Error::ArchError(err)
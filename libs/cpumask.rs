@@ -0,0 +1,197 @@
+//! CPU masks
+//!
+//! `CpuMask` is a set of physical CPU IDs below `MAX_CPUS`, one bit per
+//! CPU. It is shared by the architecture SMP code, the scheduler and the
+//! interrupt affinity code, so none of them need ad-hoc bit loops over raw
+//! integers. IDs at or above `MAX_CPUS` are never members: setting them
+//! has no effect.
+
+/// Maximum number of CPUs a mask can hold
+pub const MAX_CPUS: usize = 64;
+
+/// Bit of `cpu`, or 0 if it is out of range
+fn cpu_bit(cpu: u32) -> u64 {
+    1u64.checked_shl(cpu).unwrap_or(0)
+}
+
+/// Set of CPUs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CpuMask {
+    bits: u64,
+}
+
+impl CpuMask {
+    /// Create an empty CPU mask
+    pub const fn new() -> Self {
+        Self { bits: 0 }
+    }
+
+    /// Create a CPU mask with all bits set
+    pub const fn all() -> Self {
+        Self { bits: u64::MAX }
+    }
+
+    /// Create a CPU mask from a bit pattern
+    pub const fn from_bits(bits: u64) -> Self {
+        Self { bits }
+    }
+
+    /// Create a CPU mask for a single CPU
+    pub fn from_cpu(cpu: u32) -> Self {
+        Self { bits: cpu_bit(cpu) }
+    }
+
+    /// Get the underlying bits
+    pub const fn bits(&self) -> u64 {
+        self.bits
+    }
+
+    /// Check if a CPU is set in the mask
+    pub fn contains(&self, cpu: u32) -> bool {
+        self.bits & cpu_bit(cpu) != 0
+    }
+
+    /// Set a CPU in the mask
+    pub fn set(&mut self, cpu: u32) {
+        self.bits |= cpu_bit(cpu);
+    }
+
+    /// Clear a CPU in the mask
+    pub fn clear(&mut self, cpu: u32) {
+        self.bits &= !cpu_bit(cpu);
+    }
+
+    /// Check if the mask is empty
+    pub fn is_empty(&self) -> bool {
+        self.bits == 0
+    }
+
+    /// Check if the mask has all CPUs
+    pub fn is_all(&self) -> bool {
+        self.bits == u64::MAX
+    }
+
+    /// Count the number of CPUs in the mask
+    pub fn count(&self) -> u32 {
+        self.bits.count_ones()
+    }
+
+    /// Get the first CPU in the mask
+    pub fn first(&self) -> Option<u32> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(self.bits.trailing_zeros())
+        }
+    }
+
+    /// Get a random CPU from the mask
+    pub fn random(&self) -> Option<u32> {
+        if self.is_empty() {
+            return None;
+        }
+        let index = crate::utils::random::u32() % self.count();
+        self.iter().nth(index as usize)
+    }
+
+    /// CPUs in either mask
+    pub fn union(&self, other: &CpuMask) -> CpuMask {
+        CpuMask::from_bits(self.bits | other.bits)
+    }
+
+    /// CPUs in both masks
+    pub fn intersection(&self, other: &CpuMask) -> CpuMask {
+        CpuMask::from_bits(self.bits & other.bits)
+    }
+
+    /// CPUs in this mask but not in `other`
+    pub fn difference(&self, other: &CpuMask) -> CpuMask {
+        CpuMask::from_bits(self.bits & !other.bits)
+    }
+
+    /// CPUs not in the mask
+    pub fn complement(&self) -> CpuMask {
+        CpuMask::from_bits(!self.bits)
+    }
+
+    /// Iterate over CPUs in the mask, lowest ID first
+    pub fn iter(&self) -> CpuIter {
+        CpuIter { bits: self.bits }
+    }
+}
+
+impl FromIterator<u32> for CpuMask {
+    fn from_iter<I: IntoIterator<Item = u32>>(cpus: I) -> Self {
+        let mut mask = CpuMask::new();
+        for cpu in cpus {
+            mask.set(cpu);
+        }
+        mask
+    }
+}
+
+/// Iterator over CPUs in a mask
+pub struct CpuIter {
+    bits: u64,
+}
+
+impl Iterator for CpuIter {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.bits == 0 {
+            None
+        } else {
+            let cpu = self.bits.trailing_zeros();
+            self.bits &= self.bits - 1; // Clear the lowest set bit
+            Some(cpu)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_iteration_is_in_cpu_order() {
+        let mask: CpuMask = [63, 5, 0, 17].into_iter().collect();
+        assert_eq!(mask.iter().collect::<Vec<_>>(), [0, 5, 17, 63]);
+        assert_eq!(mask.count(), 4);
+        assert_eq!(mask.first(), Some(0));
+
+        assert_eq!(CpuMask::new().iter().next(), None);
+        assert_eq!(CpuMask::all().count(), MAX_CPUS as u32);
+    }
+
+    #[test]
+    fn test_set_and_clear() {
+        let mut mask = CpuMask::new();
+        mask.set(3);
+        mask.set(3);
+        assert!(mask.contains(3));
+        assert_eq!(mask.count(), 1);
+
+        mask.clear(3);
+        assert!(mask.is_empty());
+
+        // Out-of-range CPUs are never members
+        mask.set(MAX_CPUS as u32);
+        assert!(mask.is_empty());
+        assert!(!CpuMask::all().contains(MAX_CPUS as u32));
+        assert!(CpuMask::from_cpu(100).is_empty());
+    }
+
+    #[test]
+    fn test_set_algebra() {
+        let a = CpuMask::from_bits(0b1100);
+        let b = CpuMask::from_bits(0b1010);
+        assert_eq!(a.union(&b), CpuMask::from_bits(0b1110));
+        assert_eq!(a.intersection(&b), CpuMask::from_bits(0b1000));
+        assert_eq!(a.difference(&b), CpuMask::from_bits(0b0100));
+        assert_eq!(a.complement().intersection(&CpuMask::from_bits(0xF)), CpuMask::from_bits(0b0011));
+        assert!(a.intersection(&a.complement()).is_empty());
+        assert!(a.union(&a.complement()).is_all());
+    }
+}
@@ -0,0 +1,763 @@
+//! Architecture-neutral Flattened Device Tree (FDT) parser
+//!
+//! Provides a zero-copy, read-only view over a flattened device tree blob
+//! as handed over by firmware or the bootloader. The parser walks the
+//! structure block directly and resolves property names through the
+//! strings block, so no allocation is needed to iterate nodes or decode
+//! properties.
+//!
+//! Supported operations:
+//! - Header validation
+//! - Node iteration (depth-first, in blob order)
+//! - Lookup by path and by `compatible` string
+//! - `reg` decoding using the parent's `#address-cells`/`#size-cells`
+//! - `interrupts` cell decoding
+
+use alloc::vec::Vec;
+
+/// FDT magic number
+pub const FDT_MAGIC: u32 = 0xd00d_feed;
+
+/// Oldest FDT version this parser understands
+pub const FDT_MIN_VERSION: u32 = 16;
+
+/// Default `#address-cells` when a node does not specify one
+pub const DEFAULT_ADDRESS_CELLS: u32 = 2;
+
+/// Default `#size-cells` when a node does not specify one
+pub const DEFAULT_SIZE_CELLS: u32 = 1;
+
+/// Maximum supported node nesting depth
+pub const MAX_DEPTH: usize = 16;
+
+/// Size of the FDT header in bytes
+const HEADER_SIZE: usize = 40;
+
+/// Structure block tokens
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+const FDT_END: u32 = 0x9;
+
+/// FDT parsing errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FdtError {
+    /// Blob is smaller than its header or declared size
+    Truncated,
+    /// Magic number mismatch
+    BadMagic,
+    /// Unsupported FDT version
+    BadVersion,
+    /// Structure or strings block lies outside the blob
+    BadLayout,
+    /// Unknown token in the structure block
+    BadToken,
+    /// Node or property name is not valid UTF-8
+    BadString,
+    /// Nodes nested deeper than `MAX_DEPTH`
+    TooDeep,
+}
+
+impl From<FdtError> for crate::Error {
+    fn from(_err: FdtError) -> Self {
+        crate::Error::InvalidArgument
+    }
+}
+
+/// Read a big-endian u32 at `offset`
+fn be_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Round an offset up to the next token boundary
+const fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+/// Combine `cells` big-endian u32 cells into a u64
+fn read_cells(data: &[u8], cells: u32) -> Option<u64> {
+    let mut value = 0u64;
+    for i in 0..cells as usize {
+        value = (value << 32) | be_u32(data, i * 4)? as u64;
+    }
+    Some(value)
+}
+
+/// Read a NUL-terminated string starting at `offset`
+fn c_str(data: &[u8], offset: usize) -> Result<&str, FdtError> {
+    let tail = data.get(offset..).ok_or(FdtError::Truncated)?;
+    let len = tail.iter().position(|&b| b == 0).ok_or(FdtError::Truncated)?;
+    core::str::from_utf8(&tail[..len]).map_err(|_| FdtError::BadString)
+}
+
+/// FDT header (all fields host-endian after decoding)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FdtHeader {
+    /// Magic number
+    pub magic: u32,
+    /// Total size of the blob
+    pub totalsize: u32,
+    /// Offset of the structure block
+    pub off_dt_struct: u32,
+    /// Offset of the strings block
+    pub off_dt_strings: u32,
+    /// Offset of the memory reservation map
+    pub off_mem_rsvmap: u32,
+    /// Format version
+    pub version: u32,
+    /// Last compatible format version
+    pub last_comp_version: u32,
+    /// Physical ID of the boot CPU
+    pub boot_cpuid_phys: u32,
+    /// Size of the strings block
+    pub size_dt_strings: u32,
+    /// Size of the structure block
+    pub size_dt_struct: u32,
+}
+
+impl FdtHeader {
+    /// Decode a header from the start of a blob
+    pub fn parse(data: &[u8]) -> Result<Self, FdtError> {
+        if data.len() < HEADER_SIZE {
+            return Err(FdtError::Truncated);
+        }
+
+        let field = |index: usize| be_u32(data, index * 4).unwrap_or(0);
+        let header = Self {
+            magic: field(0),
+            totalsize: field(1),
+            off_dt_struct: field(2),
+            off_dt_strings: field(3),
+            off_mem_rsvmap: field(4),
+            version: field(5),
+            last_comp_version: field(6),
+            boot_cpuid_phys: field(7),
+            size_dt_strings: field(8),
+            size_dt_struct: field(9),
+        };
+
+        if header.magic != FDT_MAGIC {
+            return Err(FdtError::BadMagic);
+        }
+        if header.version < FDT_MIN_VERSION {
+            return Err(FdtError::BadVersion);
+        }
+
+        let total = header.totalsize as usize;
+        if total < HEADER_SIZE {
+            return Err(FdtError::BadLayout);
+        }
+        let struct_end = header.off_dt_struct as usize + header.size_dt_struct as usize;
+        let strings_end = header.off_dt_strings as usize + header.size_dt_strings as usize;
+        if struct_end > total || strings_end > total {
+            return Err(FdtError::BadLayout);
+        }
+
+        Ok(header)
+    }
+}
+
+/// A `(address, size)` pair decoded from a `reg` property
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FdtRegion {
+    /// Base address
+    pub address: u64,
+    /// Region size
+    pub size: u64,
+}
+
+/// Read-only view over a flattened device tree blob
+#[derive(Debug, Clone, Copy)]
+pub struct Fdt<'a> {
+    /// Whole blob, trimmed to `totalsize`
+    data: &'a [u8],
+    /// Decoded header
+    header: FdtHeader,
+}
+
+impl<'a> Fdt<'a> {
+    /// Parse a device tree blob
+    pub fn from_bytes(data: &'a [u8]) -> Result<Self, FdtError> {
+        let header = FdtHeader::parse(data)?;
+        let total = header.totalsize as usize;
+        if data.len() < total {
+            return Err(FdtError::Truncated);
+        }
+
+        Ok(Self {
+            data: &data[..total],
+            header,
+        })
+    }
+
+    /// Parse a device tree blob located in memory
+    ///
+    /// # Safety
+    /// `addr` must point to a readable FDT blob that stays mapped and
+    /// unmodified for the lifetime `'a`.
+    pub unsafe fn from_addr(addr: usize) -> Result<Self, FdtError> {
+        let header = core::slice::from_raw_parts(addr as *const u8, HEADER_SIZE);
+        let total = FdtHeader::parse(header)?.totalsize as usize;
+        Self::from_bytes(core::slice::from_raw_parts(addr as *const u8, total))
+    }
+
+    /// Get the decoded header
+    pub fn header(&self) -> &FdtHeader {
+        &self.header
+    }
+
+    /// Get the total size of the blob
+    pub fn total_size(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Get the structure block
+    fn struct_block(&self) -> &'a [u8] {
+        let start = self.header.off_dt_struct as usize;
+        &self.data[start..start + self.header.size_dt_struct as usize]
+    }
+
+    /// Look up a name in the strings block
+    fn string_at(&self, offset: u32) -> Result<&'a str, FdtError> {
+        let start = self.header.off_dt_strings as usize;
+        let strings = &self.data[start..start + self.header.size_dt_strings as usize];
+        c_str(strings, offset as usize)
+    }
+
+    /// Iterate over the memory reservation map
+    pub fn reserved_memory(&self) -> impl Iterator<Item = FdtRegion> + 'a {
+        let data = self.data;
+        let mut offset = self.header.off_mem_rsvmap as usize;
+        core::iter::from_fn(move || {
+            let address = read_cells(data.get(offset..)?, 2)?;
+            let size = read_cells(data.get(offset + 8..)?, 2)?;
+            if address == 0 && size == 0 {
+                return None;
+            }
+            offset += 16;
+            Some(FdtRegion { address, size })
+        })
+    }
+
+    /// Iterate over all nodes in blob order, starting with the root
+    pub fn nodes(&self) -> FdtNodeIter<'a> {
+        FdtNodeIter {
+            fdt: *self,
+            offset: 0,
+            depth: 0,
+            cells: [(DEFAULT_ADDRESS_CELLS, DEFAULT_SIZE_CELLS); MAX_DEPTH],
+            done: false,
+        }
+    }
+
+    /// Get the root node
+    pub fn root(&self) -> Option<FdtNode<'a>> {
+        self.nodes().next()
+    }
+
+    /// Find a node by absolute path (e.g. `/cpus/cpu@0`)
+    ///
+    /// A path component without a unit address matches a node whose
+    /// name only differs by its `@unit` suffix.
+    pub fn find_node(&self, path: &str) -> Option<FdtNode<'a>> {
+        let components: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+        if components.is_empty() {
+            return self.root();
+        }
+
+        // Number of leading path components matched by the current branch
+        let mut matched = 0usize;
+        for node in self.nodes() {
+            if node.depth == 0 {
+                continue;
+            }
+
+            matched = matched.min(node.depth - 1);
+            if matched == node.depth - 1
+                && matched < components.len()
+                && node.matches_name(components[matched])
+            {
+                matched = node.depth;
+                if matched == components.len() {
+                    return Some(node);
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Find the first node compatible with `compatible`
+    pub fn find_compatible(&self, compatible: &str) -> Option<FdtNode<'a>> {
+        self.nodes().find(|node| node.is_compatible(compatible))
+    }
+
+    /// Find all nodes compatible with `compatible`
+    pub fn find_all_compatible(&self, compatible: &str) -> Vec<FdtNode<'a>> {
+        self.nodes().filter(|node| node.is_compatible(compatible)).collect()
+    }
+
+    /// Collect RAM regions from all `memory` nodes
+    pub fn memory_regions(&self) -> Vec<FdtRegion> {
+        self.nodes()
+            .filter(|node| {
+                node.depth == 1
+                    && (node.device_type() == Some("memory") || node.unit_name() == "memory")
+            })
+            .flat_map(|node| node.reg())
+            .collect()
+    }
+
+    /// Collect all CPU nodes under `/cpus`
+    pub fn cpus(&self) -> Vec<FdtNode<'a>> {
+        self.nodes()
+            .filter(|node| node.depth == 2 && node.device_type() == Some("cpu"))
+            .collect()
+    }
+}
+
+/// A node in the device tree
+#[derive(Debug, Clone, Copy)]
+pub struct FdtNode<'a> {
+    /// Owning tree
+    fdt: Fdt<'a>,
+    /// Node name including unit address (empty for the root)
+    name: &'a str,
+    /// Nesting depth (0 for the root)
+    depth: usize,
+    /// Structure-block offset of the first property token
+    props_offset: usize,
+    /// `#address-cells` inherited from the parent
+    parent_address_cells: u32,
+    /// `#size-cells` inherited from the parent
+    parent_size_cells: u32,
+}
+
+impl<'a> FdtNode<'a> {
+    /// Get the full node name, e.g. `serial@10000000`
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// Get the node name without unit address, e.g. `serial`
+    pub fn unit_name(&self) -> &'a str {
+        self.name.split('@').next().unwrap_or(self.name)
+    }
+
+    /// Get the unit address, if the name has one
+    pub fn unit_address(&self) -> Option<u64> {
+        let (_, addr) = self.name.split_once('@')?;
+        u64::from_str_radix(addr, 16).ok()
+    }
+
+    /// Get the nesting depth (0 for the root)
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Check a path component against this node's name
+    fn matches_name(&self, component: &str) -> bool {
+        self.name == component || (!component.contains('@') && self.unit_name() == component)
+    }
+
+    /// Iterate over this node's properties
+    pub fn properties(&self) -> FdtPropertyIter<'a> {
+        FdtPropertyIter {
+            fdt: self.fdt,
+            offset: self.props_offset,
+        }
+    }
+
+    /// Find a property by name
+    pub fn property(&self, name: &str) -> Option<FdtProperty<'a>> {
+        self.properties().find(|prop| prop.name == name)
+    }
+
+    /// Iterate over the strings of the `compatible` property
+    pub fn compatible(&self) -> impl Iterator<Item = &'a str> {
+        self.property("compatible")
+            .into_iter()
+            .flat_map(|prop| prop.as_str_list())
+    }
+
+    /// Check if the node is compatible with `compatible`
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        self.compatible().any(|c| c == compatible)
+    }
+
+    /// Get the `device_type` property
+    pub fn device_type(&self) -> Option<&'a str> {
+        self.property("device_type")?.as_str()
+    }
+
+    /// Get the `#address-cells` this node declares for its children
+    pub fn address_cells(&self) -> u32 {
+        self.property("#address-cells")
+            .and_then(|prop| prop.as_u32())
+            .unwrap_or(DEFAULT_ADDRESS_CELLS)
+    }
+
+    /// Get the `#size-cells` this node declares for its children
+    pub fn size_cells(&self) -> u32 {
+        self.property("#size-cells")
+            .and_then(|prop| prop.as_u32())
+            .unwrap_or(DEFAULT_SIZE_CELLS)
+    }
+
+    /// Decode the `reg` property using the parent's cell sizes
+    pub fn reg(&self) -> FdtRegIter<'a> {
+        let value = self.property("reg").map_or(&[][..], |prop| prop.value);
+        FdtRegIter {
+            value,
+            address_cells: self.parent_address_cells,
+            size_cells: self.parent_size_cells,
+        }
+    }
+
+    /// Get the first `reg` entry
+    pub fn first_reg(&self) -> Option<FdtRegion> {
+        self.reg().next()
+    }
+
+    /// Iterate over the raw cells of the `interrupts` property
+    pub fn interrupts(&self) -> impl Iterator<Item = u32> + 'a {
+        self.property("interrupts")
+            .into_iter()
+            .flat_map(|prop| prop.as_u32_cells())
+    }
+}
+
+/// Depth-first iterator over device tree nodes
+pub struct FdtNodeIter<'a> {
+    /// Owning tree
+    fdt: Fdt<'a>,
+    /// Current structure-block offset
+    offset: usize,
+    /// Depth of the next node to be returned
+    depth: usize,
+    /// Child cell sizes declared by the open node at each depth
+    cells: [(u32, u32); MAX_DEPTH],
+    /// Set once `FDT_END` or a malformed token is reached
+    done: bool,
+}
+
+impl<'a> FdtNodeIter<'a> {
+    /// Advance to the next node
+    fn next_node(&mut self) -> Result<Option<FdtNode<'a>>, FdtError> {
+        let block = self.fdt.struct_block();
+
+        loop {
+            let token = be_u32(block, self.offset).ok_or(FdtError::Truncated)?;
+            self.offset += 4;
+
+            match token {
+                FDT_BEGIN_NODE => {
+                    let name = c_str(block, self.offset)?;
+                    self.offset = align4(self.offset + name.len() + 1);
+
+                    if self.depth >= MAX_DEPTH {
+                        return Err(FdtError::TooDeep);
+                    }
+
+                    let (parent_address_cells, parent_size_cells) = if self.depth == 0 {
+                        (DEFAULT_ADDRESS_CELLS, DEFAULT_SIZE_CELLS)
+                    } else {
+                        self.cells[self.depth - 1]
+                    };
+
+                    let node = FdtNode {
+                        fdt: self.fdt,
+                        name,
+                        depth: self.depth,
+                        props_offset: self.offset,
+                        parent_address_cells,
+                        parent_size_cells,
+                    };
+                    self.cells[self.depth] = (node.address_cells(), node.size_cells());
+                    self.depth += 1;
+
+                    return Ok(Some(node));
+                }
+                FDT_END_NODE => {
+                    self.depth = self.depth.checked_sub(1).ok_or(FdtError::BadToken)?;
+                }
+                FDT_PROP => {
+                    let len = be_u32(block, self.offset).ok_or(FdtError::Truncated)? as usize;
+                    self.offset = align4(self.offset + 8 + len);
+                }
+                FDT_NOP => {}
+                FDT_END => return Ok(None),
+                _ => return Err(FdtError::BadToken),
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for FdtNodeIter<'a> {
+    type Item = FdtNode<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.next_node() {
+            Ok(Some(node)) => Some(node),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                log::warn!("FDT: stopping node iteration: {:?}", e);
+                self.done = true;
+                None
+            }
+        }
+    }
+}
+
+/// A property of a device tree node
+#[derive(Debug, Clone, Copy)]
+pub struct FdtProperty<'a> {
+    /// Property name
+    pub name: &'a str,
+    /// Raw property value
+    pub value: &'a [u8],
+}
+
+impl<'a> FdtProperty<'a> {
+    /// Decode as a single u32 cell
+    pub fn as_u32(&self) -> Option<u32> {
+        if self.value.len() != 4 {
+            return None;
+        }
+        be_u32(self.value, 0)
+    }
+
+    /// Decode as a u64 (one or two cells)
+    pub fn as_u64(&self) -> Option<u64> {
+        match self.value.len() {
+            4 => read_cells(self.value, 1),
+            8 => read_cells(self.value, 2),
+            _ => None,
+        }
+    }
+
+    /// Decode as a single NUL-terminated string
+    pub fn as_str(&self) -> Option<&'a str> {
+        self.as_str_list().next()
+    }
+
+    /// Iterate over a NUL-separated string list
+    pub fn as_str_list(&self) -> impl Iterator<Item = &'a str> {
+        self.value
+            .split(|&b| b == 0)
+            .filter(|s| !s.is_empty())
+            .filter_map(|s| core::str::from_utf8(s).ok())
+    }
+
+    /// Iterate over the value as big-endian u32 cells
+    pub fn as_u32_cells(&self) -> impl Iterator<Item = u32> + 'a {
+        let value = self.value;
+        (0..value.len() / 4).filter_map(move |i| be_u32(value, i * 4))
+    }
+}
+
+/// Iterator over the properties of a node
+pub struct FdtPropertyIter<'a> {
+    /// Owning tree
+    fdt: Fdt<'a>,
+    /// Current structure-block offset
+    offset: usize,
+}
+
+impl<'a> Iterator for FdtPropertyIter<'a> {
+    type Item = FdtProperty<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let block = self.fdt.struct_block();
+
+        loop {
+            match be_u32(block, self.offset)? {
+                FDT_NOP => self.offset += 4,
+                FDT_PROP => {
+                    let len = be_u32(block, self.offset + 4)? as usize;
+                    let name_off = be_u32(block, self.offset + 8)?;
+                    let start = self.offset + 12;
+                    let value = block.get(start..start + len)?;
+                    self.offset = align4(start + len);
+
+                    let name = self.fdt.string_at(name_off).ok()?;
+                    return Some(FdtProperty { name, value });
+                }
+                // Child node, end of node or end of tree
+                _ => return None,
+            }
+        }
+    }
+}
+
+/// Iterator over `reg` entries
+pub struct FdtRegIter<'a> {
+    /// Remaining raw `reg` value
+    value: &'a [u8],
+    /// Cells per address
+    address_cells: u32,
+    /// Cells per size
+    size_cells: u32,
+}
+
+impl<'a> Iterator for FdtRegIter<'a> {
+    type Item = FdtRegion;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry_len = (self.address_cells + self.size_cells) as usize * 4;
+        if entry_len == 0 || self.value.len() < entry_len {
+            return None;
+        }
+
+        let address = read_cells(self.value, self.address_cells)?;
+        let size = read_cells(&self.value[self.address_cells as usize * 4..], self.size_cells)?;
+        self.value = &self.value[entry_len..];
+
+        Some(FdtRegion { address, size })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Small tree: two CPUs, one memory node and a UART under `/soc`
+    ///
+    /// ```text
+    /// / {
+    ///     #address-cells = <2>; #size-cells = <2>;
+    ///     compatible = "test,fixture";
+    ///     cpus {
+    ///         #address-cells = <1>; #size-cells = <0>;
+    ///         timebase-frequency = <10000000>;
+    ///         cpu@0 { device_type = "cpu"; reg = <0>; };
+    ///         cpu@1 { device_type = "cpu"; reg = <1>; };
+    ///     };
+    ///     memory@80000000 {
+    ///         device_type = "memory";
+    ///         reg = <0x0 0x80000000 0x0 0x8000000>;
+    ///     };
+    ///     soc {
+    ///         #address-cells = <2>; #size-cells = <2>;
+    ///         compatible = "simple-bus";
+    ///         serial@10000000 {
+    ///             compatible = "ns16550a";
+    ///             reg = <0x0 0x10000000 0x0 0x100>;
+    ///             interrupts = <10>;
+    ///         };
+    ///     };
+    /// };
+    /// ```
+    static FIXTURE_DTB: [u8; 608] = [
+        0xd0, 0x0d, 0xfe, 0xed, 0x00, 0x00, 0x02, 0x60, 0x00, 0x00, 0x00, 0x38, 0x00, 0x00, 0x02, 0x0c,
+        0x00, 0x00, 0x00, 0x28, 0x00, 0x00, 0x00, 0x11, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x54, 0x00, 0x00, 0x01, 0xd4, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02,
+        0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x0f, 0x00, 0x00, 0x00, 0x02,
+        0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x0d, 0x00, 0x00, 0x00, 0x1b, 0x74, 0x65, 0x73, 0x74,
+        0x2c, 0x66, 0x69, 0x78, 0x74, 0x75, 0x72, 0x65, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+        0x63, 0x70, 0x75, 0x73, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x04,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x04,
+        0x00, 0x00, 0x00, 0x0f, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x04,
+        0x00, 0x00, 0x00, 0x26, 0x00, 0x98, 0x96, 0x80, 0x00, 0x00, 0x00, 0x01, 0x63, 0x70, 0x75, 0x40,
+        0x30, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x39,
+        0x63, 0x70, 0x75, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x45,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01, 0x63, 0x70, 0x75, 0x40,
+        0x31, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x39,
+        0x63, 0x70, 0x75, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x45,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01,
+        0x6d, 0x65, 0x6d, 0x6f, 0x72, 0x79, 0x40, 0x38, 0x30, 0x30, 0x30, 0x30, 0x30, 0x30, 0x30, 0x00,
+        0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x07, 0x00, 0x00, 0x00, 0x39, 0x6d, 0x65, 0x6d, 0x6f,
+        0x72, 0x79, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x45,
+        0x00, 0x00, 0x00, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01, 0x73, 0x6f, 0x63, 0x00, 0x00, 0x00, 0x00, 0x03,
+        0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x03,
+        0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x0f, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x03,
+        0x00, 0x00, 0x00, 0x0b, 0x00, 0x00, 0x00, 0x1b, 0x73, 0x69, 0x6d, 0x70, 0x6c, 0x65, 0x2d, 0x62,
+        0x75, 0x73, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x73, 0x65, 0x72, 0x69, 0x61, 0x6c, 0x40, 0x31,
+        0x30, 0x30, 0x30, 0x30, 0x30, 0x30, 0x30, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x09,
+        0x00, 0x00, 0x00, 0x1b, 0x6e, 0x73, 0x31, 0x36, 0x35, 0x35, 0x30, 0x61, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x45, 0x00, 0x00, 0x00, 0x00,
+        0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x03,
+        0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x49, 0x00, 0x00, 0x00, 0x0a, 0x00, 0x00, 0x00, 0x02,
+        0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x09, 0x23, 0x61, 0x64, 0x64,
+        0x72, 0x65, 0x73, 0x73, 0x2d, 0x63, 0x65, 0x6c, 0x6c, 0x73, 0x00, 0x23, 0x73, 0x69, 0x7a, 0x65,
+        0x2d, 0x63, 0x65, 0x6c, 0x6c, 0x73, 0x00, 0x63, 0x6f, 0x6d, 0x70, 0x61, 0x74, 0x69, 0x62, 0x6c,
+        0x65, 0x00, 0x74, 0x69, 0x6d, 0x65, 0x62, 0x61, 0x73, 0x65, 0x2d, 0x66, 0x72, 0x65, 0x71, 0x75,
+        0x65, 0x6e, 0x63, 0x79, 0x00, 0x64, 0x65, 0x76, 0x69, 0x63, 0x65, 0x5f, 0x74, 0x79, 0x70, 0x65,
+        0x00, 0x72, 0x65, 0x67, 0x00, 0x69, 0x6e, 0x74, 0x65, 0x72, 0x72, 0x75, 0x70, 0x74, 0x73, 0x00,
+    ];
+
+    #[test]
+    fn test_header() {
+        let fdt = Fdt::from_bytes(&FIXTURE_DTB).unwrap();
+        assert_eq!(fdt.header().magic, FDT_MAGIC);
+        assert_eq!(fdt.total_size(), FIXTURE_DTB.len());
+        assert_eq!(fdt.reserved_memory().count(), 0);
+    }
+
+    #[test]
+    fn test_bad_magic() {
+        let mut blob = FIXTURE_DTB;
+        blob[0] = 0;
+        assert_eq!(Fdt::from_bytes(&blob).unwrap_err(), FdtError::BadMagic);
+        assert_eq!(Fdt::from_bytes(&FIXTURE_DTB[..16]).unwrap_err(), FdtError::Truncated);
+    }
+
+    #[test]
+    fn test_node_iteration() {
+        let fdt = Fdt::from_bytes(&FIXTURE_DTB).unwrap();
+        let names: Vec<&str> = fdt.nodes().map(|n| n.name()).collect();
+        assert_eq!(
+            names,
+            ["", "cpus", "cpu@0", "cpu@1", "memory@80000000", "soc", "serial@10000000"]
+        );
+        assert_eq!(fdt.cpus().len(), 2);
+        assert!(fdt.root().unwrap().is_compatible("test,fixture"));
+    }
+
+    #[test]
+    fn test_memory_regions() {
+        let fdt = Fdt::from_bytes(&FIXTURE_DTB).unwrap();
+        let regions = fdt.memory_regions();
+        assert_eq!(regions, [FdtRegion { address: 0x8000_0000, size: 0x800_0000 }]);
+    }
+
+    #[test]
+    fn test_uart_node() {
+        let fdt = Fdt::from_bytes(&FIXTURE_DTB).unwrap();
+        let uart = fdt.find_compatible("ns16550a").unwrap();
+        assert_eq!(uart.unit_name(), "serial");
+        assert_eq!(uart.unit_address(), Some(0x1000_0000));
+        assert_eq!(uart.first_reg(), Some(FdtRegion { address: 0x1000_0000, size: 0x100 }));
+        assert_eq!(uart.interrupts().collect::<Vec<_>>(), [10]);
+    }
+
+    #[test]
+    fn test_find_node_by_path() {
+        let fdt = Fdt::from_bytes(&FIXTURE_DTB).unwrap();
+        let cpu1 = fdt.find_node("/cpus/cpu@1").unwrap();
+        // CPU reg uses the one-cell address / zero-cell size of /cpus
+        assert_eq!(cpu1.first_reg(), Some(FdtRegion { address: 1, size: 0 }));
+
+        let cpus = fdt.find_node("/cpus").unwrap();
+        assert_eq!(
+            cpus.property("timebase-frequency").and_then(|p| p.as_u32()),
+            Some(10_000_000)
+        );
+
+        assert!(fdt.find_node("/soc/serial").is_some());
+        assert!(fdt.find_node("/cpus/cpu@2").is_none());
+        assert!(fdt.find_node("/serial@10000000").is_none());
+    }
+}
@@ -4,6 +4,10 @@
 
 use crate::{Error, Result};
 
+pub mod cpumask;
+pub mod fdt;
+pub mod rng;
+
 /// Initialize common libraries
 pub fn init() -> Result<()> {
     log::info!("Initializing common libraries");
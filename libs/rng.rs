@@ -0,0 +1,158 @@
+//! Deterministic pseudo-random number generator
+//!
+//! `Rng` is xoshiro256** seeded through splitmix64, so any 64-bit seed,
+//! including zero, yields a well-mixed state. The same seed always gives
+//! the same sequence, which keeps tests reproducible. It is not
+//! cryptographically strong and must not be used where an attacker could
+//! benefit from predicting its output.
+//!
+//! A global generator is seeded from the timestamp counter on first use
+//! and can be reseeded with `reseed()`.
+
+use crate::core::sync::SpinLock;
+
+/// xoshiro256** generator
+#[derive(Debug, Clone)]
+pub struct Rng {
+    /// Generator state, never all zero
+    state: [u64; 4],
+}
+
+impl Rng {
+    /// Create a generator from a 64-bit seed
+    pub fn new(seed: u64) -> Self {
+        let mut sm = seed;
+        let mut state = [0; 4];
+        for word in state.iter_mut() {
+            *word = splitmix64(&mut sm);
+        }
+        Self { state }
+    }
+
+    /// Create a generator seeded from the timestamp counter
+    pub fn from_timestamp() -> Self {
+        Self::new(crate::utils::get_timestamp())
+    }
+
+    /// Next 64 pseudo-random bits
+    pub fn next_u64(&mut self) -> u64 {
+        let s = &mut self.state;
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+
+        result
+    }
+
+    /// Fill `buf` with pseudo-random bytes
+    pub fn fill_bytes(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    /// Uniform value in `0..n`
+    ///
+    /// Draws that would bias the result towards small values are rejected.
+    /// Returns 0 if `n` is 0.
+    pub fn range(&mut self, n: u64) -> u64 {
+        if n == 0 {
+            return 0;
+        }
+
+        // Largest multiple of n that fits; values at or above it are biased
+        let limit = u64::MAX - u64::MAX % n;
+        loop {
+            let value = self.next_u64();
+            if value < limit {
+                return value % n;
+            }
+        }
+    }
+}
+
+/// splitmix64 step, used to expand a seed into generator state
+fn splitmix64(x: &mut u64) -> u64 {
+    *x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *x;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Global generator, seeded on first use
+static GLOBAL_RNG: SpinLock<Option<Rng>> = SpinLock::new(None);
+
+/// Run `f` on the global generator, seeding it from the timestamp counter
+/// if it hasn't been seeded yet
+fn with_global<R>(f: impl FnOnce(&mut Rng) -> R) -> R {
+    let mut rng = GLOBAL_RNG.lock();
+    f(rng.get_or_insert_with(Rng::from_timestamp))
+}
+
+/// Reseed the global generator, making its sequence reproducible
+pub fn reseed(seed: u64) {
+    *GLOBAL_RNG.lock() = Some(Rng::new(seed));
+}
+
+/// Next 64 bits from the global generator
+pub fn next_u64() -> u64 {
+    with_global(Rng::next_u64)
+}
+
+/// Fill `buf` from the global generator
+pub fn fill_bytes(buf: &mut [u8]) {
+    with_global(|rng| rng.fill_bytes(buf))
+}
+
+/// Uniform value in `0..n` from the global generator
+pub fn range(n: u64) -> u64 {
+    with_global(|rng| rng.range(n))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixed_seed_sequence() {
+        let mut rng = Rng::new(42);
+        assert_eq!(rng.next_u64(), 0x1578_0b2e_0c2e_c716);
+        assert_eq!(rng.next_u64(), 0x6104_d986_6d11_3a7e);
+        assert_eq!(rng.next_u64(), 0xae17_5332_39e4_99a1);
+        assert_eq!(rng.next_u64(), 0xecb8_ad47_03b3_60a1);
+
+        // fill_bytes consumes the same stream, little-endian
+        let mut buf = [0u8; 12];
+        Rng::new(42).fill_bytes(&mut buf);
+        assert_eq!(buf[..8], 0x1578_0b2e_0c2e_c716u64.to_le_bytes());
+        assert_eq!(buf[8..], 0x6104_d986_6d11_3a7eu64.to_le_bytes()[..4]);
+
+        // The global generator follows the seed it was given
+        reseed(42);
+        assert_eq!(next_u64(), 0x1578_0b2e_0c2e_c716);
+    }
+
+    #[test]
+    fn test_range_is_uniform() {
+        let mut rng = Rng::new(7);
+        let mut counts = [0u32; 6];
+        for _ in 0..60_000 {
+            counts[rng.range(6) as usize] += 1;
+        }
+
+        // Each bucket expects 10000; allow about five standard deviations
+        for &count in counts.iter() {
+            assert!((9_550..=10_450).contains(&count), "count {}", count);
+        }
+        assert_eq!(rng.range(1), 0);
+        assert_eq!(rng.range(0), 0);
+    }
+}
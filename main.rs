@@ -8,6 +8,7 @@
 
 // Import from the local ferrovisor library
 use ferrovisor::{init, run, Error};
+use ferrovisor::utils::console::early_write;
 
 /// Early entry point for ARM64
 #[cfg(target_arch = "aarch64")]
@@ -19,15 +20,8 @@ pub extern "C" fn _start() -> ! {
 
     // Initialize early console
     if cfg!(feature = "debug") {
-        // Early debug output
-        unsafe {
-            // Simple debug output before console is ready
-            core::ptr::write_volatile(0x9000000 as *mut u8, b'B');
-            core::ptr::write_volatile(0x9000000 as *mut u8, b'o');
-            core::ptr::write_volatile(0x9000000 as *mut u8, b'o');
-            core::ptr::write_volatile(0x9000000 as *mut u8, b't');
-            core::ptr::write_volatile(0x9000000 as *mut u8, b'\n');
-        }
+        // Simple debug output before console is ready
+        early_write(b"Boot\n");
     }
 
     // Call the main initialization
@@ -40,15 +34,8 @@ pub extern "C" fn _start() -> ! {
 pub extern "C" fn _start() -> ! {
     // Early debug output
     if cfg!(feature = "debug") {
-        unsafe {
-            // Simple debug output
-            core::ptr::write_volatile(0x10000000 as *mut u8, b'R');
-            core::ptr::write_volatile(0x10000000 as *mut u8, b'I');
-            core::ptr::write_volatile(0x10000000 as *mut u8, b'S');
-            core::ptr::write_volatile(0x10000000 as *mut u8, b'C');
-            core::ptr::write_volatile(0x10000000 as *mut u8, b'V');
-            core::ptr::write_volatile(0x10000000 as *mut u8, b'\n');
-        }
+        // Simple debug output
+        early_write(b"RISCV\n");
     }
 
     main_entry()
@@ -81,33 +68,9 @@ fn main_entry() -> ! {
 #[inline(never)]
 #[cold]
 fn early_panic(msg: &str) -> ! {
-    if cfg!(target_arch = "aarch64") {
-        // Output to UART at 0x9000000
-        for byte in msg.as_bytes() {
-            unsafe {
-                core::ptr::write_volatile(0x9000000 as *mut u8, *byte);
-            }
-        }
-        // Output panic message end marker
-        unsafe {
-            for byte in b" - PANIC!\n" {
-                core::ptr::write_volatile(0x9000000 as *mut u8, *byte);
-            }
-        }
-    } else if cfg!(target_arch = "riscv64") {
-        // Output to UART at 0x10000000
-        for byte in msg.as_bytes() {
-            unsafe {
-                core::ptr::write_volatile(0x10000000 as *mut u8, *byte);
-            }
-        }
-        // Output panic message end marker
-        unsafe {
-            for byte in b" - PANIC!\n" {
-                core::ptr::write_volatile(0x10000000 as *mut u8, *byte);
-            }
-        }
-    }
+    // Output to the early UART
+    early_write(msg.as_bytes());
+    early_write(b" - PANIC!\n");
 
     // Halt the system
     loop {
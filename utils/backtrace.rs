@@ -0,0 +1,284 @@
+//! Frame-pointer stack backtraces
+//!
+//! Walks the chain of saved frame pointers to collect return addresses:
+//! - aarch64: x29 points at `{prev x29, x30}`
+//! - riscv64: s0 points just above `{prev s0, ra}`, stored at -16/-8
+//!
+//! The walk never allocates and stops at `MAX_DEPTH` frames, or as soon
+//! as a frame pointer is misaligned, leaves the stack range, or fails to
+//! move toward the stack base, so a corrupt chain cannot run away.
+
+use core::fmt;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+/// Maximum number of frames recorded
+pub const MAX_DEPTH: usize = 32;
+
+/// Span above the current frame assumed to belong to the same stack
+pub const MAX_STACK_SPAN: usize = 64 * 1024;
+
+/// Where a frame record sits relative to the frame pointer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameLayout {
+    /// Offset of the caller's saved frame pointer
+    pub fp_offset: isize,
+    /// Offset of the saved return address
+    pub ra_offset: isize,
+}
+
+/// AArch64 frame record: `[fp] = prev fp, [fp + 8] = lr`
+pub const AARCH64_LAYOUT: FrameLayout = FrameLayout { fp_offset: 0, ra_offset: 8 };
+
+/// RISC-V frame record: `[fp - 16] = prev fp, [fp - 8] = ra`
+pub const RISCV_LAYOUT: FrameLayout = FrameLayout { fp_offset: -16, ra_offset: -8 };
+
+/// Resolves an address to a symbol name and offset
+pub type SymbolResolver = fn(addr: usize) -> Option<(&'static str, usize)>;
+
+/// Captured return addresses, innermost first
+#[derive(Clone, Copy)]
+pub struct Backtrace {
+    /// Return addresses
+    frames: [usize; MAX_DEPTH],
+    /// Valid entries in `frames`
+    len: usize,
+}
+
+impl Backtrace {
+    /// Create an empty backtrace
+    pub const fn new() -> Self {
+        Self { frames: [0; MAX_DEPTH], len: 0 }
+    }
+
+    /// Return addresses, innermost first
+    pub fn frames(&self) -> &[usize] {
+        &self.frames[..self.len]
+    }
+
+    /// Number of frames captured
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Check whether no frames were captured
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn push(&mut self, ra: usize) -> bool {
+        if self.len == MAX_DEPTH {
+            return false;
+        }
+        self.frames[self.len] = ra;
+        self.len += 1;
+        true
+    }
+}
+
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let resolver = symbol_resolver();
+        for (i, &ra) in self.frames().iter().enumerate() {
+            match resolver.and_then(|resolve| resolve(ra)) {
+                Some((name, offset)) => writeln!(f, "  #{:02} {:#018x} {}+{:#x}", i, ra, name, offset)?,
+                None => writeln!(f, "  #{:02} {:#018x}", i, ra)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Walk a frame-pointer chain starting at `fp`
+///
+/// Only frame records wholly inside `stack_lo..stack_hi` are read.
+///
+/// # Safety
+/// `stack_lo..stack_hi` must be readable memory.
+pub unsafe fn walk(mut fp: usize, stack_lo: usize, stack_hi: usize, layout: FrameLayout) -> Backtrace {
+    let word = core::mem::size_of::<usize>();
+    let record_lo = layout.fp_offset.min(layout.ra_offset);
+    let record_hi = layout.fp_offset.max(layout.ra_offset) + word as isize;
+    let mut trace = Backtrace::new();
+
+    loop {
+        if fp == 0 || fp % word != 0 {
+            break;
+        }
+
+        let lo = fp as isize + record_lo;
+        let hi = fp as isize + record_hi;
+        if lo < stack_lo as isize || hi > stack_hi as isize {
+            break;
+        }
+
+        let prev_fp = core::ptr::read((fp as isize + layout.fp_offset) as *const usize);
+        let ra = core::ptr::read((fp as isize + layout.ra_offset) as *const usize);
+        if ra == 0 || !trace.push(ra) {
+            break;
+        }
+
+        // Callers' frames lie strictly toward the stack base
+        if prev_fp <= fp {
+            break;
+        }
+        fp = prev_fp;
+    }
+
+    trace
+}
+
+/// Capture a backtrace of the current call stack
+#[inline(never)]
+pub fn capture() -> Backtrace {
+    #[cfg(target_arch = "aarch64")]
+    {
+        let fp: usize;
+        unsafe {
+            core::arch::asm!("mov {}, x29", out(reg) fp);
+            walk(fp, fp, fp.saturating_add(MAX_STACK_SPAN), AARCH64_LAYOUT)
+        }
+    }
+
+    #[cfg(target_arch = "riscv64")]
+    {
+        let fp: usize;
+        unsafe {
+            core::arch::asm!("mv {}, s0", out(reg) fp);
+            walk(fp, fp.saturating_sub(16), fp.saturating_add(MAX_STACK_SPAN), RISCV_LAYOUT)
+        }
+    }
+
+    #[cfg(not(any(target_arch = "aarch64", target_arch = "riscv64")))]
+    {
+        Backtrace::new()
+    }
+}
+
+/// Installed symbol resolver, as a function pointer; null when none is
+///
+/// Kept lock-free so backtraces can be printed from panic and exception
+/// paths that may have interrupted an installer.
+static SYMBOL_RESOLVER: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Install the resolver used to annotate printed backtraces
+pub fn set_symbol_resolver(resolver: SymbolResolver) {
+    SYMBOL_RESOLVER.store(resolver as *mut (), Ordering::Release);
+}
+
+/// The installed symbol resolver, if any
+fn symbol_resolver() -> Option<SymbolResolver> {
+    let ptr = SYMBOL_RESOLVER.load(Ordering::Acquire);
+    if ptr.is_null() {
+        return None;
+    }
+    // Only `set_symbol_resolver` stores to the slot, and always
+    // a `SymbolResolver`
+    Some(unsafe { core::mem::transmute::<*mut (), SymbolResolver>(ptr) })
+}
+
+/// Name of the image frames are resolved against
+const IMAGE_NAME: &str = "ferrovisor";
+
+/// Resolve `addr` as an offset into the text at `text_start..text_end`
+fn resolve_in_text(addr: usize, text_start: usize, text_end: usize) -> Option<(&'static str, usize)> {
+    if (text_start..text_end).contains(&addr) {
+        Some((IMAGE_NAME, addr - text_start))
+    } else {
+        None
+    }
+}
+
+/// Resolve addresses inside the hypervisor image's text
+///
+/// No symbol table is linked in, so frames are named by their offset into
+/// the text section, which `addr2line` maps back to a function.
+#[cfg(target_os = "none")]
+pub fn image_text_resolver(addr: usize) -> Option<(&'static str, usize)> {
+    extern "C" {
+        static __text_start: u8;
+        static __text_end: u8;
+    }
+
+    let (start, end) = unsafe {
+        (&__text_start as *const u8 as usize, &__text_end as *const u8 as usize)
+    };
+    resolve_in_text(addr, start, end)
+}
+
+/// Print a backtrace of the current call stack over the early UART
+///
+/// Called from the panic handlers.
+pub fn print_backtrace() {
+    let trace = capture();
+    crate::utils::console::print_fmt(format_args!("Backtrace ({} frames):\n{}", trace.len(), trace));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a chain of `depth` AArch64 frame records in `stack`
+    ///
+    /// Frames are laid out from the low end upward, each linking to the
+    /// next. Returns the innermost frame pointer.
+    fn build_chain(stack: &mut [usize], depth: usize) -> usize {
+        let base = stack.as_ptr() as usize;
+        for i in 0..depth {
+            let slot = i * 4;
+            let next_fp = if i + 1 < depth { base + (slot + 4) * 8 } else { 0 };
+            stack[slot] = next_fp;
+            stack[slot + 1] = 0x1000 + i;
+        }
+        base
+    }
+
+    #[test]
+    fn test_walk_synthetic_chain() {
+        let mut stack = [0usize; 64];
+        let fp = build_chain(&mut stack, 5);
+        let range = (stack.as_ptr() as usize, stack.as_ptr() as usize + stack.len() * 8);
+
+        let trace = unsafe { walk(fp, range.0, range.1, AARCH64_LAYOUT) };
+        assert_eq!(trace.frames(), [0x1000, 0x1001, 0x1002, 0x1003, 0x1004]);
+    }
+
+    #[test]
+    fn test_walk_stops_on_corrupt_frames() {
+        let mut stack = [0usize; 64];
+        let fp = build_chain(&mut stack, 5);
+        let lo = stack.as_ptr() as usize;
+        let hi = lo + stack.len() * 8;
+
+        // Third frame points back at the first: a loop
+        stack[2 * 4] = fp;
+        let fp = stack.as_ptr() as usize;
+        let trace = unsafe { walk(fp, lo, hi, AARCH64_LAYOUT) };
+        assert_eq!(trace.len(), 3);
+
+        // Second frame points outside the stack
+        build_chain(&mut stack, 5);
+        stack[4] = hi + 64;
+        let fp = stack.as_ptr() as usize;
+        let trace = unsafe { walk(fp, lo, hi, AARCH64_LAYOUT) };
+        assert_eq!(trace.len(), 2);
+
+        // Misaligned start
+        assert!(unsafe { walk(fp + 1, lo, hi, AARCH64_LAYOUT) }.is_empty());
+    }
+
+    #[test]
+    fn test_walk_depth_limited() {
+        let mut stack = [0usize; 4 * (MAX_DEPTH + 8)];
+        let fp = build_chain(&mut stack, MAX_DEPTH + 8);
+        let lo = stack.as_ptr() as usize;
+        let trace = unsafe { walk(fp, lo, lo + stack.len() * 8, AARCH64_LAYOUT) };
+        assert_eq!(trace.len(), MAX_DEPTH);
+    }
+
+    #[test]
+    fn test_resolve_in_text() {
+        assert_eq!(resolve_in_text(0x8010_0040, 0x8010_0000, 0x8020_0000), Some((IMAGE_NAME, 0x40)));
+        assert_eq!(resolve_in_text(0x8020_0000, 0x8010_0000, 0x8020_0000), None);
+        assert_eq!(resolve_in_text(0x1000, 0x8010_0000, 0x8020_0000), None);
+    }
+}
@@ -0,0 +1,163 @@
+//! Typed register field accessors
+//!
+//! `bitfield!` wraps an integer in a newtype with a getter and setter
+//! for each named bit range, so device emulators can write
+//! `cr.set_rxe(true)` instead of open-coded shifts and masks:
+//!
+//! ```ignore
+//! bitfield! {
+//!     /// Line control register
+//!     pub struct Lcr(u32) {
+//!         /// Parity enable
+//!         pen / set_pen: [1],
+//!         /// Word length
+//!         wlen / set_wlen: [6:5],
+//!     }
+//! }
+//! ```
+//!
+//! A single-bit field `[n]` reads and writes a `bool`. A range `[hi:lo]`
+//! (inclusive) reads and writes the backing integer type; its setter
+//! returns `Error::InvalidArgument`, leaving the register unchanged, if
+//! the value does not fit in the field.
+
+/// Mask of `width` low bits of an integer type
+#[doc(hidden)]
+#[macro_export]
+macro_rules! bitfield_mask {
+    ($ty:ty, $width:expr) => {
+        (!(0 as $ty)) >> (<$ty>::BITS - ($width) as u32)
+    };
+}
+
+/// Define an integer newtype with typed bit-field accessors
+#[macro_export]
+macro_rules! bitfield {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident($ty:ty) {
+            $(
+                $(#[$fmeta:meta])*
+                $get:ident / $set:ident : [$($bits:tt)*]
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+        $vis struct $name($ty);
+
+        impl $name {
+            /// Wrap a raw register value
+            pub const fn from_bits(bits: $ty) -> Self {
+                Self(bits)
+            }
+
+            /// Raw register value
+            pub const fn bits(self) -> $ty {
+                self.0
+            }
+
+            $(
+                $crate::bitfield!(@field $ty, [$(#[$fmeta])*], $get, $set, [$($bits)*]);
+            )*
+        }
+    };
+
+    (@field $ty:ty, [$(#[$fmeta:meta])*], $get:ident, $set:ident, [$bit:literal]) => {
+        $(#[$fmeta])*
+        pub fn $get(&self) -> bool {
+            (self.0 >> $bit) & 1 != 0
+        }
+
+        #[doc = concat!("Set `", stringify!($get), "`")]
+        pub fn $set(&mut self, value: bool) {
+            if value {
+                self.0 |= (1 as $ty) << $bit;
+            } else {
+                self.0 &= !((1 as $ty) << $bit);
+            }
+        }
+    };
+
+    (@field $ty:ty, [$(#[$fmeta:meta])*], $get:ident, $set:ident, [$hi:literal : $lo:literal]) => {
+        $(#[$fmeta])*
+        pub fn $get(&self) -> $ty {
+            (self.0 >> $lo) & $crate::bitfield_mask!($ty, $hi - $lo + 1)
+        }
+
+        #[doc = concat!("Set `", stringify!($get), "`, rejecting values wider than the field")]
+        pub fn $set(&mut self, value: $ty) -> $crate::Result<()> {
+            let mask = $crate::bitfield_mask!($ty, $hi - $lo + 1);
+            if value & !mask != 0 {
+                return Err($crate::Error::InvalidArgument);
+            }
+            self.0 = (self.0 & !(mask << $lo)) | (value << $lo);
+            Ok(())
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Error;
+
+    crate::bitfield! {
+        /// Test register
+        struct TestReg(u32) {
+            /// Bit 0
+            enable / set_enable: [0],
+            /// Bits 7:4
+            level / set_level: [7:4],
+            /// Bit 31
+            top / set_top: [31],
+            /// Bits 30:8
+            wide / set_wide: [30:8],
+        }
+    }
+
+    crate::bitfield! {
+        struct Full(u8) {
+            all / set_all: [7:0],
+        }
+    }
+
+    #[test]
+    fn test_accessors_read_correct_bits() {
+        let reg = TestReg::from_bits(0x8000_00A1);
+        assert!(reg.enable());
+        assert_eq!(reg.level(), 0xA);
+        assert!(reg.top());
+        assert_eq!(reg.wide(), 0);
+
+        assert_eq!(TestReg::from_bits(0x7FFF_FF00).wide(), 0x7F_FFFF);
+        assert_eq!(Full::from_bits(0xC3).all(), 0xC3);
+    }
+
+    #[test]
+    fn test_setters_touch_only_their_field() {
+        let mut reg = TestReg::from_bits(0xFFFF_FFFF);
+        reg.set_level(0x3).unwrap();
+        assert_eq!(reg.bits(), 0xFFFF_FF3F);
+
+        reg.set_enable(false);
+        reg.set_top(false);
+        assert_eq!(reg.bits(), 0x7FFF_FF3E);
+
+        let mut reg = TestReg::default();
+        reg.set_wide(0x12345).unwrap();
+        assert_eq!(reg.bits(), 0x0123_4500);
+    }
+
+    #[test]
+    fn test_out_of_range_values_rejected() {
+        let mut reg = TestReg::from_bits(0x50);
+        assert_eq!(reg.set_level(0x10), Err(Error::InvalidArgument));
+        assert_eq!(reg.bits(), 0x50);
+
+        assert_eq!(reg.set_wide(0x80_0000), Err(Error::InvalidArgument));
+
+        let mut full = Full::default();
+        assert_eq!(full.set_all(0xFF), Ok(()));
+        assert_eq!(full.bits(), 0xFF);
+    }
+}
@@ -113,6 +113,96 @@ impl Bitmap {
         }
     }
 
+    /// Set or clear `count` bits starting at `start`, a word at a time
+    fn fill_range(&mut self, start: usize, count: usize, value: bool) -> bool {
+        let end = match start.checked_add(count) {
+            Some(end) if end <= self.bits => end,
+            _ => return false,
+        };
+
+        let mut index = start;
+        while index < end {
+            let word = index / 64;
+            let bit = index % 64;
+            let len = (64 - bit).min(end - index);
+            let mask = if len == 64 { u64::MAX } else { ((1u64 << len) - 1) << bit };
+            unsafe {
+                let current = core::ptr::read_volatile(self.data.add(word));
+                let new_value = if value { current | mask } else { current & !mask };
+                core::ptr::write_volatile(self.data.add(word), new_value);
+            }
+            index += len;
+        }
+        true
+    }
+
+    /// Set `count` bits starting at `start`
+    ///
+    /// Returns false, changing nothing, if the range exceeds the bitmap.
+    pub fn set_range(&mut self, start: usize, count: usize) -> bool {
+        self.fill_range(start, count, true)
+    }
+
+    /// Clear `count` bits starting at `start`
+    ///
+    /// Returns false, changing nothing, if the range exceeds the bitmap.
+    pub fn clear_range(&mut self, start: usize, count: usize) -> bool {
+        self.fill_range(start, count, false)
+    }
+
+    /// Find the first run of `count` consecutive zero bits
+    pub fn find_zero_range(&self, count: usize) -> Option<usize> {
+        if count == 0 || count > self.bits {
+            return None;
+        }
+
+        let mut run_start = 0;
+        let mut run_len = 0;
+        let mut index = 0;
+        while index < self.bits {
+            let word = unsafe { core::ptr::read_volatile(self.data.add(index / 64)) };
+
+            // Whole free or full words are consumed at once
+            if index % 64 == 0 && index + 64 <= self.bits && (word == 0 || word == u64::MAX) {
+                if word == 0 {
+                    if run_len == 0 {
+                        run_start = index;
+                    }
+                    run_len += 64;
+                } else {
+                    run_len = 0;
+                }
+                index += 64;
+            } else {
+                if (word >> (index % 64)) & 1 == 0 {
+                    if run_len == 0 {
+                        run_start = index;
+                    }
+                    run_len += 1;
+                } else {
+                    run_len = 0;
+                }
+                index += 1;
+            }
+
+            if run_len >= count {
+                return Some(run_start);
+            }
+        }
+        None
+    }
+
+    /// Find and set the first run of `count` consecutive zero bits
+    ///
+    /// The search and the reservation happen under the same `&mut`
+    /// borrow, so the run cannot be taken in between. Returns the index
+    /// of the first bit of the run.
+    pub fn find_and_set_range(&mut self, count: usize) -> Option<usize> {
+        let start = self.find_zero_range(count)?;
+        self.set_range(start, count);
+        Some(start)
+    }
+
     /// Find the first set bit
     pub fn find_first_set(&self) -> Option<usize> {
         for word_idx in 0..self.words {
@@ -311,4 +401,46 @@ mod tests {
 
         assert_eq!(bitmap.find_and_set(), None);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_bitmap_find_and_set_range() {
+        let mut data = [0u64; 3];
+        let mut bitmap = Bitmap::from_slice(&mut data);
+
+        bitmap.set_bit(1);
+        let start = bitmap.find_and_set_range(4).unwrap();
+        assert_eq!(start, 2);
+        assert!((2..6).all(|i| bitmap.test(i)));
+        assert!(!bitmap.test(6));
+
+        // A run spanning a word boundary
+        bitmap.set_range(6, 56);
+        assert_eq!(bitmap.find_and_set_range(70), Some(62));
+        assert!(bitmap.test(131));
+        assert!(!bitmap.test(132));
+
+        bitmap.clear_range(62, 70);
+        assert!(!bitmap.test(62) && !bitmap.test(131));
+        assert!(bitmap.test(61));
+
+        assert!(!bitmap.set_range(190, 4));
+        assert!(!bitmap.test(190));
+    }
+
+    #[test]
+    fn test_bitmap_range_fails_when_fragmented() {
+        let mut data = [0u64; 1];
+        let mut bitmap = Bitmap::from_slice(&mut data);
+
+        // Every fourth bit free: no run of two, but plenty of single bits
+        bitmap.set_all();
+        for i in (0..64).step_by(4) {
+            bitmap.clear_bit(i);
+        }
+
+        assert_eq!(bitmap.find_and_set_range(4), None);
+        assert_eq!(bitmap.find_and_set_range(2), None);
+        assert_eq!(bitmap.find_and_set_range(1), Some(0));
+        assert_eq!(bitmap.find_and_set(), Some(4));
+    }
+}
@@ -100,31 +100,110 @@ impl Console for UartConsole {
     }
 }
 
+/// Default early UART base for the target architecture
+///
+/// PL011 on QEMU virt for ARM64, the NS16550 on QEMU virt for RISC-V and
+/// COM1 on x86_64.
+#[cfg(target_arch = "aarch64")]
+pub const DEFAULT_EARLY_UART_BASE: usize = 0x9000000;
+#[cfg(target_arch = "riscv64")]
+pub const DEFAULT_EARLY_UART_BASE: usize = 0x10000000;
+#[cfg(not(any(target_arch = "aarch64", target_arch = "riscv64")))]
+pub const DEFAULT_EARLY_UART_BASE: usize = 0x3F8;
+
+/// Early UART base, overridable at build time
+///
+/// Set `FERROVISOR_EARLY_UART_BASE` (decimal or `0x` hex) when building
+/// for a platform whose UART lives elsewhere.
+pub const EARLY_UART_BASE: usize = match option_env!("FERROVISOR_EARLY_UART_BASE") {
+    Some(base) => parse_uart_base(base),
+    None => DEFAULT_EARLY_UART_BASE,
+};
+
+/// Parse a decimal or `0x`-prefixed hex address at compile time
+const fn parse_uart_base(s: &str) -> usize {
+    let bytes = s.as_bytes();
+    let (radix, mut i) = if bytes.len() > 2 && bytes[0] == b'0' && (bytes[1] == b'x' || bytes[1] == b'X') {
+        (16, 2)
+    } else {
+        (10, 0)
+    };
+
+    let mut value = 0usize;
+    while i < bytes.len() {
+        let digit = match bytes[i] {
+            b'0'..=b'9' => bytes[i] - b'0',
+            b'a'..=b'f' if radix == 16 => bytes[i] - b'a' + 10,
+            b'A'..=b'F' if radix == 16 => bytes[i] - b'A' + 10,
+            b'_' => {
+                i += 1;
+                continue;
+            }
+            _ => panic!("invalid FERROVISOR_EARLY_UART_BASE"),
+        };
+        value = value * radix + digit as usize;
+        i += 1;
+    }
+    value
+}
+
+/// Early UART base in use, relocatable once platform info is known
+static EARLY_UART: core::sync::atomic::AtomicUsize =
+    core::sync::atomic::AtomicUsize::new(EARLY_UART_BASE);
+
+/// Get the early UART base address
+pub fn early_uart_base() -> usize {
+    EARLY_UART.load(core::sync::atomic::Ordering::Relaxed)
+}
+
+/// Move early output to the UART at `base`
+///
+/// Takes effect for early prints and, if called before the console is
+/// first used, for the default console.
+pub fn set_early_uart_base(base: usize) {
+    EARLY_UART.store(base, core::sync::atomic::Ordering::Relaxed);
+}
+
+/// Write bytes straight to the early UART data register
+///
+/// Used before the console is initialized and from the early panic path,
+/// so it neither locks nor polls for TX space.
+pub fn early_write(buf: &[u8]) {
+    let data = early_uart_base() as *mut u8;
+    for &byte in buf {
+        unsafe {
+            core::ptr::write_volatile(data, byte);
+        }
+    }
+}
+
+/// Print a formatted string straight to the early UART
+///
+/// Takes no locks, so it is safe from code that may already hold the
+/// console's lock.
+pub fn early_print_fmt(args: fmt::Arguments<'_>) {
+    let _ = fmt::write(&mut EarlyWriter, args);
+}
+
+/// Writer for formatted early UART output
+struct EarlyWriter;
+
+impl fmt::Write for EarlyWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        early_write(s.as_bytes());
+        Ok(())
+    }
+}
+
 /// Default console instance
 static mut DEFAULT_CONSOLE: Option<UartConsole> = None;
 static CONSOLE_INIT: core::sync::atomic::AtomicBool = core::sync::atomic::AtomicBool::new(false);
 
-/// Initialize the console
+/// Initialize the console on the early UART
 pub fn init() {
     if !CONSOLE_INIT.load(core::sync::atomic::Ordering::Relaxed) {
         unsafe {
-            #[cfg(target_arch = "aarch64")]
-            {
-                // Use PL011 UART at 0x9000000
-                DEFAULT_CONSOLE = Some(UartConsole::new(0x9000000));
-            }
-
-            #[cfg(target_arch = "riscv64")]
-            {
-                // Use UART at 0x10000000
-                DEFAULT_CONSOLE = Some(UartConsole::new(0x10000000));
-            }
-
-            #[cfg(target_arch = "x86_64")]
-            {
-                // Use COM1 port
-                DEFAULT_CONSOLE = Some(UartConsole::new(0x3F8));
-            }
+            DEFAULT_CONSOLE = Some(UartConsole::new(early_uart_base()));
         }
         CONSOLE_INIT.store(true, core::sync::atomic::Ordering::Relaxed);
     }
@@ -137,6 +216,37 @@ fn get_console() -> &'static UartConsole {
     }
 }
 
+/// Get the early UART console
+pub fn early_console() -> &'static UartConsole {
+    if !CONSOLE_INIT.load(core::sync::atomic::Ordering::Relaxed) {
+        init();
+    }
+    get_console()
+}
+
+/// Set once a panic starts; console output must then never wait on a lock
+static PANICKING: core::sync::atomic::AtomicBool = core::sync::atomic::AtomicBool::new(false);
+
+/// Switch console output to its panic-safe path
+///
+/// Called first thing by the panic handlers: the sinks are only written
+/// if their lock is free, and the early UART is used otherwise.
+pub fn enter_panic() {
+    PANICKING.store(true, core::sync::atomic::Ordering::Relaxed);
+}
+
+/// Write to the registered console sinks, or the early UART if none are
+fn write_all(buf: &[u8]) {
+    let written = if PANICKING.load(core::sync::atomic::Ordering::Relaxed) {
+        crate::drivers::base::console::try_write(buf)
+    } else {
+        crate::drivers::base::console::write(buf)
+    };
+    if !written {
+        get_console().write(buf);
+    }
+}
+
 /// Print a formatted string
 #[macro_export]
 macro_rules! print {
@@ -159,8 +269,7 @@ pub fn print_fmt(args: fmt::Arguments<'_>) {
     if !CONSOLE_INIT.load(core::sync::atomic::Ordering::Relaxed) {
         init();
     }
-    let console = get_console();
-    let mut writer = ConsoleWriter { console };
+    let mut writer = ConsoleWriter;
     fmt::write(&mut writer, args).unwrap();
 }
 
@@ -169,7 +278,7 @@ pub fn print_char(c: u8) {
     if !CONSOLE_INIT.load(core::sync::atomic::Ordering::Relaxed) {
         init();
     }
-    get_console().write_char(c);
+    write_all(&[c]);
 }
 
 /// Print a buffer
@@ -177,7 +286,7 @@ pub fn print_bytes(buf: &[u8]) {
     if !CONSOLE_INIT.load(core::sync::atomic::Ordering::Relaxed) {
         init();
     }
-    get_console().write(buf);
+    write_all(buf);
 }
 
 /// Flush the console
@@ -185,22 +294,16 @@ pub fn flush() {
     if !CONSOLE_INIT.load(core::sync::atomic::Ordering::Relaxed) {
         init();
     }
+    crate::drivers::base::console::flush();
     get_console().flush();
 }
 
 /// Writer for formatted output
-struct ConsoleWriter<'a> {
-    console: &'a dyn Console,
-}
+struct ConsoleWriter;
 
-impl<'a> fmt::Write for ConsoleWriter<'a> {
+impl fmt::Write for ConsoleWriter {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        self.console.write(s.as_bytes());
-        Ok(())
-    }
-
-    fn write_char(&mut self, c: char) -> fmt::Result {
-        self.console.write_char(c as u8);
+        write_all(s.as_bytes());
         Ok(())
     }
 }
@@ -235,4 +338,29 @@ pub enum Color {
     BrightMagenta = 95,
     BrightCyan = 96,
     BrightWhite = 97,
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_uart_base() {
+        assert_eq!(parse_uart_base("0x9000000"), 0x9000000);
+        assert_eq!(parse_uart_base("0X1C09_0000"), 0x1C09_0000);
+        assert_eq!(parse_uart_base("1016"), 0x3F8);
+    }
+
+    #[test]
+    fn test_early_write_uses_configured_base() {
+        let mut data_reg = core::cell::UnsafeCell::new(0u8);
+        let original = early_uart_base();
+
+        set_early_uart_base(data_reg.get() as usize);
+        early_write(b"ok");
+        set_early_uart_base(original);
+
+        assert_eq!(*data_reg.get_mut(), b'k');
+        assert_eq!(early_uart_base(), original);
+    }
+}
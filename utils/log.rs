@@ -4,6 +4,7 @@
 //! for a no_std hypervisor environment.
 
 use core::fmt;
+use core::sync::atomic::{AtomicU8, Ordering};
 use crate::utils::console;
 
 /// Log levels
@@ -34,8 +35,8 @@ impl Level {
     }
 }
 
-/// Get the current log level
-pub fn level() -> Level {
+/// Level used until `set_level` is called
+fn default_level() -> Level {
     #[cfg(feature = "debug")]
     {
         #[cfg(feature = "verbose")]
@@ -48,23 +49,41 @@ pub fn level() -> Level {
     Level::Info
 }
 
+/// Runtime log level, `LEVEL_UNSET` until `set_level` is called
+static LEVEL: AtomicU8 = AtomicU8::new(LEVEL_UNSET);
+
+/// `LEVEL` value meaning the compile-time default applies
+const LEVEL_UNSET: u8 = u8::MAX;
+
+/// Get the current log level
+pub fn level() -> Level {
+    match LEVEL.load(Ordering::Relaxed) {
+        0 => Level::Error,
+        1 => Level::Warn,
+        2 => Level::Info,
+        3 => Level::Debug,
+        4 => Level::Trace,
+        _ => default_level(),
+    }
+}
+
 /// Set the log level
+///
+/// Messages less severe than `level` are neither printed nor kept in the
+/// crash log ring.
 pub fn set_level(level: Level) {
-    // TODO: Implement log level setting
-    // For now, compile-time only
+    LEVEL.store(level as u8, Ordering::Relaxed);
 }
 
 /// Log a message
 pub fn log(level: Level, args: fmt::Arguments<'_>) {
-    if level <= level() {
-        let _timestamp = crate::utils::get_timestamp();
-
-        // TODO: Implement console output
-        // Format: [TIMESTAMP] [LEVEL] message
-        // console::print!("[{:016x}] [{}] ", timestamp, level.as_str());
-        // console::print_fmt(args);
-        // console::print!("\n");
-        let _ = args; // Suppress unused warning
+    if level <= self::level() {
+        // Keep the line for the panic-time dump
+        crate::utils::log_ring::record(level, args);
+
+        // Format: [TIMESTAMP] [LEVEL] message, to every console sink
+        let timestamp = crate::utils::get_timestamp();
+        console::print_fmt(format_args!("[{:016x}] [{}] {}\n", timestamp, level.as_str(), args));
     }
 }
 
@@ -0,0 +1,191 @@
+//! Crash log ring buffer
+//!
+//! Every log message is also recorded into a fixed-size in-memory ring so
+//! that the most recent lines survive until the panic handler can dump
+//! them over the early UART. The ring never allocates: lines longer than
+//! `LINE_LEN` are truncated and the oldest line is overwritten when full.
+
+use core::fmt::{self, Write};
+use crate::core::sync::SpinLock;
+use crate::utils::log::Level;
+
+/// Number of lines kept by the global ring
+pub const RING_LINES: usize = 64;
+
+/// Maximum bytes kept per line
+pub const LINE_LEN: usize = 128;
+
+/// One recorded line
+#[derive(Clone, Copy)]
+struct Line {
+    /// Message bytes
+    buf: [u8; LINE_LEN],
+    /// Valid bytes in `buf`
+    len: usize,
+}
+
+impl Line {
+    const EMPTY: Self = Self { buf: [0; LINE_LEN], len: 0 };
+
+    fn as_str(&self) -> &str {
+        // Truncation only ever happens on a char boundary
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or("<invalid utf-8>")
+    }
+}
+
+impl Write for Line {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let room = LINE_LEN - self.len;
+        let mut take = s.len().min(room);
+        while !s.is_char_boundary(take) {
+            take -= 1;
+        }
+        self.buf[self.len..self.len + take].copy_from_slice(&s.as_bytes()[..take]);
+        self.len += take;
+        Ok(())
+    }
+}
+
+/// Fixed-capacity ring of log lines
+pub struct LogRing<const N: usize> {
+    /// Line storage
+    lines: [Line; N],
+    /// Index of the next line to write
+    head: usize,
+    /// Number of valid lines
+    count: usize,
+}
+
+impl<const N: usize> LogRing<N> {
+    /// Create an empty ring
+    pub const fn new() -> Self {
+        Self {
+            lines: [Line::EMPTY; N],
+            head: 0,
+            count: 0,
+        }
+    }
+
+    /// Record a message, overwriting the oldest line when full
+    pub fn push(&mut self, level: Level, args: fmt::Arguments<'_>) {
+        if N == 0 {
+            return;
+        }
+
+        let line = &mut self.lines[self.head];
+        line.len = 0;
+        let _ = write!(line, "[{}] {}", level.as_str(), args);
+
+        self.head = (self.head + 1) % N;
+        self.count = (self.count + 1).min(N);
+    }
+
+    /// Number of lines held
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// Check whether the ring is empty
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Iterate over the held lines, oldest first
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        let start = (self.head + N - self.count) % N.max(1);
+        (0..self.count).map(move |i| self.lines[(start + i) % N].as_str())
+    }
+
+    /// Write the held lines to `writer`, oldest first
+    pub fn dump(&self, writer: &mut dyn Write) -> fmt::Result {
+        for line in self.iter() {
+            writer.write_str(line)?;
+            writer.write_char('\n')?;
+        }
+        Ok(())
+    }
+
+    /// Drop all lines
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.count = 0;
+    }
+}
+
+/// Global crash log ring
+static LOG_RING: SpinLock<LogRing<RING_LINES>> = SpinLock::new(LogRing::new());
+
+/// Record a message into the global ring
+///
+/// Messages logged while the ring is locked (e.g. from an interrupt that
+/// preempted a logger on this CPU) are dropped rather than deadlocking.
+pub fn record(level: Level, args: fmt::Arguments<'_>) {
+    if let Some(mut ring) = LOG_RING.try_lock() {
+        ring.push(level, args);
+    }
+}
+
+/// Dump the global ring to `writer`, oldest line first
+pub fn dump_log_ring(writer: &mut dyn Write) -> fmt::Result {
+    LOG_RING.lock().dump(writer)
+}
+
+/// Writer over the early UART console
+pub struct EarlyConsoleWriter;
+
+impl Write for EarlyConsoleWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        crate::utils::console::print_bytes(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Dump the global ring over the early UART
+///
+/// Called from the panic handlers before halting.
+pub fn dump_to_console() {
+    if LOG_RING.is_locked() {
+        // The panicking CPU may have died holding the lock; nothing else
+        // runs any more, so take it rather than lose the log
+        unsafe { LOG_RING.force_unlock() };
+    }
+
+    let mut writer = EarlyConsoleWriter;
+    let _ = writer.write_str("--- last log messages ---\n");
+    let _ = dump_log_ring(&mut writer);
+    let _ = writer.write_str("--- end of log ---\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_ring_keeps_most_recent_in_order() {
+        let mut ring: LogRing<4> = LogRing::new();
+        for i in 0..5 {
+            ring.push(Level::Info, format_args!("message {}", i));
+        }
+        assert_eq!(ring.len(), 4);
+
+        let lines: Vec<&str> = ring.iter().collect();
+        assert_eq!(lines, ["[INFO] message 1", "[INFO] message 2", "[INFO] message 3", "[INFO] message 4"]);
+
+        let mut out = String::new();
+        ring.dump(&mut out).unwrap();
+        assert_eq!(out, "[INFO] message 1\n[INFO] message 2\n[INFO] message 3\n[INFO] message 4\n");
+    }
+
+    #[test]
+    fn test_long_line_truncated() {
+        let mut ring: LogRing<2> = LogRing::new();
+        let long = "é".repeat(LINE_LEN);
+        ring.push(Level::Error, format_args!("{}", long));
+
+        let line = ring.iter().next().unwrap();
+        assert!(line.len() <= LINE_LEN);
+        assert!(line.starts_with("[ERROR] é"));
+    }
+}
@@ -4,8 +4,11 @@
 //! and helper code used throughout the hypervisor.
 
 pub mod log;
+pub mod log_ring;
+pub mod backtrace;
 pub mod console;
 pub mod bitmap;
+pub mod bitfield;
 pub mod list;
 pub mod time;
 pub mod random;
@@ -76,14 +79,18 @@ pub fn mb() {
     unsafe { core::arch::asm!("mfence") };
 }
 
-/// Get a timestamp counter
+/// Highest counter value observed on any CPU
+static LAST_TIMESTAMP: core::sync::atomic::AtomicU64 = core::sync::atomic::AtomicU64::new(0);
+
+/// Read the raw architectural counter
 #[inline]
-pub fn get_timestamp() -> u64 {
+fn read_counter() -> u64 {
     #[cfg(target_arch = "aarch64")]
     {
         let mut cnt: u64;
         unsafe {
             core::arch::asm!(
+                "isb",
                 "mrs {}, cntvct_el0",
                 out(reg) cnt,
                 options(nomem, nostack, preserves_flags)
@@ -94,23 +101,39 @@ pub fn get_timestamp() -> u64 {
 
     #[cfg(target_arch = "riscv64")]
     {
-        riscv::register::time::read()
+        riscv::register::time::read() as u64
     }
 
     #[cfg(target_arch = "x86_64")]
     {
         unsafe {
-            let mut rax: u64;
+            let lo: u32;
+            let hi: u32;
             core::arch::asm!(
                 "rdtsc",
-                out("rax") rax,
+                out("eax") lo,
+                out("edx") hi,
                 options(nomem, nostack)
             );
-            rax
+            ((hi as u64) << 32) | lo as u64
         }
     }
 }
 
+/// Get a timestamp counter
+///
+/// Returns raw counter ticks; see `time::timer_frequency()` for the rate.
+/// Values never go backwards, even when consecutive reads happen on CPUs
+/// whose counters are slightly out of sync.
+#[inline]
+pub fn get_timestamp() -> u64 {
+    use core::sync::atomic::Ordering;
+
+    let now = read_counter();
+    let last = LAST_TIMESTAMP.fetch_max(now, Ordering::AcqRel);
+    now.max(last)
+}
+
 /// Spin for a number of iterations
 #[inline]
 pub fn spin(iterations: u32) {
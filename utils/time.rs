@@ -1,28 +1,103 @@
 //! Time utilities
 //!
 //! This module provides time-related utility functions used throughout the hypervisor.
+//!
+//! Timestamps are derived from the architectural counter returned by
+//! `crate::utils::get_timestamp()` (CNTVCT_EL0 on aarch64, the TIME CSR on
+//! riscv64, the TSC on x86_64) and scaled to wall units using the platform
+//! timer frequency.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Nanoseconds per second
+pub const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+/// Fallback counter frequency when the platform does not report one (10MHz)
+pub const DEFAULT_TIMER_FREQ_HZ: u64 = 10_000_000;
+
+/// Cached counter frequency in Hz (0 = not yet probed)
+static TIMER_FREQ_HZ: AtomicU64 = AtomicU64::new(0);
+
+/// Probe the counter frequency from the architecture or platform
+fn probe_timer_frequency() -> u64 {
+    #[cfg(target_arch = "aarch64")]
+    {
+        let freq: u64;
+        unsafe {
+            core::arch::asm!("mrs {}, cntfrq_el0", out(reg) freq, options(nomem, nostack, preserves_flags));
+        }
+        freq
+    }
+
+    #[cfg(target_arch = "riscv64")]
+    {
+        crate::arch::riscv64::platform::get_timer_frequency()
+    }
+
+    #[cfg(not(any(target_arch = "aarch64", target_arch = "riscv64")))]
+    {
+        // The TSC rate is not architecturally discoverable; platforms that
+        // calibrate it report the result through `set_timer_frequency`
+        DEFAULT_TIMER_FREQ_HZ
+    }
+}
+
+/// Get the counter frequency in Hz
+pub fn timer_frequency() -> u64 {
+    let freq = TIMER_FREQ_HZ.load(Ordering::Relaxed);
+    if freq != 0 {
+        return freq;
+    }
+
+    let freq = match probe_timer_frequency() {
+        0 => DEFAULT_TIMER_FREQ_HZ,
+        freq => freq,
+    };
+    TIMER_FREQ_HZ.store(freq, Ordering::Relaxed);
+    freq
+}
+
+/// Override the counter frequency (e.g. from the device tree or TSC calibration)
+pub fn set_timer_frequency(freq_hz: u64) {
+    if freq_hz != 0 {
+        TIMER_FREQ_HZ.store(freq_hz, Ordering::Relaxed);
+    }
+}
+
+/// Convert counter ticks to nanoseconds at the given frequency
+pub fn ticks_to_ns(ticks: u64, freq_hz: u64) -> u64 {
+    if freq_hz == 0 {
+        return 0;
+    }
+    ((ticks as u128 * NSEC_PER_SEC as u128) / freq_hz as u128) as u64
+}
+
+/// Convert nanoseconds to counter ticks at the given frequency
+pub fn ns_to_ticks(ns: u64, freq_hz: u64) -> u64 {
+    ((ns as u128 * freq_hz as u128) / NSEC_PER_SEC as u128) as u64
+}
 
 /// Get timestamp in nanoseconds
 pub fn timestamp_ns() -> u64 {
-    crate::utils::get_timestamp() * 1000 // Assuming get_timestamp returns microseconds
+    ticks_to_ns(crate::utils::get_timestamp(), timer_frequency())
 }
 
 /// Get timestamp in microseconds
 pub fn timestamp_us() -> u64 {
-    crate::utils::get_timestamp()
+    timestamp_ns() / 1_000
 }
 
 /// Get timestamp in milliseconds
 pub fn timestamp_ms() -> u64 {
-    crate::utils::get_timestamp() / 1000
+    timestamp_ns() / 1_000_000
 }
 
 /// Simple delay function (busy-wait)
 pub fn delay_us(microseconds: u32) {
-    let start = crate::utils::get_timestamp();
+    let start = timestamp_us();
     let end = start + microseconds as u64;
 
-    while crate::utils::get_timestamp() < end {
+    while timestamp_us() < end {
         crate::utils::spin(10);
     }
 }
@@ -30,4 +105,44 @@ pub fn delay_us(microseconds: u32) {
 /// Simple delay function in milliseconds
 pub fn delay_ms(milliseconds: u32) {
     delay_us(milliseconds * 1000);
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timestamp_monotonic() {
+        let mut last = crate::utils::get_timestamp();
+        for _ in 0..1000 {
+            let now = crate::utils::get_timestamp();
+            assert!(now >= last);
+            last = now;
+        }
+
+        let mut last_ns = timestamp_ns();
+        for _ in 0..1000 {
+            let now_ns = timestamp_ns();
+            assert!(now_ns >= last_ns);
+            last_ns = now_ns;
+        }
+    }
+
+    #[test]
+    fn test_ticks_to_ns() {
+        // 10MHz: one tick is 100ns
+        assert_eq!(ticks_to_ns(1, 10_000_000), 100);
+        assert_eq!(ticks_to_ns(10_000_000, 10_000_000), NSEC_PER_SEC);
+        // 62.5MHz (typical CNTFRQ): 62.5M ticks per second
+        assert_eq!(ticks_to_ns(62_500_000 * 3, 62_500_000), 3 * NSEC_PER_SEC);
+        // Large tick counts must not overflow
+        assert_eq!(ticks_to_ns(u64::MAX / 2, NSEC_PER_SEC), u64::MAX / 2);
+        assert_eq!(ticks_to_ns(100, 0), 0);
+    }
+
+    #[test]
+    fn test_ns_to_ticks_round_trip() {
+        let freq = 24_000_000;
+        assert_eq!(ns_to_ticks(ticks_to_ns(24_000, freq), freq), 24_000);
+    }
+}